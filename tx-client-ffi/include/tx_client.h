@@ -0,0 +1,49 @@
+#ifndef TX_CLIENT_H
+#define TX_CLIENT_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum TxStatus {
+    TX_OK = 0,
+    /* The cluster aborted the transaction */
+    TX_ABORTED = 1,
+    /* The transaction read an account that does not exist and was aborted */
+    TX_NOT_FOUND = 2,
+    /* The transaction already committed or aborted */
+    TX_FINISHED = 3,
+    /* The connection to the coordinator failed */
+    TX_CONNECTION_FAILED = 4,
+    /* A pointer was null or a string was not valid UTF-8 */
+    TX_INVALID_ARGUMENT = 5,
+    /* The coordinator did not answer in time and the transaction was aborted */
+    TX_TIMED_OUT = 6
+} TxStatus;
+
+typedef struct TxClient TxClient;
+typedef struct TxTransaction TxTransaction;
+
+/* Opens a client for the cluster described by the config file at `path`. */
+TxStatus tx_client_open(const char *path, TxClient **out);
+void tx_client_free(TxClient *client);
+
+/* Begins a transaction, blocking while the client has too many open. */
+TxStatus tx_begin(const TxClient *client, TxTransaction **out);
+/* Sets the deadline for every later request on `tx`, or removes it if 0. */
+TxStatus tx_set_timeout(TxTransaction *tx, uint64_t millis);
+TxStatus tx_read(TxTransaction *tx, const char *account_id, int64_t *balance);
+/* Adds `amount`, which may be negative, to the balance of `account_id`. */
+TxStatus tx_write(TxTransaction *tx, const char *account_id, int64_t amount);
+TxStatus tx_commit(TxTransaction *tx);
+TxStatus tx_abort(TxTransaction *tx);
+/* Releases a transaction, aborting it if it is still open. */
+void tx_transaction_free(TxTransaction *tx);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,249 @@
+//! A C ABI over the blocking client, declared in `include/tx_client.h`.
+//! Clients and transactions are opaque handles that the caller must release
+//! with `tx_client_free` and `tx_transaction_free`. Every other function
+//! returns a `TxStatus`.
+
+use tx_client::{blocking, ClientError, PoolLimits};
+use tx_common::config::parse_config;
+use tx_proto::ClientResponse;
+use std::{ffi::{c_char, CStr}, time::Duration};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxStatus {
+    Ok = 0,
+    /// The cluster aborted the transaction
+    Aborted = 1,
+    /// The transaction read an account that does not exist and was aborted
+    NotFound = 2,
+    /// The transaction already committed or aborted
+    Finished = 3,
+    /// The connection to the coordinator failed
+    ConnectionFailed = 4,
+    /// A pointer was null or a string was not valid UTF-8
+    InvalidArgument = 5,
+    /// The coordinator did not answer in time and the transaction was aborted
+    TimedOut = 6
+}
+
+impl From<ClientError> for TxStatus {
+    fn from(err: ClientError) -> Self {
+        match err {
+            ClientError::TimedOut => TxStatus::TimedOut,
+            _ => TxStatus::ConnectionFailed
+        }
+    }
+}
+
+pub struct TxClient {
+    pool: blocking::ConnectionPool
+}
+
+pub struct TxTransaction {
+    inner: Option<blocking::Transaction>
+}
+
+impl TxTransaction {
+    fn request<F>(&mut self, request: F) -> Result<ClientResponse, TxStatus>
+    where
+        F: FnOnce(&mut blocking::Transaction) -> Result<ClientResponse, ClientError>
+    {
+        let inner = self.inner.as_mut().ok_or(TxStatus::Finished)?;
+        let response = request(inner)?;
+        if response.is_final() {
+            self.inner = None;
+        }
+
+        match response {
+            ClientResponse::AbortedNotFound => Err(TxStatus::NotFound),
+            ClientResponse::AlreadyFinished(_) => Err(TxStatus::Finished),
+            response if response.is_err() => Err(TxStatus::Aborted),
+            response => Ok(response)
+        }
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, TxStatus> {
+    if s.is_null() {
+        return Err(TxStatus::InvalidArgument);
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| TxStatus::InvalidArgument)
+}
+
+fn status(result: Result<(), TxStatus>) -> TxStatus {
+    result.err().unwrap_or(TxStatus::Ok)
+}
+
+/// Opens a client for the cluster described by the config file at `path`.
+///
+/// # Safety
+/// `path` must be a null-terminated string and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn tx_client_open(path: *const c_char, out: *mut *mut TxClient) -> TxStatus {
+    status((|| {
+        let path = str_arg(path)?;
+        let out = out.as_mut().ok_or(TxStatus::InvalidArgument)?;
+        let config = parse_config(path).map_err(|_| TxStatus::InvalidArgument)?;
+
+        let pool = blocking::ConnectionPool::new(config, PoolLimits::default());
+        *out = Box::into_raw(Box::new(TxClient { pool }));
+        Ok(())
+    })())
+}
+
+/// # Safety
+/// `client` must be null or a handle from `tx_client_open` that has not been
+/// freed. Transactions begun on the client remain valid.
+#[no_mangle]
+pub unsafe extern "C" fn tx_client_free(client: *mut TxClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Begins a transaction, blocking while the client has too many open.
+///
+/// # Safety
+/// `client` must be a live handle and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn tx_begin(client: *const TxClient, out: *mut *mut TxTransaction) -> TxStatus {
+    status((|| {
+        let client = client.as_ref().ok_or(TxStatus::InvalidArgument)?;
+        let out = out.as_mut().ok_or(TxStatus::InvalidArgument)?;
+
+        let inner = client.pool.begin()?;
+        *out = Box::into_raw(Box::new(TxTransaction { inner: Some(inner) }));
+        Ok(())
+    })())
+}
+
+/// Sets the deadline for every later request on `tx` in milliseconds, or
+/// removes it if `millis` is 0.
+///
+/// # Safety
+/// `tx` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn tx_set_timeout(tx: *mut TxTransaction, millis: u64) -> TxStatus {
+    status((|| {
+        let tx = tx.as_mut().ok_or(TxStatus::InvalidArgument)?;
+        let inner = tx.inner.as_mut().ok_or(TxStatus::Finished)?;
+        inner.set_timeout((millis > 0).then(|| Duration::from_millis(millis)));
+        Ok(())
+    })())
+}
+
+/// Reads the balance of `account_id` into `balance`.
+///
+/// # Safety
+/// `tx` must be a live handle, `account_id` a null-terminated string, and
+/// `balance` writable.
+#[no_mangle]
+pub unsafe extern "C" fn tx_read(tx: *mut TxTransaction, account_id: *const c_char, balance: *mut i64) -> TxStatus {
+    status((|| {
+        let tx = tx.as_mut().ok_or(TxStatus::InvalidArgument)?;
+        let account_id = str_arg(account_id)?;
+        let balance = balance.as_mut().ok_or(TxStatus::InvalidArgument)?;
+
+        match tx.request(|tx| tx.balance(account_id))? {
+            ClientResponse::Value(_, value) => *balance = value,
+            _ => return Err(TxStatus::ConnectionFailed)
+        }
+        Ok(())
+    })())
+}
+
+/// Adds `amount`, which may be negative, to the balance of `account_id`.
+///
+/// # Safety
+/// `tx` must be a live handle and `account_id` a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tx_write(tx: *mut TxTransaction, account_id: *const c_char, amount: i64) -> TxStatus {
+    status((|| {
+        let tx = tx.as_mut().ok_or(TxStatus::InvalidArgument)?;
+        let account_id = str_arg(account_id)?;
+        tx.request(|tx| tx.deposit(account_id, amount)).map(|_| ())
+    })())
+}
+
+/// # Safety
+/// `tx` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn tx_commit(tx: *mut TxTransaction) -> TxStatus {
+    status((|| {
+        let tx = tx.as_mut().ok_or(TxStatus::InvalidArgument)?;
+        tx.request(|tx| tx.commit()).map(|_| ())
+    })())
+}
+
+/// # Safety
+/// `tx` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn tx_abort(tx: *mut TxTransaction) -> TxStatus {
+    status((|| {
+        let tx = tx.as_mut().ok_or(TxStatus::InvalidArgument)?;
+        match tx.request(|tx| tx.abort()) {
+            Err(TxStatus::Aborted) => Ok(()),
+            result => result.map(|_| ())
+        }
+    })())
+}
+
+/// Releases a transaction, aborting it if it is still open.
+///
+/// # Safety
+/// `tx` must be null or a handle from `tx_begin` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn tx_transaction_free(tx: *mut TxTransaction) {
+    if !tx.is_null() {
+        drop(Box::from_raw(tx));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::config::node_named;
+    use tx_server::coordinator::Server;
+    use std::{ffi::CString, ptr};
+    use super::*;
+
+    #[test]
+    fn test_c_abi_transaction() {
+        let port = tx_common::testing::free_port();
+        let path = std::env::temp_dir().join(format!("tx-client-ffi-{port}.txt"));
+        std::fs::write(&path, format!("A 127.0.0.1 {port}\n")).unwrap();
+
+        let config = parse_config(path.to_str().unwrap()).unwrap();
+        let node_id = node_named(&config, "A").unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut server = runtime.block_on(Server::start(node_id, config, 5));
+        runtime.spawn(async move { server.serve().await });
+
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let account = CString::new("A.x").unwrap();
+        unsafe {
+            let mut client = ptr::null_mut();
+            assert_eq!(tx_client_open(c_path.as_ptr(), &mut client), TxStatus::Ok);
+
+            let mut tx = ptr::null_mut();
+            assert_eq!(tx_begin(client, &mut tx), TxStatus::Ok);
+            assert_eq!(tx_set_timeout(tx, 5000), TxStatus::Ok);
+            assert_eq!(tx_write(tx, account.as_ptr(), 10), TxStatus::Ok);
+            assert_eq!(tx_commit(tx), TxStatus::Ok);
+            assert_eq!(tx_commit(tx), TxStatus::Finished);
+            tx_transaction_free(tx);
+
+            let mut balance = 0;
+            assert_eq!(tx_begin(client, &mut tx), TxStatus::Ok);
+            assert_eq!(tx_read(tx, account.as_ptr(), &mut balance), TxStatus::Ok);
+            assert_eq!(balance, 10);
+            assert_eq!(tx_write(tx, account.as_ptr(), -11), TxStatus::Ok);
+            assert_eq!(tx_commit(tx), TxStatus::Aborted);
+            assert_eq!(tx_read(tx, ptr::null(), &mut balance), TxStatus::InvalidArgument);
+            tx_transaction_free(tx);
+
+            tx_client_free(client);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
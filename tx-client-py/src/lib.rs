@@ -0,0 +1,212 @@
+//! Python bindings for the blocking client. Build the module with
+//! `maturin develop` from this directory, then:
+//!
+//! ```python
+//! import tx_client
+//!
+//! client = tx_client.Client("config.txt")
+//! tx = client.begin()
+//! tx.write("A.alice", 10)
+//! tx.commit()
+//!
+//! client.run(lambda tx: tx.write("B.bob", tx.read("A.alice")))
+//! ```
+
+use pyo3::{create_exception, exceptions::{PyException, PyTimeoutError}, prelude::*};
+use std::time::Duration;
+use tx_client::{blocking, ClientError, PoolLimits};
+use tx_common::{AccountId, Amount, config::parse_config};
+use tx_proto::ClientResponse;
+
+pub static DEFAULT_ATTEMPTS: usize = 3;
+
+create_exception!(tx_client, TransactionAborted, PyException, "The cluster aborted the transaction.");
+create_exception!(tx_client, ConnectionError, PyException, "The client lost its connection to the coordinator.");
+
+fn connection_error(err: ClientError) -> PyErr {
+    match err {
+        ClientError::TimedOut => PyTimeoutError::new_err("the coordinator did not answer in time"),
+        err => ConnectionError::new_err(format!("{err:?}"))
+    }
+}
+
+/// A client that begins transactions on the nodes of a cluster.
+#[pyclass]
+struct Client {
+    pool: blocking::ConnectionPool
+}
+
+#[pymethods]
+impl Client {
+    #[new]
+    fn new(config_path: &str) -> PyResult<Self> {
+        let config = parse_config(config_path).map_err(pyo3::exceptions::PyValueError::new_err)?;
+        Ok(Self { pool: blocking::ConnectionPool::new(config, PoolLimits::default()) })
+    }
+
+    fn begin(&self, py: Python<'_>) -> PyResult<Transaction> {
+        let inner = py.allow_threads(|| self.pool.begin()).map_err(connection_error)?;
+        Ok(Transaction { inner: Some(inner) })
+    }
+
+    /// Calls `body` with a new transaction and commits it, retrying with a
+    /// fresh transaction up to `attempts` times while the cluster aborts it.
+    /// Returns what `body` returned for the transaction that committed.
+    #[pyo3(signature = (body, attempts = DEFAULT_ATTEMPTS))]
+    fn run(&self, py: Python<'_>, body: PyObject, attempts: usize) -> PyResult<PyObject> {
+        let mut attempt = 1;
+        loop {
+            let tx = Py::new(py, self.begin(py)?)?;
+            let result = body
+                .call1(py, (tx.clone_ref(py),))
+                .and_then(|value| tx.borrow_mut(py).commit(py).map(|_| value));
+
+            match result {
+                Err(e) if e.is_instance_of::<TransactionAborted>(py) && attempt < attempts => attempt += 1,
+                Err(e) => {
+                    // Release the transaction's locks if the body failed.
+                    let _ = tx.borrow_mut(py).abort(py);
+                    return Err(e);
+                },
+                Ok(value) => return Ok(value)
+            }
+        }
+    }
+}
+
+/// A transaction coordinated by one node. Once it commits or aborts, every
+/// further call raises `TransactionAborted`.
+#[pyclass]
+struct Transaction {
+    inner: Option<blocking::Transaction>
+}
+
+impl Transaction {
+    fn request<F>(&mut self, py: Python<'_>, request: F) -> PyResult<ClientResponse>
+    where
+        F: FnOnce(&mut blocking::Transaction) -> Result<ClientResponse, ClientError> + Send
+    {
+        let inner = self.inner
+            .as_mut()
+            .ok_or_else(|| TransactionAborted::new_err("the transaction already finished"))?;
+
+        let response = py.allow_threads(|| request(inner)).map_err(connection_error)?;
+        if response.is_final() {
+            self.inner = None;
+        }
+
+        if response.is_err() || matches!(response, ClientResponse::AlreadyFinished(_)) {
+            Err(TransactionAborted::new_err(response.format()))
+        } else {
+            Ok(response)
+        }
+    }
+}
+
+#[pymethods]
+impl Transaction {
+    /// Sets the deadline in seconds for every later call, or removes it if
+    /// `None`. A call that misses its deadline aborts the transaction and
+    /// raises `TimeoutError`.
+    #[pyo3(signature = (seconds))]
+    fn set_timeout(&mut self, seconds: Option<f64>) -> PyResult<()> {
+        let inner = self.inner
+            .as_mut()
+            .ok_or_else(|| TransactionAborted::new_err("the transaction already finished"))?;
+        inner.set_timeout(seconds.map(Duration::from_secs_f64));
+        Ok(())
+    }
+
+    /// Returns the balance of an account.
+    fn read(&mut self, py: Python<'_>, account_id: AccountId) -> PyResult<Amount> {
+        match self.request(py, |tx| tx.balance(account_id))? {
+            ClientResponse::Value(_, balance) => Ok(balance),
+            response => Err(ConnectionError::new_err(format!("unexpected response {response:?}")))
+        }
+    }
+
+    /// Adds `amount` to the balance of an account, which may be negative.
+    fn write(&mut self, py: Python<'_>, account_id: AccountId, amount: Amount) -> PyResult<()> {
+        self.request(py, |tx| tx.deposit(account_id, amount)).map(|_| ())
+    }
+
+    fn commit(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.request(py, |tx| tx.commit()).map(|_| ())
+    }
+
+    fn abort(&mut self, py: Python<'_>) -> PyResult<()> {
+        match self.request(py, |tx| tx.abort()) {
+            Err(e) if e.is_instance_of::<TransactionAborted>(py) => Ok(()),
+            result => result.map(|_| ())
+        }
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "tx_client")]
+fn tx_client_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Client>()?;
+    m.add_class::<Transaction>()?;
+    m.add("TransactionAborted", m.py().get_type::<TransactionAborted>())?;
+    m.add("ConnectionError", m.py().get_type::<ConnectionError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use pyo3::ffi::c_str;
+    use tx_common::config::{node_named, parse_config};
+    use tx_server::coordinator::Server;
+    use super::*;
+
+    #[test]
+    fn test_python_client_retries_and_commits() {
+        let port = tx_common::testing::free_port();
+        let path = std::env::temp_dir().join(format!("tx-client-py-{port}.txt"));
+        std::fs::write(&path, format!("A 127.0.0.1 {port}\n")).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let config = parse_config(&path).unwrap();
+        let node_id = node_named(&config, "A").unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut server = runtime.block_on(Server::start(node_id, config, 5));
+        runtime.spawn(async move { server.serve().await });
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "tx_client").unwrap();
+            tx_client_py(&module).unwrap();
+            let globals = pyo3::types::PyDict::new(py);
+            globals.set_item("tx_client", module).unwrap();
+            globals.set_item("path", &path).unwrap();
+
+            py.run(c_str!(r#"
+client = tx_client.Client(path)
+tx = client.begin()
+tx.set_timeout(5.0)
+tx.write("A.x", 10)
+tx.commit()
+
+try:
+    client.begin().read("A.missing")
+    raise AssertionError("read of a missing account committed")
+except tx_client.TransactionAborted:
+    pass
+
+attempts = []
+def body(tx):
+    attempts.append(tx)
+    if len(attempts) == 1:
+        tx.write("A.x", -100)
+    else:
+        tx.write("A.x", -4)
+    return tx.read("A.x")
+
+assert client.run(body) == 6
+assert len(attempts) == 2
+"#), Some(&globals), None).unwrap();
+        });
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
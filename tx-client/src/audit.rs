@@ -0,0 +1,152 @@
+use crate::{subscribe, AccountChange};
+use tx_common::{AccountId, Amount, config::Config, transaction_id::TransactionId};
+use futures::stream::{self, Stream, StreamExt};
+use tokio::{sync::mpsc::unbounded_channel, time::Instant};
+use std::{collections::HashMap, pin::Pin, time::Duration};
+use log::error;
+
+/// How long after the last change of a transaction arrives its changes are
+/// summed, so that changes from every shard it wrote have arrived.
+pub static SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// A committed transaction whose changes to all balances did not sum to zero.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Imbalance {
+    pub tx_id: TransactionId,
+    /// The sum of the transaction's changes to every balance
+    pub delta: Amount
+}
+
+pub type Audit = Pin<Box<dyn Stream<Item = Imbalance> + Send>>;
+
+/// Checks that every transaction conserves the total of all balances, as
+/// every transaction of a workload that only transfers between accounts must.
+/// Changes are turned into deltas against the previous balance seen for the
+/// account, so the check must see every commit since the cluster started.
+pub struct ConservationCheck {
+    settle: Duration,
+    balances: HashMap<AccountId, Amount>,
+    /// The sum of each transaction's deltas so far and when it last changed
+    pending: HashMap<TransactionId, (Amount, Instant)>
+}
+
+impl ConservationCheck {
+    pub fn new(settle: Duration) -> Self {
+        Self { settle, balances: HashMap::new(), pending: HashMap::new() }
+    }
+
+    /// Counts a change towards its transaction's total. The removal of an 
+    /// expired account is not a transfer, so its balance is forgotten rather
+    /// than counted.
+    pub fn apply(&mut self, change: AccountChange, now: Instant) {
+        if change.removed {
+            self.balances.remove(&change.account_id);
+            return;
+        }
+
+        let previous = self.balances.insert(change.account_id, change.balance).unwrap_or(0);
+        let (delta, last_seen) = self.pending.entry(change.tx_id).or_insert((0, now));
+        *delta += change.balance - previous;
+        *last_seen = now;
+    }
+
+    /// Returns the imbalanced transactions among those whose changes have all
+    /// arrived, oldest first, and stops tracking them.
+    pub fn settled(&mut self, now: Instant) -> Vec<Imbalance> {
+        let settle = self.settle;
+        let mut imbalances = Vec::new();
+        self.pending.retain(|tx_id, (delta, last_seen)| {
+            if now.duration_since(*last_seen) < settle {
+                return true;
+            }
+
+            if *delta != 0 {
+                imbalances.push(Imbalance { tx_id: *tx_id, delta: *delta });
+            }
+            false
+        });
+
+        imbalances.sort_by_key(|imbalance| imbalance.tx_id);
+        imbalances
+    }
+}
+
+/// Follows the commits of every node in `config` from the start of their
+/// commit logs, yielding every transaction that did not conserve the total of
+/// all balances. Each is also logged as an error.
+pub fn audit(config: &Config, settle: Duration) -> Audit {
+    let mut changes = stream::select_all(config.values().map(|node| subscribe(node.clone(), 0)));
+    let (imbalances_snd, mut imbalances) = unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut check = ConservationCheck::new(settle);
+        loop {
+            if let Ok(Some(change)) = tokio::time::timeout(settle, changes.next()).await {
+                check.apply(change, Instant::now());
+            }
+
+            for imbalance in check.settled(Instant::now()) {
+                error!("{} changed the total of all balances by {}", imbalance.tx_id, imbalance.delta);
+                if imbalances_snd.send(imbalance).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Box::pin(stream::poll_fn(move |cx| imbalances.poll_recv(cx)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{test::start_node, Transaction};
+    use tx_common::{config::NodeId, transaction_id::TransactionIdGenerator};
+    use tx_proto::ClientResponse;
+    use super::*;
+
+    #[test]
+    fn test_conservation_check_waits_for_every_shard() {
+        let mut ids = TransactionIdGenerator::new(NodeId(0));
+        let (t1, t2) = (ids.next(), ids.next());
+        let change = |tx_id, account_id: &str, balance| AccountChange { seq: 0, tx_id, account_id: account_id.into(), balance, removed: false };
+        let start = Instant::now();
+        let mut check = ConservationCheck::new(Duration::from_secs(1));
+
+        check.apply(change(t1, "A.x", 10), start);
+        check.apply(change(t2, "A.x", 4), start);
+        assert_eq!(check.settled(start + Duration::from_millis(500)), vec![]);
+
+        // The other half of the transfer arrives from another shard
+        check.apply(change(t2, "B.y", 6), start + Duration::from_millis(600));
+        assert_eq!(check.settled(start + Duration::from_secs(1)), vec![Imbalance { tx_id: t1, delta: 10 }]);
+        assert_eq!(check.settled(start + Duration::from_secs(2)), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_audit_reports_imbalanced_transactions() {
+        let config = start_node().await;
+        let node = &config[&NodeId(0)];
+        let mut imbalances = audit(&config, Duration::from_millis(50));
+
+        let mut setup = Transaction::begin(node).await.unwrap();
+        setup.deposit("A.x", 10).await.unwrap();
+        assert!(matches!(setup.commit().await.unwrap(), ClientResponse::CommitOk));
+
+        let mut transfer = Transaction::begin(node).await.unwrap();
+        transfer.withdraw("A.x", 4).await.unwrap();
+        transfer.deposit("A.y", 4).await.unwrap();
+        assert!(matches!(transfer.commit().await.unwrap(), ClientResponse::CommitOk));
+
+        let mut leak = Transaction::begin(node).await.unwrap();
+        leak.withdraw("A.y", 1).await.unwrap();
+        assert!(matches!(leak.commit().await.unwrap(), ClientResponse::CommitOk));
+
+        let deltas: Vec<_> = tokio::time::timeout(Duration::from_secs(5), (&mut imbalances).take(2).collect::<Vec<_>>())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|imbalance| imbalance.delta)
+            .collect();
+        assert_eq!(deltas, vec![10, -1]);
+    }
+}
@@ -0,0 +1,27 @@
+use tx_common::config::{Config, parse_config};
+use tx_client::audit::{audit, SETTLE_TIME};
+use futures::StreamExt;
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let args: Vec<_> = std::env::args().collect();
+
+    if args.len() != 2 {
+        eprintln!("Usage: {} <path to config file>", args[0]);
+        std::process::exit(1);
+    }
+
+    let config: Config = match parse_config(&args[1]) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}: {}", &args[0], e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut imbalances = audit(&config, SETTLE_TIME);
+    while let Some(imbalance) = imbalances.next().await {
+        println!("IMBALANCED {} delta={}", imbalance.tx_id, imbalance.delta);
+    }
+}
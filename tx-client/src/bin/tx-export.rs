@@ -0,0 +1,52 @@
+use tx_common::config::{self, Config, parse_config};
+use tx_client::{cluster_snapshot, shard_snapshot, export::write_parquet};
+use std::fs::File;
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let args: Vec<_> = std::env::args().collect();
+
+    if args.len() != 3 && args.len() != 4 {
+        eprintln!("Usage: {} <path to config file> <output path> [node identifier]", args[0]);
+        std::process::exit(1);
+    }
+
+    let config: Config = match parse_config(&args[1]) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}: {}", &args[0], e);
+            std::process::exit(1);
+        }
+    };
+
+    let snapshots = match args.get(3) {
+        Some(name) => match config::node_named(&config, name) {
+            Some(node_id) => shard_snapshot(&config[&node_id]).await.map(|snapshot| vec![snapshot]),
+            None => {
+                eprintln!("{}: Node {name} is not listed in config file", args[0]);
+                std::process::exit(1);
+            }
+        },
+        None => cluster_snapshot(&config).await
+    };
+
+    let snapshots = snapshots.unwrap_or_else(|e| {
+        eprintln!("{}: Failed to take snapshot: {e:?}", args[0]);
+        std::process::exit(1);
+    });
+
+    let file = File::create(&args[2]).unwrap_or_else(|e| {
+        eprintln!("{}: Failed to create {}: {e}", args[0], args[2]);
+        std::process::exit(1);
+    });
+
+    if let Err(e) = write_parquet(&snapshots, file) {
+        eprintln!("{}: Failed to write {}: {e}", args[0], args[2]);
+        std::process::exit(1);
+    }
+
+    for snapshot in snapshots {
+        println!("EXPORTED {} seq={} accounts={}", snapshot.node_id, snapshot.seq, snapshot.accounts.len());
+    }
+}
@@ -0,0 +1,361 @@
+//! Soaks a cluster of tx-server processes on localhost with transfers between
+//! a fixed set of accounts for hours, checking at every interval, while the
+//! transfers are held back, that no money was made or lost and that no node
+//! holds on to more than it did:
+//!
+//! ```text
+//! tx-soak <path to tx-server> [seconds] [clients] [check every seconds]
+//! ```
+//!
+//! The soak prints the seed its clients draw their transfers from, and 
+//! setting `TX_REPLAY_SEED` to it replays the same transfers from each 
+//! client. How the clients interleave still depends on the processes' 
+//! timing.
+//!
+//! The run fails if a check finds a problem, a node exits, the conservation
+//! audit finds an imbalanced commit, or no transfer commits between checks.
+//! Nodes cannot rejoin a cluster yet, so the soak does not kill them; once
+//! they can, killing one at random between checks belongs here too.
+
+use tx_common::{
+    admin::AdminRequest,
+    config::{parse_config, Config, NodeConfiguration},
+    replay::{replay_seed, REPLAY_SEED_VAR},
+    testing::free_port
+};
+use tx_proto::{BalanceDiff, ClientRequest, ClientResponse};
+use tx_client::{
+    audit::{audit, SETTLE_TIME}, cluster_snapshot, soak::{self, NodeSample}, ClientError, Transaction
+};
+use futures::StreamExt;
+use rand::{rngs::StdRng, seq::{IteratorRandom, SliceRandom}, Rng, SeedableRng};
+use tokio::{sync::RwLock, task::JoinSet, time::{sleep, Duration, Instant}};
+use std::{
+    fs::File, io::Write, path::{Path, PathBuf}, process::{Child, Command, Stdio},
+    sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc}
+};
+
+const NODES: [&str; 3] = ["A", "B", "C"];
+const ACCOUNTS_PER_SHARD: usize = 8;
+const INITIAL_BALANCE: i64 = 1000;
+/// Labels the soak's transactions, so each node counts those still active
+const LABEL: &str = "soak";
+/// How long the nodes may take to connect to each other
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long every commit may take to be applied everywhere once transfers stop
+const APPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How much a node's resident memory may grow over the run, on top of
+/// `RSS_SLACK_KIB`
+const MAX_RSS_GROWTH: f64 = 1.5;
+const RSS_SLACK_KIB: u64 = 16 * 1024;
+
+/// The server processes, killed when the soak ends however it ends.
+struct Cluster {
+    config: Config,
+    processes: Vec<(String, Child)>,
+    state_dir: PathBuf
+}
+
+impl Cluster {
+    fn start(server: &Path) -> std::io::Result<Self> {
+        let state_dir = std::env::temp_dir().join(format!("tx-soak-{}", std::process::id()));
+        std::fs::create_dir_all(&state_dir)?;
+        let config_path = state_dir.join("config.txt");
+        let mut config_file = File::create(&config_path)?;
+        for name in NODES {
+            writeln!(config_file, "{name} 127.0.0.1 {}", free_port())?;
+        }
+        drop(config_file);
+
+        let config = parse_config(config_path.to_str().unwrap()).map_err(std::io::Error::other)?;
+        let mut processes = Vec::new();
+        for name in NODES {
+            let log = File::create(state_dir.join(format!("{name}.log")))?;
+            let child = Command::new(server)
+                .args([name, config_path.to_str().unwrap()])
+                .env("TX_STATE_DIR", &state_dir)
+                .env("TX_COMMIT_REPORT", "silent")
+                .stdout(Stdio::null())
+                .stderr(log)
+                .spawn()?;
+            processes.push((name.to_string(), child));
+        }
+
+        Ok(Self { config, processes, state_dir })
+    }
+
+    /// Waits until every node answers, which they only do once connected.
+    async fn ready(&self) -> bool {
+        let deadline = Instant::now() + START_TIMEOUT;
+        for node in self.config.values() {
+            while !matches!(status(node).await, Ok(ClientResponse::Admin(_))) {
+                if Instant::now() > deadline {
+                    return false;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        }
+        true
+    }
+
+    /// The nodes whose process has exited.
+    fn exited(&mut self) -> Vec<String> {
+        self.processes
+            .iter_mut()
+            .filter_map(|(name, child)| match child.try_wait() {
+                Ok(None) => None,
+                Ok(Some(status)) => Some(format!("node {name} exited: {status}")),
+                Err(e) => Some(format!("node {name} cannot be waited on: {e}"))
+            })
+            .collect()
+    }
+
+    async fn sample(&self) -> Result<Vec<NodeSample>, ClientError> {
+        let mut nodes: Vec<_> = self.config.values().collect();
+        nodes.sort_by_key(|node| node.node_id);
+        let mut samples = Vec::new();
+        for (node, (_, child)) in nodes.into_iter().zip(&self.processes) {
+            let mut sample = soak::sample_node(node, LABEL).await?;
+            sample.rss_kib = soak::resident_memory(child.id());
+            samples.push(sample);
+        }
+        Ok(samples)
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for (_, child) in self.processes.iter_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+async fn status(node: &NodeConfiguration) -> Result<ClientResponse, ClientError> {
+    Transaction::begin(node).await?.request(ClientRequest::Admin(AdminRequest::Status)).await
+}
+
+fn accounts() -> Vec<String> {
+    NODES
+        .iter()
+        .flat_map(|shard| (0..ACCOUNTS_PER_SHARD).map(move |i| format!("{shard}.soak{i}")))
+        .collect()
+}
+
+/// Moves a random amount between two random accounts through a random node,
+/// returning whether it committed. Transfers that conflict, overdraw or are
+/// refused a connection simply do not commit.
+async fn transfer(rng: &mut StdRng, nodes: &[NodeConfiguration], accounts: &[String]) -> bool {
+    // The pair comes in the order of `accounts`, so pick a direction too
+    let pair = accounts.iter().choose_multiple(rng, 2);
+    let (from, to) = if rng.gen() { (pair[0].clone(), pair[1].clone()) } else { (pair[1].clone(), pair[0].clone()) };
+    let (node, amount) = (nodes.choose(rng).unwrap(), rng.gen_range(1..=100));
+
+    let Ok(mut tx) = Transaction::begin(node).await else { return false };
+    tx.set_timeout(Some(Duration::from_secs(5)));
+    let ok = |resp: Result<ClientResponse, ClientError>| matches!(resp, Ok(ClientResponse::Ok));
+    if !ok(tx.label(LABEL).await) {
+        return false;
+    }
+    match tx.balance(from.clone()).await {
+        Ok(ClientResponse::Value(_, balance)) if balance >= amount => (),
+        _ => {
+            let _ = tx.abort().await;
+            return false;
+        }
+    }
+    if !ok(tx.withdraw(from, amount).await) || !ok(tx.deposit(to, amount).await) {
+        return false;
+    }
+    matches!(tx.commit().await, Ok(ClientResponse::CommitOk))
+}
+
+/// Holds the transfers back, waits for every commit to be applied, and checks
+/// the cluster, returning the samples taken and every problem found.
+async fn check(cluster: &mut Cluster, gate: &RwLock<()>, accounts: usize, total: i64) -> (Vec<NodeSample>, Vec<String>) {
+    let _quiet = gate.write().await;
+    let mut failures = cluster.exited();
+    if !failures.is_empty() {
+        return (vec![], failures);
+    }
+
+    let applied_by = Instant::now() + APPLY_TIMEOUT;
+    let samples = loop {
+        match cluster.sample().await {
+            Ok(samples) if samples.iter().all(|sample| sample.unapplied == 0 && sample.active == 0) => break samples,
+            Ok(samples) if Instant::now() > applied_by => {
+                failures.push(format!("commits were not applied within {APPLY_TIMEOUT:?}"));
+                break samples;
+            },
+            Ok(_) => sleep(Duration::from_millis(100)).await,
+            Err(e) => {
+                failures.push(format!("failed to sample the cluster: {e:?}"));
+                return (vec![], failures);
+            }
+        }
+    };
+
+    match cluster_snapshot(&cluster.config).await {
+        Ok(snapshots) => failures.extend(soak::check_balances(&snapshots, accounts, total)),
+        Err(e) => failures.push(format!("failed to snapshot the cluster: {e:?}"))
+    }
+    failures.extend(samples
+        .iter()
+        .filter(|sample| sample.violations > 0)
+        .map(|sample| format!("node {} found {} invariant violations", sample.node_id, sample.violations)));
+    (samples, failures)
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let args: Vec<_> = std::env::args().collect();
+    if !(2..=5).contains(&args.len()) {
+        eprintln!("Usage: {} <path to tx-server> [seconds] [clients] [check every seconds]", args[0]);
+        std::process::exit(1);
+    }
+    let seed = match replay_seed() {
+        Ok(seed) => seed.unwrap_or_else(|| rand::thread_rng().gen()),
+        Err(e) => {
+            eprintln!("{}: {e}", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    let numbers: Vec<u64> = match args[2..].iter().map(|arg| arg.parse()).collect() {
+        Ok(numbers) => numbers,
+        Err(e) => {
+            eprintln!("{}: Invalid number: {e}", args[0]);
+            std::process::exit(1);
+        }
+    };
+    let duration = Duration::from_secs(numbers.first().copied().unwrap_or(3600));
+    let clients = numbers.get(1).copied().unwrap_or(16);
+    let interval = Duration::from_secs(numbers.get(2).copied().unwrap_or(60).max(1));
+
+    let mut cluster = match Cluster::start(Path::new(&args[1])) {
+        Ok(cluster) => cluster,
+        Err(e) => {
+            eprintln!("{}: Failed to start the cluster: {e}", args[0]);
+            std::process::exit(1);
+        }
+    };
+    println!("Logs and state of the run are in {}", cluster.state_dir.display());
+    if !cluster.ready().await {
+        eprintln!("{}: The cluster did not start within {START_TIMEOUT:?}", args[0]);
+        drop(cluster);
+        std::process::exit(1);
+    }
+
+    let accounts = Arc::new(accounts());
+    let total = INITIAL_BALANCE * accounts.len() as i64;
+    let node = cluster.config.values().next().unwrap().clone();
+    let mut deposits: Vec<_> = accounts
+        .iter()
+        .map(|account_id| ClientRequest::WriteBalance(account_id.clone(), BalanceDiff(INITIAL_BALANCE)))
+        .collect();
+    deposits.push(ClientRequest::Commit);
+    // Follow every commit from the first, so that the audit sees the deposits.
+    // They are the one transaction that adds money.
+    let imbalances = Arc::new(AtomicU64::new(0));
+    let mut audit = audit(&cluster.config, SETTLE_TIME);
+    let audited = imbalances.clone();
+    tokio::spawn(async move {
+        let mut deposited = false;
+        while let Some(imbalance) = audit.next().await {
+            if imbalance.delta == total && !deposited {
+                deposited = true;
+                continue;
+            }
+            println!("IMBALANCED {} delta={}", imbalance.tx_id, imbalance.delta);
+            audited.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+
+    println!("Depositing {total} across {} accounts, which the audit logs as imbalanced", accounts.len());
+    let mut depositor = Transaction::begin(&node).await;
+    for request in deposits {
+        let resp = match depositor.as_mut() {
+            Ok(depositor) => depositor.request(request).await,
+            Err(_) => break
+        };
+        if !matches!(resp, Ok(ClientResponse::Ok | ClientResponse::CommitOk)) {
+            eprintln!("{}: Failed to deposit the initial balances: {resp:?}", args[0]);
+            drop(cluster);
+            std::process::exit(1);
+        }
+    }
+    if let Err(e) = depositor {
+        eprintln!("{}: Failed to connect to the cluster: {e:?}", args[0]);
+        drop(cluster);
+        std::process::exit(1);
+    }
+
+    println!("Soaking {} nodes with {clients} clients for {duration:?}, checking every {interval:?}", NODES.len());
+    println!("Seed {seed}: replay the transfers with {REPLAY_SEED_VAR}={seed}");
+    // Sorted, so that a seed picks the same nodes in every run
+    let mut nodes: Vec<_> = cluster.config.values().cloned().collect();
+    nodes.sort_by_key(|node| node.node_id);
+    let gate = Arc::new(RwLock::new(()));
+    let stop = Arc::new(AtomicBool::new(false));
+    let commits = Arc::new(AtomicU64::new(0));
+    let mut workers = JoinSet::new();
+    for client in 0..clients {
+        let (nodes, accounts, gate, stop, commits) = (nodes.clone(), accounts.clone(), gate.clone(), stop.clone(), commits.clone());
+        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(client));
+        workers.spawn(async move {
+            while !stop.load(Ordering::Relaxed) {
+                let _running = gate.read().await;
+                if transfer(&mut rng, &nodes, &accounts).await {
+                    commits.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+    }
+
+    let started = Instant::now();
+    let mut first: Option<Vec<NodeSample>> = None;
+    let mut failures = Vec::new();
+    let mut last_commits = 0;
+    while failures.is_empty() && started.elapsed() < duration {
+        sleep(interval.min(duration.saturating_sub(started.elapsed()))).await;
+        let (samples, found) = check(&mut cluster, &gate, accounts.len(), total).await;
+        let committed = commits.load(Ordering::Relaxed);
+        println!("CHECK after {:?}: {} transfers committed", started.elapsed(), committed - last_commits);
+        for sample in &samples {
+            println!("  {}", sample.format());
+        }
+
+        failures.extend(found);
+        if committed == last_commits {
+            failures.push(format!("no transfer committed in {interval:?}"));
+        }
+        last_commits = committed;
+        match &first {
+            None => first = Some(samples),
+            Some(first) => failures.extend(soak::check_growth(first, &samples, MAX_RSS_GROWTH, RSS_SLACK_KIB))
+        }
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    workers.abort_all();
+    // Let the audit settle the last transfers' changes
+    sleep(SETTLE_TIME * 2).await;
+    let imbalanced = imbalances.load(Ordering::Relaxed);
+    if imbalanced > 0 {
+        failures.push(format!("the audit found {imbalanced} imbalanced commits"));
+    }
+
+    if failures.is_empty() {
+        println!("SOAK PASSED: {last_commits} transfers committed in {:?}", started.elapsed());
+        return;
+    }
+
+    println!("SOAK FAILED after {:?} with seed {seed}:", started.elapsed());
+    for failure in failures {
+        println!("  {failure}");
+    }
+    drop(cluster);
+    std::process::exit(1);
+}
@@ -0,0 +1,145 @@
+//! A blocking facade over the async client for callers that do not run an
+//! async runtime. Every call runs on a runtime owned by this module, so these
+//! types must not be used from within an async task.
+
+use crate::{AccountChange, ClientError, PoolLimits};
+use tx_common::{
+    AccountId, Amount,
+    config::{Config, NodeConfiguration}
+};
+use tx_proto::{ClientRequest, ClientResponse};
+use futures::StreamExt;
+use tokio::runtime::{Builder, Runtime};
+use std::{sync::OnceLock, time::Duration};
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("tx-client-blocking")
+            .enable_all()
+            .build()
+            .expect("failed to start the client runtime")
+    })
+}
+
+/// See [`crate::Transaction`].
+pub struct Transaction {
+    inner: crate::Transaction
+}
+
+impl Transaction {
+    pub fn begin(coordinator: &NodeConfiguration) -> Result<Self, ClientError> {
+        let inner = runtime().block_on(crate::Transaction::begin(coordinator))?;
+        Ok(Self { inner })
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner.set_timeout(timeout)
+    }
+
+    pub fn request(&mut self, request: ClientRequest) -> Result<ClientResponse, ClientError> {
+        runtime().block_on(self.inner.request(request))
+    }
+
+    pub fn request_with_timeout(&mut self, request: ClientRequest, deadline: Duration) -> Result<ClientResponse, ClientError> {
+        runtime().block_on(self.inner.request_with_timeout(request, deadline))
+    }
+
+    pub fn balance(&mut self, account_id: impl Into<AccountId>) -> Result<ClientResponse, ClientError> {
+        runtime().block_on(self.inner.balance(account_id))
+    }
+
+    pub fn deposit(&mut self, account_id: impl Into<AccountId>, amount: Amount) -> Result<ClientResponse, ClientError> {
+        runtime().block_on(self.inner.deposit(account_id, amount))
+    }
+
+    pub fn withdraw(&mut self, account_id: impl Into<AccountId>, amount: Amount) -> Result<ClientResponse, ClientError> {
+        runtime().block_on(self.inner.withdraw(account_id, amount))
+    }
+
+    pub fn commit(&mut self) -> Result<ClientResponse, ClientError> {
+        runtime().block_on(self.inner.commit())
+    }
+
+    pub fn abort(&mut self) -> Result<ClientResponse, ClientError> {
+        runtime().block_on(self.inner.abort())
+    }
+}
+
+/// See [`crate::ConnectionPool`]. The pool may be cloned and shared between
+/// threads; a thread that begins a transaction while the pool is full blocks
+/// until another thread drops one.
+#[derive(Clone)]
+pub struct ConnectionPool {
+    inner: crate::ConnectionPool
+}
+
+impl ConnectionPool {
+    pub fn new(config: Config, limits: PoolLimits) -> Self {
+        Self { inner: crate::ConnectionPool::new(config, limits) }
+    }
+
+    pub fn begin(&self) -> Result<Transaction, ClientError> {
+        let inner = runtime().block_on(self.inner.begin())?;
+        Ok(Transaction { inner })
+    }
+
+    pub fn available(&self) -> usize {
+        self.inner.available()
+    }
+}
+
+/// See [`crate::subscribe`]. Each call to `next` blocks until the node
+/// commits another change.
+pub struct Subscription {
+    inner: crate::Subscription
+}
+
+impl Iterator for Subscription {
+    type Item = AccountChange;
+
+    fn next(&mut self) -> Option<AccountChange> {
+        runtime().block_on(self.inner.next())
+    }
+}
+
+pub fn subscribe(node: NodeConfiguration, after: u64) -> Subscription {
+    Subscription { inner: crate::subscribe(node, after) }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::config::NodeId;
+    use super::*;
+
+    #[test]
+    fn test_blocking_transaction_and_subscription() {
+        let config = runtime().block_on(crate::test::start_node());
+        let node = config[&NodeId(0)].clone();
+        let pool = ConnectionPool::new(config, PoolLimits::default());
+
+        let threads: Vec<_> = (1..=4)
+            .map(|amount| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    let mut tx = pool.begin().unwrap();
+                    assert!(matches!(tx.deposit(format!("A.{amount}"), amount).unwrap(), ClientResponse::Ok));
+                    tx.commit().unwrap()
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            assert!(matches!(thread.join().unwrap(), ClientResponse::CommitOk));
+        }
+
+        let mut balances: Vec<_> = subscribe(node.clone(), 0).take(4).map(|c| c.balance).collect();
+        balances.sort();
+        assert_eq!(balances, vec![1, 2, 3, 4]);
+
+        let mut tx = Transaction::begin(&node).unwrap();
+        assert!(matches!(tx.balance("A.3").unwrap(), ClientResponse::Value(_, 3)));
+    }
+}
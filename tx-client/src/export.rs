@@ -0,0 +1,88 @@
+use tx_common::admin::ShardSnapshot;
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray, TimestampNanosecondArray, UInt32Array, UInt64Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema, TimeUnit};
+use parquet::{arrow::ArrowWriter, errors::ParquetError};
+use std::{io::Write, sync::Arc};
+
+/// The columns of an exported snapshot, one row per account:
+///
+/// - `shard_id`: the node whose shard holds the account
+/// - `seq`: the sequence number of the last commit in that shard's snapshot
+/// - `account_id` and `balance`: the account's committed balance
+/// - `committed_by`: the timestamp of the transaction that committed it
+pub fn schema() -> Schema {
+    Schema::new(vec![
+        Field::new("shard_id", DataType::UInt32, false),
+        Field::new("seq", DataType::UInt64, false),
+        Field::new("account_id", DataType::Utf8, false),
+        Field::new("balance", DataType::Int64, false),
+        Field::new("committed_by", DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())), false)
+    ])
+}
+
+/// Lays snapshots out as a single Arrow record batch with the columns of
+/// `schema`.
+pub fn to_record_batch(snapshots: &[ShardSnapshot]) -> Result<RecordBatch, ArrowError> {
+    let rows: Vec<_> = snapshots
+        .iter()
+        .flat_map(|snapshot| snapshot.accounts.iter().map(move |account| (snapshot, account)))
+        .collect();
+    let rows = || rows.iter();
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(rows().map(|(snapshot, _)| snapshot.node_id.0))),
+        Arc::new(UInt64Array::from_iter_values(rows().map(|(snapshot, _)| snapshot.seq))),
+        Arc::new(StringArray::from_iter_values(rows().map(|(_, account)| account.account_id.as_str()))),
+        Arc::new(Int64Array::from_iter_values(rows().map(|(_, account)| account.balance))),
+        Arc::new(TimestampNanosecondArray::from_iter_values(rows().map(|(_, account)| account.committed_by.timestamp() as i64)).with_timezone("UTC"))
+    ];
+
+    RecordBatch::try_new(Arc::new(schema()), columns)
+}
+
+/// Writes snapshots to `writer` as a Parquet file.
+pub fn write_parquet(snapshots: &[ShardSnapshot], writer: impl Write + Send) -> Result<(), ParquetError> {
+    let batch = to_record_batch(snapshots)?;
+    let mut writer = ArrowWriter::try_new(writer, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::{admin::AccountSnapshot, config::NodeId, transaction_id::TransactionId};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Int64Type;
+    use super::*;
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let account = |account_id: &str, balance, ts| AccountSnapshot {
+            account_id: account_id.into(),
+            balance,
+            committed_by: TransactionId::at(ts, NodeId(0))
+        };
+        let snapshots = vec![
+            ShardSnapshot { node_id: NodeId(0), seq: 4, accounts: vec![account("A.x", 10, 100), account("A.y", 5, 200)] },
+            ShardSnapshot { node_id: NodeId(1), seq: 1, accounts: vec![account("B.x", 7, 300)] }
+        ];
+
+        let mut file = Vec::new();
+        write_parquet(&snapshots, &mut file).unwrap();
+
+        let batches = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(file))
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].schema().as_ref(), &schema());
+        assert_eq!(batches[0], to_record_batch(&snapshots).unwrap());
+
+        let balances = batches[0].column_by_name("balance").unwrap().as_primitive::<Int64Type>();
+        assert_eq!(balances.values().to_vec(), vec![10, 5, 7]);
+    }
+}
@@ -0,0 +1,322 @@
+pub mod audit;
+pub mod blocking;
+pub mod pool;
+pub mod query;
+pub mod snapshot;
+pub mod subscribe;
+pub mod topology;
+#[cfg(feature = "cli")]
+pub mod soak;
+#[cfg(feature = "parquet")]
+pub mod export;
+
+use tx_common::{
+    AccountId, Amount,
+    config::NodeConfiguration, stream::{MessageStream, SocketOptions, StreamError}
+};
+use tx_proto::{ClientRequest, ClientResponse, BalanceDiff, BalancePredicate, CommitVerbosity, SessionSettings};
+use tokio::{net::TcpStream, sync::OwnedSemaphorePermit, time::timeout};
+use std::time::Duration;
+pub use pool::{ConnectionPool, PoolLimits};
+pub use subscribe::{subscribe, AccountChange, Subscription};
+pub use snapshot::{shard_snapshot, cluster_snapshot};
+pub use topology::cluster_info;
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// Unable to connect to the coordinator
+    Connect(std::io::Error),
+    /// Unable to send a request or decode a response
+    Stream(StreamError),
+    /// The coordinator closed the connection
+    Closed,
+    /// The coordinator answered with a response the request cannot produce
+    Unexpected(ClientResponse),
+    /// The coordinator did not answer before the deadline, so the 
+    /// transaction was aborted
+    TimedOut
+}
+
+impl From<StreamError> for ClientError {
+    fn from(err: StreamError) -> Self {
+        ClientError::Stream(err)
+    }
+}
+
+/// How long a timed out transaction waits to send its abort before it 
+/// closes the connection, which the coordinator also treats as an abort
+pub static ABORT_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A transaction coordinated by one node. A connection to a coordinator 
+/// carries one transaction after another: the request after the one that 
+/// committed or aborted a transaction starts the next. The transaction owns
+/// its connection and closes it when dropped, unless it came from a pool and
+/// no transaction is under way on it, in which case the pool keeps the 
+/// connection for the next transaction begun on it.
+pub struct Transaction {
+    /// Closed once a request times out, since the late response would 
+    /// otherwise be read as the answer to the next request
+    stream: Option<MessageStream>,
+    /// The deadline for requests made without one of their own
+    timeout: Option<Duration>,
+    /// Whether no transaction is under way on the connection: none was 
+    /// started yet, or the last one committed or aborted
+    idle: bool,
+    /// Where the connection goes back to when it came from a pool
+    pool: Option<pool::Checkout>,
+    /// Held for as long as the transaction is open when it came from a pool
+    _permits: Vec<OwnedSemaphorePermit>
+}
+
+impl Transaction {
+    /// Begins a transaction coordinated by the given node.
+    pub async fn begin(coordinator: &NodeConfiguration) -> Result<Self, ClientError> {
+        Self::begin_with(coordinator, SocketOptions::default()).await
+    }
+
+    /// Begins a transaction coordinated by the given node, setting `options`
+    /// on the connection to it.
+    pub async fn begin_with(coordinator: &NodeConfiguration, options: SocketOptions) -> Result<Self, ClientError> {
+        let addr = (coordinator.hostname.as_str(), coordinator.port);
+        let stream = TcpStream::connect(addr).await.map_err(ClientError::Connect)?;
+        options.apply(&stream).map_err(ClientError::Connect)?;
+
+        Ok(Self::over(MessageStream::from_tcp_stream(stream)))
+    }
+
+    /// Begins a transaction on a connection made some other way, such as in
+    /// memory to a node embedded in this process.
+    pub fn over(stream: MessageStream) -> Self {
+        Self { stream: Some(stream), timeout: None, idle: true, pool: None, _permits: vec![] }
+    }
+
+    /// Sets the deadline for every later request, or removes it if `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Sends a request to the coordinator and waits for its response.
+    pub async fn request(&mut self, request: ClientRequest) -> Result<ClientResponse, ClientError> {
+        match self.timeout {
+            Some(deadline) => self.request_with_timeout(request, deadline).await,
+            None => {
+                let stream = self.stream.as_mut().ok_or(ClientError::Closed)?;
+                self.idle = false;
+                let resp = exchange(stream, request).await?;
+                self.idle = resp.is_final();
+                Ok(resp)
+            }
+        }
+    }
+
+    /// Sends a request to the coordinator and waits at most `deadline` for 
+    /// its response. The deadline is sent with the request, so the 
+    /// coordinator and the shards it forwards to give up on it too. On expiry
+    /// the pending request is cancelled, the transaction is aborted, and 
+    /// every later request fails with `Closed`.
+    pub async fn request_with_timeout(&mut self, request: ClientRequest, deadline: Duration) -> Result<ClientResponse, ClientError> {
+        let stream = self.stream.as_mut().ok_or(ClientError::Closed)?;
+        self.idle = false;
+        if let Ok(result) = timeout(deadline, exchange(stream, request.with_deadline(deadline))).await {
+            let resp = result?;
+            self.idle = resp.is_final();
+            return Ok(resp);
+        }
+
+        // The request may still be in flight, so its response would arrive 
+        // ahead of the abort's. Send the abort and drop the connection 
+        // without waiting for either.
+        if let Some(mut stream) = self.stream.take() {
+            let _ = timeout(ABORT_SEND_TIMEOUT, stream.send(ClientRequest::Abort)).await;
+        }
+
+        Err(ClientError::TimedOut)
+    }
+
+    pub async fn balance(&mut self, account_id: impl Into<AccountId>) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::ReadBalance(account_id.into())).await
+    }
+
+    /// Asserts that a balance satisfies `predicate` now and, with this 
+    /// transaction's own writes, when it commits, aborting the transaction 
+    /// otherwise.
+    pub async fn assert_balance(&mut self, account_id: impl Into<AccountId>, predicate: BalancePredicate) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::AssertBalance(account_id.into(), predicate)).await
+    }
+
+    /// Reads a balance, answered with `ClientResponse::NotFound` rather than
+    /// an abort if the account does not exist, so that the transaction can 
+    /// go on to create it.
+    pub async fn balance_if_exists(&mut self, account_id: impl Into<AccountId>) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::ReadBalanceIfExists(account_id.into())).await
+    }
+
+    /// Reads a balance that may be up to `max_staleness` stale, without 
+    /// waiting on transactions writing the account. The read does not see 
+    /// this transaction's own writes.
+    pub async fn stale_balance(&mut self, account_id: impl Into<AccountId>, max_staleness: Duration) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::ReadBalanceStale(account_id.into(), max_staleness)).await
+    }
+
+    pub async fn deposit(&mut self, account_id: impl Into<AccountId>, amount: Amount) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::WriteBalance(account_id.into(), BalanceDiff(amount))).await
+    }
+
+    /// Deposits like `deposit`, but an account the deposit creates expires 
+    /// `ttl` after the transaction commits.
+    pub async fn deposit_with_ttl(&mut self, account_id: impl Into<AccountId>, amount: Amount, ttl: Duration) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::WriteBalanceWithTtl(account_id.into(), BalanceDiff(amount), ttl)).await
+    }
+
+    pub async fn withdraw(&mut self, account_id: impl Into<AccountId>, amount: Amount) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::WriteBalance(account_id.into(), BalanceDiff(-amount))).await
+    }
+
+    /// Adds `amount` to an account without reading it, so that concurrent
+    /// transactions incrementing the same account do not abort each other.
+    pub async fn increment(&mut self, account_id: impl Into<AccountId>, amount: Amount) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::Increment(account_id.into(), BalanceDiff(amount))).await
+    }
+
+    /// Labels the transaction, e.g. with the name of the application, so the
+    /// coordinator counts its outcome under that label. Labels with a quota
+    /// abort the transaction if too many with the label are running.
+    pub async fn label(&mut self, label: impl Into<String>) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::Label(label.into())).await
+    }
+
+    /// Authenticates the transaction as a tenant of the cluster. A cluster
+    /// with tenants refuses anything else until the transaction does, and
+    /// then only lets it touch the tenant's accounts, those named
+    /// `<shard>.<tenant>/<name>`.
+    pub async fn authenticate(&mut self, tenant: impl Into<String>, secret: impl Into<String>) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::Authenticate(tenant.into(), secret.into())).await
+    }
+
+    /// Asks for the transaction to be given `lifetime` from when it began to
+    /// commit, instead of its coordinator's default. The coordinator grants
+    /// at most its own maximum and answers with the lifetime it granted; ask
+    /// for `Duration::MAX` to learn the maximum. A transaction that outlives
+    /// its lifetime is aborted with `AbortedLifetimeExpired`.
+    pub async fn request_lifetime(&mut self, lifetime: Duration) -> Result<Duration, ClientError> {
+        match self.request(ClientRequest::Lifetime(lifetime)).await? {
+            ClientResponse::LifetimeGranted(granted) => Ok(granted),
+            resp => Err(ClientError::Unexpected(resp))
+        }
+    }
+
+    /// Chooses what the response to the transaction's commit says: with
+    /// `CommitVerbosity::Values`, committing answers `CommitOkWithValues` 
+    /// with the balance of every account the transaction wrote.
+    pub async fn set_commit_verbosity(&mut self, verbosity: CommitVerbosity) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::Verbosity(verbosity)).await
+    }
+
+    /// Settles how the coordinator handles the rest of the transaction's 
+    /// requests, before its first read or write. Answers with the settings 
+    /// the coordinator adopted, or `Unexpected(SettingsRefused(..))` if it
+    /// kept its own because it cannot honour one of them.
+    pub async fn hello(&mut self, settings: SessionSettings) -> Result<SessionSettings, ClientError> {
+        match self.request(ClientRequest::Hello(settings)).await? {
+            ClientResponse::SessionSettings(adopted) => Ok(adopted),
+            resp => Err(ClientError::Unexpected(resp))
+        }
+    }
+
+    pub async fn commit(&mut self) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::Commit).await
+    }
+
+    pub async fn abort(&mut self) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::Abort).await
+    }
+
+    /// Aborts, giving the coordinator a reason to record in its decision log.
+    pub async fn abort_with_reason(&mut self, reason: impl Into<String>) -> Result<ClientResponse, ClientError> {
+        self.request(ClientRequest::AbortWithReason(reason.into())).await
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if let (true, Some(stream), Some(checkout)) = (self.idle, self.stream.take(), self.pool.take()) {
+            checkout.put_back(stream);
+        }
+    }
+}
+
+async fn exchange(stream: &mut MessageStream, request: ClientRequest) -> Result<ClientResponse, ClientError> {
+    stream.send(request).await?;
+    match stream.recv().await {
+        Some(Ok(response)) => Ok(response),
+        Some(Err(e)) => Err(e.into()),
+        None => Err(ClientError::Closed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::{config::{Config, NodeConfiguration, NodeId}, stream::MessageStream};
+    use tx_proto::ClientRequest;
+    use tx_server::{coordinator::Server, testing::{local_config, start_cluster}};
+    use crate::{ClientError, Transaction};
+    use std::time::Duration;
+
+    /// Starts a single node that hosts shard A and returns its config.
+    pub(crate) async fn start_node() -> Config {
+        let config = local_config(&["A"]);
+        start_cluster(&config).await;
+        config
+    }
+
+    #[tokio::test]
+    async fn test_transactions_over_an_embedded_node() {
+        let servers = Server::embedded(&["A", "B"]).await;
+        let connector = servers[0].connector();
+        for mut server in servers {
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let mut tx = Transaction::over(connector.connect().unwrap());
+        assert!(tx.deposit("A.x", 5).await.unwrap().is_ok());
+        assert!(tx.deposit("B.y", 3).await.unwrap().is_ok());
+        assert!(tx.commit().await.unwrap().is_committed());
+
+        let mut tx = Transaction::over(connector.connect().unwrap());
+        assert!(matches!(tx.balance("B.y").await.unwrap(), tx_proto::ClientResponse::Value(_, 3)));
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_request_aborts_transaction() {
+        // A coordinator that never answers and reports what it received
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let coordinator = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = MessageStream::from_tcp_stream(stream);
+            let mut received = vec![];
+            while let Some(Ok(request)) = stream.recv::<ClientRequest>().await {
+                received.push(request);
+            }
+            received
+        });
+
+        let node_id = NodeId(0);
+        let node = NodeConfiguration {
+            node_id,
+            name: "A".into(),
+            hostname: "127.0.0.1".into(),
+            port,
+            connection_list: vec![],
+            shards: vec!["A".into()]
+        };
+
+        let mut tx = Transaction::begin(&node).await.unwrap();
+        tx.set_timeout(Some(Duration::from_millis(50)));
+        assert!(matches!(tx.balance("A.x").await, Err(ClientError::TimedOut)));
+        assert!(matches!(tx.commit().await, Err(ClientError::Closed)));
+
+        let received = tokio::time::timeout(Duration::from_secs(5), coordinator).await.unwrap().unwrap();
+        assert!(matches!(&received[..], [ClientRequest::Deadline(_, read), ClientRequest::Abort] if matches!(**read, ClientRequest::ReadBalance(_))));
+    }
+}
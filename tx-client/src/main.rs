@@ -1,9 +1,14 @@
 use tx_common::{
-    ClientRequest::*, ClientResponse, BalanceDiff, stream::MessageStream,
-    config::{Config, parse_config, NodeConfiguration}
+    config::{Config, parse_config, NodeConfiguration, NodeId}, transaction_id::TransactionId, admin::{Access, AdminRequest, AdminResponse, ConcurrencyMode}, query::Query, stream::SocketOptions
 };
+use tx_proto::{BalanceDiff, BalancePredicate, CommitVerbosity, IsolationLevel, SessionSettings, ClientRequest::*};
+use tx_client::{ClientError, Transaction};
 use rand::seq::IteratorRandom;
-use log::{error, trace};
+use std::time::Duration;
+use log::{error, info, trace};
+
+/// How long `PAUSE` without a deadline gives transactions under way to finish
+const DEFAULT_PAUSE_WITHIN: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() {
@@ -45,11 +50,16 @@ async fn main() {
         buffer.clear();
     }
 
-    trace!("Connecting to Node {}...", coordinator_cfg.node_id);
-    let mut stream = match tokio::net::TcpStream::connect(&shard_addr).await {
-        Ok(s) => MessageStream::from_tcp_stream(s),
+    let socket_options = SocketOptions::from_env().unwrap_or_else(|e| {
+        eprintln!("{}: {e}", &args[0]);
+        std::process::exit(1);
+    });
+
+    trace!("Connecting to Node {}...", coordinator_cfg.name);
+    let mut transaction = match Transaction::begin_with(coordinator_cfg, socket_options).await {
+        Ok(transaction) => transaction,
         Err(e) => {
-            eprintln!("Failed to connect to coordinator {} ({}): {}", coordinator_cfg.node_id, shard_addr, e.to_string());
+            eprintln!("Failed to connect to coordinator {} ({}): {:?}", coordinator_cfg.name, shard_addr, e);
             std::process::exit(1);
         }
     };
@@ -62,6 +72,14 @@ async fn main() {
 
         let request = match delimited[..] {
             ["BALANCE", account_id] => ReadBalance(account_id.into()),
+            ["PROBE", account_id] => ReadBalanceIfExists(account_id.into()),
+            ["BALANCE", account_id, ms] => match ms.parse::<u64>() {
+                Ok(ms) => ReadBalanceStale(account_id.into(), Duration::from_millis(ms)),
+                Err(e) => {
+                    error!("ABORTING! Failed to parse staleness: {e:?}");
+                    Abort
+                }
+            },
             ["DEPOSIT", account_id, amount] => {
                 match amount.parse::<i64>() {
                     Ok(amount) => WriteBalance(account_id.into(), BalanceDiff(amount)),
@@ -71,17 +89,137 @@ async fn main() {
                     }
                 }
             },
+            ["DEPOSIT", account_id, amount, ttl] => {
+                match (amount.parse::<i64>(), ttl.parse::<u64>()) {
+                    (Ok(amount), Ok(ttl)) => WriteBalanceWithTtl(account_id.into(), BalanceDiff(amount), Duration::from_secs(ttl)),
+                    (amount, ttl) => {
+                        error!("ABORTING! Failed to parse amount or TTL: {amount:?} {ttl:?}");
+                        Abort
+                    }
+                }
+            },
             ["WITHDRAW", account_id, amount] => {
                 match amount.parse::<i64>() {
-                    Ok(amount) => WriteBalance(account_id.into(), BalanceDiff(amount * -1)),
+                    Ok(amount) => WriteBalance(account_id.into(), BalanceDiff(-amount)),
+                    Err(e) => {
+                        error!("ABORTING! Failed to parse amount: {e:?}");
+                        Abort
+                    }
+                }
+            },
+            ["ASSERT", account_id, comparison, amount] => {
+                match amount.parse::<i64>().ok().and_then(|amount| BalancePredicate::parse(comparison, amount)) {
+                    Some(predicate) => AssertBalance(account_id.into(), predicate),
+                    None => {
+                        error!("ABORTING! Failed to parse assertion: {comparison} {amount}");
+                        Abort
+                    }
+                }
+            },
+            ["INCREMENT", account_id, amount] => {
+                match amount.parse::<i64>() {
+                    Ok(amount) => Increment(account_id.into(), BalanceDiff(amount)),
                     Err(e) => {
                         error!("ABORTING! Failed to parse amount: {e:?}");
                         Abort
                     }
                 }
             },
+            ["SWAP", first, second] => Swap(first.into(), second.into()),
             ["COMMIT"] => Commit,
+            ["DECISIONS"] => Admin(AdminRequest::DecisionLog),
+            ["DECISION", ts, node_id] => match (ts.parse::<u128>(), node_id.parse::<u32>()) {
+                (Ok(ts), Ok(node_id)) => Admin(AdminRequest::Decision(TransactionId::at(ts, NodeId(node_id)))),
+                _ => {
+                    error!("ABORTING! Failed to parse transaction id: expected <timestamp> <coordinator>");
+                    Abort
+                }
+            },
+            ["STATUS"] => Admin(AdminRequest::Status),
+            ["DRAIN"] => Admin(AdminRequest::Drain),
+            ["PAUSE"] => Admin(AdminRequest::Pause(DEFAULT_PAUSE_WITHIN)),
+            ["PAUSE", ms] => match ms.parse::<u64>() {
+                Ok(ms) => Admin(AdminRequest::Pause(Duration::from_millis(ms))),
+                Err(e) => {
+                    error!("ABORTING! Failed to parse pause deadline: {e:?}");
+                    Abort
+                }
+            },
+            ["RESUME"] => Admin(AdminRequest::Resume),
+            ["VERIFY"] => Admin(AdminRequest::Verification),
+            ["SNAPSHOT"] => Admin(AdminRequest::Snapshot),
+            ["SNAPSHOT", "ALL"] => Admin(AdminRequest::ClusterSnapshot),
+            ["CLUSTER"] => ClusterInfo,
+            ["CONTENTION"] => Admin(AdminRequest::Contention),
+            ["LABEL", label] => Label(label.into()),
+            ["LABELS"] => Admin(AdminRequest::Labels),
+            ["AUTH", tenant, secret] => Authenticate(tenant.into(), secret.into()),
+            ["LIFETIME", ms] => match ms.parse::<u64>() {
+                Ok(ms) => Lifetime(Duration::from_millis(ms)),
+                Err(e) => {
+                    error!("ABORTING! Failed to parse lifetime: {e:?}");
+                    Abort
+                }
+            },
+            ["LIFETIME"] => Lifetime(Duration::MAX),
+            ["HELLO", ref options @ ..] => match parse_settings(options) {
+                Ok(settings) => Hello(settings),
+                Err(e) => {
+                    error!("ABORTING! Failed to parse session settings: {e}");
+                    Abort
+                }
+            },
+            ["VERBOSE"] => Verbosity(CommitVerbosity::Values),
+            ["QUIET"] => Verbosity(CommitVerbosity::Quiet),
+            ["TENANTS"] => Admin(AdminRequest::Tenants),
+            ["ROUTES"] => Admin(AdminRequest::Routes),
+            ["MEMBERS"] => Admin(AdminRequest::Members),
+            ["DIGEST"] => Admin(AdminRequest::Digest),
+            ["ACLS"] => Admin(AdminRequest::Acls),
+            ["OWN", account_id, tenant] => Admin(AdminRequest::SetOwner(account_id.into(), tenant.into())),
+            ["GRANT", account_id, tenant, "r"] => Admin(AdminRequest::Grant(account_id.into(), tenant.into(), Some(Access::Read))),
+            ["GRANT", account_id, tenant, "rw"] => Admin(AdminRequest::Grant(account_id.into(), tenant.into(), Some(Access::ReadWrite))),
+            ["REVOKE", account_id, tenant] => Admin(AdminRequest::Grant(account_id.into(), tenant.into(), None)),
+            ["DISOWN", account_id] => Admin(AdminRequest::RemoveAcl(account_id.into())),
+            ["HOTTEST"] => Admin(AdminRequest::AccountStats(10)),
+            ["HOTTEST", n] => match n.parse::<usize>() {
+                Ok(n) => Admin(AdminRequest::AccountStats(n)),
+                Err(e) => {
+                    error!("ABORTING! Failed to parse number of accounts: {e:?}");
+                    Abort
+                }
+            },
+            ["MEMORY"] => Admin(AdminRequest::Memory(10)),
+            ["MEMORY", n] => match n.parse::<usize>() {
+                Ok(n) => Admin(AdminRequest::Memory(n)),
+                Err(e) => {
+                    error!("ABORTING! Failed to parse number of transactions and accounts: {e:?}");
+                    Abort
+                }
+            },
+            ["PIN", "adaptive"] => Admin(AdminRequest::PinConcurrencyMode(None)),
+            ["PIN", "timestamp-ordering"] => Admin(AdminRequest::PinConcurrencyMode(Some(ConcurrencyMode::TimestampOrdering))),
+            ["PIN", "wound-wait"] => Admin(AdminRequest::PinConcurrencyMode(Some(ConcurrencyMode::WoundWait))),
+            ["COMMITS", seq] => match seq.parse::<u64>() {
+                Ok(seq) => Admin(AdminRequest::CommitsSince(seq)),
+                Err(e) => {
+                    error!("ABORTING! Failed to parse sequence number: {e:?}");
+                    Abort
+                }
+            },
+            ["SELECT", ..] => match Query::parse(buffer.trim()) {
+                Ok(query) => {
+                    run_query(&mut transaction, query).await;
+                    buffer.clear();
+                    continue;
+                },
+                Err(e) => {
+                    error!("ABORTING! Failed to parse query: {e}");
+                    Abort
+                }
+            },
             ["ABORT"] => Abort,
+            ["ABORT", ..] => AbortWithReason(buffer.trim()["ABORT".len()..].trim().to_string()),
             _ => {
                 error!("ABORTING! Unknown command: `{}`", buffer.trim());
                 Abort
@@ -89,24 +227,23 @@ async fn main() {
         };
 
         trace!("Sending command to coordinator: {request:?}");
-        if let Err(e) = stream.send(request).await {
-            error!("Failed to send message to coordinator: {e:?}");
-            std::process::exit(1);
-        }
-
-        let response: ClientResponse = match stream.recv().await {
-            Some(Ok(response)) => response,
-            Some(Err(e)) => {
-                error!("Error on receiving response: {e:?}");
+        let response = match transaction.request(request).await {
+            Ok(response) => response,
+            Err(ClientError::Closed) => {
+                error!("Error on receiving response: other half closed");
                 std::process::exit(1);
             },
-            None => {
-                error!("Error on receiving response: other half closed");
+            Err(e) => {
+                error!("Failed to exchange message with coordinator: {e:?}");
                 std::process::exit(1);
             }
         };
 
         println!("{}", response.format());
+        if response.is_err() {
+            info!("Transaction aborted: {response:?}");
+        }
+
         if response.is_final() {
             break;
         }
@@ -114,3 +251,39 @@ async fn main() {
         buffer.clear();
     }
 }
+
+/// Parses the options of `HELLO [STALE <ms>] [DEADLINE <ms>] [VERBOSE]`.
+fn parse_settings(mut options: &[&str]) -> Result<SessionSettings, String> {
+    let mut settings = SessionSettings::default();
+    let millis = |ms: &str| ms.parse::<u64>().map(Duration::from_millis).map_err(|e| format!("`{ms}`: {e}"));
+    loop {
+        options = match options {
+            [] => return Ok(settings),
+            ["STALE", ms, rest @ ..] => {
+                settings.isolation = IsolationLevel::BoundedStaleness(millis(ms)?);
+                rest
+            },
+            ["DEADLINE", ms, rest @ ..] => {
+                settings.default_deadline = Some(millis(ms)?);
+                rest
+            },
+            ["VERBOSE", rest @ ..] => {
+                settings.verbosity = CommitVerbosity::Values;
+                rest
+            },
+            [option, ..] => return Err(format!("unknown option `{option}`"))
+        };
+    }
+}
+
+/// Prints a query's rows as they stream in, then its summary.
+async fn run_query(transaction: &mut Transaction, query: Query) {
+    let result = transaction.query(query, |rows| println!("{}", AdminResponse::QueryRows(rows).format())).await;
+    match result {
+        Ok(summary) => println!("{}", AdminResponse::QueryDone(Box::new(summary)).format()),
+        Err(e) => {
+            error!("Failed to exchange message with coordinator: {e:?}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{ClientError, Transaction};
+use tx_common::{config::{Config, NodeId}, stream::{MessageStream, SocketOptions}};
+use tokio::sync::Semaphore;
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+pub static DEFAULT_MAX_CONNECTIONS: usize = 64;
+pub static DEFAULT_MAX_PER_COORDINATOR: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PoolLimits {
+    /// The number of transactions that may be open across all coordinators
+    pub max_connections: usize,
+    /// The number of transactions that may be open against one coordinator
+    pub max_per_coordinator: usize
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            max_per_coordinator: DEFAULT_MAX_PER_COORDINATOR
+        }
+    }
+}
+
+/// Idle connections, by the coordinator at their other end.
+type IdleConnections = Arc<Mutex<HashMap<NodeId, Vec<MessageStream>>>>;
+
+/// Bounds the number of coordinator connections shared by many application
+/// tasks. A connection carries one transaction at a time, so every 
+/// transaction holds a slot in the pool until it is dropped, and tasks that
+/// begin a transaction while the pool is full wait in FIFO order for a slot.
+/// A transaction dropped after it committed or aborted leaves its connection
+/// in the pool, and the next transaction begun on that coordinator carries
+/// on over it, along with any settings the last one sent with `HELLO`. A
+/// transaction dropped while still under way closes its connection, which
+/// the coordinator treats as an abort. Idle connections count against
+/// `max_connections`, so the pool closes one to make room for a new 
+/// connection to another coordinator.
+#[derive(Clone)]
+pub struct ConnectionPool {
+    config: Arc<Config>,
+    limits: PoolLimits,
+    connections: Arc<Semaphore>,
+    coordinators: Arc<HashMap<NodeId, Arc<Semaphore>>>,
+    idle: IdleConnections,
+    socket_options: SocketOptions
+}
+
+/// Returns a transaction's connection to the pool it came from.
+pub(crate) struct Checkout {
+    idle: IdleConnections,
+    node_id: NodeId
+}
+
+impl Checkout {
+    /// Keeps `stream` for the next transaction begun on its coordinator.
+    pub(crate) fn put_back(self, stream: MessageStream) {
+        self.idle.lock().unwrap().entry(self.node_id).or_default().push(stream);
+    }
+}
+
+impl ConnectionPool {
+    pub fn new(config: Config, limits: PoolLimits) -> Self {
+        let coordinators = config
+            .keys()
+            .map(|node_id| (*node_id, Arc::new(Semaphore::new(limits.max_per_coordinator))))
+            .collect();
+
+        Self {
+            config: Arc::new(config),
+            limits,
+            connections: Arc::new(Semaphore::new(limits.max_connections)),
+            coordinators: Arc::new(coordinators),
+            idle: Arc::default(),
+            socket_options: SocketOptions::default()
+        }
+    }
+
+    /// Set the options of every connection the pool opens.
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = options;
+        self
+    }
+
+    /// Begins a transaction on the least loaded coordinator, waiting until
+    /// the pool has room for another transaction. The transaction reuses an
+    /// idle connection to that coordinator if the pool holds one.
+    pub async fn begin(&self) -> Result<Transaction, ClientError> {
+        let connection = self.connections.clone().acquire_owned().await.unwrap();
+
+        // Pick the coordinator with the most free slots, breaking ties by id
+        // so that load spreads deterministically.
+        let (node_id, slots) = self.coordinators
+            .iter()
+            .max_by_key(|(node_id, slots)| (slots.available_permits(), std::cmp::Reverse(**node_id)))
+            .expect("the pool has no coordinators");
+        let coordinator = slots.clone().acquire_owned().await.unwrap();
+
+        let mut transaction = match self.reuse(*node_id) {
+            Some(stream) => Transaction::over(stream),
+            None => Transaction::begin_with(&self.config[node_id], self.socket_options).await?
+        };
+        transaction.pool = Some(Checkout { idle: self.idle.clone(), node_id: *node_id });
+        transaction._permits = vec![coordinator, connection];
+        Ok(transaction)
+    }
+
+    /// Takes an idle connection to `node_id`, or else closes an idle 
+    /// connection to another coordinator if opening one more would exceed
+    /// `max_connections`.
+    fn reuse(&self, node_id: NodeId) -> Option<MessageStream> {
+        let mut idle = self.idle.lock().unwrap();
+        if let Some(stream) = idle.get_mut(&node_id).and_then(Vec::pop) {
+            return Some(stream);
+        }
+
+        let in_use = self.limits.max_connections - self.connections.available_permits();
+        let idle_count: usize = idle.values().map(Vec::len).sum();
+        if idle_count + in_use > self.limits.max_connections {
+            if let Some(streams) = idle.values_mut().find(|streams| !streams.is_empty()) {
+                streams.pop();
+            }
+        }
+        None
+    }
+
+    /// The number of connections kept open with no transaction under way.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().values().map(Vec::len).sum()
+    }
+
+    /// The number of transactions that can begin without waiting.
+    pub fn available(&self) -> usize {
+        let per_coordinator: usize = self.coordinators
+            .values()
+            .map(|slots| slots.available_permits())
+            .sum();
+
+        per_coordinator.min(self.connections.available_permits())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::start_node;
+    use tx_common::admin::{AdminRequest, AdminResponse};
+    use tx_proto::{ClientRequest, ClientResponse};
+    use std::time::Duration;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pool_waits_for_a_free_connection() {
+        let config = start_node().await;
+        let limits = PoolLimits { max_connections: 2, max_per_coordinator: 2 };
+        let pool = ConnectionPool::new(config, limits);
+
+        let mut first = pool.begin().await.unwrap();
+        let second = pool.begin().await.unwrap();
+        assert_eq!(pool.available(), 0);
+
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.begin().await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        assert!(matches!(first.deposit("A.x", 10).await.unwrap(), ClientResponse::Ok));
+        assert!(matches!(first.commit().await.unwrap(), ClientResponse::CommitOk));
+        drop(first);
+
+        tokio::time::timeout(Duration::from_secs(5), waiting).await.unwrap().unwrap().unwrap();
+        drop(second);
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pool_runs_concurrent_transactions() {
+        let config = start_node().await;
+        let limits = PoolLimits { max_connections: 4, max_per_coordinator: 4 };
+        let pool = ConnectionPool::new(config, limits);
+
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let mut tx = pool.begin().await.unwrap();
+                    let account_id = format!("A.{i}");
+                    assert!(matches!(tx.deposit(account_id, 1).await.unwrap(), ClientResponse::Ok));
+                    tx.commit().await.unwrap()
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            assert!(matches!(task.await.unwrap(), ClientResponse::CommitOk));
+        }
+
+        let mut tx = pool.begin().await.unwrap();
+        assert!(matches!(tx.balance("A.7").await.unwrap(), ClientResponse::Value(_, 1)));
+    }
+
+    #[tokio::test]
+    async fn test_pool_reuses_idle_connections() {
+        let config = start_node().await;
+        let pool = ConnectionPool::new(config, PoolLimits::default());
+
+        for _ in 0..3 {
+            let mut tx = pool.begin().await.unwrap();
+            assert!(matches!(tx.deposit("A.x", 1).await.unwrap(), ClientResponse::Ok));
+            assert!(matches!(tx.commit().await.unwrap(), ClientResponse::CommitOk));
+            drop(tx);
+            assert_eq!(pool.idle(), 1);
+        }
+
+        let mut tx = pool.begin().await.unwrap();
+        assert_eq!(pool.idle(), 0);
+        match tx.request(ClientRequest::Admin(AdminRequest::Memory(0))).await.unwrap() {
+            ClientResponse::Admin(AdminResponse::Memory(report)) => assert_eq!(report.sessions, 1),
+            response => panic!("unexpected response {response:?}")
+        }
+        assert!(matches!(tx.balance("A.x").await.unwrap(), ClientResponse::Value(_, 3)));
+    }
+
+    #[tokio::test]
+    async fn test_pool_closes_connections_of_unfinished_transactions() {
+        let config = start_node().await;
+        let pool = ConnectionPool::new(config, PoolLimits::default());
+
+        let mut tx = pool.begin().await.unwrap();
+        assert!(matches!(tx.deposit("A.x", 5).await.unwrap(), ClientResponse::Ok));
+        drop(tx);
+        assert_eq!(pool.idle(), 0);
+
+        let mut tx = pool.begin().await.unwrap();
+        assert!(matches!(tx.deposit("A.x", 1).await.unwrap(), ClientResponse::Ok));
+        assert!(matches!(tx.commit().await.unwrap(), ClientResponse::CommitOk));
+        drop(tx);
+
+        let mut tx = pool.begin().await.unwrap();
+        assert!(matches!(tx.balance("A.x").await.unwrap(), ClientResponse::Value(_, 1)));
+    }
+}
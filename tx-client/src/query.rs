@@ -0,0 +1,69 @@
+use crate::{ClientError, Transaction};
+use tx_common::{
+    AccountId, Amount,
+    admin::{AdminRequest, AdminResponse}, query::{Query, QuerySummary}
+};
+use tx_proto::{ClientRequest, ClientResponse};
+
+impl Transaction {
+    /// Runs a query through the coordinator, which gathers a part from every
+    /// shard, each read as this transaction, so the parts are a consistent 
+    /// cut of the cluster that includes the transaction's own writes. 
+    /// Selected rows arrive in batches, one shard after another, and 
+    /// `on_rows` is called with each batch as it is received. The query 
+    /// leaves the transaction open, but the transaction's timeout does not
+    /// apply to it.
+    pub async fn query(&mut self, query: Query, mut on_rows: impl FnMut(Vec<(AccountId, Amount)>)) -> Result<QuerySummary, ClientError> {
+        let stream = self.stream.as_mut().ok_or(ClientError::Closed)?;
+        self.idle = false;
+        stream.send(ClientRequest::Admin(AdminRequest::Query(query))).await?;
+        loop {
+            match stream.recv().await {
+                Some(Ok(ClientResponse::Admin(AdminResponse::QueryRows(rows)))) => on_rows(rows),
+                Some(Ok(ClientResponse::Admin(AdminResponse::QueryDone(summary)))) => return Ok(*summary),
+                Some(Ok(response)) => {
+                    self.idle = response.is_final();
+                    return Err(ClientError::Unexpected(response));
+                },
+                Some(Err(e)) => return Err(e.into()),
+                None => return Err(ClientError::Closed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::{config::NodeId, query::Query};
+    use tx_proto::ClientResponse;
+    use crate::{test::start_node, Transaction};
+
+    #[tokio::test]
+    async fn test_query_reads_as_the_transaction() {
+        let config = start_node().await;
+        let node = &config[&NodeId(0)];
+
+        let mut committed = Transaction::begin(node).await.unwrap();
+        for (account_id, amount) in [("A.x", 10), ("A.y", 5), ("A.z", 1)] {
+            assert!(matches!(committed.deposit(account_id, amount).await.unwrap(), ClientResponse::Ok));
+        }
+        assert!(matches!(committed.commit().await.unwrap(), ClientResponse::CommitOk));
+
+        // The query sees the transaction's own deposit, but not one of a 
+        // newer transaction
+        let mut tentative = Transaction::begin(node).await.unwrap();
+        assert!(matches!(tentative.deposit("A.w", 100).await.unwrap(), ClientResponse::Ok));
+        let mut newer = Transaction::begin(node).await.unwrap();
+        assert!(matches!(newer.deposit("A.v", 100).await.unwrap(), ClientResponse::Ok));
+
+        let mut rows = vec![];
+        let query = Query::parse("SELECT key, value WHERE value > 1").unwrap();
+        let summary = tentative.query(query, |batch| rows.extend(batch)).await.unwrap();
+        assert_eq!(rows, vec![("A.w".into(), 100), ("A.x".into(), 10), ("A.y".into(), 5)]);
+        assert_eq!((summary.count, summary.sum), (3, 115));
+        assert_eq!(summary.snapshots, vec![(NodeId(0), 1)]);
+
+        // The query left the transaction open
+        assert!(matches!(tentative.commit().await.unwrap(), ClientResponse::CommitOk));
+    }
+}
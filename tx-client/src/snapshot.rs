@@ -0,0 +1,92 @@
+use crate::{ClientError, Transaction};
+use tx_common::{
+    admin::{AdminRequest, AdminResponse, ShardSnapshot}, config::{Config, NodeConfiguration}
+};
+use tx_proto::{ClientRequest, ClientResponse};
+
+/// Asks a node for a snapshot of the committed balances on its shard. The
+/// node pauses commits while it copies them, but reads and writes carry on,
+/// so a snapshot never aborts or waits on a transaction.
+pub async fn shard_snapshot(node: &NodeConfiguration) -> Result<ShardSnapshot, ClientError> {
+    let mut tx = Transaction::begin(node).await?;
+    match tx.request(ClientRequest::Admin(AdminRequest::Snapshot)).await? {
+        ClientResponse::Admin(AdminResponse::Snapshot(snapshot)) => Ok(snapshot),
+        resp => Err(ClientError::Unexpected(resp))
+    }
+}
+
+/// Takes a snapshot of every shard in the cluster at once, sorted by node.
+/// A node of the cluster reads every shard as one transaction, so the 
+/// snapshots together are a consistent cut: a transfer an older transaction
+/// is still committing is waited for and in the snapshots of both sides, 
+/// and one a newer transaction makes is in neither. The `seq` of each 
+/// snapshot is the last commit its shard applied once it was read. 
+/// Witnesses hold no data, so they are skipped.
+pub async fn cluster_snapshot(config: &Config) -> Result<Vec<ShardSnapshot>, ClientError> {
+    let Some(node) = config.values().find(|node| !node.is_witness()) else {
+        return Ok(Vec::new());
+    };
+    let mut tx = Transaction::begin(node).await?;
+    match tx.request(ClientRequest::Admin(AdminRequest::ClusterSnapshot)).await? {
+        ClientResponse::Admin(AdminResponse::Snapshots(mut snapshots)) => {
+            snapshots.retain(|snapshot| config.get(&snapshot.node_id).is_some_and(|node| !node.is_witness()));
+            Ok(snapshots)
+        },
+        resp => Err(ClientError::Unexpected(resp))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::config::NodeId;
+    use tx_proto::ClientResponse;
+    use tx_server::testing::start_transfer_in_transit;
+    use crate::{test::start_node, Transaction};
+    use std::time::Duration;
+    use super::*;
+
+    fn total(snapshots: &[ShardSnapshot]) -> i64 {
+        snapshots.iter().flat_map(|snapshot| &snapshot.accounts).map(|account| account.balance).sum()
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_holds_committed_balances_only() {
+        let config = start_node().await;
+        let node = &config[&NodeId(0)];
+
+        let mut committed = Transaction::begin(node).await.unwrap();
+        assert!(matches!(committed.deposit("A.x", 10).await.unwrap(), ClientResponse::Ok));
+        assert!(matches!(committed.deposit("A.y", 5).await.unwrap(), ClientResponse::Ok));
+        assert!(matches!(committed.commit().await.unwrap(), ClientResponse::CommitOk));
+
+        // The snapshot waits for an older transaction to finish, and holds 
+        // none of its writes once it aborts
+        let mut tentative = Transaction::begin(node).await.unwrap();
+        assert!(matches!(tentative.deposit("A.x", 1).await.unwrap(), ClientResponse::Ok));
+        assert!(matches!(tentative.deposit("A.z", 1).await.unwrap(), ClientResponse::Ok));
+        let snapshot = tokio::spawn({
+            let config = config.clone();
+            async move { cluster_snapshot(&config).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!snapshot.is_finished());
+        tentative.abort().await.unwrap();
+
+        let snapshots = snapshot.await.unwrap().unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].seq, 1);
+        let balances: Vec<_> = snapshots[0].accounts
+            .iter()
+            .map(|account| (account.account_id.as_str(), account.balance))
+            .collect();
+        assert_eq!(balances, vec![("A.x", 10), ("A.y", 5)]);
+    }
+    #[tokio::test]
+    async fn test_cluster_snapshot_reads_a_consistent_cut() {
+        // B may still hold the deposit as prepared, but the snapshot waits 
+        // for it rather than miss the money in transit
+        let config = start_transfer_in_transit().await;
+        let snapshots = cluster_snapshot(&config).await.unwrap();
+        assert_eq!(total(&snapshots), 200, "{snapshots:?}");
+    }
+}
@@ -0,0 +1,159 @@
+//! The checks a soak test makes of a cluster between bursts of transfers.
+//! Transfers conserve the total of all balances and only touch a fixed set of
+//! accounts, so once they stop the committed balances must add up to what the
+//! test deposited, and every node must have let go of the transactions. Only
+//! built with the `cli` feature, for the tx-soak binary.
+
+use crate::{ClientError, Transaction};
+use tx_common::{
+    Amount,
+    admin::{AdminRequest, AdminResponse, ShardSnapshot},
+    config::{NodeConfiguration, NodeId}
+};
+use tx_proto::{ClientRequest, ClientResponse};
+
+/// What a node holds on to between transactions, as of one check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeSample {
+    pub node_id: NodeId,
+    /// The node's resident memory in KiB, where the platform reports it
+    pub rss_kib: Option<u64>,
+    /// Accounts with a committed balance on the node's shard
+    pub accounts: usize,
+    /// Commits the node decided that are not yet applied everywhere
+    pub unapplied: usize,
+    /// Transactions with the test's label the node still coordinates
+    pub active: usize,
+    /// Invariant violations the node's shard found since it started
+    pub violations: u64
+}
+
+impl NodeSample {
+    pub fn new(node_id: NodeId) -> Self {
+        Self { node_id, rss_kib: None, accounts: 0, unapplied: 0, active: 0, violations: 0 }
+    }
+
+    pub fn format(&self) -> String {
+        let rss = self.rss_kib.map_or("?".into(), |rss| format!("{rss}KiB"));
+        format!(
+            "node={} rss={rss} accounts={} unapplied={} active={} violations={}",
+            self.node_id, self.accounts, self.unapplied, self.active, self.violations
+        )
+    }
+}
+
+async fn admin(node: &NodeConfiguration, request: AdminRequest) -> Result<AdminResponse, ClientError> {
+    let mut tx = Transaction::begin(node).await?;
+    match tx.request(ClientRequest::Admin(request)).await? {
+        ClientResponse::Admin(resp) => Ok(resp),
+        resp => Err(ClientError::Unexpected(resp))
+    }
+}
+
+/// Samples what a node holds on to, counting active transactions labelled
+/// `label`. The resident memory is left for the caller, which knows the
+/// node's process.
+pub async fn sample_node(node: &NodeConfiguration, label: &str) -> Result<NodeSample, ClientError> {
+    let mut sample = NodeSample::new(node.node_id);
+    match admin(node, AdminRequest::Status).await? {
+        AdminResponse::Status(status) => sample.unapplied = status.unapplied.len(),
+        resp => return Err(ClientError::Unexpected(ClientResponse::Admin(resp)))
+    }
+    match admin(node, AdminRequest::Labels).await? {
+        AdminResponse::Labels(labels) =>
+            sample.active = labels.iter().filter(|stats| stats.label == label).map(|stats| stats.active).sum(),
+        resp => return Err(ClientError::Unexpected(ClientResponse::Admin(resp)))
+    }
+    match admin(node, AdminRequest::Verification).await? {
+        AdminResponse::Verification(verification) => sample.violations = verification.violations_found,
+        resp => return Err(ClientError::Unexpected(ClientResponse::Admin(resp)))
+    }
+    if !node.is_witness() {
+        match admin(node, AdminRequest::Snapshot).await? {
+            AdminResponse::Snapshot(snapshot) => sample.accounts = snapshot.accounts.len(),
+            resp => return Err(ClientError::Unexpected(ClientResponse::Admin(resp)))
+        }
+    }
+
+    Ok(sample)
+}
+
+/// The resident memory of a process in KiB, read from `/proc`, so only on
+/// Linux.
+pub fn resident_memory(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_ascii_whitespace().nth(1)?.parse().ok()
+}
+
+/// Checks the balances of a quiescent cluster against what the test
+/// deposited, returning a description of every way they differ.
+pub fn check_balances(snapshots: &[ShardSnapshot], accounts: usize, total: Amount) -> Vec<String> {
+    let mut failures = Vec::new();
+    let balances: Vec<_> = snapshots.iter().flat_map(|snapshot| snapshot.accounts.iter()).collect();
+    if balances.len() != accounts {
+        failures.push(format!("expected {accounts} accounts, found {}", balances.len()));
+    }
+
+    let sum: Amount = balances.iter().map(|account| account.balance).sum();
+    if sum != total {
+        failures.push(format!("balances sum to {sum} rather than {total}"));
+    }
+
+    failures.extend(balances
+        .iter()
+        .filter(|account| account.balance < 0)
+        .map(|account| format!("{} is overdrawn at {}", account.account_id, account.balance)));
+    failures
+}
+
+/// Checks that what each node holds on to has not grown since the first
+/// sample: its memory by at most `max_growth` times, plus `slack_kib` for
+/// allocator noise, and its accounts not at all.
+pub fn check_growth(first: &[NodeSample], last: &[NodeSample], max_growth: f64, slack_kib: u64) -> Vec<String> {
+    let mut failures = Vec::new();
+    for (first, last) in first.iter().zip(last) {
+        if let (Some(before), Some(after)) = (first.rss_kib, last.rss_kib) {
+            let limit = (before as f64 * max_growth) as u64 + slack_kib;
+            if after > limit {
+                failures.push(format!("node {} grew from {before}KiB to {after}KiB, over {limit}KiB", last.node_id));
+            }
+        }
+        if last.accounts != first.accounts {
+            failures.push(format!("node {} went from {} to {} accounts", last.node_id, first.accounts, last.accounts));
+        }
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::{admin::AccountSnapshot, transaction_id::TransactionId};
+    use super::*;
+
+    fn snapshot(balances: &[(&str, Amount)]) -> ShardSnapshot {
+        ShardSnapshot {
+            node_id: NodeId(0),
+            seq: 1,
+            accounts: balances
+                .iter()
+                .map(|(account_id, balance)| AccountSnapshot { account_id: account_id.to_string(), balance: *balance, committed_by: TransactionId::at(1, NodeId(0)) })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_checks_find_lost_money_and_growth() {
+        assert!(check_balances(&[snapshot(&[("A.x", 7), ("A.y", 3)])], 2, 10).is_empty());
+        assert_eq!(check_balances(&[snapshot(&[("A.x", 12), ("A.y", -3)])], 3, 10), [
+            "expected 3 accounts, found 2", "balances sum to 9 rather than 10", "A.y is overdrawn at -3"
+        ]);
+
+        let sample = |rss_kib, accounts| NodeSample { rss_kib: Some(rss_kib), accounts, ..NodeSample::new(NodeId(0)) };
+        assert!(check_growth(&[sample(1000, 2)], &[sample(1400, 2)], 1.5, 0).is_empty());
+        assert_eq!(check_growth(&[sample(1000, 2)], &[sample(1600, 3)], 1.5, 0), [
+            "node #0 grew from 1000KiB to 1600KiB, over 1500KiB", "node #0 went from 2 to 3 accounts"
+        ]);
+    }
+}
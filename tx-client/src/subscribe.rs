@@ -0,0 +1,141 @@
+use crate::{ClientError, Transaction};
+use tx_common::{
+    AccountId, Amount, transaction_id::TransactionId,
+    admin::{AdminRequest, AdminResponse}, config::NodeConfiguration
+};
+use tx_proto::{ClientRequest, ClientResponse};
+use futures::stream::{self, Stream};
+use std::{collections::VecDeque, pin::Pin, time::Duration};
+use log::{trace, warn};
+
+pub static POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A committed change to an account's balance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountChange {
+    /// The position of the commit in the order its shard applied commits
+    pub seq: u64,
+    pub tx_id: TransactionId,
+    pub account_id: AccountId,
+    pub balance: Amount,
+    /// Whether the account expired and was removed, leaving no balance
+    pub removed: bool
+}
+
+pub type Subscription = Pin<Box<dyn Stream<Item = AccountChange> + Send>>;
+
+/// Streams the changes committed by a node's shard after the given sequence
+/// number, oldest first. The stream only asks the node for more commits once
+/// the consumer has taken every change it already has, and it reconnects and
+/// resumes from the last change it yielded whenever the connection is lost.
+/// Changes the shard evicted from its commit log while the stream was behind
+/// are skipped.
+pub fn subscribe(node: NodeConfiguration, after: u64) -> Subscription {
+    let watch = Watch { node, connection: None, after, pending: VecDeque::new() };
+
+    Box::pin(stream::unfold(watch, |mut watch| async move {
+        let change = watch.next().await;
+        Some((change, watch))
+    }))
+}
+
+struct Watch {
+    node: NodeConfiguration,
+    connection: Option<Transaction>,
+    /// The sequence number of the last commit fetched from the node
+    after: u64,
+    pending: VecDeque<AccountChange>
+}
+
+impl Watch {
+    async fn next(&mut self) -> AccountChange {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return change;
+            }
+
+            match self.poll().await {
+                Ok(true) => {},
+                Ok(false) => tokio::time::sleep(POLL_INTERVAL).await,
+                Err(e) => {
+                    warn!("Lost subscription to {}: {e:?}", self.node.name);
+                    self.connection = None;
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Fetches the commits after the last one seen, returning whether there
+    /// were any.
+    async fn poll(&mut self) -> Result<bool, ClientError> {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => {
+                trace!("Subscribing to {} after commit {}", self.node.name, self.after);
+                self.connection.insert(Transaction::begin(&self.node).await?)
+            }
+        };
+
+        let request = ClientRequest::Admin(AdminRequest::CommitsSince(self.after));
+        let commits = match connection.request(request).await? {
+            ClientResponse::Admin(AdminResponse::Commits(commits)) => commits,
+            response => return Err(ClientError::Unexpected(response))
+        };
+
+        if commits.first_seq > self.after + 1 {
+            warn!("Missed commits {}..{} on {}", self.after + 1, commits.first_seq, self.node.name);
+        }
+
+        for commit in commits.commits.iter() {
+            self.after = commit.seq;
+            self.pending.extend(commit.writes.iter().map(|(account_id, balance)| AccountChange {
+                seq: commit.seq,
+                tx_id: commit.tx_id,
+                account_id: account_id.clone(),
+                balance: *balance,
+                removed: false
+            }));
+            self.pending.extend(commit.removed.iter().map(|account_id| AccountChange {
+                seq: commit.seq,
+                tx_id: commit.tx_id,
+                account_id: account_id.clone(),
+                balance: 0,
+                removed: true
+            }));
+        }
+
+        Ok(!commits.commits.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::start_node;
+    use futures::StreamExt;
+    use tx_common::config::NodeId;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscription_yields_committed_changes() {
+        let config = start_node().await;
+        let node = config[&NodeId(0)].clone();
+        let changes = subscribe(node.clone(), 0);
+
+        for amount in [10, 5] {
+            let mut tx = Transaction::begin(&node).await.unwrap();
+            tx.deposit("A.x", amount).await.unwrap();
+            assert!(matches!(tx.commit().await.unwrap(), ClientResponse::CommitOk));
+        }
+
+        let changes: Vec<_> = tokio::time::timeout(Duration::from_secs(5), changes.take(2).collect())
+            .await
+            .unwrap();
+        let balances: Vec<_> = changes.iter().map(|c| (c.seq, c.account_id.as_str(), c.balance)).collect();
+        assert_eq!(balances, vec![(1, "A.x", 10), (2, "A.x", 15)]);
+
+        let mut resumed = subscribe(node, 1);
+        let change = tokio::time::timeout(Duration::from_secs(5), resumed.next()).await.unwrap();
+        assert_eq!(change.map(|c| c.balance), Some(15));
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{ClientError, Transaction};
+use tx_common::config::NodeConfiguration;
+use tx_proto::{topology::ClusterInfo, ClientRequest, ClientResponse};
+
+/// Asks a node to describe the cluster: its nodes, the shards each hosts and
+/// whether the node is connected to it, and the features the node supports.
+/// `ClusterInfo::owner` then names the node to begin a transaction with so
+/// that it runs without being forwarded, if it only touches one node's 
+/// accounts.
+pub async fn cluster_info(node: &NodeConfiguration) -> Result<ClusterInfo, ClientError> {
+    let mut tx = Transaction::begin(node).await?;
+    match tx.request(ClientRequest::ClusterInfo).await? {
+        ClientResponse::ClusterInfo(info) => Ok(*info),
+        resp => Err(ClientError::Unexpected(resp))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::config::NodeId;
+    use tx_proto::{topology::{capability, NodeRole}, PROTOCOL_VERSION};
+    use crate::test::start_node;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cluster_info_routes_to_the_owner() {
+        let config = start_node().await;
+        let info = cluster_info(&config[&NodeId(0)]).await.unwrap();
+        assert_eq!((info.node_id, info.protocol_version), (NodeId(0), PROTOCOL_VERSION));
+        assert!(info.supports(capability::TWO_PHASE_COMMIT) && info.supports(capability::READS));
+
+        let owner = info.owner("A.x").unwrap();
+        assert_eq!((owner.role, owner.joined, owner.port), (NodeRole::Shard, true, config[&NodeId(0)].port));
+        assert!(info.owner("B.x").is_none());
+
+        let mut tx = Transaction::begin(&owner.configuration()).await.unwrap();
+        assert!(matches!(tx.deposit("A.x", 5).await.unwrap(), ClientResponse::Ok));
+        assert!(matches!(tx.commit().await.unwrap(), ClientResponse::CommitOk));
+    }
+}
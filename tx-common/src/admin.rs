@@ -0,0 +1,637 @@
+use crate::{config::NodeId, query::{Query, QueryPart, QuerySummary}, transaction_id::TransactionId, AccountId, Amount};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Requests that inspect the state of the node a client is connected to
+/// rather than operate on accounts. Admin requests are answered by the node
+/// that receives them and never affect the client's transaction.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum AdminRequest {
+    /// Request the most recent two-phase commit decisions this node made as
+    /// a coordinator, oldest first.
+    DecisionLog,
+    /// Ask whether the node is ready for client traffic. A node only answers 
+    /// once it has connected to the peers it needs to start, so any other 
+    /// outcome (a refused or closed connection) means the node is not ready.
+    Status,
+    /// Request the commits applied by this node's shard after the given 
+    /// sequence number, oldest first
+    CommitsSince(u64),
+    /// Stop the node from starting new transactions or voting to commit any
+    /// that have not prepared on it, and report the work still under way. 
+    /// Repeat the request to poll until the node is safe to stop.
+    Drain,
+    /// Request the results of the background checks of the invariants of 
+    /// this node's shard
+    Verification,
+    /// Request how this node's shard resolves conflicts and how often 
+    /// conflicts abort transactions on it
+    Contention,
+    /// Pin the concurrency mode of this node's shard, or let the node choose
+    /// it from the abort rate again if `None`
+    PinConcurrencyMode(Option<ConcurrencyMode>),
+    /// Request the committed balance of every account on this node's shard,
+    /// as of a single point in the order the shard applied commits
+    Snapshot,
+    /// Request the outcomes of the transactions this node coordinated, by 
+    /// the label their clients gave them
+    Labels,
+    /// Request the usage of every tenant this node serves: the transactions
+    /// it coordinated for each and the requests it refused them
+    Tenants,
+    /// Request how often the `n` most accessed accounts on this node's shard
+    /// were read, written and aborted on, busiest first
+    AccountStats(usize),
+    /// Ask how a transaction finished on this node's shard. The node answers
+    /// the same way every time until it forgets the transaction, and with 
+    /// `None` if it never saw the transaction finish or forgot it.
+    Outcome(TransactionId),
+    /// Request the access control lists this node enforces, by account
+    Acls,
+    /// Make the given tenant the owner of an account, attaching an access
+    /// control list to it if it has none. Only the owner and the tenants it
+    /// is granted to may then touch the account through this node.
+    SetOwner(AccountId, String),
+    /// Grant a tenant access to an account that has an owner, or revoke the
+    /// tenant's access if `None`
+    Grant(AccountId, String, Option<Access>),
+    /// Remove the access control list of an account, leaving it to the
+    /// namespace of its tenant
+    RemoveAcl(AccountId),
+    /// Request the node each account the client's own transaction touched
+    /// routed to, which are the shards its commit involves
+    Routes,
+    /// Request what this node has learned by gossip about every member of
+    /// the cluster, itself included
+    Members,
+    /// Request a digest of the committed state of every shard, to compare
+    /// with the digests of another run or node
+    Digest,
+    /// Evaluate a query against every shard, reading every account as the
+    /// client's transaction. The node gathers the shards' parts and streams
+    /// any selected rows back in batches of `QueryRows`, ending with a 
+    /// `QueryDone`.
+    Query(Query),
+    /// Quiesce the whole cluster for maintenance: every node stops starting
+    /// new transactions and waits up to the given time for those under way
+    /// to finish. If any node is still busy or cannot be reached by then, 
+    /// every node resumes and the pause fails. A paused cluster answers 
+    /// admin requests, such as `Snapshot`, until it is resumed.
+    Pause(Duration),
+    /// Resume every node of a paused cluster
+    Resume,
+    /// Request what this node holds in memory for transactions and clients,
+    /// with the `n` transactions and accounts on its shard that hold the 
+    /// most, to spot state that is never released on a live node
+    Memory(usize),
+    /// Request what this node's decision log holds about a transaction, such
+    /// as the reason its client gave for aborting it, for as long as the log
+    /// keeps it
+    Decision(TransactionId),
+    /// Request the balance of every account on every shard, read as the 
+    /// client's transaction, so the snapshots together are a consistent cut
+    /// of the cluster
+    ClusterSnapshot
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum AdminResponse {
+    DecisionLog(Vec<DecisionRecord>),
+    Status(Box<NodeStatus>),
+    Commits(CommitsSince),
+    Drain(DrainStatus),
+    Verification(VerificationStatus),
+    Contention(ContentionStatus),
+    Snapshot(ShardSnapshot),
+    Labels(Vec<LabelStats>),
+    Tenants(Vec<TenantStats>),
+    AccountStats(Vec<AccountStats>),
+    /// How the transaction asked about finished, if the node knows
+    Outcome(Option<Decision>),
+    /// The access control lists asked for, or the one an admin request 
+    /// changed, if the account still has one
+    Acls(Vec<AccountAcl>),
+    /// The node each account the transaction touched routed to, by account
+    Routes(Vec<(AccountId, NodeId)>),
+    /// Every member the node knows of, by node id, or none if it does not 
+    /// gossip
+    Members(Vec<MemberStatus>),
+    /// The digests of the shards that answered, by node id
+    Digests(Vec<ShardDigest>),
+    /// The next batch of rows selected by a query
+    QueryRows(Vec<(AccountId, Amount)>),
+    /// The end of a query's results
+    QueryDone(Box<QuerySummary>),
+    /// One shard's part of a query's result, sent to the node gathering them
+    QueryPart(Box<QueryPart>),
+    Pause(PauseStatus),
+    Memory(Box<MemoryReport>),
+    /// Access to the given account cannot be granted or revoked since it has
+    /// no owner, so nothing changed
+    NoOwner(AccountId),
+    /// The snapshot of every shard, by node id
+    Snapshots(Vec<ShardSnapshot>)
+}
+
+/// The outcomes of the transactions with one label since the node started.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LabelStats {
+    pub label: String,
+    /// The transactions with the label that are running
+    pub active: usize,
+    /// The most that may run at once, if limited
+    pub max_active: Option<usize>,
+    pub committed: u64,
+    pub aborted: u64,
+    /// The transactions refused since `max_active` were already running
+    pub refused: u64,
+    /// The mean and longest time from connecting to committing or aborting
+    pub mean_latency: Duration,
+    pub max_latency: Duration
+}
+
+/// The usage of one tenant since the node started.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TenantStats {
+    pub tenant: String,
+    /// The tenant's transactions that are running
+    pub active: usize,
+    /// The most that may run at once, if limited
+    pub max_active: Option<usize>,
+    /// The reads, writes and swaps the tenant may request per second, if
+    /// limited
+    pub requests_per_sec: Option<u32>,
+    /// The reads, writes and swaps the tenant requested
+    pub requests: u64,
+    pub committed: u64,
+    pub aborted: u64,
+    /// The transactions aborted since the tenant was over its request rate
+    /// or `max_active` of its transactions were already running
+    pub throttled: u64,
+    /// The transactions aborted since they gave the wrong secret or asked 
+    /// for something outside the tenant's namespace
+    pub denied: u64
+}
+
+/// How an account on a shard has been used since the shard created it. An
+/// account removed on abort or expiry starts from nothing if it is created 
+/// again.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AccountStats {
+    pub account_id: AccountId,
+    pub reads: u64,
+    pub writes: u64,
+    /// Reads and writes that aborted their transaction on a conflict
+    pub aborts: u64,
+    pub commits: u64,
+    /// The transaction that committed the account's balance, if any has
+    pub last_committer: Option<TransactionId>
+}
+
+/// What a tenant granted access to an account may do with it.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Access {
+    Read,
+    ReadWrite
+}
+
+/// Who may touch an account: its owner may read and write it, and other 
+/// tenants only as they were granted.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountAcl {
+    pub account_id: AccountId,
+    pub owner: String,
+    /// The tenants granted access, sorted
+    pub grants: Vec<(String, Access)>
+}
+
+/// Whether a member of the cluster is up, as far as one node knows.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum MemberState {
+    Alive,
+    /// A probe of the member went unanswered, and it has yet to refute it
+    Suspect,
+    Dead
+}
+
+/// What a node believes about one member. A member raises its incarnation
+/// to refute a suspicion of it, so the higher incarnation wins.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MemberStatus {
+    pub node_id: NodeId,
+    pub state: MemberState,
+    pub incarnation: u64
+}
+
+/// The state of a node that is serving clients.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NodeStatus {
+    pub node_id: NodeId,
+    /// The peers this node is currently connected to, sorted
+    pub peers: Vec<NodeId>,
+    /// The traffic on the link to each peer in `peers`, in the same order
+    pub links: Vec<LinkTraffic>,
+    /// The health of the link to each peer in `peers`, in the same order
+    pub health: Vec<PeerHealth>,
+    /// The transactions this node decided to commit that some participant
+    /// has not acknowledged applying yet, oldest first
+    pub unapplied: Vec<UnappliedCommit>
+}
+
+/// A commit decided by a coordinator that is not yet applied everywhere.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UnappliedCommit {
+    pub tx_id: TransactionId,
+    /// The participants that have not acknowledged applying it
+    pub waiting: Vec<NodeId>,
+    /// How long ago the commit was decided
+    pub since: Duration,
+    /// How many times the participants were told to commit again
+    pub retries: u32
+}
+
+/// Messages and bytes sent over one connection since it was opened. Bytes
+/// include the length prefix of every message.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LinkTraffic {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64
+}
+
+/// How well a node keeps up with sending to one peer.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PeerHealth {
+    /// The messages waiting to be sent to the peer
+    pub queued: usize,
+    /// A moving average of how long sending one message takes
+    pub send_latency: Duration,
+    /// From 100 for a link within its limits down towards 0 the further it
+    /// goes over them
+    pub score: u8,
+    /// Whether the link is over its limits, so that operations on the peer
+    /// are given less time
+    pub degraded: bool
+}
+
+/// The work a draining node has yet to finish.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DrainStatus {
+    /// Transactions coordinated by the node that have not finished
+    pub in_flight: usize,
+    /// Transactions the node voted to commit that were not decided yet
+    pub prepared: usize,
+    pub safe_to_stop: bool
+}
+
+/// The outcome of pausing or resuming the cluster, or one node of it.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PauseStatus {
+    pub paused: bool,
+    /// The nodes that did not quiesce in time, or could not be reached, if
+    /// the pause failed
+    pub busy: Vec<NodeId>
+}
+
+/// What the background checks of a shard's invariants have found.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct VerificationStatus {
+    /// The number of times every object on the shard was checked
+    pub passes: u64,
+    /// The number of violations found across all passes
+    pub violations_found: u64,
+    /// The most recent violations, oldest first
+    pub recent: Vec<Violation>
+}
+
+/// An invariant that an object on a shard did not hold when it was checked.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Violation {
+    pub account_id: AccountId,
+    pub description: String
+}
+
+/// How a shard resolves a conflict between an older transaction and newer
+/// ones that accessed the same account first.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ConcurrencyMode {
+    /// The older transaction aborts, since it arrived too late
+    #[default]
+    TimestampOrdering,
+    /// An older transaction writing an account that newer transactions read
+    /// wounds them, aborting them in its place unless they already prepared,
+    /// so accounts behave like locks held by the oldest transaction. Newer 
+    /// transactions still wait for older ones.
+    WoundWait
+}
+
+/// The operations a shard has handled since it started.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ShardCounts {
+    pub reads: u64,
+    pub writes: u64,
+    /// Commits the shard applied
+    pub commits: u64,
+    /// Aborts the shard applied, counting each time it was told to abort
+    pub aborts: u64
+}
+
+/// What a node's contention controller has measured and decided.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ContentionStatus {
+    /// The mode the shard is in
+    pub mode: ConcurrencyMode,
+    /// Whether an operator pinned the mode
+    pub pinned: bool,
+    /// The share of operations aborted by a conflict over the last window
+    pub abort_rate: f64,
+    /// The number of times the controller switched modes by itself
+    pub switches: u64
+}
+
+/// The commits a shard applied after some sequence number. Only the most 
+/// recent commits are kept, so a consumer that asked for commits before 
+/// `first_seq` has missed some.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CommitsSince {
+    /// The sequence number of the oldest commit the shard still has
+    pub first_seq: u64,
+    pub commits: Vec<CommitRecord>
+}
+
+/// A transaction's writes as applied by one shard.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CommitRecord {
+    /// The position of the commit in the order the shard applied commits
+    pub seq: u64,
+    pub tx_id: TransactionId,
+    /// The new balance of every account the commit changed
+    pub writes: Vec<(AccountId, Amount)>,
+    /// The accounts the commit removed once they expired
+    pub removed: Vec<AccountId>
+}
+
+/// The committed state of a shard between two commits, so every transaction's
+/// writes to the shard are either all in it or all missing from it. 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ShardSnapshot {
+    pub node_id: NodeId,
+    /// The sequence number of the last commit the snapshot includes, from 
+    /// which the commits after the snapshot can be followed. A snapshot read
+    /// as a transaction holds the last commit the shard applied once every
+    /// account was read, but not the commits of newer transactions
+    pub seq: u64,
+    /// Every account with a committed balance, sorted
+    pub accounts: Vec<AccountSnapshot>
+}
+
+/// A Merkle tree digest of a shard's committed balances. Two shards with the
+/// same root hold the same balances, and otherwise differ in the accounts
+/// that fall in the leaves that differ.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ShardDigest {
+    pub node_id: NodeId,
+    /// The sequence number of the last commit the digest includes
+    pub seq: u64,
+    /// The number of accounts with a committed balance
+    pub accounts: usize,
+    pub root: u64,
+    pub leaves: Vec<u64>
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountSnapshot {
+    pub account_id: AccountId,
+    pub balance: Amount,
+    /// The transaction that committed the balance
+    pub committed_by: TransactionId
+}
+
+/// What a node holds in memory, attributed to what holds it. Bytes are 
+/// estimated from the size of each entry and the heap it points to, such as
+/// an account's name, not counting the overhead of the collections that 
+/// hold them.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MemoryReport {
+    pub node_id: NodeId,
+    /// The clients connected to the node, each with its own transaction
+    pub sessions: usize,
+    /// The connections whose first message is still being read
+    pub greeting: usize,
+    /// The messages waiting in each of the node's queues, by queue
+    pub queues: Vec<(String, usize)>,
+    /// The accounts on the node's shard, and the bytes they hold in all
+    pub accounts: usize,
+    pub bytes: usize,
+    /// The transactions the shard tracks until they commit or abort on it,
+    /// and those that other operations may be waiting on
+    pub phases: usize,
+    pub notifications: usize,
+    /// The bytes held by the shard's log of recent commits, by the outcomes
+    /// of the transactions it remembers having finished, and by the node's
+    /// log of recent decisions
+    pub commit_log: usize,
+    pub finished: usize,
+    pub decisions: usize,
+    /// The transactions holding the most in tentative writes, most first
+    pub transactions: Vec<TransactionMemory>,
+    /// The accounts holding the most, most first
+    pub largest: Vec<AccountMemory>
+}
+
+/// The tentative writes and increments one transaction holds on a shard.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TransactionMemory {
+    pub tx_id: TransactionId,
+    pub writes: usize,
+    pub bytes: usize
+}
+
+/// What one account holds on a shard: its committed version, a version for 
+/// every pending write or increment, and the timestamp of every read it 
+/// must remember.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountMemory {
+    pub account_id: AccountId,
+    pub versions: usize,
+    pub reads: usize,
+    pub bytes: usize
+}
+
+/// A participant's vote in the first phase of a two-phase commit.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Vote {
+    ReadyToCommit,
+    CannotCommit
+}
+
+/// The final outcome of a two-phase commit.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Decision {
+    Commit,
+    Abort
+}
+
+/// Everything a coordinator knows about a two-phase commit it decided, or 
+/// about a transaction its client aborted giving a reason. A witness records
+/// the decisions it learns from coordinators, without the participants, 
+/// votes or prepare duration, which only the coordinator knows.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DecisionRecord {
+    pub tx_id: TransactionId,
+    /// Every shard asked to vote on the transaction, including the coordinator
+    pub participants: Vec<NodeId>,
+    /// The votes received before the decision was made, in arrival order
+    pub votes: Vec<(NodeId, Vote)>,
+    pub decision: Decision,
+    /// Time from the client's commit request to the decision
+    pub prepare_duration: Duration,
+    /// Why the client aborted the transaction, if it said
+    pub reason: Option<String>
+}
+
+impl ShardSnapshot {
+    fn format(&self) -> String {
+        let mut lines = vec![format!("SNAPSHOT {} seq={}", self.node_id, self.seq)];
+        lines.extend(self.accounts.iter().map(|a| format!("{} = {} ({})", a.account_id, a.balance, a.committed_by)));
+        lines.join("\n")
+    }
+}
+
+impl AdminResponse {
+    pub fn format(&self) -> String {
+        match self {
+            Self::DecisionLog(records) => records
+                .iter()
+                .map(|r| format!(
+                    "{} {:?} votes={:?} participants={:?} in {:?}{}",
+                    r.tx_id, r.decision, r.votes, r.participants, r.prepare_duration,
+                    r.reason.as_ref().map_or(String::new(), |reason| format!(" reason={reason:?}"))
+                ))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Status(status) => {
+                let mut lines = vec![format!("READY {} peers={:?}", status.node_id, status.peers)];
+                lines.extend(status.peers.iter().zip(status.links.iter()).zip(status.health.iter()).map(|((peer, link), health)| format!(
+                    "{peer} sent={}msg/{}B received={}msg/{}B queued={} latency={:?} score={}{}",
+                    link.messages_sent, link.bytes_sent, link.messages_received, link.bytes_received,
+                    health.queued, health.send_latency, health.score, if health.degraded { " DEGRADED" } else { "" }
+                )));
+                lines.extend(status.unapplied.iter().map(|commit| format!(
+                    "UNAPPLIED {} waiting={:?} since={:?} retries={}", commit.tx_id, commit.waiting, commit.since, commit.retries
+                )));
+                lines.join("\n")
+            },
+            Self::Commits(since) => since.commits
+                .iter()
+                .map(|c| format!("{} {} {:?}", c.seq, c.tx_id, c.writes))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Drain(status) if status.safe_to_stop => "SAFE TO STOP".into(),
+            Self::Drain(status) => format!("DRAINING in_flight={} prepared={}", status.in_flight, status.prepared),
+            Self::Pause(status) if status.paused => "PAUSED".into(),
+            Self::Pause(status) if status.busy.is_empty() => "RESUMED".into(),
+            Self::Pause(status) => format!("NOT PAUSED busy={:?}", status.busy),
+            Self::Verification(status) => {
+                let mut lines = vec![format!("VERIFIED passes={} violations={}", status.passes, status.violations_found)];
+                lines.extend(status.recent.iter().map(|v| format!("{}: {}", v.account_id, v.description)));
+                lines.join("\n")
+            },
+            Self::Snapshot(snapshot) => snapshot.format(),
+            Self::Snapshots(snapshots) => snapshots.iter().map(ShardSnapshot::format).collect::<Vec<_>>().join("\n"),
+            Self::Labels(labels) => labels
+                .iter()
+                .map(|l| format!(
+                    "{} active={}/{} committed={} aborted={} refused={} mean={:?} max={:?}",
+                    l.label, l.active, l.max_active.map_or("-".into(), |max| max.to_string()), 
+                    l.committed, l.aborted, l.refused, l.mean_latency, l.max_latency
+                ))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Tenants(tenants) => tenants
+                .iter()
+                .map(|t| format!(
+                    "{} active={}/{} rate={} requests={} committed={} aborted={} throttled={} denied={}",
+                    t.tenant, t.active, t.max_active.map_or("-".into(), |max| max.to_string()),
+                    t.requests_per_sec.map_or("-".into(), |rate| rate.to_string()),
+                    t.requests, t.committed, t.aborted, t.throttled, t.denied
+                ))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::AccountStats(accounts) => accounts
+                .iter()
+                .map(|a| format!(
+                    "{} reads={} writes={} aborts={} commits={} last_committer={}",
+                    a.account_id, a.reads, a.writes, a.aborts, a.commits,
+                    a.last_committer.map_or("-".into(), |tx_id| tx_id.to_string())
+                ))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Outcome(Some(decision)) => format!("{decision:?}"),
+            Self::Outcome(None) => "UNKNOWN".into(),
+            Self::Acls(acls) if acls.is_empty() => "NO ACL".into(),
+            Self::NoOwner(account_id) => format!("{account_id} HAS NO OWNER"),
+            Self::Acls(acls) => acls
+                .iter()
+                .map(|acl| {
+                    let grants: Vec<_> = acl.grants
+                        .iter()
+                        .map(|(tenant, access)| format!("{tenant}={}", match access {
+                            Access::Read => "r",
+                            Access::ReadWrite => "rw"
+                        }))
+                        .collect();
+                    format!("{} owner={} grants=[{}]", acl.account_id, acl.owner, grants.join(", "))
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Routes(routes) => routes
+                .iter()
+                .map(|(account_id, node_id)| format!("{account_id} -> {node_id}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Members(members) if members.is_empty() => "NO GOSSIP".into(),
+            Self::Members(members) => members
+                .iter()
+                .map(|m| format!("{} {:?} incarnation={}", m.node_id, m.state, m.incarnation))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Digests(digests) => digests
+                .iter()
+                .map(|d| format!("{} seq={} accounts={} root={:016x}", d.node_id, d.seq, d.accounts, d.root))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::QueryRows(rows) => rows
+                .iter()
+                .map(|(account_id, balance)| format!("{account_id} = {balance}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::QueryDone(summary) => {
+                let mut line = format!("COUNT {} SUM {}", summary.count, summary.sum);
+                if !summary.missing.is_empty() {
+                    line += &format!(" missing={:?}", summary.missing);
+                }
+                line
+            },
+            Self::Memory(report) => {
+                let mut lines = vec![format!(
+                    "MEMORY {} sessions={} greeting={} accounts={} bytes={} phases={} notifications={} commit_log={} finished={} decisions={}",
+                    report.node_id, report.sessions, report.greeting, report.accounts, report.bytes, report.phases, report.notifications,
+                    report.commit_log, report.finished, report.decisions
+                )];
+                lines.extend(report.queues.iter().map(|(queue, queued)| format!("QUEUE {queue} {queued}")));
+                lines.extend(report.transactions.iter().map(|t| format!("TX {} writes={} bytes={}", t.tx_id, t.writes, t.bytes)));
+                lines.extend(report.largest.iter().map(|a| format!(
+                    "ACCOUNT {} versions={} reads={} bytes={}", a.account_id, a.versions, a.reads, a.bytes
+                )));
+                lines.join("\n")
+            },
+            Self::QueryPart(part) => format!("PART {} seq={} count={} sum={}", part.node_id, part.seq, part.count, part.sum),
+            Self::Contention(status) => format!(
+                "MODE {:?}{} abort_rate={:.3} switches={}",
+                status.mode, if status.pinned { " (pinned)" } else { "" }, status.abort_rate, status.switches
+            )
+        }
+    }
+}
@@ -1,37 +1,136 @@
 use std::io::{BufRead, BufReader};
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
 
-pub type NodeId = char;
+/// Identifies a node in the cluster. Ids are assigned in the order nodes are
+/// listed in the config file, so every node reading the same config agrees 
+/// on them. Nodes are named in the config, and names may be any length.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialOrd, PartialEq, Serialize)]
+pub struct NodeId(pub u32);
+
+impl Display for NodeId {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(fmt, "#{}", self.0)
+    }
+}
+
+/// Listed in place of a node's shards to make it a witness.
+pub static WITNESS_MARKER: &str = "-";
 
 #[derive(Clone)]
 pub struct NodeConfiguration {
     pub node_id: NodeId,
+    pub name: String,
     pub hostname: String,
     pub port: u16,
-    pub connection_list: Vec<NodeId>
+    pub connection_list: Vec<NodeId>,
+    /// The names of the logical shards this node hosts
+    pub shards: Vec<String>
 }
 
 impl NodeConfiguration {
-    fn new(node_id: NodeId, hostname: String, port: u16, connection_list: Vec<NodeId>) -> Self {
+    fn new(node_id: NodeId, name: String, hostname: String, port: u16, connection_list: Vec<NodeId>, shards: Vec<String>) -> Self {
         Self {
             node_id,
+            name,
             hostname,
             port,
-            connection_list
+            connection_list,
+            shards
         }
     }
+
+    /// A witness hosts no shards, so it stores no data, but it still votes
+    /// on every transaction and learns every decision, making it a third 
+    /// party that two shards can rely on to break ties.
+    pub fn is_witness(&self) -> bool {
+        self.shards.is_empty()
+    }
 }
 
 pub type Config = HashMap<NodeId, NodeConfiguration>;
 
+/// Returns the id of the node with the given name, if it is in the config.
+pub fn node_named(config: &Config, name: &str) -> Option<NodeId> {
+    config.values().find(|node| node.name == name).map(|node| node.node_id)
+}
+
+/// The name of the logical shard that owns an account, as `ShardMap` routes
+/// it, or `None` for an empty account name.
+pub fn shard_name(account_id: &str) -> Option<&str> {
+    match account_id.split_once('.') {
+        Some((shard_name, _)) => Some(shard_name),
+        None => account_id.get(..account_id.chars().next()?.len_utf8())
+    }
+}
+
+/// Maps accounts to the nodes hosting the logical shards that own them. An 
+/// account named `<shard>.<account>` is owned by the shard with that name, so
+/// `A.foo` is owned by the shard named `A`. An account without a `.` is owned
+/// by the shard named after its first character, as when names were single 
+/// characters. Shard names are only aliases for the nodes hosting them:
+/// routing an account to another node does not move its data. A node keeps
+/// one store and takes part in two-phase commit once for all the shards it 
+/// hosts; keeping them apart, so that one can be moved, is deferred.
+#[derive(Clone, Debug, Default)]
+pub struct ShardMap {
+    names: HashMap<String, NodeId>
+}
+
+impl ShardMap {
+    pub fn new(config: &Config) -> Self {
+        let names = config
+            .values()
+            .flat_map(|node| node.shards.iter().map(|shard| (shard.clone(), node.node_id)))
+            .collect();
+
+        Self { names }
+    }
+
+    pub fn shard_for(&self, account_id: &str) -> Option<NodeId> {
+        self.names.get(shard_name(account_id)?).copied()
+    }
+
+    /// Whether any shard is hosted by the node, which only a witness's is not.
+    pub fn hosts_shard(&self, node_id: NodeId) -> bool {
+        self.names.values().any(|host| *host == node_id)
+    }
+
+    /// A fingerprint of the routing, so nodes can check that they route 
+    /// accounts the same way before they exchange requests. Nodes that were 
+    /// started with different configs have different epochs.
+    pub fn epoch(&self) -> u64 {
+        let mut routes: Vec<_> = self.names.iter().collect();
+        routes.sort();
+
+        // FNV-1a, which unlike std's hashers is stable across builds
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for (shard, node_id) in routes {
+            for byte in shard.bytes().chain([0]).chain(node_id.0.to_le_bytes()) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+
+        hash
+    }
+}
+
+/// Parses a config file with one line per node: 
+/// `<name> <hostname> <port> [shard...]`. A node hosts the logical shards 
+/// listed after its port, or the shard with its own name if none are listed.
+/// A node listed with `-` in place of its shards is a witness.
 pub fn parse_config(path: &str) -> Result<Config, String> {
+    match File::open(path) {
+        Ok(f) => parse_config_from(BufReader::new(f)),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+fn parse_config_from(mut rdr: impl BufRead) -> Result<Config, String> {
     let mut config: HashMap<NodeId, NodeConfiguration> = Config::new();
-    let mut rdr = match File::open(path) {
-        Ok(f) => BufReader::new(f),
-        Err(e) => return Err(e.to_string())
-    };
-    
     let mut buf = String::new();
     let mut nodes = Vec::new();
 
@@ -39,17 +138,27 @@ pub fn parse_config(path: &str) -> Result<Config, String> {
         if n == 0 { break }
 
         let delimited: Vec<_> = buf.split_ascii_whitespace().collect();
-        match delimited[0..3] {
-            [node_name, hostname, p] => match p.parse() {
+        match delimited[..] {
+            [node_name, hostname, p, ref shards @ ..] => match p.parse() {
                 Ok(port) => {
-                    let node_name = if node_name.len() == 1 {
-                        node_name.chars().nth(0).unwrap()
-                    } else {
-                        return Err("Node name must be a character".into());
+                    if node_named(&config, node_name).is_some() {
+                        return Err(format!("Bad config: node {node_name} is listed more than once"));
+                    }
+
+                    let shards: Vec<String> = match shards {
+                        [] => vec![node_name.into()],
+                        [marker] if *marker == WITNESS_MARKER => vec![],
+                        shards => shards.iter().map(|shard| shard.to_string()).collect()
                     };
 
-                    config.insert(node_name, NodeConfiguration::new(node_name, hostname.into(), port, nodes.clone()));
-                    nodes.push(node_name);
+                    let hosted = ShardMap::new(&config);
+                    if let Some(shard) = shards.iter().find(|shard| hosted.names.contains_key(*shard)) {
+                        return Err(format!("Bad config: shard {shard} is hosted by more than one node"));
+                    }
+
+                    let node_id = NodeId(nodes.len() as u32);
+                    config.insert(node_id, NodeConfiguration::new(node_id, node_name.into(), hostname.into(), port, nodes.clone(), shards));
+                    nodes.push(node_id);
                 },
                 Err(_) => return Err(format!("Bad config: could not parse port for node with id: {}", n))
             },
@@ -61,3 +170,72 @@ pub fn parse_config(path: &str) -> Result<Config, String> {
 
     Ok(config)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(names: &[&str]) -> Config {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let node_id = NodeId(i as u32);
+                (node_id, NodeConfiguration::new(node_id, name.to_string(), "localhost".into(), 0, vec![], vec![name.to_string()]))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shard_for_account() {
+        let config = config(&["A", "B", "shard-2"]);
+        let shards = ShardMap::new(&config);
+
+        assert_eq!(shards.shard_for("A.foo"), Some(NodeId(0)));
+        assert_eq!(shards.shard_for("B.bar"), Some(NodeId(1)));
+        assert_eq!(shards.shard_for("shard-2.baz"), Some(NodeId(2)));
+        assert_eq!(shards.shard_for("Bfoo"), Some(NodeId(1)));
+        assert_eq!(shards.shard_for("C.foo"), None);
+        assert_eq!(shards.shard_for("s.foo"), None);
+        assert_eq!(shards.shard_for(""), None);
+        assert_eq!(node_named(&config, "shard-2"), Some(NodeId(2)));
+    }
+
+    #[test]
+    fn test_parse_config_with_logical_shards() {
+        let config = parse_config_from("A localhost 10000\nB localhost 10001 B X\n".as_bytes()).unwrap();
+        let shards = ShardMap::new(&config);
+
+        assert_eq!(config[&NodeId(1)].connection_list, vec![NodeId(0)]);
+        assert_eq!(shards.shard_for("A.foo"), Some(NodeId(0)));
+        assert_eq!(shards.shard_for("B.foo"), Some(NodeId(1)));
+        assert_eq!(shards.shard_for("X.foo"), Some(NodeId(1)));
+    }
+
+    #[test]
+    fn test_parse_config_with_witness() {
+        let config = parse_config_from("A localhost 10000\nB localhost 10001\nW localhost 10002 -\n".as_bytes()).unwrap();
+        let shards = ShardMap::new(&config);
+
+        assert!(config[&NodeId(2)].is_witness());
+        assert!(!config[&NodeId(0)].is_witness());
+        assert_eq!(shards.shard_for("W.foo"), None);
+    }
+
+    #[test]
+    fn test_epoch_changes_with_routing() {
+        let config_a = parse_config_from("A localhost 10000\nB localhost 10001 B X\n".as_bytes()).unwrap();
+        let config_b = parse_config_from("A localhost 10000 A X\nB localhost 10001 B\n".as_bytes()).unwrap();
+        let config_c = parse_config_from("A otherhost 10002\nB otherhost 10003 B X\n".as_bytes()).unwrap();
+
+        let epoch = ShardMap::new(&config_a).epoch();
+        assert_ne!(epoch, ShardMap::new(&config_b).epoch());
+        assert_eq!(epoch, ShardMap::new(&config_c).epoch());
+    }
+
+    #[test]
+    fn test_parse_config_rejects_shared_shard() {
+        assert!(parse_config_from("A localhost 10000 X\nB localhost 10001 X\n".as_bytes()).is_err());
+        assert!(parse_config_from("A localhost\n".as_bytes()).is_err());
+    }
+}
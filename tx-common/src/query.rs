@@ -0,0 +1,281 @@
+use crate::{config::NodeId, AccountId, Amount};
+use serde::{Deserialize, Serialize};
+
+/// A read-only query over the committed balances of every account in the
+/// cluster, written as one of
+///
+/// ```text
+/// SELECT key, value [WHERE <condition> [AND <condition>]...]
+/// SELECT SUM(value) [WHERE ...]
+/// SELECT COUNT(*) [WHERE ...]
+/// ```
+///
+/// where a condition is `value <op> <amount>` with `<op>` one of `<`, `<=`,
+/// `=`, `!=`, `>=` or `>`, `key = '<account>'`, or `key LIKE '<prefix>%'`.
+/// Keywords are case-insensitive.
+///
+/// Each shard evaluates its part by reading every account as the 
+/// transaction running the query, so the parts together are a consistent
+/// cut: they hold every transfer of an older transaction on both sides, 
+/// waiting for it to commit if need be, and no transfer of a newer one.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Query {
+    pub select: Select,
+    /// Every condition an account must meet to be selected
+    pub filter: Vec<Condition>
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum Select {
+    /// Every selected account and its balance
+    Rows,
+    /// The sum of the selected balances
+    Sum,
+    /// The number of selected accounts
+    Count
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum Condition {
+    Value(Comparison, Amount),
+    Key(AccountId),
+    KeyPrefix(String)
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Ge,
+    Gt
+}
+
+/// The part of a query's result that one shard contributes, evaluated against
+/// the shard's balances as of the transaction running the query.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct QueryPart {
+    pub node_id: NodeId,
+    /// The sequence number of the last commit the shard applied once every
+    /// account was read
+    pub seq: u64,
+    /// The selected accounts sorted, if the query selects rows
+    pub rows: Vec<(AccountId, Amount)>,
+    pub count: u64,
+    pub sum: i128
+}
+
+/// What a query found once every shard answered.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct QuerySummary {
+    /// The number of selected accounts
+    pub count: u64,
+    /// The sum of the selected balances
+    pub sum: i128,
+    /// The sequence number of the last commit each shard applied once it 
+    /// read its part
+    pub snapshots: Vec<(NodeId, u64)>,
+    /// Shards that have not joined, whose accounts are missing from the result
+    pub missing: Vec<NodeId>
+}
+
+impl QuerySummary {
+    pub fn add(&mut self, part: &QueryPart) {
+        self.count += part.count;
+        self.sum += part.sum;
+        self.snapshots.push((part.node_id, part.seq));
+    }
+}
+
+impl Comparison {
+    fn holds(&self, left: Amount, right: Amount) -> bool {
+        match self {
+            Self::Lt => left < right,
+            Self::Le => left <= right,
+            Self::Eq => left == right,
+            Self::Ne => left != right,
+            Self::Ge => left >= right,
+            Self::Gt => left > right
+        }
+    }
+}
+
+impl Condition {
+    fn holds(&self, account_id: &str, balance: Amount) -> bool {
+        match self {
+            Self::Value(comparison, amount) => comparison.holds(balance, *amount),
+            Self::Key(key) => account_id == key,
+            Self::KeyPrefix(prefix) => account_id.starts_with(prefix.as_str())
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        let words: Vec<_> = tokens.iter().map(|token| token.to_ascii_uppercase()).collect();
+        let words: Vec<_> = words.iter().map(String::as_str).collect();
+
+        let (select, rest) = match words[..] {
+            ["SELECT", "KEY", ",", "VALUE", ..] => (Select::Rows, 4),
+            ["SELECT", "SUM", "(", "VALUE", ")", ..] => (Select::Sum, 5),
+            ["SELECT", "COUNT", "(", "*", ")", ..] => (Select::Count, 5),
+            _ => return Err("expected SELECT key, value or SELECT SUM(value) or SELECT COUNT(*)".into())
+        };
+
+        let mut filter = Vec::new();
+        let mut at = rest;
+        if at < tokens.len() {
+            if words[at] != "WHERE" {
+                return Err(format!("expected WHERE, found {}", tokens[at]));
+            }
+
+            loop {
+                let condition = match (words.get(at + 1), tokens.get(at + 2), tokens.get(at + 3)) {
+                    (Some(&"VALUE"), Some(op), Some(amount)) => {
+                        let amount = amount.parse().map_err(|_| format!("expected an amount, found {amount}"))?;
+                        Condition::Value(comparison(op)?, amount)
+                    },
+                    (Some(&"KEY"), Some(op), Some(key)) if op.eq_ignore_ascii_case("LIKE") => {
+                        match quoted(key)?.strip_suffix('%') {
+                            Some(prefix) if !prefix.contains('%') => Condition::KeyPrefix(prefix.into()),
+                            _ => return Err(format!("expected a pattern of the form 'prefix%', found {key}"))
+                        }
+                    },
+                    (Some(&"KEY"), Some(op), Some(key)) if op == "=" => Condition::Key(quoted(key)?.into()),
+                    _ => return Err("expected a condition on key or value".into())
+                };
+
+                filter.push(condition);
+                at += 4;
+                match words.get(at) {
+                    None => break,
+                    Some(&"AND") => continue,
+                    Some(_) => return Err(format!("expected AND, found {}", tokens[at]))
+                }
+            }
+        }
+
+        Ok(Self { select, filter })
+    }
+
+    pub fn matches(&self, account_id: &str, balance: Amount) -> bool {
+        self.filter.iter().all(|condition| condition.holds(account_id, balance))
+    }
+
+    /// Evaluates the query against one shard's balances, read once the commit
+    /// with sequence number `seq` was applied.
+    pub fn evaluate<'a>(&self, node_id: NodeId, seq: u64, balances: impl Iterator<Item = (&'a AccountId, Amount)>) -> QueryPart {
+        let mut part = QueryPart { node_id, seq, rows: Vec::new(), count: 0, sum: 0 };
+        for (account_id, balance) in balances.filter(|(account_id, balance)| self.matches(account_id, *balance)) {
+            part.count += 1;
+            part.sum += balance as i128;
+            if self.select == Select::Rows {
+                part.rows.push((account_id.clone(), balance));
+            }
+        }
+
+        part.rows.sort();
+        part
+    }
+}
+
+fn comparison(op: &str) -> Result<Comparison, String> {
+    match op {
+        "<" => Ok(Comparison::Lt),
+        "<=" => Ok(Comparison::Le),
+        "=" => Ok(Comparison::Eq),
+        "!=" | "<>" => Ok(Comparison::Ne),
+        ">=" => Ok(Comparison::Ge),
+        ">" => Ok(Comparison::Gt),
+        op => Err(format!("expected a comparison, found {op}"))
+    }
+}
+
+fn quoted(token: &str) -> Result<&str, String> {
+    token
+        .strip_prefix('\'')
+        .and_then(|token| token.strip_suffix('\''))
+        .ok_or_else(|| format!("expected a quoted string, found {token}"))
+}
+
+/// Splits a query into words, numbers, quoted strings (with their quotes),
+/// comparisons and punctuation.
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let end = match c {
+            c if c.is_whitespace() => continue,
+            '(' | ')' | ',' | '*' | '=' => start + 1,
+            '<' | '>' | '!' => match (c, chars.peek().map(|(_, next)| *next)) {
+                (_, Some('=')) | ('<', Some('>')) => {
+                    chars.next();
+                    start + 2
+                },
+                ('!', _) => return Err("expected != after !".into()),
+                _ => start + 1
+            },
+            '\'' => match chars.find(|(_, c)| *c == '\'') {
+                Some((end, _)) => end + 1,
+                None => return Err("unterminated string".into())
+            },
+            c if c.is_alphanumeric() || c == '-' || c == '.' || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((at, c)) = chars.peek().copied() {
+                    if !(c.is_alphanumeric() || c == '.' || c == '_') {
+                        break;
+                    }
+                    end = at + c.len_utf8();
+                    chars.next();
+                }
+                end
+            },
+            c => return Err(format!("unexpected character {c}"))
+        };
+
+        tokens.push(text[start..end].to_string());
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_queries() {
+        let query = Query::parse("select key, value where value >= -5 and key like 'A.%'").unwrap();
+        assert_eq!(query, Query {
+            select: Select::Rows,
+            filter: vec![Condition::Value(Comparison::Ge, -5), Condition::KeyPrefix("A.".into())]
+        });
+
+        assert_eq!(Query::parse("SELECT SUM(value)").unwrap(), Query { select: Select::Sum, filter: vec![] });
+        assert_eq!(
+            Query::parse("SELECT COUNT(*) WHERE value<>0 AND key = 'B.x'").unwrap().filter,
+            vec![Condition::Value(Comparison::Ne, 0), Condition::Key("B.x".into())]
+        );
+
+        assert!(Query::parse("SELECT value").is_err());
+        assert!(Query::parse("SELECT SUM(value) WHERE value >").is_err());
+        assert!(Query::parse("SELECT key, value WHERE key LIKE 'A.%x'").is_err());
+        assert!(Query::parse("SELECT key, value WHERE value > 1 OR value < 0").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_filters_and_aggregates() {
+        let balances = [("A.x".to_string(), 10), ("A.y".to_string(), -3), ("B.x".to_string(), 7)];
+        let balances = || balances.iter().map(|(account_id, balance)| (account_id, *balance));
+
+        let rows = Query::parse("SELECT key, value WHERE value > 0").unwrap().evaluate(NodeId(0), 4, balances());
+        assert_eq!(rows.rows, vec![("A.x".into(), 10), ("B.x".into(), 7)]);
+        assert_eq!((rows.count, rows.sum, rows.seq), (2, 17, 4));
+
+        let sum = Query::parse("SELECT SUM(value) WHERE key LIKE 'A.%'").unwrap().evaluate(NodeId(0), 4, balances());
+        assert!(sum.rows.is_empty());
+        assert_eq!((sum.count, sum.sum), (2, 7));
+    }
+}
@@ -0,0 +1,14 @@
+//! The seed the randomized tools draw from. The fuzz test, the soak and the
+//! chaos proxy each name the seed of every run, and all take it back from
+//! the `TX_REPLAY_SEED` environment variable to replay that run.
+
+/// The environment variable holding the seed to replay
+pub const REPLAY_SEED_VAR: &str = "TX_REPLAY_SEED";
+
+/// The seed to replay, if `TX_REPLAY_SEED` is set.
+pub fn replay_seed() -> Result<Option<u64>, String> {
+    match std::env::var(REPLAY_SEED_VAR) {
+        Ok(seed) => seed.parse().map(Some).map_err(|_| format!("Invalid {REPLAY_SEED_VAR} {seed}: expected a number")),
+        Err(_) => Ok(None)
+    }
+}
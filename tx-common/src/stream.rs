@@ -1,9 +1,125 @@
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::bytes::Bytes;
 use serde::{de::DeserializeOwned, Serialize};
 use futures::{SinkExt, StreamExt};
-use tokio::net::TcpStream;
+use tokio::{io::{AsyncRead, AsyncWrite}, net::TcpStream};
+use socket2::{SockRef, Socket, TcpKeepalive};
+use std::time::Duration;
+use crate::admin::LinkTraffic;
 
-pub(super) type FramedStream = Framed<TcpStream, LengthDelimitedCodec>;
+/// The size of the length prefix of every frame
+const FRAME_HEADER_BYTES: u64 = 4;
+
+/// How many bytes an in-memory connection buffers in each direction before
+/// the writer waits for the reader
+const IN_MEMORY_BUFFER_BYTES: usize = 64 * 1024;
+
+/// How long a connection may be idle before keepalive probes are sent, and
+/// how long sent data may go unacknowledged before the connection is dropped
+pub static TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+pub static TCP_USER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Options applied to every TCP connection between clients and nodes. With
+/// the platform defaults, a connection whose peer vanished without closing it
+/// can hang for many minutes, so keepalive and a user timeout are set to 
+/// notice it sooner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Send every message as soon as it is written instead of batching 
+    /// small writes (Nagle's algorithm)
+    pub nodelay: bool,
+    /// Send keepalive probes once the connection has been idle this long
+    pub keepalive: Option<Duration>,
+    /// Drop the connection once sent data went unacknowledged this long. 
+    /// Only Linux and Android support it, so it is ignored elsewhere.
+    pub user_timeout: Option<Duration>
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self { nodelay: true, keepalive: Some(TCP_KEEPALIVE), user_timeout: Some(TCP_USER_TIMEOUT) }
+    }
+}
+
+impl SocketOptions {
+    pub fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        self.apply_to(&SockRef::from(stream))
+    }
+
+    fn apply_to(&self, socket: &Socket) -> std::io::Result<()> {
+        socket.set_tcp_nodelay(self.nodelay)?;
+        socket.set_keepalive(self.keepalive.is_some())?;
+        if let Some(idle) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        socket.set_tcp_user_timeout(self.user_timeout)?;
+
+        Ok(())
+    }
+
+    /// The default options, overridden by the `TX_TCP_NODELAY` (`on` or 
+    /// `off`), `TX_TCP_KEEPALIVE` and `TX_TCP_USER_TIMEOUT` (seconds or `off`)
+    /// environment variables.
+    pub fn from_env() -> Result<Self, String> {
+        let mut options = Self::default();
+        if let Ok(nodelay) = std::env::var("TX_TCP_NODELAY") {
+            options.nodelay = match nodelay.as_str() {
+                "on" => true,
+                "off" => false,
+                _ => return Err(format!("Invalid TX_TCP_NODELAY {nodelay}: expected on or off"))
+            };
+        }
+
+        if let Ok(keepalive) = std::env::var("TX_TCP_KEEPALIVE") {
+            options.keepalive = parse_duration_or_off("TX_TCP_KEEPALIVE", &keepalive)?;
+        }
+
+        if let Ok(user_timeout) = std::env::var("TX_TCP_USER_TIMEOUT") {
+            options.user_timeout = parse_duration_or_off("TX_TCP_USER_TIMEOUT", &user_timeout)?;
+        }
+
+        Ok(options)
+    }
+}
+
+/// The TCP socket under a `MessageStream`, kept to change its options after
+/// the stream moved to the task that drives it.
+#[derive(Debug)]
+pub struct SocketHandle(Socket);
+
+impl SocketHandle {
+    pub fn apply(&self, options: &SocketOptions) -> std::io::Result<()> {
+        options.apply_to(&self.0)
+    }
+}
+
+fn parse_duration_or_off(name: &str, value: &str) -> Result<Option<Duration>, String> {
+    match value {
+        "off" => Ok(None),
+        secs => secs
+            .parse()
+            .map(|secs| Some(Duration::from_secs(secs)))
+            .map_err(|_| format!("Invalid {name} {value}: expected a number of seconds or off"))
+    }
+}
+
+pub type FramedStream = Framed<TcpStream, LengthDelimitedCodec>;
+
+/// Splits a connection into the length-delimited frames every message is 
+/// sent in, for tools that relay messages without decoding them.
+pub fn framed(stream: TcpStream) -> FramedStream {
+    LengthDelimitedCodec::builder()
+        .length_field_type::<u32>()
+        .new_framed(stream)
+}
+
+/// A connection messages can be framed on: a TCP connection or, for a node
+/// embedded in the process of its clients, an in-memory pipe.
+pub trait Link: AsyncRead + AsyncWrite + Send + Sync + Unpin + std::fmt::Debug + std::any::Any {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Sync + Unpin + std::fmt::Debug + 'static> Link for S {}
 
 #[derive(Debug)]
 pub enum StreamError {
@@ -23,28 +139,69 @@ impl From<Box<bincode::ErrorKind>> for StreamError {
     }
 }
 
+/// A message that was decoded as one of two types.
+#[derive(Debug)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B)
+}
+
 #[derive(Debug)]
 pub struct MessageStream {
-    stream: FramedStream
+    stream: Framed<Box<dyn Link>, LengthDelimitedCodec>,
+    traffic: LinkTraffic
 }
 
 impl MessageStream {
     pub fn from_tcp_stream(stream: TcpStream) -> Self {
+        Self::from_link(stream)
+    }
+
+    fn from_link(link: impl Link + 'static) -> Self {
         let stream = LengthDelimitedCodec::builder()
             .length_field_type::<u32>()
-            .new_framed(stream);
+            .new_framed(Box::new(link) as Box<dyn Link>);
+        Self { stream, traffic: LinkTraffic::default() }
+    }
 
-        Self { stream }
+    /// Two streams connected to each other in memory, framed like a TCP 
+    /// connection, so that a node can serve clients in its own process.
+    pub fn in_memory() -> (Self, Self) {
+        let (near, far) = tokio::io::duplex(IN_MEMORY_BUFFER_BYTES);
+        (Self::from_link(near), Self::from_link(far))
+    }
+
+    /// A handle on the TCP socket the stream runs over, or `None` for an
+    /// in-memory stream.
+    pub fn socket(&self) -> Option<std::io::Result<SocketHandle>> {
+        let link: &dyn std::any::Any = &**self.stream.get_ref();
+        link.downcast_ref::<TcpStream>().map(|stream| SockRef::from(stream).try_clone().map(SocketHandle))
+    }
+
+    /// The messages and bytes, including framing, sent and received so far.
+    pub fn traffic(&self) -> LinkTraffic {
+        self.traffic
     }
 
     pub async fn send<O>(&mut self, message: O) -> Result<(), StreamError> where O: Serialize {
         let bytes = bincode::serialize(&message)?;
-        Ok(self.stream.send(bytes.into()).await?)
+        let len = bytes.len() as u64;
+        self.stream.send(Bytes::from(bytes)).await?;
+
+        self.traffic.messages_sent += 1;
+        self.traffic.bytes_sent += len + FRAME_HEADER_BYTES;
+        Ok(())
+    }
+
+    fn count_received(&mut self, bytes: &[u8]) {
+        self.traffic.messages_received += 1;
+        self.traffic.bytes_received += bytes.len() as u64 + FRAME_HEADER_BYTES;
     }
 
     pub async fn recv<I>(&mut self) -> Option<Result<I, StreamError>> where I: DeserializeOwned {
         match self.stream.next().await {
             Some(Ok(bytes)) => {
+                self.count_received(&bytes);
                 match bincode::deserialize(&bytes) {
                     Ok(item) => Some(Ok(item)),
                     Err(e) => Some(Err(StreamError::BincodeError(e)))
@@ -54,4 +211,89 @@ impl MessageStream {
             None => None
         }
     }
-}
\ No newline at end of file
+
+    /// Receives a message that is either an `A` or a `B`, trying `A` first. 
+    /// Useful when the role of the remote end is only known from the first 
+    /// message it sends, so the two types must not decode from the same bytes.
+    pub async fn recv_either<A, B>(&mut self) -> Option<Result<Either<A, B>, StreamError>> 
+    where 
+        A: DeserializeOwned, 
+        B: DeserializeOwned
+    {
+        match self.stream.next().await {
+            Some(Ok(bytes)) => {
+                self.count_received(&bytes);
+                match bincode::deserialize(&bytes) {
+                    Ok(item) => Some(Ok(Either::Left(item))),
+                    Err(_) => match bincode::deserialize(&bytes) {
+                        Ok(item) => Some(Ok(Either::Right(item))),
+                        Err(e) => Some(Err(StreamError::BincodeError(e)))
+                    }
+                }
+            },
+            Some(Err(e)) => Some(Err(StreamError::RemoteIoError(e))),
+            None => None
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use tokio::net::TcpListener;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socket_options_applied() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let socket = SockRef::from(&stream);
+
+        let options = SocketOptions { nodelay: true, keepalive: Some(Duration::from_secs(7)), user_timeout: Some(Duration::from_secs(9)) };
+        options.apply(&stream).unwrap();
+        assert!(socket.tcp_nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            assert_eq!(socket.tcp_keepalive_time().unwrap(), Duration::from_secs(7));
+            assert_eq!(socket.tcp_user_timeout().unwrap(), Some(Duration::from_secs(9)));
+        }
+
+        let options = SocketOptions { nodelay: false, keepalive: None, user_timeout: None };
+        options.apply(&stream).unwrap();
+        assert!(!socket.tcp_nodelay().unwrap());
+        assert!(!socket.keepalive().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_socket_options_applied_through_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let socket = SockRef::from(&stream).try_clone().unwrap();
+        let stream = MessageStream::from_tcp_stream(stream);
+
+        let handle = stream.socket().unwrap().unwrap();
+        handle.apply(&SocketOptions { nodelay: false, keepalive: None, user_timeout: None }).unwrap();
+        assert!(!socket.tcp_nodelay().unwrap());
+        assert!(!socket.keepalive().unwrap());
+        handle.apply(&SocketOptions::default()).unwrap();
+        assert!(socket.tcp_nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+
+        assert!(MessageStream::in_memory().0.socket().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_streams_frame_messages() {
+        let (mut near, mut far) = MessageStream::in_memory();
+        near.send((1u32, "one".to_string())).await.unwrap();
+        assert_eq!(far.recv::<(u32, String)>().await.unwrap().unwrap(), (1, "one".to_string()));
+
+        // A message larger than the pipe's buffer waits for the reader
+        let (sent, received) = tokio::join!(near.send(vec![0u8; 100_000]), far.recv::<Vec<u8>>());
+        assert!(sent.is_ok());
+        assert_eq!(received.unwrap().unwrap().len(), 100_000);
+        assert_eq!(far.traffic().messages_received, 2);
+
+        drop(near);
+        assert!(far.recv::<u32>().await.is_none());
+    }
+}
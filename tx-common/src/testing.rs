@@ -0,0 +1,33 @@
+//! Helpers for the tests, examples and tools that run a cluster on 
+//! localhost.
+
+use crate::config::{Config, NodeConfiguration, NodeId};
+
+/// A port on localhost that nothing was listening on when it was picked.
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Builds a config for a cluster running on localhost with the given 
+/// nodes, each hosting the shard with its own name. Ids are assigned in 
+/// order, and every node dials the ones before it.
+pub fn local_config(names: &[&str]) -> Config {
+    let node_ids: Vec<_> = (0..names.len() as u32).map(NodeId).collect();
+    let mut config = Config::new();
+    for (i, node_id) in node_ids.iter().enumerate() {
+        config.insert(*node_id, NodeConfiguration { 
+            node_id: *node_id, 
+            name: names[i].into(),
+            hostname: "127.0.0.1".into(), 
+            port: free_port(), 
+            connection_list: node_ids[..i].to_vec(),
+            shards: vec![names[i].into()]
+        });
+    }
+
+    config
+}
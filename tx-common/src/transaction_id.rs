@@ -1,6 +1,6 @@
 use std::fmt::{Display, Formatter};
 use serde::{Deserialize, Serialize};
-use tx_common::config::NodeId;
+use crate::config::NodeId;
 use std::time::SystemTime;
 use std::hash::Hash;
 
@@ -24,6 +24,22 @@ impl ClockTransactionId {
     pub fn default(coordinator: NodeId) -> Self {
         Self { ts: 0, coordinator }
     }
+
+    /// The clock reading, in nanoseconds since the epoch, that orders this id
+    pub fn timestamp(&self) -> u128 {
+        self.ts
+    }
+
+    /// The node that issued this id
+    pub fn coordinator(&self) -> NodeId {
+        self.coordinator
+    }
+
+    /// An id with the given timestamp, for setting up state in tests. Ids in
+    /// a running system must come from a generator so that they are unique.
+    pub fn at(ts: u128, coordinator: NodeId) -> Self {
+        Self { ts, coordinator }
+    }
 }
 
 pub struct ClockTransactionIdGenerator {
@@ -37,6 +53,22 @@ impl ClockTransactionIdGenerator {
         Self { node_id, last_systime: 0, last_count: 0 }
     }
 
+    /// Creates a generator whose ids all have a timestamp greater than 
+    /// `after`, even if the system clock reads earlier.
+    pub fn resume(node_id: NodeId, after: u128) -> Self {
+        Self { node_id, last_systime: after, last_count: 0 }
+    }
+
+    /// Makes every id this generator issues from now on have a timestamp 
+    /// greater than `after`, such as the ids another node issued before 
+    /// this one took over its work.
+    pub fn advance_past(&mut self, after: u128) {
+        if after > self.last_systime + self.last_count as u128 {
+            self.last_systime = after;
+            self.last_count = 0;
+        }
+    }
+
     fn get_system_time() -> u128 {
         SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -44,6 +76,7 @@ impl ClockTransactionIdGenerator {
             .as_nanos()
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> ClockTransactionId {
         let mut ts = Self::get_system_time();
         if ts <= self.last_systime {
@@ -67,7 +100,7 @@ mod test {
 
     #[test]
     fn test_unique_id_generation() {
-        let mut id_gen = TransactionIdGenerator::new('A');
+        let mut id_gen = TransactionIdGenerator::new(NodeId(0));
 
         for _ in 0..100 {
             let id1 = id_gen.next();
@@ -83,8 +116,8 @@ mod test {
 
     #[test]
     fn test_unique_id_generation_different_nodes() {
-        let mut id_gen_a = TransactionIdGenerator::new('A');
-        let mut id_gen_b = TransactionIdGenerator::new('B');
+        let mut id_gen_a = TransactionIdGenerator::new(NodeId(0));
+        let mut id_gen_b = TransactionIdGenerator::new(NodeId(1));
 
         for _ in 0..100 {
             let id1 = id_gen_a.next();
@@ -98,4 +131,31 @@ mod test {
             assert!(id3 < id4);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_resumed_generator_ignores_earlier_clock() {
+        let after = ClockTransactionIdGenerator::get_system_time() + 1_000_000_000;
+        let mut id_gen = TransactionIdGenerator::resume(NodeId(0), after);
+
+        let id1 = id_gen.next();
+        let id2 = id_gen.next();
+        assert!(id1.timestamp() > after);
+        assert!(id1 < id2);
+    }
+
+    #[test]
+    fn test_advanced_generator_stays_unique() {
+        let mut id_gen = TransactionIdGenerator::new(NodeId(0));
+        let id1 = id_gen.next();
+
+        // Advancing to an earlier timestamp changes nothing
+        id_gen.advance_past(id1.timestamp() - 1);
+        let id2 = id_gen.next();
+        assert!(id1 < id2);
+
+        let after = id2.timestamp() + 1_000_000_000;
+        id_gen.advance_past(after);
+        let id3 = id_gen.next();
+        assert!(id3.timestamp() > after);
+    }
+}
@@ -0,0 +1,667 @@
+//! The messages clients and servers exchange, and those servers exchange
+//! with each other in `peer`. Every message is serialized with bincode's
+//! default options, and the tests pin the bytes of every variant, so a change
+//! to the wire format fails them rather than reaching deployed clients. A
+//! change that breaks the format must bump `PROTOCOL_VERSION` along with the
+//! pinned bytes.
+
+pub mod peer;
+pub mod topology;
+
+use tx_common::{admin::{AdminRequest, AdminResponse, Decision}, config, transaction_id::TransactionId, AccountId, Amount};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The version of the wire format, raised whenever a message serializes
+/// differently than before. New variants appended to the end of an enum keep
+/// the bytes of the others, so they only need a new version if older peers
+/// must refuse them.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// The longest reason, in bytes, a coordinator records for an abort. Longer
+/// reasons are cut short at a character boundary.
+pub const MAX_ABORT_REASON_BYTES: usize = 256;
+
+/// Cuts `reason` short to at most `MAX_ABORT_REASON_BYTES`, keeping whole 
+/// characters.
+pub fn cap_abort_reason(mut reason: String) -> String {
+    if reason.len() > MAX_ABORT_REASON_BYTES {
+        let end = (0..=MAX_ABORT_REASON_BYTES)
+            .rev()
+            .find(|end| reason.is_char_boundary(*end))
+            .unwrap_or(0);
+        reason.truncate(end);
+    }
+    reason
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct BalanceDiff(pub Amount);
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ClientRequest {
+    WriteBalance(AccountId, BalanceDiff),
+    /// Write a balance like `WriteBalance`. If the write creates the 
+    /// account, the account expires the given time after the transaction 
+    /// commits: reads find it missing and its shard removes it soon after.
+    /// The lifetime of an account that already exists is left as it is.
+    WriteBalanceWithTtl(AccountId, BalanceDiff, Duration),
+    ReadBalance(AccountId),
+    Commit,
+    Abort,
+    /// Exchange the balances of two accounts, which may be on different 
+    /// shards, as part of the transaction
+    Swap(AccountId, AccountId),
+    Admin(AdminRequest),
+    /// Attach a label, such as the name of an application or a class of 
+    /// workload, to the transaction. The coordinator aggregates the outcomes
+    /// of transactions by label and may limit how many with the same label 
+    /// run at once. A transaction keeps the first label it is given.
+    Label(String),
+    /// Authenticate as a tenant, given its name and secret. Once a node has
+    /// tenants, a transaction must authenticate before anything else, and 
+    /// may then only touch the tenant's own accounts, those named
+    /// `<shard>.<tenant>/<name>`.
+    Authenticate(String, String),
+    /// Ask for the transaction to be given the given time from when it began
+    /// to commit, rather than the coordinator's default. The coordinator
+    /// grants at most its own maximum and answers with `LifetimeGranted`, so
+    /// asking for `Duration::MAX` tells a client the most it may have. Once
+    /// the lifetime runs out, the transaction is aborted.
+    Lifetime(Duration),
+    /// Handle the wrapped request within the given time of receiving it. A 
+    /// read, write or swap still waiting when the time runs out is abandoned
+    /// and aborts the transaction. Commits and aborts run to completion.
+    Deadline(Duration, Box<ClientRequest>),
+    /// Choose what the response to the transaction's commit says. A 
+    /// transaction that asks for `CommitVerbosity::Values` is told the 
+    /// balance every account it wrote was left with, so that it need not read
+    /// them back in another transaction.
+    Verbosity(CommitVerbosity),
+    /// Add to a balance like `WriteBalance`, but without reading it, so that
+    /// transactions that only increment the same account never conflict with
+    /// each other. The increment aborts only if a newer transaction already 
+    /// read the account, and reads of the account wait for older increments
+    /// to finish. An increment that would leave the balance negative aborts
+    /// the transaction when it commits.
+    Increment(AccountId, BalanceDiff),
+    /// Read a balance that may be up to the given time stale, such as to show
+    /// on a dashboard. The read returns the last committed balance right 
+    /// away rather than waiting on transactions still writing the account, 
+    /// and never aborts them, but is not part of the transaction: it does not
+    /// see the transaction's own writes. If a write to the account has been
+    /// pending for longer than the given time, by the clock of the account's
+    /// shard, the transaction is aborted with `AbortedTooStale`.
+    ReadBalanceStale(AccountId, Duration),
+    /// Describe the cluster: its nodes, the shards each hosts, and the 
+    /// features the coordinator supports. The request is answered by the 
+    /// coordinator alone and is not part of the transaction.
+    ClusterInfo,
+    /// Settle how the coordinator handles the rest of the connection's 
+    /// requests, before the transaction's first read or write. The 
+    /// coordinator answers with the settings it adopted, or refuses them all
+    /// with `SettingsRefused` if it cannot honour one of them.
+    Hello(SessionSettings),
+    /// Read a balance like `ReadBalance`, but answered with `NotFound` 
+    /// rather than an abort if the account does not exist, so that the 
+    /// transaction can go on to create it. An older transaction can no 
+    /// longer create the account once it was found missing.
+    ReadBalanceIfExists(AccountId),
+    /// Assert that a balance satisfies a predicate, such as holding enough 
+    /// to withdraw from, without the client holding on to the balance. The
+    /// balance is read like `ReadBalance`, so no older transaction can change
+    /// it afterwards, and the transaction is aborted with 
+    /// `AbortedAssertionFailed` if the predicate does not hold now or, with
+    /// the transaction's own writes, when it commits.
+    AssertBalance(AccountId, BalancePredicate),
+    /// Abort like `Abort`, giving the reason, such as that the user cancelled
+    /// or a check failed. The coordinator records the reason, cut short to
+    /// `MAX_ABORT_REASON_BYTES`, with the abort in its decision log and 
+    /// passes it to its commit reporter.
+    AbortWithReason(String)
+}
+
+/// A comparison of a balance with a fixed amount, asserted with 
+/// `ClientRequest::AssertBalance`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum BalancePredicate {
+    AtLeast(Amount),
+    MoreThan(Amount),
+    AtMost(Amount),
+    LessThan(Amount),
+    Exactly(Amount)
+}
+
+impl BalancePredicate {
+    /// The predicate comparing a balance with `amount` by `comparison`, one
+    /// of `>=`, `>`, `<=`, `<` or `==`.
+    pub fn parse(comparison: &str, amount: Amount) -> Option<Self> {
+        match comparison {
+            ">=" => Some(Self::AtLeast(amount)),
+            ">" => Some(Self::MoreThan(amount)),
+            "<=" => Some(Self::AtMost(amount)),
+            "<" => Some(Self::LessThan(amount)),
+            "==" => Some(Self::Exactly(amount)),
+            _ => None
+        }
+    }
+
+    pub fn holds(&self, balance: Amount) -> bool {
+        match *self {
+            Self::AtLeast(amount) => balance >= amount,
+            Self::MoreThan(amount) => balance > amount,
+            Self::AtMost(amount) => balance <= amount,
+            Self::LessThan(amount) => balance < amount,
+            Self::Exactly(amount) => balance == amount
+        }
+    }
+
+    /// The predicate written as a comparison, such as `>= 100`.
+    pub fn format(&self) -> String {
+        match self {
+            Self::AtLeast(amount) => format!(">= {amount}"),
+            Self::MoreThan(amount) => format!("> {amount}"),
+            Self::AtMost(amount) => format!("<= {amount}"),
+            Self::LessThan(amount) => format!("< {amount}"),
+            Self::Exactly(amount) => format!("== {amount}")
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum CommitVerbosity {
+    /// Answer a commit with `CommitOk`
+    #[default]
+    Quiet,
+    /// Answer a commit with `CommitOkWithValues`
+    Values
+}
+
+/// How a client wants the requests of its connection handled, settled once
+/// with `ClientRequest::Hello` rather than repeated on every request. A 
+/// connection carries a single transaction and is closed once it commits or
+/// aborts, so the settings last for that transaction only, and a client must
+/// send them again at the start of each one.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SessionSettings {
+    pub isolation: IsolationLevel,
+    /// The deadline of every request sent without one of its own
+    pub default_deadline: Option<Duration>,
+    /// What the response to the transaction's commit says, until a
+    /// `Verbosity` request changes it
+    pub verbosity: CommitVerbosity,
+    /// How every message after the coordinator's answer is encoded
+    pub codec: Codec
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum IsolationLevel {
+    /// Reads wait on older transactions still writing an account, and the
+    /// transaction aborts rather than commit out of timestamp order
+    #[default]
+    Serializable,
+    /// Every `ReadBalance` is read like a `ReadBalanceStale` allowing the
+    /// given staleness, so reads never wait but may not see the latest 
+    /// commits or the transaction's own writes
+    BoundedStaleness(Duration)
+}
+
+/// How messages are encoded. Bincode is the only codec so far, so settling
+/// one chooses nothing yet; it is there so that clients and coordinators can
+/// agree on another once there is one.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Codec {
+    /// bincode with its default options
+    #[default]
+    Bincode
+}
+
+impl ClientRequest {
+    /// Attaches a deadline to the request, `budget` from when it is received.
+    pub fn with_deadline(self, budget: Duration) -> Self {
+        Self::Deadline(budget, Box::new(self))
+    }
+
+    /// Splits the deadline attached to the request, if any, from the request
+    /// itself. Of several nested deadlines, the earliest applies.
+    pub fn split_deadline(self) -> (Option<Duration>, Self) {
+        match self {
+            Self::Deadline(budget, request) => match request.split_deadline() {
+                (Some(inner), request) => (Some(budget.min(inner)), request),
+                (None, request) => (Some(budget), request)
+            },
+            request => (None, request)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ClientResponse {
+    Ok,
+    CommitOk,
+    /// The transaction committed, leaving the accounts it wrote with the 
+    /// given balances, sorted by account
+    CommitOkWithValues(Vec<(AccountId, Amount)>),
+    Aborted,
+    AbortedNotFound,
+    /// The transaction was aborted at commit time since it would have left 
+    /// the given account with a negative balance
+    AbortedNegativeBalance(AccountId),
+    /// The transaction was aborted since accessing the given account violated
+    /// timestamp ordering with the given newer transaction
+    AbortedConflict(AccountId, TransactionId),
+    /// The transaction was aborted since it accessed an account on a shard 
+    /// the coordinator is not connected to
+    AbortedUnavailable(config::NodeId),
+    /// The transaction was aborted since it requested more operations or 
+    /// wrote more data than a single transaction may
+    AbortedQuotaExceeded,
+    /// The transaction was aborted since a node it needed is draining before
+    /// maintenance
+    AbortedDraining,
+    /// The transaction was aborted since a request was not handled before
+    /// its deadline
+    AbortedDeadlineExceeded,
+    /// The transaction was aborted since as many transactions with the given
+    /// label as the coordinator allows at once were already running
+    AbortedLabelQuota(String),
+    /// The transaction was aborted since it did not authenticate, gave the
+    /// wrong credentials, or asked for something its tenant may not access
+    AbortedUnauthorized,
+    /// The transaction was aborted since the given tenant exceeded its 
+    /// request rate or was already running as many transactions as it may
+    AbortedTenantLimit(String),
+    /// The transaction was aborted since it read or swapped balances, or
+    /// wrote one with a TTL, on a cluster that runs transactions 
+    /// deterministically, which only takes plain balance changes
+    AbortedNotDeterministic,
+    /// The transaction was aborted since it did not commit within its 
+    /// lifetime
+    AbortedLifetimeExpired,
+    /// The transaction may run for the given time from when it began, the
+    /// most of what it asked for that the coordinator allows
+    LifetimeGranted(Duration),
+    Value(AccountId, Amount),
+    /// The request was rejected since the transaction was already committed 
+    /// or aborted
+    AlreadyFinished(Decision),
+    Admin(AdminResponse),
+    /// The transaction was aborted since the committed balance of the given 
+    /// account was staler than a read of it allowed
+    AbortedTooStale(AccountId),
+    ClusterInfo(Box<topology::ClusterInfo>),
+    /// The session settings the coordinator adopted
+    SessionSettings(SessionSettings),
+    /// The coordinator cannot honour the session settings, for the given 
+    /// reason, and kept its own. The transaction carries on.
+    SettingsRefused(String),
+    /// The account read with `ReadBalanceIfExists` does not exist. The 
+    /// transaction carries on.
+    NotFound(AccountId),
+    /// The transaction was aborted since the balance of the given account 
+    /// did not satisfy the predicate it asserted, when asserted or at commit
+    AbortedAssertionFailed(AccountId, BalancePredicate)
+}
+
+impl ClientResponse {
+    pub fn is_err(&self) -> bool {
+        matches!(
+            self, 
+            Self::Aborted | Self::AbortedNotFound | Self::AbortedNegativeBalance(_) | Self::AbortedConflict(..) | Self::AbortedUnavailable(_) | Self::AbortedQuotaExceeded | Self::AbortedDraining | Self::AbortedDeadlineExceeded
+                | Self::AbortedLabelQuota(_) | Self::AbortedUnauthorized | Self::AbortedTenantLimit(_)
+                | Self::AbortedNotDeterministic | Self::AbortedLifetimeExpired | Self::AbortedTooStale(_) | Self::AbortedAssertionFailed(..)
+        )
+    }
+
+    pub fn is_ok(&self) -> bool {
+        !self.is_err() && !matches!(self, Self::AlreadyFinished(_))
+    }
+
+    pub fn is_committed(&self) -> bool {
+        matches!(self, Self::CommitOk | Self::CommitOkWithValues(_))
+    }
+
+    pub fn is_final(&self) -> bool {
+        self.is_committed() || matches!(self, Self::AlreadyFinished(_)) || self.is_err()
+    }
+
+    pub fn format(&self) -> String {
+        match self {
+            Self::Ok => "OK".into(),
+            Self::Value(account_id, balance) => format!("{account_id} = {balance}"),
+            Self::CommitOk => "COMMIT OK".into(),
+            Self::CommitOkWithValues(values) => values
+                .iter()
+                .fold("COMMIT OK".to_string(), |output, (account_id, balance)| format!("{output}\n{account_id} = {balance}")),
+            Self::Aborted => "ABORTED".into(),
+            Self::AbortedNotFound => "NOT FOUND, ABORTED".into(),
+            Self::AbortedNegativeBalance(_) 
+            | Self::AbortedConflict(..) 
+            | Self::AbortedUnavailable(_) 
+            | Self::AbortedQuotaExceeded
+            | Self::AbortedDraining
+            | Self::AbortedDeadlineExceeded
+            | Self::AbortedLabelQuota(_)
+            | Self::AbortedTenantLimit(_)
+            | Self::AbortedNotDeterministic => "ABORTED".into(),
+            Self::AbortedTooStale(_) => "TOO STALE, ABORTED".into(),
+            Self::AbortedLifetimeExpired => "LIFETIME EXPIRED, ABORTED".into(),
+            Self::LifetimeGranted(lifetime) => format!("LIFETIME {lifetime:?}"),
+            Self::AbortedUnauthorized => "UNAUTHORIZED, ABORTED".into(),
+            Self::AlreadyFinished(Decision::Commit) => "TRANSACTION ALREADY COMMITTED".into(),
+            Self::AlreadyFinished(Decision::Abort) => "TRANSACTION ALREADY ABORTED".into(),
+            Self::Admin(resp) => resp.format(),
+            Self::ClusterInfo(info) => info.format(),
+            Self::SessionSettings(settings) => format!("SETTINGS {settings:?}"),
+            Self::SettingsRefused(reason) => format!("SETTINGS REFUSED: {reason}"),
+            Self::NotFound(account_id) => format!("{account_id} NOT FOUND"),
+            Self::AbortedAssertionFailed(account_id, predicate) => format!("ASSERTION {account_id} {} FAILED, ABORTED", predicate.format())
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use tx_common::{admin::*, query::{Comparison, Condition, Query, QueryPart, QuerySummary, Select}};
+    use serde::de::DeserializeOwned;
+    use std::fmt;
+    use super::*; 
+
+    /// Checks that a message serializes to the pinned bytes, given in hex,
+    /// and that the bytes deserialize back to the same message.
+    pub(crate) fn assert_wire<T: Serialize + DeserializeOwned + fmt::Debug>(message: &T, hex: &str) {
+        let bytes = bincode::serialize(message).unwrap();
+        let encoded: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+        assert_eq!(encoded, hex, "the wire format of {message:?} changed");
+
+        let decoded: T = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(bincode::serialize(&decoded).unwrap(), bytes, "{message:?} does not round-trip");
+    }
+
+    #[test]
+    fn test_client_request_wire_format() {
+        let requests = [
+            (ClientRequest::WriteBalance("A.x".into(), BalanceDiff(5)), "000000000300000000000000412e780500000000000000"),
+            (ClientRequest::WriteBalanceWithTtl("A.x".into(), BalanceDiff(-5), Duration::from_secs(2)), "010000000300000000000000412e78fbffffffffffffff020000000000000000000000"),
+            (ClientRequest::ReadBalance("A.x".into()), "020000000300000000000000412e78"),
+            (ClientRequest::Commit, "03000000"),
+            (ClientRequest::Abort, "04000000"),
+            (ClientRequest::Swap("A.x".into(), "B.y".into()), "050000000300000000000000412e780300000000000000422e79"),
+            (ClientRequest::Admin(AdminRequest::Status), "0600000001000000"),
+            (ClientRequest::Admin(AdminRequest::Decision(TransactionId::at(7, config::NodeId(1)))), "06000000170000000700000000000000000000000000000001000000"),
+            (ClientRequest::Label("batch".into()), "0700000005000000000000006261746368"),
+            (ClientRequest::Authenticate("acme".into(), "secret".into()), "08000000040000000000000061636d650600000000000000736563726574"),
+            (ClientRequest::Lifetime(Duration::from_millis(1500)), "0900000001000000000000000065cd1d"),
+            (ClientRequest::Commit.with_deadline(Duration::from_millis(100)), "0a000000000000000000000000e1f50503000000"),
+            (ClientRequest::Verbosity(CommitVerbosity::Values), "0b00000001000000"),
+            (ClientRequest::Increment("A.x".into(), BalanceDiff(5)), "0c0000000300000000000000412e780500000000000000"),
+            (ClientRequest::ReadBalanceStale("A.x".into(), Duration::from_secs(2)), "0d0000000300000000000000412e78020000000000000000000000"),
+            (ClientRequest::ClusterInfo, "0e000000"),
+            (ClientRequest::Hello(SessionSettings {
+                isolation: IsolationLevel::BoundedStaleness(Duration::from_secs(1)),
+                default_deadline: Some(Duration::from_secs(2)),
+                verbosity: CommitVerbosity::Values,
+                codec: Codec::Bincode
+            }), "0f00000001000000010000000000000000000000010200000000000000000000000100000000000000"),
+            (ClientRequest::ReadBalanceIfExists("A.x".into()), "100000000300000000000000412e78"),
+            (ClientRequest::AssertBalance("A.x".into(), BalancePredicate::AtLeast(100)), "110000000300000000000000412e78000000006400000000000000"),
+            (ClientRequest::AbortWithReason("no".into()), "1200000002000000000000006e6f")
+        ];
+        for (request, hex) in &requests {
+            assert_wire(request, hex);
+        }
+    }
+
+    #[test]
+    fn test_client_response_wire_format() {
+        let responses = [
+            (ClientResponse::Ok, "00000000"),
+            (ClientResponse::CommitOk, "01000000"),
+            (ClientResponse::CommitOkWithValues(vec![("A.x".into(), 5)]), "0200000001000000000000000300000000000000412e780500000000000000"),
+            (ClientResponse::Aborted, "03000000"),
+            (ClientResponse::AbortedNotFound, "04000000"),
+            (ClientResponse::AbortedNegativeBalance("A.x".into()), "050000000300000000000000412e78"),
+            (ClientResponse::AbortedConflict("A.x".into(), TransactionId::at(7, config::NodeId(1))), "060000000300000000000000412e780700000000000000000000000000000001000000"),
+            (ClientResponse::AbortedUnavailable(config::NodeId(2)), "0700000002000000"),
+            (ClientResponse::AbortedQuotaExceeded, "08000000"),
+            (ClientResponse::AbortedDraining, "09000000"),
+            (ClientResponse::AbortedDeadlineExceeded, "0a000000"),
+            (ClientResponse::AbortedLabelQuota("batch".into()), "0b00000005000000000000006261746368"),
+            (ClientResponse::AbortedUnauthorized, "0c000000"),
+            (ClientResponse::AbortedTenantLimit("acme".into()), "0d000000040000000000000061636d65"),
+            (ClientResponse::AbortedNotDeterministic, "0e000000"),
+            (ClientResponse::AbortedLifetimeExpired, "0f000000"),
+            (ClientResponse::LifetimeGranted(Duration::from_secs(1)), "10000000010000000000000000000000"),
+            (ClientResponse::Value("A.x".into(), -3), "110000000300000000000000412e78fdffffffffffffff"),
+            (ClientResponse::AlreadyFinished(Decision::Commit), "1200000000000000"),
+            (ClientResponse::Admin(AdminResponse::Outcome(None)), "130000000a00000000"),
+            (ClientResponse::Admin(AdminResponse::DecisionLog(vec![DecisionRecord {
+                tx_id: TransactionId::at(7, config::NodeId(1)),
+                participants: vec![config::NodeId(1), config::NodeId(2)],
+                votes: vec![(config::NodeId(2), Vote::CannotCommit)],
+                decision: Decision::Abort,
+                prepare_duration: Duration::from_millis(3),
+                reason: Some("no".into())
+            }])), "1300000000000000010000000000000007000000000000000000000000000000010000000200000000000000010000000200000001000000000000000200000001000000010000000000000000000000c0c62d000102000000000000006e6f"),
+            (ClientResponse::AbortedTooStale("A.x".into()), "140000000300000000000000412e78"),
+            (ClientResponse::ClusterInfo(Box::new(topology::ClusterInfo {
+                node_id: config::NodeId(1),
+                protocol_version: 1,
+                epoch: 2,
+                nodes: vec![topology::NodeInfo {
+                    node_id: config::NodeId(1),
+                    name: "B".into(),
+                    hostname: "h".into(),
+                    port: 80,
+                    role: topology::NodeRole::Witness,
+                    shards: vec![],
+                    joined: true
+                }],
+                capabilities: vec!["reads".into()]
+            })), "1500000001000000010000000200000000000000010000000000000001000000010000000000000042010000000000000068500001000000000000000000000001010000000000000005000000000000007265616473"),
+            (ClientResponse::SessionSettings(SessionSettings::default()), "1600000000000000000000000000000000"),
+            (ClientResponse::SettingsRefused("no".into()), "1700000002000000000000006e6f"),
+            (ClientResponse::NotFound("A.x".into()), "180000000300000000000000412e78"),
+            (ClientResponse::AbortedAssertionFailed("A.x".into(), BalancePredicate::LessThan(-5)), "190000000300000000000000412e7803000000fbffffffffffffff")
+        ];
+        for (response, hex) in &responses {
+            assert_wire(response, hex);
+        }
+    }
+
+    #[test]
+    fn test_admin_wire_format() {
+        let tx_id = TransactionId::at(7, config::NodeId(1));
+        let requests = [
+            (AdminRequest::DecisionLog, "00000000"),
+            (AdminRequest::Status, "01000000"),
+            (AdminRequest::CommitsSince(3), "020000000300000000000000"),
+            (AdminRequest::Drain, "03000000"),
+            (AdminRequest::Verification, "04000000"),
+            (AdminRequest::Contention, "05000000"),
+            (AdminRequest::PinConcurrencyMode(Some(ConcurrencyMode::WoundWait)), "060000000101000000"),
+            (AdminRequest::Snapshot, "07000000"),
+            (AdminRequest::Labels, "08000000"),
+            (AdminRequest::Tenants, "09000000"),
+            (AdminRequest::AccountStats(10), "0a0000000a00000000000000"),
+            (AdminRequest::Outcome(tx_id), "0b0000000700000000000000000000000000000001000000"),
+            (AdminRequest::Acls, "0c000000"),
+            (AdminRequest::SetOwner("A.x".into(), "acme".into()), "0d0000000300000000000000412e78040000000000000061636d65"),
+            (AdminRequest::Grant("A.x".into(), "acme".into(), Some(Access::Read)), "0e0000000300000000000000412e78040000000000000061636d650100000000"),
+            (AdminRequest::RemoveAcl("A.x".into()), "0f0000000300000000000000412e78"),
+            (AdminRequest::Routes, "10000000"),
+            (AdminRequest::Members, "11000000"),
+            (AdminRequest::Digest, "12000000"),
+            (AdminRequest::Query(Query { select: Select::Sum, filter: vec![Condition::Value(Comparison::Ge, 5), Condition::KeyPrefix("A.".into())] }), "1300000001000000020000000000000000000000040000000500000000000000020000000200000000000000412e"),
+            (AdminRequest::Pause(Duration::from_secs(10)), "140000000a0000000000000000000000"),
+            (AdminRequest::Resume, "15000000"),
+            (AdminRequest::Memory(10), "160000000a00000000000000"),
+            (AdminRequest::Decision(tx_id), "170000000700000000000000000000000000000001000000"),
+            (AdminRequest::ClusterSnapshot, "18000000")
+        ];
+        for (request, hex) in &requests {
+            assert_wire(request, hex);
+        }
+
+        let responses = [
+            (AdminResponse::DecisionLog(vec![DecisionRecord {
+                tx_id,
+                participants: vec![config::NodeId(1)],
+                votes: vec![(config::NodeId(1), Vote::ReadyToCommit)],
+                decision: Decision::Commit,
+                prepare_duration: Duration::from_millis(3),
+                reason: None
+            }]), "000000000100000000000000070000000000000000000000000000000100000001000000000000000100000001000000000000000100000000000000000000000000000000000000c0c62d0000"),
+            (AdminResponse::Status(Box::new(NodeStatus {
+                node_id: config::NodeId(1),
+                peers: vec![config::NodeId(2)],
+                links: vec![LinkTraffic { messages_sent: 1, bytes_sent: 2, messages_received: 3, bytes_received: 4 }],
+                health: vec![PeerHealth { queued: 5, send_latency: Duration::from_micros(6), score: 100, degraded: false }],
+                unapplied: vec![UnappliedCommit { tx_id, waiting: vec![config::NodeId(2)], since: Duration::from_millis(7), retries: 1 }]
+            })), "010000000100000001000000000000000200000001000000000000000100000000000000020000000000000003000000000000000400000000000000010000000000000005000000000000000000000000000000701700006400010000000000000007000000000000000000000000000000010000000100000000000000020000000000000000000000c0cf6a0001000000"),
+            (AdminResponse::Commits(CommitsSince { first_seq: 1, commits: vec![CommitRecord { seq: 2, tx_id, writes: vec![("A.x".into(), 5)], removed: vec!["A.y".into()] }] }), "02000000010000000000000001000000000000000200000000000000070000000000000000000000000000000100000001000000000000000300000000000000412e78050000000000000001000000000000000300000000000000412e79"),
+            (AdminResponse::Drain(DrainStatus { in_flight: 1, prepared: 2, safe_to_stop: false }), "030000000100000000000000020000000000000000"),
+            (AdminResponse::Verification(VerificationStatus {
+                passes: 1,
+                violations_found: 1,
+                recent: vec![Violation { account_id: "A.x".into(), description: "no".into() }]
+            }), "040000000100000000000000010000000000000001000000000000000300000000000000412e7802000000000000006e6f"),
+            (AdminResponse::Contention(ContentionStatus { mode: ConcurrencyMode::WoundWait, pinned: true, abort_rate: 0.5, switches: 2 }), "050000000100000001000000000000e03f0200000000000000"),
+            (AdminResponse::Snapshot(ShardSnapshot {
+                node_id: config::NodeId(1),
+                seq: 2,
+                accounts: vec![AccountSnapshot { account_id: "A.x".into(), balance: 5, committed_by: tx_id }]
+            }), "0600000001000000020000000000000001000000000000000300000000000000412e7805000000000000000700000000000000000000000000000001000000"),
+            (AdminResponse::Labels(vec![LabelStats {
+                label: "batch".into(),
+                active: 1,
+                max_active: Some(2),
+                committed: 3,
+                aborted: 4,
+                refused: 5,
+                mean_latency: Duration::from_millis(6),
+                max_latency: Duration::from_millis(7)
+            }]), "0700000001000000000000000500000000000000626174636801000000000000000102000000000000000300000000000000040000000000000005000000000000000000000000000000808d5b000000000000000000c0cf6a00"),
+            (AdminResponse::Tenants(vec![TenantStats {
+                tenant: "acme".into(),
+                active: 1,
+                max_active: None,
+                requests_per_sec: Some(2),
+                requests: 3,
+                committed: 4,
+                aborted: 5,
+                throttled: 6,
+                denied: 7
+            }]), "080000000100000000000000040000000000000061636d65010000000000000000010200000003000000000000000400000000000000050000000000000006000000000000000700000000000000"),
+            (AdminResponse::AccountStats(vec![AccountStats {
+                account_id: "A.x".into(),
+                reads: 1,
+                writes: 2,
+                aborts: 3,
+                commits: 4,
+                last_committer: Some(tx_id)
+            }]), "0900000001000000000000000300000000000000412e780100000000000000020000000000000003000000000000000400000000000000010700000000000000000000000000000001000000"),
+            (AdminResponse::Outcome(Some(Decision::Abort)), "0a0000000101000000"),
+            (AdminResponse::Acls(vec![AccountAcl { account_id: "A.x".into(), owner: "acme".into(), grants: vec![("beta".into(), Access::ReadWrite)] }]), "0b00000001000000000000000300000000000000412e78040000000000000061636d65010000000000000004000000000000006265746101000000"),
+            (AdminResponse::Routes(vec![("A.x".into(), config::NodeId(1))]), "0c00000001000000000000000300000000000000412e7801000000"),
+            (AdminResponse::Members(vec![MemberStatus { node_id: config::NodeId(1), state: MemberState::Suspect, incarnation: 2 }]), "0d000000010000000000000001000000010000000200000000000000"),
+            (AdminResponse::Digests(vec![ShardDigest { node_id: config::NodeId(1), seq: 2, accounts: 3, root: 4, leaves: vec![5] }]), "0e00000001000000000000000100000002000000000000000300000000000000040000000000000001000000000000000500000000000000"),
+            (AdminResponse::QueryRows(vec![("A.x".into(), 5)]), "0f00000001000000000000000300000000000000412e780500000000000000"),
+            (AdminResponse::QueryDone(Box::new(QuerySummary { count: 1, sum: -2, snapshots: vec![(config::NodeId(1), 3)], missing: vec![config::NodeId(2)] })), "100000000100000000000000feffffffffffffffffffffffffffffff0100000000000000010000000300000000000000010000000000000002000000"),
+            (AdminResponse::QueryPart(Box::new(QueryPart { node_id: config::NodeId(1), seq: 2, rows: vec![("A.x".into(), 5)], count: 1, sum: 5 })), "1100000001000000020000000000000001000000000000000300000000000000412e780500000000000000010000000000000005000000000000000000000000000000"),
+            (AdminResponse::Pause(PauseStatus { paused: false, busy: vec![config::NodeId(2)] }), "1200000000010000000000000002000000"),
+            (AdminResponse::Memory(Box::new(MemoryReport {
+                node_id: config::NodeId(1),
+                sessions: 1,
+                greeting: 2,
+                queues: vec![("peers".into(), 3)],
+                accounts: 4,
+                bytes: 5,
+                phases: 6,
+                notifications: 7,
+                commit_log: 8,
+                finished: 9,
+                decisions: 10,
+                transactions: vec![TransactionMemory { tx_id, writes: 1, bytes: 2 }],
+                largest: vec![AccountMemory { account_id: "A.x".into(), versions: 1, reads: 2, bytes: 3 }]
+            })), "13000000010000000100000000000000020000000000000001000000000000000500000000000000706565727303000000000000000400000000000000050000000000000006000000000000000700000000000000080000000000000009000000000000000a00000000000000010000000000000007000000000000000000000000000000010000000100000000000000020000000000000001000000000000000300000000000000412e78010000000000000002000000000000000300000000000000"),
+            (AdminResponse::NoOwner("A.x".into()), "140000000300000000000000412e78"),
+            (AdminResponse::Snapshots(vec![ShardSnapshot {
+                node_id: config::NodeId(1),
+                seq: 2,
+                accounts: vec![AccountSnapshot { account_id: "A.x".into(), balance: 5, committed_by: tx_id }]
+            }]), "15000000010000000000000001000000020000000000000001000000000000000300000000000000412e7805000000000000000700000000000000000000000000000001000000")
+        ];
+        for (response, hex) in &responses {
+            assert_wire(response, hex);
+        }
+    }
+
+    #[test]
+    fn test_abort_reasons_capped_on_char_boundaries() {
+        assert_eq!(cap_abort_reason("no".into()), "no");
+        assert_eq!(cap_abort_reason("x".repeat(MAX_ABORT_REASON_BYTES + 1)).len(), MAX_ABORT_REASON_BYTES);
+
+        // A character straddling the cap is dropped whole
+        let reason = format!("{}é", "x".repeat(MAX_ABORT_REASON_BYTES - 1));
+        assert_eq!(cap_abort_reason(reason), "x".repeat(MAX_ABORT_REASON_BYTES - 1));
+    }
+
+    #[test]
+    fn test_client_response_is_err() {
+        assert!(ClientResponse::Aborted.is_err());
+        assert!(ClientResponse::AbortedNotFound.is_err());
+        assert!(ClientResponse::AbortedNegativeBalance("test".into()).is_err());
+        assert!(ClientResponse::AbortedUnavailable(config::NodeId(0)).is_err());
+        assert!(ClientResponse::AbortedLabelQuota("batch".into()).is_err());
+        assert!(ClientResponse::AbortedUnauthorized.is_err());
+        assert!(ClientResponse::AbortedTenantLimit("acme".into()).is_err());
+        assert!(ClientResponse::AbortedNotDeterministic.is_err());
+        assert!(ClientResponse::AbortedLifetimeExpired.is_err());
+        assert!(ClientResponse::AbortedTooStale("test".into()).is_err());
+        assert!(ClientResponse::AbortedAssertionFailed("test".into(), BalancePredicate::AtLeast(1)).is_err());
+        assert!(!ClientResponse::LifetimeGranted(Duration::from_secs(1)).is_err());
+        assert!(!ClientResponse::Ok.is_err());
+        assert!(!ClientResponse::CommitOk.is_err());
+        assert!(!ClientResponse::Value("test".into(), 10).is_err());
+        assert!(!ClientResponse::AlreadyFinished(Decision::Abort).is_err());
+    }
+
+    #[test]
+    fn test_balance_predicates() {
+        for comparison in [">=", ">", "<=", "<", "=="] {
+            let predicate = BalancePredicate::parse(comparison, 10).unwrap();
+            assert_eq!(predicate.format(), format!("{comparison} 10"));
+        }
+        assert!(BalancePredicate::parse("!=", 10).is_none());
+
+        let holding = |predicate: BalancePredicate| [9, 10, 11].map(|balance| predicate.holds(balance));
+        assert_eq!(holding(BalancePredicate::AtLeast(10)), [false, true, true]);
+        assert_eq!(holding(BalancePredicate::MoreThan(10)), [false, false, true]);
+        assert_eq!(holding(BalancePredicate::AtMost(10)), [true, true, false]);
+        assert_eq!(holding(BalancePredicate::LessThan(10)), [true, false, false]);
+        assert_eq!(holding(BalancePredicate::Exactly(10)), [false, true, false]);
+    }
+
+    #[test]
+    fn test_split_nested_deadlines() {
+        let request = ClientRequest::Commit
+            .with_deadline(Duration::from_secs(1))
+            .with_deadline(Duration::from_secs(2));
+        assert!(matches!(request.split_deadline(), (Some(budget), ClientRequest::Commit) if budget == Duration::from_secs(1)));
+        assert!(matches!(ClientRequest::Abort.split_deadline(), (None, ClientRequest::Abort)));
+    }
+
+    #[test]
+    fn test_client_response_is_ok() {
+        assert!(!ClientResponse::Aborted.is_ok());
+        assert!(!ClientResponse::AbortedNotFound.is_ok());
+        assert!(!ClientResponse::AbortedNegativeBalance("test".into()).is_ok());
+        assert!(ClientResponse::Ok.is_ok());
+        assert!(ClientResponse::CommitOk.is_ok());
+        assert!(ClientResponse::CommitOkWithValues(vec![]).is_committed());
+        assert!(ClientResponse::CommitOkWithValues(vec![]).is_final());
+        assert!(ClientResponse::Value("test".into(), 10).is_ok());
+        assert!(!ClientResponse::AlreadyFinished(Decision::Commit).is_ok());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,251 @@
+//! The messages servers exchange with each other: requests forwarded on
+//! behalf of the transactions they coordinate, the rounds of two-phase
+//! commits, and gossip about the cluster's membership.
+
+use tx_common::{admin::{Access, MemberStatus}, config::NodeId, transaction_id::TransactionId, AccountId};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use crate::{BalanceDiff, ClientRequest, ClientResponse};
+
+/// Identifies a request a client handler forwarded, unique among the forwards
+/// of its transaction, so that each reply can be matched to the request it
+/// answers even when several forwards are in flight at once.
+pub type ForwardId = u64;
+
+/// Thus enum represents communication between shards forwarding requests on 
+/// behalf of clients coordinating transactions with shards and returning a 
+/// response to any received requests. This enum also represents the state
+/// associated with a two-phase commit of a transaction.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Forwarded {
+    /// Forwards a client request from a coordinator to a shard, with what is
+    /// left of the client's deadline for it, if it set one.
+    Request(TransactionId, ForwardId, Option<Duration>, Box<ClientRequest>),
+    /// Respond to a request from a coordinator upon processing a client request
+    /// received from this coordinator, echoing the request's `ForwardId`.
+    Response(TransactionId, ForwardId, ClientResponse),
+    /// Messages exchanged as part of a two-phase commit of a transaction, 
+    /// answering the forwarded `Commit` request with the given `ForwardId`.
+    TwoPhaseCommitStatus(TransactionId, ForwardId, CommitStatus),
+    /// Notifies a shard that all other shards are able to commit the 
+    /// transaction, so the shard can proceed with the commit. Given a 
+    /// forward id, the shard answers it with `CommitOkWithValues` once it 
+    /// committed, holding the balances the transaction wrote on it.
+    DoCommit(TransactionId, Option<ForwardId>),
+    /// Tells a coordinator that a shard applied the commit of one of its
+    /// transactions, in answer to every `DoCommit`.
+    CommitAck(TransactionId),
+    /// Asks the sequencer of a deterministic cluster to place a transaction's
+    /// balance changes in the cluster's order. The shards that run it answer
+    /// the forward with their verdicts.
+    Sequence(TransactionId, ForwardId, Vec<(AccountId, BalanceDiff)>),
+    /// Hands a sequenced transaction to a shard it writes to
+    Sequenced(Box<SequencedTransaction>),
+    /// A shard's verdict on its part of a sequenced transaction, sent to the
+    /// other shards the transaction writes to
+    Verdict(TransactionId, CommitStatus),
+    /// The two-phase commit messages a node sent a peer during an epoch, in
+    /// the order it sent them. A batch is never empty.
+    Batch(Vec<Forwarded>),
+    /// Asks a node to handle a forwarded request as part of a spanning tree
+    /// and to relay it on to the given nodes, answering with one `Relayed`
+    /// holding its own reply and those of the nodes it relayed to.
+    Relay(Vec<NodeId>, Box<Forwarded>),
+    /// The replies to a request relayed along a spanning tree from the nodes
+    /// below the sender, and the sender itself, answering the forward with
+    /// the given `ForwardId`.
+    Relayed(TransactionId, ForwardId, Vec<(NodeId, ShardReply)>),
+    /// Probes and membership updates exchanged between nodes that gossip,
+    /// which belong to no transaction
+    Gossip(Gossip),
+    /// A change to an access control list made by an admin request of the
+    /// given transaction, answered with `Ok` once the receiver applied it
+    Acl(TransactionId, ForwardId, Box<AclChange>),
+    /// The latest change to every access control list the sender knows of,
+    /// sent to a peer when it joins so that it catches up on those it missed
+    AclSync(Vec<AclChange>),
+    /// Lifts the pause the given transaction placed on the receiver, if it
+    /// still holds, answered with `Ok`
+    Unpause(TransactionId, ForwardId),
+    /// Copies a sequenced transaction to the sequencer's backup before any
+    /// shard is handed it, so that the backup can take over the cluster's 
+    /// order from the same position if the sequencer is lost
+    Replicate(Box<SequencedTransaction>),
+    /// Tells the sequencer that its backup logged every transaction up to 
+    /// the given position
+    Replicated(u64)
+}
+
+/// The access control list of an account as a change left it. Every node
+/// applies the change unless it already holds a newer one for the account,
+/// so that all nodes end up enforcing the same lists whatever order the 
+/// changes reach them in.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AclChange {
+    /// Orders the changes to the account
+    pub stamp: TransactionId,
+    pub account_id: AccountId,
+    /// The account's owner, or `None` if its list was removed
+    pub owner: Option<String>,
+    /// The tenants granted access, sorted
+    pub grants: Vec<(String, Access)>
+}
+
+/// A transaction placed in the order of a deterministic cluster. Every shard
+/// it writes to runs it after the transactions sequenced before it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SequencedTransaction {
+    /// The transaction's position in the cluster's order
+    pub seq: u64,
+    /// The id the shards run the transaction as, which increases with `seq`
+    pub id: TransactionId,
+    /// The node coordinating the transaction for its client, its id for the
+    /// transaction and the forward that awaits the shards' verdicts
+    pub coordinator: NodeId,
+    pub client_tx: TransactionId,
+    pub fwd_id: ForwardId,
+    pub writes: Vec<(AccountId, BalanceDiff)>
+}
+
+impl Forwarded {
+    /// The transaction this message is about, if it is about one.
+    pub fn tx_id(&self) -> Option<TransactionId> {
+        match self {
+            Self::Request(tx_id, ..) => Some(*tx_id),
+            Self::Response(tx_id, ..) => Some(*tx_id),
+            Self::TwoPhaseCommitStatus(tx_id, ..) => Some(*tx_id),
+            Self::DoCommit(tx_id, _) => Some(*tx_id),
+            Self::CommitAck(tx_id) => Some(*tx_id),
+            Self::Sequence(tx_id, ..) => Some(*tx_id),
+            Self::Sequenced(sequenced) => Some(sequenced.id),
+            Self::Verdict(tx_id, _) => Some(*tx_id),
+            Self::Batch(batch) => batch[0].tx_id(),
+            Self::Relay(_, msg) => msg.tx_id(),
+            Self::Relayed(tx_id, ..) => Some(*tx_id),
+            Self::Acl(tx_id, ..) => Some(*tx_id),
+            Self::Unpause(tx_id, _) => Some(*tx_id),
+            Self::Replicate(sequenced) => Some(sequenced.id),
+            Self::Gossip(_) | Self::AclSync(_) | Self::Replicated(_) => None
+        }
+    }
+
+    /// The forward this message is or answers, if it is part of one.
+    pub fn forward_id(&self) -> Option<ForwardId> {
+        match self {
+            Self::Request(_, fwd_id, ..) => Some(*fwd_id),
+            Self::Response(_, fwd_id, _) => Some(*fwd_id),
+            Self::TwoPhaseCommitStatus(_, fwd_id, _) => Some(*fwd_id),
+            Self::Sequence(_, fwd_id, _) => Some(*fwd_id),
+            Self::Relay(_, msg) => msg.forward_id(),
+            Self::Relayed(_, fwd_id, _) => Some(*fwd_id),
+            Self::DoCommit(_, fwd_id) => *fwd_id,
+            Self::Acl(_, fwd_id, _) => Some(*fwd_id),
+            Self::Unpause(_, fwd_id) => Some(*fwd_id),
+            Self::CommitAck(_) | Self::Sequenced(_) | Self::Verdict(..) | Self::Batch(_) | Self::Gossip(_) | Self::AclSync(_) | Self::Replicate(_) | Self::Replicated(_) => None
+        }
+    }
+
+    /// This node's reply to a request relayed to it along a spanning tree,
+    /// to be gathered with the replies of the nodes it relays to.
+    pub fn into_relayed(self, node_id: NodeId) -> Self {
+        match self {
+            Self::Response(tx_id, fwd_id, resp) => Self::Relayed(tx_id, fwd_id, vec![(node_id, ShardReply::Response(resp))]),
+            Self::TwoPhaseCommitStatus(tx_id, fwd_id, status) => Self::Relayed(tx_id, fwd_id, vec![(node_id, ShardReply::Vote(status))]),
+            msg => msg
+        }
+    }
+}
+
+/// A shard's reply to a message forwarded on behalf of a client handler, which
+/// the server task routes back to the client handler.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ShardReply {
+    /// The response to a forwarded client request
+    Response(ClientResponse),
+    /// The shard's vote in a two-phase commit
+    Vote(CommitStatus),
+    /// The shard has not joined yet, so the server replied in its place
+    Unreachable,
+    /// The shard has not joined the node that was to relay a request to it
+    /// along a spanning tree, which replied in its place. The coordinator
+    /// may still reach it directly.
+    Unrelayed
+}
+
+/// Status exchanged between shards as part of the two-phase commit process.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum CommitStatus {
+    /// Indicates that the shard is ready to commit the transaction upon 
+    /// checking that the transaction passes a consistency check. 
+    ReadyToCommit,
+    /// Indicates that the shard is unable to commit the transaction upon 
+    /// checking that the transaction passes a consistency check. Carries the
+    /// abort response that the coordinator should send back to the client.
+    CannotCommit(ClientResponse)
+}
+
+/// The messages nodes exchange to probe each other, each carrying some of
+/// the membership updates the sender is still spreading.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Gossip {
+    /// Probes the receiver, which acknowledges with the same sequence number
+    Ping(u64, Vec<MemberStatus>),
+    /// Asks the receiver to probe a member on the sender's behalf and pass on
+    /// its acknowledgement
+    PingReq(u64, NodeId, Vec<MemberStatus>),
+    Ack(u64, Vec<MemberStatus>)
+}
+
+
+#[cfg(test)]
+mod test {
+    use tx_common::admin::MemberState;
+    use crate::test::assert_wire;
+    use super::*;
+
+    #[test]
+    fn test_forwarded_wire_format() {
+        let tx_id = TransactionId::at(7, NodeId(1));
+        let member = |node_id, state, incarnation| MemberStatus { node_id: NodeId(node_id), state, incarnation };
+        let sequenced = SequencedTransaction {
+            seq: 9,
+            id: TransactionId::at(8, NodeId(0)),
+            coordinator: NodeId(1),
+            client_tx: tx_id,
+            fwd_id: 6,
+            writes: vec![("A.x".into(), BalanceDiff(1))]
+        };
+        let acl = AclChange { stamp: tx_id, account_id: "A.x".into(), owner: Some("acme".into()), grants: vec![("beta".into(), Access::Read)] };
+        let messages = [
+            (Forwarded::Request(tx_id, 3, Some(Duration::from_millis(100)), Box::new(ClientRequest::ReadBalance("A.x".into()))), "000000000700000000000000000000000000000001000000030000000000000001000000000000000000e1f505020000000300000000000000412e78"),
+            (Forwarded::Response(tx_id, 3, ClientResponse::Value("A.x".into(), 5)), "0100000007000000000000000000000000000000010000000300000000000000110000000300000000000000412e780500000000000000"),
+            (Forwarded::TwoPhaseCommitStatus(tx_id, 4, CommitStatus::ReadyToCommit), "020000000700000000000000000000000000000001000000040000000000000000000000"),
+            (Forwarded::TwoPhaseCommitStatus(tx_id, 4, CommitStatus::CannotCommit(ClientResponse::AbortedNegativeBalance("A.x".into()))), "020000000700000000000000000000000000000001000000040000000000000001000000050000000300000000000000412e78"),
+            (Forwarded::DoCommit(tx_id, None), "03000000070000000000000000000000000000000100000000"),
+            (Forwarded::DoCommit(tx_id, Some(5)), "030000000700000000000000000000000000000001000000010500000000000000"),
+            (Forwarded::CommitAck(tx_id), "040000000700000000000000000000000000000001000000"),
+            (Forwarded::Sequence(tx_id, 6, vec![("A.x".into(), BalanceDiff(1))]), "050000000700000000000000000000000000000001000000060000000000000001000000000000000300000000000000412e780100000000000000"),
+            (Forwarded::Sequenced(Box::new(sequenced.clone())), "0600000009000000000000000800000000000000000000000000000000000000010000000700000000000000000000000000000001000000060000000000000001000000000000000300000000000000412e780100000000000000"),
+            (Forwarded::Verdict(tx_id, CommitStatus::ReadyToCommit), "07000000070000000000000000000000000000000100000000000000"),
+            (Forwarded::Batch(vec![Forwarded::CommitAck(tx_id)]), "080000000100000000000000040000000700000000000000000000000000000001000000"),
+            (Forwarded::Relay(vec![NodeId(1), NodeId(2)], Box::new(Forwarded::DoCommit(tx_id, None))), "090000000200000000000000010000000200000003000000070000000000000000000000000000000100000000"),
+            (Forwarded::Relayed(tx_id, 7, vec![
+                (NodeId(1), ShardReply::Response(ClientResponse::Ok)),
+                (NodeId(2), ShardReply::Vote(CommitStatus::ReadyToCommit)),
+                (NodeId(3), ShardReply::Unreachable),
+                (NodeId(4), ShardReply::Unrelayed)
+            ]), "0a00000007000000000000000000000000000000010000000700000000000000040000000000000001000000000000000000000002000000010000000000000003000000020000000400000003000000"),
+            (Forwarded::Gossip(Gossip::Ping(1, vec![member(1, MemberState::Alive, 0)])), "0b000000000000000100000000000000010000000000000001000000000000000000000000000000"),
+            (Forwarded::Gossip(Gossip::PingReq(2, NodeId(3), vec![member(2, MemberState::Suspect, 1)])), "0b00000001000000020000000000000003000000010000000000000002000000010000000100000000000000"),
+            (Forwarded::Gossip(Gossip::Ack(2, vec![member(3, MemberState::Dead, 4)])), "0b000000020000000200000000000000010000000000000003000000020000000400000000000000"),
+            (Forwarded::Acl(tx_id, 8, Box::new(acl.clone())), "0c0000000700000000000000000000000000000001000000080000000000000007000000000000000000000000000000010000000300000000000000412e7801040000000000000061636d65010000000000000004000000000000006265746100000000"),
+            (Forwarded::AclSync(vec![acl, AclChange { stamp: tx_id, account_id: "A.y".into(), owner: None, grants: vec![] }]), "0d000000020000000000000007000000000000000000000000000000010000000300000000000000412e7801040000000000000061636d6501000000000000000400000000000000626574610000000007000000000000000000000000000000010000000300000000000000412e79000000000000000000"),
+            (Forwarded::Unpause(tx_id, 9), "0e00000007000000000000000000000000000000010000000900000000000000"),
+            (Forwarded::Replicate(Box::new(sequenced)), "0f00000009000000000000000800000000000000000000000000000000000000010000000700000000000000000000000000000001000000060000000000000001000000000000000300000000000000412e780100000000000000"),
+            (Forwarded::Replicated(9), "100000000900000000000000")
+        ];
+        for (message, hex) in &messages {
+            assert_wire(message, hex);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! The cluster's topology as a node describes it to clients that ask with
+//! `ClientRequest::ClusterInfo`, so that they can route transactions and
+//! display the cluster without a copy of its config.
+
+use tx_common::config::{self, NodeConfiguration, NodeId};
+use serde::{Deserialize, Serialize};
+
+/// The names of the optional features a node lists in its `ClusterInfo`.
+/// Features are named rather than enumerated so that clients built before a
+/// feature was added still decode the list.
+pub mod capability {
+    /// Transactions commit with two-phase commit
+    pub const TWO_PHASE_COMMIT: &str = "two-phase-commit";
+    /// Transactions are sequenced and only take balance changes
+    pub const DETERMINISTIC: &str = "deterministic";
+    pub const READS: &str = "reads";
+    pub const STALE_READS: &str = "stale-reads";
+    pub const SWAPS: &str = "swaps";
+    pub const INCREMENTS: &str = "increments";
+    /// Accounts created with a TTL expire
+    pub const TTL: &str = "ttl";
+    pub const DEADLINES: &str = "deadlines";
+    pub const LIFETIMES: &str = "lifetimes";
+    /// Commits may answer with the balances they wrote
+    pub const COMMIT_VALUES: &str = "commit-values";
+    pub const QUERIES: &str = "queries";
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum NodeRole {
+    /// Hosts logical shards and coordinates transactions
+    Shard,
+    /// Hosts no shards, but votes on every transaction and learns every
+    /// decision
+    Witness
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NodeInfo {
+    pub node_id: NodeId,
+    pub name: String,
+    /// Where the node serves clients
+    pub hostname: String,
+    pub port: u16,
+    pub role: NodeRole,
+    /// The logical shards the node hosts
+    pub shards: Vec<String>,
+    /// Whether the node answering is this node or connected to it. 
+    /// Transactions that access the shards of a node that has not joined are
+    /// aborted.
+    pub joined: bool
+}
+
+impl NodeInfo {
+    /// The configuration to begin a transaction coordinated by the node with.
+    pub fn configuration(&self) -> NodeConfiguration {
+        NodeConfiguration {
+            node_id: self.node_id,
+            name: self.name.clone(),
+            hostname: self.hostname.clone(),
+            port: self.port,
+            connection_list: Vec::new(),
+            shards: self.shards.clone()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ClusterInfo {
+    /// The node that answered
+    pub node_id: NodeId,
+    pub protocol_version: u32,
+    /// A fingerprint of how the cluster routes accounts, which differs
+    /// between nodes started with different configs
+    pub epoch: u64,
+    /// Every node in the cluster, sorted by id
+    pub nodes: Vec<NodeInfo>,
+    /// The features the answering node supports, named as in `capability`
+    pub capabilities: Vec<String>
+}
+
+impl ClusterInfo {
+    /// The node hosting the shard that owns an account. A transaction that
+    /// only accesses accounts on one node runs without forwarding anything if
+    /// that node coordinates it.
+    pub fn owner(&self, account_id: &str) -> Option<&NodeInfo> {
+        let shard_name = config::shard_name(account_id)?;
+        self.nodes.iter().find(|node| node.shards.iter().any(|shard| shard == shard_name))
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|supported| supported == capability)
+    }
+
+    pub fn format(&self) -> String {
+        let nodes = self.nodes.iter().fold(String::new(), |output, node| {
+            let role = match node.role {
+                NodeRole::Shard => format!("shards={}", node.shards.join(",")),
+                NodeRole::Witness => "witness".into()
+            };
+            let joined = if node.joined { "" } else { " NOT JOINED" };
+            format!("{output}\n{} {} {}:{} {role}{joined}", node.node_id, node.name, node.hostname, node.port)
+        });
+        format!(
+            "CLUSTER node={} protocol={} epoch={:016x}{nodes}\nCAPABILITIES {}",
+            self.node_id, self.protocol_version, self.epoch, self.capabilities.join(" ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(node_id: u32, name: &str, shards: &[&str]) -> NodeInfo {
+        NodeInfo {
+            node_id: NodeId(node_id),
+            name: name.into(),
+            hostname: "127.0.0.1".into(),
+            port: 4000 + node_id as u16,
+            role: if shards.is_empty() { NodeRole::Witness } else { NodeRole::Shard },
+            shards: shards.iter().map(|shard| shard.to_string()).collect(),
+            joined: true
+        }
+    }
+
+    #[test]
+    fn test_owner_routes_like_the_cluster() {
+        let info = ClusterInfo {
+            node_id: NodeId(0),
+            protocol_version: crate::PROTOCOL_VERSION,
+            epoch: 0,
+            nodes: vec![node(0, "east", &["orders", "A"]), node(1, "west", &["users"]), node(2, "tie", &[])],
+            capabilities: vec![capability::READS.into()]
+        };
+
+        let owner = |account_id| info.owner(account_id).map(|node| node.name.as_str());
+        assert_eq!(owner("orders.42"), Some("east"));
+        assert_eq!(owner("users.bob"), Some("west"));
+        // Accounts without a shard name belong to the shard named after
+        // their first character
+        assert_eq!(owner("Ax"), Some("east"));
+        assert_eq!(owner("B.x"), None);
+        assert_eq!(owner(""), None);
+        assert!(info.supports(capability::READS) && !info.supports(capability::SWAPS));
+    }
+}
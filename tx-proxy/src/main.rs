@@ -0,0 +1,54 @@
+use tx_proxy::{proxy::{run, Faults}, trace::Recorder};
+use tx_common::replay::{replay_seed, REPLAY_SEED_VAR};
+use rand::Rng;
+use tokio::net::TcpListener;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let args: Vec<_> = std::env::args().collect();
+
+    if args.len() < 4 || args.len() > 6 {
+        eprintln!("Usage: {} <listen port> <coordinator host:port> <trace file> [delay ms] [drop rate]", args[0]);
+        std::process::exit(1);
+    }
+
+    let parsed = args[1].parse::<u16>().map_err(|e| e.to_string()).and_then(|port| {
+        let delay = args.get(4).map_or(Ok(0), |ms| ms.parse::<u64>()).map_err(|e| e.to_string())?;
+        let drop_rate = args.get(5).map_or(Ok(0.0), |p| p.parse::<f64>()).map_err(|e| e.to_string())?;
+        if !(0.0..=1.0).contains(&drop_rate) {
+            return Err(format!("drop rate {drop_rate} is not between 0 and 1"));
+        }
+
+        let seed = replay_seed()?.unwrap_or_else(|| rand::thread_rng().gen());
+        Ok((port, Faults { delay: Duration::from_millis(delay), drop_rate, seed }))
+    });
+
+    let (port, faults) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}: {}", &args[0], e);
+            std::process::exit(1);
+        }
+    };
+
+    let recorder = match Recorder::create(&args[3]) {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            eprintln!("{}: failed to create {}: {}", &args[0], &args[3], e);
+            std::process::exit(1);
+        }
+    };
+
+    let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("{}: failed to listen on port {}: {}", &args[0], port, e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Seed {}: replay the dropped frames with {REPLAY_SEED_VAR}={}", faults.seed, faults.seed);
+    run(listener, args[2].clone(), faults, recorder).await;
+}
@@ -0,0 +1,166 @@
+use crate::trace::{Direction, Recorder, TraceRecord};
+use tx_common::stream::{framed, FramedStream};
+use tx_proto::{ClientRequest, ClientResponse};
+use tokio::{net::{TcpListener, TcpStream}, time::Instant};
+use futures::{stream::{SplitSink, SplitStream}, SinkExt, StreamExt};
+use tokio_util::bytes::Bytes;
+use std::{sync::Arc, time::Duration};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use log::{debug, error, info};
+
+/// Faults the proxy injects into every relayed frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Faults {
+    /// How long each frame is held before it is relayed
+    pub delay: Duration,
+    /// The probability that a frame is dropped instead of relayed
+    pub drop_rate: f64,
+    /// Picks the frames to drop. Each direction of each connection draws 
+    /// from its own generator, so that the same seed drops the same frames
+    /// of connections that send the same frames in the same order.
+    pub seed: u64
+}
+
+struct Relay {
+    connection: u64,
+    started: Instant,
+    faults: Faults,
+    recorder: Recorder
+}
+
+/// Relays every client accepted on `listener` to `upstream`, recording both
+/// directions of every connection.
+pub async fn run(listener: TcpListener, upstream: String, faults: Faults, recorder: Recorder) {
+    let started = Instant::now();
+    let mut next_connection = 0;
+
+    loop {
+        let client = match listener.accept().await {
+            Ok((stream, addr)) => {
+                info!("Accepted client {addr} as connection {next_connection}");
+                stream
+            },
+            Err(e) => {
+                error!("Failed to accept a client: {e:?}");
+                continue;
+            }
+        };
+
+        let server = match TcpStream::connect(&upstream).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Failed to connect to the coordinator at {upstream}: {e:?}");
+                continue;
+            }
+        };
+
+        let relay = Arc::new(Relay { connection: next_connection, started, faults, recorder: recorder.clone() });
+        next_connection += 1;
+
+        let (to_client, from_client) = framed(client).split();
+        let (to_server, from_server) = framed(server).split();
+        tokio::spawn(relay.clone().forward(from_client, to_server, Direction::ToServer));
+        tokio::spawn(relay.forward(from_server, to_client, Direction::ToClient));
+    }
+}
+
+impl Relay {
+    async fn forward(
+        self: Arc<Self>,
+        mut from: SplitStream<FramedStream>,
+        mut to: SplitSink<FramedStream, Bytes>,
+        direction: Direction
+    ) {
+        let stream = 2 * self.connection + (direction == Direction::ToClient) as u64;
+        let mut rng = StdRng::seed_from_u64(self.faults.seed.wrapping_add(stream));
+        while let Some(Ok(frame)) = from.next().await {
+            let dropped = rng.gen_bool(self.faults.drop_rate);
+            self.log(&frame, direction, dropped);
+            self.recorder.record(TraceRecord {
+                micros: self.started.elapsed().as_micros() as u64,
+                connection: self.connection,
+                direction,
+                dropped,
+                frame: frame.to_vec()
+            });
+
+            if dropped {
+                continue;
+            }
+
+            tokio::time::sleep(self.faults.delay).await;
+            if let Err(e) = to.send(frame.freeze()).await {
+                error!("Connection {} closed while relaying {direction:?}: {e:?}", self.connection);
+                break;
+            }
+        }
+
+        // Closing our half tells the other end that this end went away.
+        let _ = to.close().await;
+    }
+
+    fn log(&self, frame: &[u8], direction: Direction, dropped: bool) {
+        let action = if dropped { "Dropping" } else { "Relaying" };
+        match direction {
+            Direction::ToServer => match bincode::deserialize::<ClientRequest>(frame) {
+                Ok(request) => debug!("{action} {request:?} on connection {}", self.connection),
+                Err(_) => debug!("{action} undecodable request on connection {}", self.connection)
+            },
+            Direction::ToClient => match bincode::deserialize::<ClientResponse>(frame) {
+                Ok(response) => debug!("{action} {response:?} on connection {}", self.connection),
+                Err(_) => debug!("{action} undecodable response on connection {}", self.connection)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::trace::read_trace;
+    use tx_common::stream::MessageStream;
+    use tx_proto::BalanceDiff;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_proxy_relays_and_records() {
+        // A coordinator that answers every request with `Ok`
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = upstream.accept().await.unwrap();
+            let mut stream = MessageStream::from_tcp_stream(stream);
+            while let Some(Ok(_)) = stream.recv::<ClientRequest>().await {
+                stream.send(ClientResponse::Ok).await.unwrap();
+            }
+        });
+
+        let path = std::env::temp_dir().join(format!("tx-proxy-{}.trace", std::process::id()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let recorder = Recorder::create(&path).unwrap();
+        tokio::spawn(run(listener, upstream_addr, Faults::default(), recorder));
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut stream = MessageStream::from_tcp_stream(stream);
+        stream.send(ClientRequest::WriteBalance("A.x".into(), BalanceDiff(5))).await.unwrap();
+        let response: ClientResponse = stream.recv().await.unwrap().unwrap();
+        assert!(matches!(response, ClientResponse::Ok));
+
+        // The writer flushes on its own thread, so wait for both records.
+        let mut records = vec![];
+        for _ in 0..50 {
+            records = read_trace(&path).unwrap();
+            if records.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let directions: Vec<_> = records.iter().map(|r| (r.connection, r.direction, r.dropped)).collect();
+        assert_eq!(directions, vec![(0, Direction::ToServer, false), (0, Direction::ToClient, false)]);
+        let request: ClientRequest = bincode::deserialize(&records[0].frame).unwrap();
+        assert!(matches!(request, ClientRequest::WriteBalance(_, BalanceDiff(5))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io::{BufReader, BufWriter, Write}, path::Path};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use log::error;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Direction {
+    /// Sent by the client to the coordinator
+    ToServer,
+    /// Sent by the coordinator to the client
+    ToClient
+}
+
+/// One frame relayed by the proxy. Frames hold a bincode-encoded
+/// `ClientRequest` when sent to the server and a `ClientResponse` when sent
+/// to the client.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TraceRecord {
+    /// Microseconds since the proxy started
+    pub micros: u64,
+    /// Numbers the client connections in the order the proxy accepted them
+    pub connection: u64,
+    pub direction: Direction,
+    /// Whether the proxy dropped the frame instead of relaying it
+    pub dropped: bool,
+    pub frame: Vec<u8>
+}
+
+/// Appends trace records to a file from any task. Records are written by a
+/// dedicated thread so that relaying never waits on the disk.
+#[derive(Clone)]
+pub struct Recorder {
+    records: UnboundedSender<TraceRecord>
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let (records, mut rcv) = unbounded_channel::<TraceRecord>();
+
+        std::thread::spawn(move || {
+            while let Some(record) = rcv.blocking_recv() {
+                // Flush whenever the channel is drained so that a trace is
+                // complete up to the last frame even if the proxy is killed.
+                let written = bincode::serialize_into(&mut file, &record)
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| if rcv.is_empty() { file.flush().map_err(|e| e.to_string()) } else { Ok(()) });
+
+                if let Err(e) = written {
+                    error!("Failed to write to the trace: {e}");
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { records })
+    }
+
+    pub fn record(&self, record: TraceRecord) {
+        if self.records.send(record).is_err() {
+            error!("Trace writer stopped; dropping trace record");
+        }
+    }
+}
+
+/// Reads every record of a trace written by a `Recorder`.
+pub fn read_trace(path: impl AsRef<Path>) -> Result<Vec<TraceRecord>, String> {
+    let mut rdr = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut records = Vec::new();
+
+    loop {
+        match bincode::deserialize_from(&mut rdr) {
+            Ok(record) => records.push(record),
+            Err(e) => match *e {
+                bincode::ErrorKind::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                e => return Err(e.to_string())
+            }
+        }
+    }
+
+    Ok(records)
+}
@@ -0,0 +1,151 @@
+//! Measures how ordering prepares by timestamp changes commit latency.
+//! Workers run transactions directly on one shard that already holds other
+//! accounts, as a live shard does, with a pause standing in for the round
+//! trip to the coordinator between voting and committing. In the contended
+//! workload every transaction writes two neighbouring hot accounts out of a
+//! ring of a few, so each waits on an older one that may wait on an older one
+//! still, and the chain moves only as fast as its oldest transaction is
+//! decided. The same workers then each write an account of their own, which
+//! compares the orders when prepares have nothing to wait on. Rounds
+//! alternate between timestamp and arrival order so that both see the same
+//! machine conditions:
+//!
+//! ```text
+//! cargo bench -p tx-server --bench prepare_ordering -- [seconds per round] [workers] [hot accounts] [other accounts] [rounds]
+//! ```
+//!
+//! Checking a prepare visits every account on the shard, so when prepares
+//! check all at once in the order they arrived, the oldest transaction's
+//! check shares the shard with every younger one waiting on it. Timestamp
+//! order is the default, so the bench fails if it does not cut the mean
+//! commit latency of the contended workload.
+
+use tx_common::config::NodeId;
+use tx_server::sharding::{Shard, TransactionIdGenerator};
+use std::sync::Arc;
+use tokio::{task::JoinSet, time::{sleep, Duration, Instant}};
+
+/// Stands in for the coordinator collecting the votes and sending the
+/// decision back.
+const ROUND_TRIP: Duration = Duration::from_micros(200);
+
+/// The accounts each worker's transactions write.
+fn accounts(worker: u32, hot: Option<u32>) -> Vec<String> {
+    match hot {
+        Some(hot) => vec![format!("A.hot{}", worker % hot), format!("A.hot{}", (worker + 1) % hot)],
+        None => vec![format!("A.own{worker}")]
+    }
+}
+
+/// Runs transactions on `shard` from `workers` workers for `duration`,
+/// returning the latency of every commit and the number of aborts.
+async fn run(shard: Arc<Shard<String, i64>>, duration: Duration, workers: u32, hot: Option<u32>) -> (Vec<Duration>, u64) {
+    let until = Instant::now() + duration;
+    let mut tasks = JoinSet::new();
+    for worker in 0..workers {
+        let shard = shard.clone();
+        tasks.spawn(async move {
+            let mut ids = TransactionIdGenerator::new(NodeId(worker + 1));
+            let accounts = accounts(worker, hot);
+            let (mut latencies, mut aborted) = (Vec::new(), 0);
+            'transactions: while Instant::now() < until {
+                let id = ids.next();
+                let started_at = Instant::now();
+                for account in &accounts {
+                    if shard.write(&id, account.clone(), worker as i64).await.is_err() {
+                        shard.abort(&id).await.unwrap();
+                        aborted += 1;
+                        continue 'transactions;
+                    }
+                }
+
+                if shard.check_commit(&id).await.is_err() {
+                    shard.abort(&id).await.unwrap();
+                    aborted += 1;
+                    continue;
+                }
+                sleep(ROUND_TRIP).await;
+                match shard.commit(&id).await {
+                    Ok(_) => latencies.push(started_at.elapsed()),
+                    Err(_) => aborted += 1
+                }
+            }
+            (latencies, aborted)
+        });
+    }
+
+    let (mut latencies, mut aborted) = (Vec::new(), 0);
+    while let Some(result) = tasks.join_next().await {
+        let (worker_latencies, worker_aborted) = result.unwrap();
+        latencies.extend(worker_latencies);
+        aborted += worker_aborted;
+    }
+    (latencies, aborted)
+}
+
+/// Prints the throughput and latency of an order, returning the mean
+/// latency.
+fn report(order: &str, mut latencies: Vec<Duration>, aborted: u64, elapsed: f64) -> Duration {
+    latencies.sort();
+    let percentile = |p: f64| latencies
+        .get(((latencies.len() as f64 * p) as usize).min(latencies.len().saturating_sub(1)))
+        .copied()
+        .unwrap_or_default();
+    let mean = latencies.iter().sum::<Duration>() / latencies.len().max(1) as u32;
+    println!(
+        "{order}: {:.0} commits/s, {aborted} aborted, commit latency mean {mean:?} p50 {:?} p99 {:?}",
+        latencies.len() as f64 / elapsed, percentile(0.5), percentile(0.99)
+    );
+    mean
+}
+
+#[tokio::main]
+async fn main() {
+    // `cargo bench` passes `--bench` to every bench target
+    let args: Vec<u64> = std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--bench")
+        .map(|arg| arg.parse().expect("arguments must be numbers"))
+        .collect();
+    let seconds = args.first().copied().unwrap_or(2);
+    let workers = args.get(1).copied().unwrap_or(64) as u32;
+    let hot = args.get(2).copied().unwrap_or(4).max(2) as u32;
+    let others = args.get(3).copied().unwrap_or(1000);
+    let rounds = args.get(4).copied().unwrap_or(3).max(1);
+
+    let round = Duration::from_secs(seconds);
+    let elapsed = (rounds * seconds) as f64;
+    let mut contended = None;
+    for (workload, hot) in [("contended", Some(hot)), ("uncontended", None)] {
+        let shard = Arc::new(Shard::new(NodeId(0)));
+        let id = TransactionIdGenerator::new(NodeId(0)).next();
+        for i in 0..others {
+            shard.write(&id, format!("A.other{i}"), 0).await.unwrap();
+        }
+        shard.check_commit(&id).await.unwrap();
+        shard.commit(&id).await.unwrap();
+
+        let (mut ordered, mut arrival) = ((Vec::new(), 0), (Vec::new(), 0));
+        match hot {
+            Some(hot) => println!("{workload}: {workers} workers on chains over {hot} accounts, {others} others, for {rounds} rounds of {seconds}s in each order"),
+            None => println!("{workload}: {workers} workers on their own accounts, {others} others, for {rounds} rounds of {seconds}s in each order")
+        }
+        for _ in 0..rounds {
+            shard.set_prepare_ordering(false);
+            let (latencies, aborted) = run(shard.clone(), round, workers, hot).await;
+            arrival.0.extend(latencies);
+            arrival.1 += aborted;
+            shard.set_prepare_ordering(true);
+            let (latencies, aborted) = run(shard.clone(), round, workers, hot).await;
+            ordered.0.extend(latencies);
+            ordered.1 += aborted;
+        }
+
+        let arrival = report("  arrival order", arrival.0, arrival.1, elapsed);
+        let ordered = report("  timestamp order", ordered.0, ordered.1, elapsed);
+        contended.get_or_insert((arrival, ordered));
+    }
+
+    let (arrival, ordered) = contended.unwrap();
+    assert!(ordered < arrival, "timestamp order did not cut the mean commit latency of the contended workload: {ordered:?}, against {arrival:?} in arrival order");
+}
@@ -0,0 +1,88 @@
+//! Measures what counting a shard's operations costs. Workers run small
+//! transactions, each reading and writing an account of its own and
+//! committing, directly against one shard, with counting turned on and off
+//! in alternating rounds so that both see the same machine conditions:
+//!
+//! ```text
+//! cargo bench -p tx-server --bench shard_counting -- [seconds per round] [workers] [rounds]
+//! ```
+//!
+//! Counting is meant to stay on in production, so the bench fails if it
+//! costs 2% of the throughput or more. Runs of a few seconds vary by about
+//! as much, so the defaults, 16 workers for 8 rounds of 3s, are long enough
+//! to average that out.
+
+use tx_common::config::NodeId;
+use tx_server::sharding::{Shard, TransactionIdGenerator};
+use std::sync::Arc;
+use tokio::{task::JoinSet, time::{Duration, Instant}};
+
+/// The most throughput counting may cost, in percent.
+const BUDGET: f64 = 2.0;
+
+/// Runs transactions on `shard` from `workers` workers for `duration`,
+/// returning how many committed.
+async fn run(shard: Arc<Shard<String, i64>>, duration: Duration, workers: u32) -> u64 {
+    let until = Instant::now() + duration;
+    let mut tasks = JoinSet::new();
+    for worker in 0..workers {
+        let shard = shard.clone();
+        tasks.spawn(async move {
+            // Each worker coordinates its own transactions on its own account
+            let mut ids = TransactionIdGenerator::new(NodeId(worker + 1));
+            let account = format!("A.worker{worker}");
+            let mut committed = 0;
+            while Instant::now() < until {
+                let id = ids.next();
+                let balance = shard.read(&id, &account).await.unwrap_or_default();
+                let done = match shard.write(&id, account.clone(), balance + 1).await {
+                    Ok(()) => shard.check_commit(&id).await.is_ok() && shard.commit(&id).await.is_ok(),
+                    Err(_) => false
+                };
+                if done {
+                    committed += 1;
+                } else {
+                    shard.abort(&id).await.unwrap();
+                }
+            }
+            committed
+        });
+    }
+
+    let mut committed = 0;
+    while let Some(count) = tasks.join_next().await {
+        committed += count.unwrap();
+    }
+    committed
+}
+
+#[tokio::main]
+async fn main() {
+    // `cargo bench` passes `--bench` to every bench target
+    let args: Vec<u64> = std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--bench")
+        .map(|arg| arg.parse().expect("arguments must be numbers"))
+        .collect();
+    let seconds = args.first().copied().unwrap_or(3);
+    let workers = args.get(1).copied().unwrap_or(16) as u32;
+    let rounds = args.get(2).copied().unwrap_or(8).max(1);
+
+    let shard = Arc::new(Shard::new(NodeId(0)));
+    let round = Duration::from_secs(seconds);
+    let (mut with, mut without) = (0, 0);
+    println!("{workers} workers for {rounds} rounds of {seconds}s with counting on and off");
+    for _ in 0..rounds {
+        shard.set_counting(false);
+        without += run(shard.clone(), round, workers).await;
+        shard.set_counting(true);
+        with += run(shard.clone(), round, workers).await;
+    }
+
+    let elapsed = (rounds * seconds) as f64;
+    let overhead = 100.0 * (without as f64 - with as f64) / without.max(1) as f64;
+    println!("counting off: {:.0} commits/s", without as f64 / elapsed);
+    println!("counting on: {:.0} commits/s, counted {:?}", with as f64 / elapsed, shard.counts());
+    println!("overhead: {overhead:.2}% ({} the {BUDGET}% budget)", if overhead < BUDGET { "within" } else { "over" });
+    assert!(overhead < BUDGET, "counting cost {overhead:.2}% of the throughput, over its {BUDGET}% budget");
+}
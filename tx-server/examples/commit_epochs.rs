@@ -0,0 +1,113 @@
+//! Measures what batching two-phase commits over epochs trades: messages
+//! between nodes against commit latency. Each epoch length gets a fresh
+//! three-node cluster on localhost and a fixed number of clients, each
+//! depositing into accounts of its own on every shard as fast as it can:
+//!
+//! ```text
+//! cargo run --release -p tx-server --example commit_epochs -- [seconds] [clients] [epoch ms...]
+//! ```
+//!
+//! An epoch of 0 sends every message right away.
+
+use tx_common::{
+    admin::{AdminRequest, AdminResponse},
+    stream::MessageStream
+};
+use tx_proto::{BalanceDiff, ClientRequest, ClientResponse};
+use tx_server::{
+    coordinator::{AdmissionPolicy, SilentReporter},
+    testing::{finish_transaction, local_config, start_cluster_with}
+};
+use tokio::{net::TcpStream, task::JoinSet, time::{Duration, Instant}};
+
+const NODES: [&str; 3] = ["A", "B", "C"];
+
+async fn start_cluster(epoch: Option<Duration>) -> Vec<u16> {
+    let config = local_config(&NODES);
+    // Every transaction is a connection, so the accept rate must not limit it
+    let admission = AdmissionPolicy { accepts_per_sec: u32::MAX, max_per_ip: usize::MAX, allowlist: None };
+    start_cluster_with(&config, |server| server
+        .with_commit_epoch(epoch)
+        .with_commit_reporter(SilentReporter)
+        .with_admission_policy(admission.clone())).await;
+
+    config.values().map(|node| node.port).collect()
+}
+
+/// The messages a node has sent its peers.
+async fn messages_sent(port: u16) -> u64 {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let mut stream = MessageStream::from_tcp_stream(stream);
+    stream.send(ClientRequest::Admin(AdminRequest::Status)).await.unwrap();
+    match stream.recv().await.unwrap().unwrap() {
+        ClientResponse::Admin(AdminResponse::Status(status)) => status.links.iter().map(|link| link.messages_sent).sum(),
+        resp => panic!("Unexpected response to a status request: {resp:?}")
+    }
+}
+
+async fn run(epoch: Option<Duration>, duration: Duration, clients: usize) -> (Vec<Duration>, u64) {
+    let ports = start_cluster(epoch).await;
+    let until = Instant::now() + duration;
+    let mut workers = JoinSet::new();
+    for client in 0..clients {
+        let port = ports[client % ports.len()];
+        workers.spawn(async move {
+            let mut latencies = Vec::new();
+            while Instant::now() < until {
+                let mut requests: Vec<_> = NODES
+                    .iter()
+                    .map(|shard| ClientRequest::WriteBalance(format!("{shard}.client{client}"), BalanceDiff(1)))
+                    .collect();
+                requests.push(ClientRequest::Commit);
+
+                let started = Instant::now();
+                if let ClientResponse::CommitOk = finish_transaction(port, requests).await {
+                    latencies.push(started.elapsed());
+                }
+            }
+            latencies
+        });
+    }
+
+    let mut latencies = Vec::new();
+    while let Some(worker) = workers.join_next().await {
+        latencies.extend(worker.unwrap());
+    }
+
+    let mut messages = 0;
+    for port in ports {
+        messages += messages_sent(port).await;
+    }
+
+    latencies.sort();
+    (latencies, messages)
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<u64> = std::env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("arguments must be numbers"))
+        .collect();
+    let seconds = args.first().copied().unwrap_or(5);
+    let clients = args.get(1).copied().unwrap_or(64) as usize;
+    let epochs = match args.get(2..) {
+        Some(epochs) if !epochs.is_empty() => epochs.to_vec(),
+        _ => vec![0, 1, 5]
+    };
+
+    println!("{clients} clients committing to every shard for {seconds}s per epoch length");
+    for epoch_ms in epochs {
+        let epoch = (epoch_ms > 0).then(|| Duration::from_millis(epoch_ms));
+        let (latencies, messages) = run(epoch, Duration::from_secs(seconds), clients).await;
+        let commits = latencies.len().max(1);
+        let percentile = |p: usize| latencies.get(commits * p / 100).copied().unwrap_or_default();
+        println!(
+            "epoch={epoch_ms}ms: commits={} throughput={:.0}/s peer messages per commit={:.2} latency p50={:?} p99={:?}",
+            latencies.len(),
+            latencies.len() as f64 / seconds.max(1) as f64,
+            messages as f64 / commits as f64,
+            percentile(50), percentile(99)
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Compares the throughput and abort rate of the two execution modes on a
+//! contended workload. Each mode gets a fresh three-node cluster on
+//! localhost, funded with a few hot accounts spread over the shards, and a
+//! fixed number of clients that transfer between pairs of them as fast as
+//! they can:
+//!
+//! ```text
+//! cargo run --release -p tx-server --example execution_modes -- [seconds] [clients] [accounts]
+//! ```
+
+use tx_proto::{BalanceDiff, ClientRequest, ClientResponse};
+use tx_server::{
+    coordinator::{AdmissionPolicy, ExecutionMode, SilentReporter},
+    testing::{finish_transaction, local_config, start_cluster_with}
+};
+use tokio::{task::JoinSet, time::{Duration, Instant}};
+
+const NODES: [&str; 3] = ["A", "B", "C"];
+
+#[derive(Default)]
+struct Outcomes {
+    committed: u64,
+    aborted: u64
+}
+
+async fn start_cluster(mode: ExecutionMode) -> Vec<u16> {
+    let config = local_config(&NODES);
+    // Every transaction is a connection, so the accept rate must not limit it
+    let admission = AdmissionPolicy { accepts_per_sec: u32::MAX, max_per_ip: usize::MAX, allowlist: None };
+    start_cluster_with(&config, |server| server
+        .with_execution_mode(mode)
+        .with_commit_reporter(SilentReporter)
+        .with_admission_policy(admission.clone())).await;
+
+    config.values().map(|node| node.port).collect()
+}
+
+fn account(i: usize) -> String {
+    format!("{}.hot{i}", NODES[i % NODES.len()])
+}
+
+async fn run(mode: ExecutionMode, duration: Duration, clients: usize, accounts: usize) -> Outcomes {
+    let ports = start_cluster(mode).await;
+    let mut funding: Vec<_> = (0..accounts).map(|i| ClientRequest::WriteBalance(account(i), BalanceDiff(1 << 40))).collect();
+    funding.push(ClientRequest::Commit);
+    assert!(matches!(finish_transaction(ports[0], funding).await, ClientResponse::CommitOk));
+
+    let until = Instant::now() + duration;
+    let mut workers = JoinSet::new();
+    for client in 0..clients {
+        let port = ports[client % ports.len()];
+        workers.spawn(async move {
+            let mut outcomes = Outcomes::default();
+            let mut next = client;
+            while Instant::now() < until {
+                next = next.wrapping_mul(31).wrapping_add(17);
+                let from = next % accounts;
+                let to = (from + 1 + next / accounts % (accounts - 1)) % accounts;
+                let requests = vec![
+                    ClientRequest::WriteBalance(account(from), BalanceDiff(-1)),
+                    ClientRequest::WriteBalance(account(to), BalanceDiff(1)),
+                    ClientRequest::Commit
+                ];
+                match finish_transaction(port, requests).await {
+                    ClientResponse::CommitOk => outcomes.committed += 1,
+                    _ => outcomes.aborted += 1
+                }
+            }
+            outcomes
+        });
+    }
+
+    let mut total = Outcomes::default();
+    while let Some(outcomes) = workers.join_next().await {
+        let outcomes = outcomes.unwrap();
+        total.committed += outcomes.committed;
+        total.aborted += outcomes.aborted;
+    }
+    total
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<usize> = std::env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("arguments must be numbers"))
+        .collect();
+    let seconds = args.first().copied().unwrap_or(5);
+    let clients = args.get(1).copied().unwrap_or(32);
+    let accounts = args.get(2).copied().unwrap_or(6).max(2);
+
+    println!("{clients} clients transferring between {accounts} accounts for {seconds}s per mode");
+    for mode in [ExecutionMode::Interactive, ExecutionMode::Deterministic] {
+        let outcomes = run(mode, Duration::from_secs(seconds as u64), clients, accounts).await;
+        let finished = (outcomes.committed + outcomes.aborted).max(1);
+        println!(
+            "{mode:?}: committed={} aborted={} abort rate={:.1}% throughput={:.0} commits/s",
+            outcomes.committed, outcomes.aborted,
+            100.0 * outcomes.aborted as f64 / finished as f64,
+            outcomes.committed as f64 / seconds.max(1) as f64
+        );
+    }
+}
@@ -0,0 +1,628 @@
+//! Predicts how a recorded workload would fare under different concurrency
+//! control policies, without running a cluster. The trace lists the
+//! operations of its transactions in the order they reached the shards, one
+//! per line as the name of the transaction followed by a tx-client command:
+//!
+//! ```text
+//! # Lines starting with '#' are comments
+//! t1 DEPOSIT A.x 10
+//! t2 BALANCE A.x
+//! t1 COMMIT
+//! t2 WITHDRAW A.x 5
+//! t2 COMMIT
+//! ```
+//!
+//! A node started with `TX_WORKLOAD_TRACE=<path>` records the transactions
+//! it coordinates in this format. `BALANCE`, `DEPOSIT`, `WITHDRAW`,
+//! `INCREMENT`, `COMMIT` and `ABORT` are understood. Transactions are ordered
+//! by their first operation, as their coordinators would have timestamped
+//! them, and a transaction the trace does not finish is aborted at its end,
+//! as if its client disconnected:
+//!
+//! ```text
+//! cargo run -p tx-server --example what_if -- <trace>
+//! ```
+//!
+//! Timestamp ordering and wound-wait are replayed against a fresh
+//! in-process shard, which implements them. The shard has no locks and no
+//! validation phase, so strict two-phase locking, with deadlock detection,
+//! wait-die or wound-wait, and optimistic concurrency control are replayed
+//! against a model of each instead. The models share the shard's rules on
+//! missing accounts and negative balances, but not its timing: an operation
+//! takes no time, and a transaction waits only for a lock.
+
+use tx_common::{admin::ConcurrencyMode, config::NodeId};
+use tx_server::sharding::{Abort, Shard, TransactionId};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, VecDeque}, sync::Arc};
+use tokio::{sync::mpsc, task::JoinHandle, time::Duration};
+
+/// How long a replayed operation may go without finishing before it counts
+/// as blocked. The clock is paused, so it only advances once every
+/// transaction is waiting and this never slows the replay down.
+const BLOCKED_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Policy {
+    /// One of the shard's own modes, replayed against the shard
+    Shard(ConcurrencyMode),
+    /// Strict two-phase locking: reads take shared locks and writes
+    /// exclusive ones, all held until the transaction finishes
+    Locking(Deadlocks),
+    /// Optimistic concurrency control: transactions run without locks and
+    /// commit only if no account they read was committed since
+    Optimistic
+}
+
+/// How two-phase locking keeps transactions from waiting on each other
+/// forever.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Deadlocks {
+    /// Any transaction waits, unless its wait would close a cycle of waits,
+    /// in which case it aborts
+    Detect,
+    /// An older transaction waits for a younger one, and a younger one
+    /// asking for an older one's lock aborts
+    WaitDie,
+    /// An older transaction asking for a younger one's lock aborts it, and
+    /// a younger one waits for an older one
+    WoundWait
+}
+
+const POLICIES: [Policy; 6] = [
+    Policy::Shard(ConcurrencyMode::TimestampOrdering),
+    Policy::Shard(ConcurrencyMode::WoundWait),
+    Policy::Locking(Deadlocks::Detect),
+    Policy::Locking(Deadlocks::WaitDie),
+    Policy::Locking(Deadlocks::WoundWait),
+    Policy::Optimistic
+];
+
+impl Policy {
+    fn name(&self) -> &'static str {
+        match self {
+            Policy::Shard(ConcurrencyMode::TimestampOrdering) => "timestamp ordering",
+            Policy::Shard(ConcurrencyMode::WoundWait) => "shard wound-wait",
+            Policy::Locking(Deadlocks::Detect) => "2PL with deadlock detection",
+            Policy::Locking(Deadlocks::WaitDie) => "2PL wait-die",
+            Policy::Locking(Deadlocks::WoundWait) => "2PL wound-wait",
+            Policy::Optimistic => "optimistic"
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Balance(String),
+    /// Adds to a balance after reading it, creating the account if allowed
+    Change { account_id: String, diff: i64, creates: bool },
+    Increment(String, i64),
+    Commit,
+    Abort
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Outcome {
+    Committed,
+    /// The client aborted the transaction
+    Aborted,
+    /// Aborted to keep the transaction from conflicting with another
+    Conflict,
+    /// Aborted to let an older transaction go ahead
+    Wounded,
+    NotFound,
+    NegativeBalance,
+    /// The trace ended before the transaction did
+    Unfinished
+}
+
+impl From<Abort<String>> for Outcome {
+    fn from(abort: Abort<String>) -> Self {
+        match abort {
+            Abort::OrderViolation(..) => Outcome::Conflict,
+            Abort::Wounded(_) => Outcome::Wounded,
+            Abort::ConsistencyCheckFailed(_) => Outcome::NegativeBalance,
+            _ => Outcome::NotFound
+        }
+    }
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+struct Report {
+    committed: u64,
+    aborted_by_client: u64,
+    conflicts: u64,
+    wounded: u64,
+    not_found: u64,
+    negative_balance: u64,
+    unfinished: u64
+}
+
+impl Report {
+    fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Committed => self.committed += 1,
+            Outcome::Aborted => self.aborted_by_client += 1,
+            Outcome::Conflict => self.conflicts += 1,
+            Outcome::Wounded => self.wounded += 1,
+            Outcome::NotFound => self.not_found += 1,
+            Outcome::NegativeBalance => self.negative_balance += 1,
+            Outcome::Unfinished => self.unfinished += 1
+        }
+    }
+
+    fn policy_aborts(&self) -> u64 {
+        self.conflicts + self.wounded + self.not_found + self.negative_balance
+    }
+
+    fn total(&self) -> u64 {
+        self.committed + self.aborted_by_client + self.policy_aborts() + self.unfinished
+    }
+}
+
+fn parse(trace: &str) -> Result<Vec<(String, Op)>, String> {
+    let amount = |amount: &str| amount.parse::<i64>().map_err(|e| format!("invalid amount {amount}: {e}"));
+    trace
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            let words: Vec<_> = line.split_ascii_whitespace().collect();
+            let op = match words[1..] {
+                ["BALANCE", account_id] => Op::Balance(account_id.into()),
+                ["DEPOSIT", account_id, diff] => Op::Change { account_id: account_id.into(), diff: amount(diff)?, creates: true },
+                ["WITHDRAW", account_id, diff] => Op::Change { account_id: account_id.into(), diff: -amount(diff)?, creates: false },
+                ["INCREMENT", account_id, diff] => Op::Increment(account_id.into(), amount(diff)?),
+                ["COMMIT"] => Op::Commit,
+                ["ABORT"] => Op::Abort,
+                _ => return Err(format!("line {}: expected a transaction and a command: {line}", i + 1))
+            };
+            Ok((words[0].to_string(), op))
+        })
+        .collect()
+}
+
+/// Runs one operation of a transaction, returning whether it finished the
+/// transaction.
+async fn execute(shard: &Shard<String, i64>, id: &TransactionId, op: Op) -> Result<Option<Outcome>, Abort<String>> {
+    match op {
+        Op::Balance(account_id) => shard.read(id, &account_id).await.map(|_| None),
+        Op::Change { account_id, diff, creates } => match shard.read(id, &account_id).await {
+            Ok(balance) => shard.write(id, account_id, balance + diff).await.map(|_| None),
+            Err(Abort::ObjectNotFound) if creates => shard.write(id, account_id, diff).await.map(|_| None),
+            Err(e) => Err(e)
+        },
+        Op::Increment(account_id, diff) => shard.increment(id, account_id, diff).await.map(|_| None),
+        Op::Commit => {
+            shard.check_commit(id).await?;
+            shard.commit(id).await.map(|_| Some(Outcome::Committed))
+        },
+        Op::Abort => {
+            shard.abort(id).await.unwrap();
+            Ok(Some(Outcome::Aborted))
+        }
+    }
+}
+
+/// Replays the operations of one transaction as they arrive, until it
+/// finishes or the trace ends.
+fn spawn_transaction(shard: Arc<Shard<String, i64>>, id: TransactionId) -> (mpsc::UnboundedSender<Op>, JoinHandle<Outcome>) {
+    let (ops, mut incoming) = mpsc::unbounded_channel();
+    let task = tokio::spawn(async move {
+        while let Some(op) = incoming.recv().await {
+            match execute(&shard, &id, op).await {
+                Ok(None) => continue,
+                Ok(Some(outcome)) => return outcome,
+                Err(abort) => {
+                    shard.abort(&id).await.unwrap();
+                    return abort.into()
+                }
+            }
+        }
+
+        shard.abort(&id).await.unwrap();
+        Outcome::Unfinished
+    });
+
+    (ops, task)
+}
+
+/// Replays a trace against the shard in one of its modes. Must run on a
+/// runtime with a paused clock.
+async fn replay_on_shard(trace: &[(String, Op)], mode: ConcurrencyMode) -> Report {
+    let shard = Arc::new(Shard::new(NodeId(0)));
+    shard.set_mode(mode);
+
+    let mut transactions = HashMap::new();
+    let mut tasks = Vec::new();
+    for (name, op) in trace {
+        let ops = transactions.entry(name.clone()).or_insert_with(|| {
+            let id = TransactionId::at(tasks.len() as u128 + 1, NodeId(0));
+            let (ops, task) = spawn_transaction(shard.clone(), id);
+            tasks.push(task);
+            ops
+        });
+        // A transaction that already finished ignores the rest of its trace
+        let _ = ops.send(op.clone());
+
+        // Let every transaction run until it waits on another or the trace
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    drop(transactions);
+
+    let mut report = Report::default();
+    for task in tasks {
+        match tokio::time::timeout(BLOCKED_AFTER, task).await {
+            Ok(outcome) => report.record(outcome.unwrap()),
+            Err(_) => report.unfinished += 1
+        }
+    }
+    report
+}
+
+/// The locks on an account. A transaction holding the exclusive lock may
+/// also be among the readers, if it read the account first.
+#[derive(Default)]
+struct Lock {
+    readers: BTreeSet<usize>,
+    writer: Option<usize>
+}
+
+/// A transaction replayed against a model, known by its age: the index of
+/// its first operation among those of the trace's transactions.
+#[derive(Default)]
+struct Transaction {
+    /// Operations that arrived but have not run, since it waits for a lock
+    pending: VecDeque<Op>,
+    /// The balances it wrote, applied when it commits
+    writes: BTreeMap<String, i64>,
+    /// Increments, which optimistic transactions apply to the balance as it
+    /// is when they commit, rather than as they read it
+    increments: Vec<(String, i64)>,
+    /// The version of each account it read, for optimistic validation
+    read_versions: HashMap<String, u64>,
+    /// The transactions whose locks it waits for
+    waits_for: BTreeSet<usize>,
+    outcome: Option<Outcome>
+}
+
+enum Step {
+    Ran,
+    Waits,
+    Finished(Outcome)
+}
+
+/// Replays a trace under two-phase locking or optimistic concurrency
+/// control. Operations run as soon as they arrive, unless their transaction
+/// waits for a lock, in which case they run once it is granted.
+struct Model {
+    policy: Policy,
+    /// The committed balance and version of each account
+    committed: HashMap<String, (i64, u64)>,
+    locks: HashMap<String, Lock>,
+    transactions: Vec<Transaction>,
+    /// How many transactions finished, each releasing its locks
+    finished: usize
+}
+
+impl Model {
+    fn new(policy: Policy) -> Self {
+        Self { policy, committed: HashMap::new(), locks: HashMap::new(), transactions: Vec::new(), finished: 0 }
+    }
+
+    fn replay(mut self, trace: &[(String, Op)]) -> Report {
+        let mut ages = HashMap::new();
+        for (name, op) in trace {
+            let age = *ages.entry(name.as_str()).or_insert_with(|| {
+                self.transactions.push(Transaction::default());
+                self.transactions.len() - 1
+            });
+            // A transaction that already finished ignores the rest of its trace
+            if self.transactions[age].outcome.is_none() {
+                self.transactions[age].pending.push_back(op.clone());
+                self.run_until_stuck();
+            }
+        }
+
+        // Clients that did not finish their transactions disconnect, which
+        // releases their locks to those still waiting
+        loop {
+            let idle: Vec<_> = (0..self.transactions.len())
+                .filter(|age| self.transactions[*age].outcome.is_none() && self.transactions[*age].pending.is_empty())
+                .collect();
+            if idle.is_empty() {
+                break;
+            }
+            for age in idle {
+                self.finish(age, Outcome::Unfinished);
+            }
+            self.run_until_stuck();
+        }
+
+        let mut report = Report::default();
+        for transaction in &self.transactions {
+            report.record(transaction.outcome.unwrap_or(Outcome::Unfinished));
+        }
+        report
+    }
+
+    /// Runs the pending operations of every transaction, oldest first, until
+    /// none can run.
+    fn run_until_stuck(&mut self) {
+        loop {
+            let (mut ran, finished) = (false, self.finished);
+            for age in 0..self.transactions.len() {
+                while self.transactions[age].outcome.is_none() {
+                    let Some(op) = self.transactions[age].pending.front().cloned() else { break };
+                    match self.step(age, op) {
+                        Step::Ran => {
+                            self.transactions[age].pending.pop_front();
+                            ran = true;
+                        },
+                        Step::Waits => break,
+                        Step::Finished(outcome) => self.finish(age, outcome)
+                    }
+                }
+            }
+
+            // Nothing changed, so nothing that waits can run either
+            if !ran && self.finished == finished {
+                return;
+            }
+        }
+    }
+
+    fn finish(&mut self, age: usize, outcome: Outcome) {
+        for lock in self.locks.values_mut() {
+            lock.readers.remove(&age);
+            if lock.writer == Some(age) {
+                lock.writer = None;
+            }
+        }
+
+        self.finished += 1;
+        let transaction = &mut self.transactions[age];
+        transaction.outcome = Some(outcome);
+        transaction.pending.clear();
+        transaction.waits_for.clear();
+    }
+
+    /// The balance of an account as a transaction sees it, if it exists.
+    fn balance(&self, age: usize, account_id: &str) -> Option<i64> {
+        self.transactions[age].writes.get(account_id).copied()
+            .or_else(|| self.committed.get(account_id).map(|(balance, _)| *balance))
+    }
+
+    fn version(&self, account_id: &str) -> u64 {
+        self.committed.get(account_id).map_or(0, |(_, version)| *version)
+    }
+
+    fn step(&mut self, age: usize, op: Op) -> Step {
+        let locked = match (&op, self.policy) {
+            (Op::Balance(account_id), Policy::Locking(deadlocks)) => self.lock(age, account_id, false, deadlocks),
+            (Op::Change { account_id, .. } | Op::Increment(account_id, _), Policy::Locking(deadlocks)) => self.lock(age, account_id, true, deadlocks),
+            _ => None
+        };
+        if let Some(step) = locked {
+            return step;
+        }
+
+        let optimistic = self.policy == Policy::Optimistic;
+        match op {
+            Op::Balance(account_id) => {
+                if self.balance(age, &account_id).is_none() {
+                    return Step::Finished(Outcome::NotFound);
+                }
+                self.read(age, account_id);
+            },
+            Op::Change { account_id, diff, creates } => {
+                let balance = match self.balance(age, &account_id) {
+                    Some(balance) => balance + diff,
+                    None if creates => diff,
+                    None => return Step::Finished(Outcome::NotFound)
+                };
+                self.read(age, account_id.clone());
+                self.transactions[age].writes.insert(account_id, balance);
+            },
+            Op::Increment(account_id, diff) => match self.balance(age, &account_id) {
+                None => return Step::Finished(Outcome::NotFound),
+                Some(_) if optimistic => self.transactions[age].increments.push((account_id, diff)),
+                Some(balance) => {
+                    self.transactions[age].writes.insert(account_id, balance + diff);
+                }
+            },
+            Op::Commit => return Step::Finished(self.commit(age)),
+            Op::Abort => return Step::Finished(Outcome::Aborted)
+        }
+
+        Step::Ran
+    }
+
+    /// Records the version of an account an optimistic transaction read
+    /// first, unless it wrote the account itself before.
+    fn read(&mut self, age: usize, account_id: String) {
+        let version = self.version(&account_id);
+        let transaction = &mut self.transactions[age];
+        if self.policy == Policy::Optimistic && !transaction.writes.contains_key(&account_id) {
+            transaction.read_versions.entry(account_id).or_insert(version);
+        }
+    }
+
+    fn commit(&mut self, age: usize) -> Outcome {
+        let transaction = &self.transactions[age];
+        if transaction.read_versions.iter().any(|(account_id, version)| self.version(account_id) != *version) {
+            return Outcome::Conflict;
+        }
+
+        let mut writes = transaction.writes.clone();
+        for (account_id, diff) in &transaction.increments {
+            let balance = writes.get(account_id).copied().or_else(|| self.committed.get(account_id).map(|(balance, _)| *balance));
+            writes.insert(account_id.clone(), balance.unwrap_or_default() + diff);
+        }
+        if writes.values().any(|balance| *balance < 0) {
+            return Outcome::NegativeBalance;
+        }
+
+        for (account_id, balance) in writes {
+            let version = self.version(&account_id) + 1;
+            self.committed.insert(account_id, (balance, version));
+        }
+        Outcome::Committed
+    }
+
+    /// Takes a lock for a transaction, returning how its operation goes if
+    /// it cannot run yet.
+    fn lock(&mut self, age: usize, account_id: &str, exclusive: bool, deadlocks: Deadlocks) -> Option<Step> {
+        let holders = |lock: &Lock| -> BTreeSet<usize> {
+            let mut holders: BTreeSet<_> = lock.writer.into_iter().filter(|holder| *holder != age).collect();
+            if exclusive {
+                holders.extend(lock.readers.iter().copied().filter(|holder| *holder != age));
+            }
+            holders
+        };
+
+        let mut conflicting = holders(self.locks.entry(account_id.to_string()).or_default());
+        match deadlocks {
+            Deadlocks::Detect if conflicting.iter().any(|holder| self.waits_on(*holder, age)) => return Some(Step::Finished(Outcome::Conflict)),
+            Deadlocks::WaitDie if conflicting.iter().any(|holder| *holder < age) => return Some(Step::Finished(Outcome::Conflict)),
+            Deadlocks::WoundWait => {
+                for younger in conflicting.iter().copied().filter(|holder| *holder > age).collect::<Vec<_>>() {
+                    self.finish(younger, Outcome::Wounded);
+                }
+                conflicting = holders(&self.locks[account_id]);
+            },
+            _ => ()
+        }
+
+        if !conflicting.is_empty() {
+            self.transactions[age].waits_for = conflicting;
+            return Some(Step::Waits);
+        }
+
+        self.transactions[age].waits_for.clear();
+        let lock = self.locks.get_mut(account_id).unwrap();
+        if exclusive {
+            lock.writer = Some(age);
+        } else {
+            lock.readers.insert(age);
+        }
+        None
+    }
+
+    /// Whether a transaction waits, directly or through others, for another.
+    fn waits_on(&self, waiter: usize, holder: usize) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut waiting = vec![waiter];
+        while let Some(age) = waiting.pop() {
+            if age == holder {
+                return true;
+            }
+            if seen.insert(age) {
+                waiting.extend(self.transactions[age].waits_for.iter().copied());
+            }
+        }
+        false
+    }
+}
+
+async fn simulate(trace: &[(String, Op)], policy: Policy) -> Report {
+    match policy {
+        Policy::Shard(mode) => replay_on_shard(trace, mode).await,
+        policy => Model::new(policy).replay(trace)
+    }
+}
+
+#[tokio::main(flavor = "current_thread", start_paused = true)]
+async fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("usage: what_if <trace>");
+        std::process::exit(1);
+    };
+    let trace = match std::fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|trace| parse(&trace)) {
+        Ok(trace) => trace,
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    println!("{} operations from {path}", trace.len());
+    for policy in POLICIES {
+        let report = simulate(&trace, policy).await;
+        let rate = 100.0 * report.policy_aborts() as f64 / report.total().max(1) as f64;
+        println!(
+            "{}: {} of {} committed, {rate:.1}% aborted by the policy ({} conflicts, {} wounded, {} not found, {} negative balance), {} aborted by the client, {} unfinished",
+            policy.name(), report.committed, report.total(), report.conflicts, report.wounded, report.not_found, report.negative_balance,
+            report.aborted_by_client, report.unfinished
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn outcomes(report: &Report) -> (u64, u64, u64) {
+        (report.committed, report.conflicts, report.wounded)
+    }
+
+    #[test]
+    fn test_trace_parsed() {
+        let trace = parse("# setup\nt1 DEPOSIT A.x 10\n\nt1 WITHDRAW A.x 3\nt2 INCREMENT A.x 1\nt2 BALANCE A.x\nt1 COMMIT\nt2 ABORT\n").unwrap();
+        let names: Vec<_> = trace.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["t1", "t1", "t2", "t2", "t1", "t2"]);
+        assert!(matches!(&trace[1].1, Op::Change { account_id, diff: -3, creates: false } if account_id == "A.x"));
+        assert!(matches!(trace[5].1, Op::Abort));
+
+        assert_eq!(parse("t1 DEPOSIT A.x ten").unwrap_err(), "invalid amount ten: invalid digit found in string");
+        assert_eq!(parse("t1 COMMIT\nt1 TRANSFER A.x B.y 1").unwrap_err(), "line 2: expected a transaction and a command: t1 TRANSFER A.x B.y 1");
+    }
+
+    /// Two transactions read an account and then both add to it, so at most
+    /// one of them may commit under any policy. Which one, and why the other
+    /// aborts, is what tells the policies apart.
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn test_lost_update_prevented_by_every_policy() {
+        let trace = parse("t0 DEPOSIT A.x 10\nt0 COMMIT\nt1 BALANCE A.x\nt2 BALANCE A.x\nt1 DEPOSIT A.x 1\nt2 DEPOSIT A.x 1\nt1 COMMIT\nt2 COMMIT").unwrap();
+        let expected = [
+            // The older writer arrives after the newer reader
+            (Policy::Shard(ConcurrencyMode::TimestampOrdering), (2, 1, 0)),
+            (Policy::Shard(ConcurrencyMode::WoundWait), (2, 0, 1)),
+            // t1 waits for t2's shared lock, and t2 closes the cycle
+            (Policy::Locking(Deadlocks::Detect), (2, 1, 0)),
+            (Policy::Locking(Deadlocks::WaitDie), (2, 1, 0)),
+            (Policy::Locking(Deadlocks::WoundWait), (2, 0, 1)),
+            // t2 finds that t1 committed the account it read
+            (Policy::Optimistic, (2, 1, 0))
+        ];
+        for (policy, outcome) in expected {
+            let report = simulate(&trace, policy).await;
+            assert_eq!(outcomes(&report), outcome, "{}: {report:?}", policy.name());
+            assert_eq!(report.total(), 3);
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn test_unfinished_and_missing_accounts_reported_by_every_policy() {
+        let trace = parse("t1 DEPOSIT A.x 10\nt2 BALANCE A.y\nt3 WITHDRAW A.z 1\nt4 DEPOSIT A.w 1\nt4 WITHDRAW A.w 2\nt4 COMMIT\nt5 DEPOSIT A.v 1\nt5 COMMIT").unwrap();
+        let expected = Report { committed: 1, not_found: 2, negative_balance: 1, unfinished: 1, ..Default::default() };
+        for policy in POLICIES {
+            assert_eq!(simulate(&trace, policy).await, expected, "{}", policy.name());
+        }
+    }
+
+    #[test]
+    fn test_waiting_transaction_runs_once_the_lock_is_released() {
+        // t2 is younger than the holder of the lock it asks for, so it waits
+        // and then sees t1's deposit, unless it dies under wait-die
+        let trace = parse("t1 DEPOSIT A.x 10\nt2 WITHDRAW A.x 5\nt2 COMMIT\nt1 COMMIT").unwrap();
+        for (deadlocks, outcome) in [(Deadlocks::Detect, (2, 0, 0)), (Deadlocks::WaitDie, (1, 1, 0)), (Deadlocks::WoundWait, (2, 0, 0))] {
+            let policy = Policy::Locking(deadlocks);
+            let report = Model::new(policy).replay(&trace);
+            assert_eq!(outcomes(&report), outcome, "{}: {report:?}", policy.name());
+        }
+
+        // Increments from optimistic transactions never conflict
+        let trace = parse("t0 DEPOSIT A.x 0\nt0 COMMIT\nt1 INCREMENT A.x 1\nt2 INCREMENT A.x 1\nt2 COMMIT\nt1 COMMIT\nt3 WITHDRAW A.x 2\nt3 COMMIT").unwrap();
+        assert_eq!(outcomes(&Model::new(Policy::Optimistic).replay(&trace)), (4, 0, 0));
+    }
+}
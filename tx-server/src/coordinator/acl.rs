@@ -0,0 +1,285 @@
+use tx_common::{AccountId, admin::{Access, AccountAcl}, config::NodeId, transaction_id::TransactionId};
+use tx_proto::peer::AclChange;
+use log::error;
+use std::{collections::BTreeMap, io, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+/// The access control lists a coordinator enforces, by account. An account
+/// without one is left to the namespace of its tenant, while one with an
+/// owner may only be touched by the owner and the tenants it was granted to,
+/// whatever its name.
+///
+/// Every change is stamped and sent to every other node, which keeps the
+/// newest change to each account, so that a client cannot get around a list
+/// by connecting to another coordinator. Removed lists are remembered too,
+/// so that a late change from before the removal does not bring one back.
+#[derive(Debug, Default)]
+pub(super) struct Acls {
+    acls: BTreeMap<AccountId, Entry>,
+    file: Option<Arc<AclFile>>
+}
+
+#[derive(Debug)]
+struct Entry {
+    stamp: TransactionId,
+    acl: Option<Acl>
+}
+
+#[derive(Debug)]
+struct Acl {
+    owner: String,
+    grants: BTreeMap<String, Access>
+}
+
+pub(super) type SharedAcls = Arc<Mutex<Acls>>;
+
+impl Acls {
+    /// Makes `owner` the owner of an account as part of the transaction
+    /// `stamp`, returning the change for the other nodes.
+    pub(super) fn set_owner(&mut self, account_id: AccountId, owner: String, stamp: TransactionId) -> AclChange {
+        let stamp = self.stamp_after(&account_id, stamp);
+        let entry = self.acls.entry(account_id.clone()).or_insert(Entry { stamp, acl: None });
+        entry.stamp = stamp;
+        match &mut entry.acl {
+            Some(acl) => acl.owner = owner,
+            None => entry.acl = Some(Acl { owner, grants: BTreeMap::new() })
+        }
+
+        self.change(&account_id)
+    }
+
+    /// Grants or, if `access` is `None`, revokes a tenant's access to an
+    /// account as part of the transaction `stamp`, returning the change for
+    /// the other nodes, or `None` if the account has no owner to grant it.
+    pub(super) fn grant(&mut self, account_id: &str, tenant: String, access: Option<Access>, stamp: TransactionId) -> Option<AclChange> {
+        let stamp = self.stamp_after(account_id, stamp);
+        let entry = self.acls.get_mut(account_id).filter(|entry| entry.acl.is_some())?;
+        let acl = entry.acl.as_mut().unwrap();
+        match access {
+            Some(access) => acl.grants.insert(tenant, access),
+            None => acl.grants.remove(&tenant)
+        };
+        entry.stamp = stamp;
+
+        Some(self.change(account_id))
+    }
+
+    /// Removes the list of an account as part of the transaction `stamp`,
+    /// returning the change for the other nodes, or `None` if it had none.
+    pub(super) fn remove(&mut self, account_id: &str, stamp: TransactionId) -> Option<AclChange> {
+        let stamp = self.stamp_after(account_id, stamp);
+        let entry = self.acls.get_mut(account_id).filter(|entry| entry.acl.is_some())?;
+        *entry = Entry { stamp, acl: None };
+
+        Some(self.change(account_id))
+    }
+
+    /// Applies a change made on another node, returning whether it was newer
+    /// than the one this node holds for the account.
+    pub(super) fn apply(&mut self, change: AclChange) -> bool {
+        if self.acls.get(&change.account_id).is_some_and(|entry| entry.stamp >= change.stamp) {
+            return false;
+        }
+
+        let acl = change.owner.map(|owner| Acl { owner, grants: change.grants.into_iter().collect() });
+        self.acls.insert(change.account_id, Entry { stamp: change.stamp, acl });
+        true
+    }
+
+    /// The latest change to every account, removals included.
+    pub(super) fn changes(&self) -> Vec<AclChange> {
+        self.acls.keys().map(|account_id| self.change(account_id)).collect()
+    }
+
+    /// A stamp newer than both `stamp` and the one the account's list has,
+    /// so that a change always replaces the list, even on a node whose clock
+    /// is behind that of the node that last changed it.
+    fn stamp_after(&self, account_id: &str, stamp: TransactionId) -> TransactionId {
+        match self.acls.get(account_id) {
+            Some(entry) if entry.stamp >= stamp => TransactionId::at(entry.stamp.timestamp() + 1, stamp.coordinator()),
+            _ => stamp
+        }
+    }
+
+    fn change(&self, account_id: &str) -> AclChange {
+        let (account_id, entry) = self.acls.get_key_value(account_id).unwrap();
+        AclChange {
+            stamp: entry.stamp,
+            account_id: account_id.clone(),
+            owner: entry.acl.as_ref().map(|acl| acl.owner.clone()),
+            grants: entry.acl.iter().flat_map(|acl| acl.grants.iter().map(|(tenant, access)| (tenant.clone(), *access))).collect()
+        }
+    }
+
+    /// Whether `tenant`, or a transaction that did not authenticate if
+    /// `None`, may access an account as it asks, or `None` if the account
+    /// has no access control list.
+    pub(super) fn allows(&self, account_id: &str, tenant: Option<&str>, access: Access) -> Option<bool> {
+        let acl = self.acls.get(account_id)?.acl.as_ref()?;
+        let allowed = match tenant {
+            Some(tenant) if tenant == acl.owner => true,
+            Some(tenant) => acl.grants.get(tenant).is_some_and(|granted| *granted >= access),
+            None => false
+        };
+
+        Some(allowed)
+    }
+
+    pub(super) fn get(&self, account_id: &str) -> Option<AccountAcl> {
+        let (account_id, entry) = self.acls.get_key_value(account_id)?;
+        let acl = entry.acl.as_ref()?;
+        Some(AccountAcl {
+            account_id: account_id.clone(),
+            owner: acl.owner.clone(),
+            grants: acl.grants.iter().map(|(tenant, access)| (tenant.clone(), *access)).collect()
+        })
+    }
+
+    pub(super) fn list(&self) -> Vec<AccountAcl> {
+        self.acls.keys().filter_map(|account_id| self.get(account_id)).collect()
+    }
+
+    /// Keeps the lists in `file` from now on.
+    pub(super) fn set_file(&mut self, file: AclFile) {
+        self.file = Some(Arc::new(file));
+    }
+}
+
+/// The list configured for an account when a node starts, stamped older
+/// than any change made while the cluster runs.
+pub(super) fn configured(acl: AccountAcl) -> AclChange {
+    AclChange {
+        stamp: TransactionId::default(NodeId(0)),
+        account_id: acl.account_id,
+        owner: Some(acl.owner),
+        grants: acl.grants
+    }
+}
+
+/// The file a node keeps its access control lists in, so that they survive
+/// a restart. It holds the latest change to every account as JSON and is
+/// rewritten whole on every change.
+#[derive(Debug)]
+pub(super) struct AclFile {
+    path: PathBuf,
+    /// Held while the file is written, so that a write never replaces a
+    /// newer one
+    writing: Mutex<()>
+}
+
+impl AclFile {
+    /// Opens the file at `path`, returning it with the changes stored there.
+    pub(super) fn open(path: impl AsRef<Path>) -> io::Result<(Self, Vec<AclChange>)> {
+        let path = path.as_ref().to_path_buf();
+        let changes = match super::id_store::read_checked(&path)? {
+            Some(contents) => serde_json::from_slice(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            None => Vec::new()
+        };
+
+        Ok((Self { path, writing: Mutex::new(()) }, changes))
+    }
+
+    fn write(&self, acls: &SharedAcls) -> io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        let contents = serde_json::to_string(&acls.lock().unwrap().changes())?;
+        super::id_store::replace_checked(&self.path, contents)
+    }
+}
+
+/// Writes the lists to their file, if they are kept in one, off the task
+/// that changed them.
+pub(super) fn persist(acls: &SharedAcls) {
+    let Some(file) = acls.lock().unwrap().file.clone() else { return };
+    let acls = acls.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = file.write(&acls) {
+            error!("Failed to persist the access control lists to {}: {e}", file.path.display());
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use super::*;
+
+    fn stamp(ts: u128, node_id: u32) -> TransactionId {
+        TransactionId::at(ts, NodeId(node_id))
+    }
+
+    #[test]
+    fn test_grants_checked_against_access() {
+        let mut acls = Acls::default();
+        assert_eq!(acls.allows("A.acme/x", Some("acme"), Access::ReadWrite), None);
+        assert!(acls.grant("A.acme/x", "globex".into(), Some(Access::Read), stamp(1, 0)).is_none());
+
+        acls.set_owner("A.acme/x".into(), "acme".into(), stamp(2, 0));
+        assert!(acls.grant("A.acme/x", "globex".into(), Some(Access::Read), stamp(3, 0)).is_some());
+        assert!(acls.grant("A.acme/x", "initech".into(), Some(Access::ReadWrite), stamp(4, 0)).is_some());
+        assert_eq!(acls.allows("A.acme/x", Some("acme"), Access::ReadWrite), Some(true));
+        assert_eq!(acls.allows("A.acme/x", Some("globex"), Access::Read), Some(true));
+        assert_eq!(acls.allows("A.acme/x", Some("globex"), Access::ReadWrite), Some(false));
+        assert_eq!(acls.allows("A.acme/x", Some("initech"), Access::ReadWrite), Some(true));
+        assert_eq!(acls.allows("A.acme/x", Some("umbrella"), Access::Read), Some(false));
+        assert_eq!(acls.allows("A.acme/x", None, Access::Read), Some(false));
+
+        // A new owner keeps the grants, and revoked tenants lose access
+        acls.set_owner("A.acme/x".into(), "globex".into(), stamp(5, 0));
+        assert!(acls.grant("A.acme/x", "initech".into(), None, stamp(6, 0)).is_some());
+        assert_eq!(acls.allows("A.acme/x", Some("globex"), Access::ReadWrite), Some(true));
+        assert_eq!(acls.allows("A.acme/x", Some("initech"), Access::Read), Some(false));
+        assert_eq!(acls.list(), vec![AccountAcl { account_id: "A.acme/x".into(), owner: "globex".into(), grants: vec![("globex".into(), Access::Read)] }]);
+
+        assert!(acls.remove("A.acme/x", stamp(7, 0)).is_some());
+        assert!(acls.remove("A.acme/x", stamp(8, 0)).is_none());
+        assert!(acls.list().is_empty());
+    }
+
+    #[test]
+    fn test_newest_change_wins_in_any_order() {
+        let mut origin = Acls::default();
+        let owned = origin.set_owner("A.x".into(), "acme".into(), stamp(10, 0));
+        let granted = origin.grant("A.x", "globex".into(), Some(Access::Read), stamp(20, 0)).unwrap();
+        let removed = origin.remove("A.x", stamp(30, 0)).unwrap();
+
+        let mut replica = Acls::default();
+        assert!(replica.apply(granted.clone()));
+        assert!(!replica.apply(owned));
+        assert_eq!(replica.get("A.x").map(|acl| acl.grants), Some(vec![("globex".into(), Access::Read)]));
+        assert!(replica.apply(removed));
+        assert!(!replica.apply(granted));
+        assert!(replica.list().is_empty());
+        assert_eq!(replica.changes(), origin.changes());
+
+        // A node whose clock is behind still replaces the list it holds
+        let late = replica.set_owner("A.x".into(), "initech".into(), stamp(5, 1));
+        assert_eq!(late.stamp, stamp(31, 1));
+        assert!(origin.apply(late));
+        assert_eq!(origin.get("A.x").map(|acl| acl.owner), Some("initech".into()));
+
+        // A configured list never replaces one changed at runtime
+        assert!(!origin.apply(configured(AccountAcl { account_id: "A.x".into(), owner: "acme".into(), grants: vec![] })));
+    }
+
+    #[test]
+    fn test_reopened_file_holds_the_latest_changes() {
+        let path = std::env::temp_dir().join(format!("tx-server-acls-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let (file, changes) = AclFile::open(&path).unwrap();
+        assert!(changes.is_empty());
+        let acls = SharedAcls::default();
+        acls.lock().unwrap().set_owner("A.x".into(), "acme".into(), stamp(1, 0));
+        acls.lock().unwrap().set_owner("A.y".into(), "acme".into(), stamp(2, 0));
+        acls.lock().unwrap().remove("A.y", stamp(3, 0));
+        file.write(&acls).unwrap();
+
+        let (_, changes) = AclFile::open(&path).unwrap();
+        assert_eq!(changes, acls.lock().unwrap().changes());
+
+        // Lists changed on disk behind the node's back are refused
+        let contents = fs::read_to_string(&path).unwrap().replace("acme", "evil");
+        fs::write(&path, contents).unwrap();
+        assert_eq!(AclFile::open(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
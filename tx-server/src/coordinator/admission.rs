@@ -0,0 +1,115 @@
+use std::{collections::{HashMap, HashSet}, net::IpAddr};
+use tokio::time::Instant;
+
+pub static MAX_CONNECTIONS_PER_IP: usize = 256;
+pub static MAX_ACCEPTS_PER_SEC: u32 = 2048;
+
+/// Limits on which connections the server accepts on its client port, so a
+/// flood of connections cannot exhaust its memory or transaction ids.
+#[derive(Clone, Debug)]
+pub struct AdmissionPolicy {
+    /// The number of client connections one address may hold open
+    pub max_per_ip: usize,
+    /// The number of connections accepted per second across all addresses.
+    /// Up to a second's worth may be accepted in a burst.
+    pub accepts_per_sec: u32,
+    /// The only addresses allowed to connect, if set. Peers that join after
+    /// the server started also connect on the client port, so they must be
+    /// listed too.
+    pub allowlist: Option<HashSet<IpAddr>>
+}
+
+impl Default for AdmissionPolicy {
+    fn default() -> Self {
+        Self { max_per_ip: MAX_CONNECTIONS_PER_IP, accepts_per_sec: MAX_ACCEPTS_PER_SEC, allowlist: None }
+    }
+}
+
+/// Why a connection was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Refusal {
+    NotAllowed,
+    TooManyFromIp,
+    RateLimited
+}
+
+/// Applies an `AdmissionPolicy`, tracking the open connections from every
+/// address and the accept rate as a token bucket.
+pub(super) struct Admission {
+    policy: AdmissionPolicy,
+    open: HashMap<IpAddr, usize>,
+    tokens: f64,
+    refilled_at: Instant
+}
+
+impl Admission {
+    pub(super) fn new(policy: AdmissionPolicy) -> Self {
+        let tokens = policy.accepts_per_sec as f64;
+        Self { policy, open: HashMap::new(), tokens, refilled_at: Instant::now() }
+    }
+
+    /// Decides whether to serve a connection just accepted from `ip`. An
+    /// admitted connection must be registered with `open` once it is known
+    /// to be a client.
+    pub(super) fn admit(&mut self, ip: IpAddr) -> Result<(), Refusal> {
+        if self.policy.allowlist.as_ref().is_some_and(|allowed| !allowed.contains(&ip)) {
+            return Err(Refusal::NotAllowed);
+        }
+
+        if self.open.get(&ip).copied().unwrap_or(0) >= self.policy.max_per_ip {
+            return Err(Refusal::TooManyFromIp);
+        }
+
+        let now = Instant::now();
+        let rate = self.policy.accepts_per_sec as f64;
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.refilled_at = now;
+
+        if self.tokens < 1.0 {
+            return Err(Refusal::RateLimited);
+        }
+
+        self.tokens -= 1.0;
+        Ok(())
+    }
+
+    pub(super) fn open(&mut self, ip: IpAddr) {
+        *self.open.entry(ip).or_default() += 1;
+    }
+
+    pub(super) fn close(&mut self, ip: IpAddr) {
+        if let Some(count) = self.open.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                self.open.remove(&ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_admission_limits() {
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let policy = AdmissionPolicy { max_per_ip: 1, accepts_per_sec: 2, allowlist: Some(HashSet::from([a, b])) };
+        let mut admission = Admission::new(policy);
+
+        assert_eq!(admission.admit("10.0.0.3".parse().unwrap()), Err(Refusal::NotAllowed));
+
+        assert_eq!(admission.admit(a), Ok(()));
+        admission.open(a);
+        assert_eq!(admission.admit(a), Err(Refusal::TooManyFromIp));
+
+        assert_eq!(admission.admit(b), Ok(()));
+        assert_eq!(admission.admit(b), Err(Refusal::RateLimited));
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        admission.close(a);
+        assert_eq!(admission.admit(a), Ok(()));
+    }
+}
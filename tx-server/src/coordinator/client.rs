@@ -1,12 +1,54 @@
 use tx_common::{
-    ClientRequest, ClientResponse, AccountId,
-    config::NodeId, stream::MessageStream
+    AccountId, Amount,
+    config::{NodeId, ShardMap}, stream::{MessageStream, StreamError}, query::{Query, QueryPart, QuerySummary},
+    admin::{AdminRequest, AdminResponse, AccountMemory, AccountStats, CommitRecord, CommitsSince, Decision, DecisionRecord, MemoryReport, NodeStatus, PauseStatus, TransactionMemory, Vote}
 };
-use super::{protocol::*, ServerHandle, AtomicShard, format_commit_result};
+use tx_proto::{topology::{capability, ClusterInfo, NodeInfo}, BalancePredicate, ClientRequest, ClientResponse, CommitVerbosity, IsolationLevel, SessionSettings, PROTOCOL_VERSION};
+use super::{protocol::*, acl, deterministic, routes::RouteCache, forwards::{ForwardRetry, PendingForwards}, layer::{RequestContext, RequestLayer}, ServerHandle, TimestampMode, TransactionLifetime, ExecutionMode, AtomicShard, before_deadline, evaluate_query, read_snapshot, committed_snapshot, shard_digest, SharedDecisionLog, SharedCompletion, SharedDrain, SharedPeers, SharedReporter, SharedVerification, SharedContention, SharedLabels, SharedTenants, SharedAcls, SharedMembership, AbortReport, CommitReport, abort_response};
 use crate::{sharding::{Abort, TransactionId}, BalanceDiff};
-use tokio::{sync::mpsc::*, net::TcpStream};
+use tokio::{sync::{mpsc::*, oneshot}, select, time::{sleep_until, Instant}};
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
 use log::{error, info, trace};
 
+/// The lifecycle of the transaction that a client handler is coordinating. 
+/// Once the client is told a transaction reached a final state, its next 
+/// request on the connection starts a new transaction.
+#[derive(Debug)]
+enum TransactionState {
+    /// The transaction accepts reads and writes
+    Active,
+    /// The client asked to commit and the two-phase commit is in progress
+    Preparing,
+    Committed,
+    /// The transaction was aborted, holding the response the client received
+    Aborted(ClientResponse)
+}
+
+/// The most rows of a query's result sent to the client in one message.
+const QUERY_BATCH_ROWS: usize = 256;
+
+/// A read of a balance that was started but whose result was not awaited yet.
+enum PendingRead {
+    /// The read was forwarded to the shard with the given forward, to be
+    /// abandoned at the given time if the shard's link is degraded
+    Forwarded(NodeId, ForwardId, ClientRequest, Option<Instant>),
+    Done(ClientResponse)
+}
+
+/// Builds the message forwarding `request` as part of `tx_id` for each forward
+/// it is sent in, to be abandoned once `budget` runs out.
+fn forward_request(tx_id: TransactionId, budget: Option<Duration>, request: ClientRequest) -> impl Fn(ForwardId) -> Forwarded {
+    move |fwd_id| Forwarded::Request(tx_id, fwd_id, budget, Box::new(request.clone()))
+}
+
+/// Commits `tx_id` on this node's shard and reports its result.
+async fn commit_local(shard: &AtomicShard, reporter: &SharedReporter, server_id: NodeId, tx_id: TransactionId) {
+    match shard.commit(&tx_id).await {
+        Ok(result) => reporter.report(CommitReport::new(server_id, tx_id, result)),
+        Err(e) => error!("FATAL ERROR: Failed to commit {tx_id}: {e:?}")
+    }
+}
+
 /// This struct contains all the data that a client handler task uses to process
 /// a transaction from a client. This struct contains data pertaining to the 
 /// shard this server represents and channels for communicating with the client 
@@ -20,6 +62,12 @@ pub(super) struct Client {
     /// coordinate with other shards in the case other shards own an object that 
     /// the client that this task is handling requests to read or write
     shard_ids: Vec<NodeId>,
+    /// Maps the accounts the client requests to the shards that own them
+    shards: Arc<ShardMap>,
+    /// The nodes in the cluster's config, for clients that ask about it
+    nodes: Arc<Vec<NodeInfo>>,
+    /// Where the accounts the transaction touched routed to
+    routes: RouteCache,
     /// A TCP stream for communicating with the client this task is handling
     stream: MessageStream, 
     /// An atomic pointer to the shard on this server
@@ -27,75 +75,148 @@ pub(super) struct Client {
     /// This channel is used to pass messages to the server task so that the 
     /// server task can forward them onto the associated shard
     forward_snd: UnboundedSender<ClientState>,
-    /// The channel used for the server task to pass responses back to this task
-    forward_rcv: UnboundedReceiver<ClientResponse>
+    /// The channel used for the server task to pass shard replies back to this
+    /// task, tagged with the forward they answer and the shard that sent them
+    forward_rcv: UnboundedReceiver<RoutedReply>,
+    /// The forwards sent on behalf of the transaction that await replies
+    forwards: PendingForwards,
+    /// How forwards to shards that have not joined yet are retried
+    retry: ForwardRetry,
+    /// The log of two-phase commit decisions made by this server
+    decisions: SharedDecisionLog,
+    /// The commits this node decided that are not yet applied everywhere
+    completion: SharedCompletion,
+    /// The peers the server is currently connected to
+    peers: SharedPeers,
+    /// How long operations on a peer whose link is degraded may take
+    degraded_time_box: Option<Duration>,
+    /// The layers every request passes through before it is handled
+    layers: Vec<Box<dyn RequestLayer>>,
+    /// When the transaction takes its id, whether it has answered a request
+    /// and whether it has read or written
+    timestamp_mode: TimestampMode,
+    answered: bool,
+    operated: bool,
+    /// How the cluster runs transactions and, if deterministically, the 
+    /// balance changes held until the commit
+    execution_mode: ExecutionMode,
+    writes: Vec<(AccountId, BalanceDiff)>,
+    /// The predicates the transaction asserted balances satisfy, checked 
+    /// again when it commits
+    assertions: Vec<(AccountId, BalancePredicate)>,
+    /// Where the transactions this server commits are reported
+    reporter: SharedReporter,
+    /// The work the server must finish before it can stop
+    drain: SharedDrain,
+    /// What the checks of the shard's invariants have found
+    verification: SharedVerification,
+    /// Chooses how the shard resolves conflicts
+    contention: SharedContention,
+    labels: SharedLabels,
+    tenants: SharedTenants,
+    acls: SharedAcls,
+    /// The node's view of the cluster's membership, if it gossips
+    membership: Option<SharedMembership>,
+    /// When the client stops waiting for the response to the request being
+    /// handled, if it set a deadline for it
+    deadline: Option<Instant>,
+    /// The lifetimes the server allows, when the transaction began, and when
+    /// it is aborted if it has not committed by then
+    lifetime: TransactionLifetime,
+    began: Instant,
+    expiry: Option<Instant>,
+    /// What the response to the transaction's commit says
+    verbosity: CommitVerbosity,
+    /// What the client settled with `Hello`, applied to every request after
+    settings: SessionSettings,
+    /// The state of the transaction this task is coordinating
+    state: TransactionState
 }
 
 impl Client {
-    pub(super) fn new(server_handle: ServerHandle, stream: TcpStream, forward_rcv: UnboundedReceiver<ClientResponse>) -> Self {
+    pub(super) fn new(server_handle: ServerHandle, stream: MessageStream, forward_rcv: UnboundedReceiver<RoutedReply>) -> Self {
+        let began = Instant::now();
         Client {
             shard: server_handle.shard,
             server_id: server_handle.server_id,
             transaction_id: server_handle.tx_id,
             shard_ids: server_handle.shard_ids,
+            shards: server_handle.shards,
+            nodes: server_handle.nodes,
+            routes: RouteCache::default(),
             forward_snd: server_handle.forwarding_handle,
-            stream: MessageStream::from_tcp_stream(stream),
-            forward_rcv
+            stream,
+            forward_rcv,
+            forwards: PendingForwards::default(),
+            retry: server_handle.retry,
+            decisions: server_handle.decisions,
+            completion: server_handle.completion,
+            peers: server_handle.peers,
+            degraded_time_box: server_handle.degraded_time_box,
+            layers: server_handle.layers,
+            timestamp_mode: server_handle.timestamp_mode,
+            answered: false,
+            operated: false,
+            execution_mode: server_handle.execution_mode,
+            writes: Vec::new(),
+            assertions: Vec::new(),
+            reporter: server_handle.reporter,
+            drain: server_handle.drain,
+            verification: server_handle.verification,
+            contention: server_handle.contention,
+            labels: server_handle.labels,
+            tenants: server_handle.tenants,
+            acls: server_handle.acls,
+            membership: server_handle.membership,
+            deadline: None,
+            lifetime: server_handle.lifetime,
+            began,
+            expiry: TransactionLifetime::expiry(began, server_handle.lifetime.initial()),
+            verbosity: CommitVerbosity::default(),
+            settings: SessionSettings::default(),
+            state: TransactionState::Active
         }
     }
 
-    fn extract_shard(&self, acct: &AccountId) -> TargetShard {
+    fn extract_shard(&mut self, acct: &AccountId) -> TargetShard {
         use TargetShard::*;
 
-        acct.chars()
-            .next()
-            .map_or_else(|| DoesNotExist, |shard_id| {
-                if self.shard_ids.contains(&shard_id) {
-                    if self.server_id == shard_id {
-                        Local
-                    } else {
-                        Remote(shard_id)
-                    }
-                } else {
-                    DoesNotExist
-                }
-            })
+        match self.routes.route(&self.shards, acct) {
+            Some(shard_id) if self.server_id == shard_id => Local,
+            Some(shard_id) => Remote(shard_id),
+            None => DoesNotExist
+        }
     }
 
-    async fn handle_balance_change_request(&mut self, account_id: AccountId, diff: BalanceDiff) -> Result<(), ()> {
-        let account_id_fmt = format!("{account_id}");
+    /// Adds `diff` to an account, creating it with the balance `diff` if it
+    /// does not exist, to expire `ttl` after the commit if given.
+    async fn handle_balance_change_request(&mut self, account_id: AccountId, diff: BalanceDiff, ttl: Option<Duration>) -> ClientResponse {
+        let account_id_fmt = account_id.to_string();
         let resp: ClientResponse = match self.extract_shard(&account_id) {
             TargetShard::Remote(shard_id) => {
                 trace!("Forwarding client request on {} to shard {shard_id}: BalanceChange({account_id}, {diff:?})", self.transaction_id);
-                let state = ClientState::Forward(
-                    ForwardTarget::Node(shard_id), 
-                    self.transaction_id, 
-                    ClientRequest::WriteBalance(account_id, diff)
-                );
-
-                if let Err(_) = self.forward_snd.send(state) {
-                    error!("Failed to pass message to the shard server...");
-                }
-
-                trace!("Blocking wait for shard {shard_id}'s response to client request on {}", self.transaction_id);
-                self.forward_rcv.recv().await.unwrap()
+                let request = match ttl {
+                    Some(ttl) => ClientRequest::WriteBalanceWithTtl(account_id, diff, ttl),
+                    None => ClientRequest::WriteBalance(account_id, diff)
+                };
+                self.forward_to(shard_id, request).await
             },
             TargetShard::Local => {
                 trace!("Handling client request on {} locally: BalanceChange({account_id}, {diff:?})", self.transaction_id);
-                match self.shard.read(&self.transaction_id, &account_id).await {
-                    Ok(balance) => match self.shard.write(&self.transaction_id, account_id, balance + diff.0).await {
-                        Ok(_) => ClientResponse::Ok,
-                        Err(Abort::ObjectNotFound) => ClientResponse::AbortedNotFound,
-                        Err(_) => ClientResponse::Aborted
-                    },
-                    Err(Abort::ObjectNotFound) => 
-                        match self.shard.write(&self.transaction_id, account_id, diff.0).await {
+                before_deadline(self.deadline, async {
+                    match self.shard.read(&self.transaction_id, &account_id).await {
+                        Ok(balance) => match self.shard.write(&self.transaction_id, account_id, balance + diff.0).await {
                             Ok(_) => ClientResponse::Ok,
-                            Err(Abort::ObjectNotFound) => ClientResponse::AbortedNotFound,
-                            Err(_) => ClientResponse::Aborted
-                        }
-                    Err(_) => ClientResponse::Aborted
-                }
+                            Err(e) => abort_response(e)
+                        },
+                        Err(Abort::ObjectNotFound) => 
+                            match self.shard.write_expiring(&self.transaction_id, account_id, diff.0, ttl).await {
+                                Ok(_) => ClientResponse::Ok,
+                                Err(e) => abort_response(e)
+                            }
+                        Err(e) => abort_response(e)
+                    }
+                }).await
             },
             TargetShard::DoesNotExist => {
                 trace!("Unable to handle client request on {}: BalanceChange({account_id}, {diff:?}) -- account does not exist", self.transaction_id);
@@ -104,161 +225,1217 @@ impl Client {
         };
 
         trace!("Client request on {}: BalanceChange({account_id_fmt}, {diff:?}) => {resp:?}", self.transaction_id);
-        let ret_val = if resp.is_err() {
-            trace!("Aborting transaction {}...", self.transaction_id);
-            self.do_abort().await;
-            Err(())
-        } else {
-            Ok(())
+        resp
+    }
+
+    /// Adds `diff` to an account without reading it, creating the account
+    /// with the balance `diff` if it does not exist.
+    async fn handle_increment_request(&mut self, account_id: AccountId, diff: BalanceDiff) -> ClientResponse {
+        let resp = match self.extract_shard(&account_id) {
+            TargetShard::Remote(shard_id) => {
+                trace!("Forwarding client request on {} to shard {shard_id}: Increment({account_id}, {diff:?})", self.transaction_id);
+                self.forward_to(shard_id, ClientRequest::Increment(account_id, diff)).await
+            },
+            TargetShard::Local => {
+                trace!("Handling client request on {} locally: Increment({account_id}, {diff:?})", self.transaction_id);
+                before_deadline(self.deadline, async {
+                    match self.shard.increment(&self.transaction_id, account_id, diff.0).await {
+                        Ok(_) => ClientResponse::Ok,
+                        Err(e) => abort_response(e)
+                    }
+                }).await
+            },
+            TargetShard::DoesNotExist => {
+                trace!("Unable to handle client request on {}: Increment({account_id}, {diff:?}) -- account does not exist", self.transaction_id);
+                ClientResponse::AbortedNotFound
+            }
         };
 
-        if let Err(e) = self.stream.send(resp).await {
-            error!("Failed to send response to the client: {e:?}");
+        trace!("Client request on {} to increment => {resp:?}", self.transaction_id);
+        resp
+    }
+
+    /// Reads the committed balance of an account, at most `max_staleness` 
+    /// stale, without waiting on the transactions writing it.
+    async fn handle_stale_balance_request(&mut self, account_id: AccountId, max_staleness: Duration) -> ClientResponse {
+        match self.extract_shard(&account_id) {
+            TargetShard::Remote(shard_id) => {
+                trace!("Forwarding client request on {} to shard {shard_id}: ReadBalanceStale({account_id}, {max_staleness:?})", self.transaction_id);
+                self.forward_to(shard_id, ClientRequest::ReadBalanceStale(account_id, max_staleness)).await
+            },
+            TargetShard::Local => match self.shard.read_stale(&account_id, max_staleness).await {
+                Ok(value) => ClientResponse::Value(account_id, value),
+                Err(e) => abort_response(e)
+            },
+            TargetShard::DoesNotExist => ClientResponse::AbortedNotFound
         }
+    }
 
-        ret_val
+    async fn handle_balance_request(&mut self, account_id: AccountId, if_exists: bool) -> ClientResponse {
+        let read = self.start_balance_request(account_id, if_exists).await;
+        self.finish_read(read).await
     }
 
-    async fn handle_balance_request(&mut self, account_id: AccountId) -> Result<(), ()> {
-        let account_id_fmt = format!("{account_id}");
-        let resp: ClientResponse = match self.extract_shard(&account_id) {
+    /// Starts to read a balance, reading it right away if it is local and
+    /// forwarding the read without waiting for the reply otherwise. If 
+    /// `if_exists`, a missing account is answered with `NotFound` rather than
+    /// aborting the transaction.
+    async fn start_balance_request(&mut self, account_id: AccountId, if_exists: bool) -> PendingRead {
+        match self.extract_shard(&account_id) {
             TargetShard::Remote(shard_id) => {
                 trace!("Forwarding client request on {} to shard {shard_id}: Balance({account_id})", self.transaction_id);
-                let state = ClientState::Forward(
-                    ForwardTarget::Node(shard_id), 
-                    self.transaction_id, 
-                    ClientRequest::ReadBalance(account_id)
-                );
-
-                if let Err(_) = self.forward_snd.send(state) {
-                    error!("Failed to pass message to the shard server...");
-                }
-
-                trace!("Blocking wait for shard {shard_id}'s response to client request on {}", self.transaction_id);
-                self.forward_rcv.recv().await.unwrap()
+                let request = match if_exists {
+                    true => ClientRequest::ReadBalanceIfExists(account_id),
+                    false => ClientRequest::ReadBalance(account_id)
+                };
+                let time_box = self.time_box(shard_id);
+                let fwd_id = self.send_forward(ForwardTarget::Node(shard_id), self.remaining_until(time_box), request.clone());
+                PendingRead::Forwarded(shard_id, fwd_id, request, time_box)
             },
             TargetShard::Local => {
                 trace!("Handling client request on {} locally: Balance({account_id})", self.transaction_id);
-                match self.shard.read(&self.transaction_id, &account_id).await {
-                    Ok(value) => ClientResponse::Value(account_id, value),
-                    Err(Abort::ObjectNotFound) => ClientResponse::AbortedNotFound,
-                    Err(_) => ClientResponse::Aborted
-                }
+                let resp = before_deadline(self.deadline, async {
+                    let read = match if_exists {
+                        true => self.shard.read_if_exists(&self.transaction_id, &account_id).await,
+                        false => self.shard.read(&self.transaction_id, &account_id).await.map(Some)
+                    };
+                    match read {
+                        Ok(Some(value)) => ClientResponse::Value(account_id.clone(), value),
+                        Ok(None) => ClientResponse::NotFound(account_id.clone()),
+                        Err(e) => abort_response(e)
+                    }
+                }).await;
+
+                trace!("Client request on {}: Balance({account_id}) => {resp:?}", self.transaction_id);
+                PendingRead::Done(resp)
             },
             TargetShard::DoesNotExist => {
                 trace!("Unable to handle client request on {}: Balance({account_id}) -- account does not exist", self.transaction_id);
-                ClientResponse::AbortedNotFound
+                match if_exists {
+                    true => PendingRead::Done(ClientResponse::NotFound(account_id)),
+                    false => PendingRead::Done(ClientResponse::AbortedNotFound)
+                }
             }
-        };
+        }
+    }
 
-        trace!("Client request on {}: Balance({account_id_fmt}) => {resp:?}", self.transaction_id);
-        let ret_val = if resp.is_err() {
-            trace!("Aborting transaction {}...", self.transaction_id);
-            self.do_abort().await;
-            Err(())
-        } else {
-            Ok(())
+    async fn finish_read(&mut self, read: PendingRead) -> ClientResponse {
+        match read {
+            PendingRead::Forwarded(shard_id, fwd_id, request, time_box) => {
+                let resp = self.await_boxed_response(shard_id, fwd_id, request, time_box).await;
+                trace!("Client request on {} forwarded to shard {shard_id} => {resp:?}", self.transaction_id);
+                resp
+            },
+            PendingRead::Done(resp) => resp
+        }
+    }
+
+    /// Reads a balance and checks it against a predicate, which is checked 
+    /// again against the balance the transaction would commit.
+    async fn handle_assert_request(&mut self, account_id: AccountId, predicate: BalancePredicate) -> ClientResponse {
+        match self.handle_balance_request(account_id.clone(), false).await {
+            ClientResponse::Value(_, balance) if predicate.holds(balance) => {
+                self.assertions.push((account_id, predicate));
+                ClientResponse::Ok
+            },
+            ClientResponse::Value(..) => ClientResponse::AbortedAssertionFailed(account_id, predicate),
+            resp => resp
+        }
+    }
+
+    /// Checks every assertion against the balance the transaction would 
+    /// commit, counting its own writes, returning the first failure. The 
+    /// accounts are read at once, like the two of a swap.
+    async fn check_assertions(&mut self) -> Result<(), ClientResponse> {
+        let assertions = std::mem::take(&mut self.assertions);
+        let mut reads = Vec::with_capacity(assertions.len());
+        for (account_id, _) in &assertions {
+            reads.push(self.start_balance_request(account_id.clone(), false).await);
+        }
+
+        let mut failure = None;
+        for (read, (account_id, predicate)) in reads.into_iter().zip(assertions) {
+            let resp = match self.finish_read(read).await {
+                ClientResponse::Value(_, balance) if predicate.holds(balance) => continue,
+                ClientResponse::Value(..) => ClientResponse::AbortedAssertionFailed(account_id, predicate),
+                resp => resp
+            };
+            failure.get_or_insert(resp);
+        }
+
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// Swaps two balances by reading both accounts and then writing the 
+    /// difference to each, so each step is routed like any other read or 
+    /// write and the first failure is returned.
+    async fn handle_swap_request(&mut self, first: AccountId, second: AccountId) -> ClientResponse {
+        // Both reads are in flight at once, so a swap across two remote shards
+        // waits for one round trip rather than two. Both are waited for even 
+        // if the first fails, so no read is left running on a shard once the
+        // transaction is aborted.
+        let first_read = self.start_balance_request(first.clone(), false).await;
+        let second_read = self.start_balance_request(second.clone(), false).await;
+        let first_resp = self.finish_read(first_read).await;
+        let second_resp = self.finish_read(second_read).await;
+
+        let (first_balance, second_balance) = match (first_resp, second_resp) {
+            (ClientResponse::Value(_, first_balance), ClientResponse::Value(_, second_balance)) => (first_balance, second_balance),
+            (ClientResponse::Value(..), resp) | (resp, _) => return resp
         };
 
-        if let Err(e) = self.stream.send(resp).await {
-            error!("Failed to send response to the client: {e:?}");
+        let diff = second_balance - first_balance;
+        match self.handle_balance_change_request(first, BalanceDiff(diff), None).await {
+            ClientResponse::Ok => self.handle_balance_change_request(second, BalanceDiff(-diff), None).await,
+            resp => resp
         }
+    }
 
-        ret_val
+    /// What is left of the deadline of the request being handled, if any.
+    fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
     }
 
-    async fn do_abort(&mut self) {
-        self.shard.abort(&self.transaction_id).await.unwrap();
-        let abort_req = ClientState::Forward(
-            ForwardTarget::Broadcast, 
-            self.transaction_id, 
-            ClientRequest::Abort
-        );
+    /// When an operation on a shard is abandoned if the link to the shard is
+    /// degraded: after the server's time box for degraded links, or at the
+    /// request's deadline if that comes first. `None` if the link is healthy.
+    fn time_box(&self, shard_id: NodeId) -> Option<Instant> {
+        let time_box = self.degraded_time_box?;
+        let degraded = self.peers
+            .lock()
+            .unwrap()
+            .get(&shard_id)
+            .is_some_and(|(_, health)| health.lock().unwrap().degraded());
+        let boxed = Instant::now() + time_box;
+        degraded.then(|| self.deadline.map_or(boxed, |deadline| deadline.min(boxed)))
+    }
+
+    /// What is left until an operation's time box, or of the deadline of the
+    /// request being handled if it has none.
+    fn remaining_until(&self, time_box: Option<Instant>) -> Option<Duration> {
+        match time_box {
+            Some(time_box) => Some(time_box.saturating_duration_since(Instant::now())),
+            None => self.remaining()
+        }
+    }
 
-        if let Err(_) = self.forward_snd.send(abort_req) {
-            error!("Unable to forward abort request to shard server")
+    /// Asks the server to forward a request, returning the id its replies 
+    /// will carry. The shards it reaches abandon it once `budget` runs out.
+    fn send_forward(&mut self, target: ForwardTarget, budget: Option<Duration>, request: ClientRequest) -> ForwardId {
+        let fwd_id = self.forwards.start();
+        self.send_message(target, Forwarded::Request(self.transaction_id, fwd_id, budget, Box::new(request)));
+        fwd_id
+    }
+
+    fn send_message(&self, target: ForwardTarget, msg: Forwarded) {
+        if self.forward_snd.send(ClientState::Forward(target, msg)).is_err() {
+            error!("Failed to pass message to the shard server...");
         }
+    }
 
-        let mut abort_count = 1; // we aborted on this shard already!
-        while abort_count < self.shard_ids.len() {
-            if let ClientResponse::Aborted = self.forward_rcv.recv().await.unwrap() {
-                abort_count += 1;
-            } else {
-                error!("Did not receive an abort in response!")
+    /// Waits until `expected` replies to a forward arrived. Replies to other
+    /// forwards that arrive in the meantime are kept until they are awaited.
+    async fn await_replies(&mut self, fwd_id: ForwardId, expected: usize) -> ShardReplies {
+        loop {
+            if let Some(replies) = self.forwards.take(fwd_id, expected) {
+                return replies;
+            }
+
+            let (reply_id, shard_id, reply) = self.forward_rcv.recv().await.unwrap();
+            trace!("{} received a reply to forward {reply_id} from shard {shard_id}", self.transaction_id);
+            if !self.forwards.deliver(reply_id, shard_id, reply) {
+                error!("Dropping reply from shard {shard_id} for {}: forward {reply_id} is not pending", self.transaction_id);
+            }
+        }
+    }
+
+    /// Waits for a shard's response to `request`, which was forwarded to it
+    /// as `fwd_id`. While the shard has not joined, the request never reached
+    /// it, so it is forwarded again after a backoff until the retry budget 
+    /// runs out or the request's deadline would pass before the next retry.
+    async fn await_response(&mut self, shard_id: NodeId, mut fwd_id: ForwardId, request: ClientRequest) -> ClientResponse {
+        let mut retries = 0;
+        loop {
+            trace!("Blocking wait for shard {shard_id}'s response to client request on {}", self.transaction_id);
+            match self.await_replies(fwd_id, 1).await.pop().unwrap() {
+                (_, ShardReply::Response(resp)) => return resp,
+                (_, ShardReply::Unreachable) if retries < self.retry.max_retries && self.remaining().is_none_or(|left| left > self.retry.backoff) => {
+                    retries += 1;
+                    trace!("Shard {shard_id} has not joined: retrying request on {} ({retries}/{})", self.transaction_id, self.retry.max_retries);
+                    tokio::time::sleep(self.retry.backoff).await;
+                    fwd_id = self.send_forward(ForwardTarget::Node(shard_id), self.remaining(), request.clone());
+                },
+                (_, ShardReply::Unreachable) => return ClientResponse::AbortedUnavailable(shard_id),
+                (_, reply) => {
+                    error!("Expected a response from shard {shard_id} for {} - got {reply:?}", self.transaction_id);
+                    return ClientResponse::Aborted;
+                }
+            }
+        }
+    }
+
+    /// Forwards a request to the shard that owns the data it operates on and 
+    /// waits for that shard's response. 
+    async fn forward_to(&mut self, shard_id: NodeId, request: ClientRequest) -> ClientResponse {
+        let time_box = self.time_box(shard_id);
+        let fwd_id = self.send_forward(ForwardTarget::Node(shard_id), self.remaining_until(time_box), request.clone());
+        self.await_boxed_response(shard_id, fwd_id, request, time_box).await
+    }
+
+    /// Waits for a shard's response like `await_response`, giving up once
+    /// `time_box` passes if there is one. The shard was given the same time,
+    /// so it abandons the request too, and its late reply is dropped.
+    async fn await_boxed_response(&mut self, shard_id: NodeId, fwd_id: ForwardId, request: ClientRequest, time_box: Option<Instant>) -> ClientResponse {
+        match time_box {
+            Some(_) => {
+                let resp = before_deadline(time_box, self.await_response(shard_id, fwd_id, request)).await;
+                if matches!(resp, ClientResponse::AbortedDeadlineExceeded) {
+                    self.forwards.abandon(fwd_id);
+                }
+                resp
+            },
+            None => self.await_response(shard_id, fwd_id, request).await
+        }
+    }
+
+    /// Sends the message `message` builds for a forward to each of the given
+    /// shards and waits for all of their replies. Shards that have not joined
+    /// yet reply `Unreachable`. Features that need several shards to act on a
+    /// transaction build on this rather than counting replies themselves.
+    async fn for_each_shard<M>(&mut self, shard_ids: &[NodeId], message: M) -> ShardReplies 
+    where 
+        M: Fn(ForwardId) -> Forwarded
+    {
+        let fwd_id = self.forwards.start();
+        self.send_message(ForwardTarget::Nodes(shard_ids.to_vec()), message(fwd_id));
+        let (unrelayed, mut replies): (ShardReplies, ShardReplies) = self.await_replies(fwd_id, shard_ids.len())
+            .await
+            .into_iter()
+            .partition(|(_, reply)| matches!(reply, ShardReply::Unrelayed));
+
+        // A node relaying the request along a spanning tree may not have 
+        // joined a shard this node has, so those are asked directly
+        if !unrelayed.is_empty() {
+            let shard_ids = unrelayed.into_iter().map(|(shard_id, _)| shard_id).collect::<Vec<_>>();
+            trace!("Request for {} was not relayed to {shard_ids:?}: forwarding directly", self.transaction_id);
+            let fwd_id = self.forwards.start();
+            for shard_id in &shard_ids {
+                self.send_message(ForwardTarget::Node(*shard_id), message(fwd_id));
+            }
+            replies.extend(self.await_replies(fwd_id, shard_ids.len()).await);
+        }
+
+        trace!("Request for {} to {shard_ids:?} received all {} replies", self.transaction_id, replies.len());
+        replies
+    }
+
+    /// The other nodes the transaction's commit or abort involves: those 
+    /// hosting a shard it touched, and every witness, which learns every 
+    /// decision.
+    fn commit_scope(&self) -> Vec<NodeId> {
+        let participants = self.routes.participants();
+        self.shard_ids
+            .iter()
+            .filter(|id| **id != self.server_id && (participants.contains(id) || !self.shards.hosts_shard(**id)))
+            .copied()
+            .collect()
+    }
+
+    /// Aborts the transaction on this node's shard and every other node its
+    /// commit would involve. A node the transaction never reached records 
+    /// the abort without visiting its accounts.
+    async fn do_abort(&mut self) {
+        self.shard.abort(&self.transaction_id).await.unwrap();
+        let scope = self.commit_scope();
+        for (shard_id, reply) in self.for_each_shard(&scope, forward_request(self.transaction_id, None, ClientRequest::Abort)).await {
+            if !matches!(reply, ShardReply::Response(ClientResponse::Aborted) | ShardReply::Unreachable) {
+                error!("Did not receive an abort in response from shard {shard_id}: {reply:?}")
             }
         }
     }
 
     async fn do_commit(&self) {
-        match self.shard.commit(&self.transaction_id).await {
-            Ok(result) => format_commit_result(result),
-            Err(e) => error!("FATAL ERROR: Failed to commit {}: {e:?}", self.transaction_id)
+        commit_local(&self.shard, &self.reporter, self.server_id, self.transaction_id).await
+    }
+
+    /// Commits the transaction on every shard in `scope` and this node's, 
+    /// returning the balances it left the accounts it wrote with, as each 
+    /// shard acknowledges its commit.
+    async fn commit_returning_values(&mut self, scope: Vec<NodeId>) -> Vec<(AccountId, Amount)> {
+        let (shard, reporter, server_id, tx_id) = (self.shard.clone(), self.reporter.clone(), self.server_id, self.transaction_id);
+        let local = async move {
+            let values = shard.tentative_writes(&tx_id).await;
+            commit_local(&shard, &reporter, server_id, tx_id).await;
+            values
+        };
+        let commit = move |fwd_id| Forwarded::DoCommit(tx_id, Some(fwd_id));
+        let (mut values, replies) = tokio::join!(local, self.for_each_shard(&scope, commit));
+        for (shard_id, reply) in replies {
+            match reply {
+                ShardReply::Response(ClientResponse::CommitOkWithValues(written)) => values.extend(written),
+                // Like a vote, an unreachable shard holds nothing the 
+                // transaction wrote
+                ShardReply::Unreachable => (),
+                reply => error!("Expected the values {} wrote on shard {shard_id} - got {reply:?}", self.transaction_id)
+            }
         }
+
+        values.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        values
+    }
+
+    fn record_decision(&self, participants: Vec<NodeId>, votes: Vec<(NodeId, Vote)>, decision: Decision, started: Instant) {
+        self.decisions.lock().unwrap().record(DecisionRecord {
+            tx_id: self.transaction_id,
+            participants,
+            votes,
+            decision,
+            prepare_duration: started.elapsed(),
+            reason: None
+        });
+    }
+
+    /// Records the client's abort of the transaction and its reason, with
+    /// the nodes the abort involves as the participants.
+    fn record_client_abort(&self, reason: String) {
+        let reason = tx_proto::cap_abort_reason(reason);
+        info!("Client aborted {}: {reason}", self.transaction_id);
+        self.reporter.report_abort(AbortReport { coordinator: self.server_id, tx_id: self.transaction_id, reason: reason.clone() });
+        let mut participants = self.commit_scope();
+        participants.push(self.server_id);
+        participants.sort();
+        self.decisions.lock().unwrap().record(DecisionRecord {
+            tx_id: self.transaction_id,
+            participants,
+            votes: vec![],
+            decision: Decision::Abort,
+            prepare_duration: Duration::ZERO,
+            reason: Some(reason)
+        });
     }
 
-    async fn handle_commit_request(&mut self) {
-        if let Err(_) = self.shard.check_commit(&self.transaction_id).await {
-            info!("Consistency check on local shard failed: aborting...");
+    async fn handle_commit_request(&mut self) -> ClientResponse {
+        self.state = TransactionState::Preparing;
+        let started = Instant::now();
+        let local_vote = match self.check_assertions().await {
+            Ok(()) => match self.shard.check_commit(&self.transaction_id).await {
+                Ok(_) if self.drain.is_draining() => Err(ClientResponse::AbortedDraining),
+                Ok(_) => Ok(()),
+                Err(e) => Err(abort_response(e))
+            },
+            Err(resp) => Err(resp)
+        };
+
+        if let Err(resp) = local_vote {
+            info!("Local shard cannot commit {}: aborting...", self.transaction_id);
+            let mut participants = self.commit_scope();
+            participants.push(self.server_id);
+            participants.sort();
+            self.record_decision(participants, vec![(self.server_id, Vote::CannotCommit)], Decision::Abort, started);
             self.do_abort().await;
-            if let Err(e) = self.stream.send(ClientResponse::Aborted).await {
-                error!("Failed to send response to the client: {e:?}");
+            return resp;
+        }
+
+        // The first phase: every other shard the transaction touched votes on
+        // whether it can commit
+        let scope = self.commit_scope();
+        let mut participants = scope.clone();
+        participants.push(self.server_id);
+        participants.sort();
+        let mut votes = vec![(self.server_id, Vote::ReadyToCommit)];
+        let mut abort_resp = None;
+        for (shard_id, reply) in self.for_each_shard(&scope, forward_request(self.transaction_id, None, ClientRequest::Commit)).await {
+            match reply {
+                // A witness holds nothing for the transaction and has nothing
+                // to vote on. A shard it touched but whose link was lost since
+                // may hold its writes, so the transaction cannot commit
+                ShardReply::Unreachable if !self.shards.hosts_shard(shard_id) => participants.retain(|id| *id != shard_id),
+                ShardReply::Unreachable => {
+                    votes.push((shard_id, Vote::CannotCommit));
+                    abort_resp.get_or_insert(ClientResponse::AbortedUnavailable(shard_id));
+                },
+                ShardReply::Vote(CommitStatus::ReadyToCommit) => votes.push((shard_id, Vote::ReadyToCommit)),
+                ShardReply::Vote(CommitStatus::CannotCommit(resp)) => {
+                    votes.push((shard_id, Vote::CannotCommit));
+                    abort_resp.get_or_insert(resp);
+                },
+                reply => {
+                    error!("Expected a commit vote from shard {shard_id} for {} - got {reply:?}", self.transaction_id);
+                    votes.push((shard_id, Vote::CannotCommit));
+                    abort_resp.get_or_insert(ClientResponse::Aborted);
+                }
             }
+        }
 
-            return;
+        // The second phase: tell every shard the outcome
+        match abort_resp {
+            None => {
+                trace!("All shards ready to commit {}.", self.transaction_id);
+                let others = participants.iter().copied().filter(|id| *id != self.server_id);
+                self.completion.lock().unwrap().decided(self.transaction_id, others, Instant::now());
+                self.record_decision(participants, votes, Decision::Commit, started);
+                match self.verbosity {
+                    CommitVerbosity::Quiet => {
+                        for shard_id in scope {
+                            self.send_message(ForwardTarget::Node(shard_id), Forwarded::DoCommit(self.transaction_id, None));
+                        }
+
+                        self.do_commit().await;
+                        ClientResponse::CommitOk
+                    },
+                    CommitVerbosity::Values => ClientResponse::CommitOkWithValues(self.commit_returning_values(scope).await)
+                }
+            },
+            Some(resp) => {
+                trace!("Not all shards can commit {}: aborting...", self.transaction_id);
+                self.record_decision(participants, votes, Decision::Abort, started);
+                self.do_abort().await;
+                resp
+            }
         }
+    }
 
-        let check_commit_req = ClientState::Forward(
-            ForwardTarget::Broadcast, 
-            self.transaction_id, 
-            ClientRequest::Commit
-        );
+    /// Holds a balance change until the transaction commits, as a 
+    /// deterministic cluster only runs transactions once they are sequenced.
+    fn hold_balance_change(&mut self, account_id: AccountId, diff: BalanceDiff) -> ClientResponse {
+        if self.routes.route(&self.shards, &account_id).is_none() {
+            trace!("Unable to hold BalanceChange({account_id}, {diff:?}) for {}: account does not exist", self.transaction_id);
+            return ClientResponse::AbortedNotFound;
+        }
+
+        self.writes.push((account_id, diff));
+        ClientResponse::Ok
+    }
 
-        if let Err(_) = self.forward_snd.send(check_commit_req) {
-            error!("Unable to forward check_commit request to shard server")
+    /// Commits the transaction in a deterministic cluster: the sequencer puts
+    /// its balance changes in the cluster's order and every shard they write
+    /// to runs them in that order, deciding the outcome from each other's 
+    /// verdicts. The coordinator takes no part in the decision, it only
+    /// collects the verdicts, which all shards send it, to learn the outcome.
+    /// Until they arrive it sends the transaction to the sequencer again now
+    /// and then, since a sequencer that was lost may have taken it along, and
+    /// the sequencer drops it if it placed it already.
+    async fn handle_sequenced_commit(&mut self) -> ClientResponse {
+        self.state = TransactionState::Preparing;
+        let writes = std::mem::take(&mut self.writes);
+        let participants = deterministic::participants(&self.shards, &writes);
+        if participants.is_empty() {
+            return ClientResponse::CommitOk;
         }
 
-        let resp = self.forward_rcv.recv().await.unwrap();
-        match &resp {
-            ClientResponse::CommitOk => self.do_commit().await,
-            ClientResponse::Aborted => self.do_abort().await,
-            resp => error!("FATAL ERROR: waiting for CommitOk or Aborted - got {resp:?}")
+        let fwd_id = self.forwards.start();
+        let sequence = Forwarded::Sequence(self.transaction_id, fwd_id, writes);
+        self.send_to_sequencer(sequence.clone());
+
+        // Either every shard sends a verdict, or the sequencer could not be
+        // reached or refused the transaction and nothing else arrives
+        let mut abort_resp = None;
+        let mut pending = participants.len();
+        let mut resent = false;
+        loop {
+            let Ok(replies) = tokio::time::timeout(deterministic::SEQUENCE_RESEND_INTERVAL, self.await_replies(fwd_id, 1)).await else {
+                trace!("No verdict on {} yet: sending it to the sequencer again", self.transaction_id);
+                self.send_to_sequencer(sequence.clone());
+                resent = true;
+                continue;
+            };
+
+            for (shard_id, reply) in replies {
+                match reply {
+                    ShardReply::Vote(CommitStatus::ReadyToCommit) => pending -= 1,
+                    ShardReply::Vote(CommitStatus::CannotCommit(resp)) => {
+                        pending -= 1;
+                        abort_resp.get_or_insert(resp);
+                    },
+                    // Once sent again, the transaction may have been placed 
+                    // by an earlier sequencer, so it is waited on
+                    ShardReply::Unreachable | ShardReply::Unrelayed if resent => (),
+                    ShardReply::Unreachable | ShardReply::Unrelayed => return ClientResponse::AbortedUnavailable(shard_id),
+                    ShardReply::Response(resp) => return resp
+                }
+            }
+
+            if pending == 0 {
+                return abort_resp.unwrap_or(ClientResponse::CommitOk);
+            }
+            self.forwards.resume(fwd_id);
         }
+    }
 
-        if let Err(e) = self.stream.send(resp).await {
-            error!("Failed to send response to the client: {e:?}");
+    fn send_to_sequencer(&self, sequence: Forwarded) {
+        if self.forward_snd.send(ClientState::Forward(ForwardTarget::Sequencer, sequence)).is_err() {
+            error!("Failed to pass message to the shard server...");
         }
     }
 
-    pub async fn handle(mut self) {
-        while let Some(Ok(request)) = self.stream.recv::<ClientRequest>().await {
-            info!("Client task for {} handling {request:?}", self.transaction_id);
-            match request {
-                ClientRequest::WriteBalance(account_id, diff) => {
-                    if self.handle_balance_change_request(account_id, diff).await.is_err() {
-                        break;
-                    }
+    async fn handle_admin_request(&self, request: AdminRequest) -> ClientResponse {
+        let resp = match request {
+            AdminRequest::DecisionLog => {
+                let records = self.decisions.lock().unwrap().records();
+                AdminResponse::DecisionLog(records)
+            },
+            AdminRequest::Status => {
+                let (peers, (links, health)) = self.peers
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(node_id, (traffic, health))| (*node_id, (*traffic.lock().unwrap(), health.lock().unwrap().status())))
+                    .unzip();
+                let unapplied = self.completion.lock().unwrap().unapplied(Instant::now());
+                AdminResponse::Status(Box::new(NodeStatus { node_id: self.server_id, peers, links, health, unapplied }))
+            },
+            AdminRequest::Drain => {
+                self.drain.begin();
+                AdminResponse::Drain(self.drain.status())
+            },
+            AdminRequest::Verification => {
+                let status = self.verification.lock().unwrap().clone();
+                AdminResponse::Verification(status)
+            },
+            AdminRequest::Contention => AdminResponse::Contention(self.contention.lock().unwrap().status()),
+            AdminRequest::Labels => AdminResponse::Labels(self.labels.lock().unwrap().stats()),
+            AdminRequest::Tenants => AdminResponse::Tenants(self.tenants.lock().unwrap().stats()),
+            AdminRequest::AccountStats(n) => {
+                let mut accounts: Vec<_> = self.shard
+                    .object_stats()
+                    .await
+                    .into_iter()
+                    .map(|(account_id, stats)| AccountStats {
+                        account_id,
+                        reads: stats.reads,
+                        writes: stats.writes,
+                        aborts: stats.aborts,
+                        commits: stats.commits,
+                        last_committer: stats.last_committer
+                    })
+                    .collect();
+                accounts.sort_by(|a, b| (b.reads + b.writes).cmp(&(a.reads + a.writes))
+                    .then(b.aborts.cmp(&a.aborts))
+                    .then(a.account_id.cmp(&b.account_id)));
+                accounts.truncate(n);
+                AdminResponse::AccountStats(accounts)
+            },
+            AdminRequest::Memory(n) => AdminResponse::Memory(Box::new(self.memory_report(n).await)),
+            AdminRequest::Decision(tx_id) => AdminResponse::DecisionLog(self.decisions.lock().unwrap().of(&tx_id)),
+            AdminRequest::PinConcurrencyMode(mode) => {
+                let mut controller = self.contention.lock().unwrap();
+                self.shard.set_mode(controller.pin(mode));
+                AdminResponse::Contention(controller.status())
+            },
+            AdminRequest::Outcome(tx_id) => AdminResponse::Outcome(self.shard.outcome(&tx_id)),
+            AdminRequest::Routes => AdminResponse::Routes(self.routes.routes()),
+            AdminRequest::Members => AdminResponse::Members(
+                self.membership.as_ref().map_or_else(Vec::new, |membership| membership.lock().unwrap().members())
+            ),
+            AdminRequest::Acls => AdminResponse::Acls(self.acls.lock().unwrap().list()),
+            AdminRequest::CommitsSince(seq) => {
+                let (first_seq, commits) = self.shard.commits_since(seq).await;
+                let commits = commits
+                    .into_iter()
+                    .map(|entry| CommitRecord { seq: entry.seq, tx_id: entry.tx_id, writes: entry.writes, removed: entry.removed })
+                    .collect();
+                AdminResponse::Commits(CommitsSince { first_seq, commits })
+            },
+            AdminRequest::Snapshot => AdminResponse::Snapshot(committed_snapshot(&self.shard, self.server_id).await),
+            AdminRequest::Query(_) => unreachable!("queries are gathered by handle_query"),
+            AdminRequest::ClusterSnapshot => unreachable!("snapshots of every shard are gathered by handle_cluster_snapshot"),
+            AdminRequest::Digest => unreachable!("digests are gathered by handle_digest"),
+            AdminRequest::Pause(_) | AdminRequest::Resume => unreachable!("pauses are coordinated by handle_pause"),
+            AdminRequest::SetOwner(..) | AdminRequest::Grant(..) | AdminRequest::RemoveAcl(_) => unreachable!("access control lists are changed by handle_acl_change")
+        };
+
+        ClientResponse::Admin(resp)
+    }
+
+    /// Attributes what the node's shard holds to the transactions and 
+    /// accounts holding it, along with what the server holds for its clients
+    /// and in its queues, and what the node's logs of recent commits,
+    /// outcomes and decisions hold.
+    async fn memory_report(&self, n: usize) -> MemoryReport {
+        let (memory_snd, memory) = oneshot::channel();
+        if self.forward_snd.send(ClientState::Memory(memory_snd)).is_err() {
+            error!("Failed to ask the server for its memory");
+        }
+        let server = memory.await.unwrap_or_default();
+
+        let footprints = self.shard.footprints().await;
+        let mut transactions: HashMap<TransactionId, TransactionMemory> = HashMap::new();
+        for (tx_id, bytes) in footprints.iter().flat_map(|(_, footprint)| &footprint.pending) {
+            let memory = transactions.entry(*tx_id).or_insert(TransactionMemory { tx_id: *tx_id, writes: 0, bytes: 0 });
+            memory.writes += 1;
+            memory.bytes += bytes;
+        }
+        let mut transactions: Vec<_> = transactions.into_values().collect();
+        transactions.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.tx_id.cmp(&b.tx_id)));
+        transactions.truncate(n);
+
+        let (accounts, bytes) = (footprints.len(), footprints.iter().map(|(_, footprint)| footprint.bytes).sum());
+        let mut largest: Vec<_> = footprints
+            .into_iter()
+            .map(|(account_id, footprint)| AccountMemory {
+                account_id,
+                versions: footprint.pending.len() + 1,
+                reads: footprint.reads,
+                bytes: footprint.bytes
+            })
+            .collect();
+        largest.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.account_id.cmp(&b.account_id)));
+        largest.truncate(n);
+
+        let (phases, notifications) = self.shard.tracked().await;
+        let (commit_log, finished) = self.shard.history_bytes().await;
+        let decisions = self.decisions.lock().unwrap().bytes();
+        MemoryReport {
+            node_id: self.server_id,
+            sessions: server.sessions,
+            greeting: server.greeting,
+            queues: server.queues,
+            accounts,
+            bytes,
+            phases,
+            notifications,
+            commit_log,
+            finished,
+            decisions,
+            transactions,
+            largest
+        }
+    }
+
+    /// Reads a part from every shard as the transaction: this node's by 
+    /// `local`, and every other's by forwarding `request`. The shards that 
+    /// answered become part of the transaction's commit. Returns the parts,
+    /// this node's first, and the shards that have not joined, or the 
+    /// response of a shard that could not read its part, which aborts the
+    /// transaction.
+    async fn read_every_shard<F>(&mut self, request: ClientRequest, local: F) -> Result<(Vec<AdminResponse>, Vec<NodeId>), ClientResponse> 
+    where 
+        F: Future<Output = Result<AdminResponse, Abort<AccountId>>>
+    {
+        let others: Vec<_> = self.shard_ids.iter().copied().filter(|shard_id| *shard_id != self.server_id).collect();
+        let local = before_deadline(self.deadline, async {
+            match local.await {
+                Ok(part) => ClientResponse::Admin(part),
+                Err(e) => abort_response(e)
+            }
+        });
+        let message = forward_request(self.transaction_id, self.remaining(), request);
+        let (local, others) = tokio::join!(local, self.for_each_shard(&others, message));
+        let mut replies = vec![(self.server_id, ShardReply::Response(local))];
+        replies.extend(others);
+
+        let (mut parts, mut missing) = (Vec::with_capacity(replies.len()), Vec::new());
+        for (shard_id, reply) in replies {
+            match reply {
+                ShardReply::Response(ClientResponse::Admin(part)) => {
+                    self.routes.query(shard_id);
+                    parts.push(part);
                 },
-                ClientRequest::ReadBalance(account_id) => {
-                    if self.handle_balance_request(account_id).await.is_err() {
-                        break;
+                ShardReply::Response(resp) if resp.is_err() => return Err(resp),
+                ShardReply::Unreachable => missing.push(shard_id),
+                reply => {
+                    error!("Expected part of a read of every shard from shard {shard_id} - got {reply:?}");
+                    missing.push(shard_id);
+                }
+            }
+        }
+
+        Ok((parts, missing))
+    }
+
+    /// Scatters a query to every shard and streams each shard's selected rows
+    /// to the client once every part is gathered, ending with a summary of 
+    /// all of them. Every shard reads its part as the transaction, so the 
+    /// parts together are a consistent cut of the cluster. Shards that have
+    /// not joined are reported missing rather than retried.
+    async fn handle_query(&mut self, query: Query) -> ClientResponse {
+        let request = ClientRequest::Admin(AdminRequest::Query(query.clone()));
+        let (shard, server_id, tx_id) = (self.shard.clone(), self.server_id, self.transaction_id);
+        let local = async move {
+            evaluate_query(&shard, server_id, &tx_id, &query).await.map(|part| AdminResponse::QueryPart(Box::new(part)))
+        };
+        let (parts, missing) = match self.read_every_shard(request, local).await {
+            Ok(gathered) => gathered,
+            Err(resp) => return resp
+        };
+
+        let mut summary = QuerySummary { missing, ..Default::default() };
+        for part in parts {
+            match part {
+                AdminResponse::QueryPart(part) => self.stream_rows(*part, &mut summary).await,
+                part => error!("Expected part of a query - got {part:?}")
+            }
+        }
+
+        summary.snapshots.sort();
+        ClientResponse::Admin(AdminResponse::QueryDone(Box::new(summary)))
+    }
+
+    /// Snapshots every shard as the transaction, so the snapshots together
+    /// are a consistent cut of the cluster. A snapshot that misses a shard
+    /// that has not joined would not be one, so it aborts the transaction.
+    async fn handle_cluster_snapshot(&mut self) -> ClientResponse {
+        let request = ClientRequest::Admin(AdminRequest::ClusterSnapshot);
+        let (shard, server_id, tx_id) = (self.shard.clone(), self.server_id, self.transaction_id);
+        let local = async move {
+            read_snapshot(&shard, server_id, &tx_id).await.map(AdminResponse::Snapshot)
+        };
+        let mut snapshots = match self.read_every_shard(request, local).await {
+            Ok((_, missing)) if !missing.is_empty() => return ClientResponse::AbortedUnavailable(missing[0]),
+            Ok((parts, _)) => parts
+                .into_iter()
+                .filter_map(|part| match part {
+                    AdminResponse::Snapshot(snapshot) => Some(snapshot),
+                    part => {
+                        error!("Expected a snapshot - got {part:?}");
+                        None
                     }
+                })
+                .collect::<Vec<_>>(),
+            Err(resp) => return resp
+        };
+
+        snapshots.sort_by_key(|snapshot| snapshot.node_id);
+        ClientResponse::Admin(AdminResponse::Snapshots(snapshots))
+    }
+
+    /// Gathers a digest of every shard's committed state. Shards that have
+    /// not joined are left out.
+    async fn handle_digest(&mut self) -> ClientResponse {
+        let others: Vec<_> = self.shard_ids.iter().copied().filter(|shard_id| *shard_id != self.server_id).collect();
+        let mut digests = vec![shard_digest(&self.shard, self.server_id).await];
+        for (shard_id, reply) in self.for_each_shard(&others, forward_request(self.transaction_id, None, ClientRequest::Admin(AdminRequest::Digest))).await {
+            match reply {
+                ShardReply::Response(ClientResponse::Admin(AdminResponse::Digests(digest))) => digests.extend(digest),
+                ShardReply::Unreachable => trace!("Leaving shard {shard_id} out of the digests: it has not joined"),
+                reply => error!("Expected a digest from shard {shard_id} - got {reply:?}")
+            }
+        }
+
+        digests.sort_by_key(|digest| digest.node_id);
+        ClientResponse::Admin(AdminResponse::Digests(digests))
+    }
+
+    /// Pauses the cluster in two phases, or resumes it if `within` is `None`.
+    /// First every node, this one included, stops starting transactions and
+    /// waits up to `within` for those under way to finish. If any did not, 
+    /// or another pause already holds on any, every node lifts this pause 
+    /// again, so that the cluster is either paused everywhere or nowhere.
+    async fn handle_pause(&mut self, within: Option<Duration>) -> ClientResponse {
+        let others: Vec<_> = self.shard_ids.iter().copied().filter(|shard_id| *shard_id != self.server_id).collect();
+        let Some(within) = within else {
+            self.resume(&others).await;
+            return ClientResponse::Admin(AdminResponse::Pause(PauseStatus::default()));
+        };
+
+        info!("Pausing the cluster within {within:?}");
+        let drain = self.drain.clone();
+        let request = forward_request(self.transaction_id, None, ClientRequest::Admin(AdminRequest::Pause(within)));
+        let (quiesced, replies) = tokio::join!(drain.quiesce(self.transaction_id, within), self.for_each_shard(&others, request));
+        let mut busy: Vec<_> = (!quiesced).then_some(self.server_id).into_iter().collect();
+        for (shard_id, reply) in replies {
+            match reply {
+                ShardReply::Response(ClientResponse::Admin(AdminResponse::Pause(status))) => busy.extend(status.busy),
+                reply => {
+                    error!("Expected shard {shard_id} to pause - got {reply:?}");
+                    busy.push(shard_id);
+                }
+            }
+        }
+
+        if busy.is_empty() {
+            info!("Paused the cluster");
+            return ClientResponse::Admin(AdminResponse::Pause(PauseStatus { paused: true, busy }));
+        }
+
+        busy.sort();
+        info!("Unable to pause the cluster: {busy:?} still busy");
+        self.release_pause(&others).await;
+        ClientResponse::Admin(AdminResponse::Pause(PauseStatus { paused: false, busy }))
+    }
+
+    /// Changes the access control list of an account and waits for every
+    /// other node that has joined to apply the change, so that a client 
+    /// connecting to any coordinator afterwards is held to the new list. A
+    /// node that has not joined catches up on the change when it does.
+    async fn handle_acl_change(&mut self, request: AdminRequest) -> ClientResponse {
+        let (account_id, change) = {
+            let mut acls = self.acls.lock().unwrap();
+            match request {
+                AdminRequest::SetOwner(account_id, owner) => (account_id.clone(), Some(acls.set_owner(account_id, owner, self.transaction_id))),
+                AdminRequest::Grant(account_id, tenant, access) => {
+                    let change = acls.grant(&account_id, tenant, access, self.transaction_id);
+                    if change.is_none() {
+                        return ClientResponse::Admin(AdminResponse::NoOwner(account_id));
+                    }
+                    (account_id, change)
                 },
-                ClientRequest::Commit => {
-                    self.handle_commit_request().await;
-                    break;
+                AdminRequest::RemoveAcl(account_id) => {
+                    let change = acls.remove(&account_id, self.transaction_id);
+                    (account_id, change)
                 },
-                ClientRequest::Abort => {
-                    self.do_abort().await;
-                    if let Err(e) = self.stream.send(ClientResponse::Aborted).await {
-                        error!("Failed to send response to the client: {e:?}");
+                request => unreachable!("{request:?} does not change an access control list")
+            }
+        };
+
+        if let Some(change) = change {
+            acl::persist(&self.acls);
+            let others: Vec<_> = self.shard_ids.iter().copied().filter(|shard_id| *shard_id != self.server_id).collect();
+            let tx_id = self.transaction_id;
+            let message = move |fwd_id| Forwarded::Acl(tx_id, fwd_id, Box::new(change.clone()));
+            for (shard_id, reply) in self.for_each_shard(&others, message).await {
+                match reply {
+                    ShardReply::Response(ClientResponse::Ok) => (),
+                    ShardReply::Unreachable => info!("Shard {shard_id} will learn of the change to {account_id}'s access control list when it joins"),
+                    reply => error!("Expected shard {shard_id} to apply the change to {account_id}'s access control list - got {reply:?}")
+                }
+            }
+        }
+
+        ClientResponse::Admin(AdminResponse::Acls(self.acls.lock().unwrap().get(&account_id).into_iter().collect()))
+    }
+
+    /// Lifts the pause this transaction placed, leaving any other pause in
+    /// place.
+    async fn release_pause(&mut self, others: &[NodeId]) {
+        self.drain.release(&self.transaction_id);
+        let tx_id = self.transaction_id;
+        for (shard_id, reply) in self.for_each_shard(others, move |fwd_id| Forwarded::Unpause(tx_id, fwd_id)).await {
+            if !matches!(reply, ShardReply::Response(ClientResponse::Ok) | ShardReply::Unreachable) {
+                error!("Expected shard {shard_id} to lift the pause of {} - got {reply:?}", self.transaction_id);
+            }
+        }
+        info!("Lifted the pause of {}", self.transaction_id);
+    }
+
+    async fn resume(&mut self, others: &[NodeId]) {
+        self.drain.resume();
+        for (shard_id, reply) in self.for_each_shard(others, forward_request(self.transaction_id, None, ClientRequest::Admin(AdminRequest::Resume))).await {
+            if !matches!(reply, ShardReply::Response(ClientResponse::Admin(AdminResponse::Pause(_)))) {
+                error!("Expected shard {shard_id} to resume - got {reply:?}");
+            }
+        }
+        info!("Resumed the cluster");
+    }
+
+    /// Sends a shard's selected rows to the client in batches and adds the
+    /// shard's part to the summary.
+    async fn stream_rows(&mut self, part: QueryPart, summary: &mut QuerySummary) {
+        summary.add(&part);
+        for batch in part.rows.chunks(QUERY_BATCH_ROWS) {
+            let rows = ClientResponse::Admin(AdminResponse::QueryRows(batch.to_vec()));
+            if let Err(e) = self.stream.send(rows).await {
+                error!("Failed to stream query rows to the client: {e:?}");
+                return;
+            }
+        }
+    }
+
+    /// Describes the cluster as this node sees it, with the features this
+    /// node supports.
+    fn cluster_info(&self) -> ClientResponse {
+        let peers = self.peers.lock().unwrap();
+        let nodes = self.nodes
+            .iter()
+            .map(|node| NodeInfo { joined: node.node_id == self.server_id || peers.contains_key(&node.node_id), ..node.clone() })
+            .collect();
+        let mut capabilities = match self.execution_mode {
+            ExecutionMode::Interactive => vec![
+                capability::TWO_PHASE_COMMIT, capability::READS, capability::STALE_READS, capability::SWAPS, capability::INCREMENTS,
+                capability::TTL, capability::COMMIT_VALUES
+            ],
+            ExecutionMode::Deterministic => vec![capability::DETERMINISTIC, capability::INCREMENTS]
+        };
+        capabilities.extend([capability::DEADLINES, capability::LIFETIMES, capability::QUERIES]);
+
+        ClientResponse::ClusterInfo(Box::new(ClusterInfo {
+            node_id: self.server_id,
+            protocol_version: PROTOCOL_VERSION,
+            epoch: self.shards.epoch(),
+            nodes,
+            capabilities: capabilities.into_iter().map(String::from).collect()
+        }))
+    }
+
+    /// Adopts the session settings a client asked for, unless the transaction
+    /// has already read or written under the old ones or this node cannot
+    /// honour them. Settings are adopted all together or not at all.
+    fn handle_hello(&mut self, settings: SessionSettings) -> ClientResponse {
+        let refusal = if self.operated {
+            Some("the transaction has already read or written")
+        } else if settings.default_deadline == Some(Duration::ZERO) {
+            Some("the default deadline is zero")
+        } else if self.execution_mode == ExecutionMode::Deterministic && settings.isolation != IsolationLevel::Serializable {
+            Some("deterministic transactions cannot read")
+        } else if self.execution_mode == ExecutionMode::Deterministic && settings.verbosity == CommitVerbosity::Values {
+            Some("deterministic commits do not answer with values")
+        } else {
+            None
+        };
+
+        if let Some(reason) = refusal {
+            info!("Refusing session settings {settings:?} for {}: {reason}", self.transaction_id);
+            return ClientResponse::SettingsRefused(reason.into());
+        }
+
+        self.verbosity = settings.verbosity;
+        self.settings = settings.clone();
+        ClientResponse::SessionSettings(settings)
+    }
+
+    /// Replaces the transaction id with a newer one from the server. Only 
+    /// valid before the transaction has read or written anything, since the
+    /// shards know nothing about the transaction under its old id.
+    async fn renew_transaction_id(&mut self) {
+        let (renewed_snd, renewed) = oneshot::channel();
+        if self.forward_snd.send(ClientState::Renew(self.transaction_id, renewed_snd)).is_err() {
+            error!("Failed to ask the server to renew {}", self.transaction_id);
+            return;
+        }
+
+        match renewed.await {
+            Ok(tx_id) => self.transaction_id = tx_id,
+            Err(_) => error!("Server dropped the renewal of {}", self.transaction_id)
+        }
+    }
+
+    /// Starts a new transaction on the connection once the last one finished,
+    /// with a new id and a new chain of layers. The session's settings carry
+    /// over, while everything the last transaction touched, asked for or 
+    /// held is dropped.
+    async fn next_transaction(&mut self) {
+        let (next_snd, next) = oneshot::channel();
+        if self.forward_snd.send(ClientState::Next(self.transaction_id, next_snd)).is_err() {
+            error!("Failed to ask the server for the transaction after {}", self.transaction_id);
+            return;
+        }
+
+        let Ok((tx_id, layers)) = next.await else {
+            error!("Server dropped the transaction after {}", self.transaction_id);
+            return;
+        };
+        trace!("Client of {} starting {tx_id}", self.transaction_id);
+        self.transaction_id = tx_id;
+        self.layers = layers;
+        self.routes = RouteCache::default();
+        self.forwards = PendingForwards::default();
+        self.answered = false;
+        self.operated = false;
+        self.writes.clear();
+        self.assertions.clear();
+        self.began = Instant::now();
+        self.expiry = TransactionLifetime::expiry(self.began, self.lifetime.initial());
+        self.verbosity = self.settings.verbosity;
+        self.state = TransactionState::Active;
+    }
+
+    fn context(&self) -> RequestContext {
+        RequestContext {
+            tx_id: self.transaction_id,
+            active: matches!(self.state, TransactionState::Active)
+        }
+    }
+
+    /// Handles a request according to the state of the transaction once every
+    /// layer has let it through. Requests are handled one at a time, so the 
+    /// transaction is only ever observed as `Preparing` from within 
+    /// `handle_commit_request`.
+    async fn handle_request(&mut self, request: ClientRequest) -> ClientResponse {
+        use TransactionState::*;
+
+        let request = match (request, self.settings.isolation) {
+            (ClientRequest::ReadBalance(account_id), IsolationLevel::BoundedStaleness(max_staleness)) => 
+                ClientRequest::ReadBalanceStale(account_id, max_staleness),
+            (request, _) => request
+        };
+        let starts_work = matches!(
+            request, 
+            ClientRequest::WriteBalance(..) | ClientRequest::WriteBalanceWithTtl(..) | ClientRequest::Increment(..) | ClientRequest::ReadBalance(_) | ClientRequest::ReadBalanceIfExists(_) | ClientRequest::ReadBalanceStale(..) | ClientRequest::Swap(..) | ClientRequest::AssertBalance(..) | ClientRequest::Admin(AdminRequest::Query(_) | AdminRequest::ClusterSnapshot)
+        );
+        if matches!(self.state, Active) && starts_work && !self.operated {
+            self.operated = true;
+            // The id the transaction was given is already that new if this is
+            // its first request
+            if self.timestamp_mode == TimestampMode::FirstOperation && self.answered {
+                self.renew_transaction_id().await;
+            }
+        }
+
+        let cx = self.context();
+        for layer in self.layers.iter_mut() {
+            if let Some(resp) = layer.before(&cx, &request) {
+                return resp;
+            }
+        }
+
+        let deterministic = self.execution_mode == ExecutionMode::Deterministic;
+        match (&self.state, request) {
+            (_, ClientRequest::Admin(AdminRequest::Query(query))) => self.handle_query(query).await,
+            (_, ClientRequest::Admin(AdminRequest::ClusterSnapshot)) => self.handle_cluster_snapshot().await,
+            (_, ClientRequest::Admin(AdminRequest::Digest)) => self.handle_digest().await,
+            (_, ClientRequest::Admin(AdminRequest::Pause(within))) => self.handle_pause(Some(within)).await,
+            (_, ClientRequest::Admin(AdminRequest::Resume)) => self.handle_pause(None).await,
+            (_, ClientRequest::Admin(request @ (AdminRequest::SetOwner(..) | AdminRequest::Grant(..) | AdminRequest::RemoveAcl(_)))) => 
+                self.handle_acl_change(request).await,
+            (_, ClientRequest::Admin(request)) => self.handle_admin_request(request).await,
+            (_, ClientRequest::ClusterInfo) => self.cluster_info(),
+            (Active | Preparing, ClientRequest::Hello(settings)) => self.handle_hello(settings),
+            (Active | Preparing, ClientRequest::WriteBalance(account_id, diff) | ClientRequest::Increment(account_id, diff)) if deterministic => 
+                self.hold_balance_change(account_id, diff),
+            (Active | Preparing, ClientRequest::WriteBalanceWithTtl(..) | ClientRequest::ReadBalance(_) | ClientRequest::ReadBalanceIfExists(_) | ClientRequest::ReadBalanceStale(..) | ClientRequest::Swap(..) | ClientRequest::AssertBalance(..)) if deterministic => 
+                ClientResponse::AbortedNotDeterministic,
+            (Active | Preparing, ClientRequest::Commit) if deterministic => self.handle_sequenced_commit().await,
+            (Active | Preparing, ClientRequest::WriteBalance(account_id, diff)) => 
+                self.handle_balance_change_request(account_id, diff, None).await,
+            (Active | Preparing, ClientRequest::WriteBalanceWithTtl(account_id, diff, ttl)) => 
+                self.handle_balance_change_request(account_id, diff, Some(ttl)).await,
+            (Active | Preparing, ClientRequest::Increment(account_id, diff)) => 
+                self.handle_increment_request(account_id, diff).await,
+            (Active | Preparing, ClientRequest::ReadBalance(account_id)) => 
+                self.handle_balance_request(account_id, false).await,
+            (Active | Preparing, ClientRequest::ReadBalanceIfExists(account_id)) => 
+                self.handle_balance_request(account_id, true).await,
+            (Active | Preparing, ClientRequest::ReadBalanceStale(account_id, max_staleness)) => 
+                self.handle_stale_balance_request(account_id, max_staleness).await,
+            (Active | Preparing, ClientRequest::Swap(first, second)) => 
+                self.handle_swap_request(first, second).await,
+            (Active | Preparing, ClientRequest::AssertBalance(account_id, predicate)) => 
+                self.handle_assert_request(account_id, predicate).await,
+            (Active | Preparing, ClientRequest::Commit) => self.handle_commit_request().await,
+            (Active | Preparing, ClientRequest::Abort) => ClientResponse::Aborted,
+            (Active | Preparing, ClientRequest::AbortWithReason(reason)) => {
+                self.record_client_abort(reason);
+                ClientResponse::Aborted
+            },
+            (Active | Preparing, ClientRequest::Label(_) | ClientRequest::Authenticate(..)) => ClientResponse::Ok,
+            (Active | Preparing, ClientRequest::Verbosity(verbosity)) => {
+                self.verbosity = verbosity;
+                ClientResponse::Ok
+            },
+            (Active | Preparing, ClientRequest::Lifetime(requested)) => {
+                let granted = self.lifetime.grant(requested);
+                self.expiry = TransactionLifetime::expiry(self.began, Some(granted));
+                ClientResponse::LifetimeGranted(granted)
+            },
+            (_, ClientRequest::Deadline(..)) => unreachable!("deadlines are split off before requests are handled"),
+            (Committed, ClientRequest::Commit) => ClientResponse::CommitOk,
+            (Committed, _) => ClientResponse::AlreadyFinished(Decision::Commit),
+            (Aborted(resp), ClientRequest::Commit) => resp.clone(),
+            (Aborted(_), ClientRequest::Abort | ClientRequest::AbortWithReason(_)) => ClientResponse::Aborted,
+            (Aborted(ClientResponse::AbortedLifetimeExpired), _) => ClientResponse::AbortedLifetimeExpired,
+            (Aborted(_), _) => ClientResponse::AlreadyFinished(Decision::Abort)
+        }
+    }
+
+    /// Moves the transaction to a final state once a response commits or 
+    /// aborts it. Any failed operation aborts the transaction on every shard.
+    async fn transition(&mut self, resp: &ClientResponse) {
+        match (&self.state, resp) {
+            (TransactionState::Active | TransactionState::Preparing, resp) if resp.is_committed() => 
+                self.state = TransactionState::Committed,
+            (TransactionState::Active, resp) if resp.is_err() => {
+                trace!("Aborting transaction {}...", self.transaction_id);
+                self.do_abort().await;
+                self.state = TransactionState::Aborted(resp.clone());
+            },
+            (TransactionState::Preparing, resp) if resp.is_err() => 
+                self.state = TransactionState::Aborted(resp.clone()),
+            _ => ()
+        }
+    }
+
+    /// Handles a request unless the transaction's lifetime has run out. A 
+    /// read, write or swap still waiting on a shard when it runs out is 
+    /// abandoned like one whose deadline passes, while a commit that started
+    /// in time runs to completion.
+    async fn handle_within_lifetime(&mut self, request: ClientRequest) -> ClientResponse {
+        let Some(expiry) = self.expiry else {
+            return self.handle_request(request).await;
+        };
+
+        let refused = !matches!(request, ClientRequest::Abort | ClientRequest::AbortWithReason(_) | ClientRequest::Lifetime(_) | ClientRequest::Admin(_));
+        if refused && matches!(self.state, TransactionState::Active) && expiry <= Instant::now() {
+            info!("Lifetime of {} expired: refusing {request:?}", self.transaction_id);
+            return ClientResponse::AbortedLifetimeExpired;
+        }
+
+        self.deadline = Some(self.deadline.map_or(expiry, |deadline| deadline.min(expiry)));
+        match self.handle_request(request).await {
+            ClientResponse::AbortedDeadlineExceeded if self.expiry.is_some_and(|expiry| expiry <= Instant::now()) => 
+                ClientResponse::AbortedLifetimeExpired,
+            resp => resp
+        }
+    }
+
+    /// Waits for the client's next request. A transaction whose lifetime runs
+    /// out in the meantime is aborted right away, so that it does not hold up
+    /// others, and the request is answered with the abort.
+    async fn next_request(&mut self) -> Option<Result<ClientRequest, StreamError>> {
+        if let (TransactionState::Active, Some(expiry)) = (&self.state, self.expiry) {
+            select! {
+                request = self.stream.recv() => return request,
+                _ = sleep_until(expiry) => {
+                    info!("Lifetime of {} expired while waiting on the client: aborting...", self.transaction_id);
+                    let resp = ClientResponse::AbortedLifetimeExpired;
+                    let cx = self.context();
+                    for layer in self.layers.iter_mut() {
+                        layer.after(&cx, &resp);
                     }
-                    break;
+                    self.transition(&resp).await;
+                    self.drain.finish(&self.transaction_id);
                 }
             }
         }
 
+        self.stream.recv().await
+    }
+
+    /// Serves the client until it disconnects, starting with `first`, the 
+    /// request the server already read from the connection. A connection 
+    /// carries one transaction after another: the request after the one 
+    /// that committed or aborted a transaction starts the next.
+    pub async fn handle(mut self, first: ClientRequest) {
+        let mut request = first;
+        loop {
+            let (budget, inner) = request.split_deadline();
+            self.deadline = budget.or(self.settings.default_deadline).map(|budget| Instant::now() + budget);
+            let resp = self.handle_within_lifetime(inner).await;
+            let cx = self.context();
+            for layer in self.layers.iter_mut() {
+                layer.after(&cx, &resp);
+            }
+
+            self.transition(&resp).await;
+            self.answered = true;
+            if let TransactionState::Committed | TransactionState::Aborted(_) = self.state {
+                self.drain.finish(&self.transaction_id);
+            }
+
+            if let Err(e) = self.stream.send(resp).await {
+                error!("Failed to send response to the client: {e:?}");
+                break;
+            }
+
+            // A transaction whose lifetime runs out while waiting on the 
+            // client still answers its next request with the abort
+            let finished = matches!(self.state, TransactionState::Committed | TransactionState::Aborted(_));
+            request = match self.next_request().await {
+                Some(Ok(request)) => request,
+                _ => break
+            };
+            if finished {
+                self.next_transaction().await;
+            }
+        }
+
+        if let TransactionState::Active = self.state {
+            info!("Client for {} disconnected mid-transaction: aborting...", self.transaction_id);
+            self.do_abort().await;
+        }
+        self.drain.finish(&self.transaction_id);
+
         let finished = ClientState::Finished(self.transaction_id);
-        if let Err(_) = self.forward_snd.send(finished) {
+        if self.forward_snd.send(finished).is_err() {
             error!("Failed to pass finished message to server task.")
         }
     }
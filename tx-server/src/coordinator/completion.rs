@@ -0,0 +1,107 @@
+use crate::sharding::TransactionId;
+use tx_common::{config::NodeId, admin::UnappliedCommit};
+use std::{collections::{BTreeMap, BTreeSet}, sync::{Arc, Mutex}, time::Duration};
+use tokio::time::Instant;
+
+/// How long a coordinator waits for a participant to acknowledge applying a
+/// commit before telling it to commit again.
+pub static COMMIT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The transactions this node decided to commit that some participant has
+/// not yet acknowledged applying. A participant that applied a commit answers
+/// every later `DoCommit` for it with another acknowledgment, so telling it
+/// again is always safe.
+#[derive(Debug, Default)]
+pub(super) struct CommitCompletion {
+    pending: BTreeMap<TransactionId, PendingCommit>
+}
+
+#[derive(Debug)]
+struct PendingCommit {
+    waiting: BTreeSet<NodeId>,
+    decided: Instant,
+    sent: Instant,
+    retries: u32
+}
+
+pub(super) type SharedCompletion = Arc<Mutex<CommitCompletion>>;
+
+impl CommitCompletion {
+    /// Records that `participants` were just told to commit a transaction.
+    pub(super) fn decided(&mut self, tx_id: TransactionId, participants: impl IntoIterator<Item = NodeId>, now: Instant) {
+        let waiting: BTreeSet<_> = participants.into_iter().collect();
+        if !waiting.is_empty() {
+            self.pending.insert(tx_id, PendingCommit { waiting, decided: now, sent: now, retries: 0 });
+        }
+    }
+
+    /// Records that a participant applied a commit, returning true if it was
+    /// the last one to.
+    pub(super) fn acked(&mut self, tx_id: &TransactionId, node_id: NodeId) -> bool {
+        let Some(pending) = self.pending.get_mut(tx_id) else {
+            return false;
+        };
+
+        if !pending.waiting.remove(&node_id) || !pending.waiting.is_empty() {
+            return false;
+        }
+
+        self.pending.remove(tx_id);
+        true
+    }
+
+    /// Returns the participants to tell to commit again, by transaction:
+    /// those that have not acknowledged it within `interval` of last being
+    /// told.
+    pub(super) fn due(&mut self, now: Instant, interval: Duration) -> Vec<(TransactionId, Vec<NodeId>)> {
+        self.pending
+            .iter_mut()
+            .filter(|(_, pending)| now.duration_since(pending.sent) >= interval)
+            .map(|(tx_id, pending)| {
+                pending.sent = now;
+                pending.retries += 1;
+                (*tx_id, pending.waiting.iter().copied().collect())
+            })
+            .collect()
+    }
+
+    pub(super) fn unapplied(&self, now: Instant) -> Vec<UnappliedCommit> {
+        self.pending
+            .iter()
+            .map(|(tx_id, pending)| UnappliedCommit {
+                tx_id: *tx_id,
+                waiting: pending.waiting.iter().copied().collect(),
+                since: now.duration_since(pending.decided),
+                retries: pending.retries
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sharding::fixture::tx_at;
+    use super::*;
+
+    #[test]
+    fn test_commits_pending_until_every_participant_acks() {
+        let start = Instant::now();
+        let mut completion = CommitCompletion::default();
+        completion.decided(tx_at(1), [NodeId(1), NodeId(2)], start);
+        completion.decided(tx_at(2), [], start);
+        assert_eq!(completion.unapplied(start).len(), 1);
+
+        assert!(!completion.acked(&tx_at(1), NodeId(1)));
+        assert!(!completion.acked(&tx_at(1), NodeId(1)));
+        assert!(completion.due(start + Duration::from_millis(500), COMMIT_RETRY_INTERVAL).is_empty());
+        let later = start + COMMIT_RETRY_INTERVAL;
+        assert_eq!(completion.due(later, COMMIT_RETRY_INTERVAL), vec![(tx_at(1), vec![NodeId(2)])]);
+        assert!(completion.due(later, COMMIT_RETRY_INTERVAL).is_empty());
+
+        let unapplied = completion.unapplied(later);
+        assert_eq!((unapplied[0].waiting.clone(), unapplied[0].since, unapplied[0].retries), (vec![NodeId(2)], COMMIT_RETRY_INTERVAL, 1));
+        assert!(completion.acked(&tx_at(1), NodeId(2)));
+        assert!(completion.unapplied(later).is_empty());
+        assert!(!completion.acked(&tx_at(1), NodeId(2)));
+    }
+}
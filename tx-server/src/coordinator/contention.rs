@@ -0,0 +1,148 @@
+use tx_common::admin::{ConcurrencyMode, ContentionStatus};
+use std::{sync::{Arc, Mutex}, time::Duration};
+use super::AtomicShard;
+use log::info;
+
+/// When a node switches its shard between concurrency modes. Timestamp
+/// ordering aborts whichever transaction arrives late, which is cheap while
+/// conflicts are rare. Under contention, it can keep aborting long older
+/// transactions, which wound-wait lets finish by aborting newer readers
+/// instead. The two thresholds and the number of windows a switch takes keep
+/// the shard from flapping between modes as the rate hovers around one.
+#[derive(Clone, Copy, Debug)]
+pub struct ContentionPolicy {
+    /// How often the abort rate is measured
+    pub window: Duration,
+    /// The abort rate at or above which the shard switches to wound-wait
+    pub enter_wound_wait: f64,
+    /// The abort rate below which the shard switches back
+    pub exit_wound_wait: f64,
+    /// The number of consecutive windows past a threshold before a switch
+    pub windows_to_switch: u32,
+    /// Windows with fewer operations say little about contention, so they
+    /// never count towards a switch
+    pub min_operations: u64
+}
+
+impl Default for ContentionPolicy {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(1),
+            enter_wound_wait: 0.2,
+            exit_wound_wait: 0.05,
+            windows_to_switch: 3,
+            min_operations: 32
+        }
+    }
+}
+
+/// Chooses the mode of a shard from the abort rate it measures, unless an
+/// operator pinned one.
+#[derive(Debug, Default)]
+pub(super) struct Controller {
+    pub(super) policy: ContentionPolicy,
+    /// The mode the abort rate calls for, used whenever no mode is pinned
+    adaptive: ConcurrencyMode,
+    pinned: Option<ConcurrencyMode>,
+    /// The number of consecutive windows that called for a switch
+    streak: u32,
+    abort_rate: f64,
+    switches: u64
+}
+
+pub(super) type SharedContention = Arc<Mutex<Controller>>;
+
+impl Controller {
+    pub(super) fn mode(&self) -> ConcurrencyMode {
+        self.pinned.unwrap_or(self.adaptive)
+    }
+
+    /// Records a window's operations and the conflicts among them, returning
+    /// the mode the shard should be in.
+    pub(super) fn observe(&mut self, operations: u64, conflicts: u64) -> ConcurrencyMode {
+        use ConcurrencyMode::*;
+
+        self.abort_rate = if operations == 0 { 0.0 } else { conflicts as f64 / operations as f64 };
+        let wanted = match self.adaptive {
+            _ if operations < self.policy.min_operations => self.adaptive,
+            TimestampOrdering if self.abort_rate >= self.policy.enter_wound_wait => WoundWait,
+            WoundWait if self.abort_rate < self.policy.exit_wound_wait => TimestampOrdering,
+            mode => mode
+        };
+
+        if wanted == self.adaptive {
+            self.streak = 0;
+        } else {
+            self.streak += 1;
+            if self.streak >= self.policy.windows_to_switch {
+                info!("Abort rate is {:.3}: switching from {:?} to {wanted:?}", self.abort_rate, self.adaptive);
+                self.adaptive = wanted;
+                self.streak = 0;
+                self.switches += 1;
+            }
+        }
+
+        self.mode()
+    }
+
+    /// Pins the mode, or lets the abort rate choose it again if `None`,
+    /// returning the mode the shard should be in.
+    pub(super) fn pin(&mut self, mode: Option<ConcurrencyMode>) -> ConcurrencyMode {
+        info!("Concurrency mode pinned to {mode:?}");
+        self.pinned = mode;
+        self.mode()
+    }
+
+    pub(super) fn status(&self) -> ContentionStatus {
+        ContentionStatus {
+            mode: self.mode(),
+            pinned: self.pinned.is_some(),
+            abort_rate: self.abort_rate,
+            switches: self.switches
+        }
+    }
+}
+
+/// Measures the abort rate of `shard` every window of the controller's policy
+/// for as long as the server runs, switching the shard's mode as it decides.
+pub(super) async fn run(shard: AtomicShard, controller: SharedContention) {
+    let window = controller.lock().unwrap().policy.window;
+    loop {
+        tokio::time::sleep(window).await;
+        let (operations, conflicts) = shard.take_contention();
+        let mode = controller.lock().unwrap().observe(operations, conflicts);
+        shard.set_mode(mode);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_switches_with_hysteresis() {
+        use ConcurrencyMode::*;
+
+        let policy = ContentionPolicy { windows_to_switch: 2, ..Default::default() };
+        let mut controller = Controller { policy, ..Default::default() };
+
+        // A single hot window, or a quiet one, is not enough to switch
+        assert_eq!(controller.observe(100, 50), TimestampOrdering);
+        assert_eq!(controller.observe(10, 10), TimestampOrdering);
+        assert_eq!(controller.observe(100, 50), TimestampOrdering);
+        assert_eq!(controller.observe(100, 30), WoundWait);
+
+        // Rates between the thresholds keep the current mode
+        assert_eq!(controller.observe(100, 10), WoundWait);
+        assert_eq!(controller.observe(100, 10), WoundWait);
+        assert_eq!(controller.observe(100, 1), WoundWait);
+        assert_eq!(controller.observe(100, 1), TimestampOrdering);
+        assert_eq!(controller.status().switches, 2);
+
+        // A pinned mode holds whatever the rate until it is released
+        assert_eq!(controller.pin(Some(WoundWait)), WoundWait);
+        assert_eq!(controller.observe(100, 0), WoundWait);
+        assert!(controller.status().pinned);
+        assert_eq!(controller.pin(None), TimestampOrdering);
+    }
+}
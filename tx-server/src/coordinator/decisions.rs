@@ -0,0 +1,112 @@
+use tx_common::{admin::{Decision, DecisionRecord, Vote}, config::NodeId, transaction_id::TransactionId};
+use std::{collections::VecDeque, time::Duration};
+
+pub static DECISION_LOG_CAPACITY: usize = 1024;
+
+/// A bounded log of the most recent two-phase commit decisions made by this
+/// node as a coordinator, or learned by this node as a witness, along with
+/// the aborts its clients gave a reason for. Once the log
+/// is full, recording a decision evicts the oldest one, though it still
+/// counts towards the totals.
+pub struct DecisionLog {
+    records: VecDeque<DecisionRecord>,
+    capacity: usize,
+    /// Every decision recorded, to commit and to abort
+    committed: u64,
+    aborted: u64
+}
+
+impl DecisionLog {
+    pub fn new(capacity: usize) -> Self {
+        Self { records: VecDeque::with_capacity(capacity), capacity, committed: 0, aborted: 0 }
+    }
+
+    pub fn record(&mut self, record: DecisionRecord) {
+        match record.decision {
+            Decision::Commit => self.committed += 1,
+            Decision::Abort => self.aborted += 1
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+
+        self.records.push_back(record);
+    }
+
+    /// Records a decision that a coordinator made and this witness learned.
+    pub fn record_witnessed(&mut self, tx_id: TransactionId, decision: Decision) {
+        self.record(DecisionRecord { tx_id, participants: vec![], votes: vec![], decision, prepare_duration: Duration::ZERO, reason: None });
+    }
+
+    /// An estimate of the bytes the logged decisions hold, their 
+    /// participants, votes and reasons included.
+    pub fn bytes(&self) -> usize {
+        self.records
+            .iter()
+            .map(|r| {
+                size_of::<DecisionRecord>()
+                    + r.participants.len() * size_of::<NodeId>()
+                    + r.votes.len() * size_of::<(NodeId, Vote)>()
+                    + r.reason.as_ref().map_or(0, String::len)
+            })
+            .sum()
+    }
+
+    /// Returns the logged decisions, oldest first.
+    pub fn records(&self) -> Vec<DecisionRecord> {
+        self.records.iter().cloned().collect()
+    }
+
+    /// Returns the logged decisions about a transaction, oldest first.
+    pub fn of(&self, tx_id: &TransactionId) -> Vec<DecisionRecord> {
+        self.records.iter().filter(|r| &r.tx_id == tx_id).cloned().collect()
+    }
+
+        /// Returns how many decisions were recorded to commit and to abort.
+    pub fn totals(&self) -> (u64, u64) {
+        (self.committed, self.aborted)
+    }
+}
+
+impl Default for DecisionLog {
+    fn default() -> Self {
+        Self::new(DECISION_LOG_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::{config::NodeId, transaction_id::TransactionIdGenerator};
+    use super::*;
+
+    fn record(tx_id: tx_common::transaction_id::TransactionId) -> DecisionRecord {
+        DecisionRecord {
+            tx_id,
+            participants: vec![NodeId(0)],
+            votes: vec![],
+            decision: Decision::Commit,
+            prepare_duration: Duration::ZERO,
+            reason: None
+        }
+    }
+
+    #[test]
+    fn test_decision_log_evicts_oldest() {
+        let mut log = DecisionLog::new(2);
+        let mut id_gen = TransactionIdGenerator::new(NodeId(0));
+        let (tx1, tx2, tx3) = (id_gen.next(), id_gen.next(), id_gen.next());
+
+        log.record(record(tx1));
+        log.record(record(tx2));
+        log.record(record(tx3));
+
+        let logged = log.records().into_iter().map(|r| r.tx_id).collect::<Vec<_>>();
+        assert_eq!(logged, vec![tx2, tx3]);
+        assert_eq!(log.totals(), (3, 0));
+    }
+}
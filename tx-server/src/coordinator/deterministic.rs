@@ -0,0 +1,216 @@
+use tx_common::{AccountId, config::{NodeId, ShardMap}};
+use tx_proto::{BalanceDiff, ClientResponse};
+use super::{protocol::*, AtomicShard, SharedReporter, CommitReport, abort_response};
+use crate::sharding::{Abort, TransactionId};
+use tokio::sync::mpsc::*;
+use std::{collections::{BTreeSet, HashMap, HashSet, VecDeque}, sync::Arc, time::Duration};
+use log::{error, trace};
+
+/// How many of the transactions it sequenced last the sequencer and its 
+/// backup keep once the backup holds them, to hand to the shards again after
+/// a failover and to recognise a transaction a coordinator sends again.
+static SEQUENCE_LOG_CAPACITY: usize = 4096;
+/// How long a coordinator waits on the verdicts of a transaction before 
+/// sending it to the sequencer again, in case it was lost with the sequencer.
+pub(super) static SEQUENCE_RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A shard's verdict on a sequenced transaction, tagged with the shard.
+type RoutedVerdict = (TransactionId, NodeId, CommitStatus);
+
+/// The shards a transaction's balance changes write to, sorted.
+pub(super) fn participants(shards: &ShardMap, writes: &[(AccountId, BalanceDiff)]) -> Vec<NodeId> {
+    writes
+        .iter()
+        .filter_map(|(account_id, _)| shards.shard_for(account_id))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// The transactions placed in the cluster's order last, as kept by the 
+/// sequencer and by its backup. The sequencer hands a transaction to the 
+/// shards only once its backup holds a copy, so the backup can take over from
+/// the same position: whatever the shards were handed, it hands them again,
+/// and each shard drops the positions it already ran.
+#[derive(Default)]
+pub(super) struct SequenceLog {
+    entries: VecDeque<SequencedTransaction>,
+    /// The coordinator and client transaction of every entry
+    logged: HashSet<(NodeId, TransactionId)>,
+    /// The position the next transaction is placed at
+    next_seq: u64,
+    /// The positions before this one are held by the backup
+    replicated: u64
+}
+
+impl SequenceLog {
+    /// Whether a coordinator's transaction already has a position.
+    pub(super) fn contains(&self, coordinator: NodeId, client_tx: TransactionId) -> bool {
+        self.logged.contains(&(coordinator, client_tx))
+    }
+
+    /// Places a transaction at the next position, waiting on the backup.
+    pub(super) fn append(&mut self, id: TransactionId, coordinator: NodeId, client_tx: TransactionId, fwd_id: ForwardId, writes: Vec<(AccountId, BalanceDiff)>) -> SequencedTransaction {
+        let tx = SequencedTransaction { seq: self.next_seq, id, coordinator, client_tx, fwd_id, writes };
+        self.push(tx.clone());
+        tx
+    }
+
+    /// Keeps a copy of a transaction the sequencer placed, as its backup. 
+    /// Positions already held are dropped, since a new backup is sent the 
+    /// whole log.
+    pub(super) fn record(&mut self, tx: SequencedTransaction) {
+        if tx.seq >= self.next_seq {
+            self.push(tx);
+            self.replicated = self.next_seq;
+        }
+    }
+
+    fn push(&mut self, tx: SequencedTransaction) {
+        self.next_seq = tx.seq + 1;
+        self.logged.insert((tx.coordinator, tx.client_tx));
+        self.entries.push_back(tx);
+        while self.entries.len() > SEQUENCE_LOG_CAPACITY && self.entries.front().is_some_and(|tx| tx.seq < self.replicated) {
+            let tx = self.entries.pop_front().unwrap();
+            self.logged.remove(&(tx.coordinator, tx.client_tx));
+        }
+    }
+
+    /// Notes that the backup holds every position up to `seq`, returning the
+    /// transactions that can now be handed to the shards.
+    pub(super) fn acknowledge(&mut self, seq: u64) -> Vec<SequencedTransaction> {
+        let held: Vec<_> = self.entries
+            .iter()
+            .filter(|tx| tx.seq >= self.replicated && tx.seq <= seq)
+            .cloned()
+            .collect();
+        self.replicated = self.replicated.max(seq + 1);
+        held
+    }
+
+    /// Hands every transaction over to be copied to a new backup, once this
+    /// node takes over from the sequencer it was the backup of.
+    pub(super) fn take_over(&mut self) {
+        self.replicated = self.entries.front().map_or(self.next_seq, |tx| tx.seq);
+    }
+
+    pub(super) fn entries(&self) -> impl Iterator<Item = &SequencedTransaction> {
+        self.entries.iter()
+    }
+
+    /// The position the last transaction was placed at, if any
+    pub(super) fn last_seq(&self) -> Option<u64> {
+        self.next_seq.checked_sub(1)
+    }
+
+    /// The id of the last transaction placed, the newest of them
+    pub(super) fn last_id(&self) -> Option<TransactionId> {
+        self.entries.back().map(|tx| tx.id)
+    }
+}
+
+/// Where the server task passes the sequenced transactions and verdicts it
+/// receives to its executor.
+pub(super) struct ExecutorHandle {
+    pub(super) sequenced: UnboundedSender<Box<SequencedTransaction>>,
+    pub(super) verdicts: UnboundedSender<RoutedVerdict>
+}
+
+/// Runs the sequenced transactions that write to a node's shard one at a
+/// time, in the order the sequencer gave them. Every shard a transaction
+/// writes to applies its own part and sends its verdict to the others and to
+/// the coordinator, then commits only if every verdict was to commit. Each
+/// shard reaches the same outcome without a two-phase commit, and since all
+/// of them run transactions in the same order, none can wait on another that
+/// is waiting on it.
+pub(super) struct Executor {
+    node_id: NodeId,
+    shard: AtomicShard,
+    shards: Arc<ShardMap>,
+    /// Passes verdicts to the server task, which sends them on
+    out: UnboundedSender<ClientState>,
+    reporter: SharedReporter,
+    sequenced: UnboundedReceiver<Box<SequencedTransaction>>,
+    verdicts: UnboundedReceiver<RoutedVerdict>,
+    /// Verdicts on transactions this shard has not run yet
+    early: HashMap<TransactionId, Vec<(NodeId, CommitStatus)>>,
+    /// The position of the last transaction run
+    last_seq: Option<u64>
+}
+
+impl Executor {
+    pub(super) fn new(node_id: NodeId, shard: AtomicShard, shards: Arc<ShardMap>, out: UnboundedSender<ClientState>, reporter: SharedReporter) -> (Self, ExecutorHandle) {
+        let (sequenced_snd, sequenced) = unbounded_channel();
+        let (verdicts_snd, verdicts) = unbounded_channel();
+        let executor = Self { node_id, shard, shards, out, reporter, sequenced, verdicts, early: HashMap::new(), last_seq: None };
+        (executor, ExecutorHandle { sequenced: sequenced_snd, verdicts: verdicts_snd })
+    }
+
+    pub(super) async fn run(mut self) {
+        while let Some(tx) = self.sequenced.recv().await {
+            if self.last_seq.is_some_and(|last| tx.seq <= last) {
+                error!("Dropping {} sequenced at {}: already ran up to {:?}", tx.id, tx.seq, self.last_seq);
+                continue;
+            }
+
+            self.last_seq = Some(tx.seq);
+            self.execute(tx).await;
+        }
+    }
+
+    async fn execute(&mut self, tx: Box<SequencedTransaction>) {
+        trace!("Running {} sequenced at {} for {}", tx.id, tx.seq, tx.client_tx);
+        let participants = participants(&self.shards, &tx.writes);
+        let verdict = match self.apply(&tx).await {
+            Ok(()) => CommitStatus::ReadyToCommit,
+            Err(resp) => CommitStatus::CannotCommit(resp)
+        };
+
+        for node_id in participants.iter().filter(|node_id| **node_id != self.node_id) {
+            self.send(*node_id, Forwarded::Verdict(tx.id, verdict.clone()));
+        }
+        self.send(tx.coordinator, Forwarded::TwoPhaseCommitStatus(tx.client_tx, tx.fwd_id, verdict.clone()));
+
+        let mut verdicts = self.early.remove(&tx.id).unwrap_or_default();
+        verdicts.push((self.node_id, verdict));
+        while verdicts.len() < participants.len() {
+            match self.verdicts.recv().await {
+                Some((id, node_id, verdict)) if id == tx.id => verdicts.push((node_id, verdict)),
+                Some((id, node_id, verdict)) => self.early.entry(id).or_default().push((node_id, verdict)),
+                None => return
+            }
+        }
+
+        if verdicts.iter().all(|(_, verdict)| matches!(verdict, CommitStatus::ReadyToCommit)) {
+            match self.shard.commit(&tx.id).await {
+                Ok(result) => self.reporter.report(CommitReport::new(self.node_id, tx.id, result)),
+                Err(e) => error!("FATAL ERROR: Failed to commit {}: {e:?}", tx.id)
+            }
+        } else {
+            self.shard.abort(&tx.id).await.unwrap();
+        }
+    }
+
+    /// Applies the transaction's balance changes to this shard's accounts and
+    /// checks that it may commit. Nothing else runs on the shard meanwhile,
+    /// and transactions are run with increasing ids, so none of it waits.
+    async fn apply(&self, tx: &SequencedTransaction) -> Result<(), ClientResponse> {
+        let local = tx.writes.iter().filter(|(account_id, _)| self.shards.shard_for(account_id) == Some(self.node_id));
+        for (account_id, diff) in local {
+            let balance = match self.shard.read(&tx.id, account_id).await {
+                Ok(balance) => balance + diff.0,
+                Err(Abort::ObjectNotFound) => diff.0,
+                Err(e) => return Err(abort_response(e))
+            };
+            self.shard.write(&tx.id, account_id.clone(), balance).await.map_err(abort_response)?;
+        }
+
+        self.shard.check_commit(&tx.id).await.map_err(abort_response)
+    }
+
+    fn send(&self, node_id: NodeId, msg: Forwarded) {
+        if self.out.send(ClientState::Forward(ForwardTarget::Node(node_id), msg)).is_err() {
+            error!("Failed to pass verdict to the server task");
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::sharding::TransactionId;
+use tx_common::admin::DrainStatus;
+use tx_proto::{ClientRequest, ClientResponse};
+use std::{collections::HashSet, sync::{atomic::{AtomicBool, Ordering}, Mutex}, time::Duration};
+use tokio::time::{sleep, Instant};
+use super::{layer::{RequestContext, RequestLayer}, SharedDrain};
+use log::info;
+
+/// How often a node pausing checks whether the work under way has finished
+static QUIESCE_POLL: Duration = Duration::from_millis(10);
+
+/// How long a pause lasts unless it is resumed sooner
+pub static PAUSE_LEASE: Duration = Duration::from_secs(600);
+
+/// Tracks the work a node must finish before it can be stopped. Once the
+/// node starts draining, transactions that have not started yet are refused
+/// and every vote on a transaction that has not prepared here is
+/// `CannotCommit`, so the node only has to wait for the work already under
+/// way to finish. A paused node also refuses new transactions, but lets 
+/// those under way commit, and resumes once the pause is over.
+#[derive(Default)]
+pub(super) struct Drain {
+    draining: AtomicBool,
+    pause: Mutex<Pause>,
+    /// Transactions coordinated by this node that have read or written
+    started: Mutex<HashSet<TransactionId>>,
+    /// Transactions this node voted to commit that were not decided yet
+    prepared: Mutex<HashSet<TransactionId>>
+}
+
+/// The pause a node is under, held by the transaction that asked for it so
+/// that a pause that failed elsewhere only lifts itself, never another 
+/// coordinator's. It lapses after its lease in case its coordinator dies
+/// before lifting it.
+struct Pause {
+    holder: Option<(TransactionId, Instant)>,
+    lease: Duration
+}
+
+impl Default for Pause {
+    fn default() -> Self {
+        Self { holder: None, lease: PAUSE_LEASE }
+    }
+}
+
+impl Pause {
+    /// The transaction holding the pause, lifting the pause if it lapsed.
+    fn holder(&mut self) -> Option<TransactionId> {
+        let (owner, expires) = self.holder?;
+        if Instant::now() < expires {
+            return Some(owner);
+        }
+
+        info!("The pause {owner} placed lapsed after {:?}: resuming", self.lease);
+        self.holder = None;
+        None
+    }
+}
+
+impl Drain {
+    pub(super) fn begin(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub(super) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn set_pause_lease(&self, lease: Duration) {
+        self.pause.lock().unwrap().lease = lease;
+    }
+
+    /// Pauses the node on behalf of `owner` for the pause lease, returning 
+    /// false if another transaction's pause still holds.
+    fn pause(&self, owner: TransactionId) -> bool {
+        let mut pause = self.pause.lock().unwrap();
+        if pause.holder().is_some_and(|holder| holder != owner) {
+            return false;
+        }
+
+        pause.holder = Some((owner, Instant::now() + pause.lease));
+        true
+    }
+
+    fn is_paused(&self) -> bool {
+        self.pause.lock().unwrap().holder().is_some()
+    }
+
+    /// Lifts the pause, whoever placed it.
+    pub(super) fn resume(&self) {
+        self.pause.lock().unwrap().holder = None;
+    }
+
+    /// Lifts the pause if `owner` placed it.
+    pub(super) fn release(&self, owner: &TransactionId) {
+        let mut pause = self.pause.lock().unwrap();
+        if pause.holder() == Some(*owner) {
+            pause.holder = None;
+        }
+    }
+
+    /// Pauses the node on behalf of `owner` and waits up to `within` for the
+    /// transactions under way on it to finish, returning whether they did. 
+    /// The node stays paused either way, unless another transaction's pause
+    /// already holds, which fails at once.
+    pub(super) async fn quiesce(&self, owner: TransactionId, within: Duration) -> bool {
+        if !self.pause(owner) {
+            info!("Not pausing for {owner}: another pause holds");
+            return false;
+        }
+
+        let deadline = Instant::now() + within;
+        loop {
+            let status = self.status();
+            if status.in_flight == 0 && status.prepared == 0 {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                info!("Not quiesced after {within:?}: {status:?}");
+                return false;
+            }
+            sleep(QUIESCE_POLL.min(deadline - Instant::now())).await;
+        }
+    }
+
+    /// Records that a transaction coordinated by this node is about to read
+    /// or write, returning false if the node is draining or paused and the 
+    /// transaction must be refused since it has not started yet.
+    pub(super) fn start(&self, tx_id: TransactionId) -> bool {
+        let mut started = self.started.lock().unwrap();
+        if started.contains(&tx_id) {
+            return true;
+        }
+
+        if self.is_draining() || self.is_paused() {
+            return false;
+        }
+
+        started.insert(tx_id);
+        true
+    }
+
+    pub(super) fn finish(&self, tx_id: &TransactionId) {
+        self.started.lock().unwrap().remove(tx_id);
+    }
+
+    /// Records a vote to commit, returning false if the node is draining and
+    /// must vote `CannotCommit` instead.
+    pub(super) fn prepare(&self, tx_id: TransactionId) -> bool {
+        let mut prepared = self.prepared.lock().unwrap();
+        if self.is_draining() {
+            return false;
+        }
+
+        prepared.insert(tx_id);
+        true
+    }
+
+    pub(super) fn decide(&self, tx_id: &TransactionId) {
+        self.prepared.lock().unwrap().remove(tx_id);
+    }
+
+    pub(super) fn status(&self) -> DrainStatus {
+        let in_flight = self.started.lock().unwrap().len();
+        let prepared = self.prepared.lock().unwrap().len();
+
+        DrainStatus {
+            in_flight,
+            prepared,
+            safe_to_stop: self.is_draining() && in_flight == 0 && prepared == 0
+        }
+    }
+}
+
+/// Records when a transaction starts to read or write, refusing it if the
+/// node is already draining or paused.
+pub(super) struct DrainLayer(pub(super) SharedDrain);
+
+impl RequestLayer for DrainLayer {
+    fn before(&mut self, cx: &RequestContext, request: &ClientRequest) -> Option<ClientResponse> {
+        let starts_work = matches!(
+            request, 
+            ClientRequest::WriteBalance(..) | ClientRequest::WriteBalanceWithTtl(..) | ClientRequest::Increment(..) | ClientRequest::ReadBalance(_) | ClientRequest::ReadBalanceIfExists(_) | ClientRequest::ReadBalanceStale(..) | ClientRequest::Swap(..) | ClientRequest::AssertBalance(..)
+        );
+        if !cx.active || !starts_work || self.0.start(cx.tx_id) {
+            return None;
+        }
+
+        info!("Refusing {} since the server is draining or paused", cx.tx_id);
+        Some(ClientResponse::AbortedDraining)
+    }
+}
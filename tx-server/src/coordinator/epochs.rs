@@ -0,0 +1,72 @@
+use tx_common::config::NodeId;
+use tx_proto::ClientRequest;
+use std::{collections::BTreeMap, time::Duration};
+use super::protocol::Forwarded;
+
+/// Whether a message is part of a two-phase commit, which is what a node
+/// batches per epoch: prepares, votes, decisions and acknowledgments. Reads
+/// and writes are still sent right away.
+pub(super) fn is_commit_round(msg: &Forwarded) -> bool {
+    match msg {
+        Forwarded::Request(_, _, _, request) => matches!(**request, ClientRequest::Commit | ClientRequest::Abort),
+        Forwarded::TwoPhaseCommitStatus(..) | Forwarded::DoCommit(..) | Forwarded::CommitAck(_) => true,
+        _ => false
+    }
+}
+
+/// Holds the two-phase commit messages a node sends each peer during an
+/// epoch, so that every transaction prepared or decided in the epoch costs
+/// the peer one message between them rather than one each. A transaction's
+/// commit takes up to an epoch longer per round in exchange.
+#[derive(Debug)]
+pub(super) struct CommitEpochs {
+    pub(super) length: Duration,
+    held: BTreeMap<NodeId, Vec<Forwarded>>
+}
+
+impl CommitEpochs {
+    pub(super) fn new(length: Duration) -> Self {
+        Self { length, held: BTreeMap::new() }
+    }
+
+    pub(super) fn hold(&mut self, node_id: NodeId, msg: Forwarded) {
+        self.held.entry(node_id).or_default().push(msg);
+    }
+
+    /// Ends the epoch, returning the message to send each peer that was sent
+    /// anything during it. A lone message is sent as is.
+    pub(super) fn end(&mut self) -> Vec<(NodeId, Forwarded)> {
+        std::mem::take(&mut self.held)
+            .into_iter()
+            .map(|(node_id, mut msgs)| match msgs.len() {
+                1 => (node_id, msgs.pop().unwrap()),
+                _ => (node_id, Forwarded::Batch(msgs))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sharding::fixture::tx_at;
+    use tx_proto::{BalanceDiff, ClientResponse};
+    use super::*;
+
+    #[test]
+    fn test_epoch_batches_commit_rounds_per_peer() {
+        let request = |request| Forwarded::Request(tx_at(1), 0, None, Box::new(request));
+        assert!(is_commit_round(&request(ClientRequest::Commit)));
+        assert!(is_commit_round(&request(ClientRequest::Abort)));
+        assert!(is_commit_round(&Forwarded::DoCommit(tx_at(1), None)));
+        assert!(!is_commit_round(&request(ClientRequest::WriteBalance("A.x".into(), BalanceDiff(1)))));
+        assert!(!is_commit_round(&Forwarded::Response(tx_at(1), 0, ClientResponse::Aborted)));
+
+        let mut epochs = CommitEpochs::new(Duration::from_millis(5));
+        epochs.hold(NodeId(1), Forwarded::DoCommit(tx_at(1), None));
+        epochs.hold(NodeId(2), Forwarded::DoCommit(tx_at(1), None));
+        epochs.hold(NodeId(2), Forwarded::DoCommit(tx_at(2), None));
+        let ended = epochs.end();
+        assert!(matches!(ended[..], [(NodeId(1), Forwarded::DoCommit(..)), (NodeId(2), Forwarded::Batch(ref batch))] if batch.len() == 2));
+        assert!(epochs.end().is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+use tx_common::config::NodeId;
+use std::{collections::HashMap, time::Duration};
+use super::protocol::{ForwardId, ShardReply, ShardReplies};
+
+pub static FORWARD_RETRIES: u32 = 3;
+pub static FORWARD_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How a request forwarded to a shard that has not joined yet is retried
+/// before the transaction is aborted, in case the shard is about to join.
+/// Transactions have no deadline, so the budget bounds how long a request
+/// may wait on a missing shard.
+#[derive(Clone, Copy, Debug)]
+pub struct ForwardRetry {
+    /// The number of times each forward is sent again
+    pub max_retries: u32,
+    /// How long to wait before each retry
+    pub backoff: Duration
+}
+
+impl Default for ForwardRetry {
+    fn default() -> Self {
+        Self { max_retries: FORWARD_RETRIES, backoff: FORWARD_RETRY_BACKOFF }
+    }
+}
+
+/// The forwards a client handler sent that were not fully answered yet.
+/// Replies are matched to the forward they answer by its id, so several
+/// forwards may be in flight at once and their replies may arrive in any order.
+#[derive(Default)]
+pub(super) struct PendingForwards {
+    next_id: ForwardId,
+    /// The replies received so far to each pending forward
+    replies: HashMap<ForwardId, ShardReplies>
+}
+
+impl PendingForwards {
+    /// Allocates the id of a new forward and starts collecting its replies.
+    pub(super) fn start(&mut self) -> ForwardId {
+        let fwd_id = self.next_id;
+        self.next_id += 1;
+        self.replies.insert(fwd_id, Vec::new());
+        fwd_id
+    }
+
+    /// Records a reply, returning false if it answers no pending forward.
+    pub(super) fn deliver(&mut self, fwd_id: ForwardId, node_id: NodeId, reply: ShardReply) -> bool {
+        match self.replies.get_mut(&fwd_id) {
+            Some(replies) => {
+                replies.push((node_id, reply));
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Takes the replies to a forward once `expected` replies to it arrived,
+    /// after which the forward is no longer pending.
+    pub(super) fn take(&mut self, fwd_id: ForwardId, expected: usize) -> Option<ShardReplies> {
+        if self.replies.get(&fwd_id)?.len() < expected {
+            return None;
+        }
+
+        self.replies.remove(&fwd_id)
+    }
+
+    /// Stops collecting replies to a forward that is no longer awaited.
+    pub(super) fn abandon(&mut self, fwd_id: ForwardId) {
+        self.replies.remove(&fwd_id);
+    }
+
+    /// Collects replies to a forward again after they were taken, for a 
+    /// forward whose replies are awaited a few at a time.
+    pub(super) fn resume(&mut self, fwd_id: ForwardId) {
+        self.replies.entry(fwd_id).or_default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_proto::ClientResponse;
+    use super::*;
+
+    #[test]
+    fn test_crossed_replies_reach_their_forward() {
+        let mut pending = PendingForwards::default();
+        let (read, broadcast) = (pending.start(), pending.start());
+        let value = |amount| ShardReply::Response(ClientResponse::Value("B.x".into(), amount));
+
+        assert!(pending.deliver(broadcast, NodeId(2), ShardReply::Unreachable));
+        assert!(pending.deliver(read, NodeId(1), value(5)));
+        assert!(pending.take(broadcast, 2).is_none());
+        assert!(matches!(pending.take(read, 1).as_deref(), Some([(NodeId(1), ShardReply::Response(ClientResponse::Value(_, 5)))])));
+
+        // A late reply to a forward that is no longer pending is not mixed in
+        assert!(!pending.deliver(read, NodeId(1), value(6)));
+        assert!(pending.deliver(broadcast, NodeId(1), ShardReply::Unreachable));
+        assert_eq!(pending.take(broadcast, 2).map(|replies| replies.len()), Some(2));
+    }
+}
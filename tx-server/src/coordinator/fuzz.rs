@@ -0,0 +1,506 @@
+//! A randomized harness for the server task of node `A` in a cluster of
+//! three. It stands in for the server's event loop, its clients' peers and
+//! the network, feeding `handle_client_state` and `handle_server_state` the
+//! messages real client handlers and peers send, in an order picked by a
+//! seeded random number generator. Peers handle requests on separate tasks,
+//! so their replies arrive in any order and arbitrarily late: after the
+//! client gave up on them, after the client finished, or long after the
+//! commit they acknowledge was retried. Now and then the server loses the 
+//! link to a peer, as when sending to it fails, while the peer's requests 
+//! are still queued, and the peer joins again later. Every run checks that the server
+//! never panics, never tells one participant to commit a transaction and
+//! another to abort it, and forgets every transaction once it is over.
+//!
+//! A failing run names its seed. Set `TX_REPLAY_SEED` to it to run that seed
+//! alone, such as `TX_REPLAY_SEED=7 cargo test -p tx-server fuzz`. The
+//! harness links the server to its clients and peers in memory and runs on
+//! one thread with tokio's clock paused, so a replay draws the same choices
+//! at the same virtual times as the original run. A replay is not fully
+//! deterministic, though: the server takes transaction ids from the system
+//! clock and iterates hash maps, so a failure that hinges on either may not
+//! come back.
+
+use tx_common::admin::Decision;
+use tx_proto::{BalanceDiff, CommitVerbosity};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use tokio::{task::JoinHandle, time::{sleep, timeout}};
+use super::*;
+use crate::testing::local_config;
+
+const A: NodeId = NodeId(0);
+const PEERS: [NodeId; 2] = [NodeId(1), NodeId(2)];
+const ACCOUNTS: [&str; 5] = ["A.a", "A.b", "B.x", "B.y", "C.z"];
+
+const SEEDS: u64 = 16;
+const CLIENTS: usize = 12;
+/// Transactions coordinated by the peers that operate on the server's shard
+const REMOTE_TRANSACTIONS: usize = 6;
+/// How long the server may take to answer a client, or to quiet down once
+/// every transaction finished, before the run counts as stuck
+const STUCK_AFTER: Duration = Duration::from_secs(10);
+/// How often the harness delivers messages and runs the server's handlers
+const TICK: Duration = Duration::from_millis(1);
+/// The most times the server loses the link to a peer in a run
+const DISCONNECTS: usize = 2;
+
+/// The seeds to run: the one in `TX_REPLAY_SEED` if it is set, or the first
+/// `SEEDS` otherwise.
+fn seeds() -> Vec<u64> {
+    match tx_common::replay::replay_seed() {
+        Ok(Some(seed)) => vec![seed],
+        Ok(None) => (0..SEEDS).collect(),
+        Err(e) => panic!("{e}")
+    }
+}
+
+fn random_operation(rng: &mut StdRng, accounts: &[&str]) -> ClientRequest {
+    let account_id = accounts.choose(rng).unwrap().to_string();
+    match rng.gen_range(0..3) {
+        0 => ClientRequest::ReadBalance(account_id),
+        1 => ClientRequest::WriteBalance(account_id, BalanceDiff(rng.gen_range(-5..20))),
+        _ => ClientRequest::Increment(account_id, BalanceDiff(rng.gen_range(1..5)))
+    }
+}
+
+/// The requests of a client, which commits, aborts, or disconnects in the
+/// middle of its transaction.
+fn random_script(rng: &mut StdRng) -> Vec<ClientRequest> {
+    let mut script = Vec::new();
+    if rng.gen_bool(0.3) {
+        script.push(ClientRequest::Verbosity(CommitVerbosity::Values));
+    }
+    for _ in 0..rng.gen_range(1..=4) {
+        script.push(random_operation(rng, &ACCOUNTS));
+    }
+    match rng.gen_range(0..10) {
+        0..=5 => script.push(ClientRequest::Commit),
+        6..=7 => script.push(ClientRequest::Abort),
+        _ => ()
+    }
+    script
+}
+
+/// Sends a client's requests one at a time, then disconnects.
+fn run_script(mut stream: MessageStream, script: Vec<ClientRequest>, seed: u64) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        for (i, request) in script.into_iter().enumerate() {
+            // The server already read the first request
+            if i > 0 {
+                stream.send(request).await.unwrap();
+            }
+            match timeout(STUCK_AFTER, stream.recv::<ClientResponse>()).await {
+                Ok(Some(Ok(_))) => (),
+                Ok(resp) => panic!("seed {seed}: client connection failed: {resp:?}"),
+                Err(_) => panic!("seed {seed}: client never got a response")
+            }
+        }
+    })
+}
+
+/// What the peers saw of a transaction the server coordinates.
+#[derive(Default)]
+struct Outcome {
+    /// The peers asked to vote, and whether each can commit
+    votes: HashMap<NodeId, bool>,
+    committed: BTreeSet<NodeId>,
+    aborted: BTreeSet<NodeId>
+}
+
+/// A transaction a peer coordinates, on the server's shard only.
+struct RemoteTransaction {
+    coordinator: NodeId,
+    tx_id: TransactionId,
+    /// The requests left to send after `last`, ending with `Commit` or `Abort`
+    requests: VecDeque<ClientRequest>,
+    last: ClientRequest,
+    /// The forward awaiting the server's reply, if any
+    awaiting: Option<ForwardId>,
+    /// The prepare the peer gave up waiting on, whose vote it ignores
+    abandoned: Option<ForwardId>,
+    /// Replies to forwards before this one were sent before the peer lost
+    /// its link to the server, and are ignored
+    stale_below: ForwardId,
+    next_fwd: ForwardId,
+    /// Whether the server was told to commit, once it voted to
+    committing: bool,
+    acked: bool,
+    aborted: bool
+}
+
+impl RemoteTransaction {
+    fn start_forward(&mut self) -> ForwardId {
+        let fwd_id = self.next_fwd;
+        self.next_fwd += 1;
+        self.awaiting = Some(fwd_id);
+        fwd_id
+    }
+
+    fn send(&mut self, request: ClientRequest) -> Forwarded {
+        let fwd_id = self.start_forward();
+        self.last = request.clone();
+        Forwarded::Request(self.tx_id, fwd_id, None, Box::new(request))
+    }
+
+    fn done(&self) -> bool {
+        self.aborted || (self.committing && self.acked && self.awaiting.is_none())
+    }
+}
+
+struct Harness {
+    seed: u64,
+    rng: StdRng,
+    server: Server,
+    /// What the server sends each peer, read off the peers' ends of the links
+    outgoing: UnboundedReceiver<(NodeId, Forwarded)>,
+    outgoing_snd: UnboundedSender<(NodeId, Forwarded)>,
+    /// The peer whose link the server lost, and the tick it joins again at
+    lost: Option<(NodeId, u64)>,
+    disconnects: usize,
+    /// Replies and requests the peers sent, with the tick they arrive at
+    in_flight: Vec<(u64, NodeId, Forwarded)>,
+    /// How many ticks a peer may take to reply, which it may exceed the
+    /// server's time box by
+    max_delay: u64,
+    tick: u64,
+    outcomes: HashMap<TransactionId, Outcome>,
+    remote: Vec<RemoteTransaction>,
+    remote_ids: TransactionIdGenerator
+}
+
+impl Harness {
+    async fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let config = local_config(&["A", "B", "C"]);
+        let time_box = rng.gen_bool(0.5).then(|| Duration::from_millis(rng.gen_range(2..20)));
+        let mut server = Server::start_with_min_peers(A, config, 5, 0)
+            .await
+            // Every link counts as degraded once it sent anything, so the
+            // time box applies to every forward
+            .with_health_policy(HealthPolicy { max_queue: 1024, max_latency: Duration::ZERO })
+            .with_degraded_time_box(time_box)
+            .with_commit_retry(Some(Duration::from_millis(rng.gen_range(2..20))))
+            // Only a node that gossips outlives losing a peer
+            .with_gossip(Some(GOSSIP_INTERVAL));
+
+        let (outgoing_snd, outgoing) = unbounded_channel();
+        for node_id in PEERS {
+            Self::link(&mut server, node_id, outgoing_snd.clone());
+        }
+
+        Self {
+            seed,
+            max_delay: rng.gen_range(1..40),
+            rng,
+            server,
+            outgoing,
+            outgoing_snd,
+            lost: None,
+            disconnects: 0,
+            in_flight: Vec::new(),
+            tick: 0,
+            outcomes: HashMap::new(),
+            remote: Vec::new(),
+            remote_ids: TransactionIdGenerator::new(PEERS[0])
+        }
+    }
+
+    /// Links the server to a peer in memory, passing on what it sends the 
+    /// peer to `outgoing_snd`.
+    fn link(server: &mut Server, node_id: NodeId, outgoing_snd: UnboundedSender<(NodeId, Forwarded)>) {
+        let (mut peer_end, server_end) = MessageStream::in_memory();
+        server.admit_peer(server_end, node_id);
+        tokio::spawn(async move {
+            while let Some(Ok(msg)) = peer_end.recv::<Forwarded>().await {
+                if outgoing_snd.send((node_id, msg)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Has the server lose the link to a peer, as it does when sending to
+    /// the peer fails. What the peer sent before is still delivered, so the
+    /// server handles requests from a peer it can no longer reply to.
+    fn disconnect(&mut self) {
+        let node_id = *PEERS.choose(&mut self.rng).unwrap();
+        self.lost = Some((node_id, self.tick + self.rng.gen_range(1..=self.max_delay * 2)));
+        self.disconnects += 1;
+        self.server.lose_peer(node_id);
+    }
+
+    /// Links the lost peer to the server again. The peer lost the replies to
+    /// the transactions it coordinates on the server's shard, so it aborts
+    /// those under way and tells the server again to commit those it decided
+    /// to commit.
+    fn rejoin(&mut self) {
+        let Some((node_id, _)) = self.lost.take() else { return };
+        Self::link(&mut self.server, node_id, self.outgoing_snd.clone());
+        let mut resent = Vec::new();
+        for remote in self.remote.iter_mut().filter(|remote| remote.coordinator == node_id && !remote.done()) {
+            remote.stale_below = remote.next_fwd;
+            remote.awaiting = None;
+            if remote.committing {
+                if !remote.acked {
+                    resent.push(Forwarded::DoCommit(remote.tx_id, None));
+                }
+            } else {
+                resent.push(remote.send(ClientRequest::Abort));
+            }
+        }
+        for msg in resent {
+            self.arrive(node_id, msg, 0);
+        }
+    }
+
+    async fn connect_client(&mut self) -> JoinHandle<()> {
+        let script = random_script(&mut self.rng);
+        let (client_end, server_end) = MessageStream::in_memory();
+        let slot = self.server.slots.take().expect("the fuzz test stays under the client limit");
+        self.server.accept_client(server_end, EMBEDDED_PEER, script[0].clone(), slot);
+        run_script(client_end, script, self.seed)
+    }
+
+    fn start_remote_transaction(&mut self) {
+        let mut requests: VecDeque<_> = (0..self.rng.gen_range(1..=3))
+            .map(|_| random_operation(&mut self.rng, &ACCOUNTS[..2]))
+            .collect();
+        requests.push_back(if self.rng.gen_bool(0.8) { ClientRequest::Commit } else { ClientRequest::Abort });
+        let coordinator = *PEERS.choose(&mut self.rng).unwrap();
+        let mut remote = RemoteTransaction {
+            coordinator,
+            tx_id: TransactionId::at(self.remote_ids.next().timestamp(), coordinator),
+            requests,
+            last: ClientRequest::Abort,
+            awaiting: None,
+            abandoned: None,
+            stale_below: 0,
+            next_fwd: 0,
+            committing: false,
+            acked: false,
+            aborted: false
+        };
+        let request = remote.requests.pop_front().unwrap();
+        let msg = remote.send(request);
+        self.arrive(remote.coordinator, msg, 0);
+        self.remote.push(remote);
+    }
+
+    /// Has a message from a peer arrive within `max_delay` ticks.
+    fn arrive(&mut self, sender_id: NodeId, msg: Forwarded, max_delay: u64) {
+        let due = self.tick + self.rng.gen_range(0..=max_delay);
+        self.in_flight.push((due, sender_id, msg));
+    }
+
+    /// A peer's reply to something the server sent it, checking that the
+    /// server never decides a transaction both ways.
+    fn reply(&mut self, node_id: NodeId, msg: Forwarded) {
+        let seed = self.seed;
+        let max_delay = self.max_delay;
+        match msg {
+            Forwarded::Request(tx_id, fwd_id, _, request) => {
+                let outcome = self.outcomes.entry(tx_id).or_default();
+                let resp = match *request {
+                    ClientRequest::Commit => {
+                        let ready = self.rng.gen_bool(0.8);
+                        outcome.votes.insert(node_id, ready);
+                        let status = match ready {
+                            true => CommitStatus::ReadyToCommit,
+                            false => CommitStatus::CannotCommit(ClientResponse::AbortedConflict("B.x".into(), tx_id))
+                        };
+                        self.arrive(node_id, Forwarded::TwoPhaseCommitStatus(tx_id, fwd_id, status), max_delay);
+                        return;
+                    },
+                    ClientRequest::Abort => {
+                        assert!(outcome.committed.is_empty(), "seed {seed}: {tx_id} aborted on {node_id} after committing on {:?}", outcome.committed);
+                        outcome.aborted.insert(node_id);
+                        ClientResponse::Aborted
+                    },
+                    ClientRequest::ReadBalance(account_id) if self.rng.gen_bool(0.8) => ClientResponse::Value(account_id, 100),
+                    ClientRequest::WriteBalance(..) | ClientRequest::Increment(..) if self.rng.gen_bool(0.8) => ClientResponse::Ok,
+                    _ => ClientResponse::AbortedConflict("B.x".into(), tx_id)
+                };
+                self.arrive(node_id, Forwarded::Response(tx_id, fwd_id, resp), max_delay);
+            },
+            Forwarded::DoCommit(tx_id, ack) => {
+                let outcome = self.outcomes.entry(tx_id).or_default();
+                assert!(outcome.aborted.is_empty(), "seed {seed}: {tx_id} committed on {node_id} after aborting on {:?}", outcome.aborted);
+                assert_eq!(outcome.votes.get(&node_id), Some(&true), "seed {seed}: {tx_id} committed on {node_id}, which did not vote to");
+                assert!(outcome.votes.values().all(|ready| *ready), "seed {seed}: {tx_id} committed despite a vote to abort: {:?}", outcome.votes);
+                outcome.committed.insert(node_id);
+                self.arrive(node_id, Forwarded::CommitAck(tx_id), max_delay);
+                if let Some(fwd_id) = ack {
+                    self.arrive(node_id, Forwarded::Response(tx_id, fwd_id, ClientResponse::CommitOkWithValues(Vec::new())), max_delay);
+                }
+            },
+            // Replies to a transaction the peer coordinates
+            Forwarded::Response(tx_id, fwd_id, resp) => self.remote_reply(tx_id, fwd_id, resp.is_err()),
+            Forwarded::TwoPhaseCommitStatus(tx_id, fwd_id, status) =>
+                self.remote_reply(tx_id, fwd_id, !matches!(status, CommitStatus::ReadyToCommit)),
+            Forwarded::CommitAck(tx_id) => {
+                let remote = self.remote.iter_mut().find(|remote| remote.tx_id == tx_id).unwrap();
+                assert!(remote.committing, "seed {seed}: {tx_id} acknowledged a commit it was not told of");
+                remote.acked = true;
+            },
+            msg => panic!("seed {seed}: server sent {node_id} {msg:?}")
+        }
+    }
+
+    /// Sends the next request of a transaction a peer coordinates once the
+    /// server answered the last, aborting it if the server could not do what
+    /// it was asked. A transaction the server votes to commit is committed,
+    /// and the commit is now and then sent again as if it went unacknowledged.
+    /// Now and then the peer gives up on the server's vote, as a coordinator
+    /// whose time box ran out would, and aborts while the prepare is still
+    /// under way.
+    fn remote_reply(&mut self, tx_id: TransactionId, fwd_id: ForwardId, failed: bool) {
+        let seed = self.seed;
+        let (wants_values, retried, gives_up) = (self.rng.gen_bool(0.5), self.rng.gen_bool(0.3), self.rng.gen_bool(0.2));
+        let remote = self.remote.iter_mut().find(|remote| remote.tx_id == tx_id).unwrap();
+        if remote.abandoned == Some(fwd_id) || fwd_id < remote.stale_below {
+            return;
+        }
+        assert_eq!(remote.awaiting, Some(fwd_id), "seed {seed}: reply to forward {fwd_id} of {tx_id}, which was not awaited");
+        remote.awaiting = None;
+
+        let msg = match remote.last {
+            // The balances the commit wrote
+            _ if remote.committing => return,
+            ClientRequest::Abort => {
+                remote.aborted = true;
+                return;
+            },
+            ClientRequest::Commit if !failed => {
+                remote.committing = true;
+                let ack = wants_values.then(|| remote.start_forward());
+                Forwarded::DoCommit(tx_id, ack)
+            },
+            _ if failed => remote.send(ClientRequest::Abort),
+            _ => {
+                let request = remote.requests.pop_front().unwrap();
+                remote.send(request)
+            }
+        };
+        let abort = (gives_up && matches!(remote.last, ClientRequest::Commit) && !remote.committing).then(|| {
+            remote.abandoned = remote.awaiting;
+            remote.send(ClientRequest::Abort)
+        });
+        let coordinator = remote.coordinator;
+        if retried && matches!(msg, Forwarded::DoCommit(..)) {
+            self.arrive(coordinator, Forwarded::DoCommit(tx_id, None), self.max_delay);
+        }
+        self.arrive(coordinator, msg, 0);
+        if let Some(abort) = abort {
+            self.arrive(coordinator, abort, 0);
+        }
+    }
+
+    /// Runs the server's handlers on everything its clients and peers sent
+    /// it so far, as its event loop would.
+    fn pump(&mut self) {
+        match self.lost {
+            Some((_, rejoin_at)) if rejoin_at <= self.tick => self.rejoin(),
+            None if self.disconnects < DISCONNECTS && self.rng.gen_bool(0.01) => self.disconnect(),
+            _ => ()
+        }
+        while let Ok(state) = self.server.from_clients.try_recv() {
+            self.server.handle_client_state(state);
+        }
+        while let Ok(state) = self.server.from_servers.try_recv() {
+            self.server.handle_server_state(state);
+        }
+        self.server.retry_commits();
+
+        while let Ok((node_id, msg)) = self.outgoing.try_recv() {
+            self.reply(node_id, msg);
+        }
+
+        // Replies due at the same tick arrive in any order
+        let (mut due, later) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(due, ..)| *due <= self.tick);
+        self.in_flight = later;
+        due.shuffle(&mut self.rng);
+        for (_, member_id, msg) in due {
+            self.server.handle_server_state(ServerStateMessage { msg: ServerStateMessageType::Message(msg), member_id });
+        }
+
+        // Now and then, a reply meant for an earlier run of the server,
+        // whose transactions it never heard of
+        if self.rng.gen_bool(0.05) {
+            let tx_id = TransactionId::at(self.rng.gen_range(1..1000), A);
+            let member_id = *PEERS.choose(&mut self.rng).unwrap();
+            let msg = match self.rng.gen_range(0..3) {
+                0 => Forwarded::Response(tx_id, self.rng.gen_range(0..4), ClientResponse::Aborted),
+                1 => Forwarded::TwoPhaseCommitStatus(tx_id, self.rng.gen_range(0..4), CommitStatus::ReadyToCommit),
+                _ => Forwarded::CommitAck(tx_id)
+            };
+            self.server.handle_server_state(ServerStateMessage { msg: ServerStateMessageType::Message(msg), member_id });
+        }
+
+        self.tick += 1;
+    }
+
+    fn quiet(&self) -> bool {
+        self.lost.is_none() && self.in_flight.is_empty() && self.remote.iter().all(RemoteTransaction::done)
+    }
+
+    async fn run(mut self) {
+        let seed = self.seed;
+        let mut clients = Vec::new();
+        let started = Instant::now();
+        while clients.len() < CLIENTS || self.remote.len() < REMOTE_TRANSACTIONS || !clients.iter().all(JoinHandle::is_finished) || !self.quiet() {
+            assert!(started.elapsed() < 3 * STUCK_AFTER, "seed {seed}: stuck with {} messages in flight", self.in_flight.len());
+            if clients.len() < CLIENTS && self.rng.gen_bool(0.2) {
+                clients.push(self.connect_client().await);
+            }
+            if self.remote.len() < REMOTE_TRANSACTIONS && self.rng.gen_bool(0.1) {
+                self.start_remote_transaction();
+            }
+            self.pump();
+            sleep(TICK).await;
+        }
+        for client in clients {
+            client.await.unwrap();
+        }
+
+        // Let the clients' handlers finish and the last commits apply
+        let settled = Instant::now();
+        while !self.server.clients.is_empty() || !self.server.completion.lock().unwrap().unapplied(Instant::now()).is_empty() || !self.quiet() {
+            assert!(settled.elapsed() < STUCK_AFTER, "seed {seed}: {} clients never finished", self.server.clients.len());
+            self.pump();
+            sleep(TICK).await;
+        }
+        self.check().await;
+    }
+
+    /// Checks that the server forgot every transaction once it was over.
+    async fn check(&self) {
+        let seed = self.seed;
+        let records = self.server.decisions.lock().unwrap().records();
+        let decided: HashSet<_> = records.iter().map(|record| record.tx_id).collect();
+        assert_eq!(decided.len(), records.len(), "seed {seed}: a transaction was decided twice");
+        for record in &records {
+            let outcome = self.outcomes.get(&record.tx_id);
+            match record.decision {
+                Decision::Commit => assert!(outcome.is_none_or(|outcome| outcome.aborted.is_empty()), "seed {seed}: {} decided to commit but aborted", record.tx_id),
+                Decision::Abort => assert!(outcome.is_none_or(|outcome| outcome.committed.is_empty()), "seed {seed}: {} decided to abort but committed", record.tx_id)
+            }
+        }
+
+        let status = self.server.drain.status();
+        assert_eq!((status.in_flight, status.prepared), (0, 0), "seed {seed}: transactions left in flight or prepared");
+        for (account_id, state) in self.server.shard.object_states().await {
+            assert!(state.oldest_tentative_write.is_none(), "seed {seed}: {account_id} still holds a write of {:?}", state.oldest_tentative_write);
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_coordinator_survives_any_message_order() {
+    for seed in seeds() {
+        // Shown if the run fails, along with the assertion naming the seed
+        eprintln!("Running seed {seed}: replay it with TX_REPLAY_SEED={seed}");
+        Harness::new(seed).await.run().await;
+    }
+}
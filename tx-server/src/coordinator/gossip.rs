@@ -0,0 +1,357 @@
+use tx_common::{admin::{MemberState, MemberStatus}, config::NodeId};
+use tx_proto::peer::Gossip;
+use tokio::time::Instant;
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex}, time::Duration};
+use log::info;
+
+pub static GOSSIP_INTERVAL: Duration = Duration::from_millis(500);
+/// The members asked to probe a member that did not answer a direct probe
+static INDIRECT_PROBES: usize = 3;
+/// The protocol periods a suspected member has to refute the suspicion in
+/// before it is declared dead
+static SUSPICION_PERIODS: u32 = 5;
+/// The most updates piggybacked on one message
+static MAX_PIGGYBACK: usize = 8;
+
+#[derive(Debug)]
+struct Member {
+    state: MemberState,
+    incarnation: u64,
+    since: Instant
+}
+
+/// The probe of the current protocol period.
+#[derive(Debug)]
+struct Probe {
+    seq: u64,
+    target: NodeId,
+    acked: bool,
+    /// Whether other members were asked to probe the target too
+    indirect: bool
+}
+
+/// A node's view of the cluster's membership, kept up to date SWIM-style: in
+/// every protocol period the node probes one member, asks a few others to
+/// probe it if it does not answer, and suspects it if none of them hear back
+/// either. A suspected member that does not refute the suspicion in time is
+/// declared dead. Joins, suspicions and failures spread by being piggybacked
+/// on probes, so no node has to probe every other node to learn of them.
+#[derive(Debug)]
+pub(super) struct Membership {
+    node_id: NodeId,
+    pub(super) interval: Duration,
+    /// Raised to refute suspicions of this node
+    incarnation: u64,
+    members: BTreeMap<NodeId, Member>,
+    /// The updates still to be piggybacked, with how many more times each is
+    updates: Vec<(MemberStatus, usize)>,
+    next_seq: u64,
+    probe: Option<Probe>,
+    /// The member probed last, so that probes go round every member in turn
+    last_target: Option<NodeId>,
+    /// The probes this node makes for other members, by sequence number,
+    /// with the member that asked and its own sequence number
+    requested: HashMap<u64, (NodeId, u64, Instant)>
+}
+
+pub(super) type SharedMembership = Arc<Mutex<Membership>>;
+
+impl Membership {
+    pub(super) fn new(node_id: NodeId, interval: Duration, peers: impl IntoIterator<Item = NodeId>, now: Instant) -> Self {
+        let members = peers
+            .into_iter()
+            .map(|node_id| (node_id, Member { state: MemberState::Alive, incarnation: 0, since: now }))
+            .collect();
+
+        Self {
+            node_id,
+            interval,
+            incarnation: 0,
+            members,
+            updates: Vec::new(),
+            next_seq: 0,
+            probe: None,
+            last_target: None,
+            requested: HashMap::new()
+        }
+    }
+
+    /// Every member this node knows of, itself included, by node id.
+    pub(super) fn members(&self) -> Vec<MemberStatus> {
+        let mut members: Vec<_> = self.members
+            .iter()
+            .map(|(node_id, member)| MemberStatus { node_id: *node_id, state: member.state, incarnation: member.incarnation })
+            .collect();
+        members.push(MemberStatus { node_id: self.node_id, state: MemberState::Alive, incarnation: self.incarnation });
+        members.sort_by_key(|member| member.node_id);
+        members
+    }
+
+    /// Notes a member that connected to this node, which is spread as a join
+    /// if this node did not know of it, or as its recovery if this node had
+    /// suspected it or declared it dead.
+    pub(super) fn joined(&mut self, node_id: NodeId, now: Instant) {
+        match self.members.get(&node_id) {
+            None => self.apply(MemberStatus { node_id, state: MemberState::Alive, incarnation: 0 }, now),
+            Some(member) if member.state != MemberState::Alive => {
+                let incarnation = member.incarnation + 1;
+                self.apply(MemberStatus { node_id, state: MemberState::Alive, incarnation }, now);
+            },
+            Some(_) => ()
+        }
+    }
+
+    /// Notes that the link to a member was lost, suspecting it at once rather
+    /// than waiting for a probe to go unanswered.
+    pub(super) fn disconnected(&mut self, node_id: NodeId, now: Instant) {
+        if let Some(member) = self.members.get(&node_id).filter(|member| member.state == MemberState::Alive) {
+            let incarnation = member.incarnation;
+            self.apply(MemberStatus { node_id, state: MemberState::Suspect, incarnation }, now);
+        }
+    }
+
+    pub(super) fn is_dead(&self, node_id: NodeId) -> bool {
+        self.members.get(&node_id).is_some_and(|member| member.state == MemberState::Dead)
+    }
+
+    /// Ends a protocol period and starts the next, returning the messages to
+    /// send. A member that answered no probe, direct or indirect, is
+    /// suspected, and suspicions that ran out are declared failures.
+    pub(super) fn tick(&mut self, now: Instant) -> Vec<(NodeId, Gossip)> {
+        let mut out = vec![];
+        let timeout = self.interval * 2;
+        self.requested.retain(|_, (_, _, asked)| now.duration_since(*asked) < timeout);
+
+        let suspicion = self.interval * SUSPICION_PERIODS;
+        let failed: Vec<_> = self.members
+            .iter()
+            .filter(|(_, member)| member.state == MemberState::Suspect && now.duration_since(member.since) >= suspicion)
+            .map(|(node_id, member)| MemberStatus { node_id: *node_id, state: MemberState::Dead, incarnation: member.incarnation })
+            .collect();
+        for update in failed {
+            self.apply(update, now);
+        }
+
+        match self.probe.take() {
+            Some(probe) if !probe.acked && !probe.indirect => {
+                let helpers: Vec<_> = self.members
+                    .iter()
+                    .filter(|(node_id, member)| **node_id != probe.target && member.state == MemberState::Alive)
+                    .map(|(node_id, _)| *node_id)
+                    .take(INDIRECT_PROBES)
+                    .collect();
+                for helper in helpers {
+                    let updates = self.piggyback();
+                    out.push((helper, Gossip::PingReq(probe.seq, probe.target, updates)));
+                }
+                self.probe = Some(Probe { indirect: true, ..probe });
+                return out;
+            },
+            Some(probe) if !probe.acked => {
+                let incarnation = self.members.get(&probe.target).map_or(0, |member| member.incarnation);
+                self.apply(MemberStatus { node_id: probe.target, state: MemberState::Suspect, incarnation }, now);
+            },
+            _ => ()
+        }
+
+        if let Some(target) = self.next_target() {
+            let seq = self.next_seq();
+            self.probe = Some(Probe { seq, target, acked: false, indirect: false });
+            self.last_target = Some(target);
+            out.push((target, Gossip::Ping(seq, self.piggyback())));
+        }
+        out
+    }
+
+    /// Handles a message from another member, returning the messages to send
+    /// in response.
+    pub(super) fn receive(&mut self, sender: NodeId, gossip: Gossip, now: Instant) -> Vec<(NodeId, Gossip)> {
+        let (Gossip::Ping(_, updates) | Gossip::PingReq(_, _, updates) | Gossip::Ack(_, updates)) = &gossip;
+        for update in updates {
+            self.apply(*update, now);
+        }
+
+        match gossip {
+            Gossip::Ping(seq, _) => vec![(sender, Gossip::Ack(seq, self.piggyback()))],
+            Gossip::PingReq(seq, target, _) => {
+                let probe_seq = self.next_seq();
+                self.requested.insert(probe_seq, (sender, seq, now));
+                vec![(target, Gossip::Ping(probe_seq, self.piggyback()))]
+            },
+            Gossip::Ack(seq, _) => {
+                if let Some(probe) = self.probe.as_mut().filter(|probe| probe.seq == seq) {
+                    probe.acked = true;
+                }
+
+                match self.requested.remove(&seq) {
+                    Some((requester, requester_seq, _)) => vec![(requester, Gossip::Ack(requester_seq, self.piggyback()))],
+                    None => vec![]
+                }
+            }
+        }
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq
+    }
+
+    /// The member after the one probed last, in node order, that is not dead.
+    fn next_target(&self) -> Option<NodeId> {
+        let live = self.members.iter().filter(|(_, member)| member.state != MemberState::Dead).map(|(node_id, _)| *node_id);
+        let after = self.last_target.and_then(|last| live.clone().find(|node_id| *node_id > last));
+        after.or_else(|| live.clone().next())
+    }
+
+    /// Takes the updates to send on one message, most recent first.
+    fn piggyback(&mut self) -> Vec<MemberStatus> {
+        let sent: Vec<_> = self.updates.iter().rev().take(MAX_PIGGYBACK).map(|(update, _)| *update).collect();
+        let len = self.updates.len();
+        for (_, remaining) in &mut self.updates[len.saturating_sub(MAX_PIGGYBACK)..] {
+            *remaining -= 1;
+        }
+        self.updates.retain(|(_, remaining)| *remaining > 0);
+        sent
+    }
+
+    /// Queues an update to be piggybacked on the next messages, replacing any
+    /// older update about the same member.
+    fn spread(&mut self, update: MemberStatus) {
+        // Enough sends for the update to reach every member with high probability
+        let transmissions = 3 * (usize::BITS - self.members.len().leading_zeros()).max(1) as usize;
+        self.updates.retain(|(queued, _)| queued.node_id != update.node_id);
+        self.updates.push((update, transmissions));
+    }
+
+    /// Applies an update if it is newer than what this node knows about the
+    /// member. A suspicion or failure of this node is refuted instead.
+    fn apply(&mut self, update: MemberStatus, now: Instant) {
+        use MemberState::*;
+
+        if update.node_id == self.node_id {
+            if update.state != Alive && update.incarnation >= self.incarnation {
+                info!("Refuting {:?} of this node at incarnation {}", update.state, update.incarnation);
+                self.incarnation = update.incarnation + 1;
+                self.spread(MemberStatus { node_id: self.node_id, state: Alive, incarnation: self.incarnation });
+            }
+            return;
+        }
+
+        let newer = match self.members.get(&update.node_id) {
+            None => true,
+            Some(member) => match (update.state, member.state) {
+                (Alive, _) => update.incarnation > member.incarnation,
+                (Suspect, Alive) => update.incarnation >= member.incarnation,
+                (Suspect, Suspect) => update.incarnation > member.incarnation,
+                (Suspect, Dead) => false,
+                (Dead, current) => current != Dead
+            }
+        };
+        if !newer {
+            return;
+        }
+
+        info!("Member {} is {:?} at incarnation {}", update.node_id, update.state, update.incarnation);
+        self.members.insert(update.node_id, Member { state: update.state, incarnation: update.incarnation, since: now });
+        self.spread(update);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Delivers every message between the given members, and the messages
+    /// sent in response, except those to or from `down`.
+    fn deliver(nodes: &mut BTreeMap<NodeId, Membership>, mut pending: Vec<(NodeId, NodeId, Gossip)>, down: Option<NodeId>, now: Instant) {
+        while let Some((from, to, gossip)) = pending.pop() {
+            if Some(from) == down || Some(to) == down {
+                continue;
+            }
+
+            let out = nodes.get_mut(&to).unwrap().receive(from, gossip, now);
+            pending.extend(out.into_iter().map(|(next, gossip)| (to, next, gossip)));
+        }
+    }
+
+    fn round(nodes: &mut BTreeMap<NodeId, Membership>, down: Option<NodeId>, now: Instant) {
+        let mut pending = vec![];
+        for (node_id, membership) in nodes.iter_mut().filter(|(node_id, _)| Some(**node_id) != down) {
+            pending.extend(membership.tick(now).into_iter().map(|(to, gossip)| (*node_id, to, gossip)));
+        }
+        deliver(nodes, pending, down, now);
+    }
+
+    fn state_of(membership: &Membership, node_id: NodeId) -> MemberState {
+        membership.members().into_iter().find(|member| member.node_id == node_id).unwrap().state
+    }
+
+    #[test]
+    fn test_failure_detected_and_spread() {
+        let ids: Vec<_> = (0..5).map(NodeId).collect();
+        let mut now = Instant::now();
+        let mut nodes: BTreeMap<_, _> = ids
+            .iter()
+            .map(|id| (*id, Membership::new(*id, GOSSIP_INTERVAL, ids.iter().copied().filter(|peer| peer != id), now)))
+            .collect();
+
+        for _ in 0..10 {
+            now += GOSSIP_INTERVAL;
+            round(&mut nodes, None, now);
+        }
+        assert!(nodes.values().all(|membership| membership.members().iter().all(|m| m.state == MemberState::Alive)));
+
+        // Every other member learns that node 4 stopped answering, whether or
+        // not it probed node 4 itself
+        let down = NodeId(4);
+        for _ in 0..(SUSPICION_PERIODS + 10) {
+            now += GOSSIP_INTERVAL;
+            round(&mut nodes, Some(down), now);
+        }
+        for (node_id, membership) in nodes.iter().filter(|(node_id, _)| **node_id != down) {
+            assert_eq!(state_of(membership, down), MemberState::Dead, "as seen by {node_id}");
+            assert_eq!(state_of(membership, NodeId(0)), MemberState::Alive);
+        }
+    }
+
+    #[test]
+    fn test_suspicion_refuted() {
+        let now = Instant::now();
+        let mut node = Membership::new(NodeId(0), GOSSIP_INTERVAL, [NodeId(1)], now);
+        let mut peer = Membership::new(NodeId(1), GOSSIP_INTERVAL, [NodeId(0)], now);
+        let suspicion = MemberStatus { node_id: NodeId(0), state: MemberState::Suspect, incarnation: 0 };
+        peer.apply(suspicion, now);
+        assert_eq!(state_of(&peer, NodeId(0)), MemberState::Suspect);
+
+        // The suspected node hears of it on a probe and outbids it
+        let out = peer.receive(NodeId(0), Gossip::Ping(1, vec![]), now);
+        let [(_, Gossip::Ack(seq, updates))] = out.as_slice() else { panic!("Expected an ack: {out:?}") };
+        node.receive(NodeId(1), Gossip::Ack(*seq, updates.clone()), now);
+        let out = node.receive(NodeId(1), Gossip::Ping(2, vec![]), now);
+        let [(_, Gossip::Ack(seq, updates))] = out.as_slice() else { panic!("Expected an ack: {out:?}") };
+        peer.receive(NodeId(0), Gossip::Ack(*seq, updates.clone()), now);
+
+        assert_eq!(state_of(&peer, NodeId(0)), MemberState::Alive);
+        assert_eq!(peer.members()[0].incarnation, 1);
+    }
+
+    #[test]
+    fn test_lost_link_suspected_and_rejoin_revives() {
+        let mut now = Instant::now();
+        let mut node = Membership::new(NodeId(0), GOSSIP_INTERVAL, [NodeId(1)], now);
+        node.disconnected(NodeId(1), now);
+        assert_eq!(state_of(&node, NodeId(1)), MemberState::Suspect);
+
+        // Nobody refutes the suspicion, so the member is declared dead
+        for _ in 0..(SUSPICION_PERIODS + 2) {
+            now += GOSSIP_INTERVAL;
+            node.tick(now);
+        }
+        assert!(node.is_dead(NodeId(1)));
+
+        // Connecting again brings it back at a newer incarnation
+        node.joined(NodeId(1), now);
+        assert_eq!(state_of(&node, NodeId(1)), MemberState::Alive);
+        assert!(!node.is_dead(NodeId(1)));
+    }
+}
@@ -0,0 +1,228 @@
+use std::{fs, io, path::{Path, PathBuf}, sync::{mpsc::{self, Sender}, Arc, Mutex}};
+use log::error;
+
+/// How far past the newest transaction id the stored high-water mark is
+/// set, in nanoseconds. The mark is only rewritten once ids pass halfway to
+/// it, so this bounds how often the coordinator writes to disk.
+pub static ID_RESERVATION: u128 = 1_000_000_000;
+
+/// Persists a high-water mark above every transaction id a coordinator has
+/// handed out. Timestamp ordering breaks if a restarted coordinator issues an
+/// id older than one it issued before the restart (e.g. after the clock was
+/// set back), so a restarted coordinator resumes above the stored mark. The
+/// mark is moved up by a thread of its own while ids are still well under
+/// it, so that the coordinator only waits on the disk if ids outrun it.
+pub(super) struct IdStore {
+    mark: Arc<StoredMark>,
+    /// The mark the writer thread was last asked to store
+    requested: u128,
+    requests: Sender<u128>
+}
+
+/// The mark on disk, shared with the thread that writes it.
+struct StoredMark {
+    path: PathBuf,
+    stored: Mutex<u128>,
+    /// Held while the file is written, so that a write never replaces a
+    /// higher mark
+    writing: Mutex<()>
+}
+
+impl StoredMark {
+    fn store(&self, mark: u128) -> io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        if mark <= *self.stored.lock().unwrap() {
+            return Ok(());
+        }
+
+        replace_checked(&self.path, mark.to_string())?;
+        *self.stored.lock().unwrap() = mark;
+        Ok(())
+    }
+}
+
+impl IdStore {
+    /// Opens the store at `path`, returning it with the timestamp every new
+    /// transaction id must exceed.
+    pub(super) fn open(path: impl AsRef<Path>) -> io::Result<(Self, u128)> {
+        let path = path.as_ref().to_path_buf();
+        let reserved = match read_checked(&path)? {
+            Some(contents) => String::from_utf8_lossy(&contents)
+                .trim()
+                .parse::<u128>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            None => 0
+        };
+
+        let mark = Arc::new(StoredMark { path, stored: Mutex::new(reserved), writing: Mutex::new(()) });
+        let (requests, incoming) = mpsc::channel::<u128>();
+        let writer = mark.clone();
+        std::thread::spawn(move || {
+            while let Ok(requested) = incoming.recv() {
+                // Only the highest of the marks asked for meanwhile is written
+                let requested = incoming.try_iter().fold(requested, u128::max);
+                if let Err(e) = writer.store(requested) {
+                    error!("Failed to persist transaction id high-water mark to {}: {e}", writer.path.display());
+                }
+            }
+        });
+
+        Ok((Self { mark, requested: reserved, requests }, reserved))
+    }
+
+    /// Makes sure the stored mark is at least `timestamp` before an id with
+    /// that timestamp is used. Once `timestamp` is past halfway to the mark,
+    /// the writer thread is asked to move it `ID_RESERVATION` past
+    /// `timestamp`; only if `timestamp` is past the stored mark already is
+    /// the mark written before returning.
+    pub(super) fn cover(&mut self, timestamp: u128) -> io::Result<()> {
+        if timestamp + ID_RESERVATION / 2 > self.requested {
+            self.requested = timestamp + ID_RESERVATION;
+            let _ = self.requests.send(self.requested);
+        }
+
+        if timestamp <= *self.mark.stored.lock().unwrap() {
+            return Ok(());
+        }
+        self.mark.store(self.requested)
+    }
+}
+
+/// Replaces the file at `path` with `contents` as one step, writing them to
+/// `<path>.tmp` first. The temporary file is named after the whole file name,
+/// so that the files a node keeps side by side (e.g. `<node>.txid` and 
+/// `<node>.acls`) never share one.
+pub(super) fn replace_file(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, contents)?;
+    fs::File::open(&tmp)?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// The line `replace_checked` ends a file with: `crc32 ` and the CRC-32 of
+/// the rest of the file as eight hex digits.
+const CHECKSUM_LINE: usize = "\ncrc32 00000000\n".len();
+
+/// Replaces the file at `path` with `contents` like `replace_file`, followed
+/// by a line with their checksum, which `read_checked` verifies.
+pub(super) fn replace_checked(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut checked = contents.as_ref().to_vec();
+    checked.extend_from_slice(format!("\ncrc32 {:08x}\n", crc32fast::hash(contents.as_ref())).as_bytes());
+    replace_file(path, checked)
+}
+
+/// Reads the contents of a file written by `replace_checked`, or `None` if
+/// there is no file. A file without a checksum, or whose contents do not
+/// match it, such as one cut short or changed since it was written, is an
+/// `InvalidData` error rather than contents the node would trust.
+pub(super) fn read_checked(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let mut contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e)
+    };
+
+    let Some(end) = contents.len().checked_sub(CHECKSUM_LINE) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "missing checksum"));
+    };
+    let checksum = std::str::from_utf8(&contents[end..])
+        .ok()
+        .and_then(|line| line.strip_prefix("\ncrc32 "))
+        .and_then(|line| line.strip_suffix('\n'))
+        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing checksum"))?;
+    contents.truncate(end);
+    if crc32fast::hash(&contents) != checksum {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "checksum does not match the contents"));
+    }
+    Ok(Some(contents))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reopened_store_resumes_above_covered_ids() {
+        let path = std::env::temp_dir().join(format!("tx-server-ids-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let (mut store, after) = IdStore::open(&path).unwrap();
+        assert_eq!(after, 0);
+        store.cover(100).unwrap();
+        store.cover(200).unwrap();
+
+        let (mut store, after) = IdStore::open(&path).unwrap();
+        assert_eq!(after, 100 + ID_RESERVATION);
+        store.cover(after + 1).unwrap();
+
+        let (_, resumed) = IdStore::open(&path).unwrap();
+        assert_eq!(resumed, after + 1 + ID_RESERVATION);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_mark_moves_up_before_ids_reach_it() {
+        let path = std::env::temp_dir().join(format!("tx-server-ids-ahead-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let (mut store, _) = IdStore::open(&path).unwrap();
+        store.cover(100).unwrap();
+        assert_eq!(read_checked(&path).unwrap().unwrap(), (100 + ID_RESERVATION).to_string().into_bytes());
+
+        // Past halfway to the mark, the id is covered by the mark already
+        // stored while the writer thread stores the next one
+        let ahead = 100 + ID_RESERVATION / 2 + 1;
+        store.cover(ahead).unwrap();
+        while read_checked(&path).unwrap().unwrap() != (ahead + ID_RESERVATION).to_string().into_bytes() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_files_of_a_node_replace_through_their_own_temporary_files() {
+        let dir = std::env::temp_dir().join(format!("tx-server-replace-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (txid, acls) = (dir.join("A.txid"), dir.join("A.acls"));
+
+        // A write of the lists that stopped short of its rename
+        fs::write(dir.join("A.acls.tmp"), "[]").unwrap();
+        replace_file(&txid, "100").unwrap();
+        assert_eq!(fs::read_to_string(&txid).unwrap(), "100");
+        assert!(!acls.exists());
+        replace_file(&acls, "[]").unwrap();
+        assert_eq!(fs::read_to_string(&acls).unwrap(), "[]");
+        assert!(!dir.join("A.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checked_files_refuse_contents_changed_or_cut_short() {
+        let path = std::env::temp_dir().join(format!("tx-server-checked-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(read_checked(&path).unwrap(), None);
+
+        replace_checked(&path, "1000").unwrap();
+        assert_eq!(read_checked(&path).unwrap().unwrap(), b"1000");
+        let written = fs::read(&path).unwrap();
+
+        let mut changed = written.clone();
+        changed[0] = b'9';
+        fs::write(&path, changed).unwrap();
+        assert_eq!(read_checked(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        for cut in [written.len() - 1, 4, 0] {
+            fs::write(&path, &written[..cut]).unwrap();
+            assert_eq!(read_checked(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+
+        // A mark stored before checksums were kept is not trusted either
+        fs::write(&path, "1000").unwrap();
+        assert!(IdStore::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+use tx_common::admin::LabelStats;
+use tx_proto::{ClientRequest, ClientResponse};
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex}, time::Duration};
+use tokio::time::Instant;
+use super::layer::{RequestContext, RequestLayer};
+use log::info;
+
+/// The outcomes of the labelled transactions a server coordinated, and how
+/// many of them may run at once.
+#[derive(Debug, Default)]
+pub(super) struct Labels {
+    quotas: HashMap<String, usize>,
+    stats: BTreeMap<String, LabelCounters>
+}
+
+#[derive(Debug, Default)]
+struct LabelCounters {
+    active: usize,
+    committed: u64,
+    aborted: u64,
+    refused: u64,
+    total_latency: Duration,
+    max_latency: Duration
+}
+
+pub(super) type SharedLabels = Arc<Mutex<Labels>>;
+
+impl Labels {
+    pub(super) fn set_quota(&mut self, label: String, max_active: usize) {
+        self.quotas.insert(label, max_active);
+    }
+
+    /// Counts a transaction with the label as running, returning false if as
+    /// many as its quota allows already are.
+    fn start(&mut self, label: &str) -> bool {
+        let quota = self.quotas.get(label).copied();
+        let counters = self.stats.entry(label.into()).or_default();
+        if quota.is_some_and(|max_active| counters.active >= max_active) {
+            counters.refused += 1;
+            return false;
+        }
+
+        counters.active += 1;
+        true
+    }
+
+    fn finish(&mut self, label: &str, committed: bool, latency: Duration) {
+        let counters = self.stats.entry(label.into()).or_default();
+        counters.active -= 1;
+        if committed {
+            counters.committed += 1;
+        } else {
+            counters.aborted += 1;
+        }
+        counters.total_latency += latency;
+        counters.max_latency = counters.max_latency.max(latency);
+    }
+
+    pub(super) fn stats(&self) -> Vec<LabelStats> {
+        self.stats
+            .iter()
+            .map(|(label, counters)| {
+                let finished = (counters.committed + counters.aborted) as u32;
+                LabelStats {
+                    label: label.clone(),
+                    active: counters.active,
+                    max_active: self.quotas.get(label).copied(),
+                    committed: counters.committed,
+                    aborted: counters.aborted,
+                    refused: counters.refused,
+                    mean_latency: counters.total_latency.checked_div(finished).unwrap_or_default(),
+                    max_latency: counters.max_latency
+                }
+            })
+            .collect()
+    }
+}
+
+/// Labels a client's transaction and records its outcome under the label,
+/// aborting the transaction if its label's quota is used up. A transaction
+/// whose client disconnects before it finishes is recorded as aborted.
+pub(super) struct LabelLayer {
+    labels: SharedLabels,
+    connected_at: Instant,
+    label: Option<String>,
+    finished: bool
+}
+
+impl LabelLayer {
+    pub(super) fn new(labels: SharedLabels) -> Self {
+        Self { labels, connected_at: Instant::now(), label: None, finished: false }
+    }
+
+    fn finish(&mut self, committed: bool) {
+        match &self.label {
+            Some(label) if !self.finished => {
+                self.finished = true;
+                self.labels.lock().unwrap().finish(label, committed, self.connected_at.elapsed());
+            },
+            _ => ()
+        }
+    }
+}
+
+impl RequestLayer for LabelLayer {
+    fn before(&mut self, cx: &RequestContext, request: &ClientRequest) -> Option<ClientResponse> {
+        match request {
+            ClientRequest::Label(label) if cx.active && self.label.is_none() => {
+                if !self.labels.lock().unwrap().start(label) {
+                    info!("Refusing {}: too many transactions labelled {label} are running", cx.tx_id);
+                    return Some(ClientResponse::AbortedLabelQuota(label.clone()));
+                }
+
+                self.label = Some(label.clone());
+                None
+            },
+            _ => None
+        }
+    }
+
+    fn after(&mut self, _cx: &RequestContext, response: &ClientResponse) {
+        match response {
+            response if response.is_committed() => self.finish(true),
+            response if response.is_err() => self.finish(false),
+            _ => ()
+        }
+    }
+}
+
+impl Drop for LabelLayer {
+    fn drop(&mut self) {
+        self.finish(false);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sharding::fixture::tx_at;
+    use super::*;
+
+    #[test]
+    fn test_outcomes_recorded_once_per_transaction() {
+        let labels = SharedLabels::default();
+        labels.lock().unwrap().set_quota("batch".into(), 1);
+        let cx = RequestContext { tx_id: tx_at(100), active: true };
+        let label = ClientRequest::Label("batch".into());
+
+        let mut first = LabelLayer::new(labels.clone());
+        assert!(first.before(&cx, &label).is_none());
+        let mut second = LabelLayer::new(labels.clone());
+        assert!(matches!(second.before(&cx, &label), Some(ClientResponse::AbortedLabelQuota(_))));
+        drop(second);
+
+        first.after(&cx, &ClientResponse::CommitOk);
+        first.after(&cx, &ClientResponse::AlreadyFinished(tx_common::admin::Decision::Commit));
+        drop(first);
+
+        // A client that disconnects mid-transaction aborts it
+        let mut third = LabelLayer::new(labels.clone());
+        assert!(third.before(&cx, &label).is_none());
+        drop(third);
+
+        let stats = labels.lock().unwrap().stats();
+        let counts: Vec<_> = stats.iter().map(|s| (s.label.as_str(), s.active, s.committed, s.aborted, s.refused)).collect();
+        assert_eq!(counts, vec![("batch", 0, 1, 1, 1)]);
+        assert_eq!(stats[0].max_active, Some(1));
+    }
+}
@@ -0,0 +1,45 @@
+use crate::sharding::TransactionId;
+use tx_proto::{ClientRequest, ClientResponse};
+use std::sync::Arc;
+use log::{info, trace};
+
+/// What a request layer is told about the transaction a request belongs to.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestContext {
+    pub tx_id: TransactionId,
+    /// Whether the transaction still accepts reads and writes, i.e. it has
+    /// not started to commit and has not reached a final state
+    pub active: bool
+}
+
+/// A layer around the handling of client requests, for checks that apply to
+/// every request regardless of what it asks for (e.g. authentication,
+/// validation or quotas). Each client connection gets its own chain of
+/// layers, so a layer may keep state about the transaction it serves.
+pub trait RequestLayer: Send + Sync {
+    /// Called before a request is handled. Returning a response answers the
+    /// request with it instead, and the layers after this one are skipped.
+    /// A response that is an error aborts the transaction.
+    fn before(&mut self, cx: &RequestContext, request: &ClientRequest) -> Option<ClientResponse>;
+
+    /// Called with the response to every request, including requests
+    /// answered by a layer, before it is sent to the client.
+    fn after(&mut self, _cx: &RequestContext, _response: &ClientResponse) {}
+}
+
+/// Builds the layer a server adds to the chain of every client connection.
+pub(super) type LayerFactory = Arc<dyn Fn() -> Box<dyn RequestLayer> + Send + Sync>;
+
+/// Logs every request and the response it got.
+pub(super) struct TraceLayer;
+
+impl RequestLayer for TraceLayer {
+    fn before(&mut self, cx: &RequestContext, request: &ClientRequest) -> Option<ClientResponse> {
+        info!("Client task for {} handling {request:?}", cx.tx_id);
+        None
+    }
+
+    fn after(&mut self, cx: &RequestContext, response: &ClientResponse) {
+        trace!("Client task for {} responding {response:?}", cx.tx_id);
+    }
+}
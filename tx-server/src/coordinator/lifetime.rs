@@ -0,0 +1,55 @@
+use tokio::time::Instant;
+use std::time::Duration;
+
+/// How long transactions coordinated by a server may run, from the first
+/// request of their connection, before they are aborted. Interactive clients
+/// get the default, while batch jobs may ask for up to the maximum.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TransactionLifetime {
+    /// The lifetime of a transaction that does not ask for one, or none
+    pub default: Option<Duration>,
+    /// The longest lifetime a transaction may ask for, or no limit
+    pub max: Option<Duration>
+}
+
+impl TransactionLifetime {
+    /// The lifetime given to a transaction that asks for `requested`.
+    pub fn grant(&self, requested: Duration) -> Duration {
+        self.max.map_or(requested, |max| requested.min(max))
+    }
+
+    /// The lifetime of a transaction that does not ask for one: the default
+    /// capped at the maximum, or the maximum if there is no default.
+    pub fn initial(&self) -> Option<Duration> {
+        self.default.or(self.max).map(|lifetime| self.grant(lifetime))
+    }
+
+    /// When a transaction that began at `began` with `lifetime` expires, if
+    /// ever.
+    pub(super) fn expiry(began: Instant, lifetime: Option<Duration>) -> Option<Instant> {
+        lifetime.and_then(|lifetime| began.checked_add(lifetime))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lifetime_granted_up_to_max() {
+        let lifetime = TransactionLifetime { default: Some(Duration::from_secs(1)), max: Some(Duration::from_secs(60)) };
+        assert_eq!(lifetime.grant(Duration::from_secs(30)), Duration::from_secs(30));
+        assert_eq!(lifetime.grant(Duration::MAX), Duration::from_secs(60));
+        assert_eq!(TransactionLifetime::default().grant(Duration::MAX), Duration::MAX);
+        assert_eq!(TransactionLifetime::expiry(Instant::now(), Some(Duration::MAX)), None);
+    }
+
+    #[test]
+    fn test_initial_lifetime_capped_by_max() {
+        let (short, long) = (Some(Duration::from_secs(1)), Some(Duration::from_secs(60)));
+        assert_eq!(TransactionLifetime { default: short, max: long }.initial(), short);
+        assert_eq!(TransactionLifetime { default: long, max: short }.initial(), short);
+        assert_eq!(TransactionLifetime { default: None, max: long }.initial(), long);
+        assert_eq!(TransactionLifetime::default().initial(), None);
+    }
+}
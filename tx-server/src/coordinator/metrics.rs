@@ -0,0 +1,207 @@
+use tx_common::{
+    admin::{ConcurrencyMode, ContentionStatus, DrainStatus, LabelStats, LinkTraffic, ShardCounts, TenantStats},
+    config::NodeId
+};
+use serde::Serialize;
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path, sync::mpsc::{self, Sender}, time::{Duration, SystemTime}};
+use log::error;
+
+pub static METRICS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How a metrics dump is written.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MetricsFormat {
+    /// One JSON object per snapshot, per line, with every metric
+    #[default]
+    JsonLines,
+    /// A header and one row per snapshot with the node-wide metrics, leaving
+    /// out the breakdowns by peer, label and tenant
+    Csv
+}
+
+impl MetricsFormat {
+    /// The extension of a file holding a dump in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::JsonLines => "jsonl",
+            Self::Csv => "csv"
+        }
+    }
+}
+
+/// The traffic on the link to one peer.
+#[derive(Debug, Serialize)]
+pub(super) struct PeerTraffic {
+    pub(super) peer: NodeId,
+    #[serde(flatten)]
+    pub(super) traffic: LinkTraffic
+}
+
+/// Everything a node measures, as of one moment.
+#[derive(Debug, Serialize)]
+pub(super) struct MetricsSnapshot {
+    pub(super) node_id: NodeId,
+    /// The wall-clock time of the snapshot in milliseconds since the epoch
+    pub(super) taken_at_ms: u128,
+    pub(super) clients: usize,
+    pub(super) links: Vec<PeerTraffic>,
+    /// The two-phase commits the node decided, or learned as a witness
+    pub(super) committed: u64,
+    pub(super) aborted: u64,
+    pub(super) drain: DrainStatus,
+    pub(super) contention: ContentionStatus,
+    pub(super) verification_passes: u64,
+    pub(super) violations_found: u64,
+    /// The operations the node's shard counted, all zero if it does not count
+    pub(super) shard: ShardCounts,
+    pub(super) labels: Vec<LabelStats>,
+    pub(super) tenants: Vec<TenantStats>
+}
+
+static CSV_HEADER: &str = "taken_at_ms,node_id,clients,messages_sent,bytes_sent,messages_received,bytes_received,\
+    committed,aborted,in_flight,prepared,mode,abort_rate,mode_switches,verification_passes,violations_found,\
+    shard_reads,shard_writes,shard_commits,shard_aborts";
+
+impl MetricsSnapshot {
+    pub(super) fn now() -> u128 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default()
+    }
+
+    fn csv_row(&self) -> String {
+        let total = self.links.iter().fold(LinkTraffic::default(), |total, link| LinkTraffic {
+            messages_sent: total.messages_sent + link.traffic.messages_sent,
+            bytes_sent: total.bytes_sent + link.traffic.bytes_sent,
+            messages_received: total.messages_received + link.traffic.messages_received,
+            bytes_received: total.bytes_received + link.traffic.bytes_received
+        });
+        let mode = match self.contention.mode {
+            ConcurrencyMode::TimestampOrdering => "timestamp-ordering",
+            ConcurrencyMode::WoundWait => "wound-wait"
+        };
+
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{mode},{},{},{},{},{},{},{},{}",
+            self.taken_at_ms, self.node_id.0, self.clients,
+            total.messages_sent, total.bytes_sent, total.messages_received, total.bytes_received,
+            self.committed, self.aborted, self.drain.in_flight, self.drain.prepared,
+            self.contention.abort_rate, self.contention.switches, self.verification_passes, self.violations_found,
+            self.shard.reads, self.shard.writes, self.shard.commits, self.shard.aborts
+        )
+    }
+}
+
+/// Writes a snapshot of a node's metrics to a file at every interval, so
+/// that a benchmark run leaves behind everything it measured. Snapshots are
+/// written by a thread of their own, so that the server never waits on the
+/// file.
+pub(super) struct MetricsDump {
+    pub(super) interval: Duration,
+    snapshots: Sender<MetricsSnapshot>
+}
+
+impl MetricsDump {
+    /// Dumps to a new file at `path`, replacing any file there.
+    pub(super) fn create(path: impl AsRef<Path>, interval: Duration, format: MetricsFormat) -> io::Result<Self> {
+        let mut writer = MetricsWriter::new(BufWriter::new(File::create(path)?), format)?;
+        let (snapshots, incoming) = mpsc::channel::<MetricsSnapshot>();
+        std::thread::spawn(move || {
+            while let Ok(snapshot) = incoming.recv() {
+                if let Err(e) = writer.write(&snapshot) {
+                    error!("Failed to dump metrics: {e}");
+                    return;
+                }
+            }
+        });
+
+        Ok(Self { interval, snapshots })
+    }
+
+    pub(super) fn write(&self, snapshot: MetricsSnapshot) {
+        // The writer only stops if the file failed, which it logged
+        let _ = self.snapshots.send(snapshot);
+    }
+}
+
+/// Writes snapshots to a sink in one format.
+struct MetricsWriter {
+    format: MetricsFormat,
+    sink: Box<dyn Write + Send>
+}
+
+impl MetricsWriter {
+    fn new(sink: impl Write + Send + 'static, format: MetricsFormat) -> io::Result<Self> {
+        let mut writer = Self { format, sink: Box::new(sink) };
+        if format == MetricsFormat::Csv {
+            writeln!(writer.sink, "{CSV_HEADER}")?;
+            writer.sink.flush()?;
+        }
+
+        Ok(writer)
+    }
+
+    fn write(&mut self, snapshot: &MetricsSnapshot) -> io::Result<()> {
+        match self.format {
+            MetricsFormat::JsonLines => serde_json::to_writer(&mut self.sink, snapshot)?,
+            MetricsFormat::Csv => write!(self.sink, "{}", snapshot.csv_row())?
+        }
+
+        writeln!(self.sink)?;
+        self.sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::SharedBuffer;
+    use super::*;
+
+    fn snapshot() -> MetricsSnapshot {
+        let traffic = LinkTraffic { messages_sent: 2, bytes_sent: 20, messages_received: 1, bytes_received: 10 };
+        MetricsSnapshot {
+            node_id: NodeId(0),
+            taken_at_ms: 1700000000000,
+            clients: 3,
+            links: vec![PeerTraffic { peer: NodeId(1), traffic }, PeerTraffic { peer: NodeId(2), traffic }],
+            committed: 5,
+            aborted: 1,
+            drain: DrainStatus { in_flight: 3, prepared: 0, safe_to_stop: false },
+            contention: ContentionStatus::default(),
+            verification_passes: 7,
+            violations_found: 0,
+            shard: ShardCounts { reads: 8, writes: 4, commits: 2, aborts: 1 },
+            labels: vec![],
+            tenants: vec![]
+        }
+    }
+
+    #[test]
+    fn test_csv_dump_sums_links() {
+        let buffer = SharedBuffer::default();
+        let mut writer = MetricsWriter::new(buffer.clone(), MetricsFormat::Csv).unwrap();
+        writer.write(&snapshot()).unwrap();
+
+        let output = buffer.contents();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+        assert_eq!(lines[1], "1700000000000,0,3,4,40,2,20,5,1,3,0,timestamp-ordering,0,0,7,0,8,4,2,1");
+    }
+
+    #[test]
+    fn test_json_dump_keeps_breakdowns() {
+        let buffer = SharedBuffer::default();
+        let mut writer = MetricsWriter::new(buffer.clone(), MetricsFormat::JsonLines).unwrap();
+        writer.write(&snapshot()).unwrap();
+        writer.write(&snapshot()).unwrap();
+
+        let output = buffer.contents();
+        assert_eq!(output.lines().count(), 2);
+        let line: serde_json::Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert_eq!(line["links"][1]["peer"], 2);
+        assert_eq!(line["links"][1]["bytes_sent"], 20);
+        assert_eq!(line["drain"]["in_flight"], 3);
+        assert_eq!(line["shard"]["reads"], 8);
+    }
+}
@@ -1,315 +1,3782 @@
 mod protocol;
 mod client;
+mod decisions;
+mod quota;
+mod drain;
+mod id_store;
+mod admission;
+mod report;
+mod verification;
+mod layer;
+mod forwards;
+mod contention;
+mod labels;
+mod tenants;
+mod deterministic;
+mod epochs;
+mod lifetime;
+mod routes;
+mod metrics;
+mod acl;
+mod relays;
+mod gossip;
+mod completion;
+mod workload;
+mod slots;
+mod snapshots;
+mod workers;
+#[cfg(test)]
+mod fuzz;
 
 use crate::{
-    sharding::{Shard, Abort, CommitSuccess, TransactionIdGenerator, TransactionId}, 
-    pool::server::{ServerStateMessage, ServerStateMessageType},
-    pool::{ConnectionPoolBuilder, ServerGroup}
+    sharding::{Shard, Abort, TransactionIdGenerator, TransactionId}, 
+    pool::server::{ServerStateMessage, ServerStateMessageType, RemoteServerHandle, SharedTraffic},
+    pool::{ConnectionPool, ConnectionPoolBuilder, ServerGroup, Handshake, TreeBroadcast, TREE_FANOUT, HealthPolicy, SharedHealth}
 };
-use tx_common::{Amount, ClientRequest, ClientResponse, config::{NodeId, Config}};
-use tokio::{sync::mpsc::*, select, net::TcpListener};
-use std::{sync::Arc, collections::HashMap};
-use log::{error, info, trace};
+use tx_common::{
+    Amount, AccountId, admin::{AccountAcl, AccountSnapshot, AdminRequest, AdminResponse, ConcurrencyMode, Decision, PauseStatus, ShardDigest, ShardSnapshot},
+    query::{Query, QueryPart},
+    config::{NodeId, NodeConfiguration, Config, ShardMap}, stream::{MessageStream, Either, SocketOptions}
+};
+use tx_proto::{topology::{NodeInfo, NodeRole}, BalanceDiff, ClientRequest, ClientResponse};
+use tokio::{sync::mpsc::*, select, net::TcpListener, time::Instant};
+use std::{sync::{Arc, Mutex}, collections::{BTreeMap, HashMap}, net::{IpAddr, Ipv4Addr, SocketAddr}, time::Duration};
+use log::{error, info, trace, warn};
+use decisions::DecisionLog;
+pub use quota::TransactionQuota;
+pub use lifetime::TransactionLifetime;
+pub use metrics::{MetricsFormat, METRICS_INTERVAL};
+pub use drain::PAUSE_LEASE;
+pub use admission::AdmissionPolicy;
+pub use verification::VERIFY_INTERVAL;
+pub use snapshots::SNAPSHOT_INTERVAL;
+pub use layer::{RequestContext, RequestLayer};
+pub use forwards::ForwardRetry;
+pub use contention::ContentionPolicy;
+use contention::SharedContention;
+use labels::SharedLabels;
+pub use tenants::TenantPolicy;
+use tenants::SharedTenants;
+use acl::SharedAcls;
+use relays::{Delivery, Relays};
+pub use gossip::GOSSIP_INTERVAL;
+use gossip::{Membership, SharedMembership};
+pub use completion::COMMIT_RETRY_INTERVAL;
+use completion::SharedCompletion;
+use layer::{LayerFactory, TraceLayer};
+use verification::SharedVerification;
+pub use report::{AbortReport, CommitReport, CommitReporter, StdoutReporter, LogReporter, FileReporter, JsonLinesReporter, ChannelReporter, SilentReporter};
+#[cfg(feature = "kafka")]
+pub use report::KafkaReporter;
+use admission::Admission;
 use client::Client;
+use slots::{OperationSlots, Slot, Slots};
+use workers::WorkerPool;
 use protocol::*;
 
 type AtomicShard = Arc<Shard<String, Amount>>;
+type SharedDecisionLog = Arc<Mutex<DecisionLog>>;
+type Greeted = (SocketAddr, Option<(MessageStream, ClientRequest, Slot)>);
+type SharedPeers = Arc<Mutex<BTreeMap<NodeId, (SharedTraffic, SharedHealth)>>>;
+type SharedDrain = Arc<drain::Drain>;
+type SharedReporter = Arc<dyn CommitReporter>;
+
+pub static MAX_CONCURRENT_CLIENTS: usize = 1024;
+/// How many reads and writes forwarded by peers run at once by default.
+pub static MAX_CONCURRENT_REMOTE_OPERATIONS: usize = 1024;
+/// The address clients connected in memory are counted under, since they
+/// have none of their own
+const EMBEDDED_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+/// How often expired accounts are removed from the shard by default.
+pub static SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How a cluster runs transactions. Every node of a cluster must be started
+/// with the same mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// Clients read and write as they go, and coordinators commit with a 
+    /// two-phase commit, aborting transactions that conflict
+    #[default]
+    Interactive,
+    /// Experimental: coordinators hold a transaction's balance changes until
+    /// it commits, then have the lowest node id place it in the cluster's 
+    /// order. Every shard it writes to runs it in that order and the shards 
+    /// decide the outcome from each other's verdicts without a two-phase 
+    /// commit, so transactions never conflict. Reads and swaps are refused,
+    /// and every node must have joined.
+    Deterministic
+}
+
+/// When a transaction takes the id that orders it against other transactions.
+/// An older transaction wins conflicts with newer ones, so a client that takes
+/// its id long before it reads or writes is more likely to abort others and
+/// to be aborted by what committed in the meantime. Taking the id only once a
+/// transaction commits, as optimistic concurrency control does, is left out:
+/// shards order each read and write by the id it carries when it arrives, so
+/// a transaction must hold its id before it first reads or writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampMode {
+    /// The id is taken when the client sends its first request
+    #[default]
+    FirstRequest,
+    /// The id is taken when the client first reads or writes, so requests 
+    /// that do not touch any account (e.g. admin requests) do not age it
+    FirstOperation
+}
+
+/// Connects clients to a server in the same process over in-memory streams,
+/// so that they run transactions without going through the network.
+#[derive(Clone)]
+pub struct Connector(UnboundedSender<MessageStream>);
+
+impl Connector {
+    /// A new connection to the server, to send a transaction's requests on
+    /// like a TCP connection, or `None` once the server is gone.
+    pub fn connect(&self) -> Option<MessageStream> {
+        let (client, server) = MessageStream::in_memory();
+        self.0.send(server).ok()?;
+        Some(client)
+    }
+}
 
 pub struct Server {
     node_id: NodeId,
     shard: AtomicShard,
-    listener: TcpListener,
+    /// Where clients connect over TCP, unless the server is embedded
+    listener: Option<TcpListener>,
+    /// Connections made in memory with a `Connector`
+    embedded: UnboundedReceiver<MessageStream>,
+    embedded_snd: UnboundedSender<MessageStream>,
     server_pool: ServerGroup<Forwarded>,
     shard_ids: Vec<NodeId>,
+    shards: Arc<ShardMap>,
+    /// The nodes in the cluster's config, for clients that ask about it
+    nodes: Arc<Vec<NodeInfo>>,
     from_servers: UnboundedReceiver<ServerStateMessage<Forwarded>>,
+    /// The sending half of `from_servers`, used to admit peers that join late
+    to_server: UnboundedSender<ServerStateMessage<Forwarded>>,
+    /// Peers that joined after the server started serving clients
+    joining: UnboundedReceiver<(MessageStream, NodeId)>,
+    joining_snd: UnboundedSender<(MessageStream, NodeId)>,
+    /// Connections whose first message was read, holding the client's first
+    /// request or `None` if the connection was not a client that asked for
+    /// anything (e.g. a peer joining late or a port scanner)
+    greeted: UnboundedReceiver<Greeted>,
+    greeted_snd: UnboundedSender<Greeted>,
+    /// The number of accepted connections whose first message is being read
+    greeting: usize,
+    /// The peers currently connected and the traffic and health of their
+    /// links
+    peers: SharedPeers,
+    /// When the links to peers count as degraded
+    health_policy: HealthPolicy,
+    /// How long operations on a peer whose link is degraded may take, if
+    /// they are limited
+    degraded_time_box: Option<Duration>,
     clients: HashMap<TransactionId, ClientHandle>,
     id_gen: TransactionIdGenerator,
+    /// Where the high-water mark of `id_gen` is persisted, if anywhere
+    id_store: Option<id_store::IdStore>,
     from_clients: UnboundedReceiver<ClientState>,
-    client_state_snd: UnboundedSender<ClientState>
+    client_state_snd: UnboundedSender<ClientState>,
+    max_clients: usize,
+    /// Held by every client connection, up to `max_clients` at once
+    slots: Slots,
+    /// Held by every read or write forwarded by a peer while it runs
+    remote_slots: OperationSlots,
+    /// The tasks that run the client handlers
+    workers: WorkerPool,
+    /// Decides which connections on the client port are served
+    admission: Admission,
+    /// Applied to every connection accepted on the client port
+    socket_options: SocketOptions,
+    quota: TransactionQuota,
+    lifetime: TransactionLifetime,
+    /// Builds the layers added with `with_request_layer` for each client
+    layers: Vec<LayerFactory>,
+    retry: ForwardRetry,
+    timestamp_mode: TimestampMode,
+    execution_mode: ExecutionMode,
+    /// Where sequenced transactions are run, in a deterministic cluster
+    executor: Option<deterministic::ExecutorHandle>,
+    /// The node that sequences the transactions of a deterministic cluster,
+    /// the node with the lowest id until it is lost, and then the next one
+    /// after it that this node can reach
+    sequencer: NodeId,
+    /// The transactions this node sequenced, or holds as the sequencer's 
+    /// backup
+    sequence_log: deterministic::SequenceLog,
+    /// The node this node copies its sequence log to, as the sequencer
+    sequence_backup: Option<NodeId>,
+    /// The two-phase commit messages held for the end of the current epoch,
+    /// if they are batched
+    epochs: Option<epochs::CommitEpochs>,
+    /// Whether requests for many peers are sent along spanning trees
+    tree: Option<TreeBroadcast>,
+    /// The requests this node relays along spanning trees for other nodes
+    relays: Relays,
+    decisions: SharedDecisionLog,
+    /// The commits this node decided that are not yet applied everywhere
+    completion: SharedCompletion,
+    /// How long to wait for a commit to be acknowledged before telling the
+    /// participants that have not to commit again, if they are told again
+    commit_retry: Option<Duration>,
+    /// Whether this node is a witness, which hosts no shards but records the
+    /// decisions it learns
+    witness: bool,
+    reporter: SharedReporter,
+    drain: SharedDrain,
+    /// How often the shard's invariants are checked, if at all
+    verify_interval: Option<Duration>,
+    verification: SharedVerification,
+    /// Whether the shard's concurrency mode adapts to its abort rate
+    adaptive: bool,
+    contention: SharedContention,
+    labels: SharedLabels,
+    tenants: SharedTenants,
+    acls: SharedAcls,
+    /// How often expired accounts are removed from the shard, if ever
+    sweep_interval: Option<Duration>,
+    /// Where snapshots of the server's metrics are written, if anywhere
+    metrics: Option<metrics::MetricsDump>,
+    /// Where the shard's committed balances are written, and how often, if
+    /// anywhere
+    snapshots: Option<(std::path::PathBuf, Duration)>,
+    /// The server's view of the cluster's membership, if it gossips
+    membership: Option<SharedMembership>
 }
 
 struct ServerHandle {
     forwarding_handle: UnboundedSender<ClientState>,
     shard_ids: Vec<NodeId>,
+    shards: Arc<ShardMap>,
+    nodes: Arc<Vec<NodeInfo>>,
     server_id: NodeId,
     shard: AtomicShard,
-    tx_id: TransactionId
+    tx_id: TransactionId,
+    decisions: SharedDecisionLog,
+    completion: SharedCompletion,
+    peers: SharedPeers,
+    degraded_time_box: Option<Duration>,
+    layers: Vec<Box<dyn RequestLayer>>,
+    retry: ForwardRetry,
+    timestamp_mode: TimestampMode,
+    execution_mode: ExecutionMode,
+    reporter: SharedReporter,
+    drain: SharedDrain,
+    verification: SharedVerification,
+    contention: SharedContention,
+    labels: SharedLabels,
+    tenants: SharedTenants,
+    acls: SharedAcls,
+    membership: Option<SharedMembership>,
+    lifetime: TransactionLifetime
 }
 
 struct ClientHandle {
-    forward_snd: UnboundedSender<ClientResponse>,
-    commit_count: usize,
-    commit_status: CommitStatus
+    forward_snd: UnboundedSender<RoutedReply>,
+    stats: ConnectionStats
+}
+
+/// Statistics the server keeps about each connected client for as long as 
+/// the client handler for that connection is running.
+#[derive(Clone, Debug)]
+pub struct ConnectionStats {
+    /// The address of the connected client
+    pub peer: SocketAddr,
+    /// The instant the server accepted the connection
+    pub connected_at: Instant,
+    /// The number of requests the server forwarded to other shards on 
+    /// behalf of this client
+    pub forwarded: usize,
+    /// The number of responses from other shards routed back to this client
+    pub responses: usize
 }
 
-fn format_commit_result(result: CommitSuccess<Vec<(String, i64)>>) {
-    use CommitSuccess::*;
+impl ConnectionStats {
+    fn new(peer: SocketAddr) -> Self {
+        Self { peer, connected_at: Instant::now(), forwarded: 0, responses: 0 }
+    }
+}
+
+/// Describes the nodes in a config for clients that ask about the cluster,
+/// sorted by id. Whether each joined is filled in when a client asks.
+fn describe_nodes(config: &Config) -> Vec<NodeInfo> {
+    let mut nodes: Vec<_> = config
+        .values()
+        .map(|node| NodeInfo {
+            node_id: node.node_id,
+            name: node.name.clone(),
+            hostname: node.hostname.clone(),
+            port: node.port,
+            role: if node.is_witness() { NodeRole::Witness } else { NodeRole::Shard },
+            shards: node.shards.clone(),
+            joined: false
+        })
+        .collect();
+    nodes.sort_by_key(|node| node.node_id);
+    nodes
+}
 
-    let mut result = match result {
-        ValueChanged(r) => r,
-        NoChange(r) => r
-    };
-    
-    result.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
-    let mut output = String::new();
-    for (k, v) in result.into_iter() {
-        if v != 0 {
-            output += &format!("{k} = {v} ");
+/// Converts the reason a shard aborted an operation into the response that 
+/// the client receives, keeping the account and conflicting transaction that
+/// caused the abort where there is one.
+fn abort_response(abort: Abort<AccountId>) -> ClientResponse {
+    match abort {
+        Abort::ObjectNotFound => ClientResponse::AbortedNotFound,
+        Abort::ObjectNotFoundSpecialCase => ClientResponse::Aborted,
+        Abort::Wounded(older) => {
+            info!("Aborting operation: wounded by older transaction {older}");
+            ClientResponse::Aborted
+        },
+        Abort::ConsistencyCheckFailed(account_id) => ClientResponse::AbortedNegativeBalance(account_id),
+        Abort::AlreadyFinished(decision) => ClientResponse::AlreadyFinished(decision),
+        Abort::TooStale(account_id) => ClientResponse::AbortedTooStale(account_id),
+        Abort::Swept => {
+            info!("Aborting operation: the transaction may have seen accounts removed since");
+            ClientResponse::Aborted
+        },
+        Abort::OrderViolation(account_id, newer) => {
+            info!("Aborting operation on {account_id}: conflicts with newer transaction {newer}");
+            ClientResponse::AbortedConflict(account_id, newer)
         }
     }
+}
 
-    if !output.is_empty() {
-        println!("{output}");
+/// Runs an operation on the shard unless its deadline has passed, abandoning
+/// it if it is still waiting on other transactions when the deadline passes.
+/// Dropping the operation drops its waiters, and an operation only changes 
+/// the shard once it stops waiting, so an abandoned one leaves nothing behind.
+async fn before_deadline(deadline: Option<Instant>, op: impl std::future::Future<Output = ClientResponse>) -> ClientResponse {
+    match deadline {
+        Some(deadline) if deadline <= Instant::now() => ClientResponse::AbortedDeadlineExceeded,
+        Some(deadline) => tokio::time::timeout_at(deadline, op)
+            .await
+            .unwrap_or(ClientResponse::AbortedDeadlineExceeded),
+        None => op.await
     }
 }
 
+/// Evaluates a query against the balance of every account on the shard, 
+/// read as transaction `tx_id`.
+async fn evaluate_query(shard: &AtomicShard, node_id: NodeId, tx_id: &TransactionId, query: &Query) -> Result<QueryPart, Abort<AccountId>> {
+    let (seq, balances) = shard.read_all(tx_id).await?;
+    Ok(query.evaluate(node_id, seq, balances.iter().map(|(account_id, balance, _)| (account_id, *balance))))
+}
+
+/// Snapshots the balance of every account on the shard, read as transaction
+/// `tx_id`.
+async fn read_snapshot(shard: &AtomicShard, node_id: NodeId, tx_id: &TransactionId) -> Result<ShardSnapshot, Abort<AccountId>> {
+    let (seq, balances) = shard.read_all(tx_id).await?;
+    Ok(ShardSnapshot { node_id, seq, accounts: account_snapshots(balances) })
+}
+
+/// Snapshots the committed balance of every account on the shard, taken 
+/// between commits rather than read as a transaction.
+async fn committed_snapshot(shard: &AtomicShard, node_id: NodeId) -> ShardSnapshot {
+    let (seq, committed) = shard.snapshot().await;
+    ShardSnapshot { node_id, seq, accounts: account_snapshots(committed) }
+}
+
+/// Lays out the balance of each account and the transaction that committed
+/// it in account order.
+fn account_snapshots(balances: impl IntoIterator<Item = (AccountId, Amount, TransactionId)>) -> Vec<AccountSnapshot> {
+    let mut accounts: Vec<_> = balances
+        .into_iter()
+        .map(|(account_id, balance, committed_by)| AccountSnapshot { account_id, balance, committed_by })
+        .collect();
+    accounts.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+    accounts
+}
+
+async fn shard_digest(shard: &AtomicShard, node_id: NodeId) -> ShardDigest {
+    let (seq, digest) = shard.digest().await;
+    ShardDigest { node_id, seq, accounts: digest.count, root: digest.root, leaves: digest.leaves }
+}
+
 impl Server {
     pub async fn start(node_id: NodeId, config: Config, timeout: u64) -> Self {
-        let shard_ids = config.keys().map(char::clone).collect();
-        let (client_state_snd, from_clients) = unbounded_channel();
-        let server_pool = ConnectionPoolBuilder::new(config, node_id)
+        let all_peers = config.len() - 1;
+        Self::start_with_min_peers(node_id, config, timeout, all_peers).await
+    }
+
+    /// Starts serving clients once connected to at least `min_peers` other 
+    /// nodes, connecting to the rest in the background. Until a shard joins, 
+    /// transactions that access its accounts are aborted.
+    pub async fn start_with_min_peers(node_id: NodeId, config: Config, timeout: u64, min_peers: usize) -> Self {
+        let server_pool = ConnectionPoolBuilder::new(config.clone(), node_id)
             .await
             .unwrap_or_else(|e| {
                 eprintln!("Unable to construct connection pool: {e}");
                 std::process::exit(1);
             })
             .with_timeout(timeout)
+            .with_min_peers(min_peers)
             .connect()
             .await
             .unwrap_or_else(|_| {
-                eprintln!("Failed to connect to all nodes within {}s... Stopping.", timeout);
+                eprintln!("Failed to connect to {min_peers} nodes within {timeout}s... Stopping.");
                 std::process::exit(1);
             });
 
+        Self::over_pool(node_id, &config, server_pool)
+    }
+
+    /// Starts a node over a pool of connections to its peers.
+    fn over_pool(node_id: NodeId, config: &Config, server_pool: ConnectionPool<Forwarded>) -> Self {
+        let shard_ids: Vec<NodeId> = config.keys().copied().collect();
+        let sequencer = shard_ids.iter().min().copied().unwrap_or(node_id);
+        let shards = Arc::new(ShardMap::new(config));
+        let nodes = Arc::new(describe_nodes(config));
+        let witness = config[&node_id].is_witness();
+        if witness {
+            info!("Starting {node_id} as a witness");
+        }
+
+        let (client_state_snd, from_clients) = unbounded_channel();
+        let (greeted_snd, greeted) = unbounded_channel();
+        let (embedded_snd, embedded) = unbounded_channel();
+        let peers = server_pool.group
+            .iter()
+            .map(|(node_id, handle)| (*node_id, (handle.traffic.clone(), handle.health.clone())))
+            .collect();
+
         Self {
             node_id,
             shard: Arc::new(Shard::new(node_id)),
             id_gen: TransactionIdGenerator::new(node_id),
+            id_store: None,
             server_pool: server_pool.group,
             from_servers: server_pool.from_members,
+            to_server: server_pool.client_snd_handle,
+            joining: server_pool.joining,
+            joining_snd: server_pool.joining_snd,
+            greeted,
+            greeted_snd,
+            greeting: 0,
+            peers: Arc::new(Mutex::new(peers)),
+            health_policy: Default::default(),
+            degraded_time_box: None,
             listener: server_pool.listener,
+            embedded,
+            embedded_snd,
             clients: HashMap::new(),
             from_clients,
             client_state_snd,
-            shard_ids
+            shard_ids,
+            shards,
+            nodes,
+            max_clients: MAX_CONCURRENT_CLIENTS,
+            slots: Slots::new(MAX_CONCURRENT_CLIENTS),
+            remote_slots: OperationSlots::new(MAX_CONCURRENT_REMOTE_OPERATIONS),
+            workers: WorkerPool::new(WorkerPool::default_size()),
+            admission: Admission::new(Default::default()),
+            socket_options: Default::default(),
+            quota: Default::default(),
+            lifetime: Default::default(),
+            layers: Vec::new(),
+            retry: Default::default(),
+            timestamp_mode: Default::default(),
+            execution_mode: Default::default(),
+            executor: None,
+            sequencer,
+            sequence_log: Default::default(),
+            sequence_backup: None,
+            epochs: None,
+            tree: None,
+            relays: Default::default(),
+            decisions: Default::default(),
+            completion: Default::default(),
+            commit_retry: Some(COMMIT_RETRY_INTERVAL),
+            witness,
+            reporter: Arc::new(StdoutReporter),
+            drain: Default::default(),
+            verify_interval: Some(VERIFY_INTERVAL),
+            verification: Default::default(),
+            adaptive: true,
+            contention: Default::default(),
+            labels: Default::default(),
+            tenants: Default::default(),
+            acls: Default::default(),
+            sweep_interval: Some(SWEEP_INTERVAL),
+            metrics: None,
+            snapshots: None,
+            membership: None
         }
     }
 
-    fn pass_message(&self, target: NodeId, msg: Forwarded) -> Result<(), error::SendError<Forwarded>> {
-        self.server_pool
-            .get(&target)
-            .unwrap()
-            .pass_message(msg)
+    /// Starts a cluster in this process with a node for every shard in 
+    /// `shards`, such as to use it as a library or to debug a transaction in
+    /// a single process. The nodes run the same code as over TCP, forwarding
+    /// requests and running two-phase commit between each other, but over 
+    /// in-memory streams, and listen on no port: clients connect in memory 
+    /// with `connector`. Spawn the `serve` of every node. Commits are not 
+    /// reported unless `with_commit_reporter` says where.
+    pub async fn embedded(shards: &[&str]) -> Vec<Self> {
+        let config: Config = shards
+            .iter()
+            .enumerate()
+            .map(|(i, shard)| {
+                let node_id = NodeId(i as u32);
+                (node_id, NodeConfiguration {
+                    node_id,
+                    name: shard.to_string(),
+                    hostname: "127.0.0.1".into(),
+                    port: 0,
+                    connection_list: vec![],
+                    shards: vec![shard.to_string()]
+                })
+            })
+            .collect();
+
+        let mut node_ids: Vec<NodeId> = config.keys().copied().collect();
+        node_ids.sort();
+        let mut builders: Vec<_> = node_ids
+            .iter()
+            .map(|node_id| ConnectionPoolBuilder::in_memory(config.clone(), *node_id))
+            .collect();
+        for i in 0..builders.len() {
+            for j in i + 1..builders.len() {
+                let (near, far) = MessageStream::in_memory();
+                builders[i].admit_member(near, node_ids[j]);
+                builders[j].admit_member(far, node_ids[i]);
+            }
+        }
+
+        let mut servers = Vec::with_capacity(builders.len());
+        for (node_id, builder) in node_ids.into_iter().zip(builders) {
+            let server_pool = builder.connect().await.expect("every peer is already admitted");
+            let mut server = Self::over_pool(node_id, &config, server_pool);
+            server.reporter = Arc::new(SilentReporter);
+            servers.push(server);
+        }
+        servers
+    }
+
+    /// Connects clients to the server in memory, whether or not it also 
+    /// listens for them on its port.
+    pub fn connector(&self) -> Connector {
+        Connector(self.embedded_snd.clone())
+    }
+
+    /// Limit the number of client connections served at once. Connections 
+    /// that have not sent their first request count as clients. Once the 
+    /// limit is reached, the server stops accepting connections until a 
+    /// running client handler finishes, leaving new connections in the 
+    /// listen backlog.
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self.slots = Slots::new(max_clients);
+        self
+    }
+
+    /// Limit the number of reads and writes forwarded by peers that run at
+    /// once, apart from the limit on clients. Once the limit is reached, the
+    /// rest wait their turn, or until their deadline passes.
+    pub fn with_max_remote_operations(mut self, max_operations: usize) -> Self {
+        self.remote_slots = OperationSlots::new(max_operations);
+        self
+    }
+
+    /// Run client handlers on `workers` tasks, one per core by default, 
+    /// rather than on a task each.
+    pub fn with_client_workers(mut self, workers: usize) -> Self {
+        self.workers = WorkerPool::new(workers);
+        self
+    }
+
+    /// Limit which connections are served on the client port. A connection
+    /// the policy refuses is closed as soon as it is accepted, before the 
+    /// server spends a task or a transaction id on it.
+    pub fn with_admission_policy(mut self, policy: AdmissionPolicy) -> Self {
+        self.admission = Admission::new(policy);
+        self
+    }
+
+    /// Limit how much each transaction coordinated by this server may do. A
+    /// transaction that exceeds the quota is aborted.
+    pub fn with_transaction_quota(mut self, quota: TransactionQuota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Limit how long transactions coordinated by this server may run. A
+    /// transaction that has not committed when its lifetime runs out is 
+    /// aborted, whether it is waiting on the client or on a shard.
+    pub fn with_transaction_lifetime(mut self, lifetime: TransactionLifetime) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Add a layer to the chain every client request passes through before it
+    /// is handled. `layer` builds a fresh instance for each client connection.
+    /// Layers run in the order they were added, after requests are logged and
+    /// before the transaction quota and drain checks.
+    pub fn with_request_layer<L: RequestLayer + 'static>(mut self, layer: impl Fn() -> L + Send + Sync + 'static) -> Self {
+        self.layers.push(Arc::new(move || Box::new(layer())));
+        self
+    }
+
+    /// Choose how requests forwarded to a shard that has not joined yet are
+    /// retried before the transaction is aborted.
+    pub fn with_forward_retry(mut self, retry: ForwardRetry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Choose when transactions coordinated by this server take their ids.
+    pub fn with_timestamp_mode(mut self, timestamp_mode: TimestampMode) -> Self {
+        self.timestamp_mode = timestamp_mode;
+        self
     }
 
-    fn pass_to_client(&self, tx_id: &TransactionId, msg: ClientResponse) -> Result<(), error::SendError<ClientResponse>> {
+    /// Choose how the transactions committed on this server's shard are 
+    /// reported. By default, they are printed to stdout.
+    pub fn with_commit_reporter(mut self, reporter: impl CommitReporter + 'static) -> Self {
+        self.reporter = Arc::new(reporter);
+        self
+    }
+
+    /// Check the invariants of this server's shard in the background every
+    /// `interval`, or never if `None`. Violations are logged and reported to
+    /// `Verification` admin requests.
+    pub fn with_verify_interval(mut self, interval: Option<Duration>) -> Self {
+        self.verify_interval = interval;
+        self
+    }
+
+    /// Count the reads, writes, commits and aborts on the shard, as metrics
+    /// dumps report them, or not. Counting is on by default and does not
+    /// take any lock the shard's operations take.
+    pub fn with_shard_counting(self, enabled: bool) -> Self {
+        self.shard.set_counting(enabled);
+        self
+    }
+
+    /// Check the prepares that queue on the shard oldest first, or all at 
+    /// once in the order they arrived. Ordering is on by default, so that 
+    /// younger transactions spend less time waiting on older ones.
+    pub fn with_prepare_ordering(self, enabled: bool) -> Self {
+        self.shard.set_prepare_ordering(enabled);
+        self
+    }
+
+    /// Count the link to a peer as degraded once it goes over the limits of
+    /// `policy`, which are checked every time a message is sent to the peer.
+    pub fn with_health_policy(mut self, policy: HealthPolicy) -> Self {
+        self.health_policy = policy;
+        for handle in self.server_pool.values() {
+            handle.health.lock().unwrap().policy = policy;
+        }
+        self
+    }
+
+    /// Set `options` on every connection to a peer or from a client,
+    /// including the peers already connected.
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = options;
+        for handle in self.server_pool.values() {
+            Self::set_socket_options(handle, &options);
+        }
+        self
+    }
+
+    fn set_socket_options(handle: &RemoteServerHandle<Forwarded>, options: &SocketOptions) {
+        if let Some(Err(e)) = handle.socket.as_ref().map(|socket| socket.apply(options)) {
+            error!("Failed to set socket options for {}: {e:?}", handle.member_id);
+        }
+    }
+
+    /// Give reads and writes on a peer whose link is degraded at most
+    /// `time_box` before they are abandoned as if their deadline passed,
+    /// rather than wait on the peer as long as on a healthy one. Operations
+    /// are not limited if `None`.
+    pub fn with_degraded_time_box(mut self, time_box: Option<Duration>) -> Self {
+        self.degraded_time_box = time_box;
+        self
+    }
+
+    /// Tell participants that have not acknowledged applying a commit this
+    /// server decided to commit again every `interval`, or never if `None`.
+    pub fn with_commit_retry(mut self, interval: Option<Duration>) -> Self {
+        self.commit_retry = interval;
+        self
+    }
+
+    /// Lift a pause of the cluster on this node after `lease` if it was not
+    /// resumed sooner, e.g. since the node coordinating it stopped.
+    pub fn with_pause_lease(self, lease: Duration) -> Self {
+        self.drain.set_pause_lease(lease);
+        self
+    }
+
+    /// Remove the shard's expired accounts every `interval`, or never if 
+    /// `None`. Expired accounts read as missing either way.
+    pub fn with_sweep_interval(mut self, interval: Option<Duration>) -> Self {
+        self.sweep_interval = interval;
+        self
+    }
+
+    /// Limit how many transactions labelled `label` this server coordinates 
+    /// at once. Transactions given the label while that many are running are
+    /// aborted.
+    pub fn with_label_quota(self, label: impl Into<String>, max_active: usize) -> Self {
+        self.labels.lock().unwrap().set_quota(label.into(), max_active);
+        self
+    }
+
+    /// Serve a tenant. Once a server has tenants, every transaction must
+    /// authenticate as one before anything else, and may only touch the 
+    /// accounts in that tenant's namespace.
+    pub fn with_tenant(self, name: impl Into<String>, policy: TenantPolicy) -> Self {
+        self.tenants.lock().unwrap().add(name.into(), policy);
+        self
+    }
+
+    /// Attach an access control list to an account, as an operator can with
+    /// `SetOwner` and `Grant` admin requests. Only its owner and the tenants
+    /// it grants access to may then touch the account through this server.
+    /// Any change made to the account's list while the cluster runs, on this
+    /// or another node, replaces it.
+    pub fn with_acl(self, acl: AccountAcl) -> Self {
+        self.acls.lock().unwrap().apply(acl::configured(acl));
+        self
+    }
+
+    /// Persist the access control lists this server enforces in the file at
+    /// `path`, picking up those stored there by an earlier run, so that the
+    /// changes made while the cluster runs survive a restart.
+    pub fn with_acl_file(self, path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let (file, changes) = acl::AclFile::open(path)?;
+        let mut acls = self.acls.lock().unwrap();
+        for change in changes {
+            acls.apply(change);
+        }
+        acls.set_file(file);
+        drop(acls);
+        Ok(self)
+    }
+
+    /// Record the reads, writes, commits and aborts of the transactions this
+    /// server coordinates to a trace at `path`, which the `what_if` example
+    /// replays under each concurrency control policy.
+    pub fn with_workload_trace(mut self, path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let trace = workload::WorkloadTrace::create(path)?;
+        self.layers.push(Arc::new(move || Box::new(workload::WorkloadLayer(trace.clone()))));
+        Ok(self)
+    }
+
+    /// Choose when the shard switches between concurrency modes as its abort
+    /// rate changes, or keep it in its current mode if `None`.
+    pub fn with_contention_policy(mut self, policy: Option<ContentionPolicy>) -> Self {
+        self.adaptive = policy.is_some();
+        self.contention.lock().unwrap().policy = policy.unwrap_or_default();
+        self
+    }
+
+    /// Pin the concurrency mode of the shard, as an operator can with a 
+    /// `PinConcurrencyMode` admin request.
+    pub fn with_concurrency_mode(self, mode: ConcurrencyMode) -> Self {
+        let mode = self.contention.lock().unwrap().pin(Some(mode));
+        self.shard.set_mode(mode);
+        self
+    }
+
+    /// Choose how the cluster runs transactions. Every node must be given the
+    /// same mode.
+    pub fn with_execution_mode(mut self, execution_mode: ExecutionMode) -> Self {
+        self.execution_mode = execution_mode;
+        self
+    }
+
+    /// Batch the prepares, votes and decisions this server sends each peer 
+    /// over epochs of `length`, sending every peer one message per epoch 
+    /// instead of one per transaction, or send each right away if `None`. 
+    /// Batching saves messages at high throughput at the cost of up to an 
+    /// epoch of latency per round of a commit. Nodes with different epochs,
+    /// or none, still understand each other.
+    pub fn with_commit_epoch(mut self, length: Option<Duration>) -> Self {
+        self.epochs = length.map(epochs::CommitEpochs::new);
+        self
+    }
+
+    /// Send the prepares and aborts of a transaction along a spanning tree
+    /// once they are for enough peers, with each node gathering the votes or
+    /// acknowledgements of the nodes below it, rather than to every peer 
+    /// directly, or always directly if `None`. Every node relays requests
+    /// whatever its own setting, splitting them by its own fanout.
+    pub fn with_tree_broadcast(mut self, tree: Option<TreeBroadcast>) -> Self {
+        self.tree = tree;
+        self
+    }
+
+    /// Gossip with the other nodes about the cluster's membership, probing
+    /// one peer per period of `interval` and spreading the joins, suspicions
+    /// and failures it learns on its probes, or not at all if `None`. Nodes
+    /// that gossip keep their view of the membership up to date without
+    /// every node probing every other.
+    pub fn with_gossip(mut self, interval: Option<Duration>) -> Self {
+        self.membership = interval.map(|interval| {
+            let peers = self.server_pool.keys().copied();
+            Arc::new(Mutex::new(Membership::new(self.node_id, interval, peers, Instant::now())))
+        });
+        self
+    }
+
+    /// Choose how many of the transactions that finished on this server's 
+    /// shard it remembers the outcomes of, and for how long. Reads, writes 
+    /// and prepares that arrive for a remembered transaction are refused, 
+    /// and `Outcome` admin requests are answered for it.
+    pub fn with_finished_retention(self, capacity: usize, retention: Duration) -> Self {
+        self.shard.set_finished_retention(capacity, retention);
+        self
+    }
+
+    /// Persist the high-water mark of transaction ids issued by this server
+    /// in the file at `path`, resuming above the mark stored there by an 
+    /// earlier run so that ids stay monotone across restarts.
+    pub fn with_id_file(mut self, path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let (mut store, after) = id_store::IdStore::open(path)?;
+        // Reserve ids for the first transactions before serving any, so that
+        // the server only waits on the file if ids outrun the writer thread
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        store.cover(now.max(after))?;
+        self.id_gen = TransactionIdGenerator::resume(self.node_id, after);
+        self.id_store = Some(store);
+        Ok(self)
+    }
+
+    /// Write a snapshot of the server's metrics to a new file at `path` every
+    /// `interval`, in `format`, so that an experiment leaves them behind 
+    /// without anything collecting them while it runs.
+    pub fn with_metrics_dump(mut self, path: impl AsRef<std::path::Path>, interval: Duration, format: MetricsFormat) -> std::io::Result<Self> {
+        self.metrics = Some(metrics::MetricsDump::create(path, interval, format)?);
+        Ok(self)
+    }
+
+    /// Write the committed balance of every account on the shard to a file
+    /// at `path` every `interval`, replacing the last snapshot, after first
+    /// restoring the shard from the snapshot an earlier run left there, if
+    /// any. Commits made after the last snapshot before a restart are lost,
+    /// since the node keeps no log of them to replay. Fails if the file 
+    /// exists but cannot be read whole, rather than start from part of it.
+    pub async fn with_snapshots(mut self, path: impl AsRef<std::path::Path>, interval: Duration) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if let Some((seq, accounts)) = snapshots::restore(&self.shard, self.node_id, path).await? {
+            info!("Restored {accounts} accounts as of commit {seq} from {}", path.display());
+        }
+        self.snapshots = Some((path.to_path_buf(), interval));
+        Ok(self)
+    }
+
+    /// Returns a snapshot of the statistics of every connected client.
+    pub fn connection_stats(&self) -> Vec<(TransactionId, ConnectionStats)> {
         self.clients
-            .get(&tx_id)
-            .unwrap()
-            .forward_snd
-            .send(msg)
+            .iter()
+            .map(|(tx_id, handle)| (*tx_id, handle.stats.clone()))
+            .collect()
     }
 
-    fn broadcast(&self, msg: Forwarded) -> Result<(), error::SendError<Forwarded>> {
-        self.server_pool
-            .values()
-            .try_for_each(|target| target.pass_message(msg.clone()))
+    fn pass_message(&mut self, target: NodeId, msg: Forwarded) -> Result<(), error::SendError<Forwarded>> {
+        if let Some(epochs) = self.epochs.as_mut().filter(|_| epochs::is_commit_round(&msg)) {
+            epochs.hold(target, msg);
+            return Ok(());
+        }
+
+        match self.server_pool.get(&target) {
+            Some(handle) => handle.pass_message(msg),
+            None => Err(error::SendError(msg))
+        }
     }
 
-    fn get_server_send(&self, node_id: NodeId) -> UnboundedSender<Forwarded> {
-        self.server_pool.get(&node_id).unwrap().to_client.clone()
+    /// Tells the client handler for a request that a shard it forwarded to 
+    /// has not joined yet or whose link was lost, standing in for that 
+    /// shard's reply. 
+    fn reply_unreachable(&mut self, tx_id: &TransactionId, fwd_id: ForwardId, node_id: NodeId) {
+        trace!("Shard {node_id} has not joined or is down: replying to {tx_id} in its place");
+        self.pass_to_client(tx_id, (fwd_id, node_id, ShardReply::Unreachable));
     }
 
-    fn get_handle(&mut self) -> ServerHandle {        
-        ServerHandle { 
-            forwarding_handle: self.client_state_snd.clone(), 
-            shard_ids: self.shard_ids.clone(),
-            server_id: self.node_id,
-            shard: self.shard.clone(),
-            tx_id: self.id_gen.next()
+    /// Routes a reply from a shard to the client handler that forwarded the
+    /// request. A reply may arrive after its handler finished, e.g. once the
+    /// handler gave up on a degraded shard, and is then dropped. The handler
+    /// may also have exited before the server task reaped it, since its 
+    /// `Finished` message and the reply arrive on different queues, and it is
+    /// then reaped right away.
+    fn pass_to_client(&mut self, tx_id: &TransactionId, msg: RoutedReply) {
+        let Some(handle) = self.clients.get_mut(tx_id) else {
+            info!("Dropping reply from shard {} to forward {} for {tx_id}: its client finished", msg.1, msg.0);
+            return;
+        };
+        handle.stats.responses += 1;
+        if handle.forward_snd.send(msg).is_err() {
+            info!("Dropping reply to {tx_id}: its client handler exited");
+            self.reap_client(tx_id);
         }
     }
 
-    fn handle_client_state(&mut self, client_state: ClientState) {
-        use ClientState::*;
-        match client_state {
-            Finished(tx_id) => {
-                trace!("Reaping client connection for {tx_id}");
-                self.clients.remove(&tx_id);
-            },
-            Forward(ForwardTarget::Broadcast, tx_id, req) => {
-                let fwd_req: Forwarded = Forwarded::Request(tx_id, req);
-                if let Err(e) = self.broadcast(fwd_req) {
-                    error!("Unknown server disconnected: {e} ... exiting.");
-                    std::process::exit(1);
-                }
-            },
-            Forward(ForwardTarget::Node(node_id), tx_id, req) => {
-                let fwd_req = Forwarded::Request(tx_id, req);
-                if let Err(e) = self.pass_message(node_id, fwd_req) {
-                    error!("Server {node_id} disconnected: {e} ... exiting.");
-                    std::process::exit(1);
-                }
+    /// Forgets a client whose handler finished.
+    fn reap_client(&mut self, tx_id: &TransactionId) {
+        trace!("Reaping client connection for {tx_id}");
+        if let Some(handle) = self.clients.remove(tx_id) {
+            let stats = handle.stats;
+            self.admission.close(stats.peer.ip());
+            info!(
+                "Client at {} for {tx_id} disconnected after {:?} -- forwarded={} responses={}", 
+                stats.peer, stats.connected_at.elapsed(), stats.forwarded, stats.responses
+            );
+        }
+    }
+
+    fn record_forward(&mut self, msg: &Forwarded) {
+        if let Some(handle) = msg.tx_id().and_then(|tx_id| self.clients.get_mut(&tx_id)) {
+            handle.stats.forwarded += 1;
+        }
+    }
+
+    /// Tells every participant that has not acknowledged applying a commit
+    /// within the retry interval to commit again.
+    fn retry_commits(&mut self) {
+        let Some(interval) = self.commit_retry else { return };
+        let due = self.completion.lock().unwrap().due(Instant::now(), interval);
+        for (tx_id, participants) in due {
+            info!("Commit of {tx_id} not acknowledged by {participants:?}: telling them again");
+            for node_id in participants {
+                self.handle_client_state(ClientState::Forward(ForwardTarget::Node(node_id), Forwarded::DoCommit(tx_id, None)));
+            }
+        }
+    }
+
+    /// Sends every peer the two-phase commit messages held for it during the
+    /// epoch that just ended.
+    fn end_epoch(&mut self) {
+        let Some(epochs) = self.epochs.as_mut() else { return };
+        for (node_id, msg) in epochs.end() {
+            trace!("Sending {node_id} the commit messages of the last epoch");
+            let Some(handle) = self.server_pool.get(&node_id) else {
+                info!("Dropping the commit messages of the last epoch for {node_id}: its link was lost");
+                continue;
+            };
+            if let Err(e) = handle.pass_message(msg) {
+                error!("Server {node_id} disconnected: {e}");
+                self.lose_peer(node_id);
             }
+        }
+    }
+
+    /// Whether messages can be sent to a peer: it joined, its link has not
+    /// been lost, and gossip has not declared it dead.
+    fn reachable(&self, node_id: NodeId) -> bool {
+        self.server_pool.contains_key(&node_id)
+            && self.membership.as_ref().is_none_or(|membership| !membership.lock().unwrap().is_dead(node_id))
+    }
+
+    /// Handles losing the link to a peer. A node that gossips drops the link
+    /// and suspects the peer, which gossip then confirms or refutes, and 
+    /// answers requests for it from then on as if it had never joined. A 
+    /// node that does not gossip has no view of the membership to fall back
+    /// on, and exits.
+    fn lose_peer(&mut self, node_id: NodeId) {
+        let Some(membership) = &self.membership else {
+            eprintln!("Server {node_id} disconnected ... exiting.");
+            std::process::exit(1);
         };
+
+        warn!("Lost the link to {node_id}: suspecting it");
+        membership.lock().unwrap().disconnected(node_id, Instant::now());
+        self.server_pool.remove(&node_id);
+        self.peers.lock().unwrap().remove(&node_id);
+        self.follow_sequencer(node_id);
     }
 
-    fn handle_remote_request(&mut self, sender_id: NodeId, tx_id: TransactionId, request: ClientRequest) {
-        use CommitStatus::*;
-        use Forwarded::*;
+    /// Where to send replies to a peer, or `None` once its link was lost, 
+    /// such as for the requests it sent just before it disconnected.
+    fn get_server_send(&self, node_id: NodeId) -> Option<UnboundedSender<Forwarded>> {
+        self.server_pool.get(&node_id).map(|handle| handle.to_client.clone())
+    }
 
-        let resp_handle = self.get_server_send(sender_id);
-        let shard = self.shard.clone();
-        let shard_id = self.node_id;
-        info!("Handling remote request from {sender_id} for {tx_id}: {request:?}");
-        tokio::spawn(async move {
-            let fwd_resp: Forwarded = match request {
-                ClientRequest::WriteBalance(account_id, diff) => {
-                    let resp = match shard.read(&tx_id, &account_id).await {
-                        Ok(balance) => match shard.write(&tx_id, account_id, balance + diff.0).await {
-                            Ok(_) => ClientResponse::Ok,
-                            Err(Abort::ObjectNotFound) => ClientResponse::AbortedNotFound,
-                            Err(_) => ClientResponse::Aborted
-                        },
-                        Err(Abort::ObjectNotFound) => 
-                            match shard.write(&tx_id, account_id, diff.0).await {
-                                Ok(_) => ClientResponse::Ok,
-                                Err(Abort::ObjectNotFound) => ClientResponse::AbortedNotFound,
-                                Err(_) => ClientResponse::Aborted
-                            }
-                        Err(_) => ClientResponse::Aborted
-                    };
+    /// Moves the sequencing of a deterministic cluster on from a node that 
+    /// was lost, if it was the sequencer, to the next node after it 
+    /// that this node can reach. The node that takes over was the old 
+    /// sequencer's backup, so it holds every transaction the shards were 
+    /// handed, and hands them out again before sequencing more. A sequencer
+    /// that lost its backup copies its log to the next one instead.
+    fn follow_sequencer(&mut self, lost: NodeId) {
+        if self.executor.is_none() {
+            return;
+        }
 
-                    Response(tx_id, resp)
-                },
-                ClientRequest::ReadBalance(account_id) => {
-                    let resp = match shard.read(&tx_id, &account_id).await {
-                        Ok(value) => ClientResponse::Value(account_id, value),
-                        Err(Abort::ObjectNotFound) => ClientResponse::AbortedNotFound,
-                        Err(_) => ClientResponse::Aborted
-                    };
+        if lost == self.sequencer {
+            let mut hosts = self.shard_ids.clone();
+            hosts.sort();
+            let after = hosts.iter().position(|node_id| *node_id == lost).map_or(0, |i| i + 1);
+            hosts.rotate_left(after);
+            let Some(next) = hosts.into_iter().find(|node_id| *node_id == self.node_id || self.reachable(*node_id)) else { return };
+            warn!("Sequencer {lost} was lost: {next} takes over");
+            self.sequencer = next;
+            if next != self.node_id {
+                return;
+            }
 
-                    Response(tx_id, resp)
-                },
-                ClientRequest::Commit => {
-                    // Check that the commit is valid. This is the first stage 
-                    // in the 2 phase commit process.
-                    match shard.check_commit(&tx_id).await {
-                        Ok(_) => TwoPhaseCommitStatus(tx_id, ReadyToCommit),
-                        Err(e) => {
-                            info!("Unable to commit {tx_id}: {e:?}");
-                            TwoPhaseCommitStatus(tx_id, CannotCommit)
-                        }
-                    }
-                },
-                ClientRequest::Abort => {
-                    shard.abort(&tx_id).await.unwrap();
-                    info!("Abort {tx_id} completed on {shard_id}.");
-                    Response(tx_id, ClientResponse::Aborted)
+            if let Some(last_id) = self.sequence_log.last_id() {
+                self.id_gen.advance_past(last_id.timestamp());
+            }
+            self.sequence_log.take_over();
+            self.sequence_backup = None;
+        }
+
+        if self.sequencer == self.node_id {
+            self.replicate_sequence_log();
+        }
+    }
+
+    /// The node after this one, by id, that this node can reach, which
+    /// backs up its sequence log.
+    fn sequence_backup(&self) -> Option<NodeId> {
+        let mut hosts: Vec<_> = self.shard_ids.iter().copied().filter(|node_id| *node_id != self.node_id).collect();
+        hosts.sort();
+        let after = hosts.partition_point(|node_id| *node_id < self.node_id);
+        hosts.rotate_left(after);
+        hosts.into_iter().find(|node_id| self.reachable(*node_id))
+    }
+
+    /// Copies the whole sequence log to the backup once the backup changes,
+    /// such as when a node joins or the backup is lost. Without a 
+    /// backup, every transaction waiting on one is handed to its shards.
+    fn replicate_sequence_log(&mut self) {
+        let backup = self.sequence_backup();
+        if backup != self.sequence_backup {
+            self.sequence_backup = backup;
+            if let Some(backup) = backup {
+                info!("Backing up the sequence log to {backup}");
+                let entries: Vec<_> = self.sequence_log.entries().cloned().collect();
+                for tx in entries {
+                    self.send_to(backup, Forwarded::Replicate(Box::new(tx)));
                 }
-            };
+            }
+        }
 
-            if let Err(_) = resp_handle.send(fwd_resp) {
-                error!("Server {} disconnected ... exiting.", sender_id)
+        if self.sequence_backup.is_none() {
+            let held = self.sequence_log.last_seq().map(|seq| self.sequence_log.acknowledge(seq)).unwrap_or_default();
+            for tx in held {
+                self.dispatch(tx);
             }
-        });
+        }
     }
 
-    fn handle_two_phase_commit(&mut self, tx_id: TransactionId, commit_status: CommitStatus) {
-        let client_handle = self.clients.get_mut(&tx_id).unwrap();
-        client_handle.commit_count += 1;
-        if let CommitStatus::CannotCommit = commit_status {
-            client_handle.commit_status = commit_status;
+    /// Places a transaction in the cluster's order as its sequencer and, 
+    /// once the backup holds a copy, hands it to every shard it writes to,
+    /// in that order. A transaction that writes to a shard that has not 
+    /// joined is refused, since the shard could not run it, and one that
+    /// was placed already, which its coordinator sent again, is dropped.
+    fn sequence(&mut self, coordinator: NodeId, client_tx: TransactionId, fwd_id: ForwardId, writes: Vec<(AccountId, BalanceDiff)>) {
+        if self.executor.is_none() || self.sequencer != self.node_id {
+            error!("Ignoring {client_tx} from {coordinator}: this node does not sequence a deterministic cluster");
+            return;
+        }
+        if self.sequence_log.contains(coordinator, client_tx) {
+            trace!("Ignoring {client_tx} from {coordinator}: it was sequenced already");
+            return;
         }
 
-        trace!("Two-phase commit for {tx_id} received {}/{} responses", client_handle.commit_count, self.server_pool.len());
-        if client_handle.commit_count == self.server_pool.len() {
-            match client_handle.commit_status {
-                CommitStatus::ReadyToCommit => {
-                    trace!("All shards ready to commit.");
-                    let fwd_req = Forwarded::DoCommit(tx_id);
-                    if let Err(e) = self.pass_to_client(&tx_id, ClientResponse::CommitOk) {
-                        error!("Client handler for {tx_id} crashed: {e}");
-                        std::process::exit(1);
-                    }
+        let participants = deterministic::participants(&self.shards, &writes);
+        let missing = participants.iter().find(|id| **id != self.node_id && !self.server_pool.contains_key(id));
+        if let Some(missing) = missing.copied() {
+            info!("Refusing to sequence {client_tx}: shard {missing} has not joined");
+            self.send_to(coordinator, Forwarded::Response(client_tx, fwd_id, ClientResponse::AbortedUnavailable(missing)));
+            return;
+        }
 
-                    if let Err(e) = self.broadcast(fwd_req) {
-                        error!("Unknown server disconnected: {e} ... exiting.");
-                        std::process::exit(1);
-                    }   
-                },
-                CommitStatus::CannotCommit => {
-                    trace!("Not all shards can commit. Notifying client task to initiate abort.");
-                    if let Err(e) = self.pass_to_client(&tx_id, ClientResponse::Aborted) {
-                        error!("Client handler for {tx_id} crashed: {e}");
-                        std::process::exit(1);
-                    }
-                }
+        self.replicate_sequence_log();
+        let id = self.next_transaction_id();
+        let tx = self.sequence_log.append(id, coordinator, client_tx, fwd_id, writes);
+        trace!("Sequenced {client_tx} from {coordinator} at {} as {}", tx.seq, tx.id);
+        match self.sequence_backup {
+            Some(backup) => self.send_to(backup, Forwarded::Replicate(Box::new(tx))),
+            None => {
+                self.sequence_log.acknowledge(tx.seq);
+                self.dispatch(tx);
             }
         }
     }
 
-    fn handle_server_state(&mut self, state: ServerStateMessage<Forwarded>) {
-        use ServerStateMessageType::*;
-        use Forwarded::*;
-        
-        match state.msg {
-            Message(Request(tx_id, request)) => {
-                trace!("Handling remote request for {tx_id} on behalf of coordinator {}: {request:?}", state.member_id);
-                self.handle_remote_request(state.member_id, tx_id, request)
-            },
-            Message(Response(tx_id, resp)) => {
-                trace!("Passing response to remote request for {tx_id} from shard {} back to client: {resp:?}", state.member_id);
-                if let Err(e) = self.pass_to_client(&tx_id, resp) {
-                    error!("Client handler for {tx_id} crashed: {e}");
-                    std::process::exit(1); // TODO maybe abort the transaction???
-                }
-            },
-            Message(TwoPhaseCommitStatus(tx_id, commit_status)) => {
-                trace!("Handling two-phase commit status for {tx_id} initiated at {}: {commit_status:?}", state.member_id);
-                self.handle_two_phase_commit(tx_id, commit_status);
-            },
-            Message(DoCommit(tx_id)) => {
-                trace!("Doing commit for {tx_id}...");
-                let shard: AtomicShard = self.shard.clone();
-                tokio::spawn(async move {
-                    match shard.commit(&tx_id).await {
-                        Ok(result) => format_commit_result(result),
-                        Err(e) => error!("FATAL ERROR: Failed to commit {tx_id}: {e:?}")
-                    }
-                });
-            },
-            Disconnected => {
-                eprintln!("Server {} disconnected ... exiting.", state.member_id);
-                std::process::exit(1);
+    /// Hands a sequenced transaction to every shard it writes to.
+    fn dispatch(&mut self, tx: SequencedTransaction) {
+        for node_id in deterministic::participants(&self.shards, &tx.writes) {
+            self.send_to(node_id, Forwarded::Sequenced(Box::new(tx.clone())));
+        }
+    }
+
+    /// Sends a message to a node, handling it right away if it is for this 
+    /// node.
+    fn send_to(&mut self, node_id: NodeId, msg: Forwarded) {
+        if node_id == self.node_id {
+            self.handle_forwarded(node_id, msg);
+        } else if !self.reachable(node_id) {
+            error!("Dropping {msg:?} for {node_id}: it has not joined or is down");
+        } else if let Err(e) = self.pass_message(node_id, msg) {
+            error!("Server {node_id} disconnected: {e}");
+            self.lose_peer(node_id);
+        }
+    }
+
+    /// Forwards a message to several nodes. A request for enough of them is
+    /// sent along a spanning tree, and the replies of the nodes below each
+    /// node it is relayed to come back gathered in one message.
+    fn multicast(&mut self, node_ids: Vec<NodeId>, msg: Forwarded) {
+        let along_tree = matches!(msg, Forwarded::Request(..)) && self.tree.is_some_and(|tree| tree.applies(node_ids.len()));
+        if !along_tree {
+            for node_id in node_ids {
+                self.handle_client_state(ClientState::Forward(ForwardTarget::Node(node_id), msg.clone()));
             }
+            return;
+        }
+
+        let Forwarded::Request(tx_id, fwd_id, ..) = msg else { unreachable!() };
+        for node_id in self.relay(&node_ids, &msg) {
+            self.reply_unreachable(&tx_id, fwd_id, node_id);
         }
     }
 
-    pub async fn serve(&mut self) {
-        loop {
-            select! {
-                client = self.listener.accept() => match client {
-                    Ok((stream, _addr)) => {
-                        let (forward_snd, rcv) = unbounded_channel();
-                        
-                        let handle = self.get_handle();
-                        let tx_id = handle.tx_id;
-                        let client = Client::new(handle, stream, rcv);
-                        self.clients.insert(tx_id, ClientHandle { 
-                            forward_snd,
-                            commit_count: 0,
-                            commit_status: CommitStatus::ReadyToCommit
-                        });
-
-                        info!("Connected to client at {_addr:?} -- id={tx_id}");
-                        tokio::spawn(client.handle());
-                    },
-                    Err(e) => error!("failed to accept client: {e:?}")
-                },
-                Some(state) = self.from_clients.recv() => self.handle_client_state(state),
-                Some(state) = self.from_servers.recv() => self.handle_server_state(state)
+    /// Passes a request on to the nodes below this one in a spanning tree 
+    /// over `node_ids`, returning those this node cannot reach, which are 
+    /// left out of the tree.
+    fn relay(&mut self, node_ids: &[NodeId], msg: &Forwarded) -> Vec<NodeId> {
+        let tree = self.tree.unwrap_or(TreeBroadcast { threshold: 0, fanout: TREE_FANOUT });
+        let (joined, missing): (Vec<NodeId>, Vec<NodeId>) = node_ids
+            .iter()
+            .partition(|node_id| self.reachable(**node_id));
+        for (node_id, subtree) in tree.subtrees(&joined) {
+            self.record_forward(msg);
+            self.send_to(node_id, Forwarded::Relay(subtree, Box::new(msg.clone())));
+        }
+
+        missing
+    }
+
+    /// Ends a protocol period of the gossip about the cluster's membership.
+    fn gossip_round(&mut self) {
+        let Some(membership) = &self.membership else { return };
+        let out = membership.lock().unwrap().tick(Instant::now());
+        let sequencer_dead = membership.lock().unwrap().is_dead(self.sequencer);
+        self.gossip(out);
+
+        let unreachable_backup = self.sequence_backup.filter(|node_id| !self.reachable(*node_id));
+        if sequencer_dead {
+            self.follow_sequencer(self.sequencer);
+        } else if let Some(backup) = unreachable_backup {
+            self.follow_sequencer(backup);
+        }
+    }
+
+    /// Sends gossip to the peers it is for. Gossip for a peer this node is 
+    /// not connected to is lost, as it would be on an unreliable network, 
+    /// and the probe it was part of fails.
+    fn gossip(&mut self, out: Vec<(NodeId, tx_proto::peer::Gossip)>) {
+        for (node_id, gossip) in out {
+            if !self.server_pool.contains_key(&node_id) {
+                trace!("Dropping gossip for {node_id}: it has not joined");
+            } else if let Err(e) = self.pass_message(node_id, Forwarded::Gossip(gossip)) {
+                error!("Server {node_id} disconnected: {e}");
+                self.lose_peer(node_id);
+            }
+        }
+    }
+
+    fn next_transaction_id(&mut self) -> TransactionId {
+        let tx_id = self.id_gen.next();
+        if let Some(store) = self.id_store.as_mut() {
+            if let Err(e) = store.cover(tx_id.timestamp()) {
+                eprintln!("Failed to persist transaction id high-water mark: {e} ... exiting.");
+                std::process::exit(1);
             }
         }
+
+        tx_id
+    }
+
+    /// Removes the shard's expired accounts as a transaction of its own, if
+    /// any are due. It takes a fresh id so that it is ordered after the 
+    /// commits that created the accounts; accounts that a transaction still
+    /// running may see are left for a later sweep.
+    fn sweep_expired(&mut self) {
+        if !self.shard.expiry_due() {
+            return;
+        }
+        let shard = self.shard.clone();
+        let tx_id = self.next_transaction_id();
+        crate::task::spawn(format_args!("expire {tx_id}"), async move {
+            let expired = shard.expire(&tx_id).await;
+            if !expired.is_empty() {
+                info!("{tx_id} removed expired accounts {expired:?}");
+            }
+        });
+    }
+
+    fn dump_metrics(&mut self) {
+        let links = self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(node_id, (traffic, _))| metrics::PeerTraffic { peer: *node_id, traffic: *traffic.lock().unwrap() })
+            .collect();
+        let (committed, aborted) = self.decisions.lock().unwrap().totals();
+        let (verification_passes, violations_found) = {
+            let verification = self.verification.lock().unwrap();
+            (verification.passes, verification.violations_found)
+        };
+        let snapshot = metrics::MetricsSnapshot {
+            node_id: self.node_id,
+            taken_at_ms: metrics::MetricsSnapshot::now(),
+            clients: self.clients.len(),
+            links,
+            committed,
+            aborted,
+            drain: self.drain.status(),
+            contention: self.contention.lock().unwrap().status(),
+            verification_passes,
+            violations_found,
+            shard: self.shard.counts(),
+            labels: self.labels.lock().unwrap().stats(),
+            tenants: self.tenants.lock().unwrap().stats()
+        };
+
+        if let Some(dump) = &self.metrics {
+            dump.write(snapshot);
+        }
+    }
+
+    fn memory(&self) -> ServerMemory {
+        let mut queues = vec![
+            ("clients".to_string(), self.from_clients.len()),
+            ("peers".to_string(), self.from_servers.len()),
+            ("greeted".to_string(), self.greeted.len()),
+            ("joining".to_string(), self.joining.len()),
+            ("embedded".to_string(), self.embedded.len())
+        ];
+        queues.extend(self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(node_id, (_, health))| (format!("to {node_id}"), health.lock().unwrap().status().queued)));
+        ServerMemory { sessions: self.clients.len(), greeting: self.greeting, queues }
+    }
+
+    /// The chain of layers for a new transaction.
+    fn client_layers(&self) -> Vec<Box<dyn RequestLayer>> {
+        let mut layers: Vec<Box<dyn RequestLayer>> = vec![Box::new(TraceLayer)];
+        layers.extend(self.layers.iter().map(|layer| layer()));
+        layers.push(Box::new(tenants::TenantLayer::new(self.tenants.clone(), self.acls.clone())));
+        layers.push(Box::new(quota::QuotaLayer::new(self.quota)));
+        layers.push(Box::new(drain::DrainLayer(self.drain.clone())));
+        layers.push(Box::new(labels::LabelLayer::new(self.labels.clone())));
+        layers
+    }
+
+    fn get_handle(&mut self) -> ServerHandle {        
+        ServerHandle { 
+            forwarding_handle: self.client_state_snd.clone(), 
+            shard_ids: self.shard_ids.clone(),
+            shards: self.shards.clone(),
+            nodes: self.nodes.clone(),
+            server_id: self.node_id,
+            shard: self.shard.clone(),
+            tx_id: self.next_transaction_id(),
+            decisions: self.decisions.clone(),
+            completion: self.completion.clone(),
+            peers: self.peers.clone(),
+            degraded_time_box: self.degraded_time_box,
+            layers: self.client_layers(),
+            retry: self.retry,
+            timestamp_mode: self.timestamp_mode,
+            execution_mode: self.execution_mode,
+            reporter: self.reporter.clone(),
+            drain: self.drain.clone(),
+            verification: self.verification.clone(),
+            contention: self.contention.clone(),
+            labels: self.labels.clone(),
+            tenants: self.tenants.clone(),
+            acls: self.acls.clone(),
+            membership: self.membership.clone(),
+            lifetime: self.lifetime
+        }
+    }
+
+    /// Starts a client handler for a connection once the client has sent its
+    /// first request, which is when its transaction id is allocated.
+    fn accept_client(&mut self, stream: MessageStream, addr: SocketAddr, first: ClientRequest, slot: Slot) {
+        let (forward_snd, rcv) = unbounded_channel();
+        
+        let handle = self.get_handle();
+        let tx_id = handle.tx_id;
+        let client = Client::new(handle, stream, rcv);
+        self.clients.insert(tx_id, ClientHandle { 
+            forward_snd,
+            stats: ConnectionStats::new(addr)
+        });
+
+        info!("Connected to client at {addr:?} -- id={tx_id} ({}/{} clients)", self.clients.len(), self.max_clients);
+        self.workers.run(format!("{tx_id}"), async move {
+            let _slot = slot;
+            client.handle(first).await
+        });
+    }
+
+    /// Reads the first message of a new connection on a separate task before
+    /// handing the connection back to the server. A connection may be a peer
+    /// joining late, and a client is only given a transaction id once it asks
+    /// for something, so connections that never send a request (e.g. health
+    /// checks) neither use up ids nor skew which transactions win conflicts.
+    fn greet(&mut self, mut stream: MessageStream, addr: SocketAddr, slot: Slot) {
+        let greeted_snd = self.greeted_snd.clone();
+        let joining_snd = self.joining_snd.clone();
+        let epoch = self.shards.epoch();
+        self.greeting += 1;
+        crate::task::spawn(format_args!("greeting"), async move {
+            let client = match stream.recv_either::<ClientRequest, Handshake>().await {
+                Some(Ok(Either::Left(request))) => Some((stream, request, slot)),
+                Some(Ok(Either::Right(handshake))) => {
+                    match handshake.node_id(epoch) {
+                        Some(node_id) => { 
+                            let _ = joining_snd.send((stream, node_id)); 
+                        },
+                        None => error!("Unable to decode first message from {addr}: not a handshake")
+                    }
+                    None
+                },
+                Some(Err(e)) => {
+                    error!("Unable to decode first message from {addr}: {e:?}");
+                    None
+                },
+                None => {
+                    trace!("Connection from {addr} closed before sending a message");
+                    None
+                }
+            };
+
+            let _ = greeted_snd.send((addr, client));
+        });
+    }
+
+    fn handle_greeted(&mut self, addr: SocketAddr, client: Option<(MessageStream, ClientRequest, Slot)>) {
+        self.greeting -= 1;
+        match client {
+            Some((stream, first, slot)) => self.accept_client(stream, addr, first, slot),
+            None => self.admission.close(addr.ip())
+        }
+    }
+
+    fn admit_peer(&mut self, stream: MessageStream, node_id: NodeId) {
+        if node_id == self.node_id || !self.shard_ids.contains(&node_id) || self.server_pool.contains_key(&node_id) {
+            error!("Rejecting late handshake as {node_id:?}");
+            return;
+        }
+
+        info!("Shard {node_id} joined");
+        let handle = RemoteServerHandle::spawn(stream, node_id, self.to_server.clone());
+        handle.health.lock().unwrap().policy = self.health_policy;
+        Self::set_socket_options(&handle, &self.socket_options);
+        self.peers.lock().unwrap().insert(node_id, (handle.traffic.clone(), handle.health.clone()));
+        self.server_pool.insert(node_id, handle);
+        if let Some(membership) = &self.membership {
+            membership.lock().unwrap().joined(node_id, Instant::now());
+        }
+
+        // The peer may have missed changes to the access control lists while
+        // it was away, and this node those it made
+        let acls = self.acls.lock().unwrap().changes();
+        if !acls.is_empty() {
+            self.send_to(node_id, Forwarded::AclSync(acls));
+        }
+    }
+
+    fn handle_client_state(&mut self, client_state: ClientState) {
+        use ClientState::*;
+        match client_state {
+            Finished(tx_id) => self.reap_client(&tx_id),
+            Renew(tx_id, renewed_snd) => {
+                let renewed = self.next_transaction_id();
+                if let Some(handle) = self.clients.remove(&tx_id) {
+                    self.clients.insert(renewed, handle);
+                }
+
+                trace!("Renewed {tx_id} as {renewed}");
+                if renewed_snd.send(renewed).is_err() {
+                    error!("Client handler for {tx_id} crashed");
+                }
+            },
+            Next(tx_id, next_snd) => {
+                let next = self.next_transaction_id();
+                if let Some(handle) = self.clients.remove(&tx_id) {
+                    self.clients.insert(next, handle);
+                }
+
+                trace!("Starting {next} on the connection of {tx_id}");
+                if next_snd.send((next, self.client_layers())).is_err() {
+                    error!("Client handler for {tx_id} crashed");
+                }
+            },
+            Memory(report_snd) => {
+                if report_snd.send(self.memory()).is_err() {
+                    error!("Client handler asking for the memory report crashed");
+                }
+            },
+            Forward(ForwardTarget::Sequencer, fwd_req) => {
+                let sequencer = self.sequencer;
+                self.handle_client_state(Forward(ForwardTarget::Node(sequencer), fwd_req));
+            },
+            Forward(ForwardTarget::Node(node_id), fwd_req) if node_id == self.node_id => {
+                self.record_forward(&fwd_req);
+                self.handle_forwarded(node_id, fwd_req);
+            },
+            Forward(ForwardTarget::Node(node_id), fwd_req) if !self.reachable(node_id) => 
+                if let (Some(tx_id), Some(fwd_id)) = (fwd_req.tx_id(), fwd_req.forward_id()) {
+                    self.reply_unreachable(&tx_id, fwd_id, node_id)
+                },
+            Forward(ForwardTarget::Node(node_id), fwd_req) => {
+                self.record_forward(&fwd_req);
+                if let Err(e) = self.pass_message(node_id, fwd_req) {
+                    error!("Server {node_id} disconnected: {e}");
+                    self.lose_peer(node_id);
+                }
+            },
+            Forward(ForwardTarget::Nodes(node_ids), fwd_req) => self.multicast(node_ids, fwd_req)
+        };
+    }
+
+    /// Handles a request forwarded by a coordinator. The reply goes back to 
+    /// the sender, or, if the request was `relayed` along a spanning tree, to
+    /// this node to be gathered with the replies of the nodes below it.
+    fn handle_remote_request(&mut self, sender_id: NodeId, tx_id: TransactionId, fwd_id: ForwardId, budget: Option<Duration>, request: ClientRequest, relayed: bool) {
+        use CommitStatus::*;
+        use Forwarded::*;
+
+        let resp_handle = self.get_server_send(sender_id);
+        // Votes go through the server task to be batched with the epoch's
+        let batch_handle = self.epochs.is_some().then(|| self.client_state_snd.clone());
+        let relay_handle = relayed.then(|| self.client_state_snd.clone());
+        let shard = self.shard.clone();
+        let drain = self.drain.clone();
+        let witnessed = self.witness.then(|| self.decisions.clone());
+        let shard_id = self.node_id;
+        let deadline = budget.map(|budget| Instant::now() + budget);
+        // Reads and writes wait for a slot of their own, while what finishes
+        // transactions never waits on one
+        let remote_slots = match &request {
+            ClientRequest::WriteBalance(..) | ClientRequest::WriteBalanceWithTtl(..) | ClientRequest::Increment(..)
+                | ClientRequest::ReadBalance(_) | ClientRequest::ReadBalanceIfExists(_) | ClientRequest::ReadBalanceStale(..) => Some(self.remote_slots.clone()),
+            _ => None
+        };
+        info!("Handling remote request from {sender_id} for {tx_id}: {request:?}");
+        crate::task::spawn(format_args!("remote request {tx_id} from {sender_id}"), async move {
+            let slot = match remote_slots {
+                Some(remote_slots) => Some(remote_slots.take(deadline).await),
+                None => None
+            };
+            let fwd_resp: Forwarded = match request {
+                _ if matches!(slot, Some(None)) => {
+                    info!("Abandoning {request:?} for {tx_id}: its deadline passed while waiting for a slot");
+                    Response(tx_id, fwd_id, ClientResponse::AbortedDeadlineExceeded)
+                },
+                ClientRequest::WriteBalance(..) | ClientRequest::WriteBalanceWithTtl(..) => {
+                    let (account_id, diff, ttl) = match request {
+                        ClientRequest::WriteBalanceWithTtl(account_id, diff, ttl) => (account_id, diff, Some(ttl)),
+                        ClientRequest::WriteBalance(account_id, diff) => (account_id, diff, None),
+                        _ => unreachable!()
+                    };
+                    let resp = before_deadline(deadline, async {
+                        match shard.read(&tx_id, &account_id).await {
+                            Ok(balance) => match shard.write(&tx_id, account_id, balance + diff.0).await {
+                                Ok(_) => ClientResponse::Ok,
+                                Err(e) => abort_response(e)
+                            },
+                            Err(Abort::ObjectNotFound) => 
+                                match shard.write_expiring(&tx_id, account_id, diff.0, ttl).await {
+                                    Ok(_) => ClientResponse::Ok,
+                                    Err(e) => abort_response(e)
+                                }
+                            Err(e) => abort_response(e)
+                        }
+                    }).await;
+
+                    Response(tx_id, fwd_id, resp)
+                },
+                ClientRequest::Increment(account_id, diff) => {
+                    let resp = before_deadline(deadline, async {
+                        match shard.increment(&tx_id, account_id, diff.0).await {
+                            Ok(_) => ClientResponse::Ok,
+                            Err(e) => abort_response(e)
+                        }
+                    }).await;
+
+                    Response(tx_id, fwd_id, resp)
+                },
+                ClientRequest::ReadBalanceStale(account_id, max_staleness) => {
+                    let resp = match shard.read_stale(&account_id, max_staleness).await {
+                        Ok(value) => ClientResponse::Value(account_id, value),
+                        Err(e) => abort_response(e)
+                    };
+
+                    Response(tx_id, fwd_id, resp)
+                },
+                ClientRequest::ReadBalance(account_id) => {
+                    let resp = before_deadline(deadline, async {
+                        match shard.read(&tx_id, &account_id).await {
+                            Ok(value) => ClientResponse::Value(account_id, value),
+                            Err(e) => abort_response(e)
+                        }
+                    }).await;
+
+                    Response(tx_id, fwd_id, resp)
+                },
+                ClientRequest::ReadBalanceIfExists(account_id) => {
+                    let resp = before_deadline(deadline, async {
+                        match shard.read_if_exists(&tx_id, &account_id).await {
+                            Ok(Some(value)) => ClientResponse::Value(account_id, value),
+                            Ok(None) => ClientResponse::NotFound(account_id),
+                            Err(e) => abort_response(e)
+                        }
+                    }).await;
+
+                    Response(tx_id, fwd_id, resp)
+                },
+                ClientRequest::Commit => {
+                    // Check that the commit is valid. This is the first stage 
+                    // in the 2 phase commit process.
+                    match shard.check_commit(&tx_id).await {
+                        Ok(_) if drain.prepare(tx_id) => {
+                            // A decision the shard applied before the vote was
+                            // recorded, such as the commit of a prepare sent 
+                            // again, will not be applied again to remove it
+                            if shard.outcome(&tx_id).is_some() {
+                                drain.decide(&tx_id);
+                            }
+                            TwoPhaseCommitStatus(tx_id, fwd_id, ReadyToCommit)
+                        },
+                        Ok(_) => {
+                            info!("Unable to commit {tx_id}: draining");
+                            TwoPhaseCommitStatus(tx_id, fwd_id, CannotCommit(ClientResponse::AbortedDraining))
+                        },
+                        Err(e) => {
+                            info!("Unable to commit {tx_id}: {e:?}");
+                            TwoPhaseCommitStatus(tx_id, fwd_id, CannotCommit(abort_response(e)))
+                        }
+                    }
+                },
+                ClientRequest::Abort => {
+                    shard.abort(&tx_id).await.unwrap();
+                    drain.decide(&tx_id);
+                    if let Some(decisions) = witnessed {
+                        decisions.lock().unwrap().record_witnessed(tx_id, Decision::Abort);
+                    }
+                    info!("Abort {tx_id} completed on {shard_id}.");
+                    Response(tx_id, fwd_id, ClientResponse::Aborted)
+                },
+                ClientRequest::Admin(AdminRequest::Query(query)) => {
+                    let resp = before_deadline(deadline, async {
+                        match evaluate_query(&shard, shard_id, &tx_id, &query).await {
+                            Ok(part) => ClientResponse::Admin(AdminResponse::QueryPart(Box::new(part))),
+                            Err(e) => abort_response(e)
+                        }
+                    }).await;
+
+                    Response(tx_id, fwd_id, resp)
+                },
+                ClientRequest::Admin(AdminRequest::ClusterSnapshot) => {
+                    let resp = before_deadline(deadline, async {
+                        match read_snapshot(&shard, shard_id, &tx_id).await {
+                            Ok(snapshot) => ClientResponse::Admin(AdminResponse::Snapshot(snapshot)),
+                            Err(e) => abort_response(e)
+                        }
+                    }).await;
+
+                    Response(tx_id, fwd_id, resp)
+                },
+                ClientRequest::Admin(AdminRequest::Digest) => {
+                    let digest = shard_digest(&shard, shard_id).await;
+                    Response(tx_id, fwd_id, ClientResponse::Admin(AdminResponse::Digests(vec![digest])))
+                },
+                ClientRequest::Admin(AdminRequest::Pause(within)) => {
+                    let paused = drain.quiesce(tx_id, within).await;
+                    let busy = if paused { vec![] } else { vec![shard_id] };
+                    Response(tx_id, fwd_id, ClientResponse::Admin(AdminResponse::Pause(PauseStatus { paused, busy })))
+                },
+                ClientRequest::Admin(AdminRequest::Resume) => {
+                    drain.resume();
+                    Response(tx_id, fwd_id, ClientResponse::Admin(AdminResponse::Pause(PauseStatus::default())))
+                },
+                ClientRequest::Swap(..) | ClientRequest::AssertBalance(..) | ClientRequest::AbortWithReason(_) | ClientRequest::Admin(_) | ClientRequest::Label(_) | ClientRequest::Authenticate(..) | ClientRequest::Lifetime(_) | ClientRequest::Deadline(..) | ClientRequest::Verbosity(_) | ClientRequest::ClusterInfo | ClientRequest::Hello(_) => {
+                    error!("Ignoring {request:?} for {tx_id} forwarded by {sender_id}: only coordinators handle it");
+                    return
+                }
+            };
+
+            let sent = match (relay_handle, batch_handle) {
+                (Some(relay_handle), _) => 
+                    relay_handle.send(ClientState::Forward(ForwardTarget::Node(shard_id), fwd_resp.into_relayed(shard_id))).is_ok(),
+                (None, Some(batch_handle)) if epochs::is_commit_round(&fwd_resp) => 
+                    batch_handle.send(ClientState::Forward(ForwardTarget::Node(sender_id), fwd_resp)).is_ok(),
+                _ => resp_handle.is_some_and(|resp_handle| resp_handle.send(fwd_resp).is_ok())
+            };
+            if !sent {
+                error!("Server {sender_id} disconnected ... dropping the reply to {tx_id}")
+            }
+        });
+    }
+
+    fn handle_server_state(&mut self, state: ServerStateMessage<Forwarded>) {
+        match state.msg {
+            ServerStateMessageType::Message(msg) => self.handle_forwarded(state.member_id, msg),
+            ServerStateMessageType::Disconnected => self.lose_peer(state.member_id),
+            ServerStateMessageType::Degraded(true) => warn!("Link to shard {} is degraded", state.member_id),
+            ServerStateMessageType::Degraded(false) => info!("Link to shard {} recovered", state.member_id)
+        }
+    }
+
+    /// Handles a message from another node, or one this node sent itself.
+    fn handle_forwarded(&mut self, sender_id: NodeId, msg: Forwarded) {
+        use Forwarded::*;
+
+        match msg {
+            Request(tx_id, fwd_id, budget, request) => {
+                trace!("Handling remote request for {tx_id} on behalf of coordinator {sender_id}: {request:?}");
+                self.handle_remote_request(sender_id, tx_id, fwd_id, budget, *request, false)
+            },
+            Response(tx_id, fwd_id, resp) => {
+                trace!("Passing response to remote request for {tx_id} from shard {sender_id} back to client: {resp:?}");
+                self.pass_to_client(&tx_id, (fwd_id, sender_id, ShardReply::Response(resp)));
+            },
+            TwoPhaseCommitStatus(tx_id, fwd_id, commit_status) => {
+                trace!("Passing two-phase commit vote for {tx_id} from shard {sender_id} back to client: {commit_status:?}");
+                self.pass_to_client(&tx_id, (fwd_id, sender_id, ShardReply::Vote(commit_status)));
+            },
+            DoCommit(tx_id, ack) => {
+                trace!("Doing commit for {tx_id}...");
+                if self.witness {
+                    self.decisions.lock().unwrap().record_witnessed(tx_id, Decision::Commit);
+                }
+
+                let shard: AtomicShard = self.shard.clone();
+                let drain = self.drain.clone();
+                let reporter = self.reporter.clone();
+                let shard_id = self.node_id;
+                let ack = ack.and_then(|fwd_id| match self.get_server_send(sender_id) {
+                    Some(resp_handle) => Some((fwd_id, resp_handle)),
+                    None => {
+                        info!("Committing {tx_id} without sending {sender_id} the values it wrote: its link was lost");
+                        None
+                    }
+                });
+                // Acknowledgments go through the server task to be batched
+                let applied_handle = self.client_state_snd.clone();
+                crate::task::spawn(format_args!("commit values {tx_id}"), async move {
+                    // The values a transaction wrote are those its commit installs
+                    let written = match ack {
+                        Some(_) => shard.tentative_writes(&tx_id).await,
+                        None => Vec::new()
+                    };
+                    // A retried commit was reported when it was first applied
+                    let retried = shard.outcome(&tx_id) == Some(Decision::Commit);
+                    let resp = match shard.commit(&tx_id).await {
+                        Ok(result) => {
+                            if !retried {
+                                reporter.report(CommitReport::new(shard_id, tx_id, result));
+                            }
+                            // Only an applied commit is acknowledged, so the
+                            // coordinator retries one that failed
+                            if applied_handle.send(ClientState::Forward(ForwardTarget::Node(sender_id), CommitAck(tx_id))).is_err() {
+                                error!("Server task stopped ... dropping the acknowledgment of {tx_id}");
+                            }
+                            ClientResponse::CommitOkWithValues(written)
+                        },
+                        Err(e) => {
+                            error!("FATAL ERROR: Failed to commit {tx_id}: {e:?}");
+                            ClientResponse::Aborted
+                        }
+                    };
+                    drain.decide(&tx_id);
+
+                    if let Some((fwd_id, resp_handle)) = ack {
+                        if resp_handle.send(Response(tx_id, fwd_id, resp)).is_err() {
+                            error!("Server {sender_id} disconnected ... dropping the values {tx_id} wrote");
+                        }
+                    }
+                });
+            },
+            Acl(tx_id, fwd_id, change) => {
+                trace!("Applying {change:?} from {sender_id}");
+                if self.acls.lock().unwrap().apply(*change) {
+                    acl::persist(&self.acls);
+                }
+                self.send_to(sender_id, Response(tx_id, fwd_id, ClientResponse::Ok));
+            },
+            Unpause(tx_id, fwd_id) => {
+                self.drain.release(&tx_id);
+                self.send_to(sender_id, Response(tx_id, fwd_id, ClientResponse::Ok));
+            },
+            AclSync(changes) => {
+                let mut acls = self.acls.lock().unwrap();
+                let applied = changes.into_iter().filter(|change| acls.apply(change.clone())).count();
+                drop(acls);
+                if applied > 0 {
+                    info!("Caught up on {applied} access control list changes from {sender_id}");
+                    acl::persist(&self.acls);
+                }
+            },
+            CommitAck(tx_id) => {
+                if self.completion.lock().unwrap().acked(&tx_id, sender_id) {
+                    trace!("Commit of {tx_id} applied by every participant");
+                }
+            },
+            Sequence(client_tx, fwd_id, writes) => self.sequence(sender_id, client_tx, fwd_id, writes),
+            Replicate(tx) => {
+                let seq = tx.seq;
+                self.sequence_log.record(*tx);
+                self.send_to(sender_id, Replicated(seq));
+            },
+            Replicated(seq) if self.sequencer == self.node_id && self.sequence_backup == Some(sender_id) => {
+                for tx in self.sequence_log.acknowledge(seq) {
+                    self.dispatch(tx);
+                }
+            },
+            Replicated(seq) => trace!("Ignoring {sender_id} holding the sequence log up to {seq}: it is not this node's backup"),
+            Sequenced(tx) => match &self.executor {
+                Some(executor) => {
+                    if executor.sequenced.send(tx).is_err() {
+                        error!("Executor crashed ... exiting.");
+                        std::process::exit(1);
+                    }
+                },
+                None => error!("Ignoring {} sequenced by {sender_id}: the cluster is not deterministic", tx.id)
+            },
+            Verdict(tx_id, verdict) => match &self.executor {
+                Some(executor) => {
+                    if executor.verdicts.send((tx_id, sender_id, verdict)).is_err() {
+                        error!("Executor crashed ... exiting.");
+                        std::process::exit(1);
+                    }
+                },
+                None => error!("Ignoring verdict on {tx_id} from {sender_id}: the cluster is not deterministic")
+            },
+            Batch(batch) => {
+                trace!("Handling a batch of {} commit messages from {sender_id}", batch.len());
+                for msg in batch {
+                    self.handle_forwarded(sender_id, msg);
+                }
+            },
+            Relay(node_ids, msg) => {
+                let Request(tx_id, fwd_id, budget, request) = *msg else {
+                    error!("Ignoring {msg:?} relayed by {sender_id}: only requests are relayed");
+                    return;
+                };
+
+                trace!("Relaying request for {tx_id} from {sender_id} to {node_ids:?}: {request:?}");
+                let missing = self.relay(&node_ids, &Request(tx_id, fwd_id, budget, request.clone()));
+                let waiting = node_ids.len() - missing.len() + 1;
+                let replies = missing.into_iter().map(|node_id| (node_id, ShardReply::Unrelayed)).collect();
+                self.relays.start(tx_id, fwd_id, sender_id, waiting, replies);
+                self.handle_remote_request(sender_id, tx_id, fwd_id, budget, *request, true);
+            },
+            Relayed(tx_id, fwd_id, replies) => match self.relays.deliver(tx_id, fwd_id, replies) {
+                Delivery::NotRelayed(replies) => {
+                    trace!("Passing {} replies for {tx_id} relayed by {sender_id} back to client", replies.len());
+                    for (node_id, reply) in replies {
+                        self.pass_to_client(&tx_id, (fwd_id, node_id, reply));
+                    }
+                },
+                Delivery::Waiting => (),
+                Delivery::Complete(parent, replies) => {
+                    trace!("Relaying {} replies for {tx_id} back to {parent}", replies.len());
+                    self.send_to(parent, Relayed(tx_id, fwd_id, replies));
+                }
+            },
+            Gossip(gossip) => match &self.membership {
+                Some(membership) => {
+                    let out = membership.lock().unwrap().receive(sender_id, gossip, Instant::now());
+                    self.gossip(out);
+                },
+                None => trace!("Ignoring gossip from {sender_id}: this node does not gossip")
+            }
+        }
+    }
+
+    /// Runs the server's event loop. `select!` polls its branches in a random 
+    /// order on every iteration, so a steady stream of messages from other 
+    /// servers cannot starve accepting clients or handling client state (and 
+    /// vice versa). Every branch only does a bounded amount of work before 
+    /// returning to the loop since any waiting happens on spawned tasks.
+    pub async fn serve(&mut self) {
+        if let Some(interval) = self.verify_interval {
+            crate::task::spawn(format_args!("verification"), verification::run(self.shard.clone(), self.verification.clone(), interval));
+        }
+
+        if let Some((path, interval)) = self.snapshots.clone() {
+            crate::task::spawn(format_args!("snapshots"), snapshots::run(self.shard.clone(), self.node_id, path, interval));
+        }
+
+        if self.adaptive {
+            crate::task::spawn(format_args!("contention"), contention::run(self.shard.clone(), self.contention.clone()));
+        }
+
+        if self.execution_mode == ExecutionMode::Deterministic {
+            let (executor, handle) = deterministic::Executor::new(
+                self.node_id, self.shard.clone(), self.shards.clone(), self.client_state_snd.clone(), self.reporter.clone()
+            );
+            self.executor = Some(handle);
+            crate::task::spawn(format_args!("deterministic executor"), executor.run());
+        }
+
+        let mut sweep = self.sweep_interval.map(tokio::time::interval);
+        let mut commit_retry = self.commit_retry.map(tokio::time::interval);
+        let mut epoch = self.epochs.as_ref().map(|epochs| tokio::time::interval(epochs.length));
+        let mut metrics = self.metrics.as_ref().map(|dump| tokio::time::interval(dump.interval));
+        let mut gossip = self.membership.as_ref().map(|membership| tokio::time::interval(membership.lock().unwrap().interval));
+        loop {
+            select! {
+                client = async { self.listener.as_ref().unwrap().accept().await }, if self.listener.is_some() && self.slots.available() => match client {
+                    Ok((stream, addr)) => {
+                        if let Err(refusal) = self.admission.admit(addr.ip()) {
+                            info!("Refusing connection from {addr}: {refusal:?}");
+                            continue;
+                        }
+
+                        if let Err(e) = self.socket_options.apply(&stream) {
+                            error!("Failed to set socket options for {addr}: {e:?}");
+                        }
+
+                        let Some(slot) = self.slots.take() else {
+                            continue;
+                        };
+                        self.admission.open(addr.ip());
+                        self.greet(MessageStream::from_tcp_stream(stream), addr, slot);
+                    },
+                    Err(e) => error!("failed to accept client: {e:?}")
+                },
+                Some(stream) = self.embedded.recv(), if self.slots.available() => {
+                    let Some(slot) = self.slots.take() else {
+                        continue;
+                    };
+                    self.admission.open(EMBEDDED_PEER.ip());
+                    self.greet(stream, EMBEDDED_PEER, slot);
+                },
+                // A finished client may let the server accept again
+                _ = self.slots.freed() => {},
+                Some((addr, client)) = self.greeted.recv() => self.handle_greeted(addr, client),
+                Some((stream, node_id)) = self.joining.recv() => self.admit_peer(stream, node_id),
+                Some(state) = self.from_clients.recv() => self.handle_client_state(state),
+                Some(state) = self.from_servers.recv() => self.handle_server_state(state),
+                _ = async { sweep.as_mut().unwrap().tick().await }, if sweep.is_some() => self.sweep_expired(),
+                _ = async { commit_retry.as_mut().unwrap().tick().await }, if commit_retry.is_some() => self.retry_commits(),
+                _ = async { epoch.as_mut().unwrap().tick().await }, if epoch.is_some() => self.end_epoch(),
+                _ = async { metrics.as_mut().unwrap().tick().await }, if metrics.is_some() => self.dump_metrics(),
+                _ = async { gossip.as_mut().unwrap().tick().await }, if gossip.is_some() => self.gossip_round()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::{stream::MessageStream, admin::{Access, Decision, DrainStatus, MemberState, PauseStatus, ShardDigest, UnappliedCommit, Vote}, query::QuerySummary};
+    use tx_proto::{BalanceDiff, BalancePredicate, CommitVerbosity, IsolationLevel, SessionSettings};
+    use ClientRequest::*;
+    use std::collections::BTreeSet;
+    use tokio::{net::TcpStream, task::JoinSet, time::{timeout, Duration}};
+    use crate::testing::{local_config, run_transaction, run_transaction_over, start_cluster, start_cluster_with, start_transfer_in_transit};
+    use super::*;
+
+    const A: NodeId = NodeId(0);
+    const B: NodeId = NodeId(1);
+    const C: NodeId = NodeId(2);
+
+    fn deposits(account_id: &str, count: usize) -> Vec<ClientRequest> {
+        let mut requests = (0..count)
+            .map(|_| ClientRequest::WriteBalance(account_id.into(), BalanceDiff(1)))
+            .collect::<Vec<_>>();
+        requests.push(ClientRequest::Commit);
+        requests
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_serve_makes_progress_under_inter_server_load() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+        let port_a = config[&A].port;
+        let port_b = config[&B].port;
+
+        // Flood A with requests forwarded from B on behalf of B's clients
+        let mut flood = JoinSet::new();
+        for i in 0..32 {
+            flood.spawn(run_transaction(port_b, deposits(&format!("A.flood{i}"), 16)));
+        }
+
+        // While A is busy, it must still accept its own clients and forward 
+        // their requests on to B.
+        let mut clients = JoinSet::new();
+        for i in 0..16 {
+            clients.spawn(run_transaction(port_a, deposits(&format!("B.client{i}"), 4)));
+        }
+
+        let all_finished = timeout(Duration::from_secs(30), async move {
+            while let Some(responses) = clients.join_next().await {
+                assert!(matches!(responses.unwrap().last(), Some(ClientResponse::CommitOk)));
+            }
+
+            while let Some(responses) = flood.join_next().await {
+                assert!(matches!(responses.unwrap().last(), Some(ClientResponse::CommitOk)));
+            }
+        });
+
+        assert!(all_finished.await.is_ok());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_negative_balance_abort_names_account() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+
+        for account_id in ["A.bar", "B.bar"] {
+            let requests = vec![
+                ClientRequest::WriteBalance(account_id.into(), BalanceDiff(20)),
+                ClientRequest::WriteBalance(account_id.into(), BalanceDiff(-30)),
+                ClientRequest::Commit
+            ];
+
+            let responses = run_transaction(config[&A].port, requests).await;
+            assert!(matches!(
+                responses.last(), 
+                Some(ClientResponse::AbortedNegativeBalance(a)) if a == account_id
+            ));
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_decision_log_records_votes() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+        let port = config[&A].port;
+
+        let responses = run_transaction(port, deposits("B.foo", 1)).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)));
+
+        let request = vec![ClientRequest::Admin(AdminRequest::DecisionLog)];
+        let records = match run_transaction(port, request).await.pop() {
+            Some(ClientResponse::Admin(AdminResponse::DecisionLog(records))) => records,
+            resp => panic!("Expected a decision log, got {resp:?}")
+        };
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].decision, Decision::Commit);
+        let mut voters = records[0].votes.iter().map(|(node_id, _)| *node_id).collect::<Vec<_>>();
+        voters.sort();
+        assert_eq!(voters, vec![A, B]);
+        assert!(records[0].votes.iter().all(|(_, vote)| *vote == Vote::ReadyToCommit));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_requests_after_commit() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+
+        // Each request after the one that finished a transaction starts the
+        // next transaction on the same connection
+        let requests = vec![
+            WriteBalance("B.foo".into(), BalanceDiff(10)),
+            Commit,
+            ReadBalance("B.foo".into()),
+            WriteBalance("B.foo".into(), BalanceDiff(5)),
+            Abort,
+            ReadBalance("B.foo".into()),
+            Commit
+        ];
+
+        let responses = run_transaction(config[&A].port, requests).await;
+        assert!(matches!(
+            responses.as_slice(), 
+            [ClientResponse::Ok, ClientResponse::CommitOk, ClientResponse::Value(_, 10), ClientResponse::Ok, ClientResponse::Aborted, ClientResponse::Value(_, 10), ClientResponse::CommitOk]
+        ), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_requests_after_abort() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+
+        let requests = vec![
+            ReadBalance("B.missing".into()),
+            WriteBalance("B.missing".into(), BalanceDiff(1)),
+            Commit,
+            ReadBalance("B.missing".into())
+        ];
+
+        let responses = run_transaction(config[&A].port, requests).await;
+        assert!(matches!(
+            responses.as_slice(), 
+            [ClientResponse::AbortedNotFound, ClientResponse::Ok, ClientResponse::CommitOk, ClientResponse::Value(_, 1)]
+        ), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_transactions_on_one_connection_take_new_ids() {
+        let config = local_config(&["A"]);
+        start_cluster(&config).await;
+        let port = config[&A].port;
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut stream = MessageStream::from_tcp_stream(stream);
+        for request in [WriteBalance("A.x".into(), BalanceDiff(1)), Commit] {
+            stream.send(request).await.unwrap();
+            assert!(stream.recv::<ClientResponse>().await.unwrap().unwrap().is_ok());
+        }
+
+        // A transaction that began later commits in between, which the next
+        // transaction on the first connection would have to abort on if it
+        // kept the first one's id
+        let responses = run_transaction(port, vec![WriteBalance("A.x".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+        stream.send(ReadBalance("A.x".into())).await.unwrap();
+        assert!(matches!(stream.recv().await.unwrap().unwrap(), ClientResponse::Value(_, 2)));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_disconnect_aborts_active_transaction() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+        let port = config[&A].port;
+
+        // Leave a tentative write behind without committing or aborting
+        let responses = run_transaction(port, vec![WriteBalance("B.foo".into(), BalanceDiff(10))]).await;
+        assert!(matches!(responses[0], ClientResponse::Ok));
+
+        // A newer transaction would wait on that write forever unless the 
+        // disconnect aborted it
+        let responses = timeout(Duration::from_secs(5), run_transaction(port, deposits("B.foo", 1))).await;
+        assert!(matches!(responses.unwrap().last(), Some(ClientResponse::CommitOk)));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_transactions_aborted_when_lifetime_expires() {
+        let config = local_config(&["A", "B"]);
+        let lifetime = TransactionLifetime { default: Some(Duration::from_millis(200)), max: Some(Duration::from_secs(5)) };
+        let servers = [A, B].map(|id| tokio::spawn(Server::start(id, config.clone(), 5)));
+        for server in servers {
+            let mut server = server.await.unwrap().with_transaction_lifetime(lifetime);
+            tokio::spawn(async move { server.serve().await });
+        }
+        let port = config[&A].port;
+
+        // An interactive transaction that idles past the default is aborted 
+        // without waiting for its next request
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut idle = MessageStream::from_tcp_stream(stream);
+        idle.send(WriteBalance("B.x".into(), BalanceDiff(10))).await.unwrap();
+        assert!(matches!(idle.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let responses = timeout(Duration::from_secs(1), run_transaction(port, deposits("B.x", 1))).await.unwrap();
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+        idle.send(WriteBalance("A.x".into(), BalanceDiff(1))).await.unwrap();
+        assert!(matches!(idle.recv().await.unwrap().unwrap(), ClientResponse::AbortedLifetimeExpired));
+        idle.send(Commit).await.unwrap();
+        assert!(matches!(idle.recv().await.unwrap().unwrap(), ClientResponse::CommitOk));
+
+        // A batch job asks for longer and learns the most it may have
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut batch = MessageStream::from_tcp_stream(stream);
+        batch.send(ClientRequest::Lifetime(Duration::MAX)).await.unwrap();
+        assert!(matches!(batch.recv().await.unwrap().unwrap(), ClientResponse::LifetimeGranted(granted) if granted == Duration::from_secs(5)));
+        batch.send(WriteBalance("B.x".into(), BalanceDiff(10))).await.unwrap();
+        assert!(matches!(batch.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        batch.send(Commit).await.unwrap();
+        assert!(matches!(batch.recv().await.unwrap().unwrap(), ClientResponse::CommitOk));
+
+        let responses = run_transaction(port, vec![ReadBalance("B.x".into())]).await;
+        assert!(matches!(responses[0], ClientResponse::Value(_, 11)));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_reads_abandoned_at_deadline() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+        let port = config[&A].port;
+
+        // Leave tentative writes on both shards for newer reads to wait on
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut writer = MessageStream::from_tcp_stream(stream);
+        for account_id in ["A.x", "B.x"] {
+            writer.send(WriteBalance(account_id.into(), BalanceDiff(10))).await.unwrap();
+            assert!(matches!(writer.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+        }
+
+        // Both the coordinator's own shard and the shard it forwards to give
+        // up on the read rather than wait for the writer to finish
+        for account_id in ["A.x", "B.x"] {
+            let read = ReadBalance(account_id.into()).with_deadline(Duration::from_millis(100));
+            let responses = timeout(Duration::from_secs(5), run_transaction(port, vec![read])).await.unwrap();
+            assert!(matches!(responses[0], ClientResponse::AbortedDeadlineExceeded));
+        }
+
+        writer.send(Commit).await.unwrap();
+        assert!(matches!(writer.recv().await.unwrap().unwrap(), ClientResponse::CommitOk));
+        let responses = run_transaction(port, vec![ReadBalance("B.x".into()).with_deadline(Duration::from_secs(5))]).await;
+        assert!(matches!(responses[0], ClientResponse::Value(_, 10)));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_single_node_commit() {
+        let config = local_config(&["A"]);
+        start_cluster(&config).await;
+
+        let responses = run_transaction(config[&A].port, deposits("A.foo", 2)).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_account_stats_rank_busiest_accounts() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+        let port = config[&A].port;
+
+        let responses = run_transaction(port, deposits("A.hot", 3)).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)));
+        let responses = run_transaction(port, vec![ReadBalance("A.hot".into()), WriteBalance("A.cold".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)));
+
+        // Only the shard of the node asked is counted
+        let responses = run_transaction(config[&B].port, deposits("A.hot", 1)).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)));
+        let responses = run_transaction(config[&B].port, vec![Admin(AdminRequest::AccountStats(10))]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Admin(AdminResponse::AccountStats(accounts))] if accounts.is_empty()));
+
+        let responses = run_transaction(port, vec![Admin(AdminRequest::AccountStats(1))]).await;
+        let [ClientResponse::Admin(AdminResponse::AccountStats(accounts))] = responses.as_slice() else {
+            panic!("Unexpected account stats response: {responses:?}");
+        };
+        let counts: Vec<_> = accounts.iter().map(|a| (a.account_id.as_str(), a.reads, a.writes, a.aborts, a.commits)).collect();
+        // Each deposit reads the balance it adds to, once the account exists
+        assert_eq!(counts, vec![("A.hot", 4, 4, 0, 2)]);
+        assert!(accounts[0].last_committer.is_some());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_client_limit_leaves_forwarded_requests_alone() {
+        let config = local_config(&["A", "B"]);
+        start_cluster_with(&config, |server| server.with_max_clients(1)).await;
+
+        // A client of B takes its only slot for as long as it is connected,
+        // but transactions coordinated by A still reach B's shard
+        let stream = TcpStream::connect(("127.0.0.1", config[&B].port)).await.unwrap();
+        let mut holder = MessageStream::from_tcp_stream(stream);
+        holder.send(WriteBalance("B.y".into(), BalanceDiff(1))).await.unwrap();
+        assert!(matches!(holder.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+
+        let responses = run_transaction(config[&A].port, deposits("B.x", 1)).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+        holder.send(Commit).await.unwrap();
+        assert!(matches!(holder.recv().await.unwrap().unwrap(), ClientResponse::CommitOk));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_forwarded_requests_wait_for_a_slot() {
+        let config = local_config(&["A", "B"]);
+        start_cluster_with(&config, |server| server.with_max_remote_operations(1)).await;
+        let connect = || async {
+            MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", config[&A].port)).await.unwrap())
+        };
+
+        // The newer reader waits on the older writer, holding B's only slot
+        let mut writer = connect().await;
+        writer.send(WriteBalance("B.x".into(), BalanceDiff(1))).await.unwrap();
+        assert!(matches!(writer.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+        let mut reader = connect().await;
+        reader.send(ReadBalance("B.x".into())).await.unwrap();
+
+        // Another write waits its turn rather than being refused
+        let mut queued = connect().await;
+        let queued = tokio::spawn(async move {
+            queued.send(WriteBalance("B.y".into(), BalanceDiff(1))).await.unwrap();
+            queued.recv().await.unwrap().unwrap()
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!queued.is_finished());
+
+        writer.send(Commit).await.unwrap();
+        assert!(matches!(writer.recv().await.unwrap().unwrap(), ClientResponse::CommitOk));
+        assert!(matches!(reader.recv().await.unwrap().unwrap(), ClientResponse::Value(_, 1)));
+        assert!(matches!(timeout(Duration::from_secs(5), queued).await.unwrap().unwrap(), ClientResponse::Ok));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_memory_attributed_to_unfinished_transactions() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+        let port = config[&A].port;
+
+        let responses = run_transaction(port, deposits("A.done", 1)).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)));
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut writer = MessageStream::from_tcp_stream(stream);
+        for account_id in ["A.x", "A.y"] {
+            writer.send(WriteBalance(account_id.into(), BalanceDiff(10))).await.unwrap();
+            assert!(matches!(writer.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+        }
+
+        let responses = run_transaction(port, vec![Admin(AdminRequest::Memory(1))]).await;
+        let [ClientResponse::Admin(AdminResponse::Memory(report))] = responses.as_slice() else {
+            panic!("Unexpected memory response: {responses:?}");
+        };
+        // The writer and the client asking, and the depositor until it is reaped
+        assert!(report.sessions >= 2);
+        assert_eq!(report.accounts, 3);
+        assert_eq!(report.phases, 1);
+        assert!(report.queues.iter().any(|(queue, _)| queue == "to #1"));
+        let [writes] = report.transactions.as_slice() else {
+            panic!("Unexpected transactions: {:?}", report.transactions);
+        };
+        assert_eq!(writes.writes, 2);
+        // Only the largest account is listed, and it is one the writer holds
+        let [largest] = report.largest.as_slice() else {
+            panic!("Unexpected accounts: {:?}", report.largest);
+        };
+        assert_ne!(largest.account_id, "A.done");
+        assert_eq!(largest.versions, 2);
+        assert!(report.bytes > writes.bytes);
+        // The depositor's commit is logged, and its outcome remembered
+        assert!(report.commit_log > 0 && report.finished > 0 && report.decisions > 0);
+
+        writer.send(Abort).await.unwrap();
+        assert!(matches!(writer.recv().await.unwrap().unwrap(), ClientResponse::Aborted));
+        let responses = run_transaction(port, vec![Admin(AdminRequest::Memory(10))]).await;
+        assert!(matches!(
+            responses.as_slice(), 
+            [ClientResponse::Admin(AdminResponse::Memory(report))] if report.transactions.is_empty() && report.phases == 0 && report.accounts == 1
+        ), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_client_abort_reasons_logged() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+        let port = config[&A].port;
+
+        let responses = run_transaction(port, vec![
+            WriteBalance("A.x".into(), BalanceDiff(5)), WriteBalance("B.y".into(), BalanceDiff(5)), AbortWithReason("user cancelled".into())
+        ]).await;
+        assert!(matches!(responses[..], [ClientResponse::Ok, ClientResponse::Ok, ClientResponse::Aborted]), "{responses:?}");
+        // Aborts without a reason are not logged
+        let responses = run_transaction(port, vec![WriteBalance("A.x".into(), BalanceDiff(5)), Abort]).await;
+        assert!(matches!(responses[..], [ClientResponse::Ok, ClientResponse::Aborted]), "{responses:?}");
+
+        let responses = run_transaction(port, vec![Admin(AdminRequest::DecisionLog)]).await;
+        let [ClientResponse::Admin(AdminResponse::DecisionLog(records))] = responses.as_slice() else {
+            panic!("Unexpected decision log response: {responses:?}");
+        };
+        let [record] = records.as_slice() else {
+            panic!("Unexpected decisions: {records:?}");
+        };
+        assert_eq!((record.decision, record.reason.as_deref()), (Decision::Abort, Some("user cancelled")));
+        assert_eq!(record.participants, vec![A, B]);
+        assert!(ClientResponse::Admin(AdminResponse::DecisionLog(records.clone())).format().ends_with("reason=\"user cancelled\""));
+
+        // The abort still reached every shard
+        let responses = run_transaction(config[&B].port, vec![ReadBalance("B.y".into())]).await;
+        assert!(matches!(responses[..], [ClientResponse::AbortedNotFound]), "{responses:?}");
+    }
+
+    /// Passes on the aborts clients give reasons for.
+    struct AbortChannel(UnboundedSender<AbortReport>);
+
+    impl CommitReporter for AbortChannel {
+        fn report(&self, _: CommitReport) {}
+
+        fn report_abort(&self, abort: AbortReport) {
+            let _ = self.0.send(abort);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_abort_reasons_capped_reported_and_queryable() {
+        let (aborts_snd, mut aborts) = unbounded_channel();
+        let mut server = Server::embedded(&["A"]).await.remove(0).with_commit_reporter(AbortChannel(aborts_snd));
+        let connector = server.connector();
+        tokio::spawn(async move { server.serve().await });
+
+        let long_reason = "é".repeat(tx_proto::MAX_ABORT_REASON_BYTES);
+        let responses = run_transaction_over(connector.connect().unwrap(), vec![
+            WriteBalance("A.x".into(), BalanceDiff(5)), AbortWithReason(long_reason.clone())
+        ]).await;
+        assert!(matches!(responses[..], [ClientResponse::Ok, ClientResponse::Aborted]), "{responses:?}");
+
+        let abort = aborts.recv().await.unwrap();
+        assert_eq!(abort.coordinator, NodeId(0));
+        assert_eq!(abort.reason, long_reason[..tx_proto::MAX_ABORT_REASON_BYTES]);
+
+        // The decision can be looked up by the transaction's id alone
+        let responses = run_transaction_over(connector.connect().unwrap(), vec![Admin(AdminRequest::Decision(abort.tx_id))]).await;
+        let [ClientResponse::Admin(AdminResponse::DecisionLog(records))] = responses.as_slice() else {
+            panic!("Unexpected decision response: {responses:?}");
+        };
+        let [record] = records.as_slice() else {
+            panic!("Unexpected decisions: {records:?}");
+        };
+        assert_eq!((record.tx_id, record.reason.as_ref()), (abort.tx_id, Some(&abort.reason)));
+
+        let unknown = TransactionId::at(1, NodeId(0));
+        let responses = run_transaction_over(connector.connect().unwrap(), vec![Admin(AdminRequest::Decision(unknown))]).await;
+        assert!(matches!(&responses[..], [ClientResponse::Admin(AdminResponse::DecisionLog(records))] if records.is_empty()), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_shards_answer_outcomes_until_they_forget() {
+        let config = local_config(&["A", "B"]);
+        let servers = [A, B].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for server in servers {
+            let mut server = server.await.unwrap().with_finished_retention(16, Duration::from_millis(500));
+            tokio::spawn(async move { server.serve().await });
+        }
+        let (port_a, port_b) = (config[&A].port, config[&B].port);
+
+        let responses = run_transaction(port_a, deposits("B.x", 1)).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)));
+        let responses = run_transaction(port_a, vec![WriteBalance("B.x".into(), BalanceDiff(-5)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::AbortedNegativeBalance(_))));
+
+        let responses = run_transaction(port_a, vec![Admin(AdminRequest::DecisionLog)]).await;
+        let [ClientResponse::Admin(AdminResponse::DecisionLog(records))] = responses.as_slice() else {
+            panic!("Unexpected decision log response: {responses:?}");
+        };
+        let outcomes = || records.iter().map(|r| Admin(AdminRequest::Outcome(r.tx_id))).collect::<Vec<_>>();
+        let expected: Vec<_> = records.iter().map(|r| Some(r.decision)).collect();
+        for _ in 0..2 {
+            let answered: Vec<_> = run_transaction(port_b, outcomes())
+                .await
+                .into_iter()
+                .map(|resp| match resp {
+                    ClientResponse::Admin(AdminResponse::Outcome(decision)) => decision,
+                    resp => panic!("Unexpected outcome response: {resp:?}")
+                })
+                .collect();
+            assert_eq!(answered, expected);
+        }
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        let responses = run_transaction(port_b, outcomes()).await;
+        assert!(responses.iter().all(|resp| matches!(resp, ClientResponse::Admin(AdminResponse::Outcome(None)))), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_status_lists_peers_once_ready() {
+        let config = local_config(&["A", "B", "C"]);
+        start_cluster(&config).await;
+
+        let port = config.get(&B).unwrap().port;
+        let responses = run_transaction(port, vec![Admin(AdminRequest::Status)]).await;
+        match responses.as_slice() {
+            [ClientResponse::Admin(AdminResponse::Status(status))] => {
+                assert_eq!(status.node_id, B);
+                assert_eq!(status.peers, vec![A, C]);
+            },
+            other => panic!("Unexpected status response: {other:?}")
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_status_counts_traffic_per_link() {
+        let config = local_config(&["A", "B", "C"]);
+        start_cluster(&config).await;
+
+        let port = config.get(&A).unwrap().port;
+        let mut requests = deposits("B.x", 1);
+        requests.push(Admin(AdminRequest::Status));
+        let responses = run_transaction(port, requests).await;
+
+        let status = match responses.last() {
+            Some(ClientResponse::Admin(AdminResponse::Status(status))) => status,
+            other => panic!("Unexpected status response: {other:?}")
+        };
+        assert_eq!(status.peers, vec![B, C]);
+
+        // The deposit and the two-phase commit went to B, but nothing to C 
+        // beyond the handshake.
+        let (to_b, to_c) = (status.links[0], status.links[1]);
+        assert!(to_b.messages_sent > to_c.messages_sent);
+        assert!(to_b.messages_received > to_c.messages_received);
+        assert!(to_b.bytes_sent > to_b.messages_sent * 4);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_drain_finishes_started_transactions_only() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+        let (port_a, port_b) = (config[&A].port, config[&B].port);
+
+        async fn drain(port: u16) -> DrainStatus {
+            match run_transaction(port, vec![Admin(AdminRequest::Drain)]).await.as_slice() {
+                [ClientResponse::Admin(AdminResponse::Drain(status))] => *status,
+                other => panic!("Unexpected drain response: {other:?}")
+            }
+        }
+
+        let stream = TcpStream::connect(("127.0.0.1", port_a)).await.unwrap();
+        let mut started = MessageStream::from_tcp_stream(stream);
+        started.send(ClientRequest::WriteBalance("A.x".into(), BalanceDiff(1))).await.unwrap();
+        assert!(matches!(started.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+
+        // B has nothing under way, but A must wait for the started transaction
+        assert!(drain(port_b).await.safe_to_stop);
+        assert_eq!(drain(port_a).await, DrainStatus { in_flight: 1, prepared: 0, safe_to_stop: false });
+
+        let responses = run_transaction(port_a, deposits("A.y", 1)).await;
+        assert!(matches!(responses[0], ClientResponse::AbortedDraining));
+
+        // The started transaction may still write, but not commit
+        started.send(ClientRequest::WriteBalance("B.x".into(), BalanceDiff(1))).await.unwrap();
+        assert!(matches!(started.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+        started.send(ClientRequest::Commit).await.unwrap();
+        assert!(matches!(started.recv().await.unwrap().unwrap(), ClientResponse::AbortedDraining));
+
+        assert!(drain(port_a).await.safe_to_stop);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_partial_start_serves_reachable_shards() {
+        let config = local_config(&["A", "B", "C"]);
+        let port = config.get(&A).unwrap().port;
+        let servers = [A, B]
+            .map(|node_id| tokio::spawn(Server::start_with_min_peers(node_id, config.clone(), 5, 1)));
+        for server in servers {
+            let mut server = server.await.unwrap();
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let reachable = vec![WriteBalance("A.x".into(), BalanceDiff(5)), WriteBalance("B.y".into(), BalanceDiff(5)), Commit];
+        let responses = run_transaction(port, reachable).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        let unreachable = vec![WriteBalance("C.z".into(), BalanceDiff(5)), Commit];
+        let responses = run_transaction(port, unreachable.clone()).await;
+        assert!(matches!(responses.first(), Some(ClientResponse::AbortedUnavailable(C))), "{responses:?}");
+
+        let mut server = Server::start(C, config.clone(), 5).await;
+        tokio::spawn(async move { server.serve().await });
+
+        // The servers admit C as soon as they read its handshake, which may 
+        // happen after C itself is done connecting
+        let joined = async {
+            while !matches!(
+                run_transaction(port, vec![Admin(AdminRequest::Status)]).await.as_slice(),
+                [ClientResponse::Admin(AdminResponse::Status(status))] if status.peers == vec![B, C]
+            ) {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), joined).await.unwrap();
+
+        let responses = run_transaction(port, unreachable).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_forward_retried_until_shard_joins() {
+        let config = local_config(&["A", "B"]);
+        let port = config[&A].port;
+        let mut server = Server::start_with_min_peers(A, config.clone(), 5, 0)
+            .await
+            .with_forward_retry(ForwardRetry { max_retries: 100, backoff: Duration::from_millis(50) });
+        tokio::spawn(async move { server.serve().await });
+
+        let transaction = tokio::spawn(run_transaction(port, deposits("B.x", 1)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut server = Server::start(B, config.clone(), 5).await;
+        tokio::spawn(async move { server.serve().await });
+
+        let responses = timeout(Duration::from_secs(5), transaction).await.unwrap().unwrap();
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_reply_after_client_handler_exited_is_dropped() {
+        let mut server = Server::start_with_min_peers(A, local_config(&["A", "B"]), 5, 0).await;
+        let (mut client_end, server_end) = MessageStream::in_memory();
+        let slot = server.slots.take().unwrap();
+        server.accept_client(server_end, EMBEDDED_PEER, ClusterInfo, slot);
+        let tx_id = *server.clients.keys().next().unwrap();
+        client_end.recv::<ClientResponse>().await.unwrap().unwrap();
+        drop(client_end);
+
+        // The handler exited, but the server task has not reaped it yet when
+        // a late reply from B arrives
+        let finished = loop {
+            match server.from_clients.recv().await.unwrap() {
+                finished @ ClientState::Finished(_) => break finished,
+                state => server.handle_client_state(state)
+            }
+        };
+        server.handle_forwarded(B, Forwarded::Response(tx_id, 0, ClientResponse::Ok));
+        server.handle_forwarded(B, Forwarded::TwoPhaseCommitStatus(tx_id, 1, CommitStatus::ReadyToCommit));
+        assert!(server.clients.is_empty());
+
+        server.handle_client_state(finished);
+        assert!(server.clients.is_empty());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_commit_scoped_to_touched_shards() {
+        let config = local_config(&["A", "B", "C"]);
+        start_cluster(&config).await;
+
+        let requests = vec![WriteBalance("A.x".into(), BalanceDiff(1)), WriteBalance("B.y".into(), BalanceDiff(1)), Admin(AdminRequest::Routes), Commit];
+        let responses = run_transaction(config[&A].port, requests).await;
+        assert!(matches!(responses[3], ClientResponse::CommitOk), "{responses:?}");
+        match &responses[2] {
+            ClientResponse::Admin(AdminResponse::Routes(routes)) => assert_eq!(routes, &vec![("A.x".into(), A), ("B.y".into(), B)]),
+            resp => panic!("Unexpected response to a routes request: {resp:?}")
+        }
+
+        // C holds nothing for the transaction, so it neither votes nor learns
+        // the decision
+        let responses = run_transaction(config[&A].port, vec![Admin(AdminRequest::DecisionLog)]).await;
+        let tx_id = match responses.as_slice() {
+            [ClientResponse::Admin(AdminResponse::DecisionLog(records))] => {
+                assert_eq!(records[0].participants, vec![A, B]);
+                records[0].tx_id
+            },
+            resp => panic!("Unexpected response to a decision log request: {resp:?}")
+        };
+        for (node_id, outcome) in [(B, Some(Decision::Commit)), (C, None)] {
+            let responses = run_transaction(config[&node_id].port, vec![Admin(AdminRequest::Outcome(tx_id))]).await;
+            assert!(matches!(responses[0], ClientResponse::Admin(AdminResponse::Outcome(o)) if o == outcome), "{responses:?}");
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_metrics_dumped_periodically() {
+        let config = local_config(&["A", "B"]);
+        let path = std::env::temp_dir().join(format!("tx-server-metrics-{}.jsonl", std::process::id()));
+        let servers = [A, B].map(|id| tokio::spawn(Server::start(id, config.clone(), 5)));
+        for (id, server) in [A, B].into_iter().zip(servers) {
+            let mut server = server.await.unwrap();
+            if id == A {
+                server = server.with_metrics_dump(&path, Duration::from_millis(50), MetricsFormat::JsonLines).unwrap();
+            }
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let responses = run_transaction(config[&A].port, deposits("B.x", 1)).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let dumped = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let snapshots: Vec<serde_json::Value> = dumped.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert!(snapshots.len() >= 2, "{dumped}");
+        let last = snapshots.last().unwrap();
+        assert_eq!(last["committed"], 1);
+        assert_eq!(last["links"][0]["peer"], 1);
+        assert!(last["links"][0]["messages_sent"].as_u64().unwrap() > 0);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_restarted_node_restores_its_snapshot() {
+        let path = std::env::temp_dir().join(format!("tx-server-restart-{}.snapshot", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let config = local_config(&["A"]);
+        let mut server = Server::start(A, config.clone(), 5).await
+            .with_snapshots(&path, Duration::from_millis(10)).await
+            .unwrap();
+        let serving = tokio::spawn(async move { server.serve().await });
+        let responses = run_transaction(config[&A].port, deposits("A.x", 3)).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+        while std::fs::read(&path).map_or(true, |contents| !String::from_utf8_lossy(&contents).contains("A.x")) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        serving.abort();
+
+        // The node comes back on another port, with only the snapshot
+        let config = local_config(&["A"]);
+        let mut server = Server::start(A, config.clone(), 5).await
+            .with_snapshots(&path, Duration::from_secs(60)).await
+            .unwrap();
+        tokio::spawn(async move { server.serve().await });
+        let responses = run_transaction(config[&A].port, vec![ReadBalance("A.x".into()), WriteBalance("A.x".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(
+            responses.as_slice(), 
+            [ClientResponse::Value(_, 3), ClientResponse::Ok, ClientResponse::CommitOk]
+        ), "{responses:?}");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_witness_records_decisions_without_data() {
+        let mut config = local_config(&["A", "B", "C"]);
+        config.get_mut(&C).unwrap().shards.clear();
+        start_cluster(&config).await;
+
+        let responses = run_transaction(config[&A].port, vec![WriteBalance("A.x".into(), BalanceDiff(1)), WriteBalance("B.y".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+        let responses = run_transaction(config[&A].port, vec![WriteBalance("A.x".into(), BalanceDiff(1)), Abort]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::Aborted]), "{responses:?}");
+
+        let responses = run_transaction(config[&B].port, vec![WriteBalance("C.z".into(), BalanceDiff(1))]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedNotFound]), "{responses:?}");
+
+        let witnessed = async {
+            loop {
+                if let [ClientResponse::Admin(AdminResponse::DecisionLog(records))] = run_transaction(config[&C].port, vec![Admin(AdminRequest::DecisionLog)]).await.as_slice() {
+                    let decisions: Vec<_> = records.iter().map(|record| record.decision).collect();
+                    if decisions.len() == 3 {
+                        return decisions;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let decisions = timeout(Duration::from_secs(5), witnessed).await.unwrap();
+        // The transaction that touched no data on the witness is aborted 
+        // everywhere, so the witness learns that decision too
+        assert_eq!(decisions, vec![Decision::Commit, Decision::Abort, Decision::Abort]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_cluster_info_describes_roles_and_joins() {
+        let mut config = local_config(&["A", "B", "C"]);
+        config.get_mut(&C).unwrap().shards.clear();
+        let servers = [A, B]
+            .map(|node_id| tokio::spawn(Server::start_with_min_peers(node_id, config.clone(), 5, 1)));
+        for server in servers {
+            let mut server = server.await.unwrap();
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let responses = run_transaction(config[&B].port, vec![ClusterInfo]).await;
+        let [ClientResponse::ClusterInfo(info)] = responses.as_slice() else { panic!("{responses:?}") };
+        assert_eq!(info.node_id, B);
+        assert_eq!(info.epoch, ShardMap::new(&config).epoch());
+        let nodes: Vec<_> = info.nodes.iter().map(|node| (node.node_id, node.role, node.joined, node.port)).collect();
+        assert_eq!(nodes, vec![
+            (A, NodeRole::Shard, true, config[&A].port),
+            (B, NodeRole::Shard, true, config[&B].port),
+            (C, NodeRole::Witness, false, config[&C].port)
+        ]);
+        assert_eq!(info.owner("A.x").map(|node| node.node_id), Some(A));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_node_hosts_several_logical_shards() {
+        let mut config = local_config(&["A", "B"]);
+        config.get_mut(&A).unwrap().shards.push("X".into());
+        start_cluster(&config).await;
+
+        let requests = vec![WriteBalance("X.foo".into(), BalanceDiff(7)), Commit];
+        let responses = run_transaction(config[&B].port, requests).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        for port in [config[&A].port, config[&B].port] {
+            let responses = run_transaction(port, vec![ReadBalance("X.foo".into())]).await;
+            assert!(matches!(responses.as_slice(), [ClientResponse::Value(_, 7)]), "{responses:?}");
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_swap_across_shards() {
+        let config = local_config(&["A", "B"]);
+        let port = config[&A].port;
+        start_cluster(&config).await;
+
+        let setup = vec![WriteBalance("A.x".into(), BalanceDiff(5)), WriteBalance("B.y".into(), BalanceDiff(3)), Commit];
+        run_transaction(port, setup).await;
+
+        let responses = run_transaction(port, vec![Swap("A.x".into(), "B.y".into()), Commit]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+
+        let responses = run_transaction(port, vec![ReadBalance("A.x".into()), ReadBalance("B.y".into())]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Value(_, 3), ClientResponse::Value(_, 5)]), "{responses:?}");
+
+        let responses = run_transaction(port, vec![Swap("A.x".into(), "B.missing".into())]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedNotFound]), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_swap_pipelines_remote_reads() {
+        let config = local_config(&["A", "B", "C"]);
+        let port = config[&A].port;
+        start_cluster(&config).await;
+
+        let setup = vec![
+            WriteBalance("B.x".into(), BalanceDiff(1)), WriteBalance("B.y".into(), BalanceDiff(2)),
+            WriteBalance("C.z".into(), BalanceDiff(3)), Commit
+        ];
+        run_transaction(port, setup).await;
+
+        // Both reads of each swap are forwarded before either reply arrives,
+        // whether they go to one shard or two
+        let swaps = vec![Swap("B.x".into(), "B.y".into()), Swap("B.y".into(), "C.z".into()), Commit];
+        let responses = run_transaction(port, swaps).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+
+        let reads = vec![ReadBalance("B.x".into()), ReadBalance("B.y".into()), ReadBalance("C.z".into())];
+        let responses = run_transaction(port, reads).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Value(_, 2), ClientResponse::Value(_, 3), ClientResponse::Value(_, 1)]), "{responses:?}");
+
+        // The reply to the read that succeeded is not mistaken for the abort
+        let responses = run_transaction(port, vec![Swap("B.missing".into(), "C.z".into()), Abort]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedNotFound, ClientResponse::Aborted]), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_transaction_over_quota_is_aborted() {
+        let config = local_config(&["A"]);
+        let mut server = Server::start(A, config.clone(), 5)
+            .await
+            .with_transaction_quota(TransactionQuota { max_ops: 2, ..Default::default() });
+        tokio::spawn(async move { server.serve().await });
+
+        // The commit after the abort starts a transaction with a quota of its
+        // own
+        let responses = run_transaction(config[&A].port, deposits("A.foo", 3)).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::Ok, ClientResponse::AbortedQuotaExceeded, ClientResponse::CommitOk]), "{responses:?}");
+
+        let responses = run_transaction(config[&A].port, vec![ReadBalance("A.foo".into())]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedNotFound]), "{responses:?}");
+    }
+
+    /// Refuses writes to locked accounts and counts the responses it sees.
+    struct LockLayer(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl RequestLayer for LockLayer {
+        fn before(&mut self, _cx: &RequestContext, request: &ClientRequest) -> Option<ClientResponse> {
+            match request {
+                WriteBalance(account_id, _) if account_id.ends_with(".locked") => Some(ClientResponse::Aborted),
+                _ => None
+            }
+        }
+
+        fn after(&mut self, _cx: &RequestContext, _response: &ClientResponse) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_request_layer_can_refuse_requests() {
+        let config = local_config(&["A"]);
+        let responses_seen = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let seen = responses_seen.clone();
+        let mut server = Server::start(A, config.clone(), 5)
+            .await
+            .with_request_layer(move || LockLayer(seen.clone()));
+        tokio::spawn(async move { server.serve().await });
+
+        let requests = vec![WriteBalance("A.x".into(), BalanceDiff(1)), WriteBalance("A.locked".into(), BalanceDiff(1)), ClientRequest::Commit];
+        let responses = run_transaction(config[&A].port, requests).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::Aborted, ClientResponse::CommitOk]), "{responses:?}");
+
+        let responses = run_transaction(config[&A].port, deposits("A.x", 1)).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+        assert_eq!(responses_seen.load(std::sync::atomic::Ordering::SeqCst), 5);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_connections_over_per_ip_limit_are_closed() {
+        let config = local_config(&["A"]);
+        let port = config[&A].port;
+        let mut server = Server::start(A, config.clone(), 5)
+            .await
+            .with_admission_policy(AdmissionPolicy { max_per_ip: 1, ..Default::default() });
+        tokio::spawn(async move { server.serve().await });
+
+        let mut first = MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", port)).await.unwrap());
+        first.send(WriteBalance("A.x".into(), BalanceDiff(1))).await.unwrap();
+        assert!(matches!(first.recv().await, Some(Ok(ClientResponse::Ok))));
+
+        let mut second = MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", port)).await.unwrap());
+        let _ = second.send(ReadBalance("A.x".into())).await;
+        assert!(matches!(timeout(Duration::from_secs(5), second.recv::<ClientResponse>()).await, Ok(None | Some(Err(_)))));
+
+        // Once the first client leaves, its address may connect again
+        drop(first);
+        let reconnected = async {
+            loop {
+                let mut stream = MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", port)).await.unwrap());
+                let _ = stream.send(Commit).await;
+                if let Some(Ok(response)) = stream.recv::<ClientResponse>().await {
+                    break response;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let response = timeout(Duration::from_secs(5), reconnected).await.unwrap();
+        assert!(matches!(response, ClientResponse::CommitOk), "{response:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_transaction_id_allocated_at_first_request() {
+        let config = local_config(&["A"]);
+        let port = config[&A].port;
+        start_cluster(&config).await;
+
+        // A client that connects before another commits but reads after it 
+        // sees the commit, since its id is newer than the other transaction's
+        let idle = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let responses = run_transaction(port, deposits("A.x", 1)).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+
+        let mut idle = MessageStream::from_tcp_stream(idle);
+        idle.send(ReadBalance("A.x".into())).await.unwrap();
+        let response: ClientResponse = idle.recv().await.unwrap().unwrap();
+        assert!(matches!(response, ClientResponse::Value(_, 1)), "{response:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_timestamp_taken_per_mode() {
+        for mode in [TimestampMode::FirstRequest, TimestampMode::FirstOperation] {
+            let config = local_config(&["A"]);
+            let port = config[&A].port;
+            start_cluster_with(&config, |server| server.with_timestamp_mode(mode)).await;
+
+            // The client asks for the node's status before another transaction
+            // commits a write to the account it then reads
+            let mut early = MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", port)).await.unwrap());
+            early.send(Admin(AdminRequest::Status)).await.unwrap();
+            let _: ClientResponse = early.recv().await.unwrap().unwrap();
+            run_transaction(port, deposits("A.x", 1)).await;
+
+            early.send(ReadBalance("A.x".into())).await.unwrap();
+            let response: ClientResponse = early.recv().await.unwrap().unwrap();
+            match mode {
+                TimestampMode::FirstRequest => assert!(response.is_err(), "{response:?}"),
+                TimestampMode::FirstOperation => assert!(matches!(response, ClientResponse::Value(_, 1)), "{response:?}")
+            }
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_commits_reported_on_every_shard() {
+        let config = local_config(&["A", "B"]);
+        let (reports_snd, mut reports) = unbounded_channel();
+        let servers = [A, B].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for server in servers {
+            let mut server = server.await.unwrap().with_commit_reporter(ChannelReporter(reports_snd.clone()));
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let requests = vec![WriteBalance("A.x".into(), BalanceDiff(2)), WriteBalance("B.y".into(), BalanceDiff(3)), Commit];
+        run_transaction(config[&A].port, requests).await;
+
+        let mut received = [reports.recv().await.unwrap(), reports.recv().await.unwrap()];
+        received.sort_by_key(|report| report.shard_id);
+        assert_eq!(received[0].tx_id, received[1].tx_id);
+        assert_eq!(received[0].balances, vec![("A.x".to_string(), 2)]);
+        assert_eq!(received[1].to_line().as_deref(), Some("B.y = 3 "));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_verifier_finds_no_violations() {
+        let config = local_config(&["A"]);
+        let port = config[&A].port;
+        let mut server = Server::start(A, config, 5)
+            .await
+            .with_verify_interval(Some(Duration::from_millis(10)));
+        tokio::spawn(async move { server.serve().await });
+
+        run_transaction(port, deposits("A.x", 2)).await;
+        run_transaction(port, vec![ReadBalance("A.x".into()), WriteBalance("A.y".into(), BalanceDiff(1)), Commit]).await;
+        run_transaction(port, vec![ReadBalance("A.x".into()), WriteBalance("A.x".into(), BalanceDiff(1)), Abort]).await;
+
+        let verified = async {
+            loop {
+                let responses = run_transaction(port, vec![Admin(AdminRequest::Verification)]).await;
+                match responses.as_slice() {
+                    [ClientResponse::Admin(AdminResponse::Verification(status))] if status.passes >= 2 => break status.clone(),
+                    _ => tokio::time::sleep(Duration::from_millis(10)).await
+                }
+            }
+        };
+        let status = timeout(Duration::from_secs(5), verified).await.unwrap();
+        assert_eq!(status.violations_found, 0, "{:?}", status.recent);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_commits_since_sequence_number() {
+        let config = local_config(&["A", "B"]);
+        let port = config[&A].port;
+        start_cluster(&config).await;
+
+        run_transaction(port, deposits("A.x", 1)).await;
+        run_transaction(port, vec![ReadBalance("A.x".into()), Commit]).await;
+        run_transaction(port, deposits("A.y", 2)).await;
+
+        let commits = |seq| async move {
+            match run_transaction(port, vec![Admin(AdminRequest::CommitsSince(seq))]).await.as_slice() {
+                [ClientResponse::Admin(AdminResponse::Commits(since))] => since.clone(),
+                other => panic!("Unexpected commits response: {other:?}")
+            }
+        };
+
+        // The read-only transaction changed nothing, so it is not logged
+        let since = commits(0).await;
+        assert_eq!(since.first_seq, 1);
+        let logged = since.commits.iter().map(|c| (c.seq, c.writes.clone())).collect::<Vec<_>>();
+        assert_eq!(logged, vec![(1, vec![("A.x".to_string(), 1)]), (2, vec![("A.y".to_string(), 2)])]);
+        assert_eq!(commits(1).await.commits.len(), 1);
+    }
+
+    /// Runs a query through the node on `port`, returning the batches of rows
+    /// it streamed and its summary.
+    async fn run_query(port: u16, query: &str) -> (Vec<Vec<(AccountId, Amount)>>, QuerySummary) {
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        query_over(&mut MessageStream::from_tcp_stream(stream), query).await
+    }
+
+    /// Runs a query in the transaction of a client already connected.
+    async fn query_over(stream: &mut MessageStream, query: &str) -> (Vec<Vec<(AccountId, Amount)>>, QuerySummary) {
+        stream.send(Admin(AdminRequest::Query(Query::parse(query).unwrap()))).await.unwrap();
+
+        let mut batches = Vec::new();
+        loop {
+            match stream.recv().await.unwrap().unwrap() {
+                ClientResponse::Admin(AdminResponse::QueryRows(rows)) => batches.push(rows),
+                ClientResponse::Admin(AdminResponse::QueryDone(summary)) => return (batches, *summary),
+                other => panic!("Unexpected query response: {other:?}")
+            }
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_query_gathers_committed_balances_from_every_shard() {
+        let config = local_config(&["A", "B", "C"]);
+        let servers = [A, B]
+            .map(|node_id| tokio::spawn(Server::start_with_min_peers(node_id, config.clone(), 5, 1)));
+        for server in servers {
+            let mut server = server.await.unwrap();
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let port = config[&B].port;
+        let setup = vec![
+            WriteBalance("A.x".into(), BalanceDiff(10)),
+            WriteBalance("B.y".into(), BalanceDiff(3)),
+            WriteBalance("B.z".into(), BalanceDiff(20)),
+            Commit
+        ];
+        run_transaction(port, setup).await;
+
+        // The query does not see the write of a newer transaction. A client
+        // is only given its transaction once it sends a request
+        let query = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut query = MessageStream::from_tcp_stream(query);
+        query.send(Label("query".into())).await.unwrap();
+        assert!(matches!(query.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+        let tentative = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut tentative = MessageStream::from_tcp_stream(tentative);
+        tentative.send(WriteBalance("A.w".into(), BalanceDiff(50))).await.unwrap();
+        assert!(matches!(tentative.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+
+        // Each shard's rows are streamed once every part is gathered, 
+        // starting with the coordinator's own
+        let (batches, summary) = query_over(&mut query, "SELECT key, value WHERE value > 5").await;
+        assert_eq!(batches, vec![vec![("B.z".to_string(), 20)], vec![("A.x".to_string(), 10)]]);
+        assert_eq!((summary.count, summary.sum), (2, 30));
+        assert_eq!(summary.snapshots, vec![(A, 1), (B, 1)]);
+        assert_eq!(summary.missing, vec![C]);
+
+        let (batches, summary) = query_over(&mut query, "SELECT SUM(value) WHERE key LIKE 'B.%'").await;
+        assert!(batches.is_empty());
+        assert_eq!((summary.count, summary.sum), (2, 23));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_query_reads_a_consistent_cut() {
+        // A's shard holds the withdrawal while B's still holds the deposit 
+        // as prepared, so the query waits for it rather than miss the money
+        // in transit
+        let config = start_transfer_in_transit().await;
+        let (_, summary) = run_query(config[&A].port, "SELECT SUM(value)").await;
+        assert_eq!(summary.sum, 200);
+        assert_eq!(summary.snapshots, vec![(A, 2), (B, 2)]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_expired_accounts_are_swept() {
+        let config = local_config(&["A", "B"]);
+        let servers = [A, B].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for server in servers {
+            let mut server = server.await.unwrap().with_sweep_interval(Some(Duration::from_millis(50)));
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let port = config[&A].port;
+        let create = vec![WriteBalanceWithTtl("B.x".into(), BalanceDiff(10), Duration::from_millis(200)), Commit];
+        let responses = run_transaction(port, create).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+        let responses = run_transaction(port, vec![ReadBalance("B.x".into())]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Value(_, 10)]), "{responses:?}");
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let responses = run_transaction(port, vec![ReadBalance("B.x".into())]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedNotFound]), "{responses:?}");
+
+        // The sweep is logged on the account's shard like a commit
+        let responses = run_transaction(config[&B].port, vec![Admin(AdminRequest::CommitsSince(0))]).await;
+        let [ClientResponse::Admin(AdminResponse::Commits(since))] = responses.as_slice() else {
+            panic!("Unexpected commits response: {responses:?}");
+        };
+        let removed: Vec<_> = since.commits.iter().map(|c| (c.writes.len(), c.removed.clone())).collect();
+        assert_eq!(removed, vec![(1, vec![]), (0, vec!["B.x".to_string()])]);
+
+        // A deposit creates the account afresh, with no TTL
+        let responses = run_transaction(port, deposits("B.x", 1)).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let responses = run_transaction(port, vec![ReadBalance("B.x".into())]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Value(_, 1)]), "{responses:?}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_label_quota_limits_concurrent_transactions() {
+        let config = local_config(&["A"]);
+        let port = config[&A].port;
+        let mut server = Server::start(A, config.clone(), 5).await.with_label_quota("batch", 1);
+        tokio::spawn(async move { server.serve().await });
+
+        let running = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut running = MessageStream::from_tcp_stream(running);
+        running.send(Label("batch".into())).await.unwrap();
+        assert!(matches!(running.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+
+        let responses = run_transaction(port, vec![Label("batch".into()), WriteBalance("A.x".into(), BalanceDiff(1))]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedLabelQuota(_), ClientResponse::Ok]), "{responses:?}");
+        let responses = run_transaction(port, vec![Label("web".into()), WriteBalance("A.x".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        running.send(Commit).await.unwrap();
+        assert!(matches!(running.recv().await.unwrap().unwrap(), ClientResponse::CommitOk));
+        let responses = run_transaction(port, vec![Label("batch".into()), Abort]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::Aborted]), "{responses:?}");
+
+        let responses = run_transaction(port, vec![Admin(AdminRequest::Labels)]).await;
+        let [ClientResponse::Admin(AdminResponse::Labels(labels))] = responses.as_slice() else {
+            panic!("Unexpected labels response: {responses:?}");
+        };
+        let counts: Vec<_> = labels.iter().map(|l| (l.label.as_str(), l.active, l.committed, l.aborted, l.refused)).collect();
+        assert_eq!(counts, vec![("batch", 0, 1, 1, 1), ("web", 0, 1, 0, 0)]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_deterministic_cluster_runs_transactions_in_one_order() {
+        let config = local_config(&["A", "B", "C"]);
+        let servers = [A, B, C].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for server in servers {
+            let mut server = server.await.unwrap().with_execution_mode(ExecutionMode::Deterministic);
+            tokio::spawn(async move { server.serve().await });
+        }
+        let ports = [A, B, C].map(|node_id| config[&node_id].port);
+
+        let responses = run_transaction(ports[1], vec![
+            WriteBalance("A.x".into(), BalanceDiff(10)), WriteBalance("B.y".into(), BalanceDiff(5)), Commit
+        ]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+
+        // Twice as many transfers as A.x can fund, from every coordinator at
+        // once: none conflict, and exactly the ones sequenced first commit
+        let mut transfers = JoinSet::new();
+        for i in 0..20 {
+            let port = ports[i % ports.len()];
+            let requests = vec![WriteBalance("A.x".into(), BalanceDiff(-1)), WriteBalance("B.y".into(), BalanceDiff(1)), Commit];
+            transfers.spawn(run_transaction(port, requests));
+        }
+        let mut outcomes = Vec::new();
+        while let Some(responses) = transfers.join_next().await {
+            outcomes.push(responses.unwrap().pop().unwrap());
+        }
+        assert_eq!(outcomes.iter().filter(|resp| matches!(resp, ClientResponse::CommitOk)).count(), 10);
+        assert!(outcomes.iter().all(|resp| matches!(resp, ClientResponse::CommitOk | ClientResponse::AbortedNegativeBalance(_))), "{outcomes:?}");
+
+        let responses = run_transaction(ports[2], vec![ReadBalance("A.x".into())]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedNotDeterministic]), "{responses:?}");
+
+        let (rows, _) = run_query(ports[0], "SELECT key, value").await;
+        assert_eq!(rows.concat(), vec![("A.x".into(), 0), ("B.y".into(), 15)]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_sequencer_fails_over_to_its_backup() {
+        let config = local_config(&["A", "B", "C"]);
+        let servers = [A, B, C].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        let mut tasks = Vec::new();
+        for server in servers {
+            let mut server = server.await.unwrap()
+                .with_execution_mode(ExecutionMode::Deterministic)
+                .with_gossip(Some(Duration::from_millis(10)));
+            tasks.push(tokio::spawn(async move { server.serve().await }));
+        }
+        let ports = [B, C].map(|node_id| config[&node_id].port);
+
+        let responses = run_transaction(ports[0], vec![WriteBalance("B.y".into(), BalanceDiff(10)), Commit]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+
+        // Stopping the sequencer while transfers are in flight: B takes over
+        // with the transactions A left with it, and the coordinators send 
+        // the rest again, so each transfer is run exactly once
+        let mut transfers = JoinSet::new();
+        for i in 0..20 {
+            let requests = vec![WriteBalance("B.y".into(), BalanceDiff(-1)), WriteBalance("C.z".into(), BalanceDiff(1)), Commit];
+            transfers.spawn(run_transaction(ports[i % ports.len()], requests));
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        tasks.remove(0).abort();
+
+        let mut outcomes = Vec::new();
+        while let Some(responses) = transfers.join_next().await {
+            outcomes.push(responses.unwrap().pop().unwrap());
+        }
+        assert_eq!(outcomes.iter().filter(|resp| matches!(resp, ClientResponse::CommitOk)).count(), 10);
+        assert!(outcomes.iter().all(|resp| matches!(resp, ClientResponse::CommitOk | ClientResponse::AbortedNegativeBalance(_))), "{outcomes:?}");
+
+        let responses = run_transaction(ports[1], vec![WriteBalance("B.y".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+        let (rows, _) = run_query(ports[1], "SELECT key, value").await;
+        let mut rows = rows.concat();
+        rows.sort();
+        assert_eq!(rows, vec![("B.y".into(), 1), ("C.z".into(), 10)]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_commit_epochs_batch_two_phase_commits() {
+        let config = local_config(&["A", "B", "C"]);
+        let epochs = [Some(Duration::from_millis(5)), Some(Duration::from_millis(20)), None];
+        let servers = [A, B, C].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for (server, epoch) in servers.into_iter().zip(epochs) {
+            let mut server = server.await.unwrap().with_commit_epoch(epoch);
+            tokio::spawn(async move { server.serve().await });
+        }
+        let ports = [A, B, C].map(|node_id| config[&node_id].port);
+
+        // Nodes batch on epochs of their own, or not at all, and still agree
+        let mut deposits = JoinSet::new();
+        for i in 0..12 {
+            let requests = ["A", "B", "C"].map(|shard| WriteBalance(format!("{shard}.x{i}"), BalanceDiff(1)));
+            deposits.spawn(run_transaction(ports[i % ports.len()], [requests.to_vec(), vec![Commit]].concat()));
+        }
+        while let Some(responses) = deposits.join_next().await {
+            assert!(matches!(responses.unwrap().last(), Some(ClientResponse::CommitOk)));
+        }
+
+        let responses = run_transaction(ports[0], vec![
+            WriteBalance("B.x0".into(), BalanceDiff(5)), WriteBalance("C.x0".into(), BalanceDiff(-5)), Commit
+        ]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::AbortedNegativeBalance(_))), "{responses:?}");
+
+        let (_, summary) = run_query(ports[1], "SELECT key, value").await;
+        assert_eq!((summary.count, summary.sum), (36, 36));
+    }
+
+    #[tokio::test]
+    async fn test_prepares_relayed_along_spanning_tree() {
+        let names = ["A", "B", "C", "D", "E"];
+        let config = local_config(&names);
+        let tree = TreeBroadcast { threshold: 2, fanout: 2 };
+        let servers = (0..names.len()).map(|i| tokio::spawn(Server::start(NodeId(i as u32), config.clone(), 5)));
+        for server in servers.collect::<Vec<_>>() {
+            let mut server = server.await.unwrap().with_tree_broadcast(Some(tree));
+            tokio::spawn(async move { server.serve().await });
+        }
+        let port = config[&A].port;
+
+        let requests = names.map(|shard| WriteBalance(format!("{shard}.x"), BalanceDiff(1)));
+        let responses = run_transaction(port, [requests.to_vec(), vec![Commit]].concat()).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        // A vote that cannot commit comes back up the tree, and every shard 
+        // below the coordinator aborts
+        let responses = run_transaction(port, vec![
+            WriteBalance("B.x".into(), BalanceDiff(1)), WriteBalance("D.x".into(), BalanceDiff(1)), 
+            WriteBalance("E.x".into(), BalanceDiff(-2)), Commit
+        ]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::AbortedNegativeBalance(_))), "{responses:?}");
+
+        let request = vec![ClientRequest::Admin(AdminRequest::DecisionLog)];
+        let Some(ClientResponse::Admin(AdminResponse::DecisionLog(records))) = run_transaction(port, request).await.pop() else {
+            panic!("Expected a decision log");
+        };
+        let voters = |i: usize| records[i].votes.iter().map(|(node_id, _)| node_id.0).collect::<BTreeSet<_>>();
+        assert_eq!((records[0].decision, voters(0)), (Decision::Commit, BTreeSet::from([0, 1, 2, 3, 4])));
+        assert_eq!((records[1].decision, voters(1)), (Decision::Abort, BTreeSet::from([0, 1, 3, 4])));
+
+        let (_, summary) = run_query(port, "SELECT key, value").await;
+        assert_eq!((summary.count, summary.sum), (5, 5));
+    }
+
+    #[tokio::test]
+    async fn test_gossip_learns_every_member() {
+        let config = local_config(&["A", "B", "C"]);
+        let servers = [A, B, C].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for server in servers {
+            let mut server = server.await.unwrap().with_gossip(Some(Duration::from_millis(10)));
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        for node_id in [A, B, C] {
+            let request = vec![Admin(AdminRequest::Members)];
+            let Some(ClientResponse::Admin(AdminResponse::Members(members))) = run_transaction(config[&node_id].port, request).await.pop() else {
+                panic!("Expected the members known to {node_id}");
+            };
+            let states: Vec<_> = members.iter().map(|m| (m.node_id, m.state, m.incarnation)).collect();
+            assert_eq!(states, [A, B, C].map(|id| (id, MemberState::Alive, 0)), "as seen by {node_id}");
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_gossiping_node_survives_losing_a_peer() {
+        let config = local_config(&["A", "B", "C"]);
+        let servers = [A, B, C].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        let mut tasks = Vec::new();
+        for server in servers {
+            let mut server = server.await.unwrap().with_gossip(Some(Duration::from_millis(10)));
+            tasks.push(tokio::spawn(async move { server.serve().await }));
+        }
+
+        // Stopping C closes its links, which A and B see as C disconnecting
+        tasks.pop().unwrap().abort();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let port = config[&A].port;
+        let request = vec![Admin(AdminRequest::Members)];
+        let Some(ClientResponse::Admin(AdminResponse::Members(members))) = run_transaction(port, request).await.pop() else {
+            panic!("Expected the members known to A");
+        };
+        let lost = members.iter().find(|m| m.node_id == C).unwrap();
+        assert!(matches!(lost.state, MemberState::Suspect | MemberState::Dead), "{members:?}");
+
+        let responses = run_transaction(port, vec![WriteBalance("C.z".into(), BalanceDiff(1))]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedUnavailable(node_id)] if *node_id == C), "{responses:?}");
+        let responses = run_transaction(port, vec![WriteBalance("A.x".into(), BalanceDiff(1)), WriteBalance("B.y".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+    }
+
+    /// Write skew: two transactions each check that the combined balance of
+    /// two accounts covers a withdrawal, then withdraw from a different one 
+    /// of the two. Each is fine alone, but committing both would overdraw 
+    /// the pair, which snapshot isolation allows since they write different
+    /// accounts. Serializable concurrency control must abort one of them in
+    /// every concurrency mode, however the accounts are spread over shards.
+    #[test_log::test(tokio::test)]
+    async fn test_write_skew_across_shards_rejected() {
+        async fn step(stream: &mut MessageStream, request: ClientRequest) -> ClientResponse {
+            stream.send(request).await.unwrap();
+            stream.recv().await.unwrap().unwrap()
+        }
+
+        for mode in [ConcurrencyMode::TimestampOrdering, ConcurrencyMode::WoundWait] {
+            let config = local_config(&["A", "B", "C"]);
+            let servers = [A, B, C].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+            for server in servers {
+                let mut server = server.await.unwrap().with_concurrency_mode(mode);
+                tokio::spawn(async move { server.serve().await });
+            }
+
+            // The pair must keep a combined balance of at least 100
+            let port = config[&C].port;
+            let requests = vec![WriteBalance("A.x".into(), BalanceDiff(100)), WriteBalance("B.y".into(), BalanceDiff(100)), Commit];
+            assert!(matches!(run_transaction(port, requests).await.last(), Some(ClientResponse::CommitOk)));
+
+            let connect = || async { MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", port)).await.unwrap()) };
+            let (mut first, mut second) = (connect().await, connect().await);
+            let mut outcomes = [Vec::new(), Vec::new()];
+            for stream in [&mut first, &mut second] {
+                let reads = [step(stream, ReadBalance("A.x".into())).await, step(stream, ReadBalance("B.y".into())).await];
+                assert!(matches!(reads, [ClientResponse::Value(_, 100), ClientResponse::Value(_, 100)]), "{mode:?}: {reads:?}");
+            }
+            outcomes[0].push(step(&mut first, WriteBalance("A.x".into(), BalanceDiff(-100))).await);
+            outcomes[1].push(step(&mut second, WriteBalance("B.y".into(), BalanceDiff(-100))).await);
+            outcomes[0].push(step(&mut first, Commit).await);
+            outcomes[1].push(step(&mut second, Commit).await);
+
+            let committed = outcomes.iter().filter(|responses| responses.iter().all(ClientResponse::is_ok)).count();
+            assert_eq!(committed, 1, "{mode:?}: {outcomes:?}");
+
+            let requests = vec![ReadBalance("A.x".into()), ReadBalance("B.y".into()), Commit];
+            let [ClientResponse::Value(_, x), ClientResponse::Value(_, y), ClientResponse::CommitOk] = run_transaction(port, requests).await[..] else {
+                panic!("{mode:?}: Expected to read both balances");
+            };
+            assert_eq!(x + y, 100, "{mode:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_increments_all_commit() {
+        async fn step(stream: &mut MessageStream, request: ClientRequest) -> ClientResponse {
+            stream.send(request).await.unwrap();
+            stream.recv().await.unwrap().unwrap()
+        }
+
+        let config = local_config(&["A", "B", "C"]);
+        start_cluster(&config).await;
+        let connect = |port| async move { MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", port)).await.unwrap()) };
+
+        // One increment is local to its coordinator and the other forwarded,
+        // and the older transaction commits last
+        let (mut older, mut newer) = (connect(config[&A].port).await, connect(config[&B].port).await);
+        assert!(matches!(step(&mut older, Increment("A.hits".into(), BalanceDiff(2))).await, ClientResponse::Ok));
+        assert!(matches!(step(&mut newer, Increment("A.hits".into(), BalanceDiff(3))).await, ClientResponse::Ok));
+        assert!(matches!(step(&mut newer, Commit).await, ClientResponse::CommitOk));
+        assert!(matches!(step(&mut older, Commit).await, ClientResponse::CommitOk));
+
+        let responses = run_transaction(config[&C].port, vec![ReadBalance("A.hits".into()), Increment("A.hits".into(), BalanceDiff(-6)), Commit]).await;
+        assert!(matches!(responses[..], [ClientResponse::Value(_, 5), ClientResponse::Ok, ClientResponse::AbortedNegativeBalance(_)]), "{responses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_stale_reads_skip_pending_writes() {
+        let config = local_config(&["A", "B", "C"]);
+        start_cluster(&config).await;
+        let responses = run_transaction(config[&A].port, vec![WriteBalance("A.x".into(), BalanceDiff(5)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        // A write left pending would hold up a plain read, but not a stale 
+        // one, whether it is local to the coordinator or forwarded
+        let mut pending = MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", config[&A].port)).await.unwrap());
+        pending.send(WriteBalance("A.x".into(), BalanceDiff(10))).await.unwrap();
+        assert!(matches!(pending.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+        for port in [config[&A].port, config[&B].port] {
+            let responses = run_transaction(port, vec![ReadBalanceStale("A.x".into(), Duration::from_secs(60)), Commit]).await;
+            assert!(matches!(responses[..], [ClientResponse::Value(_, 5), ClientResponse::CommitOk]), "{responses:?}");
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let responses = run_transaction(config[&B].port, vec![ReadBalanceStale("A.x".into(), Duration::from_millis(1))]).await;
+        assert!(matches!(&responses[..], [ClientResponse::AbortedTooStale(account_id)] if account_id == "A.x"), "{responses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_session_settings_apply_to_later_requests() {
+        let config = local_config(&["A", "B", "C"]);
+        start_cluster(&config).await;
+        let responses = run_transaction(config[&A].port, vec![WriteBalance("A.x".into(), BalanceDiff(5)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        let mut pending = MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", config[&A].port)).await.unwrap());
+        pending.send(WriteBalance("A.x".into(), BalanceDiff(10))).await.unwrap();
+        assert!(matches!(pending.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+
+        // Plain reads of the pending write give up at the default deadline, 
+        // unless the session reads stale balances
+        let deadline = SessionSettings { default_deadline: Some(Duration::from_millis(100)), ..Default::default() };
+        let responses = run_transaction(config[&B].port, vec![Hello(deadline.clone()), ReadBalance("A.x".into())]).await;
+        assert!(matches!(responses[..], [ClientResponse::SessionSettings(_), ClientResponse::AbortedDeadlineExceeded]), "{responses:?}");
+
+        let stale = SessionSettings {
+            isolation: IsolationLevel::BoundedStaleness(Duration::from_secs(60)),
+            verbosity: CommitVerbosity::Values,
+            ..deadline
+        };
+        let responses = run_transaction(config[&B].port, vec![
+            Hello(stale.clone()), ReadBalance("A.x".into()), WriteBalance("A.y".into(), BalanceDiff(3)), Commit
+        ]).await;
+        assert!(matches!(&responses[..], [
+            ClientResponse::SessionSettings(adopted), ClientResponse::Value(_, 5), ClientResponse::Ok, ClientResponse::CommitOkWithValues(_)
+        ] if *adopted == stale), "{responses:?}");
+
+        // Settings cannot change under a transaction that has operated, and
+        // a refusal leaves the transaction running
+        let responses = run_transaction(config[&C].port, vec![WriteBalance("A.z".into(), BalanceDiff(1)), Hello(stale), Commit]).await;
+        assert!(matches!(responses[..], [ClientResponse::Ok, ClientResponse::SettingsRefused(_), ClientResponse::CommitOk]), "{responses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_embedded_cluster_runs_in_memory() {
+        let (commits_snd, mut commits) = unbounded_channel();
+        let servers = Server::embedded(&["A", "B"]).await;
+        let connectors: Vec<_> = servers.iter().map(Server::connector).collect();
+        for server in servers {
+            let mut server = server.with_commit_reporter(ChannelReporter(commits_snd.clone()));
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        // A coordinates a transaction across both shards, forwarding to B
+        let transfer = vec![WriteBalance("A.x".into(), BalanceDiff(5)), WriteBalance("B.y".into(), BalanceDiff(3)), Commit];
+        let responses = run_transaction_over(connectors[0].connect().unwrap(), transfer).await;
+        assert!(matches!(responses[..], [ClientResponse::Ok, ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+        assert!(commits.recv().await.is_some());
+
+        let responses = run_transaction_over(connectors[0].connect().unwrap(), vec![Admin(AdminRequest::Status)]).await;
+        let [ClientResponse::Admin(AdminResponse::Status(status))] = responses.as_slice() else {
+            panic!("Unexpected status response: {responses:?}");
+        };
+        assert_eq!(status.peers, vec![B]);
+
+        let responses = run_transaction_over(connectors[1].connect().unwrap(), vec![Swap("A.x".into(), "B.y".into()), Commit]).await;
+        assert!(matches!(responses[..], [ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+        let responses = run_transaction_over(connectors[0].connect().unwrap(), vec![ReadBalance("A.x".into()), ReadBalance("B.y".into())]).await;
+        assert!(matches!(responses[..], [ClientResponse::Value(_, 3), ClientResponse::Value(_, 5)]), "{responses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_probes_for_missing_accounts_do_not_abort() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+
+        let responses = run_transaction(config[&A].port, vec![
+            ReadBalanceIfExists("A.x".into()), ReadBalanceIfExists("B.y".into()), ReadBalanceIfExists("Z.z".into()),
+            WriteBalance("A.x".into(), BalanceDiff(5)), WriteBalance("B.y".into(), BalanceDiff(3)), Commit
+        ]).await;
+        assert!(matches!(responses[..], [
+            ClientResponse::NotFound(_), ClientResponse::NotFound(_), ClientResponse::NotFound(_), 
+            ClientResponse::Ok, ClientResponse::Ok, ClientResponse::CommitOk
+        ]), "{responses:?}");
+
+        let responses = run_transaction(config[&B].port, vec![ReadBalanceIfExists("A.x".into()), ReadBalanceIfExists("B.y".into()), Commit]).await;
+        assert!(matches!(responses[..], [ClientResponse::Value(_, 5), ClientResponse::Value(_, 3), ClientResponse::CommitOk]), "{responses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_assertions_checked_when_made_and_at_commit() {
+        let config = local_config(&["A", "B"]);
+        start_cluster(&config).await;
+        let port = config[&A].port;
+        let responses = run_transaction(port, vec![WriteBalance("A.x".into(), BalanceDiff(100)), WriteBalance("B.y".into(), BalanceDiff(50)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)));
+
+        // The assertion still holds with the transaction's own withdrawal
+        let responses = run_transaction(port, vec![
+            AssertBalance("B.y".into(), BalancePredicate::AtLeast(10)), WriteBalance("B.y".into(), BalanceDiff(-30)), Commit
+        ]).await;
+        assert!(matches!(responses[..], [ClientResponse::Ok, ClientResponse::Ok, ClientResponse::CommitOk]), "{responses:?}");
+
+        // But no longer does once it withdraws too much
+        let responses = run_transaction(port, vec![
+            AssertBalance("A.x".into(), BalancePredicate::Exactly(100)), AssertBalance("B.y".into(), BalancePredicate::AtLeast(10)), 
+            WriteBalance("B.y".into(), BalanceDiff(-15)), Commit
+        ]).await;
+        assert!(matches!(
+            &responses[..], 
+            [ClientResponse::Ok, ClientResponse::Ok, ClientResponse::Ok, ClientResponse::AbortedAssertionFailed(account_id, BalancePredicate::AtLeast(10))] if account_id == "B.y"
+        ), "{responses:?}");
+
+        // An assertion that fails when made aborts the transaction at once
+        let responses = run_transaction(port, vec![AssertBalance("A.x".into(), BalancePredicate::MoreThan(100)), Commit]).await;
+        assert!(matches!(
+            &responses[..], 
+            [ClientResponse::AbortedAssertionFailed(..), ClientResponse::CommitOk]
+        ), "{responses:?}");
+        let responses = run_transaction(port, vec![AssertBalance("Z.z".into(), BalancePredicate::AtLeast(0))]).await;
+        assert!(matches!(responses[..], [ClientResponse::AbortedNotFound]), "{responses:?}");
+
+        let responses = run_transaction(config[&B].port, vec![ReadBalance("A.x".into()), ReadBalance("B.y".into()), Commit]).await;
+        assert!(matches!(responses[..], [ClientResponse::Value(_, 100), ClientResponse::Value(_, 20), ClientResponse::CommitOk]), "{responses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_cluster_pauses_everywhere_or_nowhere() {
+        async fn pause(port: u16, request: AdminRequest) -> PauseStatus {
+            match run_transaction(port, vec![Admin(request)]).await.as_slice() {
+                [ClientResponse::Admin(AdminResponse::Pause(status))] => status.clone(),
+                responses => panic!("Expected the pause status: {responses:?}")
+            }
+        }
+
+        let config = local_config(&["A", "B", "C"]);
+        start_cluster(&config).await;
+        let connect = |port| async move { MessageStream::from_tcp_stream(TcpStream::connect(("127.0.0.1", port)).await.unwrap()) };
+
+        // A transaction left open on B keeps the cluster from pausing, so 
+        // every node resumes
+        let mut open = connect(config[&B].port).await;
+        open.send(WriteBalance("B.x".into(), BalanceDiff(5))).await.unwrap();
+        assert!(matches!(open.recv().await.unwrap().unwrap(), ClientResponse::Ok));
+        let status = pause(config[&A].port, AdminRequest::Pause(Duration::from_millis(50))).await;
+        assert_eq!(status, PauseStatus { paused: false, busy: vec![B] });
+        let responses = run_transaction(config[&C].port, vec![WriteBalance("A.y".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        // Once it commits while the cluster waits, the cluster pauses
+        let pausing = tokio::spawn(pause(config[&A].port, AdminRequest::Pause(Duration::from_secs(5))));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        open.send(Commit).await.unwrap();
+        assert!(matches!(open.recv().await.unwrap().unwrap(), ClientResponse::CommitOk));
+        assert_eq!(pausing.await.unwrap(), PauseStatus { paused: true, busy: vec![] });
+
+        // Paused nodes refuse new transactions but answer admin requests
+        let responses = run_transaction(config[&C].port, vec![WriteBalance("A.y".into(), BalanceDiff(1))]).await;
+        assert!(matches!(responses[..], [ClientResponse::AbortedDraining]), "{responses:?}");
+        let responses = run_transaction(config[&B].port, vec![Admin(AdminRequest::Snapshot)]).await;
+        assert!(matches!(&responses[..], [ClientResponse::Admin(AdminResponse::Snapshot(snapshot))] if snapshot.accounts.len() == 1), "{responses:?}");
+
+        assert_eq!(pause(config[&C].port, AdminRequest::Resume).await, PauseStatus::default());
+        let responses = run_transaction(config[&A].port, vec![WriteBalance("B.x".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_failed_pause_leaves_another_in_place_until_it_lapses() {
+        let config = local_config(&["A", "B", "C"]);
+        let servers = [A, B, C].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for server in servers {
+            let mut server = server.await.unwrap().with_pause_lease(Duration::from_millis(500));
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let pause = |port| run_transaction(port, vec![Admin(AdminRequest::Pause(Duration::from_secs(1)))]);
+        let responses = pause(config[&A].port).await;
+        assert!(matches!(&responses[..], [ClientResponse::Admin(AdminResponse::Pause(status))] if status.paused), "{responses:?}");
+
+        // A second pause finds every node paused already and fails, without 
+        // lifting the first
+        let responses = pause(config[&B].port).await;
+        let [ClientResponse::Admin(AdminResponse::Pause(status))] = &responses[..] else { panic!("Expected the pause status: {responses:?}") };
+        assert_eq!(*status, PauseStatus { paused: false, busy: vec![A, B, C] });
+        let responses = run_transaction(config[&C].port, vec![WriteBalance("A.y".into(), BalanceDiff(1))]).await;
+        assert!(matches!(responses[..], [ClientResponse::AbortedDraining]), "{responses:?}");
+
+        // Nobody resumes the cluster, so the first pause lapses
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        let responses = run_transaction(config[&C].port, vec![WriteBalance("A.y".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_verbose_commit_returns_written_values() {
+        let config = local_config(&["A", "B", "C"]);
+        start_cluster(&config).await;
+        let port = config[&A].port;
+        let responses = run_transaction(port, vec![WriteBalance("C.z".into(), BalanceDiff(9)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        // Only the accounts written on each shard are returned, not those read
+        let responses = run_transaction(port, vec![
+            Verbosity(CommitVerbosity::Values), WriteBalance("B.y".into(), BalanceDiff(3)), WriteBalance("A.x".into(), BalanceDiff(5)),
+            WriteBalance("A.x".into(), BalanceDiff(2)), ReadBalance("C.z".into()), Commit
+        ]).await;
+        let Some(ClientResponse::CommitOkWithValues(values)) = responses.last() else {
+            panic!("Expected the written values: {responses:?}");
+        };
+        assert_eq!(values, &vec![("A.x".to_string(), 7), ("B.y".to_string(), 3)]);
+
+        let responses = run_transaction(port, vec![Verbosity(CommitVerbosity::Values), ReadBalance("A.x".into()), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOkWithValues(values)) if values.is_empty()), "{responses:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_commits_tracked_until_applied_everywhere() {
+        async fn unapplied(port: u16) -> Vec<UnappliedCommit> {
+            let Some(ClientResponse::Admin(AdminResponse::Status(status))) = run_transaction(port, vec![Admin(AdminRequest::Status)]).await.pop() else {
+                panic!("Expected the node's status");
+            };
+            status.unapplied
+        }
+
+        // C holds its acknowledgments until the end of a long epoch
+        let config = local_config(&["A", "B", "C"]);
+        let epochs = [None, None, Some(Duration::from_secs(10))];
+        let servers = [A, B, C].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for (server, epoch) in servers.into_iter().zip(epochs) {
+            let mut server = server.await.unwrap().with_commit_epoch(epoch);
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let port = config[&A].port;
+        let requests = vec![WriteBalance("B.y".into(), BalanceDiff(1)), WriteBalance("C.z".into(), BalanceDiff(1)), Commit];
+        let responses = run_transaction(port, requests).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let pending = unapplied(port).await;
+        assert_eq!(pending.iter().map(|commit| commit.waiting.clone()).collect::<Vec<_>>(), vec![vec![C]]);
+
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert!(unapplied(port).await.is_empty());
+    }
+
+    /// Passes on the commits shards report.
+    struct CommitChannel(UnboundedSender<CommitReport>);
+
+    impl CommitReporter for CommitChannel {
+        fn report(&self, commit: CommitReport) {
+            let _ = self.0.send(commit);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retried_commits_reported_once() {
+        // B holds its acknowledgments past A's retry interval, so A sends it
+        // the commit again
+        let (commits_snd, mut commits) = unbounded_channel();
+        let config = local_config(&["A", "B"]);
+        let servers = [A, B].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for (node_id, server) in [A, B].into_iter().zip(servers) {
+            let mut server = server.await.unwrap();
+            server = match node_id {
+                A => server.with_commit_retry(Some(Duration::from_millis(50))),
+                _ => server.with_commit_epoch(Some(Duration::from_millis(300))).with_commit_reporter(CommitChannel(commits_snd.clone()))
+            };
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let responses = run_transaction(config[&A].port, deposits("B.y", 1)).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        let reported = std::iter::from_fn(|| commits.try_recv().ok()).collect::<Vec<_>>();
+        assert_eq!(reported.iter().map(|commit| (commit.shard_id, commit.balances.clone())).collect::<Vec<_>>(), vec![(B, vec![("B.y".to_string(), 1)])]);
+    }
+
+    #[tokio::test]
+    async fn test_operations_on_degraded_peers_time_boxed() {
+        // Every send from A takes longer than its policy allows, so each of
+        // its links is degraded as soon as it is used
+        let config = local_config(&["A", "B", "C"]);
+        let servers = [A, B, C].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for (node_id, server) in [A, B, C].into_iter().zip(servers) {
+            let mut server = server.await.unwrap();
+            if node_id == A {
+                server = server
+                    .with_health_policy(HealthPolicy { max_latency: Duration::ZERO, ..Default::default() })
+                    .with_degraded_time_box(Some(Duration::ZERO));
+            }
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let port = config[&A].port;
+        run_transaction(port, vec![WriteBalance("B.y".into(), BalanceDiff(1))]).await;
+        let Some(ClientResponse::Admin(AdminResponse::Status(status))) = run_transaction(port, vec![Admin(AdminRequest::Status)]).await.pop() else {
+            panic!("Expected the node's status");
+        };
+        let health = status.health[status.peers.iter().position(|peer| *peer == B).unwrap()];
+        assert!(health.degraded && health.score < 100, "{health:?}");
+
+        // Operations on B give up at once, while those on A are not limited
+        let responses = run_transaction(port, vec![WriteBalance("A.x".into(), BalanceDiff(1)), WriteBalance("B.y".into(), BalanceDiff(1))]).await;
+        assert!(matches!(responses[..], [ClientResponse::Ok, ClientResponse::AbortedDeadlineExceeded]), "{responses:?}");
+        let responses = run_transaction(port, vec![ReadBalance("B.y".into())]).await;
+        assert!(matches!(responses[..], [ClientResponse::AbortedDeadlineExceeded]), "{responses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_digests_match_across_identical_runs() {
+        async fn digests(config: &Config) -> Vec<ShardDigest> {
+            let Some(ClientResponse::Admin(AdminResponse::Digests(digests))) = run_transaction(config[&B].port, vec![Admin(AdminRequest::Digest)]).await.pop() else {
+                panic!("Expected the digests of every shard");
+            };
+            digests
+        }
+
+        let runs = [local_config(&["A", "B", "C"]), local_config(&["A", "B", "C"])];
+        for config in &runs {
+            start_cluster(config).await;
+            for account_id in ["A.x", "B.y", "C.z", "A.w"] {
+                run_transaction(config[&A].port, deposits(account_id, 2)).await;
+            }
+        }
+
+        let [first, second] = [digests(&runs[0]).await, digests(&runs[1]).await];
+        assert_eq!(first.iter().map(|d| (d.node_id, d.accounts)).collect::<Vec<_>>(), vec![(A, 2), (B, 1), (C, 1)]);
+        assert_eq!(first, second);
+
+        // One more commit on one run shows up in the digest of its shard alone
+        run_transaction(runs[1][&A].port, deposits("C.z", 1)).await;
+        let diverged = digests(&runs[1]).await;
+        assert_eq!(first[..2], diverged[..2]);
+        assert_ne!(first[2].root, diverged[2].root);
+        assert_eq!(first[2].leaves.iter().zip(&diverged[2].leaves).filter(|(a, b)| a != b).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tenants_authenticate_and_stay_in_their_namespace() {
+        let config = local_config(&["A", "B"]);
+        let port = config[&A].port;
+        let acme = TenantPolicy { secret: "acme-secret".into(), ..Default::default() };
+        let ops = TenantPolicy { secret: "ops-secret".into(), admin: true, ..Default::default() };
+        let servers = [A, B].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for server in servers {
+            let mut server = server.await.unwrap().with_tenant("acme", acme.clone()).with_tenant("ops", ops.clone());
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let auth = |tenant: &str, secret: &str| Authenticate(tenant.into(), secret.into());
+        let responses = run_transaction(port, vec![WriteBalance("A.acme/x".into(), BalanceDiff(1))]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedUnauthorized]), "{responses:?}");
+        let responses = run_transaction(port, vec![auth("acme", "ops-secret")]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::AbortedUnauthorized]), "{responses:?}");
+
+        let responses = run_transaction(port, vec![
+            auth("acme", "acme-secret"), WriteBalance("A.acme/x".into(), BalanceDiff(5)), WriteBalance("B.acme/y".into(), BalanceDiff(1)),
+            Swap("A.acme/x".into(), "B.acme/y".into()), Commit
+        ]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        let responses = run_transaction(port, vec![auth("acme", "acme-secret"), ReadBalance("B.ops/y".into()), Commit]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::AbortedUnauthorized, _]), "{responses:?}");
+        let responses = run_transaction(port, vec![auth("acme", "acme-secret"), Admin(AdminRequest::Tenants)]).await;
+        assert!(matches!(responses.as_slice(), [ClientResponse::Ok, ClientResponse::AbortedUnauthorized]), "{responses:?}");
+
+        let responses = run_transaction(port, vec![auth("ops", "ops-secret"), Admin(AdminRequest::Tenants)]).await;
+        let [ClientResponse::Ok, ClientResponse::Admin(AdminResponse::Tenants(tenants))] = responses.as_slice() else {
+            panic!("Unexpected tenants response: {responses:?}");
+        };
+        let counts: Vec<_> = tenants.iter().map(|t| (t.tenant.as_str(), t.active, t.requests, t.committed, t.aborted, t.denied)).collect();
+        assert_eq!(counts, vec![("acme", 0, 3, 1, 2, 3), ("ops", 1, 0, 0, 0, 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_acls_grant_access_outside_namespace() {
+        let config = local_config(&["A", "B"]);
+        let port = config[&A].port;
+        let tenant = |secret: &str, admin| TenantPolicy { secret: secret.into(), admin, ..Default::default() };
+        let acl = AccountAcl { account_id: "A.acme/x".into(), owner: "acme".into(), grants: vec![("globex".into(), Access::Read)] };
+        let servers = [A, B].map(|node_id| tokio::spawn(Server::start(node_id, config.clone(), 5)));
+        for server in servers {
+            let mut server = server.await.unwrap()
+                .with_tenant("acme", tenant("acme-secret", false))
+                .with_tenant("globex", tenant("globex-secret", false))
+                .with_tenant("ops", tenant("ops-secret", true))
+                .with_acl(acl.clone());
+            tokio::spawn(async move { server.serve().await });
+        }
+
+        let auth = |tenant: &str| Authenticate(tenant.into(), format!("{tenant}-secret"));
+        let responses = run_transaction(port, vec![auth("acme"), WriteBalance("A.acme/x".into(), BalanceDiff(5)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        let responses = run_transaction(port, vec![auth("globex"), ReadBalance("A.acme/x".into()), Commit]).await;
+        assert!(matches!(responses.as_slice(), [_, ClientResponse::Value(_, 5), ClientResponse::CommitOk]), "{responses:?}");
+        let responses = run_transaction(port, vec![auth("globex"), WriteBalance("A.acme/x".into(), BalanceDiff(-1))]).await;
+        assert!(matches!(responses.as_slice(), [_, ClientResponse::AbortedUnauthorized]), "{responses:?}");
+
+        // An owned account is closed to its namespace's tenant unless granted
+        let responses = run_transaction(port, vec![
+            auth("ops"), Admin(AdminRequest::SetOwner("B.acme/y".into(), "globex".into())),
+            Admin(AdminRequest::Grant("A.acme/x".into(), "globex".into(), Some(Access::ReadWrite))), Admin(AdminRequest::Acls)
+        ]).await;
+        let Some(ClientResponse::Admin(AdminResponse::Acls(acls))) = responses.last() else {
+            panic!("Unexpected ACLs response: {responses:?}");
+        };
+        assert_eq!(acls.iter().map(|acl| acl.account_id.as_str()).collect::<Vec<_>>(), vec!["A.acme/x", "B.acme/y"]);
+
+        let responses = run_transaction(port, vec![auth("acme"), WriteBalance("B.acme/y".into(), BalanceDiff(1))]).await;
+        assert!(matches!(responses.as_slice(), [_, ClientResponse::AbortedUnauthorized]), "{responses:?}");
+        let responses = run_transaction(port, vec![
+            auth("globex"), WriteBalance("B.acme/y".into(), BalanceDiff(1)), Swap("A.acme/x".into(), "B.acme/y".into()), Commit
+        ]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        // Every coordinator enforces the changes, not just the one that made
+        // them
+        let port_b = config[&B].port;
+        let responses = run_transaction(port_b, vec![auth("acme"), WriteBalance("B.acme/y".into(), BalanceDiff(1))]).await;
+        assert!(matches!(responses.as_slice(), [_, ClientResponse::AbortedUnauthorized]), "{responses:?}");
+        let responses = run_transaction(port_b, vec![auth("globex"), WriteBalance("A.acme/x".into(), BalanceDiff(-1)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+        let responses = run_transaction(port_b, vec![auth("ops"), Admin(AdminRequest::RemoveAcl("B.acme/y".into()))]).await;
+        assert!(matches!(responses.as_slice(), [_, ClientResponse::Admin(AdminResponse::Acls(acls))] if acls.is_empty()), "{responses:?}");
+        let responses = run_transaction(port, vec![auth("acme"), WriteBalance("B.acme/y".into(), BalanceDiff(1)), Commit]).await;
+        assert!(matches!(responses.last(), Some(ClientResponse::CommitOk)), "{responses:?}");
+
+        // Access to an account with no owner cannot be granted
+        let responses = run_transaction(port, vec![auth("ops"), Admin(AdminRequest::Grant("A.acme/z".into(), "globex".into(), Some(Access::Read)))]).await;
+        assert!(matches!(responses.as_slice(), [_, ClientResponse::Admin(AdminResponse::NoOwner(account_id))] if account_id == "A.acme/z"), "{responses:?}");
     }
 }
@@ -1,13 +1,19 @@
-use tx_common::{ClientRequest, ClientResponse, config::NodeId};
-use serde::{Deserialize, Serialize};
+use tx_common::config::NodeId;
+use tokio::sync::oneshot;
 use crate::sharding::TransactionId;
+use super::layer::RequestLayer;
+pub use tx_proto::peer::{CommitStatus, ForwardId, Forwarded, SequencedTransaction, ShardReply};
 
 /// This enum indicates to the server how to forward a message.
 pub enum ForwardTarget {
     /// Nofity the server to forward a message to a single node.
     Node(NodeId),
-    /// Nofity the server to broadcast a message to all nodes in the group.
-    Broadcast
+    /// Notify the server to forward a message to several nodes, along a
+    /// spanning tree if it sends messages for that many nodes that way.
+    Nodes(Vec<NodeId>),
+    /// Notify the server to forward a message to the node it takes to 
+    /// sequence the transactions of a deterministic cluster.
+    Sequencer
 }
 
 /// This enum is used for communication between the client handler and server
@@ -17,47 +23,45 @@ pub enum ForwardTarget {
 /// when they are finished processing a transaction so the server may reap any
 /// resources used to manage the client handler. 
 pub enum ClientState {
-    /// Notify the server to either forward a request from the client to another 
-    /// shard since the data requested is located on that shard or broadcast the
-    /// request if the client handler must abort or commit a transaction. 
-    Forward(ForwardTarget, TransactionId, ClientRequest),
+    /// Notify the server to either forward a message to another shard since 
+    /// the data requested is located on that shard or because the shard must
+    /// vote on, commit or abort a transaction that touched it. 
+    Forward(ForwardTarget, Forwarded),
     /// Notify the server that the client handler is finished processing a 
     /// transaction so the server may reap resources associated with the client. 
-    Finished(TransactionId)
+    Finished(TransactionId),
+    /// Ask the server for a newer transaction id to replace one that has not
+    /// read or written anything yet (see `TimestampMode::FirstOperation`).
+    Renew(TransactionId, oneshot::Sender<TransactionId>),
+    /// Ask the server for the id and the chain of layers of the next 
+    /// transaction on a connection whose transaction finished.
+    Next(TransactionId, oneshot::Sender<(TransactionId, Vec<Box<dyn RequestLayer>>)>),
+    /// Ask the server what it holds for its clients and in its queues
+    Memory(oneshot::Sender<ServerMemory>)
 }
 
-/// Thus enum represents communication between shards forwarding requests on 
-/// behalf of clients coordinating transactions with shards and returning a 
-/// response to any received requests. This enum also represents the state
-/// associated with a two-phase commit of a transaction.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub enum Forwarded {
-    /// Forwards a client request from a coordinator to a shard.
-    Request(TransactionId, ClientRequest),
-    /// Respond to a request from a coordinator upon processing a client request
-    /// received from this coordinator. 
-    Response(TransactionId, ClientResponse),
-    /// Messages exchanged as part of a two-phase commit of a transaction.
-    TwoPhaseCommitStatus(TransactionId, CommitStatus),
-    /// Notifies a shard that all other shards are able to commit the 
-    /// transaction, so the shard can proceed with the commit. 
-    DoCommit(TransactionId)
+/// What the server task holds, for a client handler reporting the memory of
+/// the node.
+#[derive(Debug, Default)]
+pub struct ServerMemory {
+    pub sessions: usize,
+    pub greeting: usize,
+    /// The messages waiting in each queue the server reads or sends to 
+    /// peers from, by queue
+    pub queues: Vec<(String, usize)>
 }
 
-/// Status exchanged between shards as part of the two-phase commit process.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub enum CommitStatus {
-    /// Indicates that the shard is ready to commit the transaction upon 
-    /// checking that the transaction passes a consistency check. 
-    ReadyToCommit,
-    /// Indicates that the shard is unable to commit the transaction upon 
-    /// checking that the transaction passes a consistency check.
-    CannotCommit
-}
+/// The replies from the shards a message was sent to on behalf of a 
+/// transaction, tagged with the shard that sent each reply. 
+pub type ShardReplies = Vec<(NodeId, ShardReply)>;
+
+/// A shard's reply as routed to a client handler, tagged with the forward it
+/// answers and the shard that sent it.
+pub type RoutedReply = (ForwardId, NodeId, ShardReply);
 
 /// This enum represents the result of attempting to identify the shard that an
-/// object is located on. Objects are associated with the shard identified with 
-/// the first character of the object's name. 
+/// object is located on. Objects are associated with the shard named by the 
+/// prefix of the object's name (see `ShardMap`). 
 pub enum TargetShard {
     /// Indicates that the object is associated with a shard not located on the
     /// server that is coordinating the transaction requesting the object.
@@ -65,6 +69,6 @@ pub enum TargetShard {
     /// Indicates that the object is associated with the server that is 
     /// coordinating the transaction requesting the object.
     Local,
-    /// Indicates that the first letter of the object is not a valid shard name. 
+    /// Indicates that the object's name does not name a shard. 
     DoesNotExist
 }
\ No newline at end of file
@@ -0,0 +1,102 @@
+use tx_common::{AccountId, Amount};
+use tx_proto::{ClientRequest, ClientResponse};
+use std::collections::HashSet;
+use super::layer::{RequestContext, RequestLayer};
+use log::info;
+
+pub static MAX_TRANSACTION_OPS: usize = 10_000;
+pub static MAX_TRANSACTION_WRITE_BYTES: usize = 1 << 20;
+
+/// Limits on how much a single transaction may do before it is aborted, so
+/// that a client stuck in a loop cannot exhaust the memory of the shards.
+#[derive(Clone, Copy, Debug)]
+pub struct TransactionQuota {
+    /// The number of reads and writes a transaction may request
+    pub max_ops: usize,
+    /// The size of the tentative writes a transaction may hold across all
+    /// shards, counting each written account's name and balance once
+    pub max_write_bytes: usize
+}
+
+impl Default for TransactionQuota {
+    fn default() -> Self {
+        Self { max_ops: MAX_TRANSACTION_OPS, max_write_bytes: MAX_TRANSACTION_WRITE_BYTES }
+    }
+}
+
+/// What a transaction has used of its quota so far.
+#[derive(Default)]
+pub(super) struct QuotaUsage {
+    ops: usize,
+    written: HashSet<AccountId>,
+    write_bytes: usize
+}
+
+impl QuotaUsage {
+    /// Charges a request against the quota, returning false if handling the
+    /// request would exceed it. Requests that neither read nor write are free.
+    pub(super) fn charge(&mut self, request: &ClientRequest, quota: &TransactionQuota) -> bool {
+        let writes = match request {
+            ClientRequest::ReadBalance(_) | ClientRequest::ReadBalanceIfExists(_) | ClientRequest::ReadBalanceStale(..) | ClientRequest::AssertBalance(..) => vec![],
+            ClientRequest::WriteBalance(account_id, _) | ClientRequest::WriteBalanceWithTtl(account_id, ..) | ClientRequest::Increment(account_id, _) => vec![account_id],
+            ClientRequest::Swap(first, second) => vec![first, second],
+            _ => return true
+        };
+
+        self.ops += 1;
+        for account_id in writes {
+            if self.written.insert(account_id.clone()) {
+                self.write_bytes += account_id.len() + std::mem::size_of::<Amount>();
+            }
+        }
+
+        self.ops <= quota.max_ops && self.write_bytes <= quota.max_write_bytes
+    }
+}
+
+/// Aborts a transaction once it exceeds its quota.
+pub(super) struct QuotaLayer {
+    quota: TransactionQuota,
+    usage: QuotaUsage
+}
+
+impl QuotaLayer {
+    pub(super) fn new(quota: TransactionQuota) -> Self {
+        Self { quota, usage: QuotaUsage::default() }
+    }
+}
+
+impl RequestLayer for QuotaLayer {
+    fn before(&mut self, cx: &RequestContext, request: &ClientRequest) -> Option<ClientResponse> {
+        if !cx.active || self.usage.charge(request, &self.quota) {
+            return None;
+        }
+
+        info!("{} exceeded its quota of {:?}", cx.tx_id, self.quota);
+        Some(ClientResponse::AbortedQuotaExceeded)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_proto::BalanceDiff;
+    use super::*;
+
+    #[test]
+    fn test_quota_counts_ops_and_distinct_writes() {
+        let quota = TransactionQuota { max_ops: 3, max_write_bytes: 2 * (3 + 8) };
+        let mut usage = QuotaUsage::default();
+        let write = |account_id: &str| ClientRequest::WriteBalance(account_id.into(), BalanceDiff(1));
+
+        assert!(usage.charge(&write("A.x"), &quota));
+        assert!(usage.charge(&write("A.x"), &quota));
+        assert!(usage.charge(&ClientRequest::Commit, &quota));
+        assert!(usage.charge(&write("A.y"), &quota));
+        assert!(!usage.charge(&ClientRequest::ReadBalance("A.x".into()), &quota));
+
+        let mut usage = QuotaUsage::default();
+        assert!(usage.charge(&write("A.x"), &quota));
+        assert!(usage.charge(&write("A.y"), &quota));
+        assert!(!usage.charge(&write("A.z"), &quota));
+    }
+}
@@ -0,0 +1,80 @@
+use tx_common::config::NodeId;
+use std::collections::HashMap;
+use crate::sharding::TransactionId;
+use super::protocol::{ForwardId, ShardReplies};
+
+/// The requests this node relays along spanning trees for other coordinators,
+/// gathering its own reply and those of the nodes below it so that the node
+/// above it gets one message rather than one per node.
+#[derive(Debug, Default)]
+pub(super) struct Relays {
+    pending: HashMap<(TransactionId, ForwardId), Relay>
+}
+
+#[derive(Debug)]
+struct Relay {
+    parent: NodeId,
+    /// The nodes whose replies have not arrived yet
+    waiting: usize,
+    replies: ShardReplies
+}
+
+/// What to do with replies relayed to this node.
+#[derive(Debug)]
+pub(super) enum Delivery {
+    /// The replies answer a forward this node did not relay, so they are for
+    /// one of its own client handlers
+    NotRelayed(ShardReplies),
+    /// Replies from other nodes below this one are still to come
+    Waiting,
+    /// Every reply arrived and goes to the node above this one
+    Complete(NodeId, ShardReplies)
+}
+
+impl Relays {
+    /// Starts relaying a request for `parent`, awaiting replies from `waiting`
+    /// nodes on top of those already known.
+    pub(super) fn start(&mut self, tx_id: TransactionId, fwd_id: ForwardId, parent: NodeId, waiting: usize, replies: ShardReplies) {
+        self.pending.insert((tx_id, fwd_id), Relay { parent, waiting, replies });
+    }
+
+    pub(super) fn deliver(&mut self, tx_id: TransactionId, fwd_id: ForwardId, replies: ShardReplies) -> Delivery {
+        let Some(relay) = self.pending.get_mut(&(tx_id, fwd_id)) else {
+            return Delivery::NotRelayed(replies);
+        };
+
+        relay.waiting = relay.waiting.saturating_sub(replies.len());
+        relay.replies.extend(replies);
+        if relay.waiting > 0 {
+            return Delivery::Waiting;
+        }
+
+        let relay = self.pending.remove(&(tx_id, fwd_id)).unwrap();
+        Delivery::Complete(relay.parent, relay.replies)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sharding::TransactionIdGenerator;
+    use super::super::protocol::ShardReply;
+    use super::*;
+
+    #[test]
+    fn test_replies_gathered_until_complete() {
+        let tx_id = TransactionIdGenerator::new(NodeId(0)).next();
+        let mut relays = Relays::default();
+        assert!(matches!(relays.deliver(tx_id, 0, vec![(NodeId(1), ShardReply::Unreachable)]), Delivery::NotRelayed(replies) if replies.len() == 1));
+
+        relays.start(tx_id, 0, NodeId(0), 3, vec![(NodeId(4), ShardReply::Unrelayed)]);
+        assert!(matches!(relays.deliver(tx_id, 0, vec![(NodeId(1), ShardReply::Unreachable)]), Delivery::Waiting));
+        assert!(matches!(relays.deliver(tx_id, 1, vec![]), Delivery::NotRelayed(_)));
+        let replies = vec![(NodeId(2), ShardReply::Unreachable), (NodeId(3), ShardReply::Unreachable)];
+        let Delivery::Complete(parent, replies) = relays.deliver(tx_id, 0, replies) else {
+            panic!("Relay should be complete");
+        };
+        assert_eq!(parent, NodeId(0));
+        assert_eq!(replies.iter().map(|(node_id, _)| node_id.0).collect::<Vec<_>>(), vec![4, 1, 2, 3]);
+        assert!(matches!(relays.deliver(tx_id, 0, vec![]), Delivery::NotRelayed(_)));
+    }
+}
@@ -0,0 +1,325 @@
+use crate::sharding::{CommitSuccess, TransactionId};
+use tx_common::{AccountId, Amount, config::NodeId};
+use tokio::sync::mpsc::UnboundedSender;
+use serde::Serialize;
+use std::{collections::BTreeMap, fs::File, io::{self, BufWriter, Write}, path::Path, sync::Mutex, time::SystemTime};
+use log::{error, info};
+
+/// A transaction as committed by one shard, with the balance of every account
+/// the shard holds after the commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitReport {
+    pub shard_id: NodeId,
+    pub tx_id: TransactionId,
+    /// Whether the transaction changed any balance on the shard
+    pub changed: bool,
+    /// Sorted by account
+    pub balances: Vec<(AccountId, Amount)>
+}
+
+impl CommitReport {
+    pub(super) fn new(shard_id: NodeId, tx_id: TransactionId, result: CommitSuccess<Vec<(AccountId, Amount)>>) -> Self {
+        let (changed, mut balances) = match result {
+            CommitSuccess::ValueChanged(r) => (true, r),
+            CommitSuccess::NoChange(r) => (false, r)
+        };
+
+        balances.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Self { shard_id, tx_id, changed, balances }
+    }
+
+    /// Formats the nonzero balances as `account = balance` pairs, returning
+    /// `None` if every balance is zero.
+    pub fn to_line(&self) -> Option<String> {
+        let mut output = String::new();
+        for (k, v) in self.balances.iter() {
+            if *v != 0 {
+                output += &format!("{k} = {v} ");
+            }
+        }
+
+        (!output.is_empty()).then_some(output)
+    }
+}
+
+/// A transaction its client aborted giving a reason, as recorded by the 
+/// node that coordinated it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AbortReport {
+    pub coordinator: NodeId,
+    pub tx_id: TransactionId,
+    pub reason: String
+}
+
+/// Receives every transaction a shard commits. Reporters are called from the
+/// tasks applying commits, so they must not block for long.
+pub trait CommitReporter: Send + Sync {
+    fn report(&self, commit: CommitReport);
+
+    /// Receives every abort a client gave a reason for, on the node that 
+    /// coordinated the transaction. Reporters that only follow balances 
+    /// ignore them.
+    fn report_abort(&self, _abort: AbortReport) {}
+}
+
+impl<R: CommitReporter + ?Sized> CommitReporter for Box<R> {
+    fn report(&self, commit: CommitReport) {
+        (**self).report(commit)
+    }
+
+    fn report_abort(&self, abort: AbortReport) {
+        (**self).report_abort(abort)
+    }
+}
+
+/// Prints every commit to stdout as a line of `account = balance` pairs.
+pub struct StdoutReporter;
+
+impl CommitReporter for StdoutReporter {
+    fn report(&self, commit: CommitReport) {
+        if let Some(line) = commit.to_line() {
+            println!("{line}");
+        }
+    }
+}
+
+/// Logs every commit at the info level.
+pub struct LogReporter;
+
+impl CommitReporter for LogReporter {
+    fn report(&self, commit: CommitReport) {
+        if let Some(line) = commit.to_line() {
+            info!("Committed {}: {line}", commit.tx_id);
+        }
+    }
+
+    fn report_abort(&self, abort: AbortReport) {
+        info!("Client aborted {}: {}", abort.tx_id, abort.reason);
+    }
+}
+
+/// Appends every commit to a file in the format of `StdoutReporter`.
+pub struct FileReporter {
+    file: Mutex<BufWriter<File>>
+}
+
+impl FileReporter {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(BufWriter::new(file)) })
+    }
+}
+
+impl CommitReporter for FileReporter {
+    fn report(&self, commit: CommitReport) {
+        if let Some(line) = commit.to_line() {
+            let mut file = self.file.lock().unwrap();
+            if let Err(e) = writeln!(file, "{line}").and_then(|_| file.flush()) {
+                error!("Failed to report commit of {}: {e}", commit.tx_id);
+            }
+        }
+    }
+}
+
+/// Writes every commit as a line of JSON, e.g.
+/// `{"tx_id":{"ts":1700000000000000000,"coordinator":0},"shard_id":1,"committed_at_ms":1700000000001,"balances":{"B.y":3}}`,
+/// where `committed_at_ms` is the wall-clock time the shard applied the 
+/// commit in milliseconds since the epoch. Unlike the other reporters, every
+/// balance on the shard is included, even if it is zero. Aborts that clients
+/// gave a reason for are written as 
+/// `{"tx_id":{..},"coordinator":0,"aborted_at_ms":1700000000001,"reason":"cancelled"}`.
+pub struct JsonLinesReporter {
+    sink: Mutex<Box<dyn Write + Send>>
+}
+
+#[derive(Serialize)]
+struct JsonCommit<'a> {
+    tx_id: TransactionId,
+    shard_id: NodeId,
+    committed_at_ms: u128,
+    balances: BTreeMap<&'a str, Amount>
+}
+
+impl<'a> JsonCommit<'a> {
+    fn new(commit: &'a CommitReport) -> Self {
+        Self {
+            tx_id: commit.tx_id,
+            shard_id: commit.shard_id,
+            committed_at_ms: now_ms(),
+            balances: commit.balances.iter().map(|(k, v)| (k.as_str(), *v)).collect()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonAbort<'a> {
+    tx_id: TransactionId,
+    coordinator: NodeId,
+    aborted_at_ms: u128,
+    reason: &'a str
+}
+
+impl<'a> JsonAbort<'a> {
+    fn new(abort: &'a AbortReport) -> Self {
+        Self { tx_id: abort.tx_id, coordinator: abort.coordinator, aborted_at_ms: now_ms(), reason: &abort.reason }
+    }
+}
+
+/// The wall-clock time in milliseconds since the epoch.
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+impl JsonLinesReporter {
+    pub fn new(sink: impl Write + Send + 'static) -> Self {
+        Self { sink: Mutex::new(Box::new(sink)) }
+    }
+
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl JsonLinesReporter {
+    fn write_line(&self, json: &impl Serialize) -> io::Result<()> {
+        let mut sink = self.sink.lock().unwrap();
+        serde_json::to_writer(&mut *sink, json)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(sink))
+            .and_then(|_| sink.flush())
+    }
+}
+
+impl CommitReporter for JsonLinesReporter {
+    fn report(&self, commit: CommitReport) {
+        if let Err(e) = self.write_line(&JsonCommit::new(&commit)) {
+            error!("Failed to report commit of {}: {e}", commit.tx_id);
+        }
+    }
+
+    fn report_abort(&self, abort: AbortReport) {
+        if let Err(e) = self.write_line(&JsonAbort::new(&abort)) {
+            error!("Failed to report abort of {}: {e}", abort.tx_id);
+        }
+    }
+}
+
+/// Produces every commit to a Kafka topic as a message in the format of 
+/// `JsonLinesReporter`, keyed by the shard that committed it so that each
+/// shard's commits stay in order on one partition. Messages are queued and
+/// delivered by a background thread, and a commit that cannot be queued, 
+/// such as while the brokers are unreachable and the queue is full, is 
+/// logged and dropped rather than holding up the shard.
+#[cfg(feature = "kafka")]
+pub struct KafkaReporter {
+    producer: rdkafka::producer::ThreadedProducer<rdkafka::producer::DefaultProducerContext>,
+    topic: String
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaReporter {
+    /// Produces to `topic` on the comma-separated `brokers`.
+    pub fn new(brokers: &str, topic: impl Into<String>) -> rdkafka::error::KafkaResult<Self> {
+        let producer = rdkafka::ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .create()?;
+        Ok(Self { producer, topic: topic.into() })
+    }
+}
+
+#[cfg(feature = "kafka")]
+impl CommitReporter for KafkaReporter {
+    fn report(&self, commit: CommitReport) {
+        let payload = match serde_json::to_vec(&JsonCommit::new(&commit)) {
+            Ok(payload) => payload,
+            Err(e) => return error!("Failed to report commit of {}: {e}", commit.tx_id)
+        };
+
+        let key = commit.shard_id.0.to_string();
+        let record = rdkafka::producer::BaseRecord::to(&self.topic).key(&key).payload(&payload);
+        if let Err((e, _)) = self.producer.send(record) {
+            error!("Failed to report commit of {} to {}: {e}", commit.tx_id, self.topic);
+        }
+    }
+
+    /// Produces the abort keyed by its coordinator, like `JsonLinesReporter`
+    /// writes it.
+    fn report_abort(&self, abort: AbortReport) {
+        let payload = match serde_json::to_vec(&JsonAbort::new(&abort)) {
+            Ok(payload) => payload,
+            Err(e) => return error!("Failed to report abort of {}: {e}", abort.tx_id)
+        };
+
+        let key = abort.coordinator.0.to_string();
+        let record = rdkafka::producer::BaseRecord::to(&self.topic).key(&key).payload(&payload);
+        if let Err((e, _)) = self.producer.send(record) {
+            error!("Failed to report abort of {} to {}: {e}", abort.tx_id, self.topic);
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+impl Drop for KafkaReporter {
+    fn drop(&mut self) {
+        use rdkafka::producer::Producer;
+        if let Err(e) = self.producer.flush(std::time::Duration::from_secs(5)) {
+            error!("Failed to deliver the last commits to {}: {e}", self.topic);
+        }
+    }
+}
+
+/// Passes every commit to a channel, so that an embedder can consume them.
+pub struct ChannelReporter(pub UnboundedSender<CommitReport>);
+
+impl CommitReporter for ChannelReporter {
+    fn report(&self, commit: CommitReport) {
+        let _ = self.0.send(commit);
+    }
+}
+
+/// Reports nothing.
+pub struct SilentReporter;
+
+impl CommitReporter for SilentReporter {
+    fn report(&self, _: CommitReport) {}
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{sharding::TransactionIdGenerator, testing::SharedBuffer};
+    use super::*;
+
+    #[test]
+    fn test_json_lines_report() {
+        let buffer = SharedBuffer::default();
+        let reporter = JsonLinesReporter::new(buffer.clone());
+        let tx_id = TransactionIdGenerator::new(NodeId(0)).next();
+        let result = CommitSuccess::ValueChanged(vec![("B.y".to_string(), 3), ("B.x".to_string(), 0)]);
+        reporter.report(CommitReport::new(NodeId(1), tx_id, result));
+
+        let output = buffer.contents();
+        assert!(output.ends_with('\n'));
+        let line = output.trim_end();
+        let expected_prefix = format!(r#"{{"tx_id":{{"ts":{},"coordinator":0}},"shard_id":1,"committed_at_ms":"#, tx_id.timestamp());
+        assert!(line.starts_with(&expected_prefix), "{line}");
+        assert!(line.ends_with(r#","balances":{"B.x":0,"B.y":3}}"#), "{line}");
+    }
+
+    #[test]
+    fn test_json_lines_abort_report() {
+        let buffer = SharedBuffer::default();
+        let reporter = JsonLinesReporter::new(buffer.clone());
+        let tx_id = TransactionIdGenerator::new(NodeId(0)).next();
+        reporter.report_abort(AbortReport { coordinator: NodeId(2), tx_id, reason: "user \"cancelled\"".into() });
+
+        let output = buffer.contents();
+        let line = output.trim_end();
+        let expected_prefix = format!(r#"{{"tx_id":{{"ts":{},"coordinator":0}},"coordinator":2,"aborted_at_ms":"#, tx_id.timestamp());
+        assert!(line.starts_with(&expected_prefix), "{line}");
+        assert!(line.ends_with(r#","reason":"user \"cancelled\""}"#), "{line}");
+    }
+}
@@ -0,0 +1,75 @@
+use tx_common::{AccountId, config::{NodeId, ShardMap}};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The node each account a transaction touched routed to. Later operations on
+/// an account reuse its route, and the routes scope the transaction's commit
+/// to the shards it touched.
+#[derive(Debug, Default)]
+pub(super) struct RouteCache {
+    routes: BTreeMap<AccountId, NodeId>,
+    /// The nodes whose every account the transaction read in a query
+    queried: BTreeSet<NodeId>
+}
+
+impl RouteCache {
+    /// Routes an account, remembering the node it routed to. Accounts that
+    /// no shard owns are not remembered.
+    pub(super) fn route(&mut self, shards: &ShardMap, account_id: &AccountId) -> Option<NodeId> {
+        match self.routes.get(account_id) {
+            Some(node_id) => Some(*node_id),
+            None => {
+                let node_id = shards.shard_for(account_id)?;
+                self.routes.insert(account_id.clone(), node_id);
+                Some(node_id)
+            }
+        }
+    }
+
+    /// Remembers that the transaction read every account on a node.
+    pub(super) fn query(&mut self, node_id: NodeId) {
+        self.queried.insert(node_id);
+    }
+
+    /// The nodes hosting a shard the transaction touched.
+    pub(super) fn participants(&self) -> BTreeSet<NodeId> {
+        self.routes.values().chain(&self.queried).copied().collect()
+    }
+
+    /// Every route, by account.
+    pub(super) fn routes(&self) -> Vec<(AccountId, NodeId)> {
+        self.routes.iter().map(|(account_id, node_id)| (account_id.clone(), *node_id)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tx_common::config::{Config, NodeConfiguration};
+    use super::*;
+
+    #[test]
+    fn test_routes_remembered_per_account() {
+        let node = |id, shards: &[&str]| (NodeId(id), NodeConfiguration {
+            node_id: NodeId(id),
+            name: format!("n{id}"),
+            hostname: "127.0.0.1".into(),
+            port: 0,
+            connection_list: vec![],
+            shards: shards.iter().map(|shard| shard.to_string()).collect()
+        });
+        let shards = ShardMap::new(&Config::from([node(0, &["A", "X"]), node(1, &["B"])]));
+
+        let mut cache = RouteCache::default();
+        assert_eq!(cache.route(&shards, &"X.y".into()), Some(NodeId(0)));
+        assert_eq!(cache.route(&shards, &"B.x".into()), Some(NodeId(1)));
+        assert_eq!(cache.route(&shards, &"X.y".into()), Some(NodeId(0)));
+        assert_eq!(cache.route(&shards, &"Z.z".into()), None);
+
+        assert_eq!(cache.participants(), BTreeSet::from([NodeId(0), NodeId(1)]));
+        assert_eq!(cache.routes(), vec![("B.x".into(), NodeId(1)), ("X.y".into(), NodeId(0))]);
+
+        // A query touches the shard without routing any account to it
+        cache.query(NodeId(2));
+        assert_eq!(cache.participants(), BTreeSet::from([NodeId(0), NodeId(1), NodeId(2)]));
+        assert_eq!(cache.routes().len(), 2);
+    }
+}
@@ -0,0 +1,100 @@
+use tokio::{sync::{Notify, OwnedSemaphorePermit, Semaphore}, time::Instant};
+use std::sync::Arc;
+
+/// The node's limit on the clients it serves at once. A slot is held by 
+/// every client connection from when it is accepted until its handler 
+/// finishes.
+pub(super) struct Slots {
+    slots: Arc<Semaphore>,
+    freed: Arc<Notify>
+}
+
+impl Slots {
+    pub(super) fn new(max: usize) -> Self {
+        Self { slots: Arc::new(Semaphore::new(max)), freed: Default::default() }
+    }
+
+    /// Takes a slot if one is free. Never waits, since the server stops 
+    /// accepting connections instead while none is.
+    pub(super) fn take(&self) -> Option<Slot> {
+        let permit = self.slots.clone().try_acquire_owned().ok()?;
+        Some(Slot { _permit: permit, freed: self.freed.clone() })
+    }
+
+    pub(super) fn available(&self) -> bool {
+        self.slots.available_permits() > 0
+    }
+
+    /// Resolves once a slot is given back, so that the server can start 
+    /// accepting connections again.
+    pub(super) async fn freed(&self) {
+        self.freed.notified().await
+    }
+}
+
+/// A slot, given back when dropped.
+pub(super) struct Slot {
+    _permit: OwnedSemaphorePermit,
+    freed: Arc<Notify>
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.freed.notify_one();
+    }
+}
+
+/// The node's limit on the reads and writes forwarded by peers that run at
+/// once, kept apart from the client slots so that the node's own clients 
+/// never crowd out transactions coordinated elsewhere. An operation that 
+/// finds no slot free waits its turn.
+#[derive(Clone)]
+pub(super) struct OperationSlots(Arc<Semaphore>);
+
+impl OperationSlots {
+    pub(super) fn new(max: usize) -> Self {
+        Self(Arc::new(Semaphore::new(max)))
+    }
+
+    /// Waits for a slot, held until the returned permit is dropped, unless 
+    /// `deadline` passes first.
+    pub(super) async fn take(self, deadline: Option<Instant>) -> Option<OwnedSemaphorePermit> {
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, self.0.acquire_owned()).await.ok()?.ok(),
+            None => self.0.acquire_owned().await.ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_slots_given_back_when_dropped() {
+        let slots = Slots::new(1);
+        let slot = slots.take().unwrap();
+        assert!(slots.take().is_none());
+        assert!(!slots.available());
+
+        drop(slot);
+        slots.freed().await;
+        assert!(slots.available());
+        assert!(slots.take().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_operations_wait_their_turn_until_their_deadline() {
+        let slots = OperationSlots::new(1);
+        let held = slots.clone().take(None).await.unwrap();
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert!(slots.clone().take(Some(deadline)).await.is_none());
+
+        let waiting = tokio::spawn(slots.clone().take(None));
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        drop(held);
+        assert!(waiting.await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,181 @@
+use tx_common::{admin::ShardSnapshot, config::NodeId, AccountId};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io, path::{Path, PathBuf}, time::Duration};
+use super::{account_snapshots, id_store::{read_checked, replace_checked}, AtomicShard};
+use log::error;
+
+/// How often a node's shard is written to its snapshot file by default.
+pub static SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What a snapshot file holds: the committed state of the shard, and when
+/// each account given a lifetime expires, which the state alone would lose.
+#[derive(Deserialize, Serialize)]
+struct SnapshotFile {
+    snapshot: ShardSnapshot,
+    expiries: Vec<(AccountId, u128)>
+}
+
+impl SnapshotFile {
+    /// Takes a snapshot of the committed state of `shard` between commits.
+    async fn take(shard: &AtomicShard, node_id: NodeId) -> Self {
+        let (seq, committed) = shard.snapshot_with_expiry().await;
+        let expiries = committed
+            .iter()
+            .filter_map(|(account_id, _, _, expires_at)| Some((account_id.clone(), (*expires_at)?)))
+            .collect();
+        let accounts = account_snapshots(committed
+            .into_iter()
+            .map(|(account_id, balance, committed_by, _)| (account_id, balance, committed_by)));
+        Self { snapshot: ShardSnapshot { node_id, seq, accounts }, expiries }
+    }
+}
+
+/// Writes the committed balance of every account on `shard` to the file at
+/// `path` every `interval` for as long as the server runs, replacing the
+/// last snapshot, which `restore` reads back when the node restarts. A 
+/// snapshot is taken between commits, so it holds every commit up to its
+/// sequence number and none after, and it is only written once a commit has
+/// changed the shard since the last one. The file is written on a blocking
+/// thread, so the shard's operations never wait on the disk.
+pub(super) async fn run(shard: AtomicShard, node_id: NodeId, path: PathBuf, interval: Duration) {
+    let mut written = None;
+    loop {
+        tokio::time::sleep(interval).await;
+        let file = SnapshotFile::take(&shard, node_id).await;
+        if written == Some(file.snapshot.seq) {
+            continue;
+        }
+
+        let seq = file.snapshot.seq;
+        let written_to = path.clone();
+        let result = tokio::task::spawn_blocking(move || -> io::Result<()> {
+            replace_checked(&written_to, serde_json::to_vec(&file)?)
+        }).await;
+
+        match result.unwrap_or_else(|e| Err(io::Error::other(e))) {
+            Ok(()) => written = Some(seq),
+            Err(e) => error!("Failed to write the snapshot of the shard to {}: {e}", path.display())
+        }
+    }
+}
+
+/// Restores `shard` from the snapshot `run` last wrote to the file at `path`,
+/// returning the sequence number of the snapshot and how many accounts it
+/// held, or `None` if there is no file. A file that fails its checksum, such
+/// as one cut short, or that holds another node's shard is an error.
+pub(super) async fn restore(shard: &AtomicShard, node_id: NodeId, path: &Path) -> io::Result<Option<(u64, usize)>> {
+    let Some(contents) = read_checked(path)? else { return Ok(None) };
+    let SnapshotFile { snapshot, expiries } = serde_json::from_slice(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("corrupt snapshot: {e}")))?;
+    if snapshot.node_id != node_id {
+        let message = format!("snapshot of node {} rather than node {node_id}", snapshot.node_id);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+
+    let expiries: HashMap<_, _> = expiries.into_iter().collect();
+    let accounts = snapshot.accounts.len();
+    let committed = snapshot.accounts
+        .into_iter()
+        .map(|account| {
+            let expires_at = expiries.get(&account.account_id).copied();
+            (account.account_id, account.balance, account.committed_by, expires_at)
+        })
+        .collect();
+    shard.restore(snapshot.seq, committed).await;
+    Ok(Some((snapshot.seq, accounts)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sharding::{Shard, TransactionIdGenerator};
+    use std::sync::Arc;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_snapshot_file_follows_commits() {
+        let path = std::env::temp_dir().join(format!("tx-server-snapshot-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let shard: AtomicShard = Arc::new(Shard::new(NodeId(0)));
+        let mut ids = TransactionIdGenerator::new(NodeId(0));
+        tokio::spawn(run(shard.clone(), NodeId(0), path.clone(), Duration::from_millis(10)));
+
+        for balance in [5, 8] {
+            let tx_id = ids.next();
+            shard.write(&tx_id, "A.x".to_string(), balance).await.unwrap();
+            shard.check_commit(&tx_id).await.unwrap();
+            shard.commit(&tx_id).await.unwrap();
+
+            let snapshot = loop {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let Ok(Some(contents)) = read_checked(&path) else { continue };
+                let SnapshotFile { snapshot, .. } = serde_json::from_slice(&contents).unwrap();
+                if snapshot.accounts.first().is_some_and(|account| account.committed_by == tx_id) {
+                    break snapshot;
+                }
+            };
+            assert_eq!(snapshot.node_id, NodeId(0));
+            assert_eq!(snapshot.accounts.len(), 1);
+            assert_eq!((snapshot.accounts[0].account_id.as_str(), snapshot.accounts[0].balance), ("A.x", balance));
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_restore_brings_back_accounts_and_their_lifetimes() {
+        let path = std::env::temp_dir().join(format!("tx-server-restore-{}", std::process::id()));
+        let shard: AtomicShard = Arc::new(Shard::new(NodeId(0)));
+        let mut ids = TransactionIdGenerator::new(NodeId(0));
+        let tx_id = ids.next();
+        shard.write(&tx_id, "A.x".to_string(), 5).await.unwrap();
+        shard.write_expiring(&tx_id, "A.y".to_string(), 7, Some(Duration::from_secs(3600))).await.unwrap();
+        shard.check_commit(&tx_id).await.unwrap();
+        shard.commit(&tx_id).await.unwrap();
+        replace_checked(&path, serde_json::to_vec(&SnapshotFile::take(&shard, NodeId(0)).await).unwrap()).unwrap();
+
+        let restored: AtomicShard = Arc::new(Shard::new(NodeId(0)));
+        assert_eq!(restore(&restored, NodeId(0), &path).await.unwrap(), Some((1, 2)));
+        let sorted = |(seq, mut committed): (u64, Vec<_>)| {
+            committed.sort();
+            (seq, committed)
+        };
+        assert_eq!(sorted(restored.snapshot_with_expiry().await), sorted(shard.snapshot_with_expiry().await));
+
+        // Commits made after the restore are numbered after the snapshot
+        let tx_id = ids.next();
+        restored.write(&tx_id, "A.x".to_string(), 6).await.unwrap();
+        restored.check_commit(&tx_id).await.unwrap();
+        restored.commit(&tx_id).await.unwrap();
+        assert_eq!(restored.snapshot().await.0, 2);
+
+        // A shard is only restored from its own snapshot
+        let other: AtomicShard = Arc::new(Shard::new(NodeId(1)));
+        let e = restore(&other, NodeId(1), &path).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_restore_refuses_a_snapshot_cut_short() {
+        let path = std::env::temp_dir().join(format!("tx-server-truncated-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let shard: AtomicShard = Arc::new(Shard::new(NodeId(0)));
+        assert_eq!(restore(&shard, NodeId(0), &path).await.unwrap(), None);
+
+        let tx_id = TransactionIdGenerator::new(NodeId(0)).next();
+        shard.write(&tx_id, "A.x".to_string(), 5).await.unwrap();
+        shard.check_commit(&tx_id).await.unwrap();
+        shard.commit(&tx_id).await.unwrap();
+        replace_checked(&path, serde_json::to_vec(&SnapshotFile::take(&shard, NodeId(0)).await).unwrap()).unwrap();
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+
+        let restored: AtomicShard = Arc::new(Shard::new(NodeId(0)));
+        let e = restore(&restored, NodeId(0), &path).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(restored.snapshot().await.1.is_empty());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
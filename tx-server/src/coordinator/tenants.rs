@@ -0,0 +1,325 @@
+use tx_common::{AccountId, admin::{Access, TenantStats}};
+use tx_proto::{ClientRequest, ClientResponse};
+use std::{collections::BTreeMap, sync::{Arc, Mutex}};
+use tokio::time::Instant;
+use subtle::ConstantTimeEq;
+use super::{acl::SharedAcls, layer::{RequestContext, RequestLayer}};
+use log::info;
+
+/// A tenant of a server: the secret it authenticates with and the share of
+/// the server its transactions may use.
+#[derive(Clone, Debug, Default)]
+pub struct TenantPolicy {
+    pub secret: String,
+    /// The reads, writes and swaps the tenant may request per second across
+    /// all of its transactions. Up to a second's worth may be requested in a
+    /// burst.
+    pub requests_per_sec: Option<u32>,
+    /// The number of the tenant's transactions that may run at once
+    pub max_active: Option<usize>,
+    /// Whether the tenant may make admin requests, which see past its
+    /// namespace
+    pub admin: bool
+}
+
+/// The tenants a server serves and what each has used of it. A server
+/// without tenants serves every client as before.
+#[derive(Debug, Default)]
+pub(super) struct Tenants {
+    tenants: BTreeMap<String, Tenant>
+}
+
+#[derive(Debug)]
+struct Tenant {
+    policy: TenantPolicy,
+    active: usize,
+    tokens: f64,
+    refilled_at: Instant,
+    requests: u64,
+    committed: u64,
+    aborted: u64,
+    throttled: u64,
+    denied: u64
+}
+
+pub(super) type SharedTenants = Arc<Mutex<Tenants>>;
+
+/// Whether an account is in a tenant's namespace, i.e. is named
+/// `<shard>.<tenant>/<name>`.
+pub(super) fn in_namespace(account_id: &str, tenant: &str) -> bool {
+    account_id
+        .split_once('.')
+        .and_then(|(_, name)| name.strip_prefix(tenant))
+        .is_some_and(|name| name.starts_with('/'))
+}
+
+/// The accounts a request touches and the access it needs to each.
+fn accessed(request: &ClientRequest) -> Vec<(&AccountId, Access)> {
+    match request {
+        ClientRequest::ReadBalance(account_id) | ClientRequest::ReadBalanceIfExists(account_id) | ClientRequest::ReadBalanceStale(account_id, _) | ClientRequest::AssertBalance(account_id, _) => vec![(account_id, Access::Read)],
+        ClientRequest::WriteBalance(account_id, _) | ClientRequest::WriteBalanceWithTtl(account_id, ..) | ClientRequest::Increment(account_id, _) => vec![(account_id, Access::ReadWrite)],
+        ClientRequest::Swap(first, second) => vec![(first, Access::ReadWrite), (second, Access::ReadWrite)],
+        _ => vec![]
+    }
+}
+
+impl Tenants {
+    pub(super) fn add(&mut self, name: String, policy: TenantPolicy) {
+        let tokens = policy.requests_per_sec.unwrap_or(0) as f64;
+        let tenant = Tenant {
+            policy, active: 0, tokens, refilled_at: Instant::now(),
+            requests: 0, committed: 0, aborted: 0, throttled: 0, denied: 0
+        };
+        self.tenants.insert(name, tenant);
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.tenants.is_empty()
+    }
+
+    /// Starts a transaction for a tenant if its secret is right and it is not
+    /// already running as many transactions as it may.
+    fn authenticate(&mut self, name: &str, secret: &str) -> Result<(), ClientResponse> {
+        let Some(tenant) = self.tenants.get_mut(name) else {
+            return Err(ClientResponse::AbortedUnauthorized);
+        };
+
+        // Compared in constant time, so that how long a wrong secret takes to
+        // refuse says nothing about how much of it was right
+        if !bool::from(tenant.policy.secret.as_bytes().ct_eq(secret.as_bytes())) {
+            tenant.denied += 1;
+            return Err(ClientResponse::AbortedUnauthorized);
+        }
+
+        if tenant.policy.max_active.is_some_and(|max_active| tenant.active >= max_active) {
+            tenant.throttled += 1;
+            return Err(ClientResponse::AbortedTenantLimit(name.into()));
+        }
+
+        tenant.active += 1;
+        Ok(())
+    }
+
+    /// Counts a request against the tenant's rate, returning false if the
+    /// tenant is over it.
+    fn charge(&mut self, name: &str) -> bool {
+        let tenant = self.tenants.get_mut(name).unwrap();
+        tenant.requests += 1;
+        let Some(rate) = tenant.policy.requests_per_sec else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let rate = rate as f64;
+        let elapsed = now.duration_since(tenant.refilled_at).as_secs_f64();
+        tenant.tokens = (tenant.tokens + elapsed * rate).min(rate);
+        tenant.refilled_at = now;
+
+        if tenant.tokens < 1.0 {
+            tenant.throttled += 1;
+            return false;
+        }
+
+        tenant.tokens -= 1.0;
+        true
+    }
+
+    fn deny(&mut self, name: &str) {
+        self.tenants.get_mut(name).unwrap().denied += 1;
+    }
+
+    fn may_admin(&self, name: &str) -> bool {
+        self.tenants[name].policy.admin
+    }
+
+    fn finish(&mut self, name: &str, committed: bool) {
+        let tenant = self.tenants.get_mut(name).unwrap();
+        tenant.active -= 1;
+        if committed {
+            tenant.committed += 1;
+        } else {
+            tenant.aborted += 1;
+        }
+    }
+
+    pub(super) fn stats(&self) -> Vec<TenantStats> {
+        self.tenants
+            .iter()
+            .map(|(name, tenant)| TenantStats {
+                tenant: name.clone(),
+                active: tenant.active,
+                max_active: tenant.policy.max_active,
+                requests_per_sec: tenant.policy.requests_per_sec,
+                requests: tenant.requests,
+                committed: tenant.committed,
+                aborted: tenant.aborted,
+                throttled: tenant.throttled,
+                denied: tenant.denied
+            })
+            .collect()
+    }
+}
+
+/// Authenticates a client's transaction as one of the server's tenants and
+/// confines it to the tenant's namespace, the accounts it was granted, and 
+/// its share of the server. Until the transaction authenticates, it may do
+/// nothing but abort. Without tenants, no transaction may touch an account
+/// with an access control list, since none can be its owner.
+pub(super) struct TenantLayer {
+    tenants: SharedTenants,
+    acls: SharedAcls,
+    tenant: Option<String>,
+    finished: bool
+}
+
+impl TenantLayer {
+    pub(super) fn new(tenants: SharedTenants, acls: SharedAcls) -> Self {
+        Self { tenants, acls, tenant: None, finished: false }
+    }
+
+    fn finish(&mut self, committed: bool) {
+        match &self.tenant {
+            Some(tenant) if !self.finished => {
+                self.finished = true;
+                self.tenants.lock().unwrap().finish(tenant, committed);
+            },
+            _ => ()
+        }
+    }
+}
+
+impl RequestLayer for TenantLayer {
+    fn before(&mut self, cx: &RequestContext, request: &ClientRequest) -> Option<ClientResponse> {
+        let mut tenants = self.tenants.lock().unwrap();
+        if !cx.active {
+            return None;
+        }
+
+        let acls = self.acls.lock().unwrap();
+        if !tenants.is_enabled() && !matches!(request, ClientRequest::Authenticate(..)) {
+            let (account_id, _) = accessed(request)
+                .into_iter()
+                .find(|(account_id, access)| acls.allows(account_id, None, *access) == Some(false))?;
+            info!("Refusing {}: {account_id} has an owner", cx.tx_id);
+            return Some(ClientResponse::AbortedUnauthorized);
+        }
+
+        let tenant = match (&self.tenant, request) {
+            (None, ClientRequest::Authenticate(name, secret)) => {
+                if let Err(resp) = tenants.authenticate(name, secret) {
+                    info!("Refusing {}: failed to authenticate as tenant {name}", cx.tx_id);
+                    return Some(resp);
+                }
+
+                self.tenant = Some(name.clone());
+                return None;
+            },
+            (None, ClientRequest::Abort | ClientRequest::AbortWithReason(_) | ClientRequest::Hello(_)) => return None,
+            (None, _) => {
+                info!("Refusing {}: the transaction has not authenticated", cx.tx_id);
+                return Some(ClientResponse::AbortedUnauthorized);
+            },
+            (Some(tenant), _) => tenant
+        };
+
+        match request {
+            ClientRequest::Admin(_) if tenants.may_admin(tenant) => return None,
+            ClientRequest::Admin(_) | ClientRequest::Authenticate(..) => {
+                info!("Refusing {}: tenant {tenant} may not make {request:?}", cx.tx_id);
+                tenants.deny(tenant);
+                return Some(ClientResponse::AbortedUnauthorized);
+            },
+            _ => ()
+        }
+
+        let accounts = accessed(request);
+        if accounts.is_empty() {
+            return None;
+        }
+
+        // An account's access control list, if it has one, decides instead of
+        // its name
+        let refused = accounts.into_iter().find(|(account_id, access)| {
+            !acls.allows(account_id, Some(tenant), *access).unwrap_or_else(|| in_namespace(account_id, tenant))
+        });
+        if let Some((account_id, access)) = refused {
+            info!("Refusing {}: tenant {tenant} may not {access:?} {account_id}", cx.tx_id);
+            tenants.deny(tenant);
+            return Some(ClientResponse::AbortedUnauthorized);
+        }
+
+        if !tenants.charge(tenant) {
+            info!("Refusing {}: tenant {tenant} is over its request rate", cx.tx_id);
+            return Some(ClientResponse::AbortedTenantLimit(tenant.clone()));
+        }
+
+        None
+    }
+
+    fn after(&mut self, _cx: &RequestContext, response: &ClientResponse) {
+        match response {
+            response if response.is_committed() => self.finish(true),
+            response if response.is_err() => self.finish(false),
+            _ => ()
+        }
+    }
+}
+
+impl Drop for TenantLayer {
+    fn drop(&mut self) {
+        self.finish(false);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sharding::fixture::tx_at;
+    use tx_common::admin::AdminRequest;
+    use tx_proto::BalanceDiff;
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn test_namespaces() {
+        assert!(in_namespace("A.acme/x", "acme"));
+        assert!(!in_namespace("A.acme", "acme"));
+        assert!(!in_namespace("A.acmex/x", "acme"));
+        assert!(!in_namespace("acme/x", "acme"));
+        assert!(!in_namespace("A.other/acme/x", "acme"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tenant_limits() {
+        let tenants = SharedTenants::default();
+        let acls = SharedAcls::default();
+        let policy = TenantPolicy { secret: "s3cret".into(), requests_per_sec: Some(2), max_active: Some(1), admin: false };
+        tenants.lock().unwrap().add("acme".into(), policy);
+        let cx = RequestContext { tx_id: tx_at(100), active: true };
+        let auth = |secret: &str| ClientRequest::Authenticate("acme".into(), secret.into());
+        let write = |account_id: &str| ClientRequest::WriteBalance(account_id.into(), BalanceDiff(1));
+
+        let mut unauthenticated = TenantLayer::new(tenants.clone(), acls.clone());
+        assert!(matches!(unauthenticated.before(&cx, &write("A.acme/x")), Some(ClientResponse::AbortedUnauthorized)));
+        assert!(matches!(unauthenticated.before(&cx, &auth("wrong")), Some(ClientResponse::AbortedUnauthorized)));
+
+        let mut first = TenantLayer::new(tenants.clone(), acls.clone());
+        assert!(first.before(&cx, &auth("s3cret")).is_none());
+        let mut second = TenantLayer::new(tenants.clone(), acls.clone());
+        assert!(matches!(second.before(&cx, &auth("s3cret")), Some(ClientResponse::AbortedTenantLimit(_))));
+
+        assert!(first.before(&cx, &write("A.acme/x")).is_none());
+        assert!(first.before(&cx, &ClientRequest::Label("web".into())).is_none());
+        assert!(first.before(&cx, &write("A.acme/y")).is_none());
+        assert!(matches!(first.before(&cx, &write("A.acme/z")), Some(ClientResponse::AbortedTenantLimit(_))));
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(first.before(&cx, &write("A.acme/z")).is_none());
+        assert!(matches!(first.before(&cx, &write("A.other/x")), Some(ClientResponse::AbortedUnauthorized)));
+        assert!(matches!(first.before(&cx, &ClientRequest::Admin(AdminRequest::Status)), Some(ClientResponse::AbortedUnauthorized)));
+        first.after(&cx, &ClientResponse::AbortedUnauthorized);
+        drop(first);
+
+        let stats = tenants.lock().unwrap().stats();
+        let counts: Vec<_> = stats.iter().map(|t| (t.tenant.as_str(), t.active, t.requests, t.committed, t.aborted, t.throttled, t.denied)).collect();
+        assert_eq!(counts, vec![("acme", 0, 4, 0, 1, 2, 3)]);
+    }
+}
@@ -0,0 +1,33 @@
+use crate::sharding::Verifier;
+use tx_common::admin::{VerificationStatus, Violation};
+use std::{sync::{Arc, Mutex}, time::Duration};
+use super::AtomicShard;
+use log::error;
+
+/// How often the invariants of a shard are checked by default.
+pub static VERIFY_INTERVAL: Duration = Duration::from_secs(10);
+/// The number of violations kept for admin requests to report.
+pub static MAX_RECENT_VIOLATIONS: usize = 64;
+
+pub(super) type SharedVerification = Arc<Mutex<VerificationStatus>>;
+
+/// Checks the invariants of `shard` every `interval` for as long as the server
+/// runs, logging every violation and recording it in `status`.
+pub(super) async fn run(shard: AtomicShard, status: SharedVerification, interval: Duration) {
+    let mut verifier = Verifier::default();
+    loop {
+        tokio::time::sleep(interval).await;
+        let violations = verifier.check(shard.object_states().await);
+
+        let mut status = status.lock().unwrap();
+        status.passes += 1;
+        status.violations_found += violations.len() as u64;
+        for (account_id, description) in violations {
+            error!("Invariant violated on {account_id}: {description}");
+            if status.recent.len() == MAX_RECENT_VIOLATIONS {
+                status.recent.remove(0);
+            }
+            status.recent.push(Violation { account_id, description });
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use tokio::{select, sync::mpsc::{unbounded_channel, UnboundedSender}};
+use std::{future::Future, panic::AssertUnwindSafe};
+use log::error;
+
+/// A fixed set of tasks that run the node's client handlers. Each worker
+/// drives every handler it is given on its own task, so the number of tasks
+/// stays the same however many clients are connected, and the handlers are
+/// spread over the workers in turn.
+pub(super) struct WorkerPool {
+    workers: Vec<UnboundedSender<BoxFuture<'static, ()>>>,
+    next: usize
+}
+
+impl WorkerPool {
+    /// Starts `workers` worker tasks, at least one. They stop once the pool
+    /// is dropped and every handler they were given finished.
+    pub(super) fn new(workers: usize) -> Self {
+        let workers = (0..workers.max(1))
+            .map(|worker| {
+                let (handler_snd, mut handlers_rcv) = unbounded_channel::<BoxFuture<'static, ()>>();
+                crate::task::spawn(format_args!("client worker {worker}"), async move {
+                    let mut running = FuturesUnordered::new();
+                    loop {
+                        select! {
+                            handler = handlers_rcv.recv() => match handler {
+                                Some(handler) => running.push(handler),
+                                None => break
+                            },
+                            Some(()) = running.next(), if !running.is_empty() => {}
+                        }
+                    }
+                    while running.next().await.is_some() {}
+                });
+                handler_snd
+            })
+            .collect();
+
+        Self { workers, next: 0 }
+    }
+
+    /// The number of workers the node starts by default: one per core.
+    pub(super) fn default_size() -> usize {
+        std::thread::available_parallelism().map_or(1, |cores| cores.get())
+    }
+
+    /// Runs `handler` on the next worker. A handler that panics is logged
+    /// and dropped, leaving the other handlers of its worker running.
+    pub(super) fn run(&mut self, name: String, handler: impl Future<Output = ()> + Send + 'static) {
+        let handler = AssertUnwindSafe(handler)
+            .catch_unwind()
+            .map(move |result| if result.is_err() {
+                error!("Client handler {name} panicked");
+            })
+            .boxed();
+        if self.workers[self.next].send(handler).is_err() {
+            error!("Client worker {} stopped", self.next);
+        }
+        self.next = (self.next + 1) % self.workers.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::sync::{oneshot, watch};
+
+    #[tokio::test]
+    async fn test_workers_run_more_handlers_than_there_are_workers() {
+        let mut pool = WorkerPool::new(2);
+        let (release_snd, release_rcv) = watch::channel(false);
+        let (finished_snd, mut finished_rcv) = unbounded_channel();
+
+        // Every handler waits until all of them started, so they only finish
+        // if the two workers run them all at once
+        let mut started = vec![];
+        for handler in 0..16 {
+            let (started_snd, started_rcv) = oneshot::channel();
+            started.push(started_rcv);
+            let (mut release_rcv, finished_snd) = (release_rcv.clone(), finished_snd.clone());
+            pool.run(format!("{handler}"), async move {
+                let _ = started_snd.send(());
+                let _ = release_rcv.wait_for(|released| *released).await;
+                let _ = finished_snd.send(handler);
+            });
+        }
+        for started_rcv in started {
+            started_rcv.await.unwrap();
+        }
+
+        release_snd.send(true).unwrap();
+        for _ in 0..16 {
+            finished_rcv.recv().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler_leaves_its_worker_running() {
+        let mut pool = WorkerPool::new(1);
+        pool.run("panics".into(), async { panic!("handler failed") });
+
+        let (ran_snd, ran_rcv) = oneshot::channel();
+        pool.run("runs".into(), async move { let _ = ran_snd.send(()); });
+        ran_rcv.await.unwrap();
+    }
+}
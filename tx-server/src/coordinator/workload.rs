@@ -0,0 +1,101 @@
+use tx_proto::{ClientRequest, ClientResponse};
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path, sync::mpsc::{self, Sender}};
+use super::layer::{RequestContext, RequestLayer};
+use log::error;
+
+/// Records the reads, writes, commits and aborts of the transactions a node
+/// coordinates, in the order they reach it, as a trace the `what_if` example
+/// replays: one line per request, holding the transaction's id as
+/// `<timestamp>@<coordinator>` followed by the tx-client command. Requests
+/// the trace has no command for, such as swaps, are left out. Lines are
+/// written by a thread of their own, so that client handlers never wait on
+/// the file.
+#[derive(Clone)]
+pub(super) struct WorkloadTrace(Sender<String>);
+
+impl WorkloadTrace {
+    pub(super) fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let (lines, incoming) = mpsc::channel::<String>();
+        std::thread::spawn(move || {
+            while let Ok(line) = incoming.recv() {
+                let written = std::iter::once(line)
+                    .chain(incoming.try_iter())
+                    .try_for_each(|line| writeln!(file, "{line}"))
+                    .and_then(|_| file.flush());
+                if let Err(e) = written {
+                    error!("Failed to record the workload trace: {e}");
+                    return;
+                }
+            }
+        });
+
+        Ok(Self(lines))
+    }
+}
+
+/// The command `what_if` replays a request as, if any.
+fn command(request: &ClientRequest) -> Option<String> {
+    match request {
+        ClientRequest::WriteBalance(account_id, diff) | ClientRequest::WriteBalanceWithTtl(account_id, diff, _) if diff.0 < 0 =>
+            Some(format!("WITHDRAW {account_id} {}", -diff.0)),
+        ClientRequest::WriteBalance(account_id, diff) | ClientRequest::WriteBalanceWithTtl(account_id, diff, _) =>
+            Some(format!("DEPOSIT {account_id} {}", diff.0)),
+        ClientRequest::Increment(account_id, diff) => Some(format!("INCREMENT {account_id} {}", diff.0)),
+        ClientRequest::ReadBalance(account_id) => Some(format!("BALANCE {account_id}")),
+        ClientRequest::Commit => Some("COMMIT".into()),
+        ClientRequest::Abort | ClientRequest::AbortWithReason(_) => Some("ABORT".into()),
+        ClientRequest::Deadline(_, request) => command(request),
+        _ => None
+    }
+}
+
+/// Adds the requests of one client connection to the trace.
+pub(super) struct WorkloadLayer(pub(super) WorkloadTrace);
+
+impl RequestLayer for WorkloadLayer {
+    fn before(&mut self, cx: &RequestContext, request: &ClientRequest) -> Option<ClientResponse> {
+        if let Some(command) = command(request) {
+            // The writer only stops if the file failed, which it logged
+            let _ = self.0.0.send(format!("{}@{} {command}", cx.tx_id.timestamp(), cx.tx_id.coordinator().0));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sharding::TransactionId;
+    use tx_common::config::NodeId;
+    use tx_proto::BalanceDiff;
+    use std::{fs, time::Duration};
+
+    #[test]
+    fn test_requests_recorded_as_commands() {
+        let path = std::env::temp_dir().join(format!("tx-server-workload-{}", std::process::id()));
+        let mut layer = WorkloadLayer(WorkloadTrace::create(&path).unwrap());
+        let cx = RequestContext { tx_id: TransactionId::at(7, NodeId(1)), active: true };
+        let requests = [
+            ClientRequest::WriteBalance("A.x".into(), BalanceDiff(-5)),
+            ClientRequest::Deadline(Duration::from_secs(1), Box::new(ClientRequest::ReadBalance("B.y".into()))),
+            ClientRequest::Swap("A.x".into(), "B.y".into()),
+            ClientRequest::Commit
+        ];
+        for request in &requests {
+            assert!(layer.before(&cx, request).is_none());
+        }
+        drop(layer);
+
+        // The writer thread flushes the lines once it is done with them
+        let expected = "7@1 WITHDRAW A.x 5\n7@1 BALANCE B.y\n7@1 COMMIT\n";
+        for _ in 0..100 {
+            if fs::read_to_string(&path).unwrap() == expected {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,10 +1,16 @@
+#[cfg(feature = "server")]
 pub mod coordinator;
 pub mod sharding;
+mod task;
+#[cfg(feature = "server")]
 pub mod pool;
+#[cfg(feature = "server")]
+pub mod testing;
 
-use sharding::Checkable;
-pub use tx_common::BalanceDiff;
+use sharding::{Checkable, Incrementable};
+pub use tx_proto::BalanceDiff;
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct NegativeBalance(tx_common::Amount);
 
@@ -32,4 +38,14 @@ impl Checkable for tx_common::Amount {
             Err(NegativeBalance(*self))
         }
     }
-}
\ No newline at end of file
+}
+
+impl Incrementable for tx_common::Amount {
+    fn incremented(&self, by: &Self) -> Self {
+        self + by
+    }
+
+    fn decreases(&self) -> bool {
+        *self < 0
+    }
+}
@@ -1,42 +1,428 @@
-use tx_common::config::{self, NodeId, Config};
-use tx_server::coordinator::Server;
+use tx_common::{config::{self, NodeId, Config}, admin::{Access, AccountAcl, ConcurrencyMode}, stream::SocketOptions};
+use std::time::Duration;
+use tx_server::pool::TreeBroadcast;
+use tx_server::coordinator::{Server, TenantPolicy, TransactionLifetime, TransactionQuota, MetricsFormat, METRICS_INTERVAL, SNAPSHOT_INTERVAL, GOSSIP_INTERVAL, PAUSE_LEASE, TimestampMode, ExecutionMode, CommitReporter, StdoutReporter, LogReporter, FileReporter, JsonLinesReporter, SilentReporter};
+#[cfg(feature = "kafka")]
+use tx_server::coordinator::KafkaReporter;
 
-pub fn parse_config(path: &str, given_node_name: char) -> Result<Config, String> {
+pub fn parse_config(path: &str, given_node_name: &str) -> Result<(Config, NodeId), String> {
     match config::parse_config(path) {
-        Ok(c) => {
-            if c.contains_key(&given_node_name) {
-                Ok(c)
-            } else {
-                return Err(format!("Bad config: node identifier is not listed in config file"));
-            }
+        Ok(c) => match config::node_named(&c, given_node_name) {
+            Some(node_id) => Ok((c, node_id)),
+            None => Err("Bad config: node identifier is not listed in config file".into())
         },
-        Err(e) => return Err(e)
+        Err(e) => Err(e)
+    }
+}
+
+/// Parses a tenant given as `<tenant>:<secret>[:<requests per sec>[:<max active>[:admin]]]`,
+/// where either limit may be `-` for none.
+fn parse_tenant(tenant: &str) -> Option<(String, TenantPolicy)> {
+    fn limit<T: std::str::FromStr>(field: Option<&str>) -> Option<Option<T>> {
+        match field {
+            None | Some("-") => Some(None),
+            Some(limit) => limit.parse().ok().map(Some)
+        }
+    }
+
+    let mut fields = tenant.split(':');
+    let (name, secret) = (fields.next()?, fields.next()?);
+    let requests_per_sec = limit(fields.next())?;
+    let max_active = limit(fields.next())?;
+    let admin = match fields.next() {
+        None => false,
+        Some("admin") => true,
+        Some(_) => return None
+    };
+
+    if name.is_empty() || secret.is_empty() || fields.next().is_some() {
+        return None;
+    }
+
+    Some((name.into(), TenantPolicy { secret: secret.into(), requests_per_sec, max_active, admin }))
+}
+
+/// Parses an access control list given as `<account>:<owner>[:<tenant>=<r|rw>...]`.
+fn parse_acl(acl: &str) -> Option<AccountAcl> {
+    let mut fields = acl.split(':');
+    let (account_id, owner) = (fields.next()?, fields.next()?);
+    let grants = fields
+        .map(|grant| match grant.split_once('=')? {
+            (tenant, "r") if !tenant.is_empty() => Some((tenant.into(), Access::Read)),
+            (tenant, "rw") if !tenant.is_empty() => Some((tenant.into(), Access::ReadWrite)),
+            _ => None
+        })
+        .collect::<Option<_>>()?;
+
+    if account_id.is_empty() || owner.is_empty() {
+        return None;
     }
+
+    Some(AccountAcl { account_id: account_id.into(), owner: owner.into(), grants })
 }
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
+    #[cfg(feature = "console")]
+    console_subscriber::init();
     let args: Vec<_> = std::env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <node identifier> <path to config file>", args[0]);
-        std::process::exit(1);
-    } else if args[1].len() != 1 {
-        eprintln!("{}: Node identifier must be a single character", args[0]);
+    if args.len() != 3 && args.len() != 4 {
+        eprintln!("Usage: {} <node identifier> <path to config file> [min peers]", args[0]);
         std::process::exit(1);
     }
 
-    let node_id: NodeId = args[1].chars().nth(0).unwrap();
-    let config: Config = match parse_config(&args[2], node_id) {
-        Ok(config) => config,
+    let (config, node_id) = match parse_config(&args[2], &args[1]) {
+        Ok(parsed) => parsed,
         Err(e) => {
             eprintln!("{}: {}", args[0], e);
             std::process::exit(1);
         }
     };
 
-    Server::start(node_id, config, 60)
+    let min_peers = match args.get(3).map(|n| n.parse::<usize>()) {
+        Some(Ok(min_peers)) => min_peers,
+        Some(Err(e)) => {
+            eprintln!("{}: Invalid minimum number of peers: {e}", args[0]);
+            std::process::exit(1);
+        },
+        None => config.len() - 1
+    };
+
+    let timestamp_mode = match std::env::var("TX_TIMESTAMP_MODE").as_deref() {
+        Ok("first-request") | Err(_) => TimestampMode::FirstRequest,
+        Ok("first-operation") => TimestampMode::FirstOperation,
+        Ok(mode) => {
+            eprintln!("{}: Invalid timestamp mode {mode}: expected first-request or first-operation", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    let execution_mode = match std::env::var("TX_EXECUTION_MODE").as_deref() {
+        Ok("interactive") | Err(_) => ExecutionMode::Interactive,
+        Ok("deterministic") => ExecutionMode::Deterministic,
+        Ok(mode) => {
+            eprintln!("{}: Invalid execution mode {mode}: expected interactive or deterministic", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    let commit_epoch = match std::env::var("TX_COMMIT_EPOCH_MS").as_deref() {
+        Ok("off") | Err(_) => None,
+        Ok(ms) => match ms.parse::<u64>() {
+            Ok(ms) if ms > 0 => Some(Duration::from_millis(ms)),
+            _ => {
+                eprintln!("{}: Invalid commit epoch {ms}: expected a positive number of milliseconds or off", args[0]);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    // `<peers>[:<fanout>]`: send prepares and aborts for at least that many
+    // peers along a spanning tree
+    let tree_broadcast = std::env::var("TX_TREE_BROADCAST").ok().map(|tree| {
+        let (threshold, fanout) = tree.split_once(':').map_or((tree.as_str(), None), |(threshold, fanout)| (threshold, Some(fanout)));
+        match (threshold.parse::<usize>(), fanout.map(|fanout| fanout.parse::<usize>())) {
+            (Ok(threshold), None) => TreeBroadcast::new(threshold),
+            (Ok(threshold), Some(Ok(fanout))) if fanout > 0 => TreeBroadcast { threshold, fanout },
+            _ => {
+                eprintln!("{}: Invalid tree broadcast {tree}: expected <peers>[:<fanout>] with a positive fanout", args[0]);
+                std::process::exit(1);
+            }
+        }
+    });
+
+    let gossip_interval = match std::env::var("TX_GOSSIP_MS").as_deref() {
+        Ok("off") | Err(_) => None,
+        Ok("on") => Some(GOSSIP_INTERVAL),
+        Ok(ms) => match ms.parse::<u64>() {
+            Ok(ms) if ms > 0 => Some(Duration::from_millis(ms)),
+            _ => {
+                eprintln!("{}: Invalid gossip interval {ms}: expected a positive number of milliseconds, on or off", args[0]);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let shard_counting = match std::env::var("TX_SHARD_COUNTING").as_deref() {
+        Ok("on") | Err(_) => true,
+        Ok("off") => false,
+        Ok(counting) => {
+            eprintln!("{}: Invalid shard counting {counting}: expected on or off", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    let prepare_ordering = match std::env::var("TX_PREPARE_ORDER").as_deref() {
+        Ok("timestamp") | Err(_) => true,
+        Ok("arrival") => false,
+        Ok(order) => {
+            eprintln!("{}: Invalid prepare order {order}: expected timestamp or arrival", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    let degraded_time_box = match std::env::var("TX_DEGRADED_TIME_BOX_MS").as_deref() {
+        Ok("off") | Err(_) => None,
+        Ok(ms) => match ms.parse::<u64>() {
+            Ok(ms) => Some(Duration::from_millis(ms)),
+            _ => {
+                eprintln!("{}: Invalid degraded time box {ms}: expected a number of milliseconds or off", args[0]);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    // Lifetimes and leases in milliseconds, or none if unset
+    let lifetime_ms = |var: &str| match std::env::var(var).as_deref() {
+        Err(_) => None,
+        Ok(ms) => match ms.parse::<u64>() {
+            Ok(ms) if ms > 0 => Some(Duration::from_millis(ms)),
+            _ => {
+                eprintln!("{}: Invalid {var} {ms}: expected a positive number of milliseconds", args[0]);
+                std::process::exit(1);
+            }
+        }
+    };
+    let lifetime = TransactionLifetime { default: lifetime_ms("TX_LIFETIME_MS"), max: lifetime_ms("TX_MAX_LIFETIME_MS") };
+    let pause_lease = lifetime_ms("TX_PAUSE_LEASE_MS").unwrap_or(PAUSE_LEASE);
+
+    // `<ops>:<bytes>`, where either may be `-` to keep its default
+    let quota = std::env::var("TX_TRANSACTION_QUOTA").ok().map(|quota| {
+        let limit = |field: &str, default: usize| match field {
+            "-" => Some(default),
+            limit => limit.parse().ok()
+        };
+        let defaults = TransactionQuota::default();
+        match quota.split_once(':').map(|(ops, bytes)| (limit(ops, defaults.max_ops), limit(bytes, defaults.max_write_bytes))) {
+            Some((Some(max_ops), Some(max_write_bytes))) => TransactionQuota { max_ops, max_write_bytes },
+            _ => {
+                eprintln!("{}: Invalid transaction quota {quota}: expected <ops>:<bytes>, either of which may be -", args[0]);
+                std::process::exit(1);
+            }
+        }
+    }).unwrap_or_default();
+
+    // Positive counts, or the default if unset
+    let count = |var: &str| match std::env::var(var).as_deref() {
+        Err(_) => None,
+        Ok(count) => match count.parse::<usize>() {
+            Ok(count) if count > 0 => Some(count),
+            _ => {
+                eprintln!("{}: Invalid {var} {count}: expected a positive number", args[0]);
+                std::process::exit(1);
+            }
+        }
+    };
+    let client_workers = count("TX_CLIENT_WORKERS");
+    let max_remote_operations = count("TX_MAX_REMOTE_OPERATIONS");
+
+    let concurrency_mode = match std::env::var("TX_CONCURRENCY_MODE").as_deref() {
+        Ok("adaptive") | Err(_) => None,
+        Ok("timestamp-ordering") => Some(ConcurrencyMode::TimestampOrdering),
+        Ok("wound-wait") => Some(ConcurrencyMode::WoundWait),
+        Ok(mode) => {
+            eprintln!("{}: Invalid concurrency mode {mode}: expected adaptive, timestamp-ordering or wound-wait", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    let reporter: Box<dyn CommitReporter> = match std::env::var("TX_COMMIT_REPORT").as_deref() {
+        Ok("stdout") | Err(_) => Box::new(StdoutReporter),
+        Ok("log") => Box::new(LogReporter),
+        Ok("silent") => Box::new(SilentReporter),
+        Ok("json") => Box::new(JsonLinesReporter::new(std::io::stdout())),
+        Ok(report) => {
+            let created = match report.split_once(':') {
+                Some(("file", path)) => FileReporter::create(path).map(|r| Box::new(r) as Box<dyn CommitReporter>),
+                Some(("json", path)) => JsonLinesReporter::create(path).map(|r| Box::new(r) as Box<dyn CommitReporter>),
+                #[cfg(feature = "kafka")]
+                Some(("kafka", target)) => match target.rsplit_once('/') {
+                    Some((brokers, topic)) if !brokers.is_empty() && !topic.is_empty() => 
+                        KafkaReporter::new(brokers, topic).map(|r| Box::new(r) as Box<dyn CommitReporter>).map_err(std::io::Error::other),
+                    _ => {
+                        eprintln!("{}: Invalid commit report {report}: expected kafka:<brokers>/<topic>", args[0]);
+                        std::process::exit(1);
+                    }
+                },
+                #[cfg(not(feature = "kafka"))]
+                Some(("kafka", _)) => {
+                    eprintln!("{}: Invalid commit report {report}: tx-server was built without the kafka feature", args[0]);
+                    std::process::exit(1);
+                },
+                _ => {
+                    eprintln!("{}: Invalid commit report {report}: expected stdout, log, silent, json, file:<path>, json:<path>, or kafka:<brokers>/<topic>", args[0]);
+                    std::process::exit(1);
+                }
+            };
+
+            created.unwrap_or_else(|e| {
+                eprintln!("{}: Failed to open {report}: {e}", args[0]);
+                std::process::exit(1);
+            })
+        }
+    };
+
+    // Comma-separated `<label>=<max active>` pairs
+    let label_quotas: Vec<(String, usize)> = match std::env::var("TX_LABEL_QUOTAS") {
+        Ok(quotas) => quotas
+            .split(',')
+            .filter(|quota| !quota.is_empty())
+            .map(|quota| match quota.split_once('=').map(|(label, max)| (label, max.parse())) {
+                Some((label, Ok(max))) => (label.to_string(), max),
+                _ => {
+                    eprintln!("{}: Invalid label quota {quota}: expected <label>=<max active>", args[0]);
+                    std::process::exit(1);
+                }
+            })
+            .collect(),
+        Err(_) => vec![]
+    };
+
+    // Comma-separated tenants in the form `parse_tenant` takes
+    let tenants: Vec<(String, TenantPolicy)> = match std::env::var("TX_TENANTS") {
+        Ok(tenants) => tenants
+            .split(',')
+            .filter(|tenant| !tenant.is_empty())
+            .map(|tenant| parse_tenant(tenant).unwrap_or_else(|| {
+                eprintln!("{}: Invalid tenant {tenant}: expected <tenant>:<secret>[:<requests per sec>[:<max active>[:admin]]]", args[0]);
+                std::process::exit(1);
+            }))
+            .collect(),
+        Err(_) => vec![]
+    };
+
+    // Comma-separated access control lists in the form `parse_acl` takes
+    let acls: Vec<AccountAcl> = match std::env::var("TX_ACLS") {
+        Ok(acls) => acls
+            .split(',')
+            .filter(|acl| !acl.is_empty())
+            .map(|acl| parse_acl(acl).unwrap_or_else(|| {
+                eprintln!("{}: Invalid ACL {acl}: expected <account>:<owner>[:<tenant>=<r|rw>...]", args[0]);
+                std::process::exit(1);
+            }))
+            .collect(),
+        Err(_) => vec![]
+    };
+
+    // `json` or `csv`, optionally followed by `:<seconds>` between snapshots
+    let metrics_dump = std::env::var("TX_METRICS_DUMP").ok().map(|dump| {
+        let (format, interval) = dump.split_once(':').map_or((dump.as_str(), None), |(format, secs)| (format, Some(secs)));
+        let format = match format {
+            "json" => MetricsFormat::JsonLines,
+            "csv" => MetricsFormat::Csv,
+            _ => {
+                eprintln!("{}: Invalid metrics dump {dump}: expected json or csv, optionally followed by :<seconds>", args[0]);
+                std::process::exit(1);
+            }
+        };
+        let interval = match interval.map(|secs| secs.parse::<u64>()) {
+            None => METRICS_INTERVAL,
+            Some(Ok(secs)) if secs > 0 => Duration::from_secs(secs),
+            Some(_) => {
+                eprintln!("{}: Invalid metrics dump {dump}: expected a positive number of seconds", args[0]);
+                std::process::exit(1);
+            }
+        };
+        (format, interval)
+    });
+
+    let snapshot_interval = match std::env::var("TX_SNAPSHOT_SECS").as_deref() {
+        Err(_) => Some(SNAPSHOT_INTERVAL),
+        Ok("off") => None,
+        Ok(secs) => match secs.parse::<u64>() {
+            Ok(secs) if secs > 0 => Some(Duration::from_secs(secs)),
+            _ => {
+                eprintln!("{}: Invalid snapshot interval {secs}: expected a positive number of seconds or off", args[0]);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let socket_options = SocketOptions::from_env().unwrap_or_else(|e| {
+        eprintln!("{}: {e}", args[0]);
+        std::process::exit(1);
+    });
+
+    let state_dir = std::env::var("TX_STATE_DIR").unwrap_or_else(|_| ".".into());
+    let id_file = std::path::Path::new(&state_dir).join(format!("{}.txid", args[1]));
+    let server = Server::start_with_min_peers(node_id, config, 60, min_peers)
         .await
-        .serve()
-        .await;
+        .with_socket_options(socket_options)
+        .with_timestamp_mode(timestamp_mode)
+        .with_execution_mode(execution_mode)
+        .with_commit_epoch(commit_epoch)
+        .with_tree_broadcast(tree_broadcast)
+        .with_gossip(gossip_interval)
+        .with_shard_counting(shard_counting)
+        .with_prepare_ordering(prepare_ordering)
+        .with_degraded_time_box(degraded_time_box)
+        .with_transaction_lifetime(lifetime)
+        .with_transaction_quota(quota)
+        .with_pause_lease(pause_lease)
+        .with_commit_reporter(reporter)
+        .with_id_file(&id_file);
+    let mut server = match server {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{}: Failed to open {}: {e}", args[0], id_file.display());
+            std::process::exit(1);
+        }
+    };
+
+    if let Some((format, interval)) = metrics_dump {
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let path = std::path::Path::new(&state_dir).join(format!("{}-metrics-{started}.{}", args[1], format.extension()));
+        server = server.with_metrics_dump(&path, interval, format).unwrap_or_else(|e| {
+            eprintln!("{}: Failed to create {}: {e}", args[0], path.display());
+            std::process::exit(1);
+        });
+    }
+
+    if let Some(interval) = snapshot_interval {
+        let path = std::path::Path::new(&state_dir).join(format!("{}.snapshot", args[1]));
+        server = server.with_snapshots(&path, interval).await.unwrap_or_else(|e| {
+            eprintln!("{}: Failed to restore {}: {e}", args[0], path.display());
+            std::process::exit(1);
+        });
+    }
+
+    if let Some(workers) = client_workers {
+        server = server.with_client_workers(workers);
+    }
+    if let Some(max_operations) = max_remote_operations {
+        server = server.with_max_remote_operations(max_operations);
+    }
+
+    if let Some(mode) = concurrency_mode {
+        server = server.with_concurrency_mode(mode);
+    }
+
+    for (label, max_active) in label_quotas {
+        server = server.with_label_quota(label, max_active);
+    }
+
+    for (name, policy) in tenants {
+        server = server.with_tenant(name, policy);
+    }
+    for acl in acls {
+        server = server.with_acl(acl);
+    }
+    let acl_file = std::path::Path::new(&state_dir).join(format!("{}.acls", args[1]));
+    server = server.with_acl_file(&acl_file).unwrap_or_else(|e| {
+        eprintln!("{}: Failed to open {}: {e}", args[0], acl_file.display());
+        std::process::exit(1);
+    });
+
+    if let Ok(path) = std::env::var("TX_WORKLOAD_TRACE") {
+        server = server.with_workload_trace(&path).unwrap_or_else(|e| {
+            eprintln!("{}: Failed to create {path}: {e}", args[0]);
+            std::process::exit(1);
+        });
+    }
+
+    server.serve().await;
 }
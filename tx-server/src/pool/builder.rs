@@ -1,50 +1,95 @@
-use super::server::{member_loop, RemoteServerData, RemoteServerHandle, ServerStateMessage};
+use super::server::{RemoteServerHandle, ServerStateMessage};
 use tokio::{
     sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel}, select,
-    io, time::{timeout, error::Elapsed}, net::{TcpStream, TcpListener}
+    io, time::{timeout, error::Elapsed, Duration}, net::{TcpStream, TcpListener}
 };
-use tx_common::{config::{Config, NodeId}, stream::MessageStream};
+use tx_common::{config::{Config, NodeId, ShardMap}, stream::{MessageStream, SocketOptions}};
 use serde::{Serialize, de::DeserializeOwned, Deserialize};
 use tokio_retry::{Retry, strategy::FixedInterval};
-use std::{net::SocketAddr, fmt, time::Duration};
+use std::{net::SocketAddr, fmt};
 use super::{ConnectionPool, ServerGroup};
 use log::{trace, error};
 
 pub struct ConnectionPoolBuilder<M> {
-    pub listener: TcpListener, 
+    pub listener: Option<TcpListener>, 
     pub group: ServerGroup<M>,
     pub node_id: NodeId,
     pub from_members: UnboundedReceiver<ServerStateMessage<M>>,
     pub client_snd_handle: UnboundedSender<ServerStateMessage<M>>,
     timeout_secs: Option<u64>,
+    min_peers: Option<usize>,
+    socket_options: SocketOptions,
+    epoch: u64,
     config: Config
 }
 
 pub static CONNECTION_POOL_INIT_TIMEOUT_SECS: u64 = 60;
 pub static CONNECTION_RETRY_DELAY_MS: u64 = 100;
+pub static HANDSHAKE_TIMEOUT_SECS: u64 = 5;
 
+/// Leads every handshake. It is not the index of any `ClientRequest` variant, 
+/// so a handshake never decodes as a client request.
+const HANDSHAKE_TAG: u32 = u32::MAX;
+
+/// The first message a node sends on a connection it opens to a peer. Since
+/// it never decodes as a `ClientRequest`, a server can tell a peer joining 
+/// late apart from a client. It carries the sender's routing epoch, so that
+/// nodes that would route accounts differently never exchange requests.
 #[derive(Debug, Deserialize, Serialize)]
-struct Handshake(NodeId);
+pub struct Handshake(u32, NodeId, u64);
+
+impl Handshake {
+    pub fn new(node_id: NodeId, epoch: u64) -> Self {
+        Self(HANDSHAKE_TAG, node_id, epoch)
+    }
+
+    /// The node that sent the handshake, if this is a handshake from a node
+    /// that routes accounts with the given epoch.
+    pub fn node_id(&self, epoch: u64) -> Option<NodeId> {
+        match self {
+            Self(HANDSHAKE_TAG, node_id, sender_epoch) if *sender_epoch == epoch => Some(*node_id),
+            Self(HANDSHAKE_TAG, node_id, sender_epoch) => {
+                error!("Node {node_id} routes with epoch {sender_epoch:x}, expected {epoch:x}");
+                None
+            },
+            _ => None
+        }
+    }
+}
 
 impl<M> ConnectionPoolBuilder<M> 
 where
     M: fmt::Debug + DeserializeOwned + Serialize
 {
     pub async fn new(config: Config, node_id: NodeId) -> Result<Self, io::Error> {
-        let (client_snd_handle, from_clients) = unbounded_channel();
         let node_config = config.get(&node_id).unwrap();
         let bind_addr: SocketAddr = ([0, 0, 0, 0], node_config.port).into();
         let listener = TcpListener::bind(bind_addr).await?;
 
-        Ok(Self {
+        Ok(Self::over(Some(listener), config, node_id))
+    }
+
+    /// A pool for a node whose peers run in the same process, which listens
+    /// on no port: the stream to every peer is handed to `admit_member` 
+    /// instead of dialed or accepted.
+    pub fn in_memory(config: Config, node_id: NodeId) -> Self {
+        Self::over(None, config, node_id)
+    }
+
+    fn over(listener: Option<TcpListener>, config: Config, node_id: NodeId) -> Self {
+        let (client_snd_handle, from_clients) = unbounded_channel();
+        Self {
             listener,
             group: Default::default(),
             node_id,
             from_members: from_clients,
             client_snd_handle,
             timeout_secs: None,
+            min_peers: None,
+            socket_options: SocketOptions::default(),
+            epoch: ShardMap::new(&config).epoch(),
             config
-        })
+        }
     }
 
     pub fn with_timeout(mut self, seconds: u64) -> Self {
@@ -52,17 +97,35 @@ where
         self
     }
 
-    async fn connect_to_node(this_node: NodeId, node_id: NodeId, host: String, port: u16, stream_snd: UnboundedSender<(MessageStream, NodeId)>) {
+    /// Finish connecting once this many peers have joined instead of waiting 
+    /// for all of them. Connections to the remaining peers continue in the 
+    /// background and arrive on `ConnectionPool::joining`.
+    pub fn with_min_peers(mut self, min_peers: usize) -> Self {
+        self.min_peers = Some(min_peers);
+        self
+    }
+
+    /// Set the options of every connection to and from a peer.
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = options;
+        self
+    }
+
+    async fn connect_to_node(this_node: NodeId, epoch: u64, node_id: NodeId, host: String, port: u16, options: SocketOptions, stream_snd: UnboundedSender<(MessageStream, NodeId)>) {
         let server_addr = format!("{host}:{port}");
         trace!("Connecting to {} at {}...", node_id, server_addr);
 
         let retry_strategy = FixedInterval::from_millis(CONNECTION_RETRY_DELAY_MS);
-        match Retry::spawn(retry_strategy, || TcpStream::connect(&server_addr)).await {
+        match Retry::start(retry_strategy, || TcpStream::connect(&server_addr)).await {
             Ok(stream) => {
                 trace!("Connected to {} at {}", node_id, server_addr);
+                if let Err(e) = options.apply(&stream) {
+                    error!("Failed to set socket options for Node {node_id}: {e:?}");
+                }
+
                 let mut stream = MessageStream::from_tcp_stream(stream);
 
-                let handshake = Handshake(this_node);
+                let handshake = Handshake::new(this_node, epoch);
                 if let Err(e) = stream.send(handshake).await {
                     error!("Failed to send handshake to Node {node_id}: {e:?}")
                 }
@@ -78,76 +141,208 @@ where
         }
     }
 
+    /// Reads the handshake of a connection accepted while the pool is 
+    /// forming, giving up on it after `HANDSHAKE_TIMEOUT_SECS`. Handshakes 
+    /// are read on their own tasks, so a connection that never sends one 
+    /// cannot hold up the peers connecting after it.
+    async fn accept_handshake(stream: TcpStream, addr: SocketAddr, epoch: u64, handshake_snd: UnboundedSender<(MessageStream, NodeId)>) {
+        let mut stream = MessageStream::from_tcp_stream(stream);
+        let deadline = Duration::from_secs(HANDSHAKE_TIMEOUT_SECS);
+
+        match timeout(deadline, stream.recv::<Handshake>()).await {
+            Ok(Some(Ok(handshake))) => match handshake.node_id(epoch) {
+                Some(node_id) => { 
+                    let _ = handshake_snd.send((stream, node_id)); 
+                },
+                None => error!("Rejecting handshake from {addr}")
+            },
+            Ok(Some(Err(e))) => error!("Error on handshake from {addr}: {e:?}"),
+            Ok(None) => error!("Failed to receive handshake from {addr}"),
+            Err(_) => error!("Handshake from {addr} timed out")
+        }
+    }
+
     pub fn admit_member(&mut self, stream: MessageStream, member_id: NodeId) where M: 'static + Send {
-        let (to_client, from_engine) = unbounded_channel();
-        let member_data = RemoteServerData {
-            stream,
-            member_id,
-            from_engine,
-            to_engine: self.client_snd_handle.clone()
-        };
-
-        let handle = tokio::spawn(member_loop(member_data));
-        self.group.insert(member_id, RemoteServerHandle { 
-            member_id,
-            to_client,
-            handle
-        });
-    }
-
-    async fn connect_inner(&mut self) where M: 'static + Send {
-        let (stream_snd, mut stream_rcv) = unbounded_channel();
+        let handle = RemoteServerHandle::spawn(stream, member_id, self.client_snd_handle.clone());
+        self.group.insert(member_id, handle);
+    }
+
+    /// Clients may connect before this node is ready to serve them, so only a 
+    /// configured node that has not yet joined the group is admitted.
+    fn is_expected_peer(&self, node_id: NodeId) -> bool {
+        node_id != self.node_id 
+            && self.config.contains_key(&node_id) 
+            && !self.group.contains_key(&node_id)
+    }
+
+    async fn connect_inner(&mut self, stream_snd: &UnboundedSender<(MessageStream, NodeId)>, stream_rcv: &mut UnboundedReceiver<(MessageStream, NodeId)>) where M: 'static + Send {
         let node_config = self.config.get(&self.node_id).unwrap();
 
         for node in node_config.connection_list.iter() {
-            let connect_config = self.config.get(&node).unwrap();
+            let connect_config = self.config.get(node).unwrap();
             let snd_clone = stream_snd.clone();
-            tokio::spawn(Self::connect_to_node(
+            crate::task::spawn(format_args!("connect to {node}"), Self::connect_to_node(
                 self.node_id, 
+                self.epoch, 
                 *node, 
                 connect_config.hostname.clone(), 
                 connect_config.port, 
+                self.socket_options,
                 snd_clone
             ));
         }
-        drop(stream_snd);
         
-        loop {
+        let all_peers = self.config.len() - 1;
+        let min_peers = self.min_peers.map_or(all_peers, |n| n.min(all_peers));
+        let (handshake_snd, mut handshake_rcv) = unbounded_channel();
+        while self.group.len() < min_peers {
             select! {
-                client = self.listener.accept() => match client {
-                    Ok((stream, _addr)) => {
-                        let mut stream = MessageStream::from_tcp_stream(stream);
-
-                        match stream.recv::<Handshake>().await {
-                            Some(Ok(Handshake(node_id))) => self.admit_member(stream, node_id),
-                            Some(Err(e)) => error!("Error on handshake from {_addr}: {e:?}"),
-                            None => error!("Failed to receive handshake from {_addr}")
+                client = async { self.listener.as_ref().unwrap().accept().await }, if self.listener.is_some() => match client {
+                    Ok((stream, addr)) => {
+                        if let Err(e) = self.socket_options.apply(&stream) {
+                            error!("Failed to set socket options for {addr}: {e:?}");
                         }
-
-                        if self.group.len() == self.config.len() - 1 { break; }
+                        crate::task::spawn(format_args!("handshake from {addr}"), Self::accept_handshake(stream, addr, self.epoch, handshake_snd.clone()));
                     },
                     Err(e) => error!("Could not accept client: {:?}", e)
                 },
-                Some((stream, member_id)) = stream_rcv.recv() => {
-                    self.admit_member(stream, member_id);
-                    if self.group.len() == self.config.len() - 1 { break; }
-                }
+                Some((stream, node_id)) = handshake_rcv.recv() => {
+                    if self.is_expected_peer(node_id) {
+                        self.admit_member(stream, node_id)
+                    } else {
+                        error!("Rejecting handshake as {node_id}")
+                    }
+                },
+                Some((stream, member_id)) = stream_rcv.recv() => self.admit_member(stream, member_id)
             }
         } 
     }
 
+    /// Connects to every other node in the config, giving up once the 
+    /// configured timeout elapses. The timeout and the retry delay between 
+    /// connection attempts both run on tokio's timer, so tests can drive them 
+    /// with paused time instead of waiting them out.
     pub async fn connect(mut self) -> Result<ConnectionPool<M>, Elapsed> where M: 'static + Send {
         let time_limit = self.timeout_secs.unwrap_or(CONNECTION_POOL_INIT_TIMEOUT_SECS);
         let time_limit = Duration::from_secs(time_limit);
+        let (joining_snd, mut joining) = unbounded_channel();
         
-        timeout(time_limit, self.connect_inner())
+        timeout(time_limit, self.connect_inner(&joining_snd, &mut joining))
             .await
             .map(|_| ConnectionPool { 
                 listener: self.listener, 
                 group: self.group, 
                 node_id: self.node_id, 
                 from_members: self.from_members, 
-                client_snd_handle: self.client_snd_handle
+                client_snd_handle: self.client_snd_handle,
+                joining,
+                joining_snd
             })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::pool::server::ServerStateMessageType;
+    use tx_common::{config::NodeConfiguration, admin::AdminRequest};
+    use tx_proto::{ClientRequest, ClientResponse};
+    use crate::testing::free_port;
+    use super::*;
+
+    const A: NodeId = NodeId(0);
+    const B: NodeId = NodeId(1);
+
+    /// Node A listens for B, and B dials A.
+    fn two_node_config() -> Config {
+        let mut config = Config::new();
+        for (node_id, name, connection_list) in [(A, "A", vec![]), (B, "B", vec![A])] {
+            config.insert(node_id, NodeConfiguration { 
+                node_id, 
+                name: name.into(),
+                shards: vec![name.into()],
+                hostname: "127.0.0.1".into(), 
+                port: free_port(), 
+                connection_list
+            });
+        }
+
+        config
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connect_times_out_waiting_for_peer() {
+        let started = std::time::Instant::now();
+        let builder = ConnectionPoolBuilder::<()>::new(two_node_config(), A).await.unwrap();
+
+        assert!(builder.connect().await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(CONNECTION_POOL_INIT_TIMEOUT_SECS));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connect_times_out_while_retrying() {
+        let started = tokio::time::Instant::now();
+        let builder = ConnectionPoolBuilder::<()>::new(two_node_config(), B)
+            .await
+            .unwrap()
+            .with_timeout(5);
+
+        assert!(builder.connect().await.is_err());
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_client_is_not_admitted_as_peer() {
+        let config = two_node_config();
+        let port = config.get(&A).unwrap().port;
+        let node_a = ConnectionPoolBuilder::<()>::new(config.clone(), A).await.unwrap();
+        let node_a = tokio::spawn(node_a.with_timeout(5).connect());
+
+        let probe = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut probe = MessageStream::from_tcp_stream(probe);
+        probe.send(ClientRequest::Admin(AdminRequest::Status)).await.unwrap();
+        assert!(probe.recv::<ClientResponse>().await.is_none());
+
+        let node_b = ConnectionPoolBuilder::<()>::new(config, B).await.unwrap();
+        let node_b = node_b.with_timeout(5).connect().await.unwrap();
+        let node_a = node_a.await.unwrap().unwrap();
+
+        assert_eq!(node_a.group.keys().collect::<Vec<_>>(), vec![&B]);
+        assert_eq!(node_b.group.keys().collect::<Vec<_>>(), vec![&A]);
+    }
+
+    #[tokio::test]
+    async fn test_peer_with_other_routing_is_rejected() {
+        let config = two_node_config();
+        let mut other_routing = config.clone();
+        other_routing.get_mut(&B).unwrap().shards.push("X".into());
+
+        let node_a = ConnectionPoolBuilder::<()>::new(config, A).await.unwrap();
+        let node_a = tokio::spawn(node_a.with_timeout(5).connect());
+        let node_b = ConnectionPoolBuilder::<()>::new(other_routing, B).await.unwrap();
+        let mut node_b = node_b.with_timeout(5).connect().await.unwrap();
+
+        // A closes the connection as soon as it reads B's handshake
+        let closed = node_b.from_members.recv().await.unwrap();
+        assert!(matches!(closed.msg, ServerStateMessageType::Disconnected));
+        assert!(!node_a.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_silent_connection_does_not_block_peers() {
+        let config = two_node_config();
+        let port = config.get(&A).unwrap().port;
+        let node_a = ConnectionPoolBuilder::<()>::new(config.clone(), A).await.unwrap();
+        let node_a = tokio::spawn(node_a.with_timeout(HANDSHAKE_TIMEOUT_SECS * 4).connect());
+
+        // Connects but never sends a handshake
+        let _silent = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+        let started = std::time::Instant::now();
+        let node_b = ConnectionPoolBuilder::<()>::new(config, B).await.unwrap();
+        node_b.with_timeout(5).connect().await.unwrap();
+        let node_a = node_a.await.unwrap().unwrap();
+
+        assert_eq!(node_a.group.keys().collect::<Vec<_>>(), vec![&B]);
+        assert!(started.elapsed() < Duration::from_secs(HANDSHAKE_TIMEOUT_SECS));
+    }
+}
@@ -0,0 +1,119 @@
+use tx_common::admin::PeerHealth;
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+/// How much of each new send latency moves the average towards it.
+static LATENCY_WEIGHT: f64 = 0.2;
+
+/// When a peer's link counts as degraded: once more messages than
+/// `max_queue` wait to be sent to it, or sending a message takes longer than
+/// `max_latency` on average.
+#[derive(Clone, Copy, Debug)]
+pub struct HealthPolicy {
+    pub max_queue: usize,
+    pub max_latency: Duration
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        Self { max_queue: 1024, max_latency: Duration::from_millis(100) }
+    }
+}
+
+/// The health of the link to a peer, measured by its handler thread as it
+/// sends.
+#[derive(Debug, Default)]
+pub struct LinkHealth {
+    pub policy: HealthPolicy,
+    queued: usize,
+    /// A moving average of how long sending a message takes, in seconds
+    latency: f64,
+    degraded: bool
+}
+
+pub type SharedHealth = Arc<Mutex<LinkHealth>>;
+
+impl LinkHealth {
+    /// Records a message sent in `latency`, with `queued` messages still
+    /// waiting behind it. Returns whether the link is now degraded, if that
+    /// changed.
+    pub fn sent(&mut self, latency: Duration, queued: usize) -> Option<bool> {
+        self.queued = queued;
+        self.latency += LATENCY_WEIGHT * (latency.as_secs_f64() - self.latency);
+        let degraded = self.pressure() > 1.0;
+        (degraded != self.degraded).then(|| {
+            self.degraded = degraded;
+            degraded
+        })
+    }
+
+    /// Records that nothing was sent on the link for a while, with `queued`
+    /// messages waiting, as a send that took no time. Without it, a link
+    /// degraded by slow sends that then falls idle would never recover.
+    /// Returns whether the link is now degraded, if that changed.
+    pub fn idle(&mut self, queued: usize) -> Option<bool> {
+        self.sent(Duration::ZERO, queued)
+    }
+
+    /// How close the link is to its limits, at 1 when it reaches either.
+    fn pressure(&self) -> f64 {
+        let queue = self.queued as f64 / self.policy.max_queue.max(1) as f64;
+        let latency = match self.policy.max_latency.as_secs_f64() {
+            0.0 if self.latency > 0.0 => f64::INFINITY,
+            0.0 => 0.0,
+            max => self.latency / max
+        };
+        queue.max(latency)
+    }
+
+    pub fn degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// A score from 100 for a link within its limits, halving each time the
+    /// link goes twice as far over them.
+    pub fn score(&self) -> u8 {
+        (100.0 / self.pressure().max(1.0)) as u8
+    }
+
+    pub fn status(&self) -> PeerHealth {
+        PeerHealth {
+            queued: self.queued,
+            send_latency: Duration::from_secs_f64(self.latency),
+            score: self.score(),
+            degraded: self.degraded
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_degraded_past_either_limit() {
+        let mut health = LinkHealth { policy: HealthPolicy { max_queue: 10, max_latency: Duration::from_millis(10) }, ..Default::default() };
+        assert_eq!(health.sent(Duration::from_millis(1), 5), None);
+        assert_eq!(health.score(), 100);
+
+        // A long queue degrades the link until it drains
+        assert_eq!(health.sent(Duration::from_millis(1), 20), Some(true));
+        assert_eq!(health.score(), 50);
+        assert_eq!(health.sent(Duration::from_millis(1), 30), None);
+        assert_eq!(health.sent(Duration::from_millis(1), 0), Some(false));
+
+        // Slow sends degrade it once they pull the average over the limit
+        assert_eq!(health.sent(Duration::from_millis(40), 0), None);
+        assert_eq!(health.sent(Duration::from_millis(40), 0), Some(true));
+        let status = health.status();
+        assert!(status.degraded && status.score < 100 && status.send_latency > Duration::from_millis(10), "{status:?}");
+    }
+
+    #[test]
+    fn test_idle_link_recovers() {
+        let mut health = LinkHealth { policy: HealthPolicy { max_queue: 10, max_latency: Duration::from_millis(10) }, ..Default::default() };
+        assert_eq!(health.sent(Duration::from_millis(100), 0), Some(true));
+        let recovered = (0..20).position(|_| health.idle(0) == Some(false));
+        assert!(recovered.is_some_and(|checks| checks > 0), "{recovered:?}");
+        assert_eq!(health.score(), 100);
+    }
+}
@@ -1,18 +1,29 @@
 pub mod server;
 mod builder;
+mod health;
+mod tree;
 
 use tokio::{sync::mpsc::{UnboundedReceiver, UnboundedSender}, net::TcpListener};
 use server::{RemoteServerHandle, ServerStateMessage};
 use std::{collections::HashMap};
-use tx_common::config::NodeId;
+use tx_common::{config::NodeId, stream::MessageStream};
 
 pub type ServerGroup<M> = HashMap<NodeId, RemoteServerHandle<M>>;
-pub use builder::ConnectionPoolBuilder;
+pub use builder::{ConnectionPoolBuilder, Handshake};
+pub use tree::{TreeBroadcast, TREE_FANOUT};
+pub use health::{HealthPolicy, LinkHealth, SharedHealth};
 
 pub struct ConnectionPool<M> {
-    pub listener: TcpListener, 
+    /// Where clients and late peers connect, unless every peer runs in this
+    /// process
+    pub listener: Option<TcpListener>, 
     pub group: ServerGroup<M>,
     pub node_id: NodeId,
     pub from_members: UnboundedReceiver<ServerStateMessage<M>>,
-    pub client_snd_handle: UnboundedSender<ServerStateMessage<M>>
+    pub client_snd_handle: UnboundedSender<ServerStateMessage<M>>,
+    /// Streams to peers that joined after the pool finished connecting, 
+    /// either dialed by this node in the background or accepted by the owner
+    /// of the listener
+    pub joining: UnboundedReceiver<(MessageStream, NodeId)>,
+    pub joining_snd: UnboundedSender<(MessageStream, NodeId)>
 }
\ No newline at end of file
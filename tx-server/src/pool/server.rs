@@ -1,18 +1,27 @@
 use tokio::{
-    sync::mpsc::{UnboundedSender, UnboundedReceiver, error::SendError}, 
-    task::JoinHandle, select
+    sync::mpsc::{UnboundedSender, UnboundedReceiver, error::SendError, unbounded_channel}, 
+    task::JoinHandle, select, time::{interval, MissedTickBehavior}
 };
 use serde::{de::DeserializeOwned, Serialize};
-use tx_common::stream::MessageStream;
-use super::NodeId;
-use log::trace;
-use std::fmt;
+use tx_common::{admin::LinkTraffic, stream::{MessageStream, SocketHandle}};
+use super::{NodeId, health::{LinkHealth, SharedHealth}};
+use log::{error, trace};
+use std::{fmt, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+/// How often a member handler re-checks the health of a link it has sent
+/// nothing on since the last check
+const HEALTH_RECHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The traffic on a member's link, updated by its handler thread
+pub type SharedTraffic = Arc<Mutex<LinkTraffic>>;
 
 /// Represents any message types a member handler thread could send the transaction engine
 #[derive(Debug)]
 pub enum ServerStateMessageType<M> {
     Message(M),
-    Disconnected
+    Disconnected,
+    /// The member's link became degraded, or recovered
+    Degraded(bool)
 }
 
 /// Represents any messages a member handler thread could send the multicast engine.
@@ -26,10 +35,46 @@ pub struct ServerStateMessage<I> {
 pub struct RemoteServerHandle<O> {
     pub member_id: NodeId,
     pub to_client: UnboundedSender<O>,
-    pub handle: JoinHandle<()>
+    pub handle: JoinHandle<()>,
+    pub traffic: SharedTraffic,
+    pub health: SharedHealth,
+    /// The socket of the member's link, to change its options while the 
+    /// handler thread uses it
+    pub socket: Option<SocketHandle>
 }
 
 impl<M> RemoteServerHandle<M> {
+    /// Spawns a member handler thread that relays messages between the engine
+    /// and the member on the other end of the stream.
+    pub fn spawn(stream: MessageStream, member_id: NodeId, to_engine: UnboundedSender<ServerStateMessage<M>>) -> Self 
+    where 
+        M: 'static + Send + fmt::Debug + DeserializeOwned + Serialize
+    {
+        let (to_client, from_engine) = unbounded_channel();
+        let traffic = Arc::new(Mutex::new(stream.traffic()));
+        let health = Arc::new(Mutex::new(LinkHealth::default()));
+        let socket = stream.socket().and_then(|socket| socket
+            .map_err(|e| error!("Failed to keep the socket of {member_id}: {e:?}"))
+            .ok());
+        let member_data = RemoteServerData {
+            stream,
+            member_id,
+            from_engine,
+            to_engine,
+            traffic: traffic.clone(),
+            health: health.clone()
+        };
+
+        Self {
+            member_id,
+            to_client,
+            handle: crate::task::spawn(format_args!("member {member_id}"), member_loop(member_data)),
+            traffic,
+            health,
+            socket
+        }
+    }
+
     pub fn pass_message(&self, msg: M) -> Result<(), SendError<M>> {
         self.to_client.send(msg)
     }
@@ -42,11 +87,13 @@ impl<M> Drop for RemoteServerHandle<M> {
     }
 }
 
-pub(super) struct RemoteServerData<I, O> {
+struct RemoteServerData<I, O> {
     pub member_id: NodeId,
     pub stream: MessageStream,
     pub to_engine: UnboundedSender<ServerStateMessage<I>>,
-    pub from_engine: UnboundedReceiver<O>
+    pub from_engine: UnboundedReceiver<O>,
+    pub traffic: SharedTraffic,
+    pub health: SharedHealth
 }
 
 impl<I, O> RemoteServerData<I, O> {
@@ -64,26 +111,65 @@ impl<I, O> RemoteServerData<I, O> {
     fn notify_network_error(&mut self) -> Result<(), SendError<ServerStateMessage<I>>> {
         self.to_engine.send(self.generate_state_msg(ServerStateMessageType::Disconnected))
     }
+
+    fn publish_traffic(&self) {
+        *self.traffic.lock().unwrap() = self.stream.traffic();
+    }
+
+    /// Records how long a send took, or that the link was idle if there was 
+    /// none, and how many messages still wait, telling the engine if that 
+    /// degraded the link or let it recover.
+    fn publish_health(&mut self, sent: Option<Instant>) -> Result<(), SendError<ServerStateMessage<I>>> {
+        let queued = self.from_engine.len();
+        let changed = match sent {
+            Some(sent) => self.health.lock().unwrap().sent(sent.elapsed(), queued),
+            None => self.health.lock().unwrap().idle(queued)
+        };
+        match changed {
+            Some(degraded) => self.notify_client_message(ServerStateMessageType::Degraded(degraded)),
+            None => Ok(())
+        }
+    }
 }
 
-pub(super) async fn member_loop<I, O>(mut member_data: RemoteServerData<I, O>) where I: DeserializeOwned + fmt::Debug, O: Serialize {
-    loop {
+async fn member_loop<I, O>(mut member_data: RemoteServerData<I, O>) where I: DeserializeOwned + fmt::Debug, O: Serialize {
+    let mut recheck = interval(HEALTH_RECHECK_INTERVAL);
+    recheck.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut sent_since_recheck = false;
+    // Once the engine stops listening there is no one to relay messages to
+    let stopped = loop {
         select! {
             Some(to_send) = member_data.from_engine.recv() => {
+                let sent = Instant::now();
                 if member_data.stream.send(to_send).await.is_err() {
-                    member_data.notify_network_error().unwrap();
-                    break;
+                    break member_data.notify_network_error();
+                }
+                member_data.publish_traffic();
+                sent_since_recheck = true;
+                if let Err(e) = member_data.publish_health(Some(sent)) {
+                    break Err(e);
                 }
             },
             received = member_data.stream.recv() => match received {
                 Some(Ok(msg)) => {
-                    member_data.notify_client_message(ServerStateMessageType::Message(msg)).unwrap();
+                    member_data.publish_traffic();
+                    if let Err(e) = member_data.notify_client_message(ServerStateMessageType::Message(msg)) {
+                        break Err(e);
+                    }
                 },
-                _ => {
-                    member_data.notify_network_error().unwrap();
-                    break;
+                _ => break member_data.notify_network_error()
+            },
+            _ = recheck.tick() => {
+                if !std::mem::take(&mut sent_since_recheck) {
+                    if let Err(e) = member_data.publish_health(None) {
+                        break Err(e);
+                    }
                 }
             }
         }
+    };
+
+    if stopped.is_err() {
+        trace!("Engine stopped ... closing the link to {}", member_data.member_id);
     }
 }
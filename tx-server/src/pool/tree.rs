@@ -0,0 +1,71 @@
+use tx_common::config::NodeId;
+
+pub static TREE_FANOUT: usize = 4;
+
+/// Sends a message meant for many peers along a spanning tree rooted at the
+/// sender rather than to each peer directly: the sender passes it to at most
+/// `fanout` peers, each of which passes it on to its part of the rest, so no
+/// node sends more than `fanout` copies. Worth it only in large clusters, so
+/// messages for fewer than `threshold` peers are still sent to each directly.
+#[derive(Clone, Copy, Debug)]
+pub struct TreeBroadcast {
+    /// The fewest peers a message must be for to be sent along a tree
+    pub threshold: usize,
+    /// The most peers any node passes a message to
+    pub fanout: usize
+}
+
+impl TreeBroadcast {
+    pub fn new(threshold: usize) -> Self {
+        Self { threshold, fanout: TREE_FANOUT }
+    }
+
+    /// Whether a message for `peers` peers is sent along a tree.
+    pub fn applies(&self, peers: usize) -> bool {
+        peers >= self.threshold
+    }
+
+    /// Splits the peers a message is for between the peers the sender passes
+    /// it to, each listed with the peers it passes it on to in turn.
+    pub fn subtrees(&self, peers: &[NodeId]) -> Vec<(NodeId, Vec<NodeId>)> {
+        if peers.is_empty() {
+            return vec![];
+        }
+
+        peers
+            .chunks(peers.len().div_ceil(self.fanout.max(1)))
+            .map(|chunk| (chunk[0], chunk[1..].to_vec()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_subtrees_bounded_by_fanout() {
+        let tree = TreeBroadcast { threshold: 4, fanout: 3 };
+        assert!(!tree.applies(3));
+        assert!(tree.applies(4));
+
+        let peers: Vec<_> = (1..=10).map(NodeId).collect();
+        let subtrees = tree.subtrees(&peers);
+        assert_eq!(subtrees, vec![
+            (NodeId(1), vec![NodeId(2), NodeId(3), NodeId(4)]),
+            (NodeId(5), vec![NodeId(6), NodeId(7), NodeId(8)]),
+            (NodeId(9), vec![NodeId(10)])
+        ]);
+
+        // Every peer is reached exactly once, however deep the tree
+        let mut reached = vec![];
+        let mut pending = subtrees;
+        while let Some((child, subtree)) = pending.pop() {
+            reached.push(child);
+            pending.extend(tree.subtrees(&subtree));
+        }
+        reached.sort();
+        assert_eq!(reached, peers);
+        assert_eq!(tree.subtrees(&[NodeId(1), NodeId(2)]), vec![(NodeId(1), vec![]), (NodeId(2), vec![])]);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::sharding::TransactionId;
+use std::collections::VecDeque;
+
+pub static COMMIT_LOG_CAPACITY: usize = 4096;
+
+/// A transaction's writes as applied by one shard, numbered in the order the
+/// shard applied them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitEntry<K, T> {
+    pub seq: u64,
+    pub tx_id: TransactionId,
+    pub writes: Vec<(K, T)>,
+    /// The objects the commit removed, which only the removal of expired 
+    /// objects does
+    pub removed: Vec<K>
+}
+
+/// A bounded log of the most recent commits applied by a shard. Sequence
+/// numbers start at 1 and increase by one with every commit that changes an
+/// object and every removal of expired ones, so a consumer can resume from
+/// the last sequence number it saw.
+pub struct CommitLog<K, T> {
+    entries: VecDeque<CommitEntry<K, T>>,
+    next_seq: u64,
+    capacity: usize
+}
+
+impl<K: Clone, T: Clone> CommitLog<K, T> {
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: VecDeque::with_capacity(capacity), next_seq: 1, capacity }
+    }
+
+    /// Numbers the commits appended from now on after `seq`, such as the last
+    /// commit of a snapshot the shard was restored from.
+    pub fn resume_after(&mut self, seq: u64) {
+        self.next_seq = self.next_seq.max(seq + 1);
+    }
+
+    /// Appends a commit, evicting the oldest one if the log is full, and
+    /// returns its sequence number.
+    pub fn append(&mut self, tx_id: TransactionId, writes: Vec<(K, T)>) -> u64 {
+        self.push(tx_id, writes, Vec::new())
+    }
+
+    /// Appends the removal of expired objects like a commit, so consumers of
+    /// the log see them go.
+    pub fn append_removal(&mut self, tx_id: TransactionId, removed: Vec<K>) -> u64 {
+        self.push(tx_id, Vec::new(), removed)
+    }
+
+    fn push(&mut self, tx_id: TransactionId, writes: Vec<(K, T)>, removed: Vec<K>) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(CommitEntry { seq, tx_id, writes, removed });
+        }
+
+        seq
+    }
+
+    /// The sequence number of the oldest commit still in the log. Commits
+    /// before it were evicted.
+    pub fn first_seq(&self) -> u64 {
+        self.entries.front().map_or(self.next_seq, |entry| entry.seq)
+    }
+
+    /// The sequence number of the most recent commit, or 0 if there was none.
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    /// An estimate of the bytes the logged commits hold, their keys' heap 
+    /// included.
+    pub fn bytes(&self) -> usize where K: AsRef<[u8]> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                size_of::<CommitEntry<K, T>>()
+                    + entry.writes.iter().map(|(key, _)| size_of::<(K, T)>() + key.as_ref().len()).sum::<usize>()
+                    + entry.removed.iter().map(|key| size_of::<K>() + key.as_ref().len()).sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// Returns the logged commits with a sequence number greater than `seq`,
+    /// oldest first.
+    pub fn since(&self, seq: u64) -> Vec<CommitEntry<K, T>> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.seq > seq)
+            .cloned()
+            .collect()
+    }
+}
+
+impl<K: Clone, T: Clone> Default for CommitLog<K, T> {
+    fn default() -> Self {
+        Self::new(COMMIT_LOG_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sharding::TransactionIdGenerator;
+    use tx_common::config::NodeId;
+    use super::*;
+
+    #[test]
+    fn test_commits_since_sequence_number() {
+        let mut log = CommitLog::new(2);
+        let mut id_gen = TransactionIdGenerator::new(NodeId(0));
+        let (tx1, tx2, tx3) = (id_gen.next(), id_gen.next(), id_gen.next());
+
+        assert_eq!(log.first_seq(), 1);
+        assert_eq!(log.append(tx1, vec![(1, 10)]), 1);
+        assert_eq!(log.append(tx2, vec![(2, 20)]), 2);
+        assert_eq!(log.append(tx3, vec![(1, 30)]), 3);
+
+        assert_eq!(log.first_seq(), 2);
+        assert_eq!(log.since(0).iter().map(|e| e.tx_id).collect::<Vec<_>>(), vec![tx2, tx3]);
+        assert_eq!(log.since(2), vec![CommitEntry { seq: 3, tx_id: tx3, writes: vec![(1, 30)], removed: vec![] }]);
+        assert!(log.since(3).is_empty());
+
+        let tx4 = id_gen.next();
+        assert_eq!(log.append_removal(tx4, vec![2]), 4);
+        assert_eq!(log.since(3), vec![CommitEntry { seq: 4, tx_id: tx4, writes: vec![], removed: vec![2] }]);
+    }
+}
@@ -0,0 +1,149 @@
+use tx_common::admin::ShardCounts;
+use std::{cell::Cell, sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}};
+
+/// The number of stripes a counter is split over. Threads beyond this many
+/// share stripes, which only costs them some contention.
+pub static COUNTER_STRIPES: usize = 16;
+
+/// A stripe on a cache line of its own, so that threads counting on
+/// different stripes never invalidate each other's caches.
+#[derive(Default)]
+#[repr(align(64))]
+struct Stripe(AtomicU64);
+
+static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static STRIPE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// The stripe the current thread counts on, handed out round-robin as
+/// threads first count.
+fn stripe() -> usize {
+    STRIPE.with(|stripe| match stripe.get() {
+        Some(stripe) => stripe,
+        None => {
+            let next = NEXT_STRIPE.fetch_add(1, Ordering::Relaxed) % COUNTER_STRIPES;
+            stripe.set(Some(next));
+            next
+        }
+    })
+}
+
+/// A counter that many threads can add to at once without contending on a
+/// single atomic. Each thread adds to a stripe of its own, and reading the
+/// counter sums the stripes, so reads are slower than with a plain atomic
+/// and see a total that may be a few counts behind.
+pub struct StripedCounter {
+    stripes: Box<[Stripe]>
+}
+
+impl Default for StripedCounter {
+    fn default() -> Self {
+        Self { stripes: (0..COUNTER_STRIPES).map(|_| Stripe::default()).collect() }
+    }
+}
+
+impl StripedCounter {
+    pub fn add(&self, n: u64) {
+        self.stripes[stripe()].0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.stripes.iter().map(|stripe| stripe.0.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Returns the count and resets it to zero. Counts added while the
+    /// stripes are reset are either returned or kept, never lost.
+    pub fn take(&self) -> u64 {
+        self.stripes.iter().map(|stripe| stripe.0.swap(0, Ordering::Relaxed)).sum()
+    }
+}
+
+/// What a shard counts about the operations on it, kept apart from its
+/// objects and their locks so that counting never waits on the data path.
+/// Counting can be turned off, such as to measure what it costs.
+pub(super) struct ShardCounters {
+    enabled: AtomicBool,
+    reads: StripedCounter,
+    writes: StripedCounter,
+    commits: StripedCounter,
+    aborts: StripedCounter
+}
+
+impl Default for ShardCounters {
+    fn default() -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+            reads: Default::default(),
+            writes: Default::default(),
+            commits: Default::default(),
+            aborts: Default::default()
+        }
+    }
+}
+
+impl ShardCounters {
+    pub(super) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    fn count(&self, counter: &StripedCounter) {
+        if self.enabled.load(Ordering::Relaxed) {
+            counter.add(1);
+        }
+    }
+
+    pub(super) fn read(&self) {
+        self.count(&self.reads);
+    }
+
+    pub(super) fn write(&self) {
+        self.count(&self.writes);
+    }
+
+    pub(super) fn commit(&self) {
+        self.count(&self.commits);
+    }
+
+    pub(super) fn abort(&self) {
+        self.count(&self.aborts);
+    }
+
+    pub(super) fn counts(&self) -> ShardCounts {
+        ShardCounts {
+            reads: self.reads.sum(),
+            writes: self.writes.sum(),
+            commits: self.commits.sum(),
+            aborts: self.aborts.sum()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use super::*;
+
+    #[test]
+    fn test_striped_counter_sums_every_thread() {
+        let counter = Arc::new(StripedCounter::default());
+        let threads: Vec<_> = (0..2 * COUNTER_STRIPES)
+            .map(|_| {
+                let counter = counter.clone();
+                std::thread::spawn(move || (0..1000).for_each(|_| counter.add(1)))
+            })
+            .collect();
+        threads.into_iter().for_each(|thread| thread.join().unwrap());
+
+        assert_eq!(counter.sum(), 2000 * COUNTER_STRIPES as u64);
+        assert_eq!(counter.take(), 2000 * COUNTER_STRIPES as u64);
+        assert_eq!(counter.sum(), 0);
+
+        let counters = ShardCounters::default();
+        counters.read();
+        counters.set_enabled(false);
+        counters.write();
+        assert_eq!(counters.counts(), ShardCounts { reads: 1, ..Default::default() });
+    }
+}
@@ -0,0 +1,154 @@
+use std::hash::{Hash, Hasher};
+
+/// The number of leaves of a digest's Merkle tree. Every key falls in the
+/// leaf picked by its hash, so two digests of shards that diverge on one key
+/// differ in exactly one leaf.
+pub static DIGEST_LEAVES: usize = 16;
+
+/// A Merkle tree over a set of key/value pairs: each leaf hashes the pairs
+/// whose keys fall in it, in key order, and each node above hashes its two
+/// children. Two sets of pairs have the same root if and only if, barring
+/// collisions, they are the same set, however they were built, and on 
+/// whichever machine or build of the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleDigest {
+    pub root: u64,
+    pub leaves: Vec<u64>,
+    /// The number of pairs the digest covers
+    pub count: usize
+}
+
+/// FNV-1a, as `ShardMap::epoch` uses, which unlike std's hashers is stable
+/// across builds. Integers are hashed little-endian, and lengths as 64 bits,
+/// so that machines of any byte order and word size agree.
+#[derive(Clone)]
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16)
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32)
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64)
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128)
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64)
+    }
+}
+
+fn hash_of(value: impl Hash) -> u64 {
+    let mut hasher = StableHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl MerkleDigest {
+    /// Digests a set of pairs given in any order.
+    pub fn new<'a, K: 'a + Hash + Ord, T: 'a + Hash>(pairs: impl IntoIterator<Item = (&'a K, &'a T)>) -> Self {
+        let mut pairs: Vec<_> = pairs.into_iter().collect();
+        pairs.sort_unstable_by_key(|(key, _)| *key);
+
+        let mut leaves = vec![StableHasher::default(); DIGEST_LEAVES];
+        for (key, value) in &pairs {
+            let leaf = &mut leaves[hash_of(key) as usize % DIGEST_LEAVES];
+            key.hash(leaf);
+            value.hash(leaf);
+        }
+
+        let leaves: Vec<u64> = leaves.iter().map(Hasher::finish).collect();
+        let mut level = leaves.clone();
+        while level.len() > 1 {
+            level = level.chunks(2).map(hash_of).collect();
+        }
+
+        Self { root: level[0], leaves, count: pairs.len() }
+    }
+
+    /// The leaves in which two digests differ, each holding at least one key
+    /// on which the digested sets diverge.
+    pub fn diverging(&self, other: &Self) -> Vec<usize> {
+        if self.root == other.root {
+            return vec![];
+        }
+
+        (0..DIGEST_LEAVES).filter(|leaf| self.leaves[*leaf] != other.leaves[*leaf]).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_digest_independent_of_order() {
+        let pairs = [("A.x".to_string(), 3), ("A.y".to_string(), 0), ("A.z".to_string(), 7)];
+        let digest = MerkleDigest::new(pairs.iter().map(|(k, v)| (k, v)));
+        assert_eq!(digest, MerkleDigest::new(pairs.iter().rev().map(|(k, v)| (k, v))));
+        assert_eq!(digest.count, 3);
+        assert!(digest.diverging(&digest).is_empty());
+
+        // Diverging on one key changes the root and one leaf
+        let changed = [("A.x".to_string(), 3), ("A.y".to_string(), 1), ("A.z".to_string(), 7)];
+        let changed = MerkleDigest::new(changed.iter().map(|(k, v)| (k, v)));
+        assert_ne!(digest.root, changed.root);
+        assert_eq!(digest.diverging(&changed), vec![hash_of("A.y".to_string()) as usize % DIGEST_LEAVES]);
+
+        let empty = MerkleDigest::new(std::iter::empty::<(&String, &i64)>());
+        assert_eq!((empty.count, empty.leaves.len()), (0, DIGEST_LEAVES));
+        assert_ne!(empty.root, digest.root);
+    }
+
+    #[test]
+    fn test_digest_stable_across_builds() {
+        let pairs = [("A.x".to_string(), 3i64), ("A.y".to_string(), -1)];
+        let digest = MerkleDigest::new(pairs.iter().map(|(k, v)| (k, v)));
+        assert_eq!(digest.root, 0x9035af16dcd65924);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::sharding::TransactionId;
+use tx_common::admin::Decision;
+use std::{collections::{HashMap, VecDeque}, time::{Duration, Instant}};
+
+pub static FINISHED_CAPACITY: usize = 4096;
+pub static FINISHED_RETENTION: Duration = Duration::from_secs(60);
+
+/// The outcomes of the transactions a shard most recently committed or
+/// aborted, so that a request for one of them that arrives late (e.g. a
+/// pipelined or retried write) can be refused rather than leave behind
+/// tentative writes that nothing will ever commit or abort, and so that the
+/// shard can say how one finished for as long as it remembers. An outcome is
+/// forgotten once it is older than the retention, or once the shard
+/// remembers as many newer ones as it may.
+pub struct FinishedTransactions {
+    decisions: HashMap<TransactionId, (Decision, Instant)>,
+    /// The remembered transactions, in the order they finished
+    order: VecDeque<TransactionId>,
+    capacity: usize,
+    retention: Duration
+}
+
+impl FinishedTransactions {
+    pub fn new(capacity: usize, retention: Duration) -> Self {
+        Self { decisions: HashMap::new(), order: VecDeque::new(), capacity, retention }
+    }
+
+    /// Remembers how a transaction finished. The first outcome recorded for
+    /// a transaction stands until it is forgotten.
+    pub fn record(&mut self, id: TransactionId, decision: Decision) {
+        self.record_at(id, decision, Instant::now())
+    }
+
+    fn record_at(&mut self, id: TransactionId, decision: Decision, now: Instant) {
+        self.forget_expired(now);
+        if self.capacity == 0 || self.decisions.contains_key(&id) {
+            return;
+        }
+
+        if self.order.len() == self.capacity {
+            let forgotten = self.order.pop_front().unwrap();
+            self.decisions.remove(&forgotten);
+        }
+        self.decisions.insert(id, (decision, now));
+        self.order.push_back(id);
+    }
+
+    /// An estimate of the bytes the remembered outcomes hold.
+    pub fn bytes(&self) -> usize {
+        self.decisions.len() * size_of::<(TransactionId, (Decision, Instant))>()
+            + self.order.len() * size_of::<TransactionId>()
+    }
+
+    pub fn get(&self, id: &TransactionId) -> Option<Decision> {
+        self.get_at(id, Instant::now())
+    }
+
+    fn get_at(&self, id: &TransactionId, now: Instant) -> Option<Decision> {
+        self.decisions
+            .get(id)
+            .filter(|(_, finished_at)| now.duration_since(*finished_at) < self.retention)
+            .map(|(decision, _)| *decision)
+    }
+
+    fn forget_expired(&mut self, now: Instant) {
+        while let Some(oldest) = self.order.front() {
+            if now.duration_since(self.decisions[oldest].1) < self.retention {
+                break;
+            }
+
+            self.decisions.remove(oldest);
+            self.order.pop_front();
+        }
+    }
+}
+
+impl Default for FinishedTransactions {
+    fn default() -> Self {
+        Self::new(FINISHED_CAPACITY, FINISHED_RETENTION)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sharding::fixture::tx_at;
+    use super::*;
+
+    #[test]
+    fn test_oldest_outcomes_forgotten_first() {
+        let mut finished = FinishedTransactions::new(2, FINISHED_RETENTION);
+        finished.record(tx_at(1), Decision::Commit);
+        finished.record(tx_at(2), Decision::Abort);
+        finished.record(tx_at(1), Decision::Abort);
+        assert_eq!(finished.get(&tx_at(1)), Some(Decision::Commit));
+
+        finished.record(tx_at(3), Decision::Commit);
+        assert_eq!(finished.get(&tx_at(1)), None);
+        assert_eq!(finished.get(&tx_at(2)), Some(Decision::Abort));
+        assert_eq!(finished.get(&tx_at(3)), Some(Decision::Commit));
+    }
+
+    #[test]
+    fn test_outcomes_forgotten_after_retention() {
+        let mut finished = FinishedTransactions::new(FINISHED_CAPACITY, Duration::from_secs(10));
+        let start = Instant::now();
+        finished.record_at(tx_at(1), Decision::Commit, start);
+        finished.record_at(tx_at(2), Decision::Abort, start + Duration::from_secs(6));
+
+        let later = start + Duration::from_secs(12);
+        assert_eq!(finished.get_at(&tx_at(1), later), None);
+        assert_eq!(finished.get_at(&tx_at(2), later), Some(Decision::Abort));
+
+        // Expired outcomes are dropped as others are recorded
+        finished.record_at(tx_at(3), Decision::Commit, later);
+        assert_eq!(finished.order, vec![tx_at(2), tx_at(3)]);
+    }
+}
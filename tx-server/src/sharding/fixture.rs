@@ -0,0 +1,77 @@
+use super::{Checkable, Incrementable, ObjectState, Shard, TransactionId};
+use tx_common::config::NodeId;
+use std::{fmt::Debug, hash::Hash, sync::Arc};
+
+/// The coordinator of every transaction id made by `tx_at`.
+pub(crate) static FIXTURE_COORDINATOR: NodeId = NodeId(1);
+
+/// The transaction id with the given timestamp.
+pub(crate) fn tx_at(ts: u128) -> TransactionId {
+    TransactionId::at(ts, FIXTURE_COORDINATOR)
+}
+
+/// Builds a shard whose objects hold committed values written at chosen
+/// timestamps, without going through a coordinator. Values are committed in
+/// timestamp order through the shard's own reads and writes, so the built
+/// shard is in a state the shard could have reached by itself.
+pub(crate) struct ShardFixture<K, T> {
+    shard_id: NodeId,
+    committed: Vec<(K, T, u128)>
+}
+
+impl<K, T> ShardFixture<K, T>
+where 
+    K: 'static + Send + Clone + Eq + Hash + Debug, 
+    T: 'static + Send + Clone + Default + Checkable + Incrementable + PartialEq + Debug
+{
+    pub(crate) fn new(shard_id: NodeId) -> Self {
+        Self { shard_id, committed: Vec::new() }
+    }
+
+    /// Commits `value` to `object_id` as the transaction at timestamp `ts`.
+    pub(crate) fn committed(mut self, object_id: K, value: T, ts: u128) -> Self {
+        self.committed.push((object_id, value, ts));
+        self
+    }
+
+    pub(crate) async fn build(mut self) -> Arc<Shard<K, T>> {
+        let shard = Arc::new(Shard::new(self.shard_id));
+        self.committed.sort_by_key(|(_, _, ts)| *ts);
+
+        // Values committed at the same timestamp are one transaction's writes
+        let mut committed = self.committed.into_iter().peekable();
+        while let Some((object_id, value, ts)) = committed.next() {
+            let tx_id = tx_at(ts);
+            shard.write(&tx_id, object_id.clone(), value).await
+                .unwrap_or_else(|e| panic!("fixture write to {object_id:?} at {ts} failed: {e:?}"));
+            if committed.peek().is_none_or(|(_, _, next)| *next != ts) {
+                shard.commit(&tx_id).await
+                    .unwrap_or_else(|e| panic!("fixture commit at {ts} failed: {e:?}"));
+            }
+        }
+
+        shard
+    }
+}
+
+/// Asserts that `object_id` holds `value`, committed by the transaction at
+/// timestamp `ts`.
+pub(crate) async fn assert_committed<K, T>(shard: &Shard<K, T>, object_id: &K, value: T, ts: u128)
+where 
+    K: 'static + Send + Clone + Eq + Hash + Debug, 
+    T: 'static + Send + Clone + Default + Checkable + Incrementable + PartialEq + Debug
+{
+    let (committed, state) = shard.inspect(object_id).await
+        .unwrap_or_else(|| panic!("{object_id:?} does not exist"));
+    assert_eq!((committed, state.committed_timestamp), (value, tx_at(ts)), "{object_id:?}");
+}
+
+/// Asserts the timestamps of `object_id`, or that it does not exist if `None`.
+pub(crate) async fn assert_state<K, T>(shard: &Shard<K, T>, object_id: &K, expected: Option<ObjectState>)
+where 
+    K: 'static + Send + Clone + Eq + Hash + Debug, 
+    T: 'static + Send + Clone + Default + Checkable + Incrementable + PartialEq + Debug
+{
+    let state = shard.inspect(object_id).await.map(|(_, state)| state);
+    assert_eq!(state, expected, "{object_id:?}");
+}
@@ -0,0 +1,64 @@
+use super::TransactionId;
+use futures::future::BoxFuture;
+use std::{future::Future, sync::{Arc, RwLock}};
+
+/// A callback run by a shard as it commits a transaction, receiving the
+/// transaction and the objects it writes with their new values.
+pub type CommitHook<K, T> = Arc<dyn Fn(TransactionId, Arc<Vec<(K, T)>>) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// The hooks registered on a shard. Pre-commit hooks run before a commit is
+/// applied and receive the transaction's tentative writes; post-commit hooks
+/// run once it is applied and receive the values that changed. Hooks run one
+/// at a time in registration order, and the commit waits for all of them.
+pub(super) struct CommitHooks<K, T> {
+    pre_commit: RwLock<Vec<CommitHook<K, T>>>,
+    post_commit: RwLock<Vec<CommitHook<K, T>>>
+}
+
+impl<K, T> Default for CommitHooks<K, T> {
+    fn default() -> Self {
+        Self { pre_commit: RwLock::new(Vec::new()), post_commit: RwLock::new(Vec::new()) }
+    }
+}
+
+pub(super) fn boxed<K, T, F>(hook: impl Fn(TransactionId, Arc<Vec<(K, T)>>) -> F + Send + Sync + 'static) -> CommitHook<K, T>
+where
+    F: Future<Output = ()> + Send + 'static
+{
+    Arc::new(move |tx_id, writes| Box::pin(hook(tx_id, writes)))
+}
+
+impl<K, T> CommitHooks<K, T> {
+    pub(super) fn add_pre_commit(&self, hook: CommitHook<K, T>) {
+        self.pre_commit.write().unwrap().push(hook);
+    }
+
+    pub(super) fn add_post_commit(&self, hook: CommitHook<K, T>) {
+        self.post_commit.write().unwrap().push(hook);
+    }
+
+    pub(super) fn has_pre_commit(&self) -> bool {
+        !self.pre_commit.read().unwrap().is_empty()
+    }
+
+    pub(super) fn has_post_commit(&self) -> bool {
+        !self.post_commit.read().unwrap().is_empty()
+    }
+
+    pub(super) async fn run_pre_commit(&self, tx_id: TransactionId, writes: Vec<(K, T)>) {
+        let hooks = self.pre_commit.read().unwrap().clone();
+        run(hooks, tx_id, writes).await
+    }
+
+    pub(super) async fn run_post_commit(&self, tx_id: TransactionId, writes: Vec<(K, T)>) {
+        let hooks = self.post_commit.read().unwrap().clone();
+        run(hooks, tx_id, writes).await
+    }
+}
+
+async fn run<K, T>(hooks: Vec<CommitHook<K, T>>, tx_id: TransactionId, writes: Vec<(K, T)>) {
+    let writes = Arc::new(writes);
+    for hook in hooks {
+        hook(tx_id, writes.clone()).await;
+    }
+}
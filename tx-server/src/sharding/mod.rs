@@ -1,13 +1,39 @@
-mod transaction_id;
 mod shard;
 mod object;
+mod commit_log;
+mod finished;
+mod verifier;
+mod hooks;
+mod digest;
+mod counters;
+mod prepares;
+#[cfg(test)]
+pub(crate) mod fixture;
+#[cfg(test)]
+pub(crate) mod schedule;
 
-pub use transaction_id::{TransactionIdGenerator, TransactionId};
+pub use tx_common::transaction_id::{TransactionIdGenerator, TransactionId};
 pub use shard::{Abort, Shard};
 pub use object::CommitSuccess; 
+pub use commit_log::CommitEntry;
+pub use object::{ObjectFootprint, ObjectState, ObjectStats};
+pub use verifier::Verifier;
+pub use hooks::CommitHook;
+pub use digest::{MerkleDigest, DIGEST_LEAVES};
+pub use counters::{StripedCounter, COUNTER_STRIPES};
 
 pub trait Checkable {
     type ConsistencyCheckError: std::fmt::Debug + Send;
     
     fn check(&self) -> Result<(), Self::ConsistencyCheckError>;
-}
\ No newline at end of file
+}
+
+/// Values that can be counted up or down by increments, which commute, so
+/// that transactions that only increment a value never conflict.
+pub trait Incrementable {
+    /// The value with `by` added to it
+    fn incremented(&self, by: &Self) -> Self;
+
+    /// Whether adding this value to another makes it smaller
+    fn decreases(&self) -> bool;
+}
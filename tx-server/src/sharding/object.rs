@@ -1,42 +1,100 @@
 use std::{
     collections::{BTreeMap, BTreeSet}, 
-    ops::Bound::{Excluded, Included},
-    convert::Infallible
+    ops::Bound::{Excluded, Included, Unbounded},
+    convert::Infallible,
+    mem::size_of,
+    time::{Duration, Instant}
 };
-use super::{transaction_id::TransactionId, Checkable};
+use super::{TransactionId, Checkable, Incrementable};
 use tx_common::config::NodeId;
 use log::{debug};
 
 #[derive(Debug)]
 struct TentativeWrite<T> where {
-    value: T
+    value: T,
+    /// How long the object lives once the write commits, if it creates it
+    ttl: Option<Duration>
 }
 
 impl<T> TentativeWrite<T>
 where 
     T: Checkable 
 {
-    fn new(value: T) -> Self {
-        Self { value }
+    fn new(value: T, ttl: Option<Duration>) -> Self {
+        Self { value, ttl }
     }
 
-    fn update(&mut self, value: T) {
+    fn update(&mut self, value: T, ttl: Option<Duration>) {
         self.value = value;
+        self.ttl = ttl.or(self.ttl);
     }
 }
 
 pub struct TimestampedObject<T> {
     value: T,
     committed_timestamp: TransactionId,
+    /// The transaction timestamp from which the object no longer exists, set
+    /// by the commit that created it
+    expires_at: Option<u128>,
     read_timestamps: BTreeSet<TransactionId>,
-    tentative_writes: BTreeMap<TransactionId, TentativeWrite<T>>
+    tentative_writes: BTreeMap<TransactionId, TentativeWrite<T>>,
+    /// The increments transactions made to the object without reading or
+    /// writing it. Increments commute, so they commit in any order.
+    increments: BTreeMap<TransactionId, T>,
+    /// When each transaction with a write or increment pending first made 
+    /// one, by this node's clock
+    pending_since: BTreeMap<TransactionId, Instant>,
+    /// The newest transaction that committed a write rather than an
+    /// increment, which older increments can no longer be applied under
+    overwritten: TransactionId,
+    stats: ObjectStats
+}
+
+/// How much an object has been used since it was created, kept cheap enough
+/// to count on every access.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ObjectStats {
+    /// Reads of the object, counting a transaction's rereads
+    pub reads: u64,
+    /// Tentative writes to the object, counting a transaction's rewrites and
+    /// increments
+    pub writes: u64,
+    /// Reads and writes of the object that aborted their transaction on a 
+    /// timestamp-ordering conflict
+    pub aborts: u64,
+    /// Commits that changed the object
+    pub commits: u64,
+    /// The transaction that committed the object's current value, if any has
+    pub last_committer: Option<TransactionId>
+}
+
+/// What an object holds for transactions that have not finished on it, so 
+/// that the shard's memory can be attributed to them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObjectFootprint {
+    /// Every pending write or increment, by transaction, with the bytes it 
+    /// holds
+    pub pending: Vec<(TransactionId, usize)>,
+    /// The number of reads whose timestamps the object remembers
+    pub reads: usize,
+    /// The bytes the object holds, its committed value included
+    pub bytes: usize
+}
+
+/// The timestamps of an object that its invariants are stated in terms of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectState {
+    pub committed_timestamp: TransactionId,
+    pub oldest_tentative_write: Option<TransactionId>,
+    pub oldest_read: Option<TransactionId>
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum RWFailure {
     WaitFor(TransactionId),
     AbortedNotFound,
-    Abort
+    /// Violates timestamp ordering with the given newer transaction
+    Abort(TransactionId)
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -59,18 +117,41 @@ pub enum CheckCommitSuccess<T> {
 
 impl<T> TimestampedObject<T> 
 where 
-    T: Clone + Checkable
+    T: Clone + Checkable + Incrementable
 {
     pub fn default(owner_id: NodeId) -> Self where T: Default {
         Self {
             value: Default::default(),
             committed_timestamp: TransactionId::default(owner_id),
+            expires_at: None,
             read_timestamps: BTreeSet::new(),
-            tentative_writes: BTreeMap::new()
+            tentative_writes: BTreeMap::new(),
+            increments: BTreeMap::new(),
+            pending_since: BTreeMap::new(),
+            overwritten: TransactionId::default(owner_id),
+            stats: ObjectStats::default()
         }
     }
 
+    /// An object holding `value` as committed by `committed_by`, expiring at
+    /// `expires_at` if given, such as one restored from a snapshot.
+    pub fn restored(owner_id: NodeId, value: T, committed_by: TransactionId, expires_at: Option<u128>) -> Self where T: Default {
+        Self { value, committed_timestamp: committed_by, expires_at, overwritten: committed_by, ..Self::default(owner_id) }
+    }
+
     pub fn read(&mut self, id: &TransactionId) -> Result<T, RWFailure> {
+        // An older increment changes the value this transaction must read
+        if let Some(older) = self.increments.range(..*id).next() {
+            return Err(RWFailure::WaitFor(*older.0));
+        }
+
+        self.read_without_increments(id).map(|value| match self.increments.get(id) {
+            Some(increment) => value.incremented(increment),
+            None => value
+        })
+    }
+
+    fn read_without_increments(&mut self, id: &TransactionId) -> Result<T, RWFailure> {
         if id > &self.committed_timestamp {
             // Get a range of timestamps starting from the committed timestamp
             // to the timestamp of the read request transaction, inclusive
@@ -88,18 +169,25 @@ where
                     // has not performed a tentative write, and there were no
                     // transactions older than this one that DID write that we 
                     // can wait on... so abort
-                    if self.committed_timestamp.is_default() {
+                    if self.committed_timestamp.is_default() && self.increments.contains_key(id) {
+                        // The transaction's own increment creates the object
+                        self.read_timestamps.insert(*id);
+                        self.stats.reads += 1;
+                        Ok(self.value.clone())
+                    } else if self.committed_timestamp.is_default() || self.expired_for(id) {
                         Err(RWFailure::AbortedNotFound)
                     } else {
                         // if the timestamp we found is the committed timestamp
                         // read Ds and add Tc to RTS list (if not already added)
                         self.read_timestamps.insert(*id);
+                        self.stats.reads += 1;
                         Ok(self.value.clone())
                     }
                 },
                 Some((ts, tw)) => {
                     if ts == id { // if Ds was written by Tc, simply read Ds
                         self.read_timestamps.insert(*id);
+                        self.stats.reads += 1;
                         Ok(tw.value.clone())
                     } else {
                         // Wait until the transaction that wrote Ds is committed 
@@ -114,38 +202,86 @@ where
         } else {
             // Too late! A transaction with a later timestamp has either already 
             // read or has already written to this object
-            Err(RWFailure::Abort)
+            Err(RWFailure::Abort(self.committed_timestamp))
         }
     }
 
+    #[cfg(test)]
     pub fn write(&mut self, id: &TransactionId, value: T) -> Result<(), RWFailure> {
+        self.write_expiring(id, value, None)
+    }
+
+    /// Writes the object like `write`, but if the write creates the object, 
+    /// or creates it again after it expired, the object expires `ttl` after 
+    /// the transaction's timestamp.
+    pub fn write_expiring(&mut self, id: &TransactionId, value: T, ttl: Option<Duration>) -> Result<(), RWFailure> {
         debug!("{:?}", self.read_timestamps);
-        let is_after_mrt = self.read_timestamps
+        let newer_read = self.read_timestamps
             .iter()
             .next_back()
-            .map_or_else(|| true, |mrt| id >= mrt);
+            .filter(|mrt| id < *mrt)
+            .copied();
 
         // If the requesting transaction is OR is after the max read timestamp 
         // on the object AND is after the write timestamp on the committed 
         // version of the object, then perform a tentative write on the object
-        if is_after_mrt && id > &self.committed_timestamp {
+        if newer_read.is_none() && id > &self.committed_timestamp {
             // Modify the entry for the tentative write if the requesting 
             // transaction has already performed a tentative write. Otherwise,
             // insert a tentative write for the object for the transaction.
+            // The value written was read with the transaction's increment
+            self.increments.remove(id);
             self.tentative_writes
                 .entry(*id)
-                .and_modify(|tw| tw.update(value.clone()))
-                .or_insert(TentativeWrite::new(value));
+                .and_modify(|tw| tw.update(value.clone(), ttl))
+                .or_insert(TentativeWrite::new(value, ttl));
+            self.pending_since.entry(*id).or_insert_with(Instant::now);
+            self.stats.writes += 1;
 
             Ok(())
         } else {
             // Too late! A transaction with a later timestamp has either already 
             // read or has already written to this object
-            Err(RWFailure::Abort)
+            Err(RWFailure::Abort(newer_read.unwrap_or(self.committed_timestamp)))
+        }
+    }
+
+    /// Adds `by` to the object without reading it. Increments by different
+    /// transactions never conflict with each other, only with a newer 
+    /// transaction that read the object or an older one that has not yet
+    /// committed a write to it. A transaction that wrote the object has its
+    /// increment added to its write.
+    pub fn increment(&mut self, id: &TransactionId, by: T) -> Result<(), RWFailure> {
+        let newer_read = self.read_timestamps
+            .iter()
+            .next_back()
+            .filter(|mrt| id < *mrt)
+            .copied();
+        if let Some(newer) = newer_read.or((id <= &self.overwritten).then_some(self.overwritten)) {
+            return Err(RWFailure::Abort(newer));
+        }
+
+        match self.tentative_writes.get_mut(id) {
+            Some(tw) => tw.value = tw.value.incremented(&by),
+            None => {
+                let increment = match self.increments.remove(id) {
+                    Some(increment) => increment.incremented(&by),
+                    None => by
+                };
+                self.increments.insert(*id, increment);
+            }
         }
+        self.pending_since.entry(*id).or_insert_with(Instant::now);
+        self.stats.writes += 1;
+
+        Ok(())
     }
 
     pub fn check_commit(&self, id: &TransactionId) -> Result<CheckCommitSuccess<()>, CommitFailure<T::ConsistencyCheckError>> {
+        if let Some(increment) = self.increments.get(id) {
+            return self.check_increment(id, increment);
+        }
+
         if !self.tentative_writes.contains_key(id) {
             return Ok(CheckCommitSuccess::NothingToCommit);
         }
@@ -160,8 +296,8 @@ where
 
                     tw.value
                         .check()
-                        .map(|v| CheckCommitSuccess::CommitValue(v))
-                        .map_err(|e| CommitFailure::ConsistencyCheckFailed(e))
+                        .map(CheckCommitSuccess::CommitValue)
+                        .map_err(CommitFailure::ConsistencyCheckFailed)
                 } else {
                     Err(CommitFailure::WaitFor(*first))
                 }
@@ -170,15 +306,54 @@ where
         }
     }
 
+    /// Checks that an increment can commit: every older write to the object
+    /// committed or aborted, and the object passes its consistency check 
+    /// even if every other decrement pending on it commits first.
+    fn check_increment(&self, id: &TransactionId, increment: &T) -> Result<CheckCommitSuccess<()>, CommitFailure<T::ConsistencyCheckError>> {
+        if let Some(older) = self.tentative_writes.range(..*id).next() {
+            return Err(CommitFailure::WaitFor(*older.0));
+        }
+
+        self.increments
+            .iter()
+            .filter(|(ts, decrement)| *ts != id && decrement.decreases())
+            .fold(self.value.incremented(increment), |value, (_, decrement)| value.incremented(decrement))
+            .check()
+            .map(CheckCommitSuccess::CommitValue)
+            .map_err(CommitFailure::ConsistencyCheckFailed)
+    }
+
     pub fn commit(&mut self, id: &TransactionId) -> Result<CommitSuccess<T>, CommitFailure<T::ConsistencyCheckError>> {
         self.check_commit(id)
             .map(|success| {
-                if let CheckCommitSuccess::CommitValue(_) = success {
+                self.pending_since.remove(id);
+                if let Some(increment) = self.increments.remove(id) {
+                    self.committed_timestamp = self.committed_timestamp.max(*id);
+                    self.value = self.value.incremented(&increment);
+                    self.stats.commits += 1;
+                    self.stats.last_committer = Some(*id);
+
+                    // Reads are kept, since they must still abort older 
+                    // increments
+
+                    CommitSuccess::ValueChanged(self.value.clone())
+                } else if let CheckCommitSuccess::CommitValue(_) = success {
                     let (ts, tw) = self.tentative_writes
                         .remove_entry(id)
                         .unwrap();
+                    if self.committed_timestamp.is_default() || self.expired_for(&ts) {
+                        self.expires_at = tw.ttl.map(|ttl| ts.timestamp().saturating_add(ttl.as_nanos()));
+                    }
                     self.committed_timestamp = ts;
+                    self.overwritten = ts;
                     self.value = tw.value;
+                    self.stats.commits += 1;
+                    self.stats.last_committer = Some(ts);
+
+                    // A write must be newer than the committed version, so
+                    // reads at or before it can no longer abort any write
+                    self.read_timestamps = self.read_timestamps.split_off(&ts);
+                    self.read_timestamps.remove(&ts);
 
                     CommitSuccess::ValueChanged(self.value.clone())
                 } else {
@@ -187,16 +362,138 @@ where
         })
     }
 
+    /// Whether the object has a committed value or a write or increment 
+    /// pending, rather than only the reads of transactions that found it
+    /// missing.
+    pub fn exists(&self) -> bool {
+        !self.committed_timestamp.is_default() || !self.tentative_writes.is_empty() || !self.increments.is_empty()
+    }
+
+    /// Records that transaction `id` found the object missing, so that older
+    /// transactions can no longer create it.
+    pub fn read_missing(&mut self, id: &TransactionId) {
+        self.read_timestamps.insert(*id);
+        self.stats.reads += 1;
+    }
+
+    pub fn committed_value(&self) -> &T {
+        &self.value
+    }
+
+    /// The value a transaction would leave the object with if it committed
+    /// now, if it wrote or incremented the object.
+    pub fn tentative_write(&self, id: &TransactionId) -> Option<T> {
+        match self.increments.get(id) {
+            Some(increment) => Some(self.value.incremented(increment)),
+            None => self.tentative_writes.get(id).map(|tw| tw.value.clone())
+        }
+    }
+
+    pub fn state(&self) -> ObjectState {
+        ObjectState {
+            committed_timestamp: self.committed_timestamp,
+            oldest_tentative_write: self.tentative_writes.keys().next().copied(),
+            oldest_read: self.read_timestamps.first().copied()
+        }
+    }
+
+    pub fn stats(&self) -> ObjectStats {
+        self.stats
+    }
+
+    pub fn footprint(&self) -> ObjectFootprint {
+        let write = size_of::<TransactionId>() + size_of::<TentativeWrite<T>>();
+        let increment = size_of::<TransactionId>() + size_of::<T>();
+        let pending: Vec<_> = self.tentative_writes
+            .keys()
+            .map(|id| (*id, write))
+            .chain(self.increments.keys().map(|id| (*id, increment)))
+            .collect();
+        let bytes = size_of::<Self>() 
+            + pending.iter().map(|(_, bytes)| bytes).sum::<usize>()
+            + self.read_timestamps.len() * size_of::<TransactionId>()
+            + self.pending_since.len() * size_of::<(TransactionId, Instant)>();
+
+        ObjectFootprint { pending, reads: self.read_timestamps.len(), bytes }
+    }
+
+    /// Counts an access that aborted its transaction. Left to the shard, 
+    /// which decides whether a conflict aborts a transaction or wounds the
+    /// newer ones instead.
+    pub fn record_abort(&mut self) {
+        self.stats.aborts += 1;
+    }
+
+    /// The transactions newer than `id` that read the object.
+    pub fn reads_after(&self, id: &TransactionId) -> Vec<TransactionId> {
+        self.read_timestamps
+            .range((Excluded(*id), Unbounded))
+            .copied()
+            .collect()
+    }
+
+    /// The transaction whose write or increment to the object has been 
+    /// pending the longest, and since when by this node's clock. The 
+    /// committed value is current as of then.
+    pub fn oldest_pending(&self) -> Option<(TransactionId, Instant)> {
+        self.pending_since
+            .iter()
+            .min_by_key(|(_, since)| **since)
+            .map(|(id, since)| (*id, *since))
+    }
+
+    /// Whether the committed value no longer exists as of timestamp `ts`, 
+    /// since the commit that created it gave it a lifetime that ends by then.
+    pub fn expired_at(&self, ts: u128) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= ts)
+    }
+
+    /// Whether the committed value no longer exists for transaction `id`. 
+    /// Expiry is decided by the transaction's timestamp rather than by when
+    /// the shard handles it, so that transactions agree on whether the 
+    /// object exists in the order of their timestamps.
+    pub fn expired_for(&self, id: &TransactionId) -> bool {
+        self.expired_at(id.timestamp())
+    }
+
+    /// When the committed value stops existing, if it was given a lifetime.
+    pub fn expires_at(&self) -> Option<u128> {
+        self.expires_at
+    }
+
+    /// The newest timestamp of a transaction that may have seen the object,
+    /// or found it missing in a way that orders it: its last read, the write
+    /// it holds, or the last moment before it expired. Older transactions 
+    /// must not find the object missing once it is removed.
+    pub fn seen_until(&self) -> u128 {
+        let read = self.read_timestamps.last().map_or(0, |read| read.timestamp());
+        let expiry = self.expires_at.map_or(0, |expires_at| expires_at.saturating_sub(1));
+        read.max(self.committed_timestamp.timestamp()).max(expiry)
+    }
+
+    /// Whether transaction `id` may remove the object: nothing newer than it
+    /// read or wrote the object, and no write to it is pending.
+    pub fn can_expire(&self, id: &TransactionId) -> bool {
+        self.tentative_writes.is_empty()
+            && self.increments.is_empty()
+            && &self.committed_timestamp < id
+            && self.read_timestamps.last().is_none_or(|read| read < id)
+    }
+
     pub fn can_reap(&self, aborting_id: &TransactionId) -> bool {
         let only_violation = self.tentative_writes.len() == 1 
             && self.tentative_writes.contains_key(aborting_id);
         
         self.committed_timestamp.is_default() 
+            && self.increments.keys().all(|ts| ts == aborting_id)
             && (self.tentative_writes.is_empty() || only_violation)
+            && self.read_timestamps.iter().all(|ts| ts == aborting_id)
     }
 
     pub fn abort(&mut self, id: &TransactionId) -> Result<(), Infallible> {
         self.tentative_writes.remove(id);
+        self.increments.remove(id);
+        self.pending_since.remove(id);
         self.read_timestamps.remove(id); // TODO confirm we need this
 
         Ok(())
@@ -205,21 +502,21 @@ where
 
 #[cfg(test)]
 mod test {
-    use crate::sharding::{transaction_id::*};
+    use crate::sharding::TransactionIdGenerator;
     use super::*;
 
     fn verify_check_commit_success(object: &TimestampedObject<i64>, id: &TransactionId) {
-        assert!(object.check_commit(&id).is_ok());
+        assert!(object.check_commit(id).is_ok());
     }
 
     fn verify_check_commit_failure(object: &TimestampedObject<i64>, id: &TransactionId, f: CommitFailure<()>) {
-        let check = object.check_commit(&id);
+        let check = object.check_commit(id);
         assert!(check.is_err());
         assert_eq!(check.unwrap_err(), f);
     }
 
     fn verify_commit_success(object: &mut TimestampedObject<i64>, id: &TransactionId, expected: i64) {
-        let commit_res = object.commit(&id);
+        let commit_res = object.commit(id);
         assert!(commit_res.is_ok());
         assert_eq!(commit_res.unwrap(), CommitSuccess::ValueChanged(expected));
         assert_eq!(object.value, expected);
@@ -230,7 +527,7 @@ mod test {
         let original_value = object.value;
         let original_cts = object.committed_timestamp;
 
-        let commit_res = object.commit(&id);
+        let commit_res = object.commit(id);
         assert!(commit_res.is_err());
         assert_eq!(commit_res.unwrap_err(), f);
 
@@ -240,15 +537,15 @@ mod test {
     }
 
     fn verify_read(object: &mut TimestampedObject<i64>, id: &TransactionId, expected: i64) {
-        let read_res = object.read(&id);
+        let read_res = object.read(id);
         assert!(read_res.is_ok());
         assert_eq!(read_res.unwrap(), expected);
     }
 
     #[test]
     fn test_basic_write() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx = id_gen.next();
 
         // Basic write should be able to write with no conflicting transactions
@@ -260,8 +557,8 @@ mod test {
 
     #[test]
     fn test_basic_write_with_update() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx = id_gen.next();
 
         // Basic write should be able to write with no conflicting transactions
@@ -279,8 +576,8 @@ mod test {
 
     #[test]
     fn test_commit_stall() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -305,8 +602,8 @@ mod test {
 
     #[test]
     fn test_write_after_newer_commit() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -322,13 +619,34 @@ mod test {
         // transaction has written and committed a value
         let write_res = object.write(&tx1, 10);
         assert!(write_res.is_err());
-        assert_eq!(write_res.unwrap_err(), RWFailure::Abort);
+        assert_eq!(write_res.unwrap_err(), RWFailure::Abort(tx2));
     }
     
+    #[test]
+    fn test_write_after_newer_read() {
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
+        let tx1 = id_gen.next();
+        let tx2 = id_gen.next();
+        let tx3 = id_gen.next();
+
+        assert!(object.write(&tx1, 10).is_ok());
+        verify_commit_success(&mut object, &tx1, 10);
+
+        // A newer transaction reads the committed value...
+        verify_read(&mut object, &tx3, 10);
+
+        // ...so an older transaction can no longer write, and the abort 
+        // identifies the newer reader it conflicted with
+        let write_res = object.write(&tx2, 20);
+        assert!(write_res.is_err());
+        assert_eq!(write_res.unwrap_err(), RWFailure::Abort(tx3));
+    }
+
     #[test]
     fn test_newer_transaction_writes_first() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -349,8 +667,8 @@ mod test {
 
     #[test]
     fn test_basic_abort() {
-        let mut object = TimestampedObject::<i64>::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::<i64>::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx = id_gen.next();
 
         // Basic write should be able to write with no conflicting transactions
@@ -364,13 +682,13 @@ mod test {
 
         // Ensure that no updates have been made to the object
         assert_eq!(object.value, 0);
-        assert_eq!(object.committed_timestamp, TransactionId::default('A'));
+        assert_eq!(object.committed_timestamp, TransactionId::default(NodeId(0)));
     }
 
     #[test]
     fn test_aborted_transaction_with_future_commits() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -385,7 +703,7 @@ mod test {
 
         // Ensure that no updates have been made to the object
         assert_eq!(object.value, 0);
-        assert_eq!(object.committed_timestamp, TransactionId::default('A'));
+        assert_eq!(object.committed_timestamp, TransactionId::default(NodeId(0)));
 
         // Newer transaction should be able to commit after older transaction
         // was aborted, and the older transaction should not be applied.
@@ -395,8 +713,8 @@ mod test {
 
     #[test]
     fn test_basic_consistency_check_failure() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx = id_gen.next();
 
         // Basic write should be able to write with no conflicting transactions
@@ -411,8 +729,8 @@ mod test {
 
     #[test]
     fn test_consistency_check_failure_with_future_commit() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -435,8 +753,8 @@ mod test {
 
     #[test]
     fn test_basic_read() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -451,8 +769,8 @@ mod test {
 
     #[test]
     fn test_read_before_non_committed_write() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
         let tx3 = id_gen.next();
@@ -467,8 +785,8 @@ mod test {
 
     #[test]
     fn test_read_after_non_committed_write() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
         let tx3 = id_gen.next();
@@ -491,8 +809,8 @@ mod test {
 
     #[test]
     fn test_read_before_committed_write() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -502,13 +820,13 @@ mod test {
 
         let read_res = object.read(&tx1);
         assert!(read_res.is_err());
-        assert_eq!(read_res.unwrap_err(), RWFailure::Abort);
+        assert_eq!(read_res.unwrap_err(), RWFailure::Abort(tx2));
     }
 
     #[test]
     fn test_read_after_write_on_same_tx() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx = id_gen.next();
 
         assert!(object.write(&tx, 10).is_ok());
@@ -522,8 +840,8 @@ mod test {
 
     #[test]
     fn test_read_after_write_on_same_tx_multiple_tx() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -542,8 +860,8 @@ mod test {
 
     #[test]
     fn test_read_after_commit_on_different_tx() {
-        let mut object = TimestampedObject::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -561,8 +879,8 @@ mod test {
 
     #[test]
     fn test_read_created_object() {
-        let mut object = TimestampedObject::<i64>::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::<i64>::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx = id_gen.next();
 
         let read_res = object.read(&tx);
@@ -572,8 +890,8 @@ mod test {
 
     #[test]
     fn test_read_on_unwritten_object() {
-        let mut object = TimestampedObject::<i64>::default('A');
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let mut object = TimestampedObject::<i64>::default(NodeId(0));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -583,4 +901,50 @@ mod test {
         assert!(read_res.is_err());
         assert_eq!(read_res.unwrap_err(), RWFailure::AbortedNotFound);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_ttl_set_by_creating_commit_only() {
+        let mut object = TimestampedObject::<i64>::default(NodeId(0));
+        let at = |ts| TransactionId::at(ts, NodeId(1));
+        let ttl = Duration::from_nanos(30);
+
+        assert!(object.write_expiring(&at(10), 10, Some(ttl)).is_ok());
+        assert!(!object.expired_at(100));
+        verify_commit_success(&mut object, &at(10), 10);
+        assert!(!object.expired_at(39));
+        assert!(object.expired_at(40));
+
+        // A later write cannot extend the life of the object
+        assert!(object.write_expiring(&at(20), 20, Some(Duration::from_secs(3600))).is_ok());
+        assert!(!object.can_expire(&at(30)));
+        verify_commit_success(&mut object, &at(20), 20);
+        assert!(object.expired_at(40));
+
+        // Nor can it be removed by a transaction older than one that read it
+        verify_read(&mut object, &at(35), 20);
+        assert!(!object.can_expire(&at(30)));
+        assert!(object.can_expire(&at(50)));
+    }
+
+    #[test]
+    fn test_expiry_decided_by_transaction_timestamp() {
+        let mut object = TimestampedObject::<i64>::default(NodeId(0));
+        let at = |ts| TransactionId::at(ts, NodeId(1));
+        assert!(object.write_expiring(&at(10), 10, Some(Duration::from_nanos(30))).is_ok());
+        verify_commit_success(&mut object, &at(10), 10);
+
+        // Whenever they arrive, transactions before the expiry see the object
+        // and those after it do not
+        assert_eq!(object.read(&at(45)), Err(RWFailure::AbortedNotFound));
+        verify_read(&mut object, &at(39), 10);
+
+        // A transaction after the expiry creates the object again, with a 
+        // lifetime of its own, and a newer one waits on it
+        assert!(object.write_expiring(&at(50), 7, Some(Duration::from_nanos(100))).is_ok());
+        assert_eq!(object.read(&at(60)), Err(RWFailure::WaitFor(at(50))));
+        verify_commit_success(&mut object, &at(50), 7);
+        verify_read(&mut object, &at(60), 7);
+        assert!(!object.expired_at(149));
+        assert!(object.expired_at(150));
+    }
+}
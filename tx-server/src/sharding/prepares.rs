@@ -0,0 +1,195 @@
+use crate::sharding::TransactionId;
+use std::{collections::BTreeMap, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}};
+use event_listener::Event;
+
+/// The prepares waiting to check their transactions on a shard. When many
+/// queue at once, they check one at a time in timestamp order rather than
+/// in the order they arrived, so that older transactions, which younger
+/// ones may be waiting on, are decided first. A prepare that has to wait on
+/// another transaction gives up its turn while it waits.
+pub struct PrepareQueue {
+    /// Whether prepares are ordered at all, rather than all checking at once
+    enabled: AtomicBool,
+    queued: Mutex<Queued>
+}
+
+/// A waiting prepare: its transaction, and the order it queued in, which
+/// tells repeated prepares of one transaction apart
+type Waiter = (TransactionId, u64);
+
+#[derive(Default)]
+struct Queued {
+    /// The waiting prepares, each woken alone when its turn may have come
+    waiting: BTreeMap<Waiter, Arc<Event>>,
+    queued: u64,
+    checking: bool
+}
+
+impl Queued {
+    /// Wakes the oldest waiting prepare if none is checking.
+    fn wake_next(&self) {
+        if let (false, Some((_, next))) = (self.checking, self.waiting.first_key_value()) {
+            next.notify(1);
+        }
+    }
+}
+
+impl Default for PrepareQueue {
+    fn default() -> Self {
+        Self { enabled: AtomicBool::new(true), queued: Default::default() }
+    }
+}
+
+/// A prepare's place in the queue, held until it is done checking. Dropping
+/// it, including by cancelling the wait for it, lets the next one check.
+pub struct PrepareTurn<'a> {
+    queue: Option<&'a PrepareQueue>,
+    waiter: Waiter,
+    admitted: bool
+}
+
+impl PrepareQueue {
+    /// Turns ordering prepares on or off. It is on by default; prepares that
+    /// are already waiting keep their place.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Waits until no older prepare is waiting and none is checking, and
+    /// returns the turn of `id` to check. Returns at once if ordering is off.
+    /// A transaction prepared again while an earlier prepare of it waits, 
+    /// e.g. by a retried forward, queues behind it.
+    pub async fn admit(&self, id: TransactionId) -> PrepareTurn<'_> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return PrepareTurn { queue: None, waiter: (id, 0), admitted: false };
+        }
+
+        let event = Arc::new(Event::new());
+        let waiter = {
+            let mut queued = self.queued.lock().unwrap();
+            let waiter = (id, queued.queued);
+            queued.queued += 1;
+            queued.waiting.insert(waiter, event.clone());
+            waiter
+        };
+        let mut turn = PrepareTurn { queue: Some(self), waiter, admitted: false };
+        loop {
+            // Listen before looking, so a wake-up in between is not missed
+            let listener = event.listen();
+            {
+                let mut queued = self.queued.lock().unwrap();
+                if !queued.checking && queued.waiting.first_key_value().is_some_and(|(first, _)| *first == waiter) {
+                    queued.waiting.remove(&waiter);
+                    queued.checking = true;
+                    turn.admitted = true;
+                    return turn;
+                }
+            }
+            listener.await;
+        }
+    }
+
+    /// The number of prepares waiting for their turn.
+    #[cfg(test)]
+    pub fn waiting(&self) -> usize {
+        self.queued.lock().unwrap().waiting.len()
+    }
+}
+
+impl Drop for PrepareTurn<'_> {
+    fn drop(&mut self) {
+        let Some(queue) = self.queue else { return };
+        let mut queued = queue.queued.lock().unwrap();
+        if self.admitted {
+            queued.checking = false;
+        } else {
+            queued.waiting.remove(&self.waiter);
+        }
+        queued.wake_next();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tx_common::config::NodeId;
+    use std::{sync::Arc, time::Duration};
+
+    #[tokio::test]
+    async fn test_prepares_admitted_in_timestamp_order() {
+        let queue = Arc::new(PrepareQueue::default());
+        let first = queue.admit(TransactionId::at(5, NodeId(1))).await;
+
+        // While one prepare checks, a younger and an older one queue behind it
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for ts in [9, 3] {
+            let (waiter, order) = (queue.clone(), order.clone());
+            tasks.push(tokio::spawn(async move {
+                let _turn = waiter.admit(TransactionId::at(ts, NodeId(1))).await;
+                order.lock().unwrap().push(ts);
+            }));
+            while queue.waiting() < tasks.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        drop(first);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![3, 9]);
+        assert_eq!(queue.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_prepare_leaves_queue() {
+        let queue = PrepareQueue::default();
+        let first = queue.admit(TransactionId::at(5, NodeId(1))).await;
+        let older = tokio::time::timeout(Duration::from_millis(10), queue.admit(TransactionId::at(1, NodeId(1)))).await;
+        assert!(older.is_err());
+        assert_eq!(queue.waiting(), 0);
+
+        // The cancelled prepare does not hold up a younger one
+        drop(first);
+        let _younger = queue.admit(TransactionId::at(7, NodeId(1))).await;
+    }
+
+    #[tokio::test]
+    async fn test_repeated_prepares_of_one_transaction_both_admitted() {
+        let queue = Arc::new(PrepareQueue::default());
+        let first = queue.admit(TransactionId::at(5, NodeId(1))).await;
+
+        let id = TransactionId::at(3, NodeId(1));
+        let mut repeats = Vec::new();
+        for _ in 0..2 {
+            let waiter = queue.clone();
+            repeats.push(tokio::spawn(async move {
+                let _turn = waiter.admit(id).await;
+            }));
+            while queue.waiting() < repeats.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        // Cancelling one of them leaves the other waiting
+        let cancelled = tokio::time::timeout(Duration::from_millis(10), queue.admit(id)).await;
+        assert!(cancelled.is_err());
+        assert_eq!(queue.waiting(), 2);
+
+        drop(first);
+        for repeat in repeats {
+            tokio::time::timeout(Duration::from_secs(1), repeat).await.unwrap().unwrap();
+        }
+        assert_eq!(queue.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_queue_admits_at_once() {
+        let queue = PrepareQueue::default();
+        queue.set_enabled(false);
+        let _first = queue.admit(TransactionId::at(5, NodeId(1))).await;
+        let _second = queue.admit(TransactionId::at(1, NodeId(1))).await;
+        assert_eq!(queue.waiting(), 0);
+    }
+}
@@ -0,0 +1,147 @@
+use super::{fixture::tx_at, Abort, Checkable, Incrementable, Shard, TransactionId};
+use tokio::task::JoinHandle;
+use std::{fmt::Debug, hash::Hash, sync::Arc, time::Duration};
+
+/// How long an operation may go without finishing before it is considered
+/// blocked. Schedules run with time paused, so time only advances once every
+/// task is waiting and this never slows a test down.
+static BLOCKED_AFTER: Duration = Duration::from_secs(60);
+
+type Outcome<K, T> = Result<Option<T>, Abort<K>>;
+
+/// Drives transactions against a shard one operation at a time so that a
+/// test reads as the schedule it checks, e.g.
+///
+/// ```ignore
+/// let (t1, t2) = (schedule.tx(1), schedule.tx(2));
+/// t1.write(a, 5).ok().await;
+/// let read = t2.read(a).blocked().await;
+/// t1.commit().ok().await;
+/// read.value(5).await;
+/// ```
+///
+/// Tests must run with `#[tokio::test(start_paused = true)]`.
+pub(crate) struct Schedule<K: Eq + Hash, T> {
+    shard: Arc<Shard<K, T>>
+}
+
+pub(crate) struct ScheduledTx<K: Eq + Hash, T> {
+    shard: Arc<Shard<K, T>>,
+    id: TransactionId
+}
+
+/// An operation that has started but whose outcome was not checked yet.
+#[must_use]
+pub(crate) struct Op<K, T> {
+    description: String,
+    handle: JoinHandle<Outcome<K, T>>
+}
+
+impl<K, T> Schedule<K, T>
+where
+    K: 'static + Send + Sync + Clone + Eq + Hash + Debug,
+    T: 'static + Send + Sync + Clone + Default + Checkable + Incrementable + PartialEq + Debug
+{
+    pub(crate) fn new(shard: Arc<Shard<K, T>>) -> Self {
+        Self { shard }
+    }
+
+    /// The transaction at timestamp `ts`, ordered against the fixture's
+    /// committed values by the same timestamps.
+    pub(crate) fn tx(&self, ts: u128) -> ScheduledTx<K, T> {
+        ScheduledTx { shard: self.shard.clone(), id: tx_at(ts) }
+    }
+}
+
+impl<K, T> ScheduledTx<K, T>
+where
+    K: 'static + Send + Sync + Clone + Eq + Hash + Debug,
+    T: 'static + Send + Sync + Clone + Default + Checkable + Incrementable + PartialEq + Debug
+{
+    pub(crate) fn id(&self) -> TransactionId {
+        self.id
+    }
+
+    pub(crate) fn read(&self, object_id: K) -> Op<K, T> {
+        let (shard, id) = (self.shard.clone(), self.id);
+        Op::spawn(format!("{id} read {object_id:?}"), async move {
+            shard.read(&id, &object_id).await.map(Some)
+        })
+    }
+
+    pub(crate) fn write(&self, object_id: K, value: T) -> Op<K, T> {
+        let (shard, id) = (self.shard.clone(), self.id);
+        Op::spawn(format!("{id} write {object_id:?}"), async move {
+            shard.write(&id, object_id, value).await.map(|_| None)
+        })
+    }
+
+    pub(crate) fn increment(&self, object_id: K, by: T) -> Op<K, T> {
+        let (shard, id) = (self.shard.clone(), self.id);
+        Op::spawn(format!("{id} increment {object_id:?}"), async move {
+            shard.increment(&id, object_id, by).await.map(|_| None)
+        })
+    }
+
+    pub(crate) fn commit(&self) -> Op<K, T> {
+        let (shard, id) = (self.shard.clone(), self.id);
+        Op::spawn(format!("{id} commit"), async move {
+            shard.check_commit(&id).await?;
+            shard.commit(&id).await.map(|_| None)
+        })
+    }
+
+    pub(crate) fn abort(&self) -> Op<K, T> {
+        let (shard, id) = (self.shard.clone(), self.id);
+        Op::spawn(format!("{id} abort"), async move {
+            shard.abort(&id).await.unwrap();
+            Ok(None)
+        })
+    }
+}
+
+impl<K, T> Op<K, T>
+where
+    K: 'static + Send + Debug + PartialEq,
+    T: 'static + Send + Debug + PartialEq
+{
+    fn spawn(description: String, op: impl std::future::Future<Output = Outcome<K, T>> + Send + 'static) -> Self {
+        Self { description, handle: tokio::spawn(op) }
+    }
+
+    async fn outcome(self) -> Outcome<K, T> {
+        match tokio::time::timeout(BLOCKED_AFTER, self.handle).await {
+            Ok(outcome) => outcome.unwrap(),
+            Err(_) => panic!("{} is blocked", self.description)
+        }
+    }
+
+    /// Asserts that a read or write succeeded, or a commit or abort finished.
+    pub(crate) async fn ok(self) {
+        let description = self.description.clone();
+        let outcome = self.outcome().await;
+        assert!(outcome.is_ok(), "{description}: {outcome:?}");
+    }
+
+    /// Asserts that a read returned `value`.
+    pub(crate) async fn value(self, value: T) {
+        let description = self.description.clone();
+        assert_eq!(self.outcome().await, Ok(Some(value)), "{description}");
+    }
+
+    /// Asserts that the operation aborted the transaction for `reason`.
+    pub(crate) async fn aborts(self, reason: Abort<K>) {
+        let description = self.description.clone();
+        assert_eq!(self.outcome().await, Err(reason), "{description}");
+    }
+
+    /// Asserts that the operation is waiting on another transaction,
+    /// returning it to check its outcome once it is unblocked.
+    pub(crate) async fn blocked(mut self) -> Self {
+        if let Ok(outcome) = tokio::time::timeout(BLOCKED_AFTER, &mut self.handle).await {
+            panic!("{} was expected to block: {:?}", self.description, outcome.unwrap());
+        }
+
+        self
+    }
+}
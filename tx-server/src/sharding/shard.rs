@@ -1,19 +1,50 @@
-use std::{collections::HashMap, hash::Hash, sync::Arc, convert::Infallible};
-use crate::sharding::{object::*, transaction_id::TransactionId};
-use futures::{future, lock::Mutex, stream::FuturesUnordered};
-use tx_common::config::NodeId;
-use tokio::sync::Notify;
+use std::{collections::{BTreeMap, HashMap, HashSet}, hash::Hash, sync::Arc, convert::Infallible, time::{Duration, SystemTime}};
+use crate::sharding::{object::*, commit_log::{CommitLog, CommitEntry}, counters::{ShardCounters, StripedCounter}, digest::MerkleDigest, finished::FinishedTransactions, hooks::{self, CommitHooks}, prepares::PrepareQueue, TransactionId};
+use futures::{future, lock::Mutex};
+use tx_common::{config::NodeId, admin::{ConcurrencyMode, Decision, ShardCounts}};
+use async_lock::RwLock;
+use event_listener::Event;
 use log::{trace, error};
-use super::{Checkable};
+use super::{Checkable, Incrementable};
 
 #[derive(Debug, Eq, PartialEq)]
-pub enum Abort {
-    ConsistencyCheckFailed,
-    OrderViolation,
+pub enum Abort<K> {
+    /// The consistency check failed on the object identified by the key
+    ConsistencyCheckFailed(K),
+    /// The operation on the object identified by the key violates timestamp 
+    /// ordering with the given newer transaction
+    OrderViolation(K, TransactionId),
     ObjectNotFound,
-    ObjectNotFoundSpecialCase
+    ObjectNotFoundSpecialCase,
+    /// The transaction was aborted to let the given older transaction write
+    /// an object it read (see `ConcurrencyMode::WoundWait`)
+    Wounded(TransactionId),
+    /// The transaction already committed or aborted on the shard, so the 
+    /// operation arrived too late to be part of it
+    AlreadyFinished(Decision),
+    /// The committed value of the object identified by the key is staler 
+    /// than the read allowed
+    TooStale(K),
+    /// The transaction found an object missing, but is old enough that it
+    /// may have seen an object the shard has since removed
+    Swept
 }
 
+/// How far a transaction that accessed a shard got, until it commits or 
+/// aborts there.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Phase {
+    Active,
+    /// The shard found the transaction able to commit, so it may no longer 
+    /// be wounded
+    Prepared,
+    /// The given older transaction wounded the transaction, which must abort
+    Wounded(TransactionId)
+}
+
+/// The number of objects committed between yields to other tasks.
+pub static COMMIT_YIELD_INTERVAL: usize = 64;
+
 pub struct Shard<K, T> 
 where 
     K: Hash + Eq
@@ -26,20 +57,345 @@ where
     // A collection of notifications that are triggered when transactions are
     // resolved. These notifications wake up other operations waiting on pending 
     // transactions to resolve. 
-    notifications: Mutex<HashMap<TransactionId, Arc<Notify>>>
+    notifications: Mutex<HashMap<TransactionId, Arc<Event>>>,
+
+    // The most recent commits that changed objects on this shard, in the 
+    // order they were applied
+    commit_log: Mutex<CommitLog<K, T>>,
+
+    // Callbacks run around every commit
+    hooks: CommitHooks<K, T>,
+
+    // Held shared while a commit is applied and exclusively while a snapshot
+    // is taken, so that snapshots never see part of a commit
+    commit_gate: RwLock<()>,
+
+    // How conflicts are resolved, and the phase of every transaction that 
+    // accessed the shard and has not committed or aborted on it yet
+    mode: std::sync::Mutex<ConcurrencyMode>,
+    phases: Mutex<HashMap<TransactionId, Phase>>,
+
+    // The prepares waiting to check their transactions, in timestamp order
+    prepares: PrepareQueue,
+
+    // The newest transaction that read every object on the shard, which no
+    // older transaction may create an object behind
+    scanned: std::sync::Mutex<Option<TransactionId>>,
+
+    // The objects given a lifetime, by when they expire, and the objects 
+    // created with nothing committed yet, which are all `expire` visits
+    expiring: std::sync::Mutex<BTreeMap<u128, Vec<K>>>,
+    created: std::sync::Mutex<HashSet<K>>,
+
+    // The newest timestamp of a transaction that may have seen an object the
+    // shard removed, at or before which transactions that find an object 
+    // missing abort
+    swept: std::sync::Mutex<u128>,
+
+    // How the transactions that most recently committed or aborted on the 
+    // shard finished
+    finished: std::sync::Mutex<FinishedTransactions>,
+
+    // The reads and writes since contention was last taken, and how many of
+    // them aborted a transaction
+    operations: StripedCounter,
+    conflicts: StripedCounter,
+
+    // What the shard counts about its operations for observability
+    counters: ShardCounters
 }
 
 impl<K, T> Shard<K, T>
 where 
     K: 'static + Send + Clone + Eq + Hash, 
-    T: 'static + Send + Clone + Default + Checkable, 
+    T: 'static + Send + Clone + Default + Checkable + Incrementable, 
 {
     pub fn new(shard_id: NodeId) -> Self {
         Self {
             shard_id,
             objects: Default::default(),
-            notifications: Default::default()
+            notifications: Default::default(),
+            commit_log: Default::default(),
+            hooks: Default::default(),
+            commit_gate: RwLock::new(()),
+            mode: Default::default(),
+            phases: Default::default(),
+            prepares: Default::default(),
+            scanned: Default::default(),
+            expiring: Default::default(),
+            created: Default::default(),
+            swept: Default::default(),
+            finished: Default::default(),
+            operations: Default::default(),
+            conflicts: Default::default(),
+            counters: Default::default()
+        }
+    }
+
+    pub fn mode(&self) -> ConcurrencyMode {
+        *self.mode.lock().unwrap()
+    }
+
+    /// Changes how conflicts are resolved from the next conflict on.
+    pub fn set_mode(&self, mode: ConcurrencyMode) {
+        *self.mode.lock().unwrap() = mode;
+    }
+
+    /// Turns checking queued prepares in timestamp order, rather than all at
+    /// once as they arrive, on or off. It is on by default.
+    pub fn set_prepare_ordering(&self, enabled: bool) {
+        self.prepares.set_enabled(enabled);
+    }
+
+    /// Changes how many finished transactions the shard remembers the 
+    /// outcomes of, and for how long, forgetting the ones it remembers now.
+    pub fn set_finished_retention(&self, capacity: usize, retention: Duration) {
+        *self.finished.lock().unwrap() = FinishedTransactions::new(capacity, retention);
+    }
+
+    /// How a transaction finished on the shard, if it did so recently enough
+    /// for the shard to remember. Asking again gives the same answer until 
+    /// the shard forgets.
+    pub fn outcome(&self, id: &TransactionId) -> Option<Decision> {
+        self.finished.lock().unwrap().get(id)
+    }
+
+    /// Turns counting the shard's operations for `counts` on or off. Counting
+    /// is on by default, and what it counts is lost while it is off.
+    pub fn set_counting(&self, enabled: bool) {
+        self.counters.set_enabled(enabled);
+    }
+
+    /// Returns the operations the shard counted.
+    pub fn counts(&self) -> ShardCounts {
+        self.counters.counts()
+    }
+
+    /// Returns the number of reads and writes since the last call and how 
+    /// many of them aborted a transaction, counting each wounded transaction.
+    pub fn take_contention(&self) -> (u64, u64) {
+        (self.operations.take(), self.conflicts.take())
+    }
+
+    /// Registers an operation by a transaction, failing if it was wounded or
+    /// already finished.
+    async fn enter(&self, id: &TransactionId) -> Result<(), Abort<K>> {
+        if let Some(decision) = self.outcome(id) {
+            return Err(Abort::AlreadyFinished(decision));
+        }
+
+        match self.phases.lock().await.entry(*id).or_insert(Phase::Active) {
+            Phase::Wounded(older) => Err(Abort::Wounded(*older)),
+            _ => Ok(())
+        }
+    }
+
+    /// Marks the transactions in `victims` wounded by `older` unless any of
+    /// them prepared or already finished, returning whether they were.
+    async fn wound(&self, older: &TransactionId, victims: &[TransactionId]) -> bool {
+        let mut phases = self.phases.lock().await;
+        let woundable = victims
+            .iter()
+            .all(|victim| matches!(phases.get(victim), Some(Phase::Active | Phase::Wounded(_))));
+        if woundable {
+            for victim in victims {
+                phases.insert(*victim, Phase::Wounded(*older));
+            }
+            self.conflicts.add(victims.len() as u64);
+        }
+
+        woundable
+    }
+
+    /// Registers a hook run before every commit is applied, receiving the 
+    /// transaction's tentative writes.
+    pub fn on_pre_commit<F>(&self, hook: impl Fn(TransactionId, Arc<Vec<(K, T)>>) -> F + Send + Sync + 'static) 
+    where 
+        F: std::future::Future<Output = ()> + Send + 'static
+    {
+        self.hooks.add_pre_commit(hooks::boxed(hook));
+    }
+
+    /// Registers a hook run after every commit is applied, receiving the 
+    /// objects whose values the commit changed.
+    pub fn on_post_commit<F>(&self, hook: impl Fn(TransactionId, Arc<Vec<(K, T)>>) -> F + Send + Sync + 'static) 
+    where 
+        F: std::future::Future<Output = ()> + Send + 'static
+    {
+        self.hooks.add_post_commit(hooks::boxed(hook));
+    }
+
+    /// Returns the values transaction `id` wrote and has not yet committed.
+    pub async fn tentative_writes(&self, id: &TransactionId) -> Vec<(K, T)> {
+        let objects = self.objects
+            .lock()
+            .await
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+
+        let mut writes = Vec::new();
+        for (key, obj) in objects {
+            if let Some(value) = obj.lock().await.tentative_write(id) {
+                writes.push((key, value));
+            }
+        }
+
+        writes
+    }
+
+    /// Returns the sequence number of the oldest commit still logged, and the
+    /// logged commits after the given sequence number.
+    pub async fn commits_since(&self, seq: u64) -> (u64, Vec<CommitEntry<K, T>>) {
+        let log = self.commit_log.lock().await;
+        (log.first_seq(), log.since(seq))
+    }
+
+    /// Returns the state of every object on the shard, leaving out those that
+    /// only record reads that found them missing. Objects are inspected one
+    /// at a time, so the states are not a consistent snapshot.
+    pub async fn object_states(&self) -> Vec<(K, ObjectState)> {
+        let objects = self.objects
+            .lock()
+            .await
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+
+        let mut states = Vec::with_capacity(objects.len());
+        for (key, obj) in objects {
+            let obj = obj.lock().await;
+            if obj.exists() {
+                states.push((key, obj.state()));
+            }
+        }
+
+        states
+    }
+
+    /// Returns the statistics of every object on the shard. Like 
+    /// `object_states`, it leaves out objects that only record reads that 
+    /// found them missing, and does not read them at a single point in time.
+    pub async fn object_stats(&self) -> Vec<(K, ObjectStats)> {
+        let objects = self.objects
+            .lock()
+            .await
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+
+        let mut stats = Vec::with_capacity(objects.len());
+        for (key, obj) in objects {
+            let obj = obj.lock().await;
+            if obj.exists() {
+                stats.push((key, obj.stats()));
+            }
+        }
+
+        stats
+    }
+
+    /// Returns what every object on the shard holds for unfinished 
+    /// transactions, with the bytes it holds in all, its key included. Like
+    /// `object_states`, the objects are not read at a single point in time.
+    pub async fn footprints(&self) -> Vec<(K, ObjectFootprint)> where K: AsRef<[u8]> {
+        let objects = self.objects
+            .lock()
+            .await
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+
+        let mut footprints = Vec::with_capacity(objects.len());
+        for (key, obj) in objects {
+            let mut footprint = obj.lock().await.footprint();
+            footprint.bytes += size_of::<K>() + key.as_ref().len();
+            footprints.push((key, footprint));
         }
+
+        footprints
+    }
+
+    /// Returns estimates of the bytes held by the shard's log of recent 
+    /// commits and by the outcomes of the transactions it remembers having
+    /// finished. Both are bounded, but fill up under any steady load.
+    pub async fn history_bytes(&self) -> (usize, usize) where K: AsRef<[u8]> {
+        let commit_log = self.commit_log.lock().await.bytes();
+        (commit_log, self.finished.lock().unwrap().bytes())
+    }
+
+    /// Returns the number of transactions whose phase the shard tracks and
+    /// the number of notifications it holds for waiters on transactions. 
+    /// Both only grow if transactions are never committed or aborted.
+    pub async fn tracked(&self) -> (usize, usize) {
+        let phases = self.phases.lock().await.len();
+        (phases, self.notifications.lock().await.len())
+    }
+
+    /// Returns the committed value of every object that has one and has not
+    /// expired, with the transaction that committed it, and the sequence 
+    /// number of the last commit the values include. Commits wait while the 
+    /// snapshot is taken so that none is partly in it, but reads and writes 
+    /// carry on, and the snapshot neither waits on nor aborts any transaction.
+    pub async fn snapshot(&self) -> (u64, Vec<(K, T, TransactionId)>) {
+        let (seq, committed) = self.snapshot_with_expiry().await;
+        (seq, committed.into_iter().map(|(key, value, committed_by, _)| (key, value, committed_by)).collect())
+    }
+
+    /// Takes a snapshot like `snapshot`, along with when each object expires,
+    /// if it was given a lifetime, which is all `restore` needs.
+    pub async fn snapshot_with_expiry(&self) -> (u64, Vec<(K, T, TransactionId, Option<u128>)>) {
+        let _gate = self.commit_gate.write().await;
+        let objects = self.objects
+            .lock()
+            .await
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+
+        let now = now_timestamp();
+        let mut committed = Vec::with_capacity(objects.len());
+        for (key, obj) in objects {
+            let obj = obj.lock().await;
+            let committed_timestamp = obj.state().committed_timestamp;
+            if !committed_timestamp.is_default() && !obj.expired_at(now) {
+                committed.push((key, obj.committed_value().clone(), committed_timestamp, obj.expires_at()));
+            }
+        }
+
+        (self.commit_log.lock().await.last_seq(), committed)
+    }
+
+    /// Fills the shard with committed objects, such as those of a snapshot 
+    /// taken before the node restarted, numbering the commits that follow 
+    /// after `seq`. Meant for a shard that has not served any transaction.
+    pub async fn restore(&self, seq: u64, committed: Vec<(K, T, TransactionId, Option<u128>)>) {
+        let _gate = self.commit_gate.write().await;
+        let mut objects = self.objects.lock().await;
+        for (key, value, committed_by, expires_at) in committed {
+            if let Some(expires_at) = expires_at {
+                self.expiring.lock().unwrap().entry(expires_at).or_default().push(key.clone());
+            }
+            let object = TimestampedObject::restored(self.shard_id, value, committed_by, expires_at);
+            objects.insert(key, Arc::new(Mutex::new(object)));
+        }
+        self.commit_log.lock().await.resume_after(seq);
+    }
+
+    /// Digests the committed value of every object in a snapshot, so that the
+    /// shard's state can be compared with another's without sending it.
+    /// Returns the sequence number of the last commit the digest includes.
+    pub async fn digest(&self) -> (u64, MerkleDigest) where K: Ord, T: Hash {
+        let (seq, committed) = self.snapshot().await;
+        (seq, MerkleDigest::new(committed.iter().map(|(key, value, _)| (key, value))))
+    }
+
+    /// Returns the committed value and state of an object, if it exists.
+    #[cfg(test)]
+    pub async fn inspect(&self, object_id: &K) -> Option<(T, ObjectState)> {
+        let obj = self.get_object(object_id).await?;
+        let guard = obj.lock().await;
+        guard.exists().then(|| (guard.committed_value().clone(), guard.state()))
     }
 
     async fn get_object(&self, object_id: &K) -> Option<Arc<Mutex<TimestampedObject<T>>>> {
@@ -50,61 +406,167 @@ where
             .map(Clone::clone)
     }
 
-    async fn get_object_or_insert_if_valid(&self, object_id: &K, value: &T) -> Option<Arc<Mutex<TimestampedObject<T>>>> {
+    async fn get_object_or_insert(&self, object_id: &K) -> Arc<Mutex<TimestampedObject<T>>> {
+        self.objects
+            .lock()
+            .await
+            .entry(object_id.clone())
+            .or_insert_with(|| {
+                self.created.lock().unwrap().insert(object_id.clone());
+                Arc::new(Mutex::new(TimestampedObject::default(self.shard_id)))
+            })
+            .clone()
+    }
+
+    /// Fails if transaction `id`, which found an object missing, may have
+    /// seen an object that was removed since.
+    fn check_swept(&self, id: &TransactionId) -> Result<(), Abort<K>> {
+        match id.timestamp() <= *self.swept.lock().unwrap() {
+            true => Err(Abort::Swept),
+            false => Ok(())
+        }
+    }
+
+    /// The object to write, created if it does not exist and `value` is a 
+    /// valid initial value. An object that only holds the reads of 
+    /// transactions that found it missing, or that expired for transaction
+    /// `id`, is created by the write too. A transaction older than one that
+    /// read every object may not create an object the read did not see.
+    async fn get_object_or_insert_if_valid(&self, id: &TransactionId, object_id: &K, value: &T) -> Result<Arc<Mutex<TimestampedObject<T>>>, Abort<K>> {
         let mut guard = self.objects
             .lock()
             .await;
 
-        match guard.get(&object_id) {
-            Some(object) => Some(object.clone()),
+        match guard.get(object_id) {
+            Some(object) if value.check().is_err() && {
+                let object = object.lock().await;
+                !object.exists() || object.expired_for(id)
+            } => Err(Abort::ObjectNotFound),
+            Some(object) => Ok(object.clone()),
+            None if value.check().is_err() => Err(Abort::ObjectNotFound),
             None => {
-                if value.check().is_ok() {
-                    guard.insert(object_id.clone(), Arc::new(Mutex::new(TimestampedObject::default(self.shard_id))));
-                    guard.get(&object_id).map(Clone::clone)
-                } else {
-                    None
+                if let Some(scanned) = *self.scanned.lock().unwrap() {
+                    if *id < scanned {
+                        return Err(Abort::OrderViolation(object_id.clone(), scanned));
+                    }
                 }
+                self.check_swept(id)?;
+
+                let object = Arc::new(Mutex::new(TimestampedObject::default(self.shard_id)));
+                guard.insert(object_id.clone(), object.clone());
+                self.created.lock().unwrap().insert(object_id.clone());
+                Ok(object)
             }
         }
     }
 
-    async fn get_notification(&self, id: &TransactionId) -> Arc<Notify> {
+    async fn get_notification(&self, id: &TransactionId) -> Arc<Event> {
         self.notifications
             .lock()
             .await
-            .entry(id.clone())
-            .or_insert(Arc::new(Notify::new()))
+            .entry(*id)
+            .or_insert(Arc::new(Event::new()))
             .clone()
     }
 
+    /// Waits until transaction `id` commits, aborts or releases what it 
+    /// holds on the shard. Returns at once if it already finished, since a 
+    /// waiter that found it pending would otherwise miss the wake-up if it 
+    /// finished in between. A transaction that finished may still be 
+    /// releasing what it holds, so the waiter yields first, lest it retry
+    /// in a loop that keeps the release from running.
+    async fn wait_on(&self, id: &TransactionId) {
+        if self.outcome(id).is_some() {
+            crate::task::yield_now().await;
+            return;
+        }
+
+        let listener = self.get_notification(id).await.listen();
+        if self.outcome(id).is_none() {
+            listener.await;
+        }
+    }
+
     async fn notify_and_remove(&self, id: &TransactionId) {
-        self.notifications
-            .lock()
-            .await
-            .remove(id)
-            .and_then(|notify| Some(notify.notify_waiters()));
+        if let Some(notify) = self.notifications.lock().await.remove(id) {
+            notify.notify(usize::MAX);
+        }
     }
 
-    pub async fn read(&self, id: &TransactionId, object_id: &K) -> Result<T, Abort> where T: Clone, K: std::fmt::Debug {
-        trace!("read(id={id}, object_id={object_id:?})");
+    pub async fn read(&self, id: &TransactionId, object_id: &K) -> Result<T, Abort<K>> where T: Clone, K: std::fmt::Debug {
+        self.read_object(id, object_id, false).await?.map(|(value, _)| value).ok_or(Abort::ObjectNotFound)
+    }
+
+    /// Reads an object like `read`, but answers `None` rather than aborting
+    /// if the object does not exist as of the transaction. The read still 
+    /// counts, so that an older transaction can no longer create the object:
+    /// an object that was never written is created empty to hold the read,
+    /// and stays until it is written, every reader that found it missing 
+    /// aborts, or `expire` removes it.
+    pub async fn read_if_exists(&self, id: &TransactionId, object_id: &K) -> Result<Option<T>, Abort<K>> where T: Clone, K: std::fmt::Debug {
+        Ok(self.read_object(id, object_id, true).await?.map(|(value, _)| value))
+    }
+
+    /// Reads an object, along with the transaction whose write the value is:
+    /// the reader's own if it wrote the object, or the one that committed it.
+    async fn read_object(&self, id: &TransactionId, object_id: &K, if_exists: bool) -> Result<Option<(T, TransactionId)>, Abort<K>> where T: Clone, K: std::fmt::Debug {
+        trace!("read(id={id}, object_id={object_id:?}, if_exists={if_exists})");
+        self.operations.add(1);
+        self.counters.read();
         loop {
-            let obj = match self.get_object(&object_id).await {
+            self.enter(id).await?;
+            let obj = match self.get_object(object_id).await {
                 Some(obj) => obj,
                 None => {
-                    trace!("ABORT read(id={id}, object_id={object_id:?}) -- object does not exist");
-                    return Err(Abort::ObjectNotFound)
+                    if let Err(e) = self.check_swept(id) {
+                        trace!("ABORT read(id={id}, object_id={object_id:?}) -- object may have been removed");
+                        return Err(e)
+                    }
+                    if if_exists {
+                        self.get_object_or_insert(object_id).await
+                    } else {
+                        trace!("ABORT read(id={id}, object_id={object_id:?}) -- object does not exist");
+                        return Err(Abort::ObjectNotFound)
+                    }
                 }
             };
             let mut guard = obj.lock().await;
+            if !guard.exists() {
+                if if_exists {
+                    trace!("read(id={id}, object_id={object_id:?}) DONE -- object does not exist");
+                    guard.read_missing(id);
+                    return Ok(None)
+                }
+                trace!("ABORT read(id={id}, object_id={object_id:?}) -- object does not exist");
+                return Err(Abort::ObjectNotFound)
+            }
 
             match guard.read(id) {
                 Ok(value) => {
                     trace!("read(id={id}, object_id={object_id:?}) DONE");
-                    return Ok(value)
+                    let written_by = match guard.tentative_write(id) {
+                        Some(_) => *id,
+                        None => guard.state().committed_timestamp
+                    };
+                    return Ok(Some((value, written_by)))
+                },
+                Err(RWFailure::Abort(newer)) => {
+                    trace!("ABORT read(id={id}, object_id={object_id:?}) -- timestamp ordering violation with {newer}");
+                    self.conflicts.add(1);
+                    guard.record_abort();
+                    return Err(Abort::OrderViolation(object_id.clone(), newer))
                 },
-                Err(RWFailure::Abort) => {
-                    trace!("ABORT read(id={id}, object_id={object_id:?}) -- timestamp ordering violation");
-                    return Err(Abort::OrderViolation)
+                Err(RWFailure::AbortedNotFound) if if_exists => {
+                    trace!("read(id={id}, object_id={object_id:?}) DONE -- object expired or only exists for newer transactions");
+                    guard.read_missing(id);
+                    return Ok(None)
+                },
+                Err(RWFailure::AbortedNotFound) if guard.expired_for(id) => {
+                    // The read still counts, so that an older transaction 
+                    // can no longer create the object again
+                    trace!("ABORT read(id={id}, object_id={object_id:?}) -- object expired");
+                    guard.read_missing(id);
+                    return Err(Abort::ObjectNotFound)
                 },
                 Err(RWFailure::AbortedNotFound) => {
                     trace!("ABORT read(id={id}, object_id={object_id:?}) -- SPECIAL CASE WHERE OBJECT EXISTS BC OF NEWER TRANSACTION");
@@ -113,33 +575,92 @@ where
                 Err(RWFailure::WaitFor(waiting_on)) => {
                     drop(guard);
                     trace!("read(id={id}, object_id={object_id:?}) waiting on {waiting_on}");
-                    self.get_notification(&waiting_on).await.notified().await;
+                    self.wait_on(&waiting_on).await;
                 }
             }
         }
     }
 
-    pub async fn write(&self, id: &TransactionId, object_id: K, value: T) -> Result<(), Abort> where K: std::fmt::Debug {
+    /// Reads every object that exists as of transaction `id`, like a 
+    /// `read_if_exists` of each, returning their values and the transactions
+    /// whose writes they are, along with the sequence number of the last 
+    /// commit applied once they were read. Since
+    /// every read orders the transaction, the values are a consistent cut:
+    /// they hold every write of the older transactions that commit and none
+    /// of the newer ones. An older transaction may no longer create an object
+    /// on the shard once the objects are listed, since the cut would miss it.
+    /// A transaction that may have seen an object removed since aborts, as 
+    /// the cut would miss that too.
+    pub async fn read_all(&self, id: &TransactionId) -> Result<(u64, Vec<(K, T, TransactionId)>), Abort<K>> where T: Clone, K: std::fmt::Debug {
+        trace!("read_all(id={id})");
+        self.enter(id).await?;
+        self.check_swept(id)?;
+        let object_ids: Vec<_> = {
+            let objects = self.objects.lock().await;
+            let mut scanned = self.scanned.lock().unwrap();
+            *scanned = (*scanned).max(Some(*id));
+            objects.keys().cloned().collect()
+        };
+
+        let mut values = Vec::with_capacity(object_ids.len());
+        for object_id in object_ids {
+            if let Some((value, written_by)) = self.read_object(id, &object_id, true).await? {
+                values.push((object_id, value, written_by));
+            }
+        }
+
+        trace!("read_all(id={id}) DONE");
+        Ok((self.commit_log.lock().await.last_seq(), values))
+    }
+
+    pub async fn write(&self, id: &TransactionId, object_id: K, value: T) -> Result<(), Abort<K>> where K: std::fmt::Debug {
+        self.write_expiring(id, object_id, value, None).await
+    }
+
+    /// Writes an object like `write`. If the write creates the object, the
+    /// object expires `ttl` after the transaction's timestamp: transactions
+    /// from then on read it as not found, it may be removed by `expire`, and
+    /// a write by one of them creates it again, as if it had been removed.
+    pub async fn write_expiring(&self, id: &TransactionId, object_id: K, value: T, ttl: Option<Duration>) -> Result<(), Abort<K>> where K: std::fmt::Debug {
         let obj_id_fmt = format!("{object_id:?}");
         trace!("write(id={id}, object_id={object_id:?})");
+        self.operations.add(1);
+        self.counters.write();
 
         loop {
-            let obj = match self.get_object_or_insert_if_valid(&object_id, &value).await {
-                Some(obj) => obj,
-                None => {
-                    trace!("ABORT write(id={id}, object_id={object_id:?}) -- initial diff is invalid");
-                    return Err(Abort::ObjectNotFound)
+            self.enter(id).await?;
+            let obj = match self.get_object_or_insert_if_valid(id, &object_id, &value).await {
+                Ok(obj) => obj,
+                Err(e) => {
+                    trace!("ABORT write(id={id}, object_id={object_id:?}) -- cannot create the object: {e:?}");
+                    return Err(e)
                 }
             };
             let mut guard = obj.lock().await;
-            match guard.write(id, value.clone()) {
+            match guard.write_expiring(id, value.clone(), ttl) {
                 Ok(_) => {
                     trace!("write(id={id}, object_id={obj_id_fmt}) DONE");
                     return Ok(())
                 },
-                Err(RWFailure::Abort) => {
-                    trace!("ABORT write(id={id}, object_id={obj_id_fmt}) -- timestamp ordering violation");
-                    return Err(Abort::OrderViolation)
+                Err(RWFailure::Abort(newer)) => {
+                    // Only newer reads can be undone, not a newer commit
+                    let victims = guard.reads_after(id);
+                    let woundable = self.mode() == ConcurrencyMode::WoundWait 
+                        && guard.state().committed_timestamp < *id
+                        && !victims.is_empty();
+                    if woundable && self.wound(id, &victims).await {
+                        drop(guard);
+                        trace!("write(id={id}, object_id={obj_id_fmt}) wounded {victims:?}");
+                        for victim in victims.iter() {
+                            self.release(victim).await;
+                        }
+                        continue;
+                    }
+
+                    trace!("ABORT write(id={id}, object_id={obj_id_fmt}) -- timestamp ordering violation with {newer}");
+                    self.conflicts.add(1);
+                    guard.record_abort();
+                    return Err(Abort::OrderViolation(object_id, newer))
                 }
                 Err(RWFailure::AbortedNotFound) => {
                     trace!("ABORT read(id={id}, object_id={object_id:?}) -- SPECIAL CASE WHERE OBJECT EXISTS BC OF NEWER TRANSACTION");
@@ -148,35 +669,116 @@ where
                 Err(RWFailure::WaitFor(waiting_on)) => {
                     drop(guard);
                     trace!("write(id={id}, object_id={obj_id_fmt}) waiting on {waiting_on}");
-                    self.get_notification(&waiting_on).await.notified().await;
+                    self.wait_on(&waiting_on).await;
                 }
             }
         }
     }
 
-    pub async fn check_commit(&self, id: &TransactionId) -> Result<(), Abort> {
+    /// Reads the committed value of an object without recording the read or
+    /// waiting on other transactions, provided the value is at most 
+    /// `max_staleness` old. The committed value is current up to the oldest 
+    /// write still pending on the object, so it is as stale as that write,
+    /// timed from when the write reached this shard so that skew between the
+    /// coordinator's clock and this node's does not count.
+    /// The read is not part of any transaction, so it does not see a 
+    /// transaction's own writes and never aborts a write.
+    pub async fn read_stale(&self, object_id: &K, max_staleness: Duration) -> Result<T, Abort<K>> where T: Clone, K: std::fmt::Debug {
+        trace!("read_stale(object_id={object_id:?}, max_staleness={max_staleness:?})");
+        self.operations.add(1);
+        self.counters.read();
+        let Some(obj) = self.get_object(object_id).await else {
+            return Err(Abort::ObjectNotFound)
+        };
+        let guard = obj.lock().await;
+        let now = now_timestamp();
+        if guard.state().committed_timestamp.is_default() || guard.expired_at(now) {
+            return Err(Abort::ObjectNotFound)
+        }
+
+        match guard.oldest_pending() {
+            Some((pending, since)) if since.elapsed() > max_staleness => {
+                trace!("ABORT read_stale(object_id={object_id:?}) -- pending since {pending}");
+                Err(Abort::TooStale(object_id.clone()))
+            },
+            _ => Ok(guard.committed_value().clone())
+        }
+    }
+
+    /// Adds `by` to an object without reading it, creating the object if it
+    /// does not exist. Transactions that only increment an object never 
+    /// conflict with each other, since increments commit in any order, so 
+    /// an increment only aborts if a newer transaction already read the 
+    /// object or an older one has not yet committed a write to it. Reads of
+    /// the object wait for older increments to commit or abort.
+    pub async fn increment(&self, id: &TransactionId, object_id: K, by: T) -> Result<(), Abort<K>> where K: std::fmt::Debug {
+        trace!("increment(id={id}, object_id={object_id:?})");
+        self.operations.add(1);
+        self.counters.write();
+        self.enter(id).await?;
+        let obj = match self.get_object_or_insert_if_valid(id, &object_id, &by).await {
+            Ok(obj) => obj,
+            Err(e) => {
+                trace!("ABORT increment(id={id}, object_id={object_id:?}) -- cannot create the object: {e:?}");
+                return Err(e)
+            }
+        };
+        let mut guard = obj.lock().await;
+        if guard.expired_for(id) {
+            trace!("ABORT increment(id={id}, object_id={object_id:?}) -- object expired");
+            return Err(Abort::ObjectNotFound)
+        }
+
+        match guard.increment(id, by) {
+            Ok(()) => {
+                trace!("increment(id={id}, object_id={object_id:?}) DONE");
+                Ok(())
+            },
+            Err(RWFailure::Abort(newer)) => {
+                trace!("ABORT increment(id={id}, object_id={object_id:?}) -- timestamp ordering violation with {newer}");
+                self.conflicts.add(1);
+                guard.record_abort();
+                Err(Abort::OrderViolation(object_id, newer))
+            },
+            Err(failure) => unreachable!("increments never wait or miss objects: {failure:?}")
+        }
+    }
+
+    /// Checks that a transaction can commit, marking it prepared. A 
+    /// transaction that already committed can, so a repeated prepare gets 
+    /// the same vote, and one that already aborted cannot. Prepares that 
+    /// queue up check one at a time, oldest first, and one that has to wait
+    /// on another transaction lets the next check in the meantime.
+    pub async fn check_commit(&self, id: &TransactionId) -> Result<(), Abort<K>> where K: std::fmt::Debug {
         trace!("check_commit(id={id})");
+        match self.outcome(id) {
+            Some(Decision::Commit) => return Ok(()),
+            Some(Decision::Abort) => return Err(Abort::AlreadyFinished(Decision::Abort)),
+            None => ()
+        }
+
         loop {
+            let turn = self.prepares.admit(*id).await;
             let map_guard = self.objects.lock().await;
             let tasks = map_guard
-                .values()
-                .map(Clone::clone)
-                .map(|v| {
-                    let tx = id.clone();
-                    tokio::spawn(async move {
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .map(|(k, v)| {
+                    let tx = *id;
+                    crate::task::spawn(format_args!("shard check commit"), async move {
                         let obj = v.lock().await;
-                        obj.check_commit(&tx)
+                        (k, obj.check_commit(&tx))
                     }
-                )}).collect::<FuturesUnordered<_>>();
+                )}).collect::<Vec<_>>();
             drop(map_guard);
 
             let mut wait = None;
             for fut in future::join_all(tasks).await.into_iter() {
-                let commit_res = fut.unwrap();
+                let (key, commit_res) = fut.unwrap();
                 match commit_res {
                     Err(CommitFailure::ConsistencyCheckFailed(e)) => {
                         trace!("ABORT check_commit(id={id}) -- consistency check fail: {e:?}");
-                        return Err(Abort::ConsistencyCheckFailed)
+                        return Err(Abort::ConsistencyCheckFailed(key))
                     },
                     Err(CommitFailure::WaitFor(waiting_on)) => {
                         trace!("check_commit(id={id}) waiting on {waiting_on}");
@@ -188,8 +790,24 @@ where
             }
 
             match wait {
-                Some(wait_on) => self.get_notification(&wait_on).await.notified().await,
+                Some(wait_on) => {
+                    drop(turn);
+                    self.wait_on(&wait_on).await
+                },
                 None => {
+                    let mut phases = self.phases.lock().await;
+                    if let Some(Phase::Wounded(older)) = phases.get(id) {
+                        trace!("ABORT check_commit(id={id}) -- wounded by {older}");
+                        return Err(Abort::Wounded(*older));
+                    }
+                    // An abort that ran meanwhile already removed the phase 
+                    // this would put back
+                    if self.outcome(id) == Some(Decision::Abort) {
+                        trace!("ABORT check_commit(id={id}) -- aborted while checking");
+                        return Err(Abort::AlreadyFinished(Decision::Abort));
+                    }
+
+                    phases.insert(*id, Phase::Prepared);
                     trace!("check_commit(id={id}) DONE");
                     return Ok(())
                 }
@@ -197,32 +815,61 @@ where
         }
     }
 
-    pub async fn commit(&self, id: &TransactionId) -> Result<CommitSuccess<Vec<(K, T)>>, Abort> where K: std::fmt::Debug {
+    /// Applies a transaction's tentative writes. Objects are committed one at
+    /// a time, yielding to other tasks every `COMMIT_YIELD_INTERVAL` objects 
+    /// so that committing to a large shard does not stall unrelated requests.
+    /// Readers never observe part of a commit: a reader newer than the 
+    /// transaction waits on any object the transaction has not committed yet,
+    /// and a reader older than it aborts on any object it has committed. 
+    /// Committing a transaction again changes nothing, and committing one 
+    /// that aborted fails.
+    pub async fn commit(&self, id: &TransactionId) -> Result<CommitSuccess<Vec<(K, T)>>, Abort<K>> where K: std::fmt::Debug {
         trace!("commit(id={id})");
+        match self.outcome(id) {
+            Some(Decision::Commit) => return Ok(CommitSuccess::NoChange(Vec::new())),
+            Some(Decision::Abort) => return Err(Abort::AlreadyFinished(Decision::Abort)),
+            None => ()
+        }
+
+        if self.hooks.has_pre_commit() {
+            let writes = self.tentative_writes(id).await;
+            self.hooks.run_pre_commit(*id, writes).await;
+        }
+
+        let gate = self.commit_gate.read().await;
         loop {
-            let map_guard = self.objects.lock().await;
-            let tasks = map_guard
+            let objects = self.objects
+                .lock()
+                .await
                 .iter()
                 .map(|(k, v)| (k.clone(), v.clone()))
-                .map(|(k, v)| {
-                    let tx = id.clone();
-                    tokio::spawn(async move {
-                        let mut obj = v.lock().await;
-                        (k, obj.commit(&tx))
-                    }
-                )}).collect::<FuturesUnordered<_>>();
-            drop(map_guard);
+                .collect::<Vec<_>>();
 
             let mut wait = None;
             let mut result = Vec::new();
-            for fut in future::join_all(tasks).await.into_iter() {
-                let (key, commit_res) = fut.unwrap();
+            for (i, (key, obj)) in objects.into_iter().enumerate() {
+                if i > 0 && i % COMMIT_YIELD_INTERVAL == 0 {
+                    crate::task::yield_now().await;
+                }
+
+                let commit_res = {
+                    let mut guard = obj.lock().await;
+                    let commit_res = guard.commit(id);
+                    if let (Ok(CommitSuccess::ValueChanged(_)), Some(expires_at)) = (&commit_res, guard.expires_at()) {
+                        let mut expiring = self.expiring.lock().unwrap();
+                        let keys = expiring.entry(expires_at).or_default();
+                        if !keys.contains(&key) {
+                            keys.push(key.clone());
+                        }
+                    }
+                    commit_res
+                };
                 match commit_res {
                     Ok(v) => result.push((key, v)),
                     Err(CommitFailure::ConsistencyCheckFailed(e)) => {
                         error!("SHOULD NOT BE HERE ... commit(id={id}, object_id={key:?}) getting aborted -- {e:?}");
                         self.notify_and_remove(id).await;
-                        return Err(Abort::ConsistencyCheckFailed)
+                        return Err(Abort::ConsistencyCheckFailed(key))
                     },
                     Err(CommitFailure::WaitFor(waiting_on)) => {
                         error!("SHOULD NOT BE HERE ... commit(id={id}, object_id={key:?}) looping -- waiting on {waiting_on}");
@@ -233,9 +880,28 @@ where
             }
 
             match wait {
-                Some(wait_on) => self.get_notification(&wait_on).await.notified().await,
+                Some(wait_on) => self.wait_on(&wait_on).await,
                 None => {
                     trace!("commit(id={id}) DONE");
+                    let changed = result
+                        .iter()
+                        .filter_map(|(k, cr)| match cr {
+                            CommitSuccess::ValueChanged(v) => Some((k.clone(), v.clone())),
+                            CommitSuccess::NoChange(_) => None
+                        })
+                        .collect::<Vec<_>>();
+                    if !changed.is_empty() {
+                        self.commit_log.lock().await.append(*id, changed.clone());
+                    }
+                    drop(gate);
+
+                    if self.hooks.has_post_commit() {
+                        self.hooks.run_post_commit(*id, changed).await;
+                    }
+
+                    self.finished.lock().unwrap().record(*id, Decision::Commit);
+                    self.counters.commit();
+                    self.phases.lock().await.remove(id);
                     self.notify_and_remove(id).await;
                     let did_change = result
                         .iter()
@@ -257,23 +923,135 @@ where
         }
     }
 
-    pub async fn abort(&self, id: &TransactionId) -> Result<(), Infallible> where K: std::fmt::Debug {
+    /// Removes the objects that expired for every transaction that may still
+    /// see them, returning their keys, along with the empty objects left by
+    /// `read_if_exists` and by creates that aborted. Only the objects given a
+    /// lifetime that ended and those created with nothing committed yet are
+    /// visited. The sweep is bounded by `id` or by the oldest transaction 
+    /// still active on the shard, whichever is older: an object read or 
+    /// written by a transaction from the bound on, or with a write pending,
+    /// is left for a later call, since removing it would reorder 
+    /// transactions. A transaction older than what a removed object was seen
+    /// by that reaches the shard later aborts if it finds an object missing.
+    /// The removal of expired objects is logged as a commit of `id`, so 
+    /// followers of the commit log see them go, and snapshots never see part
+    /// of it.
+    pub async fn expire(&self, id: &TransactionId) -> Vec<K> {
+        let _gate = self.commit_gate.read().await;
+        let bound = self.phases.lock().await.keys().min().map_or(*id, |oldest| (*oldest).min(*id));
+        let due: Vec<(u128, K)> = self.expiring
+            .lock()
+            .unwrap()
+            .range(..=bound.timestamp())
+            .flat_map(|(expires_at, keys)| keys.iter().map(|key| (*expires_at, key.clone())))
+            .collect();
+        let created: Vec<K> = self.created.lock().unwrap().iter().cloned().collect();
+
+        let mut objects = self.objects.lock().await;
+        let (mut expired, mut empty, mut settled) = (Vec::new(), Vec::new(), Vec::new());
+        let mut seen_until = 0;
+        for (expires_at, key) in due {
+            let Some(obj) = objects.get(&key) else {
+                settled.push((Some(expires_at), key));
+                continue;
+            };
+            let obj = obj.lock().await;
+            if obj.expires_at() != Some(expires_at) {
+                settled.push((Some(expires_at), key));
+            } else if obj.can_expire(&bound) {
+                seen_until = seen_until.max(obj.seen_until());
+                expired.push(key.clone());
+                settled.push((Some(expires_at), key));
+            }
+        }
+        for key in created {
+            let Some(obj) = objects.get(&key) else {
+                settled.push((None, key));
+                continue;
+            };
+            let obj = obj.lock().await;
+            if !obj.state().committed_timestamp.is_default() {
+                settled.push((None, key));
+            } else if !obj.exists() && obj.can_expire(&bound) {
+                seen_until = seen_until.max(obj.seen_until());
+                empty.push(key.clone());
+                settled.push((None, key));
+            }
+        }
+
+        {
+            let mut swept = self.swept.lock().unwrap();
+            *swept = (*swept).max(seen_until);
+        }
+        for key in expired.iter().chain(empty.iter()) {
+            objects.remove(key);
+        }
+        {
+            let (mut expiring, mut created) = (self.expiring.lock().unwrap(), self.created.lock().unwrap());
+            for (expires_at, key) in settled {
+                match expires_at {
+                    Some(expires_at) => if let Some(keys) = expiring.get_mut(&expires_at) {
+                        keys.retain(|other| *other != key);
+                        if keys.is_empty() {
+                            expiring.remove(&expires_at);
+                        }
+                    },
+                    None => { created.remove(&key); }
+                }
+            }
+        }
+        if !expired.is_empty() {
+            self.commit_log.lock().await.append_removal(*id, expired.clone());
+        }
+
+        expired.extend(empty);
+        expired
+    }
+
+    /// Whether `expire` has anything to visit: an object whose lifetime 
+    /// ended by this node's clock, or one created with nothing committed yet.
+    pub fn expiry_due(&self) -> bool {
+        let now = now_timestamp();
+        self.expiring.lock().unwrap().keys().next().is_some_and(|expires_at| *expires_at <= now)
+            || !self.created.lock().unwrap().is_empty()
+    }
+
+    /// Aborts a transaction, returning whether it had reached the shard. One
+    /// that never read or wrote on the shard holds nothing on its objects, so
+    /// only its outcome is recorded, without visiting every object.
+    pub async fn abort(&self, id: &TransactionId) -> Result<bool, Infallible> where K: std::fmt::Debug {
         trace!("abort({id})");
+        self.finished.lock().unwrap().record(*id, Decision::Abort);
+        self.counters.abort();
+        let touched = self.phases.lock().await.contains_key(id);
+        if touched {
+            self.release(id).await;
+        } else {
+            trace!("abort({id}) -- never reached the shard");
+            self.notify_and_remove(id).await;
+        }
+        self.phases.lock().await.remove(id);
 
+        Ok(touched)
+    }
+
+    /// Undoes everything a transaction did on the shard and wakes the 
+    /// operations waiting on it.
+    async fn release(&self, id: &TransactionId) where K: std::fmt::Debug {
         let mut map_guard = self.objects.lock().await;
         let tasks = map_guard
             .iter()
             .map(|(k, v)| {
                 let k = k.clone();
                 let v = v.clone();
-                let tx = id.clone();
-                tokio::spawn(async move {
+                let tx = *id;
+                crate::task::spawn(format_args!("shard release"), async move {
                     let mut obj = v.lock().await;
                     obj.abort(&tx).unwrap();
 
                     (k, obj.can_reap(&tx))
                 }
-            )}).collect::<FuturesUnordered<_>>();
+            )}).collect::<Vec<_>>();
 
         trace!("abort({id}) -- beginning reap");
 
@@ -290,29 +1068,426 @@ where
         drop(map_guard);
         trace!("abort({id}) -- reap finished");
         self.notify_and_remove(id).await;
-
-        Ok(())
     }
 }
 
+/// The current time as a transaction timestamp, for reads that are not part
+/// of any transaction.
+fn now_timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Clock may have gone backwards")
+        .as_nanos()
+}
+
 #[cfg(test)]
 mod test {
     use tokio::{task::JoinHandle, time::sleep};
-    use crate::sharding::{transaction_id::*};
+    use crate::sharding::TransactionIdGenerator;
     use std::time::{Duration, Instant};
     use super::*;
 
     async fn verify_commit(shard: &Arc<Shard<i32, i64>>, id: &TransactionId, expected: Vec<(i32, i64)>) {
-        assert!(shard.check_commit(&id).await.is_ok());
-        let commit_res = shard.commit(&id).await;
+        assert!(shard.check_commit(id).await.is_ok());
+        let commit_res = shard.commit(id).await;
         assert!(commit_res.is_ok());
         assert_eq!(commit_res.unwrap(), CommitSuccess::ValueChanged(expected));
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_operations_after_finish_are_refused() {
+        use crate::sharding::fixture::tx_at;
+
+        let shard: Shard<i32, i64> = Shard::new(NodeId(0));
+        assert!(shard.write(&tx_at(100), 1, 5).await.is_ok());
+        assert!(shard.commit(&tx_at(100)).await.is_ok());
+        assert!(shard.abort(&tx_at(200)).await.is_ok());
+
+        // Late writes leave no tentative write behind, nor create an object
+        assert_eq!(shard.write(&tx_at(100), 1, 6).await, Err(Abort::AlreadyFinished(Decision::Commit)));
+        assert_eq!(shard.write(&tx_at(200), 2, 6).await, Err(Abort::AlreadyFinished(Decision::Abort)));
+        assert_eq!(shard.read(&tx_at(200), &1).await, Err(Abort::AlreadyFinished(Decision::Abort)));
+        assert_eq!(shard.object_states().await.len(), 1);
+        assert_eq!(shard.tentative_writes(&tx_at(100)).await, vec![]);
+
+        // Prepares and commits are answered the same way every time
+        for _ in 0..2 {
+            assert_eq!(shard.check_commit(&tx_at(100)).await, Ok(()));
+            assert_eq!(shard.commit(&tx_at(100)).await, Ok(CommitSuccess::NoChange(vec![])));
+            assert_eq!(shard.check_commit(&tx_at(200)).await, Err(Abort::AlreadyFinished(Decision::Abort)));
+            assert_eq!(shard.commit(&tx_at(200)).await, Err(Abort::AlreadyFinished(Decision::Abort)));
+        }
+        assert_eq!(shard.outcome(&tx_at(100)), Some(Decision::Commit));
+        assert_eq!(shard.outcome(&tx_at(300)), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_object_stats_count_accesses_and_aborts() {
+        use crate::sharding::fixture::tx_at;
+
+        let shard: Shard<i32, i64> = Shard::new(NodeId(0));
+        assert!(shard.write(&tx_at(100), 1, 5).await.is_ok());
+        assert!(shard.write(&tx_at(100), 1, 6).await.is_ok());
+        assert!(shard.commit(&tx_at(100)).await.is_ok());
+        assert_eq!(shard.read(&tx_at(300), &1).await, Ok(6));
+
+        // An older write after a newer read aborts on the object
+        assert_eq!(shard.write(&tx_at(200), 1, 7).await, Err(Abort::OrderViolation(1, tx_at(300))));
+        assert_eq!(shard.read(&tx_at(300), &2).await, Err(Abort::ObjectNotFound));
+
+        // Probing a missing account leaves no account behind to report
+        assert_eq!(shard.read_if_exists(&tx_at(300), &3).await, Ok(None));
+        assert!(shard.inspect(&3).await.is_none());
+        assert_eq!(shard.object_states().await.len(), 1);
+
+        let stats = shard.object_stats().await;
+        let expected = ObjectStats { reads: 1, writes: 2, aborts: 1, commits: 1, last_committer: Some(tx_at(100)) };
+        assert_eq!(stats, vec![(1, expected)]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_fixture_orders_operations_against_committed_state() {
+        use crate::sharding::fixture::*;
+
+        let shard = ShardFixture::new(NodeId(0))
+            .committed(1, 10, 100)
+            .committed(2, 5, 300)
+            .build()
+            .await;
+        assert_committed(&shard, &1, 10, 100).await;
+
+        assert_eq!(shard.write(&tx_at(200), 2, 7).await, Err(Abort::OrderViolation(2, tx_at(300))));
+        assert_eq!(shard.read(&tx_at(200), &1).await, Ok(10));
+        assert!(shard.write(&tx_at(400), 1, 11).await.is_ok());
+        let state = ObjectState { committed_timestamp: tx_at(100), oldest_tentative_write: Some(tx_at(400)), oldest_read: Some(tx_at(200)) };
+        assert_state(&shard, &1, Some(state)).await;
+
+        // Committing prunes the reads it makes irrelevant
+        assert!(shard.commit(&tx_at(400)).await.is_ok());
+        assert_committed(&shard, &1, 11, 400).await;
+        let state = ObjectState { committed_timestamp: tx_at(400), oldest_tentative_write: None, oldest_read: None };
+        assert_state(&shard, &1, Some(state)).await;
+        assert_state(&shard, &3, None).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_commit_hooks_see_tentative_and_committed_writes() {
+        use crate::sharding::fixture::{tx_at, ShardFixture};
+
+        let shard = ShardFixture::new(NodeId(0)).committed(1, 10, 100).build().await;
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for stage in ["pre", "post"] {
+            let calls = calls.clone();
+            let hook = move |tx_id: TransactionId, writes: Arc<Vec<(i32, i64)>>| {
+                let calls = calls.clone();
+                async move {
+                    let mut writes = writes.to_vec();
+                    writes.sort();
+                    calls.lock().unwrap().push((stage, tx_id, writes));
+                }
+            };
+
+            match stage {
+                "pre" => shard.on_pre_commit(hook),
+                _ => shard.on_post_commit(hook)
+            }
+        }
+
+        let tx = tx_at(200);
+        assert!(shard.write(&tx, 1, 10).await.is_ok());
+        assert!(shard.write(&tx, 2, 5).await.is_ok());
+        assert!(shard.commit(&tx).await.is_ok());
+
+        let calls = calls.lock().unwrap().clone();
+        assert_eq!(calls, vec![("pre", tx, vec![(1, 10), (2, 5)]), ("post", tx, vec![(1, 10), (2, 5)])]);
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn test_schedule_of_reads_waiting_on_older_writes() {
+        use crate::sharding::{fixture::ShardFixture, schedule::Schedule};
+
+        let schedule = Schedule::new(ShardFixture::new(NodeId(0)).committed(1, 10, 100).build().await);
+        let (t1, t2, t3) = (schedule.tx(200), schedule.tx(300), schedule.tx(400));
+
+        t1.write(1, 20).ok().await;
+        t2.write(1, 30).ok().await;
+        let read = t3.read(1).blocked().await;
+        let commit = t2.commit().blocked().await;
+
+        // t3 reads what t2 wrote once t1 steps out of the way
+        t1.abort().ok().await;
+        commit.ok().await;
+        read.value(30).await;
+
+        // t1 already aborted, and t4 is older than t3, which already read 
+        // the object
+        t1.write(1, 5).aborts(Abort::AlreadyFinished(Decision::Abort)).await;
+        let t4 = schedule.tx(250);
+        t4.write(1, 5).aborts(Abort::OrderViolation(1, t3.id())).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_stale_reads_do_not_wait_on_pending_writes() {
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut ids = TransactionIdGenerator::new(NodeId(0));
+        let (t1, t2) = (ids.next(), ids.next());
+        assert!(shard.write(&t1, 1, 5).await.is_ok());
+        assert_eq!(shard.read_stale(&1, Duration::from_secs(60)).await, Err(Abort::ObjectNotFound));
+        verify_commit(&shard, &t1, vec![(1, 5)]).await;
+
+        // The committed value is served while a write is pending, until the 
+        // write has been pending for longer than the read allows
+        assert!(shard.write(&t2, 1, 7).await.is_ok());
+        assert_eq!(shard.read_stale(&1, Duration::from_secs(60)).await, Ok(5));
+        sleep(Duration::from_millis(5)).await;
+        assert_eq!(shard.read_stale(&1, Duration::from_millis(1)).await, Err(Abort::TooStale(1)));
+        assert_eq!(shard.read_stale(&2, Duration::from_secs(60)).await, Err(Abort::ObjectNotFound));
+
+        // Stale reads are not recorded, so the write commits as usual
+        verify_commit(&shard, &t2, vec![(1, 7)]).await;
+        assert_eq!(shard.read_stale(&1, Duration::ZERO).await, Ok(7));
+
+        // Staleness is timed by the shard, however far behind its clock the 
+        // coordinator that took the timestamps is
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let (t3, t4) = (TransactionId::at(1, NodeId(1)), TransactionId::at(2, NodeId(1)));
+        assert!(shard.write(&t3, 1, 5).await.is_ok());
+        verify_commit(&shard, &t3, vec![(1, 5)]).await;
+        assert!(shard.write(&t4, 1, 7).await.is_ok());
+        assert_eq!(shard.read_stale(&1, Duration::from_secs(60)).await, Ok(5));
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn test_schedule_of_increments_that_do_not_conflict() {
+        use crate::sharding::{fixture::ShardFixture, schedule::Schedule};
+
+        let schedule = Schedule::new(ShardFixture::new(NodeId(0)).committed(1, 10, 100).build().await);
+        let (t1, t2, t3) = (schedule.tx(200), schedule.tx(300), schedule.tx(400));
+
+        // Increments commit in any order, and a transaction reads its own
+        t2.increment(1, 5).ok().await;
+        t1.increment(1, 3).ok().await;
+        t1.read(1).value(13).await;
+        t2.commit().ok().await;
+        let read = t3.read(1).blocked().await;
+        t1.commit().ok().await;
+        read.value(18).await;
+
+        // An increment older than a read of the object is too late, even once
+        // newer increments committed
+        let t5 = schedule.tx(500);
+        t5.increment(1, 1).ok().await;
+        t5.commit().ok().await;
+        let t4 = schedule.tx(350);
+        t4.increment(1, 1).aborts(Abort::OrderViolation(1, t3.id())).await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn test_wound_wait_aborts_newer_readers_that_did_not_prepare() {
+        use crate::sharding::{fixture::ShardFixture, schedule::Schedule};
+
+        let shard = ShardFixture::new(NodeId(0)).committed(1, 10, 100).committed(2, 10, 100).build().await;
+        shard.set_mode(ConcurrencyMode::WoundWait);
+        let schedule = Schedule::new(shard.clone());
+        let (t1, t2, t3) = (schedule.tx(200), schedule.tx(300), schedule.tx(400));
+
+        // The older writer takes the object from the newer reader
+        t2.read(1).value(10).await;
+        t1.write(1, 20).ok().await;
+        t2.read(2).aborts(Abort::Wounded(t1.id())).await;
+        t2.commit().aborts(Abort::Wounded(t1.id())).await;
+        t2.abort().ok().await;
+
+        // A reader that prepared is not wounded, so the writer aborts instead
+        t3.read(2).value(10).await;
+        assert!(shard.check_commit(&t3.id()).await.is_ok());
+        t1.write(2, 20).aborts(Abort::OrderViolation(2, t3.id())).await;
+
+        // Both the wound and the abort count, as do the fixture's writes
+        assert_eq!(shard.take_contention(), (7, 2));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_expired_objects_read_as_not_found_until_removed() {
+        use crate::sharding::fixture::{ShardFixture, assert_state, tx_at};
+
+        let shard = ShardFixture::new(NodeId(0)).committed(2, 5, 100).build().await;
+        assert!(shard.write_expiring(&tx_at(200), 1, 10, Some(Duration::from_nanos(400))).await.is_ok());
+        assert!(shard.commit(&tx_at(200)).await.is_ok());
+
+        // Whether the object exists is decided by each transaction's timestamp
+        assert_eq!(shard.read(&tx_at(700), &1).await, Err(Abort::ObjectNotFound));
+        assert_eq!(shard.read(&tx_at(550), &1).await, Ok(10));
+        assert_eq!(shard.read_if_exists(&tx_at(650), &1).await, Ok(None));
+        assert_eq!(shard.write(&tx_at(700), 1, -1).await, Err(Abort::ObjectNotFound));
+        assert_eq!(shard.increment(&tx_at(700), 1, 1).await, Err(Abort::ObjectNotFound));
+        for ts in [550, 650, 700] {
+            assert!(shard.commit(&tx_at(ts)).await.is_ok());
+        }
+
+        // Removing the object before the newer read would reorder them
+        assert!(shard.expire(&tx_at(500)).await.is_empty());
+        assert!(shard.expire(&tx_at(690)).await.is_empty());
+        assert_eq!(shard.expire(&tx_at(800)).await, vec![1]);
+        assert_state(&shard, &1, None).await;
+        assert!(shard.inspect(&2).await.is_some());
+
+        // The removal is logged like a commit
+        let (_, entries) = shard.commits_since(0).await;
+        let last = entries.last().unwrap();
+        assert_eq!((last.tx_id, last.writes.clone(), last.removed.clone()), (tx_at(800), vec![], vec![1]));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_expired_objects_created_again_before_removal() {
+        use crate::sharding::fixture::{ShardFixture, tx_at};
+
+        let shard = ShardFixture::<i32, i64>::new(NodeId(0)).build().await;
+        assert!(shard.write_expiring(&tx_at(200), 1, 10, Some(Duration::from_nanos(100))).await.is_ok());
+        assert!(shard.commit(&tx_at(200)).await.is_ok());
+
+        // A transaction the object expired for creates it anew, with a 
+        // lifetime of its own, while one before the expiry still sees it
+        assert_eq!(shard.read(&tx_at(400), &1).await, Err(Abort::ObjectNotFound));
+        assert!(shard.write_expiring(&tx_at(400), 1, 3, Some(Duration::from_nanos(1000))).await.is_ok());
+        assert_eq!(shard.read(&tx_at(250), &1).await, Ok(10));
+        assert!(shard.check_commit(&tx_at(400)).await.is_ok());
+        assert!(shard.commit(&tx_at(400)).await.is_ok());
+        assert_eq!(shard.read(&tx_at(1300), &1).await, Ok(3));
+        assert_eq!(shard.read(&tx_at(1400), &1).await, Err(Abort::ObjectNotFound));
+
+        // An older transaction cannot create it again once a newer one found
+        // it missing
+        assert_eq!(shard.write(&tx_at(1350), 1, 1).await, Err(Abort::OrderViolation(1, tx_at(1400))));
+        assert!(shard.expire(&tx_at(1300)).await.is_empty());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_probes_outlive_older_transactions_still_running() {
+        use crate::sharding::fixture::{ShardFixture, tx_at};
+
+        let shard = ShardFixture::<i32, i64>::new(NodeId(0)).build().await;
+        assert_eq!(shard.read(&tx_at(100), &2).await, Err(Abort::ObjectNotFound));
+        assert_eq!(shard.read_if_exists(&tx_at(300), &1).await, Ok(None));
+        assert!(shard.commit(&tx_at(300)).await.is_ok());
+
+        // The older transaction is still running, so the probe stays to keep
+        // it from creating the object behind the reader
+        assert!(shard.expire(&tx_at(400)).await.is_empty());
+        assert_eq!(shard.write(&tx_at(100), 1, 10).await, Err(Abort::OrderViolation(1, tx_at(300))));
+        shard.abort(&tx_at(100)).await.unwrap();
+
+        // Once it finished the probe goes, and a transaction older than the
+        // reader that arrives later still cannot create the object
+        assert_eq!(shard.expire(&tx_at(400)).await, vec![1]);
+        assert_eq!(shard.write(&tx_at(200), 1, 10).await, Err(Abort::Swept));
+        assert_eq!(shard.read_if_exists(&tx_at(250), &1).await, Err(Abort::Swept));
+        assert!(shard.write(&tx_at(500), 1, 10).await.is_ok());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_expired_objects_outlive_older_writers_still_running() {
+        use crate::sharding::fixture::{ShardFixture, tx_at};
+
+        let shard = ShardFixture::<i32, i64>::new(NodeId(0)).build().await;
+        assert!(shard.write_expiring(&tx_at(200), 1, 10, Some(Duration::from_nanos(400))).await.is_ok());
+        assert!(shard.commit(&tx_at(200)).await.is_ok());
+
+        // A transaction before the expiry still sees the object, so it stays
+        // while the transaction runs, and its deposit adds to the balance 
+        // rather than create the object again
+        assert_eq!(shard.read(&tx_at(500), &1).await, Ok(10));
+        assert!(shard.expire(&tx_at(800)).await.is_empty());
+        assert!(shard.write(&tx_at(500), 1, 15).await.is_ok());
+        assert!(shard.check_commit(&tx_at(500)).await.is_ok());
+        assert!(shard.commit(&tx_at(500)).await.is_ok());
+        assert_eq!(shard.read(&tx_at(550), &1).await, Ok(15));
+        assert!(shard.commit(&tx_at(550)).await.is_ok());
+
+        // Once it finished the object goes, and a transaction before the 
+        // expiry that arrives later aborts rather than find it missing
+        assert_eq!(shard.expire(&tx_at(800)).await, vec![1]);
+        assert_eq!(shard.read(&tx_at(560), &1).await, Err(Abort::Swept));
+        assert_eq!(shard.increment(&tx_at(570), 1, 5).await, Err(Abort::Swept));
+        assert_eq!(shard.read(&tx_at(900), &1).await, Err(Abort::ObjectNotFound));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_probes_of_missing_objects_order_later_creates() {
+        use crate::sharding::fixture::{ShardFixture, assert_state, tx_at};
+
+        let shard = ShardFixture::new(NodeId(0)).committed(2, 5, 100).build().await;
+        assert_eq!(shard.read_if_exists(&tx_at(300), &2).await, Ok(Some(5)));
+        assert_eq!(shard.read_if_exists(&tx_at(300), &1).await, Ok(None));
+        assert_eq!(shard.read(&tx_at(400), &1).await, Err(Abort::ObjectNotFound));
+
+        // An older transaction can no longer create what a newer one found
+        // missing, but the prober itself can
+        assert_eq!(shard.write(&tx_at(200), 1, 10).await, Err(Abort::OrderViolation(1, tx_at(300))));
+        assert!(shard.write(&tx_at(300), 1, 7).await.is_ok());
+        assert!(shard.commit(&tx_at(300)).await.is_ok());
+        assert_eq!(shard.read_if_exists(&tx_at(400), &1).await, Ok(Some(7)));
+        shard.abort(&tx_at(200)).await.unwrap();
+        assert!(shard.commit(&tx_at(400)).await.is_ok());
+
+        // The empty object left by a probe goes once it no longer orders anything
+        assert_eq!(shard.read_if_exists(&tx_at(500), &3).await, Ok(None));
+        shard.abort(&tx_at(500)).await.unwrap();
+        assert_state(&shard, &3, None).await;
+        assert_eq!(shard.read_if_exists(&tx_at(600), &4).await, Ok(None));
+        assert!(shard.commit(&tx_at(600)).await.is_ok());
+        assert!(shard.expire(&tx_at(550)).await.is_empty());
+        assert_eq!(shard.expire(&tx_at(700)).await, vec![4]);
+        assert!(shard.snapshot().await.1.iter().all(|(key, ..)| *key != 4));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_read_all_is_a_cut_at_the_reader() {
+        use crate::sharding::fixture::{ShardFixture, tx_at};
+
+        let shard = ShardFixture::new(NodeId(0)).committed(1, 10, 100).committed(2, 20, 100).build().await;
+        assert!(shard.write(&tx_at(200), 1, 15).await.is_ok());
+        assert!(shard.write(&tx_at(400), 3, 5).await.is_ok());
+
+        // The reader waits on the older write but does not see the newer one
+        let reader = tokio::spawn({
+            let shard = shard.clone();
+            async move { shard.read_all(&tx_at(300)).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!reader.is_finished());
+        assert!(shard.commit(&tx_at(200)).await.is_ok());
+        let (_, mut values) = reader.await.unwrap().unwrap();
+        values.sort();
+        assert_eq!(values, vec![(1, 15, tx_at(200)), (2, 20, tx_at(100))]);
+
+        // An older transaction can no longer create an object behind the 
+        // reader, nor write one it read, but a newer one can
+        assert_eq!(shard.write(&tx_at(250), 4, 1).await, Err(Abort::OrderViolation(4, tx_at(300))));
+        assert_eq!(shard.increment(&tx_at(250), 2, 1).await, Err(Abort::OrderViolation(2, tx_at(300))));
+        assert!(shard.write(&tx_at(350), 4, 1).await.is_ok());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_aborts_of_transactions_that_never_arrived_skip_cleanup() {
+        use crate::sharding::fixture::{ShardFixture, assert_committed, tx_at};
+
+        let shard = ShardFixture::new(NodeId(0)).committed(1, 5, 100).build().await;
+        assert!(shard.write(&tx_at(200), 1, 7).await.is_ok());
+        assert_eq!(shard.abort(&tx_at(200)).await, Ok(true));
+        assert_eq!(shard.abort(&tx_at(300)).await, Ok(false));
+
+        // Either way the outcome is recorded, so a late operation is refused
+        assert_eq!(shard.read(&tx_at(300), &1).await, Err(Abort::AlreadyFinished(Decision::Abort)));
+        assert_eq!(shard.read(&tx_at(400), &1).await, Ok(5));
+        assert!(shard.commit(&tx_at(400)).await.is_ok());
+        assert_committed(&shard, &1, 5, 100).await;
+    }
+
     #[test_log::test(tokio::test(flavor="multi_thread", worker_threads=2))]
     async fn test_basic_write_stall() {
-        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new('A'));
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -340,10 +1515,31 @@ mod test {
         assert!(join_tx1.await.unwrap() < join_tx2.await.unwrap());
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_prepare_aborted_while_waiting_is_refused() {
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
+        let tx1 = id_gen.next();
+        let tx2 = id_gen.next();
+        assert!(shard.write(&tx1, 1, 10).await.is_ok());
+        assert!(shard.write(&tx2, 1, 20).await.is_ok());
+
+        // The newer transaction's prepare waits on the older one, and is
+        // aborted meanwhile, such as by a coordinator that gave up on its vote
+        let shard_clone = shard.clone();
+        let prepare = tokio::spawn(async move { shard_clone.check_commit(&tx2).await });
+        sleep(Duration::from_millis(50)).await;
+        assert!(shard.abort(&tx2).await.is_ok());
+        verify_commit(&shard, &tx1, vec![(1, 10)]).await;
+
+        assert_eq!(prepare.await.unwrap(), Err(Abort::AlreadyFinished(Decision::Abort)));
+        assert_eq!(shard.tracked().await.0, 0);
+    }
+
     #[test_log::test(tokio::test(flavor="multi_thread", worker_threads=2))]
     async fn test_aborted_write_stall() {
-        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new('A'));
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -375,8 +1571,8 @@ mod test {
 
     #[test_log::test(tokio::test(flavor="multi_thread", worker_threads=2))]
     async fn test_aborted_initial_invalid_write() {
-        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new('A'));
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx = id_gen.next();
 
         // Ensure that an invalid write will not create an object
@@ -390,8 +1586,8 @@ mod test {
 
     #[test_log::test(tokio::test(flavor="multi_thread", worker_threads=2))]
     async fn test_read_after_non_committed_write() {
-        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new('A'));
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
         let tx3 = id_gen.next();
@@ -422,8 +1618,8 @@ mod test {
 
     #[test_log::test(tokio::test(flavor="multi_thread", worker_threads=2))]
     async fn test_read_after_aborted_write() {
-        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new('A'));
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
 
@@ -441,7 +1637,7 @@ mod test {
             // Try to commit, but fail due to failed consistency check
             let check_res = shard_clone1.check_commit(&tx1).await;
             assert!(check_res.is_err());
-            assert_eq!(check_res.unwrap_err(), Abort::ConsistencyCheckFailed);
+            assert_eq!(check_res.unwrap_err(), Abort::ConsistencyCheckFailed(1));
 
             // Abort the transaction
             assert!(shard_clone1.abort(&tx1).await.is_ok());
@@ -468,8 +1664,8 @@ mod test {
     
     #[test_log::test(tokio::test(flavor="multi_thread", worker_threads=2))]
     async fn test_write_after_aborted_write_and_read() {
-        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new('A'));
-        let mut id_gen = TransactionIdGenerator::new('B');
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
         let tx1 = id_gen.next();
         let tx2 = id_gen.next();
         let tx3 = id_gen.next();
@@ -502,14 +1698,81 @@ mod test {
         // consistency check upon attempting to commit
         let check_res = shard.check_commit(&tx1).await;
         assert!(check_res.is_err());
-        assert_eq!(check_res.unwrap_err(), Abort::ConsistencyCheckFailed);
+        assert_eq!(check_res.unwrap_err(), Abort::ConsistencyCheckFailed(1));
+        let resolved_at = Instant::now();
         assert!(shard.abort(&tx1).await.is_ok());
 
         // The read transaction should finish after the oldest transaction 
-        // finishes since the read must wait for the oldest write to resolve
-        assert!(Instant::now() < join_tx2.await.unwrap());
+        // is resolved since the read must wait for the oldest write. The 
+        // abort wakes the read before it returns, so the read may finish 
+        // before the abort does.
+        assert!(resolved_at < join_tx2.await.unwrap());
 
         // Verify that the newest write following the aborts will be committed
         verify_commit(&shard, &tx3, vec![(1, 10)]).await;
     }
+
+    #[test_log::test(tokio::test)]
+    async fn test_large_commit_yields_to_unrelated_reads() {
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
+        let (tx1, tx2, tx3) = (id_gen.next(), id_gen.next(), id_gen.next());
+
+        assert!(shard.write(&tx1, 0, 10).await.is_ok());
+        assert!(shard.commit(&tx1).await.is_ok());
+        for k in 1..(COMMIT_YIELD_INTERVAL as i32 * 16) {
+            assert!(shard.write(&tx2, k, 10).await.is_ok());
+        }
+
+        let shard_clone = shard.clone();
+        let commit = tokio::spawn(async move { shard_clone.commit(&tx2).await });
+        tokio::task::yield_now().await;
+
+        // The runtime is single threaded, so the read can only complete now if
+        // the commit yielded part way through
+        assert_eq!(shard.read(&tx3, &0).await, Ok(10));
+        assert!(!commit.is_finished());
+        assert!(commit.await.unwrap().is_ok());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_commit_is_never_partially_visible() {
+        let shard: Arc<Shard<i32, i64>> = Arc::new(Shard::new(NodeId(0)));
+        let mut id_gen = TransactionIdGenerator::new(NodeId(1));
+        let (tx1, older, tx2, newer) = (id_gen.next(), id_gen.next(), id_gen.next(), id_gen.next());
+        let keys = 0..(COMMIT_YIELD_INTERVAL as i32 * 4);
+
+        for k in keys.clone() {
+            assert!(shard.write(&tx1, k, 10).await.is_ok());
+        }
+        assert!(shard.commit(&tx1).await.is_ok());
+        for k in keys.clone() {
+            assert!(shard.write(&tx2, k, 20).await.is_ok());
+        }
+
+        let shard_clone = shard.clone();
+        let commit = tokio::spawn(async move { shard_clone.commit(&tx2).await });
+        tokio::task::yield_now().await;
+        assert!(!commit.is_finished());
+
+        // A reader older than the commit sees only the previous values until 
+        // it reaches an object the commit has already applied, then aborts
+        let mut older_reads = Vec::new();
+        for k in keys.clone() {
+            match shard.read(&older, &k).await {
+                Ok(value) => older_reads.push(value),
+                Err(e) => {
+                    assert_eq!(e, Abort::OrderViolation(k, tx2));
+                    break
+                }
+            }
+        }
+        assert!(older_reads.iter().all(|value| *value == 10), "{older_reads:?}");
+
+        // A newer reader waits for the commit and sees all of it
+        for k in keys {
+            assert_eq!(shard.read(&newer, &k).await, Ok(20));
+        }
+        assert!(commit.await.unwrap().is_ok());
+    }
 }
@@ -0,0 +1,79 @@
+use super::{ObjectState, TransactionId};
+use std::{collections::HashMap, hash::Hash};
+
+/// Checks the invariants of timestamp ordering on the objects of a shard:
+///
+/// - an object's committed timestamp never moves backwards,
+/// - no tentative write is at or before the committed timestamp, since it
+///   could never commit, and
+/// - read timestamps at or before the committed timestamp are pruned, since
+///   they can no longer abort any write.
+///
+/// A violation means the shard engine has a bug, not that a client misbehaved.
+pub struct Verifier<K> {
+    /// The committed timestamp of every object as of the last check
+    last_committed: HashMap<K, TransactionId>
+}
+
+impl<K: Clone + Eq + Hash> Default for Verifier<K> {
+    fn default() -> Self {
+        Self { last_committed: HashMap::new() }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Verifier<K> {
+    /// Checks the state of every object on the shard, returning a description
+    /// of each violation found. Objects missing from `states` were reaped, so
+    /// they are forgotten.
+    pub fn check(&mut self, states: Vec<(K, ObjectState)>) -> Vec<(K, String)> {
+        let mut violations = Vec::new();
+        let mut last_committed = HashMap::with_capacity(states.len());
+
+        for (key, state) in states {
+            let committed = state.committed_timestamp;
+            if let Some(last) = self.last_committed.get(&key).filter(|last| committed < **last) {
+                violations.push((key.clone(), format!("committed timestamp moved back from {last} to {committed}")));
+            }
+
+            if let Some(write) = state.oldest_tentative_write.filter(|ts| *ts <= committed) {
+                violations.push((key.clone(), format!("tentative write by {write} is not newer than committed {committed}")));
+            }
+
+            if let Some(read) = state.oldest_read.filter(|ts| !committed.is_default() && *ts <= committed) {
+                violations.push((key.clone(), format!("read by {read} was not pruned after {committed} committed")));
+            }
+
+            last_committed.insert(key, committed);
+        }
+
+        self.last_committed = last_committed;
+        violations
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sharding::TransactionIdGenerator;
+    use tx_common::config::NodeId;
+    use super::*;
+
+    #[test]
+    fn test_verifier_reports_violations() {
+        let mut id_gen = TransactionIdGenerator::new(NodeId(0));
+        let (t1, t2, t3) = (id_gen.next(), id_gen.next(), id_gen.next());
+        let state = |committed_timestamp, oldest_tentative_write, oldest_read| ObjectState {
+            committed_timestamp, oldest_tentative_write, oldest_read
+        };
+
+        let mut verifier = Verifier::default();
+        assert!(verifier.check(vec![("a", state(t2, Some(t3), Some(t3)))]).is_empty());
+
+        let violations = verifier.check(vec![("a", state(t1, Some(t1), None)), ("b", state(t2, None, Some(t1)))]);
+        let keys: Vec<_> = violations.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec!["a", "a", "b"]);
+
+        // "a" was reaped and recreated, so its history starts over
+        assert!(verifier.check(vec![("b", state(t2, None, None))]).is_empty());
+        assert!(verifier.check(vec![("a", state(t1, None, None))]).is_empty());
+    }
+}
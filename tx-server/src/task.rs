@@ -0,0 +1,67 @@
+//! Spawns the node's tasks under names, so that tokio-console can tell the
+//! client handlers, member loops and shard commit tasks of a live node apart.
+//! Tasks only carry names in builds with `--cfg tokio_unstable`, which the
+//! console needs anyway; elsewhere `spawn` is `tokio::spawn`, and the name,
+//! taken as `format_args!` so that it is only formatted when it is kept,
+//! costs nothing. The sharding
+//! engine built without the `server` feature has no tokio to spawn on, so
+//! its work runs on the caller's task instead, on whatever executor that is.
+
+use std::{fmt, future::Future};
+#[cfg(feature = "server")]
+use tokio::task::JoinHandle;
+
+#[cfg(all(feature = "server", tokio_unstable))]
+#[track_caller]
+pub(crate) fn spawn<F>(name: fmt::Arguments, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static
+{
+    tokio::task::Builder::new()
+        .name(&name.to_string())
+        .spawn(future)
+        .expect("tasks can be spawned from within the runtime")
+}
+
+#[cfg(all(feature = "server", not(tokio_unstable)))]
+#[track_caller]
+pub(crate) fn spawn<F>(_name: fmt::Arguments, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static
+{
+    tokio::spawn(future)
+}
+
+/// Runs `future` when the returned future is polled, which fails no more
+/// than awaiting it directly would.
+#[cfg(not(feature = "server"))]
+pub(crate) fn spawn<F>(_name: fmt::Arguments, future: F) -> impl Future<Output = Result<F::Output, std::convert::Infallible>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static
+{
+    async move { Ok(future.await) }
+}
+
+/// Lets other tasks run before carrying on.
+#[cfg(feature = "server")]
+pub(crate) async fn yield_now() {
+    tokio::task::yield_now().await
+}
+
+/// Lets other tasks run before carrying on, by returning to the executor
+/// once after asking to be polled again.
+#[cfg(not(feature = "server"))]
+pub(crate) async fn yield_now() {
+    let mut yielded = false;
+    std::future::poll_fn(|cx| {
+        if yielded {
+            return std::task::Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        std::task::Poll::Pending
+    }).await
+}
@@ -0,0 +1,119 @@
+//! Helpers for the tests, examples and benchmarks that run servers in 
+//! process on localhost.
+
+use crate::coordinator::Server;
+use tx_common::{config::{Config, NodeId}, stream::MessageStream};
+use tx_proto::{BalanceDiff, ClientRequest, ClientResponse};
+use tokio::net::TcpStream;
+use std::{io::{self, Write}, sync::{Arc, Mutex}, time::Duration};
+pub use tx_common::testing::{free_port, local_config};
+
+/// Starts a server for every node in the config and returns once all of 
+/// the servers are connected to each other and serving clients. 
+pub async fn start_cluster(config: &Config) {
+    start_cluster_with(config, |server| server).await
+}
+
+/// Starts a cluster like `start_cluster`, letting `configure` set each 
+/// server up before it serves.
+pub async fn start_cluster_with(config: &Config, configure: impl Fn(Server) -> Server) {
+    let servers = config.keys()
+        .map(|node_id| tokio::spawn(Server::start(*node_id, config.clone(), 5)))
+        .collect::<Vec<_>>();
+
+    for server in servers {
+        let mut server = configure(server.await.unwrap());
+        tokio::spawn(async move { server.serve().await });
+    }
+}
+
+/// Starts nodes `A` and `B`, with A holding its commit decisions to B until
+/// the end of each second, then funds `A.x` and `B.y` with 100 each and 
+/// transfers 10 from `A.x` to `B.y`, both coordinated by A. Returns the 
+/// config once A applied the transfer, while B may apply it up to a second
+/// later.
+pub async fn start_transfer_in_transit() -> Config {
+    let config = local_config(&["A", "B"]);
+    let a = NodeId(0);
+    let servers = config.keys()
+        .map(|node_id| (*node_id, tokio::spawn(Server::start(*node_id, config.clone(), 5))))
+        .collect::<Vec<_>>();
+    for (node_id, server) in servers {
+        let mut server = server.await.unwrap();
+        if node_id == a {
+            server = server.with_commit_epoch(Some(Duration::from_secs(1)));
+        }
+        tokio::spawn(async move { server.serve().await });
+    }
+
+    let port = config[&a].port;
+    for (x, y) in [(100, 100), (-10, 10)] {
+        let requests = vec![
+            ClientRequest::WriteBalance("A.x".into(), BalanceDiff(x)),
+            ClientRequest::WriteBalance("B.y".into(), BalanceDiff(y)),
+            ClientRequest::Commit
+        ];
+        let responses = run_transaction(port, requests).await;
+        assert!(matches!(responses.as_slice(), [.., ClientResponse::CommitOk]), "{responses:?}");
+    }
+
+    config
+}
+
+/// Sends a transaction's requests to the server listening on `port`, and 
+/// returns the response to each, even those sent after the transaction 
+/// ended.
+pub async fn run_transaction(port: u16, requests: Vec<ClientRequest>) -> Vec<ClientResponse> {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    run_transaction_over(MessageStream::from_tcp_stream(stream), requests).await
+}
+
+/// Runs a transaction like `run_transaction`, over a connection already 
+/// made.
+pub async fn run_transaction_over(mut stream: MessageStream, requests: Vec<ClientRequest>) -> Vec<ClientResponse> {
+    let mut responses = Vec::new();
+
+    for request in requests.into_iter() {
+        stream.send(request).await.unwrap();
+        responses.push(stream.recv().await.unwrap().unwrap());
+    }
+
+    responses
+}
+
+/// Sends a transaction's requests to the server listening on `port` until
+/// one ends the transaction, and returns the response that ended it, or
+/// `Aborted` if none did.
+pub async fn finish_transaction(port: u16, requests: Vec<ClientRequest>) -> ClientResponse {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let mut stream = MessageStream::from_tcp_stream(stream);
+    for request in requests {
+        stream.send(request).await.unwrap();
+        let response: ClientResponse = stream.recv().await.unwrap().unwrap();
+        if response.is_final() {
+            return response;
+        }
+    }
+
+    ClientResponse::Aborted
+}
+
+/// A writer whose output can still be read once it was handed over.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}